@@ -0,0 +1,15 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // The gRPC service is generated from its definition. protoc is vendored so building it needs
+    // nothing installed. Only the server is generated, other services bring their own client.
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("protoc is vendored");
+        std::env::set_var("PROTOC", protoc);
+        tonic_build::configure()
+            .build_client(false)
+            .compile_protos(&["proto/payments.proto"], &["proto"])
+            .expect("proto/payments.proto compiles");
+    }
+}
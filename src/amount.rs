@@ -1,35 +1,191 @@
 use std::str::FromStr;
 
-use rust_decimal::Decimal;
+use rust_decimal::{Decimal, RoundingStrategy};
 
 // Using rust_decimal as it's a finance based decimal crate that allows specification of precision.
+// With the `fixed-point` feature amounts are instead held as an i128 count of the smallest unit,
+// which is much cheaper to add and compare. rust_decimal is then only used to parse and format
+// amounts.
+//
+// The number of decimal places is a const parameter of `ScaledAmount`, so amounts of assets with
+// different precisions are different types and can't be mixed by mistake. The engine works in
+// `Amount`, whose precision is `DECIMAL_PLACES`. It's 4 unless `PAYMENTS_DECIMAL_PLACES` is set
+// when building, e.g. to 8 for crypto assets or 2 for ledgers kept in cents.
+
+/// The decimal places of the amounts the engine works in.
+pub const DECIMAL_PLACES: u32 = match option_env!("PAYMENTS_DECIMAL_PLACES") {
+    Some(places) => parse_places(places),
+    None => 4,
+};
+
+/// The most decimal places a `Decimal` can hold.
+const MAX_DECIMAL_PLACES: u32 = 28;
+
+const fn parse_places(places: &str) -> u32 {
+    let bytes = places.as_bytes();
+    assert!(!bytes.is_empty(), "PAYMENTS_DECIMAL_PLACES is empty");
+    let mut parsed = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        assert!(
+            bytes[i].is_ascii_digit(),
+            "PAYMENTS_DECIMAL_PLACES isn't a number"
+        );
+        parsed = parsed * 10 + (bytes[i] - b'0') as u32;
+        assert!(
+            parsed <= MAX_DECIMAL_PLACES,
+            "PAYMENTS_DECIMAL_PLACES is above 28"
+        );
+        i += 1;
+    }
+    parsed
+}
+
+/// The amounts the engine works in.
+pub type Amount = ScaledAmount<DECIMAL_PLACES>;
+
+#[cfg(not(feature = "fixed-point"))]
+type Value = Decimal;
 
-const DECIMAL_PLACES: u32 = 4;
+#[cfg(feature = "fixed-point")]
+type Value = i128;
 
-#[derive(Copy, Clone, PartialEq)]
-pub struct Amount {
-    value: Decimal,
+#[cfg(not(feature = "fixed-point"))]
+fn to_decimal(value: Value, _places: u32) -> Decimal {
+    value
 }
 
-impl Amount {
-    /// Creates a new Amount with 4 decimal places.
-    pub fn new(value: i64) -> Self {
-        Self {
-            value: Decimal::new(value, DECIMAL_PLACES),
+#[cfg(not(feature = "fixed-point"))]
+fn from_decimal(value: Decimal) -> Value {
+    value
+}
+
+#[cfg(feature = "fixed-point")]
+fn to_decimal(value: Value, places: u32) -> Decimal {
+    Decimal::from_i128_with_scale(value, places)
+}
+
+/// Expects the decimal to already have been rescaled to the amount's decimal places.
+#[cfg(feature = "fixed-point")]
+fn from_decimal(value: Decimal) -> Value {
+    value.mantissa()
+}
+
+/// Why text couldn't be read as an amount.
+#[derive(Debug, PartialEq)]
+pub enum AmountError {
+    /// The text isn't a decimal number.
+    Invalid(rust_decimal::Error),
+    /// The amount has more decimal places than are kept, and the policy was to reject it.
+    ExcessPrecision { passed: String, places: u32 },
+}
+
+impl std::fmt::Display for AmountError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid(e) => write!(f, "{}", e),
+            Self::ExcessPrecision { passed, places } => {
+                write!(f, "{} has more than {} decimal places", passed, places)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AmountError {}
+
+impl From<rust_decimal::Error> for AmountError {
+    fn from(e: rust_decimal::Error) -> Self {
+        Self::Invalid(e)
+    }
+}
+
+/// How amounts are rounded wherever digits are dropped.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum RoundingMode {
+    /// Round to the nearest digit, with halves rounded away from zero.
+    #[default]
+    HalfUp,
+    /// Round to the nearest digit, with halves rounded to the even one, i.e. banker's rounding.
+    HalfEven,
+    /// Drop the extra digits.
+    Truncate,
+}
+
+impl RoundingMode {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            Self::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Self::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Self::Truncate => RoundingStrategy::ToZero,
+        }
+    }
+}
+
+impl FromStr for RoundingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half-up" => Ok(Self::HalfUp),
+            "half-even" => Ok(Self::HalfEven),
+            "truncate" => Ok(Self::Truncate),
+            _ => Err(String::from(s)),
+        }
+    }
+}
+
+/// What happens to amounts with more decimal places than are kept.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PrecisionPolicy {
+    /// Return `AmountError::ExcessPrecision`.
+    Error,
+    /// Round the extra digits away.
+    Round(RoundingMode),
+}
+
+impl Default for PrecisionPolicy {
+    fn default() -> Self {
+        Self::Round(RoundingMode::default())
+    }
+}
+
+impl FromStr for PrecisionPolicy {
+    type Err = String;
+
+    /// Parses `error`, `round` for the default rounding mode, or a rounding mode to round with.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Self::Error),
+            "round" => Ok(Self::default()),
+            _ => RoundingMode::from_str(s).map(Self::Round),
         }
     }
+}
 
-    /// Creates a decimal from the given string
-    pub fn from_str(s: &str) -> Result<Self, rust_decimal::Error> {
-        // TODO: return an error if the decimal places are truncated?
-        let mut value = Decimal::from_str(s)?;
-        value.rescale(DECIMAL_PLACES);
+/// An amount kept to `PLACES` decimal places.
+#[derive(Copy, Clone, PartialEq, PartialOrd)]
+pub struct ScaledAmount<const PLACES: u32> {
+    value: Value,
+}
 
-        Ok(Self { value })
+impl<const PLACES: u32> ScaledAmount<PLACES> {
+    /// Creates a new amount from a count of its smallest unit, e.g. `new(314)` is 0.0314 with 4
+    /// decimal places.
+    pub fn new(value: i64) -> Self {
+        Self {
+            value: from_decimal(Decimal::new(value, PLACES)),
+        }
     }
 
     fn base_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.value)
+        // Always every decimal place, whatever scale the value was computed at.
+        write!(f, "{:.*}", PLACES as usize, to_decimal(self.value, PLACES))
+    }
+
+    /// The amount without trailing zeros after the decimal point, e.g. `1.5` rather than
+    /// `1.5000`.
+    pub fn trimmed(&self) -> String {
+        to_decimal(self.value, PLACES).normalize().to_string()
     }
 
     /// An amount set to 0.
@@ -41,9 +197,74 @@ impl Amount {
     pub fn less_than_zero(&self) -> bool {
         self.value < Self::zero().value
     }
+
+    /// Rounds to a multiple of the step with the rounding mode.
+    pub fn round_to(&self, step: Self, mode: RoundingMode) -> Self {
+        self.multiple_of(step, mode.strategy())
+    }
+
+    /// Rounds down to the multiple of the step at or below the amount.
+    pub fn floor_to(&self, step: Self) -> Self {
+        self.multiple_of(step, RoundingStrategy::ToNegativeInfinity)
+    }
+
+    /// Multiplies the amount by the factor, rounding the product with the rounding mode.
+    pub fn scale(&self, factor: Decimal, mode: RoundingMode) -> Self {
+        let mut value = (to_decimal(self.value, PLACES) * factor)
+            .round_dp_with_strategy(PLACES, mode.strategy());
+        value.rescale(PLACES);
+        Self {
+            value: from_decimal(value),
+        }
+    }
+
+    /// Parses the amount, applying the policy if it has more than `PLACES` decimal places.
+    pub fn parse(s: &str, policy: PrecisionPolicy) -> Result<Self, AmountError> {
+        let value = Decimal::from_str(s)?;
+        let mut value = match policy {
+            PrecisionPolicy::Round(mode) => value.round_dp_with_strategy(PLACES, mode.strategy()),
+            PrecisionPolicy::Error => {
+                if value.round_dp_with_strategy(PLACES, RoundingStrategy::ToZero) != value {
+                    return Err(AmountError::ExcessPrecision {
+                        passed: String::from(s),
+                        places: PLACES,
+                    });
+                }
+                value
+            }
+        };
+        value.rescale(PLACES);
+
+        Ok(Self {
+            value: from_decimal(value),
+        })
+    }
+
+    fn multiple_of(&self, step: Self, strategy: RoundingStrategy) -> Self {
+        let step = to_decimal(step.value, PLACES);
+        if step.is_zero() {
+            return *self;
+        }
+
+        let mut value =
+            (to_decimal(self.value, PLACES) / step).round_dp_with_strategy(0, strategy) * step;
+        value.rescale(PLACES);
+        Self {
+            value: from_decimal(value),
+        }
+    }
+}
+
+impl<const PLACES: u32> FromStr for ScaledAmount<PLACES> {
+    type Err = AmountError;
+
+    /// Creates a decimal from the given string, rounding any decimal places past `PLACES`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, PrecisionPolicy::default())
+    }
 }
 
-impl std::ops::Add for Amount {
+impl<const PLACES: u32> std::ops::Add for ScaledAmount<PLACES> {
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self::Output {
@@ -53,7 +274,7 @@ impl std::ops::Add for Amount {
     }
 }
 
-impl std::ops::Sub for Amount {
+impl<const PLACES: u32> std::ops::Sub for ScaledAmount<PLACES> {
     type Output = Self;
 
     fn sub(self, rhs: Self) -> Self::Output {
@@ -63,28 +284,62 @@ impl std::ops::Sub for Amount {
     }
 }
 
-impl Default for Amount {
+impl<const PLACES: u32> Default for ScaledAmount<PLACES> {
     fn default() -> Self {
         Self::zero()
     }
 }
 
-impl std::fmt::Debug for Amount {
+impl<const PLACES: u32> std::fmt::Debug for ScaledAmount<PLACES> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.base_fmt(f)
     }
 }
 
-impl std::fmt::Display for Amount {
+impl<const PLACES: u32> std::fmt::Display for ScaledAmount<PLACES> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.base_fmt(f)
     }
 }
 
+/// Serialized as a decimal string with `PLACES` decimal places, e.g. `"1.5000"`, so no precision is
+/// lost to floats.
+impl<const PLACES: u32> serde::Serialize for ScaledAmount<PLACES> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Read from a decimal string, rounding any decimal places past `PLACES` as `from_str` does.
+impl<'de, const PLACES: u32> serde::Deserialize<'de> for ScaledAmount<PLACES> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AmountVisitor<const PLACES: u32>;
+
+        impl<const PLACES: u32> serde::de::Visitor<'_> for AmountVisitor<PLACES> {
+            type Value = ScaledAmount<PLACES>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "a decimal amount as a string")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                ScaledAmount::from_str(value.trim())
+                    .map_err(|_| E::invalid_value(serde::de::Unexpected::Str(value), &self))
+            }
+        }
+
+        deserializer.deserialize_str(AmountVisitor::<PLACES>)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // Written for 4 decimal places, whatever `DECIMAL_PLACES` the engine is built with. The
+    // properties below check the engine's own `Amount`.
+    type Amount = ScaledAmount<4>;
+
     #[test]
     fn amount_display_returns_expected() {
         let amount = Amount::new(314);
@@ -124,10 +379,91 @@ mod tests {
         assert_eq!(Amount::new(1414), a - b);
     }
 
+    #[test]
+    fn amount_round_to_returns_expected() {
+        let step = Amount::new(1000000);
+        let half_up = RoundingMode::HalfUp;
+
+        assert_eq!(
+            Amount::new(1000000),
+            Amount::new(1490000).round_to(step, half_up)
+        );
+        assert_eq!(
+            Amount::new(2000000),
+            Amount::new(1500000).round_to(step, half_up)
+        );
+        assert_eq!(
+            Amount::new(-2000000),
+            Amount::new(-1500000).round_to(step, half_up)
+        );
+        assert_eq!(
+            Amount::new(1234),
+            Amount::new(1234).round_to(Amount::zero(), half_up)
+        );
+    }
+
+    #[test]
+    fn amount_round_to_honors_rounding_mode() {
+        let step = Amount::new(1000000);
+
+        assert_eq!(
+            Amount::new(2000000),
+            Amount::new(1500000).round_to(step, RoundingMode::HalfEven)
+        );
+        assert_eq!(
+            Amount::new(2000000),
+            Amount::new(2500000).round_to(step, RoundingMode::HalfEven)
+        );
+        assert_eq!(
+            Amount::new(-2000000),
+            Amount::new(-2500000).round_to(step, RoundingMode::HalfEven)
+        );
+        assert_eq!(
+            Amount::new(1000000),
+            Amount::new(1990000).round_to(step, RoundingMode::Truncate)
+        );
+    }
+
+    #[test]
+    fn amount_parse_half_even_rounds_halves_to_even() {
+        let half_even = PrecisionPolicy::Round(RoundingMode::HalfEven);
+
+        assert_eq!(Ok(Amount::new(12344)), Amount::parse("1.23445", half_even));
+        assert_eq!(Ok(Amount::new(12346)), Amount::parse("1.23455", half_even));
+        assert_eq!(Ok(Amount::new(12346)), Amount::parse("1.234551", half_even));
+        assert_eq!(
+            Ok(Amount::new(-12344)),
+            Amount::parse("-1.23445", half_even)
+        );
+    }
+
+    #[test]
+    fn precision_policy_from_str_reads_rounding_modes() {
+        assert_eq!(Ok(PrecisionPolicy::Error), "error".parse());
+        assert_eq!(Ok(PrecisionPolicy::default()), "round".parse());
+        assert_eq!(
+            Ok(PrecisionPolicy::Round(RoundingMode::HalfEven)),
+            "half-even".parse()
+        );
+        assert_eq!(
+            Ok(PrecisionPolicy::Round(RoundingMode::Truncate)),
+            "truncate".parse()
+        );
+        assert_eq!(true, "floor".parse::<PrecisionPolicy>().is_err());
+    }
+
+    #[test]
+    fn amount_floor_to_returns_expected() {
+        let step = Amount::new(1000000);
+
+        assert_eq!(Amount::new(1000000), Amount::new(1990000).floor_to(step));
+        assert_eq!(Amount::new(-1000000), Amount::new(-10000).floor_to(step));
+    }
+
     #[test]
     fn amount_negative_one_less_than_zero_returns_true() {
         let amount = Amount {
-            value: Decimal::new(-1, 4),
+            value: from_decimal(Decimal::new(-1, 4)),
         };
 
         assert_eq!(true, amount.less_than_zero());
@@ -136,7 +472,7 @@ mod tests {
     #[test]
     fn amount_one_less_than_zero_returns_false() {
         let amount = Amount {
-            value: Decimal::new(1, 4),
+            value: from_decimal(Decimal::new(1, 4)),
         };
 
         assert_eq!(false, amount.less_than_zero());
@@ -145,7 +481,7 @@ mod tests {
     #[test]
     fn amount_zero_returns_zero() {
         let expected = Amount {
-            value: Decimal::new(0, 4),
+            value: from_decimal(Decimal::new(0, 4)),
         };
         let actual = Amount::zero();
 
@@ -155,7 +491,7 @@ mod tests {
     #[test]
     fn amount_default_returns_zero() {
         let expected = Amount {
-            value: Decimal::new(0, 4),
+            value: from_decimal(Decimal::new(0, 4)),
         };
         let actual = Amount::default();
 
@@ -183,4 +519,145 @@ mod tests {
         let actual = result.unwrap();
         assert_eq!(Amount::new(12004444233), actual);
     }
+
+    #[test]
+    fn amount_parse_excess_precision_applies_policy() {
+        assert_eq!(
+            Ok(Amount::new(12346)),
+            Amount::parse("1.23456", PrecisionPolicy::Round(RoundingMode::HalfUp))
+        );
+        assert_eq!(
+            Ok(Amount::new(12345)),
+            Amount::parse("1.23456", PrecisionPolicy::Round(RoundingMode::Truncate))
+        );
+        assert_eq!(
+            Ok(Amount::new(-12345)),
+            Amount::parse("-1.23456", PrecisionPolicy::Round(RoundingMode::Truncate))
+        );
+        assert_eq!(
+            Err(AmountError::ExcessPrecision {
+                passed: String::from("1.23456"),
+                places: 4,
+            }),
+            Amount::parse("1.23456", PrecisionPolicy::Error)
+        );
+    }
+
+    #[test]
+    fn scaled_amount_honors_its_decimal_places() {
+        let satoshis = ScaledAmount::<8>::from_str("0.00000001").unwrap();
+        assert_eq!(ScaledAmount::<8>::new(1), satoshis);
+        assert_eq!("0.00000003", format!("{}", satoshis + satoshis + satoshis));
+        assert_eq!(
+            Err(AmountError::ExcessPrecision {
+                passed: String::from("0.000000001"),
+                places: 8,
+            }),
+            ScaledAmount::<8>::parse("0.000000001", PrecisionPolicy::Error)
+        );
+
+        let cents = ScaledAmount::<2>::from_str("1.005").unwrap();
+        assert_eq!("1.01", format!("{}", cents));
+        assert_eq!("0.00", format!("{}", ScaledAmount::<2>::zero()));
+        assert_eq!(
+            ScaledAmount::<2>::new(100),
+            cents.floor_to(ScaledAmount::<2>::new(100))
+        );
+    }
+
+    #[test]
+    fn parse_places_reads_a_count_of_decimal_places() {
+        assert_eq!(8, parse_places("8"));
+        assert_eq!(28, parse_places("28"));
+    }
+
+    #[test]
+    fn amount_trimmed_drops_trailing_zeros() {
+        assert_eq!("1.5", Amount::new(15000).trimmed());
+        assert_eq!("2", Amount::new(20000).trimmed());
+        assert_eq!("0.0314", Amount::new(314).trimmed());
+        assert_eq!("0", Amount::zero().trimmed());
+        assert_eq!("-0.01", Amount::new(-100).trimmed());
+    }
+
+    #[test]
+    fn amount_serde_json_round_trips_as_string() {
+        let amount = Amount::new(15000);
+
+        assert_eq!("\"1.5000\"", serde_json::to_string(&amount).unwrap());
+        assert_eq!(amount, serde_json::from_str("\"1.5\"").unwrap());
+        // Numbers would be read through floats, so only strings are accepted.
+        assert_eq!(true, serde_json::from_str::<Amount>("2").is_err());
+        assert_eq!(true, serde_json::from_str::<Amount>("\"1.x\"").is_err());
+        assert_eq!(
+            "\"0.15\"",
+            serde_json::to_string(&ScaledAmount::<2>::new(15)).unwrap()
+        );
+    }
+
+    #[test]
+    fn amount_deserialize_reads_csv_fields() {
+        #[derive(serde::Deserialize)]
+        struct Row {
+            amount: Amount,
+        }
+
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader("amount\n 2.25 \n".as_bytes());
+        let row: Row = rdr.deserialize().next().unwrap().unwrap();
+
+        assert_eq!(Amount::new(22500), row.amount);
+    }
+
+    #[test]
+    fn amount_parse_trailing_zeros_are_not_excess_precision() {
+        assert_eq!(
+            Ok(Amount::new(12340)),
+            Amount::parse("1.234000", PrecisionPolicy::Error)
+        );
+    }
+
+    // The amount must behave exactly like the rust_decimal value it represents, whichever
+    // representation is compiled in.
+    mod properties {
+        use super::*;
+        use crate::amount::Amount;
+        use proptest::prelude::*;
+
+        fn decimal(value: i64) -> Decimal {
+            Decimal::new(value, DECIMAL_PLACES)
+        }
+
+        proptest! {
+            #[test]
+            fn amount_add_matches_decimal(a in any::<i64>(), b in any::<i64>()) {
+                let actual = format!("{}", Amount::new(a) + Amount::new(b));
+                prop_assert_eq!(format!("{}", decimal(a) + decimal(b)), actual);
+            }
+
+            #[test]
+            fn amount_sub_matches_decimal(a in any::<i64>(), b in any::<i64>()) {
+                let actual = format!("{}", Amount::new(a) - Amount::new(b));
+                prop_assert_eq!(format!("{}", decimal(a) - decimal(b)), actual);
+            }
+
+            #[test]
+            fn amount_ordering_matches_decimal(a in any::<i64>(), b in any::<i64>()) {
+                prop_assert_eq!(decimal(a) < decimal(b), Amount::new(a) < Amount::new(b));
+                prop_assert_eq!(decimal(a) == decimal(b), Amount::new(a) == Amount::new(b));
+                prop_assert_eq!(decimal(a).is_sign_negative() && a != 0, Amount::new(a).less_than_zero());
+            }
+
+            #[test]
+            fn amount_from_str_matches_decimal(value in any::<i64>(), scale in 0u32..10) {
+                let text = format!("{}", Decimal::new(value, scale));
+                let mut expected = Decimal::from_str(&text).unwrap();
+                expected.rescale(DECIMAL_PLACES);
+
+                let actual = Amount::from_str(&text).unwrap();
+                prop_assert_eq!(format!("{}", expected), format!("{}", actual));
+            }
+        }
+    }
 }
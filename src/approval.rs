@@ -0,0 +1,329 @@
+use crate::{
+    amount::Amount,
+    client::ClientId,
+    clock::Timestamp,
+    currency::Currency,
+    parse_csv::{CsvTransaction, ParseCsvError},
+    transaction::{ReasonCode, Transaction, TransactionId, TransactionType},
+};
+use std::path::Path;
+
+// Transactions above the approval threshold are parked in a queue file instead of being applied.
+// An operator then approves or rejects them, and approved transactions are applied on the next run.
+
+#[derive(Copy, Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApprovalStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(Debug)]
+pub enum ApprovalError {
+    Io(std::io::Error),
+    Csv(csv::Error),
+    InvalidRecord(ParseCsvError),
+    NotFound { transaction_id: TransactionId },
+    NotPending { current_status: ApprovalStatus },
+}
+
+impl From<std::io::Error> for ApprovalError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<csv::Error> for ApprovalError {
+    fn from(e: csv::Error) -> Self {
+        Self::Csv(e)
+    }
+}
+
+/// A single row in the persisted approval queue.
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+struct QueueRecord {
+    status: ApprovalStatus,
+    #[serde(rename = "type")]
+    transaction_type: String,
+    client: String,
+    tx: TransactionId,
+    amount: Option<String>,
+    reason: Option<ReasonCode>,
+    // Queues written before transfers existed have no recipient column.
+    #[serde(default)]
+    to: Option<ClientId>,
+    // Nor do queues written before currencies were read.
+    #[serde(default)]
+    currency: Option<Currency>,
+    #[serde(default)]
+    to_currency: Option<Currency>,
+    #[serde(default)]
+    converted: Option<String>,
+    // Nor do queues written before timestamps were kept, whose transactions are released without.
+    #[serde(default)]
+    timestamp: Option<Timestamp>,
+}
+
+/// A queue of transactions awaiting operator approval, persisted to a CSV file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApprovalQueue {
+    entries: Vec<(ApprovalStatus, Transaction)>,
+}
+
+impl ApprovalQueue {
+    /// Creates an empty queue.
+    pub fn new() -> Self {
+        Self { entries: vec![] }
+    }
+
+    /// Loads the queue from the given file. A missing file is treated as an empty queue.
+    pub fn load(path: &str) -> Result<Self, ApprovalError> {
+        if !Path::new(path).exists() {
+            return Ok(Self::new());
+        }
+
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+
+        let mut entries = vec![];
+        for result in rdr.deserialize() {
+            let record: QueueRecord = result?;
+            let status = record.status;
+
+            let csv_transaction = CsvTransaction {
+                transaction_type: record.transaction_type,
+                client: record.client,
+                tx: record.tx,
+                amount: record.amount,
+                reason: record.reason,
+                to: record.to,
+                case: None,
+                timestamp: record.timestamp,
+                currency: record.currency,
+                to_currency: record.to_currency,
+                converted: record.converted,
+            };
+
+            match csv_transaction.into_transaction() {
+                Ok(Some(transaction)) => entries.push((status, transaction)),
+                Ok(None) => {}
+                Err(e) => return Err(ApprovalError::InvalidRecord(e)),
+            }
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Writes the queue to the given file, replacing its contents.
+    pub fn save(&self, path: &str) -> Result<(), ApprovalError> {
+        let mut wtr = csv::Writer::from_path(path)?;
+
+        for (status, transaction) in self.entries.iter() {
+            let csv_transaction = CsvTransaction::from_transaction(transaction);
+            wtr.serialize(QueueRecord {
+                status: *status,
+                transaction_type: csv_transaction.transaction_type,
+                client: csv_transaction.client,
+                tx: csv_transaction.tx,
+                amount: csv_transaction.amount,
+                reason: csv_transaction.reason,
+                to: csv_transaction.to,
+                currency: csv_transaction.currency,
+                to_currency: csv_transaction.to_currency,
+                converted: csv_transaction.converted,
+                timestamp: csv_transaction.timestamp,
+            })?;
+        }
+
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// All queued transactions with their current status.
+    pub fn entries(&self) -> &[(ApprovalStatus, Transaction)] {
+        &self.entries
+    }
+
+    /// Parks a transaction in the queue until an operator decides on it.
+    pub fn park(&mut self, transaction: Transaction) {
+        self.entries.push((ApprovalStatus::Pending, transaction));
+    }
+
+    /// Marks a pending transaction as approved.
+    pub fn approve(&mut self, transaction_id: TransactionId) -> Result<(), ApprovalError> {
+        self.decide(transaction_id, ApprovalStatus::Approved)
+    }
+
+    /// Marks a pending transaction as rejected.
+    pub fn reject(&mut self, transaction_id: TransactionId) -> Result<(), ApprovalError> {
+        self.decide(transaction_id, ApprovalStatus::Rejected)
+    }
+
+    /// Removes every decided transaction from the queue, returning the approved ones in queue order.
+    pub fn take_approved(&mut self) -> Vec<Transaction> {
+        let approved = self
+            .entries
+            .iter()
+            .filter(|(status, _)| *status == ApprovalStatus::Approved)
+            .map(|(_, transaction)| *transaction)
+            .collect();
+
+        self.entries
+            .retain(|(status, _)| *status == ApprovalStatus::Pending);
+
+        approved
+    }
+
+    fn decide(
+        &mut self,
+        transaction_id: TransactionId,
+        decision: ApprovalStatus,
+    ) -> Result<(), ApprovalError> {
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|(_, transaction)| transaction.id == transaction_id);
+
+        match entry {
+            Some((status, _)) => {
+                if *status != ApprovalStatus::Pending {
+                    return Err(ApprovalError::NotPending {
+                        current_status: *status,
+                    });
+                }
+
+                *status = decision;
+                Ok(())
+            }
+            None => Err(ApprovalError::NotFound { transaction_id }),
+        }
+    }
+}
+
+impl Default for ApprovalQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether the transaction must be approved by an operator before it is applied.
+pub fn requires_approval(transaction: &Transaction, threshold: Amount) -> bool {
+    match transaction.transaction_type {
+        TransactionType::Deposit(amount)
+        | TransactionType::Withdrawal(amount)
+        | TransactionType::Transfer { amount, .. } => amount > threshold,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deposit(id: TransactionId, amount: Amount) -> Transaction {
+        Transaction {
+            transaction_type: TransactionType::Deposit(amount),
+            client: 3,
+            id,
+            timestamp: None,
+            currency: None,
+        }
+    }
+
+    #[test]
+    fn requires_approval_only_above_threshold() {
+        let threshold = Amount::new(10000);
+
+        assert_eq!(false, requires_approval(&deposit(1, threshold), threshold));
+        assert_eq!(
+            true,
+            requires_approval(&deposit(1, Amount::new(10001)), threshold)
+        );
+
+        let dispute = Transaction {
+            transaction_type: TransactionType::Dispute { case: None },
+            client: 3,
+            id: 1,
+            timestamp: None,
+            currency: None,
+        };
+        assert_eq!(false, requires_approval(&dispute, threshold));
+    }
+
+    #[test]
+    fn approval_queue_approve_pending_returns_ok() {
+        let mut queue = ApprovalQueue::new();
+        queue.park(deposit(1, Amount::new(5)));
+
+        assert_eq!(true, queue.approve(1).is_ok());
+        assert_eq!(ApprovalStatus::Approved, queue.entries()[0].0);
+    }
+
+    #[test]
+    fn approval_queue_decide_twice_returns_err() {
+        let mut queue = ApprovalQueue::new();
+        queue.park(deposit(1, Amount::new(5)));
+        queue.reject(1).unwrap();
+
+        let result = queue.approve(1);
+        assert_eq!(true, result.is_err());
+        match result.unwrap_err() {
+            ApprovalError::NotPending { current_status } => {
+                assert_eq!(ApprovalStatus::Rejected, current_status)
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn approval_queue_decide_missing_returns_not_found() {
+        let mut queue = ApprovalQueue::new();
+
+        match queue.approve(12).unwrap_err() {
+            ApprovalError::NotFound { transaction_id } => assert_eq!(12, transaction_id),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn approval_queue_take_approved_keeps_only_pending() {
+        let mut queue = ApprovalQueue::new();
+        queue.park(deposit(1, Amount::new(5)));
+        queue.park(deposit(2, Amount::new(6)));
+        queue.park(deposit(3, Amount::new(7)));
+        queue.approve(1).unwrap();
+        queue.reject(2).unwrap();
+
+        assert_eq!(vec![deposit(1, Amount::new(5))], queue.take_approved());
+        assert_eq!(
+            &[(ApprovalStatus::Pending, deposit(3, Amount::new(7)))],
+            queue.entries()
+        );
+    }
+
+    #[test]
+    fn approval_queue_save_load_round_trips() {
+        let path = std::env::temp_dir().join("payments_approval_queue_round_trip.csv");
+        let path = path.to_str().unwrap();
+
+        let mut queue = ApprovalQueue::new();
+        queue.park(deposit(1, Amount::new(50000)));
+        queue.park(Transaction {
+            transaction_type: TransactionType::Withdrawal(Amount::new(70000)),
+            client: 9,
+            id: 2,
+            timestamp: Some(1_614_556_800),
+            currency: "EUR".parse().ok(),
+        });
+        queue.approve(2).unwrap();
+
+        queue.save(path).unwrap();
+        let loaded = ApprovalQueue::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(queue, loaded);
+    }
+}
@@ -0,0 +1,321 @@
+use crate::{
+    client::{Client, ClientAccount},
+    currency::Currency,
+    database::AccountObserver,
+    parse_csv::CsvTransaction,
+    pipeline::{Disposition, TransactionObserver},
+    transaction::{Transaction, TransactionError},
+};
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+// The audit log is a complete trail for compliance review: every record read, what the engine
+// decided about it, and the client's balances afterwards. It's only ever appended to, so the
+// logs of successive runs build up in one file. Decisions on transactions come from the database,
+// so with anonymized replays they're logged under the anonymized ids, while records ignored,
+// refused by an enricher or blocked as high risk come from the pipeline as the database never
+// sees them.
+
+/// How entries are written to the audit log.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum AuditFormat {
+    #[default]
+    Csv,
+    JsonLines,
+}
+
+impl FromStr for AuditFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "jsonl" => Ok(Self::JsonLines),
+            _ => Err(String::from(s)),
+        }
+    }
+}
+
+/// A single decision as written to the audit log.
+#[derive(Debug, PartialEq, serde::Serialize)]
+struct AuditEntry {
+    decision: &'static str,
+    error: Option<String>,
+    /// The input line of an ignored record.
+    line: Option<u64>,
+    #[serde(rename = "type")]
+    transaction_type: Option<String>,
+    client: Option<String>,
+    tx: Option<u32>,
+    amount: Option<String>,
+    currency: Option<Currency>,
+    available: Option<String>,
+    held: Option<String>,
+    total: Option<String>,
+    locked: Option<bool>,
+}
+
+impl AuditEntry {
+    fn decided<Account: ClientAccount>(
+        transaction: &Transaction,
+        result: &Result<(), TransactionError>,
+        account: Option<&Account>,
+    ) -> Self {
+        let record = CsvTransaction::from_transaction(transaction);
+        let (decision, error) = match result {
+            Ok(()) => ("applied", None),
+            Err(e) => ("rejected", Some(format!("{:?}", e))),
+        };
+
+        Self {
+            decision,
+            error,
+            line: None,
+            transaction_type: Some(record.transaction_type),
+            client: Some(record.client),
+            tx: Some(record.tx),
+            amount: record.amount,
+            currency: record.currency,
+            available: account.map(|account| format!("{:?}", account.available())),
+            held: account.map(|account| format!("{:?}", account.held())),
+            total: account.map(|account| format!("{:?}", account.total())),
+            locked: account.map(|account| account.locked()),
+        }
+    }
+
+    fn ignored(line: Option<u64>, reason: &'static str) -> Self {
+        Self {
+            decision: "ignored",
+            error: Some(String::from(reason)),
+            line,
+            transaction_type: None,
+            client: None,
+            tx: None,
+            amount: None,
+            currency: None,
+            available: None,
+            held: None,
+            total: None,
+            locked: None,
+        }
+    }
+}
+
+enum AuditWriter {
+    Csv(Box<csv::Writer<Box<dyn Write + Send>>>),
+    JsonLines(Box<dyn Write + Send>),
+}
+
+/// Appends an entry for every decision the engine makes.
+pub struct AuditLog {
+    writer: AuditWriter,
+    /// The first failure to write an entry, kept until the log is finished.
+    error: Option<io::Error>,
+}
+
+impl AuditLog {
+    /// Writes entries to the writer. CSV starts with a header row unless `has_headers` is false,
+    /// as when appending to a log that already has one.
+    pub fn new(writer: Box<dyn Write + Send>, format: AuditFormat, has_headers: bool) -> Self {
+        let writer = match format {
+            AuditFormat::Csv => AuditWriter::Csv(Box::new(
+                csv::WriterBuilder::new()
+                    .has_headers(has_headers)
+                    .from_writer(writer),
+            )),
+            AuditFormat::JsonLines => AuditWriter::JsonLines(writer),
+        };
+        Self {
+            writer,
+            error: None,
+        }
+    }
+
+    /// Appends entries to the file at the path, creating it if needed.
+    pub fn append(path: &str, format: AuditFormat) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let empty = file.metadata()?.len() == 0;
+        Ok(Self::new(
+            Box::new(BufWriter::new(file)) as Box<dyn Write + Send>,
+            format,
+            empty,
+        ))
+    }
+
+    /// Flushes every entry to the log. Returns the first failure to write one, if any.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        match &mut self.writer {
+            AuditWriter::Csv(writer) => writer.flush(),
+            AuditWriter::JsonLines(writer) => writer.flush(),
+        }
+    }
+
+    fn write(&mut self, entry: &AuditEntry) {
+        if self.error.is_some() {
+            return;
+        }
+        let written = match &mut self.writer {
+            AuditWriter::Csv(writer) => writer.serialize(entry).map_err(io::Error::from),
+            AuditWriter::JsonLines(writer) => serde_json::to_writer(&mut *writer, entry)
+                .map_err(io::Error::from)
+                .and_then(|_| writeln!(writer)),
+        };
+        if let Err(e) = written {
+            self.error = Some(e);
+        }
+    }
+}
+
+/// Shares an audit log with the database and the pipeline, so it can be finished once the run is
+/// over.
+#[derive(Clone)]
+pub struct AuditObserver(pub Arc<Mutex<AuditLog>>);
+
+impl<Account: ClientAccount> AccountObserver<Account> for AuditObserver {
+    fn on_transaction(
+        &mut self,
+        transaction: &Transaction,
+        result: &Result<(), TransactionError>,
+        account: Option<&Account>,
+    ) {
+        let entry = AuditEntry::decided(transaction, result, account);
+        self.0.lock().unwrap().write(&entry);
+    }
+}
+
+impl TransactionObserver for AuditObserver {
+    /// Only logs transactions rejected before reaching the database, which logs the rest with
+    /// their balances.
+    fn on_transaction(&mut self, transaction: &Transaction, result: &Result<(), TransactionError>) {
+        if let Err(TransactionError::Refused { .. } | TransactionError::HighRisk { .. }) = result {
+            let entry = AuditEntry::decided::<Client>(transaction, result, None);
+            self.0.lock().unwrap().write(&entry);
+        }
+    }
+
+    fn on_disposition(&mut self, line: Option<u64>, disposition: &Disposition) {
+        if let Disposition::Ignored { reason } = disposition {
+            self.0
+                .lock()
+                .unwrap()
+                .write(&AuditEntry::ignored(line, reason));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{amount, places};
+    use crate::transaction::TransactionType;
+
+    /// Collects everything written to it so tests can inspect the output.
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn log(format: AuditFormat) -> (AuditObserver, SharedOutput) {
+        let output = SharedOutput::default();
+        let log = AuditLog::new(Box::new(output.clone()), format, true);
+        (AuditObserver(Arc::new(Mutex::new(log))), output)
+    }
+
+    fn record(observer: &mut AuditObserver) {
+        let deposit = Transaction {
+            transaction_type: TransactionType::Deposit(amount("2")),
+            client: 1,
+            id: 1,
+            timestamp: None,
+            currency: None,
+        };
+        let mut client = Client::new(1);
+        client.execute_transaction(deposit).unwrap();
+
+        AccountObserver::on_transaction(observer, &deposit, &Ok(()), Some(&client));
+        AccountObserver::<Client>::on_transaction(
+            observer,
+            &deposit,
+            &Err(TransactionError::ClientLocked),
+            None,
+        );
+        TransactionObserver::on_transaction(observer, &deposit, &Ok(()));
+        TransactionObserver::on_transaction(
+            observer,
+            &deposit,
+            &Err(TransactionError::Refused {
+                reason: "unknown currency",
+            }),
+        );
+        observer.on_disposition(
+            Some(4),
+            &Disposition::Ignored {
+                reason: "deposit without an amount",
+            },
+        );
+        observer.0.lock().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn audit_log_writes_csv_entries() {
+        let (mut observer, output) = log(AuditFormat::Csv);
+        record(&mut observer);
+
+        let actual = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            places(
+                "decision,error,line,type,client,tx,amount,currency,available,held,total,locked\n\
+             applied,,,deposit,1,1,2.0000,,2.0000,0.0000,2.0000,false\n\
+             rejected,ClientLocked,,deposit,1,1,2.0000,,,,,\n\
+             rejected,\"Refused { reason: \"\"unknown currency\"\" }\",,deposit,1,1,2.0000,,,,,\n\
+             ignored,deposit without an amount,4,,,,,,,,,\n"
+            ),
+            actual
+        );
+    }
+
+    #[test]
+    fn audit_log_writes_json_lines_entries() {
+        let (mut observer, output) = log(AuditFormat::JsonLines);
+        record(&mut observer);
+
+        let actual = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = actual.lines().collect();
+        assert_eq!(4, lines.len());
+        assert_eq!(
+            places("{\"decision\":\"applied\",\"error\":null,\"line\":null,\"type\":\"deposit\",\"client\":\"1\",\"tx\":1,\"amount\":\"2.0000\",\"currency\":null,\"available\":\"2.0000\",\"held\":\"0.0000\",\"total\":\"2.0000\",\"locked\":false}"),
+            lines[0]
+        );
+        assert_eq!(true, lines[3].starts_with("{\"decision\":\"ignored\""));
+    }
+
+    #[test]
+    fn audit_log_append_keeps_one_header_row() {
+        let path = std::env::temp_dir().join("payments_audit_log_append.csv");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        for _ in 0..2 {
+            let log = AuditLog::append(path, AuditFormat::Csv).unwrap();
+            record(&mut AuditObserver(Arc::new(Mutex::new(log))));
+        }
+
+        let actual = std::fs::read_to_string(path).unwrap();
+        assert_eq!(9, actual.lines().count());
+        assert_eq!(1, actual.matches("decision,").count());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,302 @@
+use crate::{
+    amount::Amount,
+    approval,
+    client::{Client, ClientAccount},
+    database::Database,
+    pipeline::{EngineConfig, PipelineBuilder, PipelineError, TransactionObserver},
+    source::IterSource,
+    transaction::{Transaction, TransactionError, TransactionId, TransactionType, Warning},
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::str::FromStr;
+
+// Backtests replay the same transactions under several policies so their outcomes can be compared
+// side by side. Nothing is written to disk: transactions a policy would park for approval are
+// counted instead of queued.
+
+/// A named engine configuration to backtest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Policy {
+    pub name: String,
+    /// Transactions above this amount are parked rather than applied.
+    pub approval_threshold: Option<Amount>,
+    pub config: EngineConfig,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BacktestError {
+    InvalidPolicy { passed: String },
+}
+
+impl FromStr for Policy {
+    type Err = BacktestError;
+
+    /// Parses `name` or `name:setting,setting`, where the settings are `approval-threshold=<amount>`,
+    /// `soft-limit=<amount>` and `coalesce`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || BacktestError::InvalidPolicy {
+            passed: String::from(s),
+        };
+
+        let (name, settings) = match s.split_once(':') {
+            Some((name, settings)) => (name, settings),
+            None => (s, ""),
+        };
+        if name.trim().is_empty() {
+            return Err(invalid());
+        }
+
+        let mut policy = Policy {
+            name: String::from(name.trim()),
+            approval_threshold: None,
+            config: EngineConfig::default(),
+        };
+
+        for setting in settings.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match setting.split_once('=') {
+                Some(("approval-threshold", value)) => {
+                    let threshold = Amount::from_str(value.trim()).map_err(|_| invalid())?;
+                    policy.approval_threshold = Some(threshold);
+                }
+                Some(("soft-limit", value)) => {
+                    let limit = Amount::from_str(value.trim()).map_err(|_| invalid())?;
+                    policy.config.soft_limit = Some(limit);
+                }
+                None if setting == "coalesce" => policy.config.coalesce = true,
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(policy)
+    }
+}
+
+/// The outcome of replaying the transactions under a single policy.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PolicyOutcome {
+    pub name: String,
+    pub accounts: usize,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked_accounts: usize,
+    pub parked: usize,
+    pub rejected: usize,
+    /// Applied transactions that breached the soft limit.
+    pub warnings: usize,
+    /// The sum of deposits that were charged back.
+    pub chargeback_losses: Amount,
+}
+
+/// Tracks rejected transactions and the amounts lost to chargebacks.
+#[derive(Default)]
+struct Losses {
+    amounts: HashMap<TransactionId, Amount>,
+    rejected: usize,
+    warnings: usize,
+    chargebacks: Amount,
+}
+
+struct LossObserver(Rc<RefCell<Losses>>);
+
+impl TransactionObserver for LossObserver {
+    fn on_transaction(&mut self, transaction: &Transaction, result: &Result<(), TransactionError>) {
+        let mut losses = self.0.borrow_mut();
+
+        if result.is_err() {
+            losses.rejected += 1;
+            return;
+        }
+
+        match transaction.transaction_type {
+            TransactionType::Deposit(amount) => {
+                losses.amounts.entry(transaction.id).or_insert(amount);
+            }
+            TransactionType::Chargeback { .. } => {
+                if let Some(amount) = losses.amounts.get(&transaction.id).copied() {
+                    losses.chargebacks = losses.chargebacks + amount;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_warning(&mut self, _transaction: &Transaction, _warning: &Warning) {
+        self.0.borrow_mut().warnings += 1;
+    }
+}
+
+/// Replays the transactions under every policy, each starting from an empty database.
+pub fn run(
+    transactions: &[Transaction],
+    policies: &[Policy],
+) -> Result<Vec<PolicyOutcome>, PipelineError> {
+    policies
+        .iter()
+        .map(|policy| run_policy(transactions, policy))
+        .collect()
+}
+
+fn run_policy(
+    transactions: &[Transaction],
+    policy: &Policy,
+) -> Result<PolicyOutcome, PipelineError> {
+    let (applied, parked): (Vec<Transaction>, Vec<Transaction>) = transactions
+        .iter()
+        .cloned()
+        .partition(|transaction| match policy.approval_threshold {
+            Some(threshold) => !approval::requires_approval(transaction, threshold),
+            None => true,
+        });
+
+    let losses = Rc::new(RefCell::new(Losses::default()));
+
+    let database = PipelineBuilder::new(Database::<Client>::new())
+        .source(Box::new(IterSource::new(applied)))
+        .config(policy.config.clone())
+        .observer(Box::new(LossObserver(losses.clone())))
+        .output(Box::new(io::sink()))
+        .build()?
+        .run()?;
+
+    let mut outcome = PolicyOutcome {
+        name: policy.name.clone(),
+        accounts: 0,
+        available: Amount::zero(),
+        held: Amount::zero(),
+        total: Amount::zero(),
+        locked_accounts: 0,
+        parked: parked.len(),
+        rejected: losses.borrow().rejected,
+        warnings: losses.borrow().warnings,
+        chargeback_losses: losses.borrow().chargebacks,
+    };
+
+    for account in database.accounts() {
+        outcome.accounts += 1;
+        outcome.available = outcome.available + account.available();
+        outcome.held = outcome.held + account.held();
+        outcome.total = outcome.total + account.total();
+        if account.locked() {
+            outcome.locked_accounts += 1;
+        }
+    }
+
+    Ok(outcome)
+}
+
+/// Writes the outcomes as a CSV report with one row per policy.
+pub fn write_report(outcomes: &[PolicyOutcome], writer: &mut dyn Write) -> io::Result<()> {
+    writeln!(
+        writer,
+        "policy, accounts, available, held, total, locked, parked, rejected, warnings, chargeback_losses"
+    )?;
+
+    for outcome in outcomes {
+        writeln!(
+            writer,
+            "{}, {}, {}, {}, {}, {}, {}, {}, {}, {}",
+            outcome.name,
+            outcome.accounts,
+            outcome.available,
+            outcome.held,
+            outcome.total,
+            outcome.locked_accounts,
+            outcome.parked,
+            outcome.rejected,
+            outcome.warnings,
+            outcome.chargeback_losses
+        )?;
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{amount, places};
+
+    fn transaction(transaction_type: TransactionType, id: TransactionId) -> Transaction {
+        Transaction {
+            transaction_type,
+            client: 1,
+            id,
+            timestamp: None,
+            currency: None,
+        }
+    }
+
+    fn transactions() -> Vec<Transaction> {
+        vec![
+            transaction(TransactionType::Deposit(amount("5")), 1),
+            transaction(TransactionType::Deposit(amount("2000")), 2),
+            transaction(TransactionType::Withdrawal(amount("10")), 3),
+            transaction(TransactionType::Dispute { case: None }, 1),
+            transaction(TransactionType::Chargeback { case: None }, 1),
+        ]
+    }
+
+    #[test]
+    fn policy_from_str_returns_expected() {
+        let policy = Policy::from_str("strict: approval-threshold=1000, coalesce").unwrap();
+
+        assert_eq!("strict", policy.name);
+        assert_eq!(Some(amount("1000")), policy.approval_threshold);
+        assert_eq!(true, policy.config.coalesce);
+    }
+
+    #[test]
+    fn policy_from_str_unknown_setting_returns_err() {
+        assert_eq!(true, Policy::from_str("strict:overdraft").is_err());
+        assert_eq!(true, Policy::from_str(":coalesce").is_err());
+    }
+
+    #[test]
+    fn run_compares_policies() {
+        let policies = vec![
+            Policy::from_str("baseline").unwrap(),
+            Policy::from_str("strict:approval-threshold=1000").unwrap(),
+        ];
+
+        let outcomes = run(&transactions(), &policies).unwrap();
+
+        // The baseline applies the large deposit, so the withdrawal succeeds before the chargeback.
+        assert_eq!(amount("1990"), outcomes[0].total);
+        assert_eq!(0, outcomes[0].rejected);
+        assert_eq!(amount("5"), outcomes[0].chargeback_losses);
+        assert_eq!(1, outcomes[0].locked_accounts);
+
+        // The strict policy parks the large deposit, so the withdrawal is rejected.
+        assert_eq!(Amount::zero(), outcomes[1].total);
+        assert_eq!(1, outcomes[1].parked);
+        assert_eq!(1, outcomes[1].rejected);
+        assert_eq!(amount("5"), outcomes[1].chargeback_losses);
+    }
+
+    #[test]
+    fn run_counts_soft_limit_warnings() {
+        let policies = vec![Policy::from_str("soft:soft-limit=1000").unwrap()];
+
+        let outcomes = run(&transactions(), &policies).unwrap();
+
+        assert_eq!(1, outcomes[0].warnings);
+        assert_eq!(amount("1990"), outcomes[0].total);
+    }
+
+    #[test]
+    fn write_report_returns_expected() {
+        let outcomes = run(&transactions(), &[Policy::from_str("baseline").unwrap()]).unwrap();
+        let mut output = vec![];
+
+        write_report(&outcomes, &mut output).unwrap();
+
+        assert_eq!(
+            places("policy, accounts, available, held, total, locked, parked, rejected, warnings, chargeback_losses\nbaseline, 1, 1990.0000, 0.0000, 1990.0000, 1, 0, 0, 0, 5.0000\n"),
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use crate::{
+    client::{Client, ClientAccount},
+    database::Database,
+    snapshot::{Snapshot, SnapshotError},
+    source::InputPosition,
+};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+
+// A long batch run that crashes halfway would otherwise start over. Checkpoints are taken every
+// so many transactions and hold the accounts together with the position in the input just after
+// the last transaction applied, so a resumed run continues from there without applying anything
+// twice.
+
+/// The state of an interrupted run.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Checkpoint {
+    /// The input file the run was reading.
+    pub input_path: String,
+    pub position: InputPosition,
+    pub snapshot: Snapshot,
+}
+
+impl Checkpoint {
+    /// Reads a checkpoint from a file.
+    pub fn read_from_path(path: &str) -> Result<Self, SnapshotError> {
+        let reader = BufReader::new(File::open(path)?);
+        bincode::deserialize_from(reader).map_err(|e| SnapshotError::Codec(e))
+    }
+
+    /// Writes the checkpoint to a file. It's written beside the file and then renamed over it, so
+    /// a crash while writing leaves the previous checkpoint intact.
+    pub fn write_to_path(&self, path: &str) -> Result<(), SnapshotError> {
+        let partial = format!("{}.partial", path);
+
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        bincode::serialize_into(&mut writer, self).map_err(|e| SnapshotError::Codec(e))?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+
+        fs::rename(&partial, path)?;
+        Ok(())
+    }
+}
+
+/// Saves the progress of a run so it can be resumed.
+pub trait Checkpointer<Account>
+where
+    Account: ClientAccount,
+{
+    /// Called with the accounts once every transaction before the position has been applied.
+    fn checkpoint(
+        &mut self,
+        database: &Database<Account>,
+        position: InputPosition,
+    ) -> Result<(), SnapshotError>;
+}
+
+/// Keeps the latest checkpoint of a run over the input file in a single file.
+pub struct FileCheckpointer {
+    pub path: String,
+    pub input_path: String,
+}
+
+impl Checkpointer<Client> for FileCheckpointer {
+    fn checkpoint(
+        &mut self,
+        database: &Database<Client>,
+        position: InputPosition,
+    ) -> Result<(), SnapshotError> {
+        Checkpoint {
+            input_path: self.input_path.clone(),
+            position,
+            snapshot: database.snapshot(),
+        }
+        .write_to_path(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csv::TransactionKinds;
+    use crate::pipeline::{InputFormat, PipelineBuilder};
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+
+    /// Keeps every checkpoint taken in memory.
+    struct RecordingCheckpointer(Rc<RefCell<Vec<(InputPosition, Snapshot)>>>);
+
+    impl Checkpointer<Client> for RecordingCheckpointer {
+        fn checkpoint(
+            &mut self,
+            database: &Database<Client>,
+            position: InputPosition,
+        ) -> Result<(), SnapshotError> {
+            self.0.borrow_mut().push((position, database.snapshot()));
+            Ok(())
+        }
+    }
+
+    fn run(
+        path: &str,
+        format: InputFormat,
+        database: Database<Client>,
+        resume: Option<InputPosition>,
+        checkpoints: Rc<RefCell<Vec<(InputPosition, Snapshot)>>>,
+    ) -> Snapshot {
+        let mut pipeline = PipelineBuilder::new(database)
+            .input_path(path)
+            .format(format)
+            .output(Box::new(io::sink()))
+            .checkpoint(2, Box::new(RecordingCheckpointer(checkpoints)));
+        if let Some(position) = resume {
+            pipeline = pipeline.resume_from(position);
+        }
+        pipeline.build().unwrap().run().unwrap().snapshot()
+    }
+
+    fn resumed_runs_match_full_run(name: &str, format: InputFormat, input: &str) {
+        let path = std::env::temp_dir().join(name);
+        let path = path.to_str().unwrap();
+        fs::write(path, input).unwrap();
+
+        let checkpoints = Rc::new(RefCell::new(vec![]));
+        let full = run(path, format, Database::new(), None, checkpoints.clone());
+        let taken = checkpoints.borrow().clone();
+
+        assert_eq!(3, taken.len());
+        for (position, snapshot) in taken {
+            let database = Database::from_snapshot(snapshot).unwrap();
+            let resumed = run(path, format, database, Some(position), Rc::default());
+            assert_eq!(full, resumed, "resumed from {:?}", position);
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn pipeline_resumed_from_checkpoint_matches_full_run() {
+        resumed_runs_match_full_run(
+            "payments_checkpoint_resume.csv",
+            InputFormat::default(),
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 5.0\n\
+             deposit, 2, 2, 3.0\n\
+             \n\
+             withdrawal, 1, 3, 1.0\n\
+             dispute, 2, 2,\n\
+             deposit, 1, 4, 1.0\n\
+             chargeback, 2, 2,\n",
+        );
+        resumed_runs_match_full_run(
+            "payments_checkpoint_resume_parallel.csv",
+            InputFormat::ParallelCsv {
+                delimiter: b',',
+                has_headers: true,
+            },
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 5.0\n\
+             deposit, 2, 2, 3.0\n\
+             \n\
+             withdrawal, 1, 3, 1.0\n\
+             dispute, 2, 2,\n\
+             deposit, 1, 4, 1.0\n\
+             chargeback, 2, 2,\n",
+        );
+        resumed_runs_match_full_run(
+            "payments_checkpoint_resume.jsonl",
+            InputFormat::JsonLines,
+            "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"5.0\"}\n\
+             {\"type\": \"deposit\", \"client\": 2, \"tx\": 2, \"amount\": \"3.0\"}\n\
+             \n\
+             {\"type\": \"withdrawal\", \"client\": 1, \"tx\": 3, \"amount\": \"1.0\"}\n\
+             {\"type\": \"dispute\", \"client\": 2, \"tx\": 2}\n\
+             {\"type\": \"deposit\", \"client\": 1, \"tx\": 4, \"amount\": \"1.0\"}\n\
+             {\"type\": \"chargeback\", \"client\": 2, \"tx\": 2}\n",
+        );
+    }
+
+    #[test]
+    fn csv_source_resume_reports_file_lines() {
+        let path = std::env::temp_dir().join("payments_checkpoint_lines.csv");
+        let path = path.to_str().unwrap();
+        fs::write(
+            path,
+            "type, client, tx, amount\ndeposit, 1, 1, 5.0\n\ndeposit, x, 2, 1.0\n",
+        )
+        .unwrap();
+
+        let mut source = InputFormat::default().source(
+            Box::new(File::open(path).unwrap()),
+            false,
+            &TransactionKinds::default(),
+        );
+        source.next().unwrap().unwrap();
+        let position = source.position().unwrap();
+        assert_eq!(2, position.line);
+        assert_eq!(true, source.next().unwrap().is_err());
+
+        let mut resumed = InputFormat::default()
+            .resume(
+                File::open(path).unwrap(),
+                false,
+                &TransactionKinds::default(),
+                position,
+            )
+            .unwrap();
+        assert_eq!(true, resumed.next().unwrap().is_err());
+        assert_eq!(source.line(), resumed.line());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_write_read_round_trips() {
+        let path = std::env::temp_dir().join("payments_checkpoint_round_trip.bin");
+        let path = path.to_str().unwrap();
+
+        let checkpoint = Checkpoint {
+            input_path: String::from("transactions.csv"),
+            position: InputPosition { byte: 42, line: 3 },
+            snapshot: Database::new().snapshot(),
+        };
+        checkpoint.write_to_path(path).unwrap();
+        let loaded = Checkpoint::read_from_path(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(checkpoint, loaded);
+    }
+}
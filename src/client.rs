@@ -1,6 +1,15 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
 use crate::amount::Amount;
+use crate::clock::Timestamp;
+use crate::currency::Currency;
+use crate::event::{AccountEvent, Change};
+use crate::parse_csv::CsvTransaction;
+use crate::snapshot::{BalanceSnapshot, ClientSnapshot, SnapshotError};
 use crate::transaction::{
-    Transaction, TransactionError, TransactionId, TransactionState, TransactionType,
+    CaseId, ReasonCode, Transaction, TransactionError, TransactionId, TransactionState,
+    TransactionType,
 };
 
 pub type ClientId = u16;
@@ -12,7 +21,7 @@ pub trait ClientAccount: Clone {
     /// The amount of funds the client has available to use.
     fn available(&self) -> Amount;
 
-    /// The amount of funds held due to disputes.
+    /// The amount of funds held due to disputes and risk holds.
     fn held(&self) -> Amount;
 
     /// Whether the client is frozen or not.
@@ -21,18 +30,339 @@ pub trait ClientAccount: Clone {
     /// The total balance on the account.
     fn total(&self) -> Amount;
 
+    /// Lifetime counters for the account.
+    fn stats(&self) -> AccountStats;
+
+    /// The currency the account is kept in, once a transaction with one has been applied.
+    fn currency(&self) -> Option<Currency> {
+        None
+    }
+
+    /// A view of the account for each other currency it holds a balance in, so it can be reported
+    /// a row per currency.
+    fn other_currencies(&self) -> Vec<Self> {
+        vec![]
+    }
+
+    /// The funds available in the balance a transaction in the currency would be applied to.
+    fn available_in(&self, _currency: Option<Currency>) -> Amount {
+        self.available()
+    }
+
+    /// Lets withdrawals and fees take the available balance down to minus the limit.
+    fn set_overdraft_limit(&mut self, _limit: Amount) {}
+
     /// Attempts to execute a transaction for the client.
     fn execute_transaction(&mut self, transaction: Transaction) -> Result<(), TransactionError>;
+
+    /// How many transactions are kept in the account's history.
+    fn history_len(&self) -> usize {
+        0
+    }
+
+    /// Drops history entries that no dispute, resolve or chargeback can refer to, returning how
+    /// many were dropped.
+    fn compact_history(&mut self) -> usize {
+        0
+    }
+
+    /// Removes the oldest history entries until at most `keep` remain, returning them.
+    fn spill_history(&mut self, _keep: usize) -> Vec<(TransactionState, Transaction)> {
+        vec![]
+    }
+
+    /// Puts back an entry removed by `spill_history`.
+    fn restore_history(&mut self, _state: TransactionState, _transaction: Transaction) {}
+
+    /// Replaces the last history entry, a run of transactions applied as one, with the
+    /// transactions it was merged from, so each can be found by its own id.
+    fn unmerge_history(&mut self, _transactions: &[Transaction]) {}
 }
 
-/// A record that keeps track of a client's account.
-#[derive(Clone, Debug, PartialEq)]
-pub struct Client {
-    id: ClientId,
+/// Lifetime counters that are updated as transactions are applied.
+#[derive(Copy, Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct AccountStats {
+    /// The sum of all accepted deposits.
+    pub deposits: Amount,
+    /// The sum of all accepted withdrawals.
+    pub withdrawals: Amount,
+    /// The sum of all fees charged.
+    pub fees: Amount,
+    /// The number of accepted disputes.
+    pub disputes: u32,
+    /// The number of accepted chargebacks.
+    pub chargebacks: u32,
+    /// The latest timestamp of an accepted transaction, if any had one.
+    pub last_activity: Option<Timestamp>,
+}
+
+/// The funds a client holds in one currency, with the lifetime counters of that currency.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+struct Balance {
     available: Amount,
     held: Amount,
+    risk_holds: BTreeMap<ReasonCode, Amount>,
+    stats: AccountStats,
+}
+
+impl Balance {
+    /// The amount of funds currently held by risk for the given reason.
+    fn risk_held(&self, reason: ReasonCode) -> Amount {
+        self.risk_holds.get(&reason).copied().unwrap_or_default()
+    }
+
+    /// Adds the funds and lifetime counters of another balance to this one.
+    fn absorb(&mut self, other: &Balance) {
+        self.available = self.available + other.available;
+        self.held = self.held + other.held;
+        for (reason, amount) in other.risk_holds.iter() {
+            let risk_held = self.risk_held(*reason);
+            self.risk_holds.insert(*reason, risk_held + *amount);
+        }
+        self.stats.deposits = self.stats.deposits + other.stats.deposits;
+        self.stats.withdrawals = self.stats.withdrawals + other.stats.withdrawals;
+        self.stats.fees = self.stats.fees + other.stats.fees;
+        self.stats.disputes += other.stats.disputes;
+        self.stats.chargebacks += other.stats.chargebacks;
+        self.stats.last_activity = self.stats.last_activity.max(other.stats.last_activity);
+    }
+
+    fn to_snapshot(&self, currency: Currency) -> BalanceSnapshot {
+        BalanceSnapshot {
+            currency,
+            available: self.available.to_string(),
+            held: self.held.to_string(),
+            risk_holds: risk_holds_snapshot(&self.risk_holds),
+            lifetime_deposits: self.stats.deposits.to_string(),
+            lifetime_withdrawals: self.stats.withdrawals.to_string(),
+            dispute_count: self.stats.disputes,
+            chargeback_count: self.stats.chargebacks,
+            last_activity: self.stats.last_activity,
+            lifetime_fees: Some(self.stats.fees.to_string()),
+        }
+    }
+
+    fn from_snapshot(snapshot: &BalanceSnapshot) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            available: parse_snapshot_amount(&snapshot.available)?,
+            held: parse_snapshot_amount(&snapshot.held)?,
+            risk_holds: parse_risk_holds(&snapshot.risk_holds)?,
+            stats: AccountStats {
+                deposits: parse_snapshot_amount(&snapshot.lifetime_deposits)?,
+                withdrawals: parse_snapshot_amount(&snapshot.lifetime_withdrawals)?,
+                fees: parse_lifetime_fees(&snapshot.lifetime_fees)?,
+                disputes: snapshot.dispute_count,
+                chargebacks: snapshot.chargeback_count,
+                last_activity: snapshot.last_activity,
+            },
+        })
+    }
+}
+
+/// Left in place of a client's transaction history once it has been erased.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Tombstone {
+    /// The number of transactions removed from the history.
+    pub erased_transactions: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ErasureError {
+    /// Funds are still held, so disputes or risk holds may yet refer to the history.
+    FundsHeld { held: Amount },
+}
+
+/// A client's balances and lock state, folded from the events of its account.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct AccountState {
+    /// The balance in the client's currency, which transactions without one also use.
+    balance: Balance,
+    /// The balances in every other currency the client has transacted in.
+    balances: BTreeMap<Currency, Balance>,
     locked: bool,
+    /// The currency of the first transaction applied with one.
+    currency: Option<Currency>,
+}
+
+impl AccountState {
+    /// Applies the events to the state in order.
+    pub fn fold<'a>(mut self, events: impl IntoIterator<Item = &'a AccountEvent>) -> Self {
+        for event in events {
+            self.apply(event);
+        }
+        self
+    }
+
+    /// The amount of funds available to use.
+    pub fn available(&self) -> Amount {
+        self.balance.available
+    }
+
+    /// The amount of funds held due to disputes and risk holds.
+    pub fn held(&self) -> Amount {
+        self.balance.held
+    }
+
+    /// The total balance.
+    pub fn total(&self) -> Amount {
+        self.available() + self.held()
+    }
+
+    /// Whether the account is frozen or not.
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Lifetime counters for the account.
+    pub fn stats(&self) -> AccountStats {
+        self.balance.stats
+    }
+
+    /// The currency the account is kept in, once a transaction with one has been applied.
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    /// Applies the change an event records. Its transaction was checked before it was accepted,
+    /// so applying it can't fail.
+    fn apply(&mut self, event: &AccountEvent) {
+        let balance = self.balance_mut(event.currency);
+        match event.change {
+            Change::Deposited { amount } => {
+                balance.available = balance.available + amount;
+                balance.stats.deposits = balance.stats.deposits + amount;
+            }
+            Change::Withdrawn { amount } => {
+                balance.available = balance.available - amount;
+                balance.stats.withdrawals = balance.stats.withdrawals + amount;
+            }
+            Change::Disputed { amount, deposit } => {
+                if deposit {
+                    balance.available = balance.available - amount;
+                }
+                balance.held = balance.held + amount;
+                balance.stats.disputes += 1;
+            }
+            Change::Resolved { amount } => {
+                balance.available = balance.available + amount;
+                balance.held = balance.held - amount;
+            }
+            Change::ChargedBack { amount } => {
+                balance.held = balance.held - amount;
+                balance.stats.chargebacks += 1;
+            }
+            Change::Held { amount, reason } => {
+                balance.available = balance.available - amount;
+                balance.held = balance.held + amount;
+
+                let risk_held = balance.risk_held(reason);
+                balance.risk_holds.insert(reason, risk_held + amount);
+            }
+            Change::Released { amount, reason } => {
+                balance.available = balance.available + amount;
+                balance.held = balance.held - amount;
+
+                let risk_held = balance.risk_held(reason) - amount;
+                if risk_held == Amount::zero() {
+                    balance.risk_holds.remove(&reason);
+                } else {
+                    balance.risk_holds.insert(reason, risk_held);
+                }
+            }
+            Change::TransferSent { amount } | Change::Converted { amount, .. } => {
+                balance.available = balance.available - amount;
+            }
+            Change::TransferReceived { amount } => {
+                balance.available = balance.available + amount;
+            }
+            Change::Unlocked => {}
+            Change::FeeCharged { amount } => {
+                balance.available = balance.available - amount;
+                balance.stats.fees = balance.stats.fees + amount;
+            }
+        }
+        if event.timestamp > balance.stats.last_activity {
+            balance.stats.last_activity = event.timestamp;
+        }
+
+        match event.change {
+            Change::ChargedBack { .. } => self.locked = true,
+            Change::Unlocked => self.locked = false,
+            _ => {}
+        }
+        if self.currency.is_none() {
+            self.currency = event.currency;
+        }
+
+        // The other leg of a conversion is credited once the client's currency is known.
+        if let Change::Converted { to, converted, .. } = event.change {
+            let balance = self.balance_mut(Some(to));
+            balance.available = balance.available + converted;
+            if event.timestamp > balance.stats.last_activity {
+                balance.stats.last_activity = event.timestamp;
+            }
+        }
+    }
+
+    /// The other currency whose balance a transaction in the currency uses, or `None` for the
+    /// client's own. Until the client has a currency, every transaction uses its own balance.
+    fn balance_key(&self, currency: Option<Currency>) -> Option<Currency> {
+        match (self.currency, currency) {
+            (Some(own), Some(currency)) if own != currency => Some(currency),
+            _ => None,
+        }
+    }
+
+    /// The balance kept in the currency, if one has been opened.
+    fn balance(&self, currency: Option<Currency>) -> Option<&Balance> {
+        match self.balance_key(currency) {
+            Some(currency) => self.balances.get(&currency),
+            None => Some(&self.balance),
+        }
+    }
+
+    /// The balance kept in the currency, opening it if there's none yet.
+    fn balance_mut(&mut self, currency: Option<Currency>) -> &mut Balance {
+        match self.balance_key(currency) {
+            Some(currency) => self.balances.entry(currency).or_default(),
+            None => &mut self.balance,
+        }
+    }
+}
+
+/// A record that keeps track of a client's account. Accepted transactions are recorded as events,
+/// and its balances are the fold of those events over the state it was opened or restored in.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct Client {
+    id: ClientId,
+    /// The account as of the latest event.
+    state: AccountState,
+    /// The account before the first event, restored from a snapshot or left by an erasure or by
+    /// compacting the history.
+    base: AccountState,
+    /// Every change made to the account since `base`, oldest first.
+    events: Vec<AccountEvent>,
     transactions: Vec<(TransactionState, Transaction)>,
+    /// The position in `transactions` of the first transaction with each id.
+    transaction_indexes: HashMap<TransactionId, usize>,
+    /// The case each disputed or charged back transaction belongs to.
+    cases: HashMap<TransactionId, CaseId>,
+    tombstone: Option<Tombstone>,
+    /// How far below zero withdrawals and fees may take the available balance.
+    overdraft_limit: Amount,
+}
+
+/// Clients are equal when their accounts are, however many events those were folded from.
+impl PartialEq for Client {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+            && self.state == other.state
+            && self.transactions == other.transactions
+            && self.transaction_indexes == other.transaction_indexes
+            && self.cases == other.cases
+            && self.tombstone == other.tombstone
+            && self.overdraft_limit == other.overdraft_limit
+    }
 }
 
 impl ClientAccount for Client {
@@ -43,202 +373,657 @@ impl ClientAccount for Client {
 
     /// The amount of funds the client has available to use.
     fn available(&self) -> Amount {
-        self.available
+        self.state.available()
     }
 
-    /// The amount of funds held due to disputes.
+    /// The amount of funds held due to disputes and risk holds.
     fn held(&self) -> Amount {
-        self.held
+        self.state.held()
     }
 
     /// Whether the client is frozen or not.
     fn locked(&self) -> bool {
-        self.locked
+        self.state.locked()
     }
 
     /// The total balance on the account.
     fn total(&self) -> Amount {
-        self.available() + self.held()
+        self.state.total()
+    }
+
+    /// Lifetime counters for the account.
+    fn stats(&self) -> AccountStats {
+        self.state.stats()
+    }
+
+    /// The currency the account is kept in, once a transaction with one has been applied.
+    fn currency(&self) -> Option<Currency> {
+        self.state.currency()
+    }
+
+    fn other_currencies(&self) -> Vec<Self> {
+        self.state
+            .balances
+            .iter()
+            .map(|(currency, balance)| Self {
+                state: AccountState {
+                    balance: balance.clone(),
+                    balances: BTreeMap::new(),
+                    locked: self.state.locked,
+                    currency: Some(*currency),
+                },
+                ..Self::new(self.id)
+            })
+            .collect()
+    }
+
+    fn set_overdraft_limit(&mut self, limit: Amount) {
+        self.overdraft_limit = limit;
+    }
+
+    fn available_in(&self, currency: Option<Currency>) -> Amount {
+        self.state
+            .balance(currency)
+            .map(|balance| balance.available)
+            .unwrap_or_default()
     }
 
     /// Attempts to execute a transaction for the client.
     fn execute_transaction(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        // A transfer is executed by both the sender and the recipient.
+        let receiving = matches!(
+            transaction.transaction_type,
+            TransactionType::Transfer { to, .. } if to == self.id && transaction.client != self.id
+        );
+
         // Only apply if it matches this client
-        if transaction.client != self.id {
+        if transaction.client != self.id && !receiving {
             return Err(TransactionError::InvalidClient {
                 actual: transaction.client,
                 expected: self.id,
             });
         }
 
-        // Check if frozen
-        if self.locked {
+        // Check if frozen. Unlocking is the only thing a locked account accepts.
+        if self.state.locked && transaction.transaction_type != TransactionType::Unlock {
             return Err(TransactionError::ClientLocked);
         }
 
-        // Attempt to apply the transaction
+        // Each currency is kept in its own balance, which the transaction is checked against.
+        let currency = self.currency_of(&transaction)?;
+        let change = self.decide(transaction, currency, receiving)?;
+        let event = AccountEvent {
+            currency,
+            timestamp: transaction.timestamp,
+            change,
+        };
+        self.state.apply(&event);
+        self.events.push(event);
+
+        // It was a valid transaction, so log it
+        self.settle(transaction);
+        self.record(TransactionState::Ok, transaction);
+
+        Ok(())
+    }
+
+    /// How many transactions are kept in the client's history.
+    fn history_len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    /// Drops every entry that isn't the first with its id, such as disputes and resolves, as
+    /// lookups by id only ever find the first. The events are folded into the base state, so the
+    /// account keeps no more than its history does.
+    fn compact_history(&mut self) -> usize {
+        self.base = self.state.clone();
+        self.events.clear();
+
+        let indexes = std::mem::take(&mut self.transaction_indexes);
+        let transactions = std::mem::take(&mut self.transactions);
+        let before = transactions.len();
+
+        for (position, (state, transaction)) in transactions.into_iter().enumerate() {
+            if indexes.get(&transaction.id) == Some(&position) {
+                self.record(state, transaction);
+            }
+        }
+
+        before - self.transactions.len()
+    }
+
+    /// Compacts the history first, so every id left is only in the history once.
+    fn spill_history(&mut self, keep: usize) -> Vec<(TransactionState, Transaction)> {
+        self.compact_history();
+        if self.transactions.len() <= keep {
+            return vec![];
+        }
+
+        let spill = self.transactions.len() - keep;
+        let spilled: Vec<_> = self.transactions.drain(..spill).collect();
+        self.transaction_indexes = self
+            .transactions
+            .iter()
+            .enumerate()
+            .map(|(position, (_, transaction))| (transaction.id, position))
+            .collect();
+
+        spilled
+    }
+
+    /// The entry is what lookups by its id find again, even if its id was reused since.
+    fn restore_history(&mut self, state: TransactionState, transaction: Transaction) {
+        self.transaction_indexes
+            .insert(transaction.id, self.transactions.len());
+        self.transactions.push((state, transaction));
+    }
+
+    fn unmerge_history(&mut self, transactions: &[Transaction]) {
+        if let Some((_, merged)) = self.transactions.pop() {
+            if self.transaction_indexes.get(&merged.id) == Some(&self.transactions.len()) {
+                self.transaction_indexes.remove(&merged.id);
+            }
+        }
+        for transaction in transactions {
+            self.record(TransactionState::Ok, *transaction);
+        }
+    }
+}
+
+impl Client {
+    /// Creates a new client with the given id.
+    pub fn new(id: ClientId) -> Self {
+        Self {
+            id,
+            state: AccountState::default(),
+            base: AccountState::default(),
+            events: vec![],
+            transactions: vec![],
+            transaction_indexes: HashMap::new(),
+            cases: HashMap::new(),
+            tombstone: None,
+            overdraft_limit: Amount::zero(),
+        }
+    }
+
+    /// Every change made to the account since it was opened, restored from a snapshot, erased or
+    /// compacted, oldest first.
+    pub fn events(&self) -> &[AccountEvent] {
+        &self.events
+    }
+
+    /// The account as it was once the first `events` of its events had been applied, folded
+    /// again from the state before them.
+    pub fn as_of(&self, events: usize) -> AccountState {
+        let events = &self.events[..events.min(self.events.len())];
+        self.base.clone().fold(events)
+    }
+
+    /// The amount of funds currently held by risk for the given reason.
+    pub fn risk_held(&self, reason: ReasonCode) -> Amount {
+        self.state.balance.risk_held(reason)
+    }
+
+    /// Captures the client's balances, lock state and transaction history.
+    pub fn to_snapshot(&self) -> ClientSnapshot {
+        ClientSnapshot {
+            transactions: self
+                .transactions
+                .iter()
+                .map(|(state, transaction)| (*state, CsvTransaction::from_transaction(transaction)))
+                .collect(),
+            cases: self.cases(),
+            ..self.to_snapshot_without_history()
+        }
+    }
+
+    /// Captures the client's balances and lock state, leaving the transaction history empty.
+    pub fn to_snapshot_without_history(&self) -> ClientSnapshot {
+        let balance = &self.state.balance;
+        ClientSnapshot {
+            id: self.id,
+            available: balance.available.to_string(),
+            held: balance.held.to_string(),
+            locked: self.state.locked,
+            transactions: vec![],
+            risk_holds: risk_holds_snapshot(&balance.risk_holds),
+            lifetime_deposits: balance.stats.deposits.to_string(),
+            lifetime_withdrawals: balance.stats.withdrawals.to_string(),
+            dispute_count: balance.stats.disputes,
+            chargeback_count: balance.stats.chargebacks,
+            tombstone: self.tombstone,
+            cases: vec![],
+            last_activity: balance.stats.last_activity,
+            currency: self.state.currency,
+            balances: self
+                .state
+                .balances
+                .iter()
+                .map(|(currency, balance)| balance.to_snapshot(*currency))
+                .collect(),
+            lifetime_fees: Some(balance.stats.fees.to_string()),
+        }
+    }
+
+    /// Restores a client from a snapshot. Its events start from the restored balances.
+    pub fn from_snapshot(snapshot: ClientSnapshot) -> Result<Self, SnapshotError> {
+        let mut client = Self::new(snapshot.id);
+        client.base.balance = Balance {
+            available: parse_snapshot_amount(&snapshot.available)?,
+            held: parse_snapshot_amount(&snapshot.held)?,
+            risk_holds: parse_risk_holds(&snapshot.risk_holds)?,
+            stats: AccountStats {
+                deposits: parse_snapshot_amount(&snapshot.lifetime_deposits)?,
+                withdrawals: parse_snapshot_amount(&snapshot.lifetime_withdrawals)?,
+                fees: parse_lifetime_fees(&snapshot.lifetime_fees)?,
+                disputes: snapshot.dispute_count,
+                chargebacks: snapshot.chargeback_count,
+                last_activity: snapshot.last_activity,
+            },
+        };
+        for balance in snapshot.balances.iter() {
+            client
+                .base
+                .balances
+                .insert(balance.currency, Balance::from_snapshot(balance)?);
+        }
+        client.base.locked = snapshot.locked;
+        client.base.currency = snapshot.currency;
+        client.state = client.base.clone();
+
+        for (state, record) in snapshot.transactions {
+            let tx = record.tx;
+            match record.into_transaction() {
+                Ok(Some(transaction)) => client.record(state, transaction),
+                _ => return Err(SnapshotError::InvalidTransaction { tx }),
+            }
+        }
+
+        client.tombstone = snapshot.tombstone;
+        client.cases = snapshot.cases.into_iter().collect();
+
+        Ok(client)
+    }
+
+    /// Set once the client's transaction history has been erased.
+    pub fn tombstone(&self) -> Option<Tombstone> {
+        self.tombstone
+    }
+
+    /// Removes the transaction history of a closed client, keeping its balances and lifetime
+    /// counters for accounting. A client is closed once no funds are held for it. The events
+    /// are erased along with the history, so the account is folded from its balances onwards.
+    pub fn erase(&mut self) -> Result<Tombstone, ErasureError> {
+        let held = self
+            .state
+            .balances
+            .values()
+            .fold(self.state.balance.held, |held, balance| held + balance.held);
+        if held != Amount::zero() {
+            return Err(ErasureError::FundsHeld { held });
+        }
+
+        let previously_erased = self
+            .tombstone
+            .map(|tombstone| tombstone.erased_transactions)
+            .unwrap_or_default();
+        let tombstone = Tombstone {
+            erased_transactions: previously_erased + self.transactions.len() as u64,
+        };
+
+        self.transactions.clear();
+        self.transaction_indexes.clear();
+        self.cases.clear();
+        self.base = self.state.clone();
+        self.events.clear();
+        self.tombstone = Some(tombstone);
+
+        Ok(tombstone)
+    }
+
+    /// Combines the account of the same client from a run over other transactions, summing the
+    /// balances and lifetime counters and keeping both histories. The account is locked if either
+    /// was, and its events start from the combined balances.
+    pub fn merge(&mut self, other: Client) {
+        if self.state.currency.is_none() {
+            self.state.currency = other.state.currency;
+        }
+        self.state
+            .balance_mut(other.state.currency)
+            .absorb(&other.state.balance);
+        for (currency, balance) in other.state.balances.iter() {
+            self.state.balance_mut(Some(*currency)).absorb(balance);
+        }
+        self.state.locked = self.state.locked || other.state.locked;
+        self.base = self.state.clone();
+        self.events.clear();
+
+        for (state, transaction) in other.transactions {
+            self.record(state, transaction);
+        }
+        self.cases.extend(other.cases);
+        self.tombstone = match (self.tombstone, other.tombstone) {
+            (None, None) => None,
+            (tombstone, other) => Some(Tombstone {
+                erased_transactions: tombstone.map_or(0, |t| t.erased_transactions)
+                    + other.map_or(0, |t| t.erased_transactions),
+            }),
+        };
+    }
+
+    /// The currency of the funds the transaction moves. A dispute, resolve or chargeback moves
+    /// those of the transaction it refers to, so it can't name a different currency, and an
+    /// unlock moves none.
+    fn currency_of(&self, transaction: &Transaction) -> Result<Option<Currency>, TransactionError> {
+        let original = match transaction.transaction_type {
+            TransactionType::Unlock => return Ok(None),
+            _ if transaction.introduces_id() => return Ok(transaction.currency),
+            _ => match self.transaction_index(transaction.id) {
+                Some(index) => self.transactions[index].1.currency,
+                None => return Ok(transaction.currency),
+            },
+        };
+
+        match (transaction.currency, original.or(self.state.currency)) {
+            (Some(passed), Some(expected))
+                if self.state.balance_key(Some(passed))
+                    != self.state.balance_key(Some(expected)) =>
+            {
+                Err(TransactionError::CurrencyMismatch { expected, passed })
+            }
+            _ => Ok(original),
+        }
+    }
+
+    /// Checks the transaction against the balance of its currency, returning the change it makes
+    /// if it's accepted.
+    fn decide(
+        &self,
+        transaction: Transaction,
+        currency: Option<Currency>,
+        receiving: bool,
+    ) -> Result<Change, TransactionError> {
+        let balance = self.state.balance(currency);
+        let available = balance.map(|balance| balance.available).unwrap_or_default();
+
         match transaction.transaction_type {
             TransactionType::Deposit(amount) => {
                 if amount.less_than_zero() {
                     return Err(TransactionError::InvalidDeposit { amount });
                 }
 
-                self.available = self.available + amount;
+                Ok(Change::Deposited { amount })
             }
             TransactionType::Withdrawal(amount) => {
-                let diff = self.available - amount;
+                let diff = available - amount;
 
-                if amount.less_than_zero() || diff.less_than_zero() {
+                if amount.less_than_zero() || (diff + self.overdraft_limit).less_than_zero() {
                     return Err(TransactionError::InvalidWithdrawal {
                         resulting_amount: diff,
                     });
                 }
 
-                self.available = diff;
+                Ok(Change::Withdrawn { amount })
             }
-            TransactionType::Dispute => match self.transaction_index(transaction.id) {
-                Some(transaction_index) => {
-                    let (state, transaction) = self.transactions[transaction_index];
-
-                    match state {
-                        TransactionState::Ok => {
-                            let disputed_amount = transaction.amount().unwrap_or_default();
-
-                            match transaction.transaction_type {
-                                TransactionType::Deposit(amount) => {
-                                    self.available = self.available - disputed_amount;
-                                }
-                                TransactionType::Withdrawal(amount) => {}
-                                _ => {}
-                            }
-
-                            self.held = self.held + disputed_amount;
-
-                            self.transactions[transaction_index] =
-                                (TransactionState::Disputed, transaction);
-                        }
-                        _ => {
-                            return Err(TransactionError::Unprocessable {
-                                current_state: state,
-                                required_state: TransactionState::Ok,
-                            });
-                        }
-                    }
+            TransactionType::Dispute { .. } => {
+                let disputed = self.referenced(transaction.id, TransactionState::Ok)?;
+                Ok(Change::Disputed {
+                    amount: disputed.amount().unwrap_or_default(),
+                    deposit: matches!(disputed.transaction_type, TransactionType::Deposit(_)),
+                })
+            }
+            TransactionType::Resolve => {
+                let disputed = self.referenced(transaction.id, TransactionState::Disputed)?;
+                Ok(Change::Resolved {
+                    amount: disputed.amount().unwrap_or_default(),
+                })
+            }
+            TransactionType::Chargeback { .. } => {
+                let disputed = self.referenced(transaction.id, TransactionState::Disputed)?;
+                Ok(Change::ChargedBack {
+                    amount: disputed.amount().unwrap_or_default(),
+                })
+            }
+            TransactionType::Hold { amount, reason } => {
+                let diff = available - amount;
+
+                if amount.less_than_zero() || diff.less_than_zero() {
+                    return Err(TransactionError::InvalidHold {
+                        resulting_amount: diff,
+                    });
                 }
-                None => {
-                    return Err(TransactionError::NotFound {
-                        transaction_id: transaction.id,
+
+                Ok(Change::Held { amount, reason })
+            }
+            TransactionType::Release { amount, reason } => {
+                let risk_held = balance
+                    .map(|balance| balance.risk_held(reason))
+                    .unwrap_or_default();
+                let diff = risk_held - amount;
+
+                if amount.less_than_zero() || diff.less_than_zero() {
+                    return Err(TransactionError::InvalidRelease {
+                        reason,
+                        resulting_amount: diff,
                     });
                 }
-            },
-            TransactionType::Resolve => match self.transaction_index(transaction.id) {
-                Some(transaction_index) => {
-                    let (state, transaction) = self.transactions[transaction_index];
-                    match state {
-                        TransactionState::Disputed => {
-                            let disputed_amount = transaction.amount().unwrap_or_default();
-                            self.available = self.available + disputed_amount;
-                            self.held = self.held - disputed_amount;
-
-                            self.transactions[transaction_index] =
-                                (TransactionState::Ok, transaction);
-                        }
-                        _ => {
-                            return Err(TransactionError::Unprocessable {
-                                current_state: state,
-                                required_state: TransactionState::Disputed,
-                            });
-                        }
+
+                Ok(Change::Released { amount, reason })
+            }
+            TransactionType::Transfer { to, amount } => {
+                if to == transaction.client {
+                    return Err(TransactionError::TransferToSelf);
+                }
+
+                // The sender has already been debited when the recipient is credited, so a
+                // credit is never rejected for leaving an overdrawn recipient below zero.
+                if receiving {
+                    if amount.less_than_zero() {
+                        return Err(TransactionError::InvalidTransfer {
+                            resulting_amount: available + amount,
+                        });
                     }
+                    return Ok(Change::TransferReceived { amount });
                 }
-                None => {
-                    return Err(TransactionError::NotFound {
-                        transaction_id: transaction.id,
-                    });
+
+                let resulting_amount = available - amount;
+                if amount.less_than_zero() || resulting_amount.less_than_zero() {
+                    return Err(TransactionError::InvalidTransfer { resulting_amount });
                 }
-            },
-            TransactionType::Chargeback => match self.transaction_index(transaction.id) {
-                Some(transaction_index) => {
-                    let (state, transaction) = self.transactions[transaction_index];
-                    match state {
-                        TransactionState::Disputed => {
-                            self.locked = true;
-
-                            let disputed_amount = transaction.amount().unwrap_or_default();
-                            self.held = self.held - disputed_amount;
-
-                            self.transactions[transaction_index] =
-                                (TransactionState::Chargebacked, transaction);
-                        }
-                        _ => {
-                            return Err(TransactionError::Unprocessable {
-                                current_state: state,
-                                required_state: TransactionState::Disputed,
-                            });
-                        }
-                    }
+
+                Ok(Change::TransferSent { amount })
+            }
+            TransactionType::Unlock => {
+                if !self.state.locked {
+                    return Err(TransactionError::NotLocked);
+                }
+
+                Ok(Change::Unlocked)
+            }
+            TransactionType::Convert {
+                amount,
+                to,
+                converted,
+            } => {
+                let from = transaction.currency.or(self.state.currency);
+                if from == Some(to) {
+                    return Err(TransactionError::ConvertToSameCurrency);
                 }
-                None => {
-                    return Err(TransactionError::NotFound {
-                        transaction_id: transaction.id,
+                let converted = match (from, converted) {
+                    (Some(_), Some(converted)) => converted,
+                    _ => return Err(TransactionError::RateUnavailable { from, to }),
+                };
+
+                let diff = available - amount;
+                if amount.less_than_zero() || converted.less_than_zero() || diff.less_than_zero() {
+                    return Err(TransactionError::InvalidConversion {
+                        resulting_amount: diff,
+                    });
+                }
+
+                Ok(Change::Converted {
+                    amount,
+                    to,
+                    converted,
+                })
+            }
+            TransactionType::Fee { amount } => {
+                let diff = available - amount;
+
+                if amount.less_than_zero() || (diff + self.overdraft_limit).less_than_zero() {
+                    return Err(TransactionError::InvalidFee {
+                        resulting_amount: diff,
                     });
                 }
+
+                Ok(Change::FeeCharged { amount })
+            }
+        }
+    }
+
+    /// The transaction a dispute, resolve or chargeback refers to, if it's in the required state.
+    fn referenced(
+        &self,
+        transaction_id: TransactionId,
+        required_state: TransactionState,
+    ) -> Result<Transaction, TransactionError> {
+        match self.transaction_index(transaction_id) {
+            Some(index) => match self.transactions[index] {
+                (state, transaction) if state == required_state => Ok(transaction),
+                (current_state, _) => Err(TransactionError::Unprocessable {
+                    current_state,
+                    required_state,
+                }),
             },
+            None => Err(TransactionError::NotFound { transaction_id }),
         }
+    }
 
-        // It was a valid transaction, so log it
-        self.transactions.push((TransactionState::Ok, transaction));
+    /// Moves the transaction an accepted dispute, resolve or chargeback refers to into its new
+    /// state, keeping the case it's under.
+    fn settle(&mut self, transaction: Transaction) {
+        let (state, case) = match transaction.transaction_type {
+            TransactionType::Dispute { case } => (TransactionState::Disputed, case),
+            TransactionType::Resolve => (TransactionState::Ok, None),
+            TransactionType::Chargeback { case } => (TransactionState::Chargebacked, case),
+            _ => return,
+        };
+        if let Some(index) = self.transaction_index(transaction.id) {
+            self.transactions[index].0 = state;
+        }
+        match (state, case) {
+            (TransactionState::Ok, _) => {
+                self.cases.remove(&transaction.id);
+            }
+            (_, Some(case)) => {
+                self.cases.insert(transaction.id, case);
+            }
+            _ => {}
+        }
+    }
 
-        Ok(())
+    /// Appends a transaction to the history, indexing it if it's the first with its id.
+    fn record(&mut self, state: TransactionState, transaction: Transaction) {
+        self.transaction_indexes
+            .entry(transaction.id)
+            .or_insert(self.transactions.len());
+        self.transactions.push((state, transaction));
     }
-}
 
-impl Client {
-    /// Creates a new client with the given id.
-    pub fn new(id: ClientId) -> Self {
-        Self {
-            id,
-            available: Amount::zero(),
-            held: Amount::zero(),
-            locked: false,
-            transactions: vec![],
-        }
+    /// Every transaction applied to the client with its current state, in order.
+    pub fn history(&self) -> &[(TransactionState, Transaction)] {
+        &self.transactions
+    }
+
+    /// The case the transaction was disputed or charged back under, if one was given.
+    pub fn case(&self, transaction_id: TransactionId) -> Option<CaseId> {
+        self.cases.get(&transaction_id).copied()
+    }
+
+    /// Every case held, by transaction id.
+    pub fn cases(&self) -> Vec<(TransactionId, CaseId)> {
+        let mut cases: Vec<_> = self.cases.iter().map(|(id, case)| (*id, *case)).collect();
+        cases.sort_unstable();
+        cases
+    }
+
+    /// The transactions in the history that are under dispute, with their case. Entries moved to
+    /// a spill file aren't included.
+    pub fn pending_disputes(&self) -> impl Iterator<Item = (&Transaction, Option<CaseId>)> + '_ {
+        self.transactions
+            .iter()
+            .filter(|(state, _)| *state == TransactionState::Disputed)
+            .map(move |(_, transaction)| (transaction, self.case(transaction.id)))
+    }
+
+    /// The position in the history of the first transaction with the given id.
+    pub fn history_index(&self, transaction_id: TransactionId) -> Option<usize> {
+        self.transaction_indexes.get(&transaction_id).copied()
+    }
+
+    /// The ids of every transaction in the history other than disputes, resolves and chargebacks,
+    /// leaving out transfers received from other clients.
+    pub fn transaction_ids(&self) -> impl Iterator<Item = TransactionId> + '_ {
+        let id = self.id;
+        self.transactions
+            .iter()
+            .filter(move |(_, transaction)| transaction.introduces_id() && transaction.client == id)
+            .map(|(_, transaction)| transaction.id)
     }
 
     fn transaction_index(&self, transaction_id: TransactionId) -> Option<usize> {
-        for (i, (_, transaction)) in self.transactions.iter().enumerate() {
-            if transaction.id == transaction_id {
-                // Ignore anything that isn't a deposit or withdrawal
-                match transaction.transaction_type {
-                    TransactionType::Deposit(_) => {}
-                    TransactionType::Withdrawal(_) => {}
-                    _ => {
-                        return None;
-                    }
-                }
+        let index = *self.transaction_indexes.get(&transaction_id)?;
 
-                return Some(i);
-            }
+        // Ignore anything that isn't a deposit or withdrawal
+        match self.transactions[index].1.transaction_type {
+            TransactionType::Deposit(_) => Some(index),
+            TransactionType::Withdrawal(_) => Some(index),
+            _ => None,
         }
+    }
+}
 
-        None
+fn parse_snapshot_amount(amount: &str) -> Result<Amount, SnapshotError> {
+    Amount::from_str(amount).map_err(|_| SnapshotError::InvalidAmount {
+        passed: String::from(amount),
+    })
+}
+
+fn risk_holds_snapshot(risk_holds: &BTreeMap<ReasonCode, Amount>) -> Vec<(ReasonCode, String)> {
+    risk_holds
+        .iter()
+        .map(|(reason, amount)| (*reason, amount.to_string()))
+        .collect()
+}
+
+fn parse_risk_holds(
+    risk_holds: &[(ReasonCode, String)],
+) -> Result<BTreeMap<ReasonCode, Amount>, SnapshotError> {
+    risk_holds
+        .iter()
+        .map(|(reason, amount)| Ok((*reason, parse_snapshot_amount(amount)?)))
+        .collect()
+}
+
+/// Snapshots taken before fees were charged have none.
+fn parse_lifetime_fees(fees: &Option<String>) -> Result<Amount, SnapshotError> {
+    match fees {
+        Some(fees) => parse_snapshot_amount(fees),
+        None => Ok(Amount::zero()),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixtures;
 
     fn create_transaction(
         client: &Client,
         transaction_id: TransactionId,
         transaction_type: TransactionType,
     ) -> Transaction {
-        Transaction {
-            client: client.id,
-            id: transaction_id,
-            transaction_type,
-        }
+        fixtures::transaction(client.id, transaction_id, transaction_type)
     }
 
     fn create_deposit(client: &Client, amount: Amount) -> Transaction {
@@ -250,15 +1035,15 @@ mod tests {
     }
 
     fn create_dispute(client: &Client, id: TransactionId) -> Transaction {
-        create_transaction(client, id, TransactionType::Dispute)
+        fixtures::dispute(client.id, id)
     }
 
     fn create_resolve(client: &Client, id: TransactionId) -> Transaction {
-        create_transaction(client, id, TransactionType::Resolve)
+        fixtures::resolve(client.id, id)
     }
 
     fn create_chargeback(client: &Client, id: TransactionId) -> Transaction {
-        create_transaction(client, id, TransactionType::Chargeback)
+        fixtures::chargeback(client.id, id)
     }
 
     #[test]
@@ -281,7 +1066,7 @@ mod tests {
         let chargeback = create_chargeback(&client, deposit2.id);
         let _result = client.execute_transaction(chargeback);
 
-        assert_eq!(deposit1_amount, client.available);
+        assert_eq!(deposit1_amount, client.state.balance.available);
         assert_eq!(deposit1_amount, client.total());
     }
 
@@ -299,60 +1084,171 @@ mod tests {
         deposit2.id = 5;
         let _result = client.execute_transaction(deposit2);
 
-        let withdrawal_amount = Amount::new(15000);
-        let mut withdrawal = create_withdrawal(&client, withdrawal_amount);
-        withdrawal.id = 7;
-        let _result = client.execute_transaction(withdrawal);
+        let withdrawal_amount = Amount::new(15000);
+        let mut withdrawal = create_withdrawal(&client, withdrawal_amount);
+        withdrawal.id = 7;
+        let _result = client.execute_transaction(withdrawal);
+
+        let dispute = create_dispute(&client, withdrawal.id);
+        let _result = client.execute_transaction(dispute);
+
+        assert_eq!(
+            deposit1_amount + deposit2_amount - withdrawal_amount,
+            client.state.balance.available
+        );
+        assert_eq!(withdrawal_amount, client.state.balance.held);
+        assert_eq!(deposit1_amount + deposit2_amount, client.total());
+
+        let chargeback = create_chargeback(&client, withdrawal.id);
+        let _result = client.execute_transaction(chargeback);
+
+        assert_eq!(
+            deposit1_amount + deposit2_amount - withdrawal_amount,
+            client.state.balance.available
+        );
+        assert_eq!(Amount::zero(), client.state.balance.held);
+        assert_eq!(
+            deposit1_amount + deposit2_amount - withdrawal_amount,
+            client.total()
+        );
+    }
+
+    #[test]
+    fn client_transaction_index_not_found_returns_none() {
+        let transaction_id = 100000;
+        let client = Client::new(4482);
+
+        assert_eq!(true, client.transaction_index(transaction_id).is_none())
+    }
+
+    #[test]
+    fn client_transaction_index_found_returns_expected() {
+        let mut client = Client::new(4482);
+
+        let deposit = create_deposit(&client, Amount::new(40000));
+        client.execute_transaction(deposit).unwrap();
+        let deposit_index = 0;
+
+        let withdrawal = create_withdrawal(&client, Amount::new(40000));
+        client.execute_transaction(withdrawal).unwrap();
+        let withdrawal_index = 1;
+
+        assert_eq!(Some(deposit_index), client.transaction_index(deposit.id));
+        assert_eq!(
+            Some(withdrawal_index),
+            client.transaction_index(withdrawal.id)
+        )
+    }
+
+    #[test]
+    fn client_transaction_index_duplicate_id_returns_first() {
+        let mut client = Client::new(4482);
+
+        let deposit = create_deposit(&client, Amount::new(40000));
+        client.execute_transaction(deposit).unwrap();
+        client.execute_transaction(deposit).unwrap();
+
+        assert_eq!(Some(0), client.transaction_index(deposit.id));
+    }
+
+    #[test]
+    fn client_from_snapshot_rebuilds_transaction_index() {
+        let mut client = Client::new(4482);
+
+        let deposit = create_deposit(&client, Amount::new(40000));
+        client.execute_transaction(deposit).unwrap();
+        let withdrawal = create_withdrawal(&client, Amount::new(10000));
+        client.execute_transaction(withdrawal).unwrap();
 
-        let dispute = create_dispute(&client, withdrawal.id);
-        let _result = client.execute_transaction(dispute);
+        let restored = Client::from_snapshot(client.to_snapshot()).unwrap();
+        assert_eq!(Some(1), restored.transaction_index(withdrawal.id));
+    }
 
-        assert_eq!(
-            deposit1_amount + deposit2_amount - withdrawal_amount,
-            client.available
-        );
-        assert_eq!(withdrawal_amount, client.held);
-        assert_eq!(deposit1_amount + deposit2_amount, client.total());
+    #[test]
+    fn client_erase_removes_history_keeps_balances() {
+        let mut client = Client::new(4482);
 
-        let chargeback = create_chargeback(&client, withdrawal.id);
-        let _result = client.execute_transaction(chargeback);
+        let deposit = create_deposit(&client, Amount::new(40000));
+        client.execute_transaction(deposit).unwrap();
+        let withdrawal = create_withdrawal(&client, Amount::new(10000));
+        client.execute_transaction(withdrawal).unwrap();
 
+        let tombstone = client.erase().unwrap();
+
+        assert_eq!(2, tombstone.erased_transactions);
+        assert_eq!(Some(tombstone), client.tombstone());
+        assert_eq!(Amount::new(30000), client.available());
+        assert_eq!(Amount::new(40000), client.stats().deposits);
+        assert_eq!(None, client.transaction_index(deposit.id));
         assert_eq!(
-            deposit1_amount + deposit2_amount - withdrawal_amount,
-            client.available
+            Some(tombstone),
+            Client::from_snapshot(client.to_snapshot())
+                .unwrap()
+                .tombstone()
         );
-        assert_eq!(Amount::zero(), client.held);
+    }
+
+    #[test]
+    fn client_compact_history_keeps_first_with_each_id() {
+        let mut client = Client::new(1);
+        let deposit = create_deposit(&client, Amount::new(100));
+        client.execute_transaction(deposit).unwrap();
+        client
+            .execute_transaction(create_dispute(&client, deposit.id))
+            .unwrap();
+        client
+            .execute_transaction(create_resolve(&client, deposit.id))
+            .unwrap();
+
+        assert_eq!(2, client.compact_history());
+        assert_eq!(vec![(TransactionState::Ok, deposit)], client.history());
+        assert_eq!(0, client.events().len());
+        assert_eq!(client.state, client.as_of(0));
         assert_eq!(
-            deposit1_amount + deposit2_amount - withdrawal_amount,
-            client.total()
+            Ok(()),
+            client.execute_transaction(create_dispute(&client, deposit.id))
         );
     }
 
     #[test]
-    fn client_transaction_index_not_found_returns_none() {
-        let transaction_id = 100000;
-        let client = Client::new(4482);
-
-        assert_eq!(true, client.transaction_index(transaction_id).is_none())
+    fn client_spill_history_then_restore_keeps_state() {
+        let mut client = Client::new(1);
+        let first = create_transaction(&client, 1, TransactionType::Deposit(Amount::new(100)));
+        let second = create_transaction(&client, 2, TransactionType::Deposit(Amount::new(50)));
+        client.execute_transaction(first).unwrap();
+        client.execute_transaction(second).unwrap();
+        client
+            .execute_transaction(create_dispute(&client, 1))
+            .unwrap();
+
+        let spilled = client.spill_history(1);
+        assert_eq!(vec![(TransactionState::Disputed, first)], spilled);
+        assert_eq!(None, client.history_index(1));
+        assert_eq!(Some(0), client.history_index(2));
+
+        client.restore_history(spilled[0].0, spilled[0].1);
+        client
+            .execute_transaction(create_resolve(&client, 1))
+            .unwrap();
+        assert_eq!(Amount::new(150), client.available());
     }
 
     #[test]
-    fn client_transaction_index_found_returns_expected() {
+    fn client_erase_with_held_funds_returns_err() {
         let mut client = Client::new(4482);
 
         let deposit = create_deposit(&client, Amount::new(40000));
         client.execute_transaction(deposit).unwrap();
-        let deposit_index = 0;
-
-        let withdrawal = create_withdrawal(&client, Amount::new(40000));
-        client.execute_transaction(withdrawal).unwrap();
-        let withdrawal_index = 1;
+        client
+            .execute_transaction(create_dispute(&client, deposit.id))
+            .unwrap();
 
-        assert_eq!(Some(deposit_index), client.transaction_index(deposit.id));
         assert_eq!(
-            Some(withdrawal_index),
-            client.transaction_index(withdrawal.id)
-        )
+            Err(ErasureError::FundsHeld {
+                held: Amount::new(40000)
+            }),
+            client.erase()
+        );
     }
 
     #[test]
@@ -363,9 +1259,9 @@ mod tests {
         let resolve = create_resolve(&client, 2);
         let chargeback = create_chargeback(&client, 3);
 
-        client.transactions.push((TransactionState::Ok, dispute));
-        client.transactions.push((TransactionState::Ok, resolve));
-        client.transactions.push((TransactionState::Ok, chargeback));
+        client.record(TransactionState::Ok, dispute);
+        client.record(TransactionState::Ok, resolve);
+        client.record(TransactionState::Ok, chargeback);
 
         assert_eq!(None, client.transaction_index(dispute.id));
         assert_eq!(None, client.transaction_index(resolve.id));
@@ -376,7 +1272,7 @@ mod tests {
     fn client_execute_transaction_dispute_deposit_holds_funds_changes_state() {
         let mut client = Client::new(4453);
         let initial = Amount::new(9921);
-        client.available = initial;
+        client.state.balance.available = initial;
 
         let amount = Amount::new(444438097);
         let deposit = create_deposit(&client, amount);
@@ -388,15 +1284,40 @@ mod tests {
 
         assert_eq!(true, result.is_ok());
         assert_eq!(TransactionState::Disputed, client.transactions[0].0);
-        assert_eq!(amount, client.held);
-        assert_eq!(initial, client.available);
+        assert_eq!(amount, client.state.balance.held);
+        assert_eq!(initial, client.state.balance.available);
         assert_eq!(total, client.total());
     }
+    #[test]
+    fn client_execute_transaction_dispute_case_kept_until_resolved() {
+        let mut client = Client::new(4453);
+        let deposit = create_deposit(&client, Amount::new(10000));
+        client.execute_transaction(deposit).unwrap();
+
+        let dispute = create_transaction(
+            &client,
+            deposit.id,
+            TransactionType::Dispute { case: Some(9001) },
+        );
+        client.execute_transaction(dispute).unwrap();
+        let pending: Vec<_> = client.pending_disputes().collect();
+        assert_eq!(vec![(&deposit, Some(9001))], pending);
+
+        let restored = Client::from_snapshot(client.to_snapshot()).unwrap();
+        assert_eq!(Some(9001), restored.case(deposit.id));
+
+        client
+            .execute_transaction(create_resolve(&client, deposit.id))
+            .unwrap();
+        assert_eq!(None, client.case(deposit.id));
+        assert_eq!(0, client.pending_disputes().count());
+    }
+
     #[test]
     fn client_execute_transaction_dispute_withdrawal_holds_funds_changes_state() {
         let mut client = Client::new(4453);
         let initial = Amount::new(9921);
-        client.available = initial;
+        client.state.balance.available = initial;
 
         let amount = Amount::new(9921);
         let withdrawal = create_withdrawal(&client, amount);
@@ -407,8 +1328,8 @@ mod tests {
 
         assert_eq!(true, result.is_ok());
         assert_eq!(TransactionState::Disputed, client.transactions[0].0);
-        assert_eq!(amount, client.held);
-        assert_eq!(initial - amount, client.available);
+        assert_eq!(amount, client.state.balance.held);
+        assert_eq!(initial - amount, client.state.balance.available);
         assert_eq!(initial, client.total());
     }
 
@@ -418,7 +1339,7 @@ mod tests {
         for state in states {
             let mut client = Client::new(4453);
             let initial = Amount::new(9921);
-            client.available = initial;
+            client.state.balance.available = initial;
 
             let amount = Amount::new(444438097);
             let deposit = create_deposit(&client, amount);
@@ -447,7 +1368,7 @@ mod tests {
     fn client_execute_transaction_resolve_deposit_releases_funds_changes_state() {
         let mut client = Client::new(4453);
         let initial = Amount::new(9921);
-        client.available = initial;
+        client.state.balance.available = initial;
 
         let amount = Amount::new(444438097);
         let deposit = create_deposit(&client, amount);
@@ -462,15 +1383,15 @@ mod tests {
 
         assert_eq!(true, result.is_ok());
         assert_eq!(TransactionState::Ok, client.transactions[0].0);
-        assert_eq!(Amount::zero(), client.held);
-        assert_eq!(total, client.available);
+        assert_eq!(Amount::zero(), client.state.balance.held);
+        assert_eq!(total, client.state.balance.available);
         assert_eq!(total, client.total());
     }
     #[test]
     fn client_execute_transaction_resolve_withdrawal_holds_funds_changes_state() {
         let mut client = Client::new(4453);
         let initial = Amount::new(9921);
-        client.available = initial;
+        client.state.balance.available = initial;
 
         let amount = Amount::new(33);
         let withdrawal = create_withdrawal(&client, amount);
@@ -484,8 +1405,8 @@ mod tests {
 
         assert_eq!(true, result.is_ok());
         assert_eq!(TransactionState::Ok, client.transactions[0].0);
-        assert_eq!(Amount::zero(), client.held);
-        assert_eq!(initial, client.available);
+        assert_eq!(Amount::zero(), client.state.balance.held);
+        assert_eq!(initial, client.state.balance.available);
         assert_eq!(initial, client.total());
     }
 
@@ -495,7 +1416,7 @@ mod tests {
         for state in states {
             let mut client = Client::new(4453);
             let initial = Amount::new(9921);
-            client.available = initial;
+            client.state.balance.available = initial;
 
             let amount = Amount::new(444438097);
             let deposit = create_deposit(&client, amount);
@@ -544,7 +1465,7 @@ mod tests {
     fn client_execute_transaction_dispute_is_locked_returns_err() {
         let mut client = Client::new(4482);
 
-        client.locked = true;
+        client.state.locked = true;
 
         let transaction = create_dispute(&client, 29292);
         let result = client.execute_transaction(transaction);
@@ -581,7 +1502,7 @@ mod tests {
     fn client_execute_transaction_resolve_is_locked_returns_err() {
         let mut client = Client::new(4482);
 
-        client.locked = true;
+        client.state.locked = true;
 
         let transaction = create_resolve(&client, 29292);
         let result = client.execute_transaction(transaction);
@@ -596,7 +1517,7 @@ mod tests {
     fn client_execute_transaction_chargeback_deposit_releases_funds_changes_state() {
         let mut client = Client::new(4453);
         let initial = Amount::new(9921);
-        client.available = initial;
+        client.state.balance.available = initial;
 
         let amount = Amount::new(444438097);
         let deposit = create_deposit(&client, amount);
@@ -610,21 +1531,20 @@ mod tests {
         let result = client.execute_transaction(chargeback);
 
         assert_eq!(true, result.is_ok());
-        assert_eq!(true, client.locked);
+        assert_eq!(true, client.state.locked);
         assert_eq!(TransactionState::Chargebacked, client.transactions[0].0);
-        assert_eq!(Amount::zero(), client.held);
+        assert_eq!(Amount::zero(), client.state.balance.held);
         assert_eq!(total - amount, client.total());
     }
     #[test]
     fn client_execute_transaction_chargeback_withdrawal_holds_funds_changes_state() {
         let mut client = Client::new(4453);
         let initial = Amount::new(9921);
-        client.available = initial;
+        client.state.balance.available = initial;
 
         let amount = Amount::new(33);
         let withdrawal = create_withdrawal(&client, amount);
         client.execute_transaction(withdrawal).unwrap();
-        let total = client.total();
 
         let dispute = create_dispute(&client, withdrawal.id);
         let _result = client.execute_transaction(dispute);
@@ -633,9 +1553,9 @@ mod tests {
         let result = client.execute_transaction(chargeback);
 
         assert_eq!(true, result.is_ok());
-        assert_eq!(true, client.locked);
+        assert_eq!(true, client.state.locked);
         assert_eq!(TransactionState::Chargebacked, client.transactions[0].0);
-        assert_eq!(Amount::zero(), client.held);
+        assert_eq!(Amount::zero(), client.state.balance.held);
         assert_eq!(initial - amount, client.total());
     }
 
@@ -645,7 +1565,7 @@ mod tests {
         for state in states {
             let mut client = Client::new(4453);
             let initial = Amount::new(9921);
-            client.available = initial;
+            client.state.balance.available = initial;
 
             let amount = Amount::new(444438097);
             let deposit = create_deposit(&client, amount);
@@ -694,7 +1614,7 @@ mod tests {
     fn client_execute_transaction_chargeback_is_locked_returns_err() {
         let mut client = Client::new(4482);
 
-        client.locked = true;
+        client.state.locked = true;
 
         let transaction = create_chargeback(&client, 29292);
         let result = client.execute_transaction(transaction);
@@ -705,6 +1625,31 @@ mod tests {
         assert_eq!(0, client.transactions.len());
     }
 
+    #[test]
+    fn client_execute_transaction_unlock_locked_unlocks_records() {
+        let mut client = Client::new(4482);
+
+        client.state.locked = true;
+
+        let unlock = create_transaction(&client, 29292, TransactionType::Unlock);
+        let result = client.execute_transaction(unlock);
+
+        assert_eq!(true, result.is_ok());
+        assert_eq!(false, client.state.locked);
+        assert_eq!((TransactionState::Ok, unlock), client.transactions[0]);
+    }
+
+    #[test]
+    fn client_execute_transaction_unlock_not_locked_returns_err() {
+        let mut client = Client::new(4482);
+
+        let unlock = create_transaction(&client, 29292, TransactionType::Unlock);
+        let result = client.execute_transaction(unlock);
+
+        assert_eq!(Err(TransactionError::NotLocked), result);
+        assert_eq!(0, client.transactions.len());
+    }
+
     #[test]
     fn client_execute_transaction_withdrawal_negative_returns_err() {
         let mut client = Client::new(4482);
@@ -719,7 +1664,7 @@ mod tests {
         assert_eq!(true, result.is_err());
         let result = result.unwrap_err();
         let expected = TransactionError::InvalidWithdrawal {
-            resulting_amount: client.available - amount,
+            resulting_amount: client.state.balance.available - amount,
         };
 
         assert_eq!(expected, result);
@@ -740,7 +1685,7 @@ mod tests {
         assert_eq!(true, result.is_err());
         let result = result.unwrap_err();
         let expected = TransactionError::InvalidWithdrawal {
-            resulting_amount: client.available - amount,
+            resulting_amount: client.state.balance.available - amount,
         };
 
         assert_eq!(expected, result);
@@ -760,7 +1705,7 @@ mod tests {
         let result = client.execute_transaction(transaction);
 
         assert_eq!(true, result.is_ok());
-        assert_eq!(original_amount - amount, client.available);
+        assert_eq!(original_amount - amount, client.state.balance.available);
 
         assert_eq!(2, client.transactions.len());
         assert_eq!((TransactionState::Ok, transaction), client.transactions[1]);
@@ -779,7 +1724,7 @@ mod tests {
         let result = client.execute_transaction(transaction);
 
         assert_eq!(true, result.is_ok());
-        assert_eq!(original_amount - amount, client.available);
+        assert_eq!(original_amount - amount, client.state.balance.available);
 
         assert_eq!(2, client.transactions.len());
         assert_eq!((TransactionState::Ok, transaction), client.transactions[1]);
@@ -789,7 +1734,7 @@ mod tests {
     fn client_execute_transaction_withdrawal_is_locked_returns_err() {
         let mut client = Client::new(4482);
 
-        client.locked = true;
+        client.state.locked = true;
 
         let amount = Amount::new(1);
         let transaction = create_withdrawal(&client, amount);
@@ -831,7 +1776,7 @@ mod tests {
 
         assert_eq!(true, result.is_ok());
 
-        assert_eq!(Amount::zero(), client.available);
+        assert_eq!(Amount::zero(), client.state.balance.available);
         assert_eq!((TransactionState::Ok, transaction), client.transactions[0]);
     }
 
@@ -845,7 +1790,7 @@ mod tests {
 
         assert_eq!(true, result.is_ok());
 
-        assert_eq!(deposit_amount, client.available);
+        assert_eq!(deposit_amount, client.state.balance.available);
         assert_eq!((TransactionState::Ok, transaction), client.transactions[0]);
     }
 
@@ -853,7 +1798,7 @@ mod tests {
     fn client_execute_transaction_deposit_is_locked_returns_err() {
         let mut client = Client::new(4482);
 
-        client.locked = true;
+        client.state.locked = true;
 
         let amount = Amount::new(1);
         let transaction = create_deposit(&client, amount);
@@ -865,14 +1810,140 @@ mod tests {
         assert_eq!(0, client.transactions.len());
     }
 
+    #[test]
+    fn client_execute_transaction_hold_moves_funds_to_held() {
+        let mut client = Client::new(4482);
+        let initial = Amount::new(40000);
+        client.state.balance.available = initial;
+
+        let amount = Amount::new(15000);
+        let hold = create_transaction(&client, 30, TransactionType::Hold { amount, reason: 7 });
+        let result = client.execute_transaction(hold);
+
+        assert_eq!(true, result.is_ok());
+        assert_eq!(initial - amount, client.state.balance.available);
+        assert_eq!(amount, client.state.balance.held);
+        assert_eq!(amount, client.risk_held(7));
+        assert_eq!(initial, client.total());
+        assert_eq!((TransactionState::Ok, hold), client.transactions[0]);
+    }
+
+    #[test]
+    fn client_execute_transaction_hold_exceeding_available_returns_err() {
+        let mut client = Client::new(4482);
+        client.state.balance.available = Amount::new(40000);
+
+        let amount = Amount::new(40001);
+        let hold = create_transaction(&client, 30, TransactionType::Hold { amount, reason: 7 });
+        let snapshot = client.clone();
+        let result = client.execute_transaction(hold);
+
+        assert_eq!(true, result.is_err());
+        assert_eq!(
+            TransactionError::InvalidHold {
+                resulting_amount: Amount::new(-1)
+            },
+            result.unwrap_err()
+        );
+        assert_eq!(snapshot, client);
+    }
+
+    #[test]
+    fn client_execute_transaction_withdrawal_within_overdraft_returns_ok() {
+        let mut client = Client::new(4482);
+        client.state.balance.available = Amount::new(10000);
+        client.set_overdraft_limit(Amount::new(50000));
+
+        let withdrawal = create_withdrawal(&client, Amount::new(40000));
+        assert_eq!(Ok(()), client.execute_transaction(withdrawal));
+        assert_eq!(Amount::new(-30000), client.available());
+        assert_eq!(Amount::new(-30000), client.total());
+
+        let withdrawal = create_withdrawal(&client, Amount::new(20001));
+        assert_eq!(
+            Err(TransactionError::InvalidWithdrawal {
+                resulting_amount: Amount::new(-50001)
+            }),
+            client.execute_transaction(withdrawal)
+        );
+        assert_eq!(Amount::new(-30000), client.available());
+    }
+
+    #[test]
+    fn client_execute_transaction_fee_debits_available() {
+        let mut client = Client::new(4482);
+        client.state.balance.available = Amount::new(40000);
+
+        let fee = |amount| create_transaction(&client, 23, TransactionType::Fee { amount });
+        let charged = fee(Amount::new(5000));
+        let too_large = fee(Amount::new(35001));
+
+        assert_eq!(Ok(()), client.execute_transaction(charged));
+        assert_eq!(
+            Err(TransactionError::InvalidFee {
+                resulting_amount: Amount::new(-1)
+            }),
+            client.execute_transaction(too_large)
+        );
+        assert_eq!(Amount::new(35000), client.available());
+        assert_eq!(Amount::new(5000), client.stats().fees);
+        // A fee isn't a transaction that can be disputed.
+        assert_eq!(
+            Err(TransactionError::NotFound { transaction_id: 23 }),
+            client.execute_transaction(create_dispute(&client, 23))
+        );
+    }
+
+    #[test]
+    fn client_execute_transaction_release_returns_funds_to_available() {
+        let mut client = Client::new(4482);
+        let initial = Amount::new(40000);
+        client.state.balance.available = initial;
+
+        let amount = Amount::new(15000);
+        let hold = create_transaction(&client, 30, TransactionType::Hold { amount, reason: 7 });
+        client.execute_transaction(hold).unwrap();
+
+        let release =
+            create_transaction(&client, 31, TransactionType::Release { amount, reason: 7 });
+        let result = client.execute_transaction(release);
+
+        assert_eq!(true, result.is_ok());
+        assert_eq!(initial, client.state.balance.available);
+        assert_eq!(Amount::zero(), client.state.balance.held);
+        assert_eq!(Amount::zero(), client.risk_held(7));
+        assert_eq!(true, client.state.balance.risk_holds.is_empty());
+    }
+
+    #[test]
+    fn client_execute_transaction_release_other_reason_returns_err() {
+        let mut client = Client::new(4482);
+        client.state.balance.available = Amount::new(40000);
+
+        let amount = Amount::new(15000);
+        let hold = create_transaction(&client, 30, TransactionType::Hold { amount, reason: 7 });
+        client.execute_transaction(hold).unwrap();
+        let snapshot = client.clone();
+
+        let release =
+            create_transaction(&client, 31, TransactionType::Release { amount, reason: 8 });
+        let result = client.execute_transaction(release);
+
+        assert_eq!(true, result.is_err());
+        assert_eq!(
+            TransactionError::InvalidRelease {
+                reason: 8,
+                resulting_amount: Amount::zero() - amount,
+            },
+            result.unwrap_err()
+        );
+        assert_eq!(snapshot, client);
+    }
+
     #[test]
     fn client_execute_transaction_mismatched_client_returns_err() {
         let mut client = Client::new(4482);
-        let transaction = Transaction {
-            client: 25,
-            id: 23,
-            transaction_type: TransactionType::Resolve,
-        };
+        let transaction = fixtures::resolve(25, 23);
 
         let result = client.execute_transaction(transaction);
         assert_eq!(true, result.is_err());
@@ -893,8 +1964,8 @@ mod tests {
         let available = Amount::new(1);
 
         let mut client = Client::new(314);
-        client.held = held;
-        client.available = available;
+        client.state.balance.held = held;
+        client.state.balance.available = available;
 
         let expected = held + available;
         let actual = client.total();
@@ -905,7 +1976,7 @@ mod tests {
     #[test]
     fn client_locked_returns_expected() {
         let mut client = Client::new(314);
-        client.locked = true;
+        client.state.locked = true;
 
         assert_eq!(true, client.locked());
     }
@@ -915,7 +1986,7 @@ mod tests {
         let held = Amount::new(428382);
         let mut client = Client::new(314);
 
-        client.held = held;
+        client.state.balance.held = held;
         assert_eq!(held, client.held());
     }
 
@@ -924,7 +1995,7 @@ mod tests {
         let available = Amount::new(48382);
         let mut client = Client::new(314);
 
-        client.available = available;
+        client.state.balance.available = available;
         assert_eq!(available, client.available());
     }
 
@@ -938,6 +2009,351 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn client_stats_count_accepted_transactions() {
+        let mut client = Client::new(4482);
+
+        let mut deposit = create_deposit(&client, Amount::new(40000));
+        client.execute_transaction(deposit).unwrap();
+        deposit.id = 25;
+        client.execute_transaction(deposit).unwrap();
+        let withdrawal = create_withdrawal(&client, Amount::new(15000));
+        client.execute_transaction(withdrawal).unwrap();
+        let rejected = create_withdrawal(&client, Amount::new(1000000));
+        let _result = client.execute_transaction(rejected);
+
+        client
+            .execute_transaction(create_dispute(&client, deposit.id))
+            .unwrap();
+        client
+            .execute_transaction(create_chargeback(&client, deposit.id))
+            .unwrap();
+
+        let expected = AccountStats {
+            deposits: Amount::new(80000),
+            withdrawals: Amount::new(15000),
+            fees: Amount::zero(),
+            disputes: 1,
+            chargebacks: 1,
+            last_activity: None,
+        };
+        assert_eq!(expected, client.stats());
+    }
+
+    #[test]
+    fn client_currencies_keep_separate_balances() {
+        let mut client = Client::new(4482);
+        let eur: Currency = "EUR".parse().unwrap();
+        let usd: Currency = "USD".parse().unwrap();
+
+        // Transactions without a currency are applied whatever the account's is.
+        let mut deposit = create_deposit(&client, Amount::new(40000));
+        client.execute_transaction(deposit).unwrap();
+        assert_eq!(None, client.currency());
+
+        deposit.id = 25;
+        deposit.currency = Some(eur);
+        client.execute_transaction(deposit).unwrap();
+        assert_eq!(Some(eur), client.currency());
+
+        // Nothing has been deposited in dollars to withdraw.
+        let mut withdrawal = create_withdrawal(&client, Amount::new(15000));
+        withdrawal.currency = Some(usd);
+        assert_eq!(
+            Err(TransactionError::InvalidWithdrawal {
+                resulting_amount: Amount::new(-15000),
+            }),
+            client.execute_transaction(withdrawal)
+        );
+        assert_eq!(true, client.other_currencies().is_empty());
+
+        deposit.id = 26;
+        deposit.currency = Some(usd);
+        client.execute_transaction(deposit).unwrap();
+        client.execute_transaction(withdrawal).unwrap();
+        assert_eq!(Amount::new(80000), client.available());
+
+        let others = client.other_currencies();
+        assert_eq!(1, others.len());
+        assert_eq!(Some(usd), others[0].currency());
+        assert_eq!(Amount::new(25000), others[0].available());
+        assert_eq!(Amount::new(40000), others[0].stats().deposits);
+        assert_eq!(Amount::new(15000), others[0].stats().withdrawals);
+    }
+
+    #[test]
+    fn client_dispute_holds_funds_in_currency_of_transaction() {
+        let mut client = Client::new(4482);
+        let eur: Currency = "EUR".parse().unwrap();
+        let usd: Currency = "USD".parse().unwrap();
+
+        let mut deposit = create_deposit(&client, Amount::new(40000));
+        deposit.currency = Some(eur);
+        client.execute_transaction(deposit).unwrap();
+        deposit.id = 25;
+        deposit.currency = Some(usd);
+        client.execute_transaction(deposit).unwrap();
+
+        // A dispute without a currency uses that of the deposit.
+        let dispute = create_dispute(&client, 25);
+        client.execute_transaction(dispute).unwrap();
+        assert_eq!(Amount::new(40000), client.available());
+        assert_eq!(Amount::zero(), client.held());
+        let others = client.other_currencies();
+        assert_eq!(Amount::zero(), others[0].available());
+        assert_eq!(Amount::new(40000), others[0].held());
+        assert_eq!(true, client.erase().is_err());
+
+        let mut resolve = create_resolve(&client, 25);
+        resolve.currency = Some(eur);
+        assert_eq!(
+            Err(TransactionError::CurrencyMismatch {
+                expected: usd,
+                passed: eur,
+            }),
+            client.execute_transaction(resolve)
+        );
+        resolve.currency = Some(usd);
+        client.execute_transaction(resolve).unwrap();
+        assert_eq!(Amount::new(40000), client.other_currencies()[0].available());
+    }
+
+    fn create_convert(
+        client: &Client,
+        id: TransactionId,
+        amount: Amount,
+        to: &str,
+        converted: Option<Amount>,
+    ) -> Transaction {
+        create_transaction(
+            client,
+            id,
+            TransactionType::Convert {
+                amount,
+                to: to.parse().unwrap(),
+                converted,
+            },
+        )
+    }
+
+    #[test]
+    fn client_execute_transaction_convert_moves_funds_between_currencies() {
+        let mut client = Client::new(4482);
+        let usd: Currency = "USD".parse().unwrap();
+
+        let mut deposit = create_deposit(&client, Amount::new(40000));
+        deposit.currency = "EUR".parse().ok();
+        client.execute_transaction(deposit).unwrap();
+
+        let convert = create_convert(
+            &client,
+            30,
+            Amount::new(10000),
+            "USD",
+            Some(Amount::new(10825)),
+        );
+        client.execute_transaction(convert).unwrap();
+
+        assert_eq!(Amount::new(30000), client.available());
+        let others = client.other_currencies();
+        assert_eq!(Some(usd), others[0].currency());
+        assert_eq!(Amount::new(10825), others[0].available());
+        // Both legs are kept in the one history entry.
+        assert_eq!(
+            Some(&(TransactionState::Ok, convert)),
+            client.history().last()
+        );
+        assert_eq!(Amount::new(40000), client.stats().deposits);
+
+        // Dollars can be converted back, but not beyond what's held.
+        let mut back = create_convert(
+            &client,
+            31,
+            Amount::new(20000),
+            "EUR",
+            Some(Amount::new(18000)),
+        );
+        back.currency = Some(usd);
+        assert_eq!(
+            Err(TransactionError::InvalidConversion {
+                resulting_amount: Amount::new(-9175),
+            }),
+            client.execute_transaction(back)
+        );
+    }
+
+    #[test]
+    fn client_execute_transaction_convert_without_rate_returns_err() {
+        let mut client = Client::new(4482);
+        let usd: Currency = "USD".parse().unwrap();
+
+        let deposit = create_deposit(&client, Amount::new(40000));
+        client.execute_transaction(deposit).unwrap();
+
+        // The client's currency isn't known, so there's nothing to convert from.
+        let convert = create_convert(&client, 30, Amount::new(100), "USD", Some(Amount::new(1)));
+        assert_eq!(
+            Err(TransactionError::RateUnavailable {
+                from: None,
+                to: usd,
+            }),
+            client.execute_transaction(convert)
+        );
+
+        let mut convert = create_convert(&client, 30, Amount::new(100), "USD", None);
+        convert.currency = "EUR".parse().ok();
+        assert_eq!(
+            Err(TransactionError::RateUnavailable {
+                from: convert.currency,
+                to: usd,
+            }),
+            client.execute_transaction(convert)
+        );
+
+        convert.currency = Some(usd);
+        assert_eq!(
+            Err(TransactionError::ConvertToSameCurrency),
+            client.execute_transaction(convert)
+        );
+        assert_eq!(Amount::new(40000), client.available());
+    }
+
+    #[test]
+    fn client_snapshot_round_trips_other_currencies() {
+        let mut client = Client::new(4482);
+
+        let mut deposit = create_deposit(&client, Amount::new(40000));
+        deposit.currency = "EUR".parse().ok();
+        client.execute_transaction(deposit).unwrap();
+        deposit.id = 25;
+        deposit.currency = "USD".parse().ok();
+        client.execute_transaction(deposit).unwrap();
+
+        let actual = Client::from_snapshot(client.to_snapshot()).unwrap();
+
+        assert_eq!(client, actual);
+    }
+
+    #[test]
+    fn client_serde_json_round_trips() {
+        let mut client = Client::new(4482);
+
+        let mut deposit = create_deposit(&client, Amount::new(40000));
+        deposit.currency = "EUR".parse().ok();
+        client.execute_transaction(deposit).unwrap();
+        deposit.id = 25;
+        deposit.currency = "USD".parse().ok();
+        client.execute_transaction(deposit).unwrap();
+        let dispute = create_dispute(&client, deposit.id);
+        client.execute_transaction(dispute).unwrap();
+
+        let json = serde_json::to_string(&client).unwrap();
+        let actual: Client = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(client, actual);
+    }
+
+    #[test]
+    fn client_snapshot_round_trips() {
+        let mut client = Client::new(4482);
+
+        let deposit = create_deposit(&client, Amount::new(40000));
+        client.execute_transaction(deposit).unwrap();
+        let dispute = create_dispute(&client, deposit.id);
+        client.execute_transaction(dispute).unwrap();
+        let hold = create_transaction(
+            &client,
+            30,
+            TransactionType::Hold {
+                amount: Amount::new(0),
+                reason: 2,
+            },
+        );
+        client.execute_transaction(hold).unwrap();
+        client.state.locked = true;
+
+        let actual = Client::from_snapshot(client.to_snapshot()).unwrap();
+
+        assert_eq!(client, actual);
+    }
+
+    #[test]
+    fn client_as_of_replays_events() {
+        let mut client = Client::new(4482);
+
+        let deposit = create_deposit(&client, Amount::new(40000));
+        client.execute_transaction(deposit).unwrap();
+        let withdrawal = create_withdrawal(&client, Amount::new(10000));
+        client.execute_transaction(withdrawal).unwrap();
+        client
+            .execute_transaction(create_withdrawal(&client, Amount::new(90000)))
+            .unwrap_err();
+        client
+            .execute_transaction(create_dispute(&client, deposit.id))
+            .unwrap();
+        client
+            .execute_transaction(create_chargeback(&client, deposit.id))
+            .unwrap();
+
+        // Only accepted transactions are events.
+        assert_eq!(4, client.events().len());
+        assert_eq!(
+            Change::ChargedBack {
+                amount: Amount::new(40000)
+            },
+            client.events()[3].change
+        );
+        assert_eq!(client.state, client.as_of(client.events().len()));
+
+        let before_dispute = client.as_of(2);
+        assert_eq!(Amount::new(30000), before_dispute.available());
+        assert_eq!(Amount::zero(), before_dispute.held());
+        assert_eq!(false, before_dispute.locked());
+        assert_eq!(AccountState::default(), client.as_of(0));
+    }
+
+    #[test]
+    fn client_erase_folds_from_remaining_balances() {
+        let mut client = Client::new(4482);
+        client
+            .execute_transaction(create_deposit(&client, Amount::new(40000)))
+            .unwrap();
+        client.erase().unwrap();
+
+        assert_eq!(0, client.events().len());
+        assert_eq!(Amount::new(40000), client.as_of(0).available());
+
+        client
+            .execute_transaction(create_withdrawal(&client, Amount::new(10000)))
+            .unwrap();
+        assert_eq!(client.state, client.as_of(1));
+    }
+
+    #[test]
+    fn client_merge_sums_balances_and_keeps_histories() {
+        let mut client = Client::new(4482);
+        let deposit = create_deposit(&client, Amount::new(40000));
+        client.execute_transaction(deposit).unwrap();
+
+        let mut other = Client::new(4482);
+        let withdrawal = create_withdrawal(&other, Amount::new(0));
+        let mut eur = create_transaction(&other, 30, TransactionType::Deposit(Amount::new(5000)));
+        eur.currency = "EUR".parse().ok();
+        other.execute_transaction(eur).unwrap();
+        other.execute_transaction(withdrawal).unwrap();
+        other.state.locked = true;
+
+        client.merge(other);
+
+        assert_eq!(Amount::new(45000), client.available());
+        assert_eq!(Amount::new(45000), client.stats().deposits);
+        assert_eq!(true, client.locked());
+        assert_eq!("EUR".parse().ok(), client.currency());
+        assert_eq!(3, client.history_len());
+        assert_eq!(Some(2), client.history_index(withdrawal.id));
+        assert_eq!(client.state, client.as_of(0));
+    }
+
     #[test]
     fn client_new_returns_expected() {
         let id: ClientId = 124;
@@ -945,10 +2361,14 @@ mod tests {
         let actual = Client::new(id);
         let expected = Client {
             id,
-            available: Amount::zero(),
-            held: Amount::zero(),
-            locked: false,
+            state: AccountState::default(),
+            base: AccountState::default(),
+            events: vec![],
             transactions: vec![],
+            transaction_indexes: HashMap::new(),
+            cases: HashMap::new(),
+            tombstone: None,
+            overdraft_limit: Amount::zero(),
         };
 
         assert_eq!(expected, actual);
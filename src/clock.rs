@@ -0,0 +1,101 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Time-dependent features read the time through a clock so tests and backtests can control it.
+
+/// Seconds since the Unix epoch.
+pub type Timestamp = u64;
+
+/// A source of the current time.
+pub trait Clock: Send + Sync {
+    /// The current time.
+    fn now(&self) -> Timestamp;
+}
+
+/// The wall clock.
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        // A system clock set before 1970 is treated as the epoch.
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default()
+    }
+}
+
+/// A clock that always returns the same time.
+#[derive(Debug)]
+pub struct FixedClock {
+    now: Timestamp,
+}
+
+impl FixedClock {
+    pub fn new(now: Timestamp) -> Self {
+        Self { now }
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> Timestamp {
+        self.now
+    }
+}
+
+/// A clock that moves forward by a fixed step every time it is read, starting at `start`.
+#[derive(Debug)]
+pub struct SteppingClock {
+    next: AtomicU64,
+    step: u64,
+}
+
+impl SteppingClock {
+    pub fn new(start: Timestamp, step: u64) -> Self {
+        Self {
+            next: AtomicU64::new(start),
+            step,
+        }
+    }
+
+    /// Moves the clock forward without reading it.
+    pub fn advance(&self, seconds: u64) {
+        self.next.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for SteppingClock {
+    fn now(&self) -> Timestamp {
+        self.next.fetch_add(self.step, Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_clock_now_returns_same_time() {
+        let clock = FixedClock::new(1_600_000_000);
+
+        assert_eq!(1_600_000_000, clock.now());
+        assert_eq!(1_600_000_000, clock.now());
+    }
+
+    #[test]
+    fn stepping_clock_now_steps_forward() {
+        let clock = SteppingClock::new(100, 10);
+
+        assert_eq!(100, clock.now());
+        assert_eq!(110, clock.now());
+
+        clock.advance(1000);
+        assert_eq!(1120, clock.now());
+    }
+
+    #[test]
+    fn system_clock_now_is_after_2020() {
+        assert_eq!(true, SystemClock.now() > 1_577_836_800);
+    }
+}
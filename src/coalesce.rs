@@ -0,0 +1,281 @@
+use crate::{
+    amount::Amount,
+    client::ClientId,
+    currency::Currency,
+    transaction::{Transaction, TransactionType},
+};
+use std::collections::HashMap;
+
+// Bulk loads are dominated by long runs of deposits and withdrawals. Coalescing a client's
+// consecutive transactions of the same type and currency means the account is only touched once
+// per run. The account still keeps each merged transaction in its history under its own id, so
+// coalescing can't be told apart from a normal run once the run is over.
+
+/// A unit of work for the engine.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    /// A transaction applied on its own.
+    Single(Transaction),
+    /// Consecutive deposits or withdrawals in one currency for one client, applied as a single
+    /// transaction.
+    Run(Vec<Transaction>),
+}
+
+impl Step {
+    /// The transactions the step stands for, in order.
+    pub fn transactions(&self) -> &[Transaction] {
+        match self {
+            Self::Single(transaction) => std::slice::from_ref(transaction),
+            Self::Run(transactions) => transactions,
+        }
+    }
+
+    /// The transaction applied for the step. A run is merged into one transaction that carries
+    /// the id of its first transaction, the sum of their amounts and the latest of their
+    /// timestamps.
+    pub fn merged(&self) -> Transaction {
+        match self {
+            Self::Single(transaction) => *transaction,
+            Self::Run(transactions) => {
+                let first = transactions[0];
+                let total = transactions
+                    .iter()
+                    .filter_map(|transaction| transaction.amount())
+                    .fold(Amount::zero(), |total, amount| total + amount);
+
+                let transaction_type = match first.transaction_type {
+                    TransactionType::Withdrawal(_) => TransactionType::Withdrawal(total),
+                    _ => TransactionType::Deposit(total),
+                };
+
+                Transaction {
+                    transaction_type,
+                    timestamp: transactions
+                        .iter()
+                        .filter_map(|transaction| transaction.timestamp)
+                        .max(),
+                    ..first
+                }
+            }
+        }
+    }
+}
+
+/// Whether a transaction refers back to an earlier one, which coalescing would break.
+fn refers_to_earlier(transaction: &Transaction) -> bool {
+    matches!(
+        transaction.transaction_type,
+        TransactionType::Dispute { .. }
+            | TransactionType::Resolve
+            | TransactionType::Chargeback { .. }
+    )
+}
+
+/// The kind of run a transaction can join, if any: whether it's a run of deposits, and its
+/// currency. Negative amounts are always rejected by the account, so they are left on their own
+/// to keep that behaviour.
+fn run_kind(transaction: &Transaction) -> Option<(bool, Option<Currency>)> {
+    let deposit = match transaction.transaction_type {
+        TransactionType::Deposit(amount) if !amount.less_than_zero() => true,
+        TransactionType::Withdrawal(amount) if !amount.less_than_zero() => false,
+        _ => return None,
+    };
+    Some((deposit, transaction.currency))
+}
+
+/// Splits a batch into steps, merging each client's consecutive deposits and withdrawals.
+/// Every transaction is its own step if the batch contains any disputes, resolves or chargebacks.
+pub fn plan(transactions: Vec<Transaction>) -> Vec<Step> {
+    group(&transactions)
+        .into_iter()
+        .map(|indexes| step(&transactions, &indexes))
+        .collect()
+}
+
+/// Splits a batch into steps as `plan` does, keeping the input line of each transaction in the
+/// step, in the order of its transactions.
+pub fn plan_lines(batch: Vec<(Transaction, Option<u64>)>) -> Vec<(Step, Vec<Option<u64>>)> {
+    let (transactions, lines): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+    group(&transactions)
+        .into_iter()
+        .map(|indexes| {
+            let lines = indexes.iter().map(|index| lines[*index]).collect();
+            (step(&transactions, &indexes), lines)
+        })
+        .collect()
+}
+
+/// The step made of the transactions at the indexes.
+fn step(transactions: &[Transaction], indexes: &[usize]) -> Step {
+    match indexes {
+        [index] => Step::Single(transactions[*index]),
+        _ => Step::Run(indexes.iter().map(|index| transactions[*index]).collect()),
+    }
+}
+
+/// The positions of the transactions in each step, in the order the steps are applied.
+fn group(transactions: &[Transaction]) -> Vec<Vec<usize>> {
+    if transactions.iter().any(refers_to_earlier) {
+        return (0..transactions.len()).map(|index| vec![index]).collect();
+    }
+
+    let mut steps: Vec<Vec<usize>> = vec![];
+    // The step each client's current run lives in and the kind of run it is.
+    let mut open_runs: HashMap<ClientId, (usize, (bool, Option<Currency>))> = HashMap::new();
+
+    for (index, transaction) in transactions.iter().enumerate() {
+        let kind = match run_kind(transaction) {
+            Some(kind) => kind,
+            None => {
+                open_runs.remove(&transaction.client);
+                // A transfer changes the recipient's balance too, so its runs can't span one.
+                if let TransactionType::Transfer { to, .. } = transaction.transaction_type {
+                    open_runs.remove(&to);
+                }
+                steps.push(vec![index]);
+                continue;
+            }
+        };
+
+        match open_runs.get(&transaction.client) {
+            Some((step, run_kind)) if *run_kind == kind => steps[*step].push(index),
+            _ => {
+                open_runs.insert(transaction.client, (steps.len(), kind));
+                steps.push(vec![index]);
+            }
+        }
+    }
+
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{deposit, dispute, transaction, withdrawal};
+
+    #[test]
+    fn plan_merges_consecutive_deposits_per_client() {
+        let steps = plan(vec![
+            deposit(1, 1, 10000),
+            deposit(2, 2, 10000),
+            deposit(1, 3, 20000),
+            withdrawal(1, 4, 5000),
+        ]);
+
+        assert_eq!(
+            vec![
+                Step::Run(vec![deposit(1, 1, 10000), deposit(1, 3, 20000)]),
+                Step::Single(deposit(2, 2, 10000)),
+                Step::Single(withdrawal(1, 4, 5000)),
+            ],
+            steps
+        );
+        assert_eq!(deposit(1, 1, 30000), steps[0].merged());
+    }
+
+    #[test]
+    fn plan_lines_keeps_line_of_each_transaction() {
+        let steps = plan_lines(vec![
+            (deposit(1, 1, 10000), Some(2)),
+            (deposit(2, 2, 10000), Some(3)),
+            (deposit(1, 3, 20000), Some(4)),
+        ]);
+
+        assert_eq!(
+            vec![
+                (
+                    Step::Run(vec![deposit(1, 1, 10000), deposit(1, 3, 20000)]),
+                    vec![Some(2), Some(4)]
+                ),
+                (Step::Single(deposit(2, 2, 10000)), vec![Some(3)]),
+            ],
+            steps
+        );
+    }
+
+    #[test]
+    fn plan_with_dispute_returns_single_steps() {
+        let dispute = dispute(1, 1);
+        let steps = plan(vec![deposit(1, 1, 10000), deposit(1, 2, 10000), dispute]);
+
+        assert_eq!(
+            vec![
+                Step::Single(deposit(1, 1, 10000)),
+                Step::Single(deposit(1, 2, 10000)),
+                Step::Single(dispute),
+            ],
+            steps
+        );
+    }
+
+    #[test]
+    fn plan_transfer_breaks_recipient_run() {
+        let transfer = transaction(
+            1,
+            2,
+            TransactionType::Transfer {
+                to: 2,
+                amount: Amount::new(10000),
+            },
+        );
+        let steps = plan(vec![
+            withdrawal(2, 1, 10000),
+            transfer,
+            withdrawal(2, 3, 10000),
+        ]);
+
+        assert_eq!(
+            vec![
+                Step::Single(withdrawal(2, 1, 10000)),
+                Step::Single(transfer),
+                Step::Single(withdrawal(2, 3, 10000)),
+            ],
+            steps
+        );
+    }
+
+    #[test]
+    fn plan_negative_amount_breaks_run() {
+        let steps = plan(vec![
+            deposit(1, 1, 10000),
+            deposit(1, 2, -10000),
+            deposit(1, 3, 10000),
+        ]);
+
+        assert_eq!(3, steps.len());
+    }
+
+    #[test]
+    fn plan_currency_change_breaks_run() {
+        let mut usd = deposit(1, 1, 10000);
+        usd.currency = "USD".parse().ok();
+        let mut eur = deposit(1, 2, 5000);
+        eur.currency = "EUR".parse().ok();
+        let mut more_eur = deposit(1, 3, 5000);
+        more_eur.currency = eur.currency;
+
+        let steps = plan(vec![usd, eur, more_eur]);
+
+        assert_eq!(
+            vec![Step::Single(usd), Step::Run(vec![eur, more_eur])],
+            steps
+        );
+        assert_eq!(eur.currency, steps[1].merged().currency);
+    }
+
+    #[test]
+    fn step_merged_carries_latest_timestamp() {
+        let mut first = deposit(1, 1, 10000);
+        first.timestamp = Some(200);
+        let mut second = deposit(1, 2, 10000);
+        second.timestamp = Some(300);
+        let mut third = deposit(1, 3, 10000);
+        third.timestamp = Some(100);
+
+        let merged = Step::Run(vec![first, second, third]).merged();
+
+        assert_eq!(Some(300), merged.timestamp);
+        assert_eq!(Amount::new(30000), merged.amount().unwrap());
+    }
+}
@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+// Archives of transactions are kept zstd-compressed, and can run to many gigabytes. Files ending
+// in `.zst` are decompressed while they're read and compressed while they're written, so they
+// never need to be unpacked to disk. A compressed stream can't be sought, so compressed input
+// can't be resumed from a checkpoint or followed.
+
+const ZSTD_EXTENSION: &str = "zst";
+
+/// Whether the file at the path is zstd-compressed, judging by its extension.
+pub fn is_compressed(path: &str) -> bool {
+    Path::new(path).extension().and_then(|ext| ext.to_str()) == Some(ZSTD_EXTENSION)
+}
+
+/// The path without its compression extension, e.g. `transactions.csv` for
+/// `transactions.csv.zst`, so the format of the contents can be told from it.
+pub fn uncompressed_path(path: &str) -> &str {
+    if is_compressed(path) {
+        &path[..path.len() - ZSTD_EXTENSION.len() - 1]
+    } else {
+        path
+    }
+}
+
+/// Opens the file for reading, decompressing it if it's compressed.
+pub fn open(path: &str) -> io::Result<Box<dyn Read>> {
+    let file = File::open(path)?;
+    if is_compressed(path) {
+        Ok(Box::new(zstd::Decoder::new(file)?))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// Wraps a writer to the file at the path, compressing what's written if the path is compressed.
+/// The compressed stream is completed when the writer is dropped.
+pub fn compress_for(path: &str, writer: Box<dyn Write>) -> io::Result<Box<dyn Write>> {
+    if !is_compressed(path) {
+        return Ok(writer);
+    }
+
+    let path = String::from(path);
+    let encoder = zstd::Encoder::new(writer, zstd::DEFAULT_COMPRESSION_LEVEL)?.on_finish(
+        move |result: io::Result<Box<dyn Write>>| {
+            if let Err(e) = result.and_then(|mut writer| writer.flush()) {
+                eprintln!("Failed to finish writing {}: {}", path, e);
+            }
+        },
+    );
+    Ok(Box::new(encoder))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn uncompressed_path_returns_expected() {
+        assert_eq!("in/t.csv", uncompressed_path("in/t.csv.zst"));
+        assert_eq!("in/t.csv", uncompressed_path("in/t.csv"));
+        assert_eq!(true, is_compressed("t.jsonl.zst"));
+        assert_eq!(false, is_compressed("t.zst.csv"));
+    }
+
+    #[test]
+    fn compress_for_compresses_when_dropped() {
+        let output = SharedOutput::default();
+        let mut writer = compress_for("report.csv.zst", Box::new(output.clone())).unwrap();
+        writer.write_all(b"client, available\n").unwrap();
+        drop(writer);
+
+        let decompressed = zstd::decode_all(output.0.borrow().as_slice()).unwrap();
+        assert_eq!(b"client, available\n".to_vec(), decompressed);
+    }
+}
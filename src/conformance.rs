@@ -0,0 +1,152 @@
+use crate::{
+    amount::Amount,
+    client::{ClientAccount, ClientId},
+    database::Database,
+    protocol,
+};
+use std::fs;
+use std::io;
+use std::sync::Mutex;
+
+// The conformance suite pins down the reference semantics, dispute edge cases in particular, as
+// data rather than code. Each case feeds headerless CSV records to an empty database and lists the
+// `ResultCode` expected for every record and the accounts expected at the end, so alternative
+// `ClientAccount` implementations can check they behave exactly like `Client`.
+
+/// The suite shipped with the crate, from `conformance/cases.json`.
+pub const SUITE: &str = include_str!("../conformance/cases.json");
+
+#[derive(Debug)]
+pub enum ConformanceError {
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl From<io::Error> for ConformanceError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ConformanceError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+/// A single conformance case.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct Case {
+    pub name: String,
+    /// Headerless CSV records, one per entry.
+    pub input: Vec<String>,
+    /// The `ResultCode` expected for each record.
+    pub codes: Vec<u8>,
+    /// Every account expected once all records are applied, ordered by client.
+    pub accounts: Vec<ExpectedAccount>,
+}
+
+/// An account's balances, compared as amounts so the suite holds whatever decimal places the
+/// engine is built with.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct ExpectedAccount {
+    pub client: ClientId,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+impl ExpectedAccount {
+    fn from_account<Account: ClientAccount>(account: &Account) -> Self {
+        Self {
+            client: account.id(),
+            available: account.available(),
+            held: account.held(),
+            total: account.total(),
+            locked: account.locked(),
+        }
+    }
+}
+
+/// How a case's outcome differed from what was expected.
+#[derive(Debug, PartialEq)]
+pub enum Mismatch {
+    Codes {
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+    Accounts {
+        expected: Vec<ExpectedAccount>,
+        actual: Vec<ExpectedAccount>,
+    },
+}
+
+/// Parses a suite from JSON.
+pub fn parse(json: &str) -> Result<Vec<Case>, ConformanceError> {
+    Ok(serde_json::from_str(json)?)
+}
+
+/// Reads a suite from the file at the given path.
+pub fn load(path: &str) -> Result<Vec<Case>, ConformanceError> {
+    parse(&fs::read_to_string(path)?)
+}
+
+/// Runs a case against accounts opened with `open_account`.
+pub fn run_case<Account>(case: &Case, open_account: fn(ClientId) -> Account) -> Result<(), Mismatch>
+where
+    Account: ClientAccount,
+{
+    let database = Mutex::new(Database::with_accounts(open_account));
+
+    let codes = protocol::process_batch(case.input.join("\n").as_bytes(), &database);
+    if codes != case.codes {
+        return Err(Mismatch::Codes {
+            expected: case.codes.clone(),
+            actual: codes,
+        });
+    }
+
+    let database = database.into_inner().unwrap();
+    let accounts: Vec<ExpectedAccount> = database
+        .accounts()
+        .map(ExpectedAccount::from_account)
+        .collect();
+    if accounts != case.accounts {
+        return Err(Mismatch::Accounts {
+            expected: case.accounts.clone(),
+            actual: accounts,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+
+    #[test]
+    fn suite_client_passes_every_case() {
+        for case in parse(SUITE).unwrap() {
+            assert_eq!(Ok(()), run_case(&case, Client::new), "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn run_case_wrong_code_returns_mismatch() {
+        let case = Case {
+            name: String::from("wrong"),
+            input: vec![String::from("dispute,1,1")],
+            codes: vec![0],
+            accounts: vec![],
+        };
+
+        let expected = Mismatch::Codes {
+            expected: vec![0],
+            actual: vec![15],
+        };
+        assert_eq!(Err(expected), run_case(&case, Client::new));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::amount::Amount;
+use std::str::FromStr;
+
+// Files that mix currencies can carry an ISO 4217 code on each transaction. An account takes the
+// currency of the first transaction applied to it that has one, and keeps a separate balance for
+// each other currency it sees, so amounts in different units are never added together.
+// Transactions without a code use the account's own balance, in whatever units it's kept.
+
+/// A three letter currency code, such as `EUR`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        // Only ASCII letters are ever stored.
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    /// Parses three ASCII letters in either case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().as_bytes() {
+            [a, b, c] if [a, b, c].iter().all(|letter| letter.is_ascii_alphabetic()) => Ok(Self([
+                a.to_ascii_uppercase(),
+                b.to_ascii_uppercase(),
+                c.to_ascii_uppercase(),
+            ])),
+            _ => Err(String::from(s)),
+        }
+    }
+}
+
+impl std::fmt::Debug for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::fmt::Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl serde::Serialize for Currency {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Currency {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct CurrencyVisitor;
+
+        impl serde::de::Visitor<'_> for CurrencyVisitor {
+            type Value = Currency;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "a three letter currency code")
+            }
+
+            // Reading the code from a borrowed string keeps CSV records from allocating.
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Currency, E> {
+                Currency::from_str(value)
+                    .map_err(|_| E::invalid_value(serde::de::Unexpected::Str(value), &self))
+            }
+        }
+
+        deserializer.deserialize_str(CurrencyVisitor)
+    }
+}
+
+/// An amount in a currency, if it's known.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Money {
+    pub amount: Amount,
+    pub currency: Option<Currency>,
+}
+
+impl std::fmt::Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.currency {
+            Some(currency) => write!(f, "{} {}", self.amount, currency),
+            None => write!(f, "{}", self.amount),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{amount, places};
+
+    #[test]
+    fn currency_from_str_reads_three_letters() {
+        assert_eq!("EUR", Currency::from_str("eur").unwrap().as_str());
+        assert_eq!("USD", Currency::from_str(" USD ").unwrap().as_str());
+        assert_eq!(true, Currency::from_str("EU").is_err());
+        assert_eq!(true, Currency::from_str("EURO").is_err());
+        assert_eq!(true, Currency::from_str("E1R").is_err());
+    }
+
+    #[test]
+    fn money_display_includes_currency() {
+        let amount = amount("1.5");
+
+        assert_eq!(
+            places("1.5000 EUR"),
+            Money {
+                amount,
+                currency: Some(Currency::from_str("EUR").unwrap()),
+            }
+            .to_string()
+        );
+        assert_eq!(
+            places("1.5000"),
+            Money {
+                amount,
+                currency: None,
+            }
+            .to_string()
+        );
+    }
+}
@@ -1,122 +1,1846 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "sled-storage")]
+use crate::storage::SledStorage;
 use crate::{
+    amount::{Amount, RoundingMode},
     client::{Client, ClientAccount, ClientId},
+    fees::FeeSchedule,
+    manifest,
+    overdraft::OverdraftLimits,
+    rates::ExchangeRates,
+    report::Report,
+    rules::{RuleDecision, TransactionRule},
+    shard::Partitioner,
+    sink::{ReportSink, SinkError},
+    snapshot::{self, Snapshot, SnapshotError, SnapshotFormat},
+    spill::HistorySpill,
+    storage::{Storage, StorageError},
+    telemetry::{self, MemoryStats},
     transaction::{Transaction, TransactionError, TransactionId, TransactionType},
+    velocity::{Activity, VelocityLimit, VelocityLimits, VelocityPolicy},
+    wal::{WalError, WriteAheadLog},
 };
 
-#[derive(PartialEq, Debug)]
-enum Status {
-    Valid,
-    Invalid,
+#[derive(Debug, PartialEq)]
+pub enum MergeError {
+    /// Both databases applied a transaction with the id.
+    ConflictingTransactionId { id: TransactionId },
+}
+
+/// What happens when a transaction other than a dispute, resolve or chargeback reuses an id that
+/// has already been applied.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Reject the transaction with `TransactionError::DuplicateTransactionId`.
+    #[default]
+    Reject,
+    /// Apply the transaction anyway and keep its id in `Database::duplicates`.
+    Warn,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "warn" => Ok(Self::Warn),
+            _ => Err(String::from(s)),
+        }
+    }
+}
+
+/// Notified of every transaction the database applies or rejects, with the client's account as
+/// it is afterwards. Each fee charged is notified as a fee entry after the transaction it was
+/// charged on. Once the database is split into shards, only each client's own transactions
+/// arrive in order.
+pub trait AccountObserver<Account>: Send
+where
+    Account: ClientAccount,
+{
+    /// Called after a transaction has been applied or rejected. The account is `None` if the
+    /// client has none, such as when its first transaction was rejected.
+    fn on_transaction(
+        &mut self,
+        transaction: &Transaction,
+        result: &Result<(), TransactionError>,
+        account: Option<&Account>,
+    );
 }
 
+/// Accounts are only created once a transaction for the client is seen, so memory grows with the
+/// number of clients rather than the range of client ids. Accounts are kept ordered by id.
 pub struct Database<Account>
 where
     Account: ClientAccount,
 {
-    clients: Vec<(Account, Status)>,
+    clients: BTreeMap<ClientId, Account>,
+    open_account: fn(ClientId) -> Account,
+    /// The client of every applied transaction that introduces an id, by id.
+    owners: HashMap<TransactionId, ClientId>,
+    /// Ids restored from a snapshot or storage, which were applied in previous runs.
+    processed: HashSet<TransactionId>,
+    duplicates: Vec<TransactionId>,
+    /// The first failure to save an account, kept until the storage is flushed.
+    storage_error: Option<StorageError>,
+    compacted: u64,
+    /// The fee entries applied since they were last taken.
+    charged: Vec<Transaction>,
+    /// Each client's recent activity, as it's counted against the velocity limits.
+    activity: HashMap<ClientId, Activity>,
+    velocity_breaches: Vec<(TransactionId, VelocityLimit)>,
+    config: Config<Account>,
+}
+
+/// What the builder methods set up: the policies transactions are held to and where they're
+/// recorded. Every shard the database is split into gets a copy, and everything behind an `Arc`
+/// is shared between the shards rather than copied, so they all write to the same storage, log
+/// and spill file, and tell the same observers.
+struct Config<Account>
+where
+    Account: ClientAccount,
+{
+    duplicate_policy: DuplicatePolicy,
+    storage: Option<Arc<Mutex<Box<dyn Storage<Account>>>>>,
+    /// Where transactions are logged before they're applied.
+    wal: Option<Arc<Mutex<WriteAheadLog>>>,
+    /// How many history entries an account may keep before it's compacted.
+    history_cap: Option<usize>,
+    /// Where history is moved once compacting isn't enough.
+    spill: Option<Arc<Mutex<HistorySpill>>>,
+    /// The rates conversions without a converted amount are priced at.
+    rates: Option<(Arc<ExchangeRates>, RoundingMode)>,
+    /// The fees charged on deposits and withdrawals.
+    fees: Option<(Arc<FeeSchedule>, RoundingMode)>,
+    /// How far below zero clients may draw their available balance.
+    overdraft: Option<Arc<OverdraftLimits>>,
+    /// The available balance withdrawals must leave.
+    minimum_balance: Option<Amount>,
+    /// The largest a single deposit or withdrawal may be.
+    maximum_amount: Option<Amount>,
+    /// The velocity limits every client is held to.
+    velocity: Option<(Arc<VelocityLimits>, VelocityPolicy)>,
+    /// Checks every transaction must pass, in order.
+    rules: Vec<Arc<dyn TransactionRule<Account>>>,
+    /// Told about every transaction.
+    observers: Vec<Arc<Mutex<Box<dyn AccountObserver<Account>>>>>,
+}
+
+impl<Account> Default for Config<Account>
+where
+    Account: ClientAccount,
+{
+    fn default() -> Self {
+        Self {
+            duplicate_policy: DuplicatePolicy::default(),
+            storage: None,
+            wal: None,
+            history_cap: None,
+            spill: None,
+            rates: None,
+            fees: None,
+            overdraft: None,
+            minimum_balance: None,
+            maximum_amount: None,
+            velocity: None,
+            rules: vec![],
+            observers: vec![],
+        }
+    }
+}
+
+impl<Account> Clone for Config<Account>
+where
+    Account: ClientAccount,
+{
+    fn clone(&self) -> Self {
+        Self {
+            duplicate_policy: self.duplicate_policy,
+            storage: self.storage.clone(),
+            wal: self.wal.clone(),
+            history_cap: self.history_cap,
+            spill: self.spill.clone(),
+            rates: self.rates.clone(),
+            fees: self.fees.clone(),
+            overdraft: self.overdraft.clone(),
+            minimum_balance: self.minimum_balance,
+            maximum_amount: self.maximum_amount,
+            velocity: self.velocity.clone(),
+            rules: self.rules.clone(),
+            observers: self.observers.clone(),
+        }
+    }
 }
 
 impl<Account> Database<Account>
 where
     Account: ClientAccount,
 {
+    /// An empty database that uses `open_account` to create a client on its first transaction.
+    pub fn with_accounts(open_account: fn(ClientId) -> Account) -> Self {
+        Self {
+            clients: BTreeMap::new(),
+            open_account,
+            owners: HashMap::new(),
+            processed: HashSet::new(),
+            duplicates: vec![],
+            storage_error: None,
+            compacted: 0,
+            charged: vec![],
+            activity: HashMap::new(),
+            velocity_breaches: vec![],
+            config: Config::default(),
+        }
+    }
+
+    /// Replays the write-ahead log at the path into the database, then logs every transaction
+    /// before applying it. Called on a new database, this recovers the state reached before a
+    /// crash. Ids replayed from the log are already processed, so a resent transaction that was
+    /// logged before the crash is skipped rather than applied twice.
+    pub fn with_wal(mut self, path: &str) -> Result<Self, WalError> {
+        let (wal, transactions) = WriteAheadLog::open(path)?;
+
+        // The fees charged were logged too, so none are charged again while replaying.
+        let fees = self.config.fees.take();
+        for transaction in transactions {
+            // Rejected transactions are logged too, and are rejected again the same way.
+            let _result = self.apply_transaction(transaction);
+        }
+        self.config.fees = fees;
+        self.processed.extend(self.owners.keys().copied());
+
+        self.config.wal = Some(Arc::new(Mutex::new(wal)));
+        Ok(self)
+    }
+
+    /// Compacts the history of an account once it keeps more than `cap` transactions. If that
+    /// isn't enough and there's a spill file, the oldest half is moved there until it's needed.
+    pub fn with_history_cap(mut self, cap: usize, spill: Option<HistorySpill>) -> Self {
+        self.config.history_cap = Some(cap);
+        self.config.spill = spill.map(|spill| Arc::new(Mutex::new(spill)));
+        self
+    }
+
+    /// Prices conversions that don't give the amount they credit at the rates, rounding with the
+    /// rounding mode.
+    pub fn with_rates(mut self, rates: ExchangeRates, mode: RoundingMode) -> Self {
+        self.config.rates = Some((Arc::new(rates), mode));
+        self
+    }
+
+    /// Charges the fees in the schedule on deposits and withdrawals, rounding percentages with the
+    /// rounding mode.
+    pub fn with_fees(mut self, fees: FeeSchedule, mode: RoundingMode) -> Self {
+        self.config.fees = Some((Arc::new(fees), mode));
+        self
+    }
+
+    /// Lets withdrawals and fees take each client's available balance down to minus its limit.
+    pub fn with_overdraft(mut self, limits: OverdraftLimits) -> Self {
+        self.config.overdraft = Some(Arc::new(limits));
+        self
+    }
+
+    /// Rejects withdrawals that would leave less than the minimum available balance.
+    pub fn with_minimum_balance(mut self, minimum: Amount) -> Self {
+        self.config.minimum_balance = Some(minimum);
+        self
+    }
+
+    /// Rejects deposits and withdrawals above the maximum amount.
+    pub fn with_maximum_amount(mut self, maximum: Amount) -> Self {
+        self.config.maximum_amount = Some(maximum);
+        self
+    }
+
+    /// Holds every client to the velocity limits, with the policy deciding what happens to
+    /// transactions that exceed them.
+    pub fn with_velocity_limits(mut self, limits: VelocityLimits, policy: VelocityPolicy) -> Self {
+        self.config.velocity = Some((Arc::new(limits), policy));
+        self
+    }
+
+    /// Adds a rule every transaction must pass, after the rules added before it.
+    pub fn with_rule(mut self, rule: Box<dyn TransactionRule<Account>>) -> Self {
+        self.config.rules.push(Arc::from(rule));
+        self
+    }
+
+    /// Tells the observer about every transaction applied or rejected from now on.
+    pub fn subscribe(&mut self, observer: Box<dyn AccountObserver<Account>>) {
+        self.config.observers.push(Arc::new(Mutex::new(observer)));
+    }
+
+    /// Sets what happens to transactions that reuse an id.
+    pub fn with_duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> Self {
+        self.config.duplicate_policy = duplicate_policy;
+        self
+    }
+
     pub fn apply_transaction(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
-        let client_index = transaction.client as usize;
+        let charged = self.charged.len();
+        let result = self.execute(transaction);
+        self.notify(&transaction, &result);
+        self.notify_fees(charged, &transaction);
+        result
+    }
+
+    /// Applies the transaction without telling the observers.
+    fn execute(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        let owner = self.owners.get(&transaction.id).copied();
 
-        self.clients[client_index].1 = Status::Valid;
-        self.clients[client_index]
-            .0
-            .execute_transaction(transaction)
+        // A file delivered again is skipped rather than applied twice, whatever the duplicate
+        // policy. Ids reused by another client are still duplicates.
+        if self.already_processed(&transaction) {
+            return Err(TransactionError::AlreadyProcessed {
+                transaction_id: transaction.id,
+            });
+        }
+
+        let duplicate = transaction.introduces_id() && owner.is_some();
+        if duplicate && self.config.duplicate_policy == DuplicatePolicy::Reject {
+            return Err(TransactionError::DuplicateTransactionId {
+                transaction_id: transaction.id,
+            });
+        }
+
+        // Disputes, resolves and chargebacks can only refer to the client's own transactions.
+        match owner {
+            Some(owner) if !transaction.introduces_id() && owner != transaction.client => {
+                return Err(TransactionError::BelongsToAnotherClient {
+                    transaction_id: transaction.id,
+                    owner,
+                });
+            }
+            _ => {}
+        }
+
+        self.check_rules(&transaction)?;
+        self.check_maximum(&transaction)?;
+        let breach = self.check_velocity(&transaction)?;
+        let transaction = self.price(transaction)?;
+        let fee = self.fee(&transaction);
+        let fees = fee.and_then(|fee| fee.amount()).unwrap_or_default();
+        self.cover(&transaction, fees)?;
+        self.keep_minimum(&transaction, fees)?;
+        self.log(&transaction)?;
+
+        if !transaction.introduces_id() {
+            self.unspill(&transaction);
+        }
+
+        let result = match transaction.transaction_type {
+            TransactionType::Transfer { to, .. } => self.transfer(transaction, to),
+            _ => self
+                .open(transaction.client)
+                .execute_transaction(transaction),
+        };
+
+        if let (Ok(()), Some(fee)) = (result, fee) {
+            self.charge(fee);
+        }
+
+        self.cap_history(transaction.client);
+        self.save(transaction.client, &transaction);
+        if let TransactionType::Transfer { to, .. } = transaction.transaction_type {
+            self.cap_history(to);
+            self.save(to, &transaction);
+        }
+
+        if result.is_ok() {
+            self.record_activity(&transaction);
+            if let Some(limit) = breach {
+                self.velocity_breaches.push((transaction.id, limit));
+            }
+        }
+
+        if result.is_ok() && transaction.introduces_id() {
+            if duplicate {
+                self.duplicates.push(transaction.id);
+            } else {
+                self.owners.insert(transaction.id, transaction.client);
+            }
+        }
+
+        result
     }
 
-    pub fn output(&self) {
-        println!("client, available, held, total, locked");
+    /// Tells the observers about a transaction.
+    fn notify(&self, transaction: &Transaction, result: &Result<(), TransactionError>) {
+        let account = self.clients.get(&transaction.client);
+        for observer in &self.config.observers {
+            observer
+                .lock()
+                .unwrap()
+                .on_transaction(transaction, result, account);
+        }
+    }
 
-        self.clients
+    /// Tells the observers about the fees charged on the transaction since `charged` fees were.
+    fn notify_fees(&self, charged: usize, transaction: &Transaction) {
+        for fee in self.charged[charged..]
             .iter()
-            .filter(|(_account, status)| *status == Status::Valid)
-            .map(|(account, _)| account)
-            .for_each(|client| {
-                println!(
-                    "{:?}, {:?}, {:?}, {:?}, {:?}",
-                    client.id(),
-                    client.available(),
-                    client.held(),
-                    client.total(),
-                    client.locked()
-                );
+            .filter(|fee| fee.id == transaction.id)
+        {
+            self.notify(fee, &Ok(()));
+        }
+    }
+
+    /// Sets the amount a conversion credits from the loaded rates, along with the currency it's
+    /// made from, so it's logged and kept as it was applied. Other transactions are unchanged.
+    fn price(&self, mut transaction: Transaction) -> Result<Transaction, TransactionError> {
+        let (amount, to) = match transaction.transaction_type {
+            TransactionType::Convert {
+                amount,
+                to,
+                converted: None,
+            } => (amount, to),
+            _ => return Ok(transaction),
+        };
+        let (rates, mode) = match &self.config.rates {
+            Some(rates) => rates,
+            None => return Ok(transaction),
+        };
+
+        let from = transaction.currency.or_else(|| {
+            self.clients
+                .get(&transaction.client)
+                .and_then(|account| account.currency())
+        });
+        let converted = from.and_then(|from| rates.convert(amount, from, to, *mode));
+        if converted.is_none() && from != Some(to) {
+            return Err(TransactionError::RateUnavailable { from, to });
+        }
+
+        transaction.currency = from;
+        transaction.transaction_type = TransactionType::Convert {
+            amount,
+            to,
+            converted,
+        };
+        Ok(transaction)
+    }
+
+    /// The fee entry charged for the transaction under the fee schedule, if it's charged one.
+    fn fee(&self, transaction: &Transaction) -> Option<Transaction> {
+        let (fees, mode) = self.config.fees.as_ref()?;
+        let amount = fees.fee(&transaction.transaction_type, *mode)?;
+        Some(Transaction {
+            transaction_type: TransactionType::Fee { amount },
+            ..*transaction
+        })
+    }
+
+    /// Rejects the transaction if the balance it's applied to couldn't then cover the fees, so a
+    /// fee is never left uncharged. Transactions that would be rejected anyway are left to be.
+    fn cover(&self, transaction: &Transaction, fees: Amount) -> Result<(), TransactionError> {
+        let available = match self.clients.get(&transaction.client) {
+            Some(account) if account.locked() => return Ok(()),
+            Some(account) => account.available_in(transaction.currency),
+            None => Amount::zero(),
+        };
+        let after = match transaction.transaction_type {
+            TransactionType::Deposit(amount) => available + amount,
+            TransactionType::Withdrawal(amount) => available - amount,
+            _ => return Ok(()),
+        };
+
+        let limit = self.overdraft_limit(transaction.client);
+        let resulting_amount = after - fees;
+        if !(after + limit).less_than_zero() && (resulting_amount + limit).less_than_zero() {
+            return Err(TransactionError::InvalidFee { resulting_amount });
+        }
+        Ok(())
+    }
+
+    /// Rejects the transaction if any of the rules does.
+    fn check_rules(&self, transaction: &Transaction) -> Result<(), TransactionError> {
+        let account = self.clients.get(&transaction.client);
+        for rule in &self.config.rules {
+            if let RuleDecision::Reject { reason } = rule.evaluate(account, transaction) {
+                return Err(TransactionError::RuleRejected { reason });
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects a deposit or withdrawal above the maximum amount.
+    fn check_maximum(&self, transaction: &Transaction) -> Result<(), TransactionError> {
+        let amount = match transaction.transaction_type {
+            TransactionType::Deposit(amount) | TransactionType::Withdrawal(amount) => amount,
+            _ => return Ok(()),
+        };
+        match self.config.maximum_amount {
+            Some(maximum) if amount > maximum => {
+                Err(TransactionError::AboveMaximumAmount { maximum })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The velocity limit the transaction exceeds, if any. It's rejected instead under
+    /// `VelocityPolicy::Reject`.
+    fn check_velocity(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<Option<VelocityLimit>, TransactionError> {
+        let (limits, policy) = match &self.config.velocity {
+            Some(velocity) => velocity,
+            None => return Ok(None),
+        };
+        match limits.breach(self.activity.get(&transaction.client), transaction) {
+            Some(limit) if *policy == VelocityPolicy::Reject => {
+                Err(TransactionError::VelocityLimitExceeded { limit })
+            }
+            breach => Ok(breach),
+        }
+    }
+
+    /// Counts an applied transaction against the client's velocity limits.
+    fn record_activity(&mut self, transaction: &Transaction) {
+        if let Some((limits, _)) = &self.config.velocity {
+            self.activity
+                .entry(transaction.client)
+                .or_default()
+                .record(limits, transaction);
+        }
+    }
+
+    /// Rejects a withdrawal that would leave less than the minimum balance once its fees are
+    /// charged. Withdrawals that would be rejected anyway are left to be.
+    fn keep_minimum(
+        &self,
+        transaction: &Transaction,
+        fees: Amount,
+    ) -> Result<(), TransactionError> {
+        let (minimum, amount) = match (self.config.minimum_balance, transaction.transaction_type) {
+            (Some(minimum), TransactionType::Withdrawal(amount)) => (minimum, amount),
+            _ => return Ok(()),
+        };
+        let available = match self.clients.get(&transaction.client) {
+            Some(account) if account.locked() => return Ok(()),
+            Some(account) => account.available_in(transaction.currency),
+            None => Amount::zero(),
+        };
+
+        let after = available - amount;
+        let limit = self.overdraft_limit(transaction.client);
+        if amount.less_than_zero() || (after + limit).less_than_zero() {
+            return Ok(());
+        }
+
+        let resulting_amount = after - fees;
+        if resulting_amount < minimum {
+            return Err(TransactionError::BelowMinimumBalance {
+                minimum,
+                resulting_amount,
             });
+        }
+        Ok(())
+    }
+
+    /// Logs and applies a fee entry once the transaction it's charged for has been applied.
+    fn charge(&mut self, fee: Transaction) {
+        // The balance was checked to cover it before the transaction was applied.
+        if self.log(&fee).is_ok() && self.open(fee.client).execute_transaction(fee).is_ok() {
+            self.charged.push(fee);
+        }
+    }
+
+    /// Applies `merged` in place of `transactions`, marking all of their ids as used. Fails
+    /// without applying anything if any of the ids is reused, a rule rejects any of them, any of
+    /// them exceeds a velocity limit, or the fees charged on them can't be covered or would breach the minimum balance,
+    /// so the transactions can be applied one by one instead.
+    pub fn apply_merged(
+        &mut self,
+        transactions: &[Transaction],
+        merged: Transaction,
+    ) -> Result<(), TransactionError> {
+        let mut ids = HashSet::new();
+        for transaction in transactions {
+            self.check_rules(transaction)?;
+            self.check_maximum(transaction)?;
+            if self.already_processed(transaction) {
+                return Err(TransactionError::AlreadyProcessed {
+                    transaction_id: transaction.id,
+                });
+            }
+            if self.owners.contains_key(&transaction.id) || !ids.insert(transaction.id) {
+                return Err(TransactionError::DuplicateTransactionId {
+                    transaction_id: transaction.id,
+                });
+            }
+        }
+
+        // Each is counted on top of the ones before it. A breach that would only be reported is
+        // still left to be applied one by one, so it's reported against the right id.
+        let mut activity = None;
+        if let Some((limits, _)) = &self.config.velocity {
+            let mut counted = self
+                .activity
+                .get(&merged.client)
+                .cloned()
+                .unwrap_or_default();
+            for transaction in transactions {
+                if let Some(limit) = limits.breach(Some(&counted), transaction) {
+                    return Err(TransactionError::VelocityLimitExceeded { limit });
+                }
+                counted.record(limits, transaction);
+            }
+            activity = Some(counted);
+        }
+
+        let fees: Vec<Transaction> = transactions
+            .iter()
+            .filter_map(|transaction| self.fee(transaction))
+            .collect();
+        let total = fees
+            .iter()
+            .filter_map(Transaction::amount)
+            .fold(Amount::zero(), |total, fee| total + fee);
+        self.cover(&merged, total)?;
+        self.keep_minimum(&merged, total)?;
+
+        let account = self.open(merged.client);
+        account.execute_transaction(merged)?;
+        // Each original is kept in the history under its own id, so it can be disputed and is
+        // remembered as processed once the account is restored.
+        account.unmerge_history(transactions);
+
+        // The originals are logged rather than the merged transaction, so replaying the log
+        // marks all of their ids as used. They're only logged once the run is applied, as a
+        // rejected run is applied again one by one, logging each then. A failure is kept for
+        // `flush_storage` like any other.
+        for transaction in transactions {
+            let _result = self.log(transaction);
+        }
+        let charged = self.charged.len();
+        for fee in fees {
+            self.charge(fee);
+        }
+        self.cap_history(merged.client);
+        for transaction in transactions {
+            self.save(merged.client, transaction);
+        }
+        if let Some(activity) = activity {
+            self.activity.insert(merged.client, activity);
+        }
+
+        self.owners
+            .extend(ids.into_iter().map(|id| (id, merged.client)));
+        for transaction in transactions {
+            self.notify(transaction, &Ok(()));
+            self.notify_fees(charged, transaction);
+        }
+        Ok(())
+    }
+
+    /// Whether the transaction introduces an id the same client used in a previous run.
+    fn already_processed(&self, transaction: &Transaction) -> bool {
+        transaction.introduces_id()
+            && self.processed.contains(&transaction.id)
+            && self.owner(transaction.id) == Some(transaction.client)
+    }
+
+    /// Debits the sender and credits the recipient, changing neither if the transfer is rejected.
+    /// Only a locked recipient can reject a transfer, which is checked before the sender is
+    /// debited. The credit itself is never rejected, even into an overdrawn account.
+    fn transfer(&mut self, transaction: Transaction, to: ClientId) -> Result<(), TransactionError> {
+        let recipient_locked = self.account(to).is_some_and(|account| account.locked());
+        if recipient_locked && to != transaction.client {
+            return Err(TransactionError::RecipientLocked { client: to });
+        }
+
+        self.open(transaction.client)
+            .execute_transaction(transaction)?;
+        self.open(to).execute_transaction(transaction)
+    }
+
+    /// Appends the transaction to the write-ahead log, if the database has one. A failure is kept
+    /// for `flush_storage` and the transaction isn't applied.
+    fn log(&mut self, transaction: &Transaction) -> Result<(), TransactionError> {
+        if let Some(wal) = &self.config.wal {
+            if let Err(e) = wal.lock().unwrap().append(transaction) {
+                self.storage_error.get_or_insert(e.into());
+                return Err(TransactionError::NotLogged);
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the entry a dispute, resolve or chargeback refers to back from the spill file, if
+    /// it was spilled. A failure is kept for `flush_storage`.
+    fn unspill(&mut self, transaction: &Transaction) {
+        let spill = match &self.config.spill {
+            Some(spill) => spill,
+            None => return,
+        };
+
+        match spill
+            .lock()
+            .unwrap()
+            .take(transaction.client, transaction.id)
+        {
+            Ok(Some((state, spilled))) => {
+                if let Some(account) = self.clients.get_mut(&transaction.client) {
+                    account.restore_history(state, spilled);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                self.storage_error.get_or_insert(e.into());
+            }
+        }
+    }
+
+    /// Compacts the client's history if it's over the cap, spilling its oldest half if that
+    /// isn't enough. Entries that fail to spill are kept, and the failure is kept for
+    /// `flush_storage`.
+    fn cap_history(&mut self, client: ClientId) {
+        let (cap, account) = match (self.config.history_cap, self.clients.get_mut(&client)) {
+            (Some(cap), Some(account)) if account.history_len() > cap => (cap, account),
+            _ => return,
+        };
+
+        self.compacted += account.compact_history() as u64;
+        if account.history_len() <= cap {
+            return;
+        }
+
+        if let Some(spill) = &self.config.spill {
+            let spilled = account.spill_history(cap / 2);
+            if let Err(e) = spill.lock().unwrap().write(client, &spilled) {
+                for (state, transaction) in spilled {
+                    account.restore_history(state, transaction);
+                }
+                self.storage_error.get_or_insert(e.into());
+            }
+        }
+    }
+
+    /// How much the database is holding on to.
+    pub fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            resident_bytes: telemetry::resident_bytes(),
+            accounts: self.clients.len(),
+            transaction_ids: self.owners.len(),
+            history_entries: self.accounts().map(|account| account.history_len()).sum(),
+            largest_history: self
+                .accounts()
+                .map(|account| account.history_len())
+                .max()
+                .unwrap_or_default(),
+            compacted: self.compacted,
+            spilled: self
+                .config
+                .spill
+                .as_ref()
+                .map_or(0, |spill| spill.lock().unwrap().len()),
+        }
+    }
+
+    /// Saves the changes the transaction made to the client, if the database has storage.
+    /// Transactions are applied in memory either way, so a failure is kept for `flush_storage`.
+    fn save(&mut self, client: ClientId, transaction: &Transaction) {
+        if let (Some(storage), Some(account)) = (&self.config.storage, self.clients.get(&client)) {
+            if let Err(e) = storage.lock().unwrap().save(account, transaction) {
+                self.storage_error.get_or_insert(e);
+            }
+        }
+    }
+
+    /// Makes every change saved to storage durable. Returns the first failure to save an account
+    /// since the last flush, if any.
+    pub fn flush_storage(&mut self) -> Result<(), StorageError> {
+        if let Some(e) = self.storage_error.take() {
+            return Err(e);
+        }
+
+        match &self.config.storage {
+            Some(storage) => storage.lock().unwrap().flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Splits the database into `count` databases, each keeping the clients of one shard and the
+    /// ids they introduced, with clients assigned by the partitioner. Each shard gets a copy of the
+    /// database's config.
+    pub fn split(self, count: usize, partitioner: &dyn Partitioner) -> Vec<Self> {
+        let mut shards: Vec<Self> = (0..count.max(1))
+            .map(|_| Self {
+                config: self.config.clone(),
+                ..Self::with_accounts(self.open_account)
+            })
+            .collect();
+        let count = shards.len();
+
+        for (id, account) in self.clients {
+            shards[partitioner.shard(id, count)]
+                .clients
+                .insert(id, account);
+        }
+        for (transaction_id, owner) in self.owners {
+            let shard = &mut shards[partitioner.shard(owner, count)];
+            shard.owners.insert(transaction_id, owner);
+            if self.processed.contains(&transaction_id) {
+                shard.processed.insert(transaction_id);
+            }
+        }
+        for (client, activity) in self.activity {
+            shards[partitioner.shard(client, count)]
+                .activity
+                .insert(client, activity);
+        }
+        shards[0].duplicates = self.duplicates;
+        shards[0].velocity_breaches = self.velocity_breaches;
+        shards[0].charged = self.charged;
+        shards[0].storage_error = self.storage_error;
+        shards[0].compacted = self.compacted;
+
+        shards
+    }
+
+    /// Adds back the clients and ids of a shard split off by `split`.
+    pub fn join(&mut self, shard: Self) {
+        self.clients.extend(shard.clients);
+        self.owners.extend(shard.owners);
+        self.processed.extend(shard.processed);
+        self.duplicates.extend(shard.duplicates);
+        self.activity.extend(shard.activity);
+        self.velocity_breaches.extend(shard.velocity_breaches);
+        self.charged.extend(shard.charged);
+        self.compacted += shard.compacted;
+        if let Some(e) = shard.storage_error {
+            self.storage_error.get_or_insert(e);
+        }
+    }
+
+    /// Records that a client kept by another shard introduced the id, so its reuse is caught here.
+    pub(crate) fn note_owner(&mut self, transaction_id: TransactionId, owner: ClientId) {
+        self.owners.insert(transaction_id, owner);
+    }
+
+    /// Takes a client out of the database, so a transfer kept by another shard can reach it.
+    pub(crate) fn remove_account(&mut self, client: ClientId) -> Option<Account> {
+        self.clients.remove(&client)
+    }
+
+    /// Puts back a client taken out with `remove_account`.
+    pub(crate) fn insert_account(&mut self, account: Account) {
+        self.clients.insert(account.id(), account);
+    }
+
+    /// The client with the given id, creating it if this is its first transaction.
+    fn open(&mut self, client: ClientId) -> &mut Account {
+        let open_account = self.open_account;
+        let limit = self
+            .config
+            .overdraft
+            .is_some()
+            .then(|| self.overdraft_limit(client));
+        let account = self
+            .clients
+            .entry(client)
+            .or_insert_with(|| open_account(client));
+        // Set every time, as accounts may also be restored from snapshots or storage.
+        if let Some(limit) = limit {
+            account.set_overdraft_limit(limit);
+        }
+        account
+    }
+
+    /// How far below zero the client may draw its available balance.
+    fn overdraft_limit(&self, client: ClientId) -> Amount {
+        self.config
+            .overdraft
+            .as_ref()
+            .map(|limits| limits.limit(client))
+            .unwrap_or_default()
+    }
+
+    /// The client whose transaction introduced the given id.
+    pub fn owner(&self, transaction_id: TransactionId) -> Option<ClientId> {
+        self.owners.get(&transaction_id).copied()
+    }
+
+    /// Every id introduced by an applied transaction, with its client.
+    pub(crate) fn owners(&self) -> impl Iterator<Item = (TransactionId, ClientId)> + '_ {
+        self.owners.iter().map(|(id, owner)| (*id, *owner))
+    }
+
+    /// Ids reused by transactions that were applied under `DuplicatePolicy::Warn`.
+    pub fn duplicates(&self) -> &[TransactionId] {
+        &self.duplicates
+    }
+
+    /// The transactions applied under `VelocityPolicy::Report` despite exceeding a velocity limit,
+    /// by id, with the limit they exceeded.
+    pub fn velocity_breaches(&self) -> &[(TransactionId, VelocityLimit)] {
+        &self.velocity_breaches
+    }
+
+    /// Takes the fee entries applied since this was last called, in the order they were charged.
+    /// Fees are applied inside the database, so this is how they reach a journal or ledger.
+    pub fn take_charged_fees(&mut self) -> Vec<Transaction> {
+        std::mem::take(&mut self.charged)
+    }
+
+    /// The client with the given id, if it has been touched by a transaction.
+    pub fn account(&self, client: ClientId) -> Option<&Account> {
+        self.clients.get(&client)
+    }
+
+    /// The client with the given id, for changes outside of transactions.
+    pub fn account_mut(&mut self, client: ClientId) -> Option<&mut Account> {
+        self.clients.get_mut(&client)
+    }
+
+    /// Every client that has been touched by a transaction.
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.clients.values()
+    }
+
+    /// Sends the report for every client that has been touched by a transaction to the sink.
+    pub fn write_to_sink(
+        &self,
+        report: &Report<Account>,
+        sink: &mut dyn ReportSink<Account>,
+    ) -> Result<(), SinkError> {
+        sink.write_report(&report.for_accounts(self.accounts()), &mut self.accounts())
+    }
+
+    /// Prints the report for every client that has been touched by a transaction.
+    pub fn output(&self, report: &Report<Account>) {
+        let stdout = std::io::stdout();
+        // Printing can only fail if stdout was closed, in which case there's nowhere to report it.
+        let _result = self.write_report(report, &mut stdout.lock());
+    }
+
+    /// Writes the report for every client that has been touched by a transaction.
+    pub fn write_report(
+        &self,
+        report: &Report<Account>,
+        writer: &mut dyn Write,
+    ) -> std::io::Result<()> {
+        let report = report.for_accounts(self.accounts());
+        writeln!(writer, "{}", report.header())?;
+
+        for account in self.accounts() {
+            writeln!(writer, "{}", report.row(account))?;
+            for other in account.other_currencies() {
+                writeln!(writer, "{}", report.row(&other))?;
+            }
+        }
+
+        writer.flush()
     }
 }
 
 impl Database<Client> {
     pub fn new() -> Self {
-        let mut clients = Vec::with_capacity(ClientId::MAX as usize);
+        Self::with_accounts(Client::new)
+    }
+
+    /// Captures every client that has been touched by a transaction.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            engine_version: String::from(manifest::engine_version()),
+            config_hash: None,
+            clients: self
+                .accounts()
+                .map(|account| account.to_snapshot())
+                .collect(),
+        }
+    }
 
-        for client_id in 0..ClientId::MAX as usize + 1 {
-            clients.push((Client::new(client_id as ClientId), Status::Invalid));
+    /// Restores a database from a snapshot.
+    pub fn from_snapshot(snapshot: Snapshot) -> Result<Self, SnapshotError> {
+        let mut database = Self::new();
+
+        for client in snapshot.clients {
+            database.restore(Client::from_snapshot(client)?);
         }
-        Self { clients }
+
+        Ok(database)
+    }
+
+    /// Saves every client's balances, lock state and transaction states to a file, so a later run
+    /// can start from them.
+    pub fn save_snapshot(&self, path: &str, format: SnapshotFormat) -> Result<(), SnapshotError> {
+        snapshot::write_to_path(&self.snapshot(), path, format)
+    }
+
+    /// Loads a database saved with `save_snapshot`, refusing one saved by an incompatible engine
+    /// version unless `force_migrate` is set.
+    pub fn load_snapshot(
+        path: &str,
+        format: SnapshotFormat,
+        force_migrate: bool,
+    ) -> Result<Self, SnapshotError> {
+        snapshot::read_from_path(path, format, force_migrate).and_then(Self::from_snapshot)
+    }
+
+    /// Combines the end state of a run over transactions with other ids, such as a run over
+    /// another range of the same input. The accounts of clients in both are merged. Nothing is
+    /// changed if both applied a transaction with the same id.
+    pub fn merge(&mut self, other: Self) -> Result<(), MergeError> {
+        let conflict = other
+            .owners
+            .keys()
+            .filter(|id| self.owners.contains_key(id))
+            .min();
+        if let Some(id) = conflict {
+            return Err(MergeError::ConflictingTransactionId { id: *id });
+        }
+
+        for (id, client) in other.clients {
+            match self.clients.get_mut(&id) {
+                Some(account) => account.merge(client),
+                None => {
+                    self.clients.insert(id, client);
+                }
+            }
+        }
+        self.owners.extend(other.owners);
+        self.processed.extend(other.processed);
+        self.duplicates.extend(other.duplicates);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "sled-storage")]
+    /// Opens the accounts kept in the sled database at the path.
+    pub fn open_storage(path: &str) -> Result<Self, StorageError> {
+        Self::with_storage(Box::new(SledStorage::open(path)?))
+    }
+
+    #[cfg(not(feature = "sled-storage"))]
+    pub fn open_storage(_path: &str) -> Result<Self, StorageError> {
+        Err(StorageError::Unavailable)
+    }
+
+    /// Loads every account saved in the storage, then saves every change made to them.
+    pub fn with_storage(mut storage: Box<dyn Storage<Client>>) -> Result<Self, StorageError> {
+        let mut database = Self::new();
+
+        for client in storage.load()? {
+            database.restore(client);
+        }
+
+        database.config.storage = Some(Arc::new(Mutex::new(storage)));
+        Ok(database)
+    }
+
+    /// Adds a previously saved client, remembering the ids it used as already processed.
+    fn restore(&mut self, client: Client) {
+        let id = client.id();
+        self.owners
+            .extend(client.transaction_ids().map(|tx| (tx, id)));
+        self.processed.extend(client.transaction_ids());
+        self.clients.insert(id, client);
+    }
+}
+
+impl Default for Database<Client> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::amount::Amount;
+    use crate::fixtures::{
+        amount, chargeback, deposit, dispute, places, resolve, transaction, withdrawal,
+    };
+    use crate::rules::Blocklist;
+    use crate::velocity::{VelocityLimit, VelocityLimits, VelocityPolicy};
+    use crate::{
+        amount::Amount,
+        currency::Currency,
+        diff,
+        fees::Fee,
+        transaction::{TransactionState, TransactionType},
+    };
 
     #[test]
     fn database_new_returns_expected() {
         let db = Database::<Client>::new();
-        for (id, (client, status)) in db.clients.iter().enumerate() {
-            assert_eq!(id as ClientId, client.id());
-            assert_eq!(Status::Invalid, *status);
-        }
+        assert_eq!(0, db.accounts().count());
     }
 
     #[test]
-    fn database_apply_transaction_sets_account_to_valid_returns_result() {
+    fn database_apply_transaction_creates_account_returns_result() {
         // TODO: With more time, the ideal would have been to make a mock implementation of the ClientAccount trait and use it for testing.
 
         let mut db = Database::<Client>::new();
         let client_id = 45;
 
-        let transaction = Transaction {
-            transaction_type: TransactionType::Deposit(Amount::new(342)),
-            client: client_id,
-            id: 23,
-        };
+        let transaction = deposit(client_id, 23, 342);
         let db_result = db.apply_transaction(transaction);
 
-        assert_eq!(Status::Valid, db.clients[client_id as usize].1);
+        assert_eq!(true, db.account(client_id).is_some());
         assert_eq!(
-            db.clients[client_id as usize]
-                .0
+            db.clients
+                .get_mut(&client_id)
+                .unwrap()
                 .execute_transaction(transaction),
             db_result
         );
     }
 
+    #[test]
+    fn database_accounts_returns_touched_clients_in_id_order() {
+        let mut db = Database::<Client>::new();
+
+        for client in [9, 2, 5] {
+            let transaction = deposit(client, client as u32, 342);
+            db.apply_transaction(transaction).unwrap();
+        }
+
+        let ids: Vec<ClientId> = db.accounts().map(|account| account.id()).collect();
+        assert_eq!(vec![2, 5, 9], ids);
+    }
+
+    #[test]
+    fn database_snapshot_round_trips_touched_clients() {
+        let mut db = Database::<Client>::new();
+        let transaction = deposit(45, 23, 342);
+        db.apply_transaction(transaction).unwrap();
+
+        let snapshot = db.snapshot();
+        assert_eq!(1, snapshot.clients.len());
+
+        let restored = Database::from_snapshot(snapshot).unwrap();
+        assert_eq!(db.account(45), restored.account(45));
+        assert_eq!(true, restored.account(46).is_none());
+    }
+
+    #[test]
+    fn database_save_snapshot_then_load_restores_state() {
+        let mut db = Database::<Client>::new();
+        db.apply_transaction(deposit(1, 7, 342)).unwrap();
+        db.apply_transaction(deposit(2, 8, 342)).unwrap();
+        db.apply_transaction(dispute(2, 8)).unwrap();
+        db.apply_transaction(chargeback(2, 8)).unwrap();
+
+        for (format, extension) in [
+            (SnapshotFormat::Bincode, "bin"),
+            (SnapshotFormat::Json, "json"),
+        ] {
+            let path =
+                std::env::temp_dir().join(format!("payments_database_snapshot.{}", extension));
+            let path = path.to_str().unwrap();
+            db.save_snapshot(path, format).unwrap();
+
+            let mut loaded = Database::load_snapshot(path, format, false).unwrap();
+            assert_eq!(db.account(1), loaded.account(1));
+            assert_eq!(true, loaded.account(2).unwrap().locked());
+            assert_eq!(
+                TransactionState::Chargebacked,
+                loaded.account(2).unwrap().history()[0].0
+            );
+            assert_eq!(true, loaded.apply_transaction(deposit(1, 8, 342)).is_err());
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn database_merge_combines_accounts() {
+        let mut db = Database::<Client>::new();
+        db.apply_transaction(deposit(1, 7, 342)).unwrap();
+        db.apply_transaction(deposit(2, 8, 342)).unwrap();
+        let mut other = Database::<Client>::new();
+        other.apply_transaction(deposit(1, 9, 342)).unwrap();
+        other.apply_transaction(deposit(3, 10, 342)).unwrap();
+
+        db.merge(other).unwrap();
+
+        assert_eq!(Amount::new(684), db.account(1).unwrap().available());
+        assert_eq!(Amount::new(342), db.account(2).unwrap().available());
+        assert_eq!(Amount::new(342), db.account(3).unwrap().available());
+        assert_eq!(true, db.apply_transaction(deposit(2, 10, 342)).is_err());
+    }
+
+    #[test]
+    fn database_merge_conflicting_ids_returns_err() {
+        let mut db = Database::<Client>::new();
+        db.apply_transaction(deposit(1, 7, 342)).unwrap();
+        let mut other = Database::<Client>::new();
+        other.apply_transaction(deposit(2, 7, 342)).unwrap();
+
+        let expected = MergeError::ConflictingTransactionId { id: 7 };
+        assert_eq!(Err(expected), db.merge(other));
+        assert_eq!(true, db.account(2).is_none());
+    }
+
     #[test]
     fn database_apply_transaction_works_for_max_clients() {
         let mut db = Database::<Client>::new();
 
         for client in 0..ClientId::MAX as usize + 1 {
             let client_id = client as ClientId;
-            let transaction = Transaction {
-                transaction_type: TransactionType::Deposit(Amount::new(342)),
-                client: client_id,
-                id: 23,
-            };
+            let transaction = deposit(client_id, client as u32, 342);
             let db_result = db.apply_transaction(transaction);
 
-            assert_eq!(Status::Valid, db.clients[client_id as usize].1);
+            assert_eq!(true, db.account(client_id).is_some());
             assert_eq!(
-                db.clients[client_id as usize]
-                    .0
+                db.clients
+                    .get_mut(&client_id)
+                    .unwrap()
                     .execute_transaction(transaction),
                 db_result
             );
         }
     }
+
+    #[test]
+    fn database_apply_transaction_duplicate_id_returns_err() {
+        let mut db = Database::<Client>::new();
+        db.apply_transaction(deposit(1, 7, 342)).unwrap();
+
+        let expected = TransactionError::DuplicateTransactionId { transaction_id: 7 };
+        assert_eq!(Err(expected), db.apply_transaction(deposit(2, 7, 342)));
+        assert_eq!(Err(expected), db.apply_transaction(deposit(1, 7, 342)));
+        assert_eq!(Amount::new(342), db.account(1).unwrap().total());
+    }
+
+    #[test]
+    fn database_apply_transaction_duplicate_id_warn_applies() {
+        let mut db = Database::<Client>::new().with_duplicate_policy(DuplicatePolicy::Warn);
+        db.apply_transaction(deposit(1, 7, 342)).unwrap();
+
+        assert_eq!(Ok(()), db.apply_transaction(deposit(1, 7, 342)));
+        assert_eq!(Amount::new(684), db.account(1).unwrap().total());
+        assert_eq!(&[7], db.duplicates());
+    }
+
+    #[test]
+    fn database_apply_transaction_dispute_reuses_id() {
+        let mut db = Database::<Client>::new();
+        db.apply_transaction(deposit(1, 7, 342)).unwrap();
+
+        let dispute = dispute(1, 7);
+        assert_eq!(Ok(()), db.apply_transaction(dispute));
+    }
+
+    #[test]
+    fn database_apply_transaction_dispute_of_other_client_returns_err() {
+        let mut db = Database::<Client>::new();
+        db.apply_transaction(deposit(1, 7, 342)).unwrap();
+
+        let dispute = |id| dispute(2, id);
+
+        let expected = TransactionError::BelongsToAnotherClient {
+            transaction_id: 7,
+            owner: 1,
+        };
+        assert_eq!(Err(expected), db.apply_transaction(dispute(7)));
+        assert_eq!(
+            Err(TransactionError::NotFound { transaction_id: 8 }),
+            db.apply_transaction(dispute(8))
+        );
+        assert_eq!(Some(1), db.owner(7));
+    }
+
+    #[test]
+    fn database_write_report_adds_currency_column_for_other_currencies() {
+        let mut db = Database::<Client>::new();
+        let deposit = |client, id| transaction(client, id, TransactionType::Deposit(amount("1.5")));
+        for (id, currency) in [(1, "EUR"), (2, "USD")] {
+            db.apply_transaction(Transaction {
+                currency: Currency::from_str(currency).ok(),
+                ..deposit(1, id)
+            })
+            .unwrap();
+        }
+        db.apply_transaction(deposit(2, 3)).unwrap();
+
+        let mut output = vec![];
+        db.write_report(&Report::standard(), &mut output).unwrap();
+
+        let expected = "client, available, held, total, locked, currency\n\
+                        1, 1.5000, 0.0000, 1.5000, false, EUR\n\
+                        1, 1.5000, 0.0000, 1.5000, false, USD\n\
+                        2, 1.5000, 0.0000, 1.5000, false, \n";
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(places(expected), output);
+        assert_eq!(3, diff::read_report(output.as_bytes()).unwrap().len());
+    }
+
+    #[test]
+    fn database_with_rates_prices_conversions() {
+        let eur = Currency::from_str("EUR").unwrap();
+        let usd = Currency::from_str("USD").unwrap();
+        let mut rates = ExchangeRates::default();
+        rates.insert(eur, usd, rust_decimal::Decimal::new(15, 1));
+        let mut db = Database::<Client>::new().with_rates(rates, RoundingMode::HalfUp);
+
+        let mut deposit = deposit(1, 7, 342);
+        deposit.currency = Some(eur);
+        db.apply_transaction(deposit).unwrap();
+
+        let convert = |to| {
+            transaction(
+                1,
+                8,
+                TransactionType::Convert {
+                    amount: Amount::new(101),
+                    to,
+                    converted: None,
+                },
+            )
+        };
+        assert_eq!(
+            Err(TransactionError::RateUnavailable {
+                from: Some(usd),
+                to: eur,
+            }),
+            db.apply_transaction(Transaction {
+                currency: Some(usd),
+                ..convert(eur)
+            })
+        );
+        assert_eq!(Ok(()), db.apply_transaction(convert(usd)));
+
+        let account = db.account(1).unwrap();
+        assert_eq!(Amount::new(241), account.available());
+        assert_eq!(Amount::new(152), account.other_currencies()[0].available());
+        // The conversion is kept as it was priced.
+        let expected = TransactionType::Convert {
+            amount: Amount::new(101),
+            to: usd,
+            converted: Some(Amount::new(152)),
+        };
+        assert_eq!(
+            expected,
+            account.history().last().unwrap().1.transaction_type
+        );
+    }
+
+    fn fee_schedule() -> FeeSchedule {
+        FeeSchedule {
+            deposit: Some(Fee::Percent(rust_decimal::Decimal::ONE)),
+            withdrawal: Some(Fee::Flat(Amount::new(100))),
+        }
+    }
+
+    #[test]
+    fn database_with_fees_charges_fee_entries() {
+        let mut db = Database::<Client>::new().with_fees(fee_schedule(), RoundingMode::HalfUp);
+
+        // 1% of 0.0342 rounds to 0.0003.
+        assert_eq!(Ok(()), db.apply_transaction(deposit(1, 7, 342)));
+        assert_eq!(Ok(()), db.apply_transaction(withdrawal(1, 8, 200)));
+        // The withdrawal alone would leave 0.0000, which can't cover its fee.
+        assert_eq!(
+            Err(TransactionError::InvalidFee {
+                resulting_amount: Amount::new(-100),
+            }),
+            db.apply_transaction(withdrawal(1, 9, 39))
+        );
+
+        let account = db.account(1).unwrap();
+        assert_eq!(Amount::new(39), account.available());
+        assert_eq!(Amount::new(103), account.stats().fees);
+        let fees: Vec<_> = account
+            .history()
+            .iter()
+            .filter_map(|(_, transaction)| match transaction.transaction_type {
+                TransactionType::Fee { amount } => Some((transaction.id, amount)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vec![(7, Amount::new(3)), (8, Amount::new(100))], fees);
+        assert_eq!(None, db.owner(9));
+    }
+
+    #[test]
+    fn database_with_wal_logs_rejected_merged_run_once() {
+        let path = std::env::temp_dir().join("payments_database_merged.wal");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let mut db = Database::<Client>::new().with_wal(path).unwrap();
+        db.apply_transaction(deposit(1, 7, 342)).unwrap();
+        let run = [withdrawal(1, 8, 200), withdrawal(1, 9, 200)];
+        assert_eq!(true, db.apply_merged(&run, withdrawal(1, 8, 400)).is_err());
+        for transaction in run {
+            let _result = db.apply_transaction(transaction);
+        }
+
+        let (_, logged) = WriteAheadLog::open(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(vec![deposit(1, 7, 342), run[0], run[1]], logged);
+    }
+
+    #[test]
+    fn database_with_wal_does_not_charge_replayed_fees_again() {
+        let path = std::env::temp_dir().join("payments_database_fees.wal");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let mut db = Database::<Client>::new()
+            .with_fees(fee_schedule(), RoundingMode::HalfUp)
+            .with_wal(path)
+            .unwrap();
+        db.apply_transaction(deposit(1, 7, 342)).unwrap();
+        db.apply_transaction(withdrawal(1, 8, 200)).unwrap();
+
+        let replayed = Database::<Client>::new()
+            .with_fees(fee_schedule(), RoundingMode::HalfUp)
+            .with_wal(path)
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let account = replayed.account(1).unwrap();
+        assert_eq!(Amount::new(39), account.available());
+        assert_eq!(Amount::new(103), account.stats().fees);
+    }
+
+    #[test]
+    fn database_with_overdraft_applies_limit_of_each_client() {
+        let mut limits = OverdraftLimits::new(Amount::new(100));
+        limits.insert(2, Amount::new(1000));
+        let mut db = Database::<Client>::new()
+            .with_overdraft(limits)
+            .with_fees(fee_schedule(), RoundingMode::HalfUp);
+
+        // Client 1 can go to -0.0100, so the withdrawal fits but its fee doesn't.
+        db.apply_transaction(deposit(1, 1, 342)).unwrap();
+        assert_eq!(
+            Err(TransactionError::InvalidFee {
+                resulting_amount: Amount::new(-200),
+            }),
+            db.apply_transaction(withdrawal(1, 2, 439))
+        );
+        assert_eq!(Ok(()), db.apply_transaction(withdrawal(1, 3, 339)));
+        assert_eq!(Amount::new(-100), db.account(1).unwrap().available());
+
+        // Client 2 has its own limit of 0.1000.
+        assert_eq!(Ok(()), db.apply_transaction(withdrawal(2, 4, 800)));
+        assert_eq!(Amount::new(-900), db.account(2).unwrap().available());
+        assert_eq!(true, db.apply_transaction(withdrawal(2, 5, 1)).is_err());
+    }
+
+    #[test]
+    fn database_with_minimum_balance_rejects_withdrawals_below_it() {
+        let mut db = Database::<Client>::new()
+            .with_minimum_balance(Amount::new(150))
+            .with_fees(fee_schedule(), RoundingMode::HalfUp);
+        db.apply_transaction(deposit(1, 1, 342)).unwrap();
+
+        // 0.0339 less 0.0089 and its 0.0100 fee leaves 0.0150.
+        assert_eq!(Ok(()), db.apply_transaction(withdrawal(1, 2, 89)));
+        assert_eq!(
+            Err(TransactionError::BelowMinimumBalance {
+                minimum: Amount::new(150),
+                resulting_amount: Amount::new(49),
+            }),
+            db.apply_transaction(withdrawal(1, 3, 1))
+        );
+        // Withdrawals beyond the available balance are still invalid.
+        assert_eq!(
+            Err(TransactionError::InvalidWithdrawal {
+                resulting_amount: Amount::new(-1),
+            }),
+            db.apply_transaction(withdrawal(1, 4, 151))
+        );
+        assert_eq!(Amount::new(150), db.account(1).unwrap().available());
+    }
+
+    type Observed = Arc<Mutex<Vec<(TransactionId, bool, Option<Amount>)>>>;
+
+    /// Records each transaction's id, whether it was applied, and the available balance after it.
+    struct RecordingObserver(Observed);
+
+    impl AccountObserver<Client> for RecordingObserver {
+        fn on_transaction(
+            &mut self,
+            transaction: &Transaction,
+            result: &Result<(), TransactionError>,
+            account: Option<&Client>,
+        ) {
+            self.0.lock().unwrap().push((
+                transaction.id,
+                result.is_ok(),
+                account.map(|account| account.available()),
+            ));
+        }
+    }
+
+    #[test]
+    fn database_subscribe_notifies_observers_with_resulting_account() {
+        let observed = Observed::default();
+        let mut db = Database::<Client>::new();
+        db.subscribe(Box::new(RecordingObserver(observed.clone())));
+
+        db.apply_transaction(withdrawal(1, 1, 1)).unwrap_err();
+        db.apply_transaction(deposit(1, 2, 342)).unwrap();
+        db.apply_transaction(withdrawal(1, 3, 1000)).unwrap_err();
+        db.apply_merged(
+            &[deposit(1, 4, 342), deposit(1, 5, 342)],
+            deposit(1, 4, 684),
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                (1, false, Some(Amount::zero())),
+                (2, true, Some(Amount::new(342))),
+                (3, false, Some(Amount::new(342))),
+                (4, true, Some(Amount::new(1026))),
+                (5, true, Some(Amount::new(1026))),
+            ],
+            *observed.lock().unwrap()
+        );
+    }
+
+    /// Rejects withdrawals from accounts holding disputed funds.
+    struct NoWithdrawalsWhileHeld;
+
+    impl TransactionRule<Client> for NoWithdrawalsWhileHeld {
+        fn evaluate(&self, account: Option<&Client>, transaction: &Transaction) -> RuleDecision {
+            match (account, transaction.transaction_type) {
+                (Some(account), TransactionType::Withdrawal(_))
+                    if account.held() > Amount::zero() =>
+                {
+                    RuleDecision::Reject {
+                        reason: "funds are held",
+                    }
+                }
+                _ => RuleDecision::Allow,
+            }
+        }
+    }
+
+    #[test]
+    fn database_with_rule_rejects_transactions_the_rules_reject() {
+        let mut db = Database::<Client>::new()
+            .with_rule(Box::new(Blocklist(vec![2].into_iter().collect())))
+            .with_rule(Box::new(NoWithdrawalsWhileHeld));
+        db.apply_transaction(deposit(1, 1, 342)).unwrap();
+        db.apply_transaction(deposit(1, 2, 342)).unwrap();
+        let dispute = dispute(1, 1);
+        db.apply_transaction(dispute).unwrap();
+
+        assert_eq!(
+            Err(TransactionError::RuleRejected {
+                reason: "funds are held"
+            }),
+            db.apply_transaction(withdrawal(1, 3, 1))
+        );
+        assert_eq!(
+            Err(TransactionError::RuleRejected {
+                reason: "client is blocklisted"
+            }),
+            db.apply_transaction(deposit(2, 4, 342))
+        );
+        assert_eq!(true, db.account(2).is_none());
+
+        let resolve = resolve(1, 1);
+        db.apply_transaction(resolve).unwrap();
+        assert_eq!(Ok(()), db.apply_transaction(withdrawal(1, 3, 1)));
+    }
+
+    fn velocity_limits() -> VelocityLimits {
+        VelocityLimits {
+            withdrawal_total: Some((Amount::new(100), 86400)),
+            transaction_count: Some((2, 3600)),
+        }
+    }
+
+    fn timed(transaction: Transaction, timestamp: u64) -> Transaction {
+        Transaction {
+            timestamp: Some(timestamp),
+            ..transaction
+        }
+    }
+
+    #[test]
+    fn database_with_velocity_limits_rejects_transactions_beyond_them() {
+        let mut db = Database::<Client>::new()
+            .with_velocity_limits(velocity_limits(), VelocityPolicy::Reject);
+        db.apply_transaction(timed(deposit(1, 1, 342), 0)).unwrap();
+        db.apply_transaction(timed(withdrawal(1, 2, 60), 10))
+            .unwrap();
+
+        let withdrawn = Err(TransactionError::VelocityLimitExceeded {
+            limit: VelocityLimit::WithdrawalTotal {
+                total: Amount::new(100),
+                window: 86400,
+            },
+        });
+        assert_eq!(
+            withdrawn,
+            db.apply_transaction(timed(withdrawal(1, 3, 50), 4000))
+        );
+        // Rejected transactions aren't counted, and untimed ones never are.
+        assert_eq!(
+            Ok(()),
+            db.apply_transaction(timed(withdrawal(1, 4, 40), 4000))
+        );
+        assert_eq!(Ok(()), db.apply_transaction(deposit(1, 5, 342)));
+
+        db.apply_transaction(timed(deposit(1, 6, 342), 4010))
+            .unwrap();
+        assert_eq!(
+            Err(TransactionError::VelocityLimitExceeded {
+                limit: VelocityLimit::TransactionCount {
+                    count: 2,
+                    window: 3600
+                },
+            }),
+            db.apply_transaction(timed(deposit(1, 7, 342), 4020))
+        );
+        // Other clients have their own windows.
+        assert_eq!(
+            Ok(()),
+            db.apply_transaction(timed(deposit(2, 8, 342), 4020))
+        );
+        assert_eq!(true, db.velocity_breaches().is_empty());
+    }
+
+    #[test]
+    fn database_with_velocity_limits_reports_transactions_beyond_them() {
+        let mut db = Database::<Client>::new()
+            .with_velocity_limits(velocity_limits(), VelocityPolicy::Report);
+        db.apply_transaction(timed(deposit(1, 1, 342), 0)).unwrap();
+
+        assert_eq!(
+            Ok(()),
+            db.apply_transaction(timed(withdrawal(1, 2, 101), 10))
+        );
+        assert_eq!(
+            &[(
+                2,
+                VelocityLimit::WithdrawalTotal {
+                    total: Amount::new(100),
+                    window: 86400
+                }
+            )],
+            db.velocity_breaches()
+        );
+        assert_eq!(Amount::new(241), db.account(1).unwrap().available());
+    }
+
+    fn transfer(client: ClientId, id: TransactionId, to: ClientId) -> Transaction {
+        transaction(
+            client,
+            id,
+            TransactionType::Transfer {
+                to,
+                amount: Amount::new(100),
+            },
+        )
+    }
+
+    #[test]
+    fn database_apply_transaction_transfer_moves_funds() {
+        let mut db = Database::<Client>::new();
+        db.apply_transaction(deposit(1, 7, 342)).unwrap();
+
+        assert_eq!(Ok(()), db.apply_transaction(transfer(1, 8, 2)));
+        assert_eq!(Amount::new(242), db.account(1).unwrap().available());
+        assert_eq!(Amount::new(100), db.account(2).unwrap().available());
+        assert_eq!(Some(1), db.owner(8));
+
+        let restored = Database::from_snapshot(db.snapshot()).unwrap();
+        assert_eq!(Some(1), restored.owner(8));
+        assert_eq!(Amount::new(100), restored.account(2).unwrap().available());
+    }
+
+    #[test]
+    fn database_apply_transaction_transfer_without_funds_changes_nothing() {
+        let mut db = Database::<Client>::new();
+
+        let expected = TransactionError::InvalidTransfer {
+            resulting_amount: Amount::new(-100),
+        };
+        assert_eq!(Err(expected), db.apply_transaction(transfer(1, 8, 2)));
+        assert_eq!(true, db.account(2).is_none());
+    }
+
+    #[test]
+    fn database_apply_transaction_transfer_to_locked_client_returns_err() {
+        let mut db = Database::<Client>::new();
+        db.apply_transaction(deposit(1, 7, 342)).unwrap();
+        db.apply_transaction(deposit(2, 9, 342)).unwrap();
+        for transaction_type in [
+            TransactionType::Dispute { case: None },
+            TransactionType::Chargeback { case: None },
+        ] {
+            db.apply_transaction(transaction(2, 9, transaction_type))
+                .unwrap();
+        }
+
+        let expected = TransactionError::RecipientLocked { client: 2 };
+        assert_eq!(Err(expected), db.apply_transaction(transfer(1, 8, 2)));
+        assert_eq!(Amount::new(342), db.account(1).unwrap().available());
+        assert_eq!(Amount::zero(), db.account(2).unwrap().available());
+    }
+
+    #[test]
+    fn database_apply_transaction_transfer_to_overdrawn_client_moves_funds() {
+        let mut limits = OverdraftLimits::new(Amount::zero());
+        limits.insert(2, Amount::new(1000));
+        let mut db = Database::<Client>::new().with_overdraft(limits);
+        db.apply_transaction(deposit(1, 7, 342)).unwrap();
+        let withdrawal = withdrawal(2, 9, 600);
+        db.apply_transaction(withdrawal).unwrap();
+
+        assert_eq!(Ok(()), db.apply_transaction(transfer(1, 8, 2)));
+        assert_eq!(Amount::new(242), db.account(1).unwrap().available());
+        assert_eq!(Amount::new(-500), db.account(2).unwrap().available());
+    }
+
+    /// Remembers which clients were saved for which transactions.
+    #[derive(Clone, Default)]
+    struct RecordingStorage(std::sync::Arc<std::sync::Mutex<Vec<(ClientId, TransactionId)>>>);
+
+    impl Storage<Client> for RecordingStorage {
+        fn load(&mut self) -> Result<Vec<Client>, StorageError> {
+            let mut client = Client::new(1);
+            client.execute_transaction(deposit(1, 7, 342)).unwrap();
+            Ok(vec![client])
+        }
+
+        fn save(
+            &mut self,
+            account: &Client,
+            transaction: &Transaction,
+        ) -> Result<(), StorageError> {
+            self.0.lock().unwrap().push((account.id(), transaction.id));
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), StorageError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn database_with_storage_loads_and_saves_touched_clients() {
+        let storage = RecordingStorage::default();
+        let mut db = Database::with_storage(Box::new(storage.clone())).unwrap();
+
+        assert_eq!(Amount::new(342), db.account(1).unwrap().total());
+        assert_eq!(true, db.apply_transaction(deposit(2, 7, 342)).is_err());
+
+        db.apply_transaction(transfer(1, 8, 2)).unwrap();
+        assert_eq!(Ok(()), db.flush_storage().map_err(|_| ()));
+        assert_eq!(vec![(1, 8), (2, 8)], *storage.0.lock().unwrap());
+    }
+
+    #[test]
+    fn database_from_snapshot_remembers_used_ids() {
+        let mut db = Database::<Client>::new();
+        db.apply_transaction(deposit(1, 7, 342)).unwrap();
+
+        let mut restored = Database::from_snapshot(db.snapshot()).unwrap();
+
+        assert_eq!(
+            true,
+            restored.apply_transaction(deposit(2, 7, 342)).is_err()
+        );
+    }
+
+    #[test]
+    fn database_from_snapshot_skips_already_processed() {
+        let mut db = Database::<Client>::new();
+        db.apply_transaction(deposit(1, 7, 342)).unwrap();
+
+        let mut restored = Database::from_snapshot(db.snapshot())
+            .unwrap()
+            .with_duplicate_policy(DuplicatePolicy::Warn);
+
+        let expected = TransactionError::AlreadyProcessed { transaction_id: 7 };
+        assert_eq!(
+            Err(expected),
+            restored.apply_transaction(deposit(1, 7, 342))
+        );
+        assert_eq!(db.account(1), restored.account(1));
+        assert_eq!(true, restored.duplicates().is_empty());
+
+        let mut next = Database::from_snapshot(restored.snapshot()).unwrap();
+        next.apply_transaction(deposit(1, 8, 342)).unwrap();
+        let expected = TransactionError::DuplicateTransactionId { transaction_id: 8 };
+        assert_eq!(Err(expected), next.apply_transaction(deposit(1, 8, 342)));
+    }
+
+    #[test]
+    fn database_apply_merged_duplicate_id_returns_err() {
+        let mut db = Database::<Client>::new();
+        db.apply_transaction(deposit(1, 7, 342)).unwrap();
+
+        let run = [deposit(1, 8, 342), deposit(1, 7, 342)];
+        assert_eq!(true, db.apply_merged(&run, deposit(1, 8, 342)).is_err());
+        assert_eq!(Amount::new(342), db.account(1).unwrap().total());
+
+        let run = [deposit(1, 8, 342), deposit(1, 9, 342)];
+        assert_eq!(Ok(()), db.apply_merged(&run, deposit(1, 8, 342)));
+        assert_eq!(true, db.apply_transaction(deposit(1, 9, 342)).is_err());
+    }
+
+    #[test]
+    fn database_apply_merged_keeps_every_id_after_restore() {
+        let mut db = Database::<Client>::new();
+        let run = [
+            deposit(1, 1, 10000),
+            deposit(1, 2, 20000),
+            deposit(1, 3, 30000),
+        ];
+        db.apply_merged(&run, deposit(1, 1, 60000)).unwrap();
+
+        let mut restored = Database::from_snapshot(db.snapshot()).unwrap();
+
+        let expected = TransactionError::AlreadyProcessed { transaction_id: 2 };
+        assert_eq!(Err(expected), restored.apply_transaction(run[1]));
+        assert_eq!(Ok(()), restored.apply_transaction(dispute(1, 3)));
+        let account = restored.account(1).unwrap();
+        assert_eq!(Amount::new(60000), account.total());
+        assert_eq!(Amount::new(30000), account.held());
+    }
+
+    #[test]
+    fn database_with_history_cap_spills_and_restores_history() {
+        let path = std::env::temp_dir().join("payments_database_spill.bin");
+        let path = path.to_str().unwrap();
+
+        let spill = HistorySpill::create(path).unwrap();
+        let mut capped = Database::<Client>::new().with_history_cap(4, Some(spill));
+        let mut uncapped = Database::<Client>::new();
+
+        let mut transactions: Vec<Transaction> = (1..=10).map(|id| deposit(1, id, 342)).collect();
+        for transaction_type in [
+            TransactionType::Dispute { case: None },
+            TransactionType::Chargeback { case: None },
+        ] {
+            transactions.push(transaction(1, 2, transaction_type));
+        }
+
+        for transaction in transactions {
+            let expected = uncapped.apply_transaction(transaction);
+            assert_eq!(expected, capped.apply_transaction(transaction));
+        }
+        let stats = capped.memory_stats();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            uncapped.account(1).unwrap().total(),
+            capped.account(1).unwrap().total()
+        );
+        assert_eq!(true, capped.account(1).unwrap().locked());
+        assert_eq!(true, stats.largest_history <= 4);
+        assert_eq!(true, capped.account(1).unwrap().events().len() <= 4);
+        // Every applied transaction is still in memory, spilled or compacted away.
+        let recorded = stats.history_entries + stats.spilled + stats.compacted as usize;
+        assert_eq!(12, recorded);
+        assert_eq!(10, stats.transaction_ids);
+    }
 }
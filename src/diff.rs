@@ -0,0 +1,298 @@
+use crate::{
+    amount::Amount,
+    client::{ClientAccount, ClientId},
+    currency::Currency,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+// Comparing the reports of two runs shows what changed between engine versions or revisions of
+// an input. Either side may be a report or a snapshot, which are both read into the balances of
+// each client. A client's other currencies are reported on the rows after its first, so those
+// rows are matched by currency and the first by client alone.
+
+/// The columns a report needs to be compared.
+const REQUIRED_COLUMNS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+#[derive(Debug)]
+pub enum DiffError {
+    Csv(csv::Error),
+    MissingColumn {
+        name: &'static str,
+    },
+    InvalidValue {
+        column: &'static str,
+        passed: String,
+    },
+    /// More than one row was reported for the balance, such as a client's other currencies in a
+    /// report without a currency column.
+    DuplicateBalance {
+        client: ClientId,
+        currency: Option<Currency>,
+    },
+}
+
+impl From<csv::Error> for DiffError {
+    fn from(e: csv::Error) -> Self {
+        Self::Csv(e)
+    }
+}
+
+/// What a report says about one of a client's balances.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ReportedBalance {
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+/// Every reported balance, by client and the currency of the client's other balances.
+pub type ReportedBalances = BTreeMap<(ClientId, Option<Currency>), ReportedBalance>;
+
+/// How a client's balance differs between two reports. A side is `None` if the balance wasn't
+/// reported in it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BalanceDiff {
+    pub client: ClientId,
+    pub currency: Option<Currency>,
+    pub before: Option<ReportedBalance>,
+    pub after: Option<ReportedBalance>,
+}
+
+/// Reads the balances of a CSV report. Columns other than the standard ones are ignored, and a
+/// stamp comment row is skipped.
+pub fn read_report<R: Read>(reader: R) -> Result<ReportedBalances, DiffError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .from_reader(reader);
+
+    let headers = reader.headers()?.clone();
+    let mut positions = [0; REQUIRED_COLUMNS.len()];
+    for (position, name) in positions.iter_mut().zip(REQUIRED_COLUMNS) {
+        *position = headers
+            .iter()
+            .position(|header| header == name)
+            .ok_or(DiffError::MissingColumn { name })?;
+    }
+    let currency_position = headers.iter().position(|header| header == "currency");
+
+    let mut balances = ReportedBalances::new();
+    let mut seen = BTreeSet::new();
+    for record in reader.records() {
+        let record = record?;
+        let field = |column: usize| record.get(positions[column]).unwrap_or_default();
+        let client = parse(REQUIRED_COLUMNS[0], field(0))?;
+        let balance = ReportedBalance {
+            available: parse(REQUIRED_COLUMNS[1], field(1))?,
+            held: parse(REQUIRED_COLUMNS[2], field(2))?,
+            total: parse(REQUIRED_COLUMNS[3], field(3))?,
+            locked: parse(REQUIRED_COLUMNS[4], field(4))?,
+        };
+
+        let first = seen.insert(client);
+        let currency = match currency_position.and_then(|position| record.get(position)) {
+            Some(currency) if !first && !currency.is_empty() => Some(parse("currency", currency)?),
+            _ => None,
+        };
+        if balances.insert((client, currency), balance).is_some() {
+            return Err(DiffError::DuplicateBalance { client, currency });
+        }
+    }
+
+    Ok(balances)
+}
+
+/// The balances the accounts would be reported with.
+pub fn account_balances<'a, Account>(
+    accounts: impl Iterator<Item = &'a Account>,
+) -> ReportedBalances
+where
+    Account: ClientAccount + 'a,
+{
+    let reported = |account: &Account| ReportedBalance {
+        available: account.available(),
+        held: account.held(),
+        total: account.total(),
+        locked: account.locked(),
+    };
+
+    let mut balances = ReportedBalances::new();
+    for account in accounts {
+        balances.insert((account.id(), None), reported(account));
+        for other in account.other_currencies() {
+            balances.insert((other.id(), other.currency()), reported(&other));
+        }
+    }
+    balances
+}
+
+/// Every balance that differs between the reports, in client order.
+pub fn diff(before: &ReportedBalances, after: &ReportedBalances) -> Vec<BalanceDiff> {
+    let keys: BTreeSet<_> = before.keys().chain(after.keys()).collect();
+    keys.into_iter()
+        .map(|key| BalanceDiff {
+            client: key.0,
+            currency: key.1,
+            before: before.get(key).copied(),
+            after: after.get(key).copied(),
+        })
+        .filter(|diff| diff.before != diff.after)
+        .collect()
+}
+
+/// Writes a row per differing balance with the change in each amount. A balance only reported
+/// on one side is compared against an empty, unlocked one.
+pub fn write_diff(diffs: &[BalanceDiff], writer: &mut dyn Write) -> io::Result<()> {
+    writeln!(
+        writer,
+        "client, currency, change, available, held, total, locked"
+    )?;
+
+    let empty = ReportedBalance {
+        available: Amount::zero(),
+        held: Amount::zero(),
+        total: Amount::zero(),
+        locked: false,
+    };
+    for diff in diffs {
+        let change = match (diff.before, diff.after) {
+            (None, _) => "added",
+            (_, None) => "removed",
+            _ => "changed",
+        };
+        let before = diff.before.unwrap_or(empty);
+        let after = diff.after.unwrap_or(empty);
+        let locked = match before.locked == after.locked {
+            true => after.locked.to_string(),
+            false => format!("{} -> {}", before.locked, after.locked),
+        };
+
+        writeln!(
+            writer,
+            "{}, {}, {}, {}, {}, {}, {}",
+            diff.client,
+            diff.currency
+                .map(|currency| currency.to_string())
+                .unwrap_or_default(),
+            change,
+            after.available - before.available,
+            after.held - before.held,
+            after.total - before.total,
+            locked
+        )?;
+    }
+
+    writer.flush()
+}
+
+fn parse<T: FromStr>(column: &'static str, value: &str) -> Result<T, DiffError> {
+    value.parse().map_err(|_| DiffError::InvalidValue {
+        column,
+        passed: String::from(value),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{amount, places, transaction};
+    use crate::{client::Client, transaction::TransactionType};
+
+    fn balance(available: &str, held: &str, locked: bool) -> ReportedBalance {
+        ReportedBalance {
+            available: amount(available),
+            held: amount(held),
+            total: amount(available) + amount(held),
+            locked,
+        }
+    }
+
+    #[test]
+    fn read_report_returns_expected() {
+        let report = "# engine_version=0.1.0 config_hash=0\n\
+                      client, available, held, total, locked, currency\n\
+                      1, 1.5000, 0.0000, 1.5000, false, USD\n\
+                      1, 2.0000, 0.0000, 2.0000, false, EUR\n\
+                      2, 0.0000, 1.0000, 1.0000, true, \n";
+
+        let actual = read_report(report.as_bytes()).unwrap();
+
+        let mut expected = ReportedBalances::new();
+        expected.insert((1, None), balance("1.5", "0", false));
+        expected.insert((1, "EUR".parse().ok()), balance("2", "0", false));
+        expected.insert((2, None), balance("0", "1", true));
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn read_report_missing_column_returns_err() {
+        let actual = read_report("client, available, held, total\n".as_bytes());
+
+        assert_eq!(
+            true,
+            matches!(actual, Err(DiffError::MissingColumn { name: "locked" }))
+        );
+    }
+
+    #[test]
+    fn read_report_duplicate_balance_returns_err() {
+        let report = "client, available, held, total, locked\n\
+                      1, 1.5000, 0.0000, 1.5000, false\n\
+                      1, 2.0000, 0.0000, 2.0000, false\n";
+
+        let actual = read_report(report.as_bytes());
+
+        assert_eq!(
+            true,
+            matches!(
+                actual,
+                Err(DiffError::DuplicateBalance {
+                    client: 1,
+                    currency: None
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn account_balances_match_report() {
+        let mut client = Client::new(1);
+        client
+            .execute_transaction(transaction(1, 1, TransactionType::Deposit(amount("1.5"))))
+            .unwrap();
+
+        let report = "client, available, held, total, locked\n1, 1.5000, 0.0000, 1.5000, false\n";
+        let expected = read_report(report.as_bytes()).unwrap();
+
+        assert_eq!(expected, account_balances([client].iter()));
+    }
+
+    #[test]
+    fn write_diff_writes_changed_balances() {
+        let mut before = ReportedBalances::new();
+        before.insert((1, None), balance("1", "0", false));
+        before.insert((2, None), balance("1", "0", false));
+        before.insert((3, None), balance("0.5", "0", false));
+        let mut after = ReportedBalances::new();
+        after.insert((1, None), balance("1", "0", false));
+        after.insert((2, None), balance("0", "1", true));
+        after.insert((4, None), balance("0.5", "0", false));
+
+        let diffs = diff(&before, &after);
+        let mut actual = vec![];
+        write_diff(&diffs, &mut actual).unwrap();
+
+        assert_eq!(
+            places(
+                "client, currency, change, available, held, total, locked\n\
+                 2, , changed, -1.0000, 1.0000, 0.0000, false -> true\n\
+                 3, , removed, -0.5000, 0.0000, -0.5000, false\n\
+                 4, , added, 0.5000, 0.0000, 0.5000, false\n"
+            ),
+            String::from_utf8(actual).unwrap()
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{
+    client::{Client, ClientAccount},
+    database::Database,
+    parse_csv::{CsvSource, TransactionKinds},
+    pipeline::InputFormat,
+    report::Report,
+    source::{self, SourceError, TransactionSource},
+    transaction::{Transaction, TransactionError},
+};
+use std::io::Read;
+
+// The engine is the simplest way to use the library: feed it transactions and ask for the report.
+// Embedders that need approvals, observers or custom sinks should use the pipeline instead.
+
+/// Applies transactions to an in-memory database of accounts.
+pub struct Engine<Account = Client>
+where
+    Account: ClientAccount,
+{
+    database: Database<Account>,
+}
+
+impl Engine<Client> {
+    /// An engine without any accounts.
+    pub fn new() -> Self {
+        Self::from_database(Database::new())
+    }
+}
+
+impl Default for Engine<Client> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Account> Engine<Account>
+where
+    Account: ClientAccount,
+{
+    /// An engine that continues from an existing database.
+    pub fn from_database(database: Database<Account>) -> Self {
+        Self { database }
+    }
+
+    /// Applies a single transaction.
+    pub fn apply(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        self.database.apply_transaction(transaction)
+    }
+
+    /// Applies every transaction in a CSV file with headers. Ignored records and transactions
+    /// rejected by an account are skipped, as they are on the command line, while malformed input
+    /// stops processing.
+    pub fn process_reader<R: Read>(&mut self, reader: R) -> Result<(), SourceError> {
+        self.process_source(&mut CsvSource::new(reader))
+    }
+
+    /// Applies every transaction in the file at the path, read in the given format and
+    /// decompressed if it's compressed. Records are skipped as they are by `process_reader`.
+    pub fn process_file(&mut self, path: &str, format: InputFormat) -> Result<(), SourceError> {
+        let mut source = format.open(path, false, &TransactionKinds::default())?;
+        self.process_source(source.as_mut())
+    }
+
+    /// Applies every transaction of the source. Records are skipped as they are by
+    /// `process_reader`.
+    pub fn process_source(
+        &mut self,
+        source: &mut dyn TransactionSource,
+    ) -> Result<(), SourceError> {
+        for transaction in source::transactions(source) {
+            let _result = self.apply(transaction?);
+        }
+
+        Ok(())
+    }
+
+    /// The standard CSV report for every account that has been touched.
+    pub fn report(&self) -> String {
+        let mut output = vec![];
+        // Writing to a Vec can't fail.
+        let _result = self.database.write_report(&Report::standard(), &mut output);
+
+        String::from_utf8_lossy(&output).into_owned()
+    }
+
+    /// The accounts the engine is tracking.
+    pub fn database(&self) -> &Database<Account> {
+        &self.database
+    }
+
+    /// Stops the engine, returning its accounts.
+    pub fn into_database(self) -> Database<Account> {
+        self.database
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::places;
+    use crate::{allocations, amount::Amount, transaction::TransactionType};
+
+    #[test]
+    fn engine_process_reader_returns_expected_report() {
+        let mut engine = Engine::new();
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 2.0\nwithdrawal, 1, 2, 5.0\n";
+
+        engine.process_reader(input.as_bytes()).unwrap();
+
+        assert_eq!(
+            places("client, available, held, total, locked\n1, 2.0000, 0.0000, 2.0000, false\n"),
+            engine.report()
+        );
+    }
+
+    #[test]
+    fn engine_process_file_skips_ignored_records() {
+        let path = std::env::temp_dir().join("payments_engine_process_file.csv");
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            "type, client, tx, amount\ndeposit, 1, 1, 2.0\ndeposit, 1, 2,\nwithdrawal, 1, 3, 5.0\n",
+        )
+        .unwrap();
+        let mut engine = Engine::new();
+
+        engine
+            .process_file(path, InputFormat::from_path(path))
+            .unwrap();
+
+        assert_eq!(
+            places("client, available, held, total, locked\n1, 2.0000, 0.0000, 2.0000, false\n"),
+            engine.report()
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn engine_process_reader_steady_state_does_not_allocate_per_record() {
+        let input = |records: u32| {
+            let mut input = String::from("type, client, tx, amount\n");
+            for tx in 0..records {
+                let kind = if tx % 3 == 0 { "withdrawal" } else { "deposit" };
+                input.push_str(&format!("{}, {}, {}, {}.5\n", kind, tx % 10, tx, tx % 7));
+            }
+            input
+        };
+        let allocations = |input: &str| {
+            let mut engine = Engine::new();
+            allocations::counting(|| engine.process_reader(input.as_bytes()).unwrap()).1
+        };
+
+        let small = allocations(&input(10_000));
+        let large = allocations(&input(20_000));
+
+        // Only the growth of the histories and the id index may allocate.
+        assert_eq!(
+            true,
+            large - small < 100,
+            "{} more allocations",
+            large - small
+        );
+    }
+
+    #[test]
+    fn engine_apply_returns_result() {
+        let mut engine = Engine::new();
+        let withdrawal = Transaction {
+            transaction_type: TransactionType::Withdrawal(Amount::new(10000)),
+            client: 2,
+            id: 1,
+            timestamp: None,
+            currency: None,
+        };
+
+        assert_eq!(true, engine.apply(withdrawal).is_err());
+        assert_eq!(1, engine.database().accounts().count());
+    }
+
+    #[test]
+    fn engine_process_reader_malformed_input_returns_err() {
+        let mut engine = Engine::new();
+        let input = "type, client, tx, amount\ndeposit, one, 1, 2.0\n";
+
+        assert_eq!(true, engine.process_reader(input.as_bytes()).is_err());
+    }
+}
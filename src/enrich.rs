@@ -0,0 +1,125 @@
+use crate::{client::ClientId, currency::Currency, transaction::Transaction};
+use std::collections::HashMap;
+
+// Enrichers sit between parsing and application. They fill in what the input leaves out from
+// systems the engine doesn't know about, such as a client's currency from their profile, or
+// refuse records that shouldn't be applied at all. A refused transaction is reported like any
+// other rejection, as `TransactionError::Refused`.
+
+/// Transforms or refuses each transaction before it is applied.
+pub trait Enricher {
+    /// The transaction to apply in place of the one read, or why it is refused.
+    fn enrich(&mut self, transaction: Transaction) -> Result<Transaction, &'static str>;
+}
+
+impl<F> Enricher for F
+where
+    F: FnMut(Transaction) -> Result<Transaction, &'static str>,
+{
+    fn enrich(&mut self, transaction: Transaction) -> Result<Transaction, &'static str> {
+        self(transaction)
+    }
+}
+
+/// Runs the transaction through each enricher in turn, stopping at the first that refuses it.
+pub fn enrich(
+    enrichers: &mut [Box<dyn Enricher>],
+    transaction: Transaction,
+) -> Result<Transaction, &'static str> {
+    enrichers
+        .iter_mut()
+        .try_fold(transaction, |transaction, enricher| {
+            enricher.enrich(transaction)
+        })
+}
+
+/// Attaches the currency of each client's profile to transactions that don't carry one.
+pub struct ClientCurrencies {
+    currencies: HashMap<ClientId, Currency>,
+    /// Refuse transactions of clients without a profile, rather than passing them on unchanged.
+    required: bool,
+}
+
+impl ClientCurrencies {
+    pub fn new(currencies: HashMap<ClientId, Currency>) -> Self {
+        Self {
+            currencies,
+            required: false,
+        }
+    }
+
+    /// Refuses transactions of clients without a profile.
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+}
+
+impl Enricher for ClientCurrencies {
+    fn enrich(&mut self, transaction: Transaction) -> Result<Transaction, &'static str> {
+        match self.currencies.get(&transaction.client) {
+            Some(currency) => Ok(Transaction {
+                currency: transaction.currency.or(Some(*currency)),
+                ..transaction
+            }),
+            None if self.required => Err("client has no profile"),
+            None => Ok(transaction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::Amount, transaction::TransactionType};
+    use std::str::FromStr;
+
+    fn deposit(client: ClientId, currency: Option<Currency>) -> Transaction {
+        Transaction {
+            transaction_type: TransactionType::Deposit(Amount::new(1)),
+            client,
+            id: 1,
+            timestamp: None,
+            currency,
+        }
+    }
+
+    #[test]
+    fn client_currencies_attaches_missing_currency() {
+        let eur = Currency::from_str("EUR").unwrap();
+        let usd = Currency::from_str("USD").unwrap();
+        let mut enricher = ClientCurrencies::new(vec![(1, eur)].into_iter().collect());
+
+        assert_eq!(Ok(deposit(1, Some(eur))), enricher.enrich(deposit(1, None)));
+        assert_eq!(
+            Ok(deposit(1, Some(usd))),
+            enricher.enrich(deposit(1, Some(usd)))
+        );
+        assert_eq!(Ok(deposit(2, None)), enricher.enrich(deposit(2, None)));
+
+        let mut enricher = enricher.required();
+        assert_eq!(
+            Err("client has no profile"),
+            enricher.enrich(deposit(2, None))
+        );
+    }
+
+    #[test]
+    fn enrich_stops_at_first_refusal() {
+        let eur = Currency::from_str("EUR").unwrap();
+        let refuse_eur = move |transaction: Transaction| match transaction.currency {
+            Some(currency) if currency == eur => Err("no euros"),
+            _ => Ok(transaction),
+        };
+        let mut enrichers: Vec<Box<dyn Enricher>> = vec![
+            Box::new(ClientCurrencies::new(vec![(1, eur)].into_iter().collect())),
+            Box::new(refuse_eur),
+        ];
+
+        assert_eq!(Err("no euros"), enrich(&mut enrichers, deposit(1, None)));
+        assert_eq!(
+            Ok(deposit(2, None)),
+            enrich(&mut enrichers, deposit(2, None))
+        );
+    }
+}
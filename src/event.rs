@@ -0,0 +1,64 @@
+use crate::{amount::Amount, clock::Timestamp, currency::Currency, transaction::ReasonCode};
+
+// A client's balances aren't changed by transactions directly. Each transaction accepted becomes
+// an event recording what it changed, with everything it depended on already resolved, such as
+// the amount of the transaction a dispute refers to. Folding the events in order gives the
+// balances, so an account can be replayed from its events or viewed as it was part way through.
+
+/// A change to a client's account, recorded once the transaction making it has been accepted.
+#[derive(Copy, Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct AccountEvent {
+    /// The currency of the balance changed, if the transaction had one.
+    pub currency: Option<Currency>,
+    /// When the transaction happened upstream, if it was known.
+    pub timestamp: Option<Timestamp>,
+    pub change: Change,
+}
+
+/// What an accepted transaction changed.
+#[derive(Copy, Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum Change {
+    Deposited {
+        amount: Amount,
+    },
+    Withdrawn {
+        amount: Amount,
+    },
+    /// The funds of a transaction were held while it's disputed. Those of a deposit are taken
+    /// from the available balance.
+    Disputed {
+        amount: Amount,
+        deposit: bool,
+    },
+    Resolved {
+        amount: Amount,
+    },
+    /// The held funds of a disputed transaction were returned, locking the account.
+    ChargedBack {
+        amount: Amount,
+    },
+    Held {
+        amount: Amount,
+        reason: ReasonCode,
+    },
+    Released {
+        amount: Amount,
+        reason: ReasonCode,
+    },
+    TransferSent {
+        amount: Amount,
+    },
+    TransferReceived {
+        amount: Amount,
+    },
+    Unlocked,
+    /// Funds were taken from the balance and `converted` credited to the balance in `to`.
+    Converted {
+        amount: Amount,
+        to: Currency,
+        converted: Amount,
+    },
+    FeeCharged {
+        amount: Amount,
+    },
+}
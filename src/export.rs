@@ -0,0 +1,405 @@
+use crate::{
+    client::{Client, ClientAccount, ClientId, Tombstone},
+    currency::Currency,
+    parse_csv::CsvTransaction,
+    transaction::{CaseId, ReasonCode, TransactionId, TransactionState},
+};
+use std::io::{self, Write};
+
+// Everything held about a single client, bundled for data subject access requests. Amounts can be
+// redacted when the bundle is shared outside the company. Erasure is recorded here too, as an
+// audit event written when a client's history is removed.
+
+const REDACTED: &str = "[redacted]";
+
+/// Everything held about a client.
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct ClientExport {
+    pub client: ClientId,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+    pub lifetime_deposits: String,
+    pub lifetime_withdrawals: String,
+    pub lifetime_fees: String,
+    pub dispute_count: u32,
+    pub chargeback_count: u32,
+    pub risk_holds: Vec<RiskHoldExport>,
+    /// The currency the balances above are kept in.
+    pub currency: Option<Currency>,
+    /// The balances the client holds in other currencies.
+    pub balances: Vec<BalanceExport>,
+    pub transactions: Vec<TransactionExport>,
+    /// The transactions that have been disputed or charged back.
+    pub disputes: Vec<DisputeExport>,
+    /// Set if the client's transaction history has been erased.
+    pub tombstone: Option<Tombstone>,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct RiskHoldExport {
+    pub reason: ReasonCode,
+    pub amount: String,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct BalanceExport {
+    pub currency: Option<Currency>,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct TransactionExport {
+    pub tx: TransactionId,
+    #[serde(rename = "type")]
+    pub transaction_type: String,
+    pub amount: Option<String>,
+    pub reason: Option<ReasonCode>,
+    pub state: TransactionState,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct DisputeExport {
+    pub tx: TransactionId,
+    pub state: TransactionState,
+    pub case: Option<CaseId>,
+}
+
+impl ClientExport {
+    /// Bundles everything held about the client. With `redact_amounts` every amount is replaced,
+    /// leaving only the shape of the account's history.
+    pub fn new(client: &Client, redact_amounts: bool) -> Self {
+        let amount = |amount: String| {
+            if redact_amounts {
+                String::from(REDACTED)
+            } else {
+                amount
+            }
+        };
+
+        let snapshot = client.to_snapshot();
+
+        let transactions: Vec<TransactionExport> = snapshot
+            .transactions
+            .into_iter()
+            .map(|(state, record)| TransactionExport {
+                tx: record.tx,
+                transaction_type: record.transaction_type,
+                amount: record.amount.map(amount),
+                reason: record.reason,
+                state,
+            })
+            .collect();
+
+        let disputes = transactions
+            .iter()
+            .filter(|transaction| transaction.state != TransactionState::Ok)
+            .map(|transaction| DisputeExport {
+                tx: transaction.tx,
+                state: transaction.state,
+                case: client.case(transaction.tx),
+            })
+            .collect();
+
+        Self {
+            client: client.id(),
+            available: amount(client.available().to_string()),
+            held: amount(client.held().to_string()),
+            total: amount(client.total().to_string()),
+            locked: client.locked(),
+            lifetime_deposits: amount(snapshot.lifetime_deposits),
+            lifetime_withdrawals: amount(snapshot.lifetime_withdrawals),
+            lifetime_fees: amount(client.stats().fees.to_string()),
+            dispute_count: snapshot.dispute_count,
+            chargeback_count: snapshot.chargeback_count,
+            risk_holds: snapshot
+                .risk_holds
+                .into_iter()
+                .map(|(reason, held)| RiskHoldExport {
+                    reason,
+                    amount: amount(held),
+                })
+                .collect(),
+            currency: client.currency(),
+            balances: client
+                .other_currencies()
+                .iter()
+                .map(|other| BalanceExport {
+                    currency: other.currency(),
+                    available: amount(other.available().to_string()),
+                    held: amount(other.held().to_string()),
+                    total: amount(other.total().to_string()),
+                })
+                .collect(),
+            transactions,
+            disputes,
+            tombstone: client.tombstone(),
+        }
+    }
+
+    /// Writes the bundle as pretty printed JSON.
+    pub fn write_json(&self, writer: &mut dyn Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut *writer, self)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+}
+
+/// A transaction still under dispute, as listed in the pending-dispute report.
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct PendingDispute {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: String,
+    /// The dispute management case the dispute was raised under.
+    pub case: Option<CaseId>,
+}
+
+/// Every transaction still under dispute, by client and then in the order they were applied.
+pub fn pending_disputes<'a>(clients: impl Iterator<Item = &'a Client>) -> Vec<PendingDispute> {
+    let mut clients: Vec<&Client> = clients.collect();
+    clients.sort_unstable_by_key(|client| client.id());
+
+    clients
+        .into_iter()
+        .flat_map(|client| {
+            client
+                .pending_disputes()
+                .map(move |(transaction, case)| PendingDispute {
+                    client: client.id(),
+                    tx: transaction.id,
+                    amount: transaction.amount().unwrap_or_default().to_string(),
+                    case,
+                })
+        })
+        .collect()
+}
+
+/// Writes the pending-dispute report as CSV with a header row.
+pub fn write_pending_disputes(
+    disputes: &[PendingDispute],
+    writer: &mut dyn Write,
+) -> Result<(), csv::Error> {
+    // The header is written even when nothing is under dispute.
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer);
+    wtr.write_record(["client", "tx", "amount", "case"])?;
+    for dispute in disputes {
+        wtr.serialize(dispute)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// A transaction in a client's history, with the state it's in now.
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct HistoryEntry {
+    pub tx: TransactionId,
+    #[serde(rename = "type")]
+    pub transaction_type: String,
+    pub amount: Option<String>,
+    pub currency: Option<Currency>,
+    pub state: TransactionState,
+}
+
+/// The client's history in the order it was applied. Transactions erased or spilled from the
+/// history aren't included.
+pub fn history(client: &Client) -> Vec<HistoryEntry> {
+    client
+        .history()
+        .iter()
+        .map(|(state, transaction)| {
+            let record = CsvTransaction::from_transaction(transaction);
+            HistoryEntry {
+                tx: record.tx,
+                transaction_type: record.transaction_type,
+                amount: record.amount,
+                currency: record.currency,
+                state: *state,
+            }
+        })
+        .collect()
+}
+
+/// Writes a client's history as CSV with a header row.
+pub fn write_history(history: &[HistoryEntry], writer: &mut dyn Write) -> Result<(), csv::Error> {
+    // The header is written even when the history is empty.
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer);
+    wtr.write_record(["tx", "type", "amount", "currency", "state"])?;
+    for entry in history {
+        wtr.serialize(entry)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// The audit event recorded when a client's history is erased.
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct TombstoneEvent {
+    pub event: &'static str,
+    pub client: ClientId,
+    pub erased_transactions: u64,
+}
+
+impl TombstoneEvent {
+    pub fn new(client: ClientId, tombstone: Tombstone) -> Self {
+        Self {
+            event: "tombstone",
+            client,
+            erased_transactions: tombstone.erased_transactions,
+        }
+    }
+
+    /// Writes the event as a single JSON line.
+    pub fn write_json_line(&self, writer: &mut dyn Write) -> io::Result<()> {
+        serde_json::to_writer(&mut *writer, self)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{amount, places};
+    use crate::transaction::{Transaction, TransactionType};
+
+    fn client() -> Client {
+        let mut client = Client::new(42);
+        for transaction_type in [
+            TransactionType::Deposit(amount("2")),
+            TransactionType::Dispute { case: Some(77) },
+        ] {
+            client
+                .execute_transaction(Transaction {
+                    transaction_type,
+                    client: 42,
+                    id: 1,
+                    timestamp: None,
+                    currency: None,
+                })
+                .unwrap();
+        }
+        client
+    }
+
+    #[test]
+    fn client_export_new_returns_history_and_disputes() {
+        let export = ClientExport::new(&client(), false);
+
+        assert_eq!(places("2.0000"), export.held);
+        assert_eq!(2, export.transactions.len());
+        assert_eq!(Some(places("2.0000")), export.transactions[0].amount);
+        assert_eq!(
+            vec![DisputeExport {
+                tx: 1,
+                state: TransactionState::Disputed,
+                case: Some(77),
+            }],
+            export.disputes
+        );
+    }
+
+    #[test]
+    fn client_export_new_redacts_amounts() {
+        let export = ClientExport::new(&client(), true);
+
+        assert_eq!(REDACTED, export.available);
+        assert_eq!(REDACTED, export.held);
+        assert_eq!(REDACTED, export.lifetime_deposits);
+        assert_eq!(REDACTED, export.lifetime_fees);
+        assert_eq!(Some(String::from(REDACTED)), export.transactions[0].amount);
+        assert_eq!(None, export.transactions[1].amount);
+        assert_eq!(1, export.dispute_count);
+    }
+
+    #[test]
+    fn write_pending_disputes_returns_expected() {
+        let disputed = client();
+        let mut resolved = Client::new(7);
+        for transaction_type in [
+            TransactionType::Deposit(amount("1")),
+            TransactionType::Dispute { case: Some(5) },
+            TransactionType::Resolve,
+        ] {
+            resolved
+                .execute_transaction(Transaction {
+                    transaction_type,
+                    client: 7,
+                    id: 3,
+                    timestamp: None,
+                    currency: None,
+                })
+                .unwrap();
+        }
+
+        let disputes = pending_disputes([resolved, disputed].iter());
+        let mut output = vec![];
+        write_pending_disputes(&disputes, &mut output).unwrap();
+
+        assert_eq!(
+            places("client,tx,amount,case\n42,1,2.0000,77\n"),
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn write_history_returns_expected() {
+        let mut client = client();
+        client
+            .execute_transaction(Transaction {
+                transaction_type: TransactionType::Withdrawal(amount("0.5")),
+                client: 42,
+                id: 2,
+                timestamp: None,
+                currency: None,
+            })
+            .unwrap_err();
+        client
+            .execute_transaction(Transaction {
+                transaction_type: TransactionType::Deposit(amount("0.5")),
+                client: 42,
+                id: 3,
+                timestamp: None,
+                currency: None,
+            })
+            .unwrap();
+
+        let mut output = vec![];
+        write_history(&history(&client), &mut output).unwrap();
+
+        assert_eq!(
+            places(
+                "tx,type,amount,currency,state\n\
+             1,deposit,2.0000,,Disputed\n\
+             1,dispute,,,Ok\n\
+             3,deposit,0.5000,,Ok\n"
+            ),
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn tombstone_event_write_json_line_returns_expected() {
+        let mut output = vec![];
+        let event = TombstoneEvent::new(
+            42,
+            Tombstone {
+                erased_transactions: 2,
+            },
+        );
+
+        event.write_json_line(&mut output).unwrap();
+
+        assert_eq!(
+            "{\"event\":\"tombstone\",\"client\":42,\"erased_transactions\":2}\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
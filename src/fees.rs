@@ -0,0 +1,232 @@
+use crate::{
+    amount::{Amount, RoundingMode},
+    transaction::TransactionType,
+};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+// A fee schedule charges deposits and withdrawals a flat amount or a percentage of the amount, as
+// `type, kind, value` rows such as `withdrawal, flat, 0.50` or `deposit, percent, 1.5`. Fees are
+// charged by the database once the transaction is applied, as a separate fee entry in the
+// client's history with the same id, so they're logged and replayed like any other transaction.
+
+#[derive(Debug)]
+pub enum FeesError {
+    Csv(csv::Error),
+    /// Fees can only be charged on deposits and withdrawals.
+    UnsupportedType {
+        passed: String,
+    },
+    /// The kind isn't `flat` or `percent`, or the value isn't a non-negative decimal number.
+    InvalidFee {
+        kind: String,
+        value: String,
+    },
+    /// The file lists the type more than once.
+    DuplicateType {
+        passed: String,
+    },
+}
+
+impl From<csv::Error> for FeesError {
+    fn from(e: csv::Error) -> Self {
+        Self::Csv(e)
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct FeeRecord {
+    #[serde(rename = "type")]
+    transaction_type: String,
+    kind: String,
+    value: String,
+}
+
+/// What a transaction type is charged.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Fee {
+    Flat(Amount),
+    /// A percentage of the transaction's amount.
+    Percent(Decimal),
+}
+
+impl Fee {
+    fn parse(kind: &str, value: &str) -> Option<Self> {
+        let fee = match kind {
+            "flat" => Self::Flat(Amount::from_str(value).ok()?),
+            "percent" => Self::Percent(Decimal::from_str(value).ok()?),
+            _ => return None,
+        };
+
+        match fee {
+            Self::Flat(amount) if amount.less_than_zero() => None,
+            Self::Percent(percent) if percent < Decimal::ZERO => None,
+            _ => Some(fee),
+        }
+    }
+
+    /// The fee on the amount, rounding percentages with the rounding mode.
+    pub fn on(&self, amount: Amount, mode: RoundingMode) -> Amount {
+        match self {
+            Self::Flat(fee) => *fee,
+            Self::Percent(percent) => amount.scale(*percent / Decimal::ONE_HUNDRED, mode),
+        }
+    }
+}
+
+/// The fees charged on deposits and withdrawals.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FeeSchedule {
+    pub deposit: Option<Fee>,
+    pub withdrawal: Option<Fee>,
+}
+
+impl FeeSchedule {
+    /// Reads a CSV fee schedule with `type, kind, value` columns.
+    pub fn load(path: &str) -> Result<Self, FeesError> {
+        let rdr = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        Self::read(rdr)
+    }
+
+    fn read<R: std::io::Read>(mut rdr: csv::Reader<R>) -> Result<Self, FeesError> {
+        let mut schedule = Self::default();
+        for result in rdr.deserialize() {
+            let record: FeeRecord = result?;
+            let fee = match Fee::parse(&record.kind, &record.value) {
+                Some(fee) => fee,
+                None => {
+                    return Err(FeesError::InvalidFee {
+                        kind: record.kind,
+                        value: record.value,
+                    })
+                }
+            };
+
+            let slot = match record.transaction_type.as_str() {
+                "deposit" => &mut schedule.deposit,
+                "withdrawal" => &mut schedule.withdrawal,
+                _ => {
+                    return Err(FeesError::UnsupportedType {
+                        passed: record.transaction_type,
+                    })
+                }
+            };
+            if slot.replace(fee).is_some() {
+                return Err(FeesError::DuplicateType {
+                    passed: record.transaction_type,
+                });
+            }
+        }
+
+        Ok(schedule)
+    }
+
+    /// The fee charged on the transaction, if it's charged one greater than zero.
+    pub fn fee(&self, transaction_type: &TransactionType, mode: RoundingMode) -> Option<Amount> {
+        let (fee, amount) = match transaction_type {
+            TransactionType::Deposit(amount) => (self.deposit?, *amount),
+            TransactionType::Withdrawal(amount) => (self.withdrawal?, *amount),
+            _ => return None,
+        };
+
+        let fee = fee.on(amount, mode);
+        if fee.less_than_zero() || fee == Amount::zero() {
+            None
+        } else {
+            Some(fee)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::amount;
+
+    fn read(input: &str) -> Result<FeeSchedule, FeesError> {
+        FeeSchedule::read(
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(input.as_bytes()),
+        )
+    }
+
+    #[test]
+    fn fee_schedule_read_returns_expected() {
+        let schedule =
+            read("type, kind, value\nwithdrawal, flat, 0.50\ndeposit, percent, 1.5\n").unwrap();
+
+        assert_eq!(Some(Fee::Flat(amount("0.5"))), schedule.withdrawal);
+        assert_eq!(Some(Fee::Percent(Decimal::new(15, 1))), schedule.deposit);
+    }
+
+    #[test]
+    fn fee_schedule_read_invalid_rows_return_err() {
+        assert_eq!(
+            true,
+            matches!(
+                read("type, kind, value\ndeposit, flat, -1\n"),
+                Err(FeesError::InvalidFee { .. })
+            )
+        );
+        assert_eq!(
+            true,
+            matches!(
+                read("type, kind, value\ndeposit, tiered, 1\n"),
+                Err(FeesError::InvalidFee { .. })
+            )
+        );
+        assert_eq!(
+            true,
+            matches!(
+                read("type, kind, value\ndispute, flat, 1\n"),
+                Err(FeesError::UnsupportedType { .. })
+            )
+        );
+        assert_eq!(
+            true,
+            matches!(
+                read("type, kind, value\ndeposit, flat, 1\ndeposit, percent, 1\n"),
+                Err(FeesError::DuplicateType { .. })
+            )
+        );
+    }
+
+    #[test]
+    fn fee_schedule_fee_returns_expected() {
+        let schedule = FeeSchedule {
+            deposit: Some(Fee::Percent(Decimal::new(15, 1))),
+            withdrawal: Some(Fee::Flat(Amount::new(5000))),
+        };
+
+        // 1.5% of 1.0001 is 0.0150015.
+        assert_eq!(
+            Some(Amount::new(150)),
+            schedule.fee(
+                &TransactionType::Deposit(Amount::new(10001)),
+                RoundingMode::HalfUp
+            )
+        );
+        assert_eq!(
+            Some(Amount::new(5000)),
+            schedule.fee(
+                &TransactionType::Withdrawal(Amount::new(10)),
+                RoundingMode::HalfUp
+            )
+        );
+        assert_eq!(
+            None,
+            schedule.fee(
+                &TransactionType::Deposit(Amount::new(1)),
+                RoundingMode::HalfUp
+            )
+        );
+        assert_eq!(
+            None,
+            schedule.fee(&TransactionType::Resolve, RoundingMode::HalfUp)
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use std::str::FromStr;
+
+use crate::amount::Amount;
+use crate::client::ClientId;
+use crate::transaction::{Transaction, TransactionId, TransactionType};
+
+// Helpers for the tests of every module. The transactions have no timestamp or currency, which a
+// test sets with struct update syntax when it needs one, e.g.
+// `Transaction { currency, ..deposit(..) }`.
+//
+// Expected output is written with amounts to 4 decimal places, and `places` rewrites it to the
+// `DECIMAL_PLACES` the engine is built with.
+
+/// The text with every amount written to 4 decimal places, e.g. `1.5000`, written the way the
+/// engine's amounts are instead.
+pub fn places(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut rewritten = String::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() {
+            i += 1;
+            continue;
+        }
+
+        let mut end = i;
+        while end < bytes.len() && bytes[end].is_ascii_digit() {
+            end += 1;
+        }
+        if end < bytes.len() && bytes[end] == b'.' {
+            let fraction = end + 1;
+            end = fraction;
+            while end < bytes.len() && bytes[end].is_ascii_digit() {
+                end += 1;
+            }
+            if end - fraction == 4 {
+                rewritten.push_str(&text[start..i]);
+                rewritten.push_str(&amount(&text[i..end]).to_string());
+                start = end;
+            }
+        }
+        i = end;
+    }
+    rewritten.push_str(&text[start..]);
+    rewritten
+}
+
+/// An amount read from text like `1.5`.
+pub fn amount(text: &str) -> Amount {
+    Amount::from_str(text).unwrap()
+}
+
+/// A transaction of the type.
+pub fn transaction(
+    client: ClientId,
+    id: TransactionId,
+    transaction_type: TransactionType,
+) -> Transaction {
+    Transaction {
+        transaction_type,
+        client,
+        id,
+        timestamp: None,
+        currency: None,
+    }
+}
+
+/// A deposit of the amount, as a count of its smallest unit like `Amount::new`.
+pub fn deposit(client: ClientId, id: TransactionId, amount: i64) -> Transaction {
+    transaction(client, id, TransactionType::Deposit(Amount::new(amount)))
+}
+
+/// A withdrawal of the amount, as a count of its smallest unit like `Amount::new`.
+pub fn withdrawal(client: ClientId, id: TransactionId, amount: i64) -> Transaction {
+    transaction(client, id, TransactionType::Withdrawal(Amount::new(amount)))
+}
+
+/// A dispute of the transaction with the id, outside of any case.
+pub fn dispute(client: ClientId, id: TransactionId) -> Transaction {
+    transaction(client, id, TransactionType::Dispute { case: None })
+}
+
+/// A resolve of the disputed transaction with the id.
+pub fn resolve(client: ClientId, id: TransactionId) -> Transaction {
+    transaction(client, id, TransactionType::Resolve)
+}
+
+/// A chargeback of the disputed transaction with the id, outside of any case.
+pub fn chargeback(client: ClientId, id: TransactionId) -> Transaction {
+    transaction(client, id, TransactionType::Chargeback { case: None })
+}
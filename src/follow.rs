@@ -0,0 +1,262 @@
+use crate::{
+    parse_csv::{self, TransactionKinds},
+    source::{InputPosition, SourceError, TransactionSource},
+    transaction::Transaction,
+};
+use std::io::{BufRead, BufReader, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Another process may keep appending to the file being processed. A followed file is read a line
+// at a time, and a line is only parsed once its newline has been written, so a record caught half
+// written is picked up whole on a later read. Reaching the end of the file doesn't end the run;
+// the source reports that it's waiting and the pipeline polls again until it's asked to stop.
+
+/// How long to wait before looking for new rows once the end of the file is reached.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How a followed run is told to write the report or to stop, e.g. from signal handlers.
+#[derive(Clone, Debug)]
+pub struct Follow {
+    pub poll_interval: Duration,
+    /// Set to have the report written once the transactions read so far are applied.
+    pub report: Arc<AtomicBool>,
+    /// Set to finish the run as if the end of the file had been reached.
+    pub stop: Arc<AtomicBool>,
+}
+
+impl Follow {
+    pub fn new() -> Self {
+        Self {
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            report: Arc::new(AtomicBool::new(false)),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    #[cfg(unix)]
+    /// Has SIGUSR1 write the report and SIGINT or SIGTERM finish the run. A second SIGINT or
+    /// SIGTERM exits straight away.
+    pub fn from_signals() -> Self {
+        use signal_hook::{consts, flag};
+
+        let follow = Self::new();
+        let registered = flag::register(consts::SIGUSR1, follow.report.clone()).and_then(|_| {
+            for signal in [consts::SIGINT, consts::SIGTERM] {
+                flag::register_conditional_shutdown(signal, 1, follow.stop.clone())?;
+                flag::register(signal, follow.stop.clone())?;
+            }
+            Ok(())
+        });
+        if let Err(e) = registered {
+            eprintln!("Failed to register signal handlers: {}", e);
+        }
+        follow
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_signals() -> Self {
+        Self::new()
+    }
+
+    /// Whether a report was asked for since the last call.
+    pub fn take_report_request(&self) -> bool {
+        self.report.swap(false, Ordering::SeqCst)
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+}
+
+impl Default for Follow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Streams transactions from a CSV file with a header row as rows are appended to it. Returns
+/// `SourceError::Waiting` whenever there's no complete row to read yet.
+pub struct FollowSource<R: Read> {
+    reader: BufReader<R>,
+    headers: Option<csv::StringRecord>,
+    /// The start of a row whose newline hasn't been written yet.
+    partial: Vec<u8>,
+    line: u64,
+    position: InputPosition,
+    strict: bool,
+    kinds: TransactionKinds,
+    delimiter: u8,
+}
+
+impl<R: Read> FollowSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            headers: None,
+            partial: vec![],
+            line: 0,
+            position: InputPosition::default(),
+            strict: false,
+            kinds: TransactionKinds::default(),
+            delimiter: parse_csv::DEFAULT_DELIMITER,
+        }
+    }
+
+    /// Reads from a reader that has already been moved past the header row to the given position
+    /// of its file, using the headers read from the start of the file.
+    pub fn resume(reader: R, headers: csv::StringRecord, position: InputPosition) -> Self {
+        Self {
+            headers: Some(headers),
+            line: position.line,
+            position,
+            ..Self::new(reader)
+        }
+    }
+
+    /// Returns records that would be ignored as errors instead of skipping them.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// How records of a type the engine doesn't know are handled.
+    pub fn kinds(mut self, kinds: TransactionKinds) -> Self {
+        self.kinds = kinds;
+        self
+    }
+
+    /// Separates fields with the delimiter rather than a comma.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Whether the first row is a header row, as it is by default. Without one, every row is a
+    /// record with the columns in the order of `parse_csv::positional_headers`.
+    pub fn has_headers(mut self, has_headers: bool) -> Self {
+        if !has_headers {
+            self.headers = Some(parse_csv::positional_headers());
+        }
+        self
+    }
+
+    /// Reads the next complete row, or `None` if its newline hasn't been written yet.
+    fn read_row(&mut self) -> Result<Option<Vec<u8>>, SourceError> {
+        self.reader.read_until(b'\n', &mut self.partial)?;
+        if self.partial.last() != Some(&b'\n') {
+            return Ok(None);
+        }
+
+        let row = std::mem::take(&mut self.partial);
+        self.position = InputPosition {
+            byte: self.position.byte + row.len() as u64,
+            line: self.position.line + 1,
+        };
+        Ok(Some(row))
+    }
+}
+
+impl<R: Read> TransactionSource for FollowSource<R> {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        loop {
+            let row = match self.read_row() {
+                Ok(Some(row)) => row,
+                Ok(None) => return Some(Err(SourceError::Waiting)),
+                Err(e) => return Some(Err(e)),
+            };
+
+            let headers = match &self.headers {
+                Some(headers) => headers,
+                None => match parse_csv::read_headers(row.as_slice(), self.delimiter) {
+                    Ok(headers) => {
+                        self.headers = Some(self.kinds.headers(&headers));
+                        continue;
+                    }
+                    Err(e) => return Some(Err(e)),
+                },
+            };
+
+            let mut rdr = parse_csv::reader_builder(false)
+                .delimiter(self.delimiter)
+                .from_reader(row.as_slice());
+            let mut record = csv::StringRecord::new();
+            match rdr.read_record(&mut record) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => return Some(Err(e.into())),
+            }
+            self.line = self.position.line;
+
+            let parsed = match record.deserialize(Some(headers)) {
+                Ok(record) => self.kinds.convert(record, self.line, self.strict),
+                Err(e) => Err(SourceError::InvalidRecord {
+                    line: self.line,
+                    error: Box::new(parse_csv::ParseCsvError::Csv(e)),
+                }),
+            };
+
+            return Some(parsed);
+        }
+    }
+
+    fn line(&self) -> Option<u64> {
+        Some(self.line)
+    }
+
+    fn position(&self) -> Option<InputPosition> {
+        Some(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Seek, SeekFrom, Write};
+    use std::sync::{Arc, Mutex};
+
+    /// A file another process appends to.
+    #[derive(Clone, Default)]
+    struct GrowingFile(Arc<Mutex<Cursor<Vec<u8>>>>);
+
+    impl GrowingFile {
+        fn append(&self, data: &str) {
+            let mut file = self.0.lock().unwrap();
+            let position = file.position();
+            file.seek(SeekFrom::End(0)).unwrap();
+            file.write_all(data.as_bytes()).unwrap();
+            file.set_position(position);
+        }
+    }
+
+    impl Read for GrowingFile {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().read(buf)
+        }
+    }
+
+    fn next(source: &mut FollowSource<GrowingFile>) -> String {
+        match source.next().unwrap() {
+            Ok(transaction) => format!("{:?}", transaction.id),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn follow_source_waits_for_complete_rows() {
+        let file = GrowingFile::default();
+        let mut source = FollowSource::new(file.clone());
+
+        file.append("type, client, tx, amount\ndeposit, 1, 1, 2.0\ndeposit, 1, 2");
+        assert_eq!("1", next(&mut source));
+        assert_eq!("waiting for more input", next(&mut source));
+
+        file.append(", 3.0\n\ndeposit, 1, 3, 1.0\n");
+        assert_eq!("2", next(&mut source));
+        assert_eq!(Some(3), source.line());
+        assert_eq!("3", next(&mut source));
+        assert_eq!("waiting for more input", next(&mut source));
+        assert_eq!(Some(InputPosition { byte: 83, line: 5 }), source.position());
+    }
+}
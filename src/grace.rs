@@ -0,0 +1,197 @@
+use crate::{
+    client::ClientId,
+    source::{InputPosition, SourceError, TransactionSource},
+    transaction::{Transaction, TransactionId, TransactionType},
+};
+use std::collections::{HashSet, VecDeque};
+
+// Some feeds deliver a resolve or chargeback a record or two before the dispute it settles, which
+// the account rejects as unprocessable. With a grace window, a resolve or chargeback of a
+// transaction read earlier in the input that hasn't been disputed yet is held back until its
+// dispute arrives, and returned straight after it. If the dispute doesn't arrive within the window
+// the record is returned anyway and rejected as before. Transactions from earlier runs aren't
+// tracked, so references to them are never held.
+
+/// A resolve or chargeback waiting for its dispute.
+struct Held {
+    transaction: Transaction,
+    line: Option<u64>,
+    /// How many records will have been read when it stops waiting.
+    until: u64,
+}
+
+/// Holds resolves and chargebacks that arrive before their dispute for up to `grace` records.
+pub struct GraceSource {
+    inner: Box<dyn TransactionSource>,
+    grace: u64,
+    read: u64,
+    /// Records ready to be returned, with their line.
+    ready: VecDeque<(Result<Transaction, SourceError>, Option<u64>)>,
+    held: VecDeque<Held>,
+    /// Transactions read so far that a dispute can refer to.
+    introduced: HashSet<(ClientId, TransactionId)>,
+    /// Transactions whose dispute has been read but not yet resolved or charged back.
+    disputed: HashSet<(ClientId, TransactionId)>,
+    line: Option<u64>,
+}
+
+impl GraceSource {
+    pub fn new(inner: Box<dyn TransactionSource>, grace: u64) -> Self {
+        Self {
+            inner,
+            grace,
+            read: 0,
+            ready: VecDeque::new(),
+            held: VecDeque::new(),
+            introduced: HashSet::new(),
+            disputed: HashSet::new(),
+            line: None,
+        }
+    }
+
+    /// Queues a transaction just read, holding it back if it settles a dispute not yet read.
+    fn accept(&mut self, transaction: Transaction, line: Option<u64>) {
+        let key = (transaction.client, transaction.id);
+        match transaction.transaction_type {
+            TransactionType::Dispute { .. } => {
+                self.ready.push_back((Ok(transaction), line));
+                self.disputed.insert(key);
+                self.release(|held| (held.client, held.id) == key);
+            }
+            TransactionType::Resolve | TransactionType::Chargeback { .. } => {
+                if self.introduced.contains(&key) && !self.disputed.contains(&key) {
+                    self.held.push_back(Held {
+                        transaction,
+                        line,
+                        until: self.read + self.grace,
+                    });
+                } else {
+                    self.disputed.remove(&key);
+                    self.ready.push_back((Ok(transaction), line));
+                }
+            }
+            _ => {
+                self.introduced.insert(key);
+                self.ready.push_back((Ok(transaction), line));
+            }
+        }
+    }
+
+    /// Moves the held records matching `releases` to the ready queue, keeping their order.
+    fn release<F>(&mut self, releases: F)
+    where
+        F: Fn(&Transaction) -> bool,
+    {
+        let (released, held): (VecDeque<Held>, VecDeque<Held>) = self
+            .held
+            .drain(..)
+            .partition(|held| releases(&held.transaction));
+        self.held = held;
+
+        for held in released {
+            self.disputed
+                .remove(&(held.transaction.client, held.transaction.id));
+            self.ready.push_back((Ok(held.transaction), held.line));
+        }
+    }
+}
+
+impl TransactionSource for GraceSource {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        loop {
+            if let Some((result, line)) = self.ready.pop_front() {
+                self.line = line;
+                return Some(result);
+            }
+
+            match self.inner.next() {
+                Some(Ok(transaction)) => {
+                    self.read += 1;
+
+                    // Records that have waited long enough go before the one just read.
+                    let read = self.read;
+                    while self.held.front().is_some_and(|held| held.until < read) {
+                        let held = self.held.pop_front().unwrap();
+                        self.ready.push_back((Ok(held.transaction), held.line));
+                    }
+
+                    self.accept(transaction, self.inner.line());
+                }
+                Some(Err(e)) => self.ready.push_back((Err(e), self.inner.line())),
+                None if self.held.is_empty() => return None,
+                None => self.release(|_| true),
+            }
+        }
+    }
+
+    fn line(&self) -> Option<u64> {
+        self.line
+    }
+
+    /// Held records would be lost if a run resumed after them, so there's no position to resume
+    /// from while any are held.
+    fn position(&self) -> Option<InputPosition> {
+        if self.held.is_empty() && self.ready.is_empty() {
+            self.inner.position()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::Amount, source::IterSource};
+
+    fn transaction(transaction_type: TransactionType, id: TransactionId) -> Transaction {
+        Transaction {
+            transaction_type,
+            client: 1,
+            id,
+            timestamp: None,
+            currency: None,
+        }
+    }
+
+    fn read_all(transactions: Vec<Transaction>, grace: u64) -> Vec<Transaction> {
+        let mut source = GraceSource::new(Box::new(IterSource::new(transactions)), grace);
+        let mut read = vec![];
+        while let Some(result) = source.next() {
+            read.push(result.unwrap());
+        }
+        read
+    }
+
+    #[test]
+    fn grace_source_returns_early_chargeback_after_its_dispute() {
+        let deposit = transaction(TransactionType::Deposit(Amount::new(10000)), 1);
+        let chargeback = transaction(TransactionType::Chargeback { case: None }, 1);
+        let other = transaction(TransactionType::Deposit(Amount::new(10000)), 2);
+        let dispute = transaction(TransactionType::Dispute { case: None }, 1);
+
+        let read = read_all(vec![deposit, chargeback, other, dispute], 2);
+
+        assert_eq!(vec![deposit, other, dispute, chargeback], read);
+    }
+
+    #[test]
+    fn grace_source_gives_up_once_window_passes() {
+        let deposit = transaction(TransactionType::Deposit(Amount::new(10000)), 1);
+        let resolve = transaction(TransactionType::Resolve, 1);
+        let others: Vec<Transaction> = (2..5)
+            .map(|id| transaction(TransactionType::Deposit(Amount::new(10000)), id))
+            .collect();
+        let dispute = transaction(TransactionType::Dispute { case: None }, 1);
+
+        let mut input = vec![deposit, resolve];
+        input.extend(others.iter().copied());
+        input.push(dispute);
+        let read = read_all(input, 1);
+
+        assert_eq!(
+            vec![deposit, others[0], resolve, others[1], others[2], dispute],
+            read
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use crate::{
+    client::{Client, ClientAccount, ClientId},
+    database::Database,
+    parse_csv::CsvTransaction,
+    protocol::ResultCode,
+    scheduler::FairScheduler,
+    transaction::Transaction,
+};
+use std::convert::TryFrom;
+use std::io;
+use std::sync::{Arc, RwLock};
+use tonic::{transport::Server, Request, Response, Status, Streaming};
+
+// The service is generated from proto/payments.proto. Transactions submitted over gRPC are queued
+// on the same scheduler as the TCP connections, so they take turns by client with every other
+// submitter and are applied to the same accounts.
+
+/// The messages and service generated from `proto/payments.proto`.
+pub mod proto {
+    tonic::include_proto!("payments");
+}
+
+use proto::payment_engine_server::{PaymentEngine, PaymentEngineServer};
+
+/// The payment engine served over gRPC.
+pub struct PaymentService {
+    scheduler: Arc<FairScheduler>,
+    database: Arc<RwLock<Database<Client>>>,
+}
+
+impl PaymentService {
+    pub fn new(scheduler: Arc<FairScheduler>, database: Arc<RwLock<Database<Client>>>) -> Self {
+        Self {
+            scheduler,
+            database,
+        }
+    }
+
+    /// Applies the transaction once it's its client's turn, without blocking the runtime.
+    async fn process(&self, request: proto::TransactionRequest) -> Result<ResultCode, Status> {
+        let transaction = match parse_request(request) {
+            Some(transaction) => transaction,
+            None => return Ok(ResultCode::Malformed),
+        };
+
+        let scheduler = self.scheduler.clone();
+        tokio::task::spawn_blocking(move || scheduler.process(transaction))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .ok_or_else(|| Status::unavailable("transactions are no longer being applied"))
+    }
+}
+
+/// Converts a request into a transaction, the same way a CSV record is. Returns `None` if it's
+/// malformed.
+fn parse_request(request: proto::TransactionRequest) -> Option<Transaction> {
+    let reason = match request.reason {
+        Some(reason) => Some(u16::try_from(reason).ok()?),
+        None => None,
+    };
+    let to = match request.to {
+        Some(to) => Some(ClientId::try_from(to).ok()?),
+        None => None,
+    };
+    let record = CsvTransaction {
+        transaction_type: request.r#type,
+        client: request.client.to_string(),
+        tx: request.tx,
+        amount: request.amount,
+        reason,
+        to,
+        case: request.case,
+        timestamp: None,
+        currency: None,
+        to_currency: None,
+        converted: None,
+    };
+
+    record.parse().ok()
+}
+
+/// Adds the outcome of a streamed transaction to the summary.
+fn count(summary: &mut proto::StreamSummary, code: ResultCode) {
+    summary.received += 1;
+    match code {
+        ResultCode::Applied => summary.applied += 1,
+        ResultCode::Ignored => summary.ignored += 1,
+        ResultCode::Malformed => summary.malformed += 1,
+        _ => summary.rejected += 1,
+    }
+}
+
+#[tonic::async_trait]
+impl PaymentEngine for PaymentService {
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::TransactionRequest>,
+    ) -> Result<Response<proto::SubmitReply>, Status> {
+        let code = self.process(request.into_inner()).await?;
+
+        Ok(Response::new(proto::SubmitReply {
+            result: format!("{:?}", code),
+            code: code as u32,
+        }))
+    }
+
+    async fn stream_transactions(
+        &self,
+        request: Request<Streaming<proto::TransactionRequest>>,
+    ) -> Result<Response<proto::StreamSummary>, Status> {
+        let mut stream = request.into_inner();
+        let mut summary = proto::StreamSummary::default();
+
+        while let Some(request) = stream.message().await? {
+            count(&mut summary, self.process(request).await?);
+        }
+
+        Ok(Response::new(summary))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::AccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let id = request.into_inner().client;
+        let not_found = || Status::not_found(format!("client {} not found", id));
+
+        let client = ClientId::try_from(id).map_err(|_| not_found())?;
+        let database = self.database.read().unwrap();
+        let account = database.account(client).ok_or_else(not_found)?;
+
+        Ok(Response::new(proto::Account {
+            client: u32::from(account.id()),
+            available: format!("{:?}", account.available()),
+            held: format!("{:?}", account.held()),
+            total: format!("{:?}", account.total()),
+            locked: account.locked(),
+        }))
+    }
+}
+
+/// Serves the engine over gRPC on the given address until the server fails.
+pub fn serve(
+    addr: &str,
+    scheduler: Arc<FairScheduler>,
+    database: Arc<RwLock<Database<Client>>>,
+) -> io::Result<()> {
+    let addr = addr
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let service = PaymentService::new(scheduler, database);
+
+    tokio::runtime::Runtime::new()?.block_on(async {
+        Server::builder()
+            .add_service(PaymentEngineServer::new(service))
+            .serve(addr)
+            .await
+            .map_err(io::Error::other)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn request(transaction_type: &str, tx: u32, amount: Option<&str>) -> proto::TransactionRequest {
+        proto::TransactionRequest {
+            r#type: String::from(transaction_type),
+            client: 1,
+            tx,
+            amount: amount.map(String::from),
+            reason: None,
+            to: None,
+            case: None,
+        }
+    }
+
+    #[test]
+    fn payment_service_applies_submitted_and_streamed_transactions() {
+        let database = Arc::new(RwLock::new(Database::new()));
+        let scheduler = Arc::new(FairScheduler::new(8));
+        {
+            let database = database.clone();
+            let scheduler = scheduler.clone();
+            thread::spawn(move || scheduler.run_shared(&database, vec![], |_| {}));
+        }
+        let service = PaymentService::new(scheduler, database);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (reply, summary, account) = runtime.block_on(async {
+            let reply = service
+                .submit_transaction(Request::new(request("deposit", 1, Some("5.0"))))
+                .await
+                .unwrap()
+                .into_inner();
+
+            let mut summary = proto::StreamSummary::default();
+            for request in [
+                request("withdrawal", 2, Some("2.0")),
+                request("withdrawal", 3, Some("9.0")),
+                request("deposit", 4, Some("x")),
+            ] {
+                count(&mut summary, service.process(request).await.unwrap());
+            }
+
+            let account = service
+                .get_account(Request::new(proto::AccountRequest { client: 1 }))
+                .await
+                .unwrap()
+                .into_inner();
+            (reply, summary, account)
+        });
+
+        assert_eq!("Applied", reply.result);
+        assert_eq!(3, summary.received);
+        assert_eq!(1, summary.applied);
+        assert_eq!(1, summary.rejected);
+        assert_eq!(1, summary.malformed);
+        assert_eq!("3.0000", account.total);
+    }
+}
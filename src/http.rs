@@ -0,0 +1,631 @@
+use crate::{
+    amount::Amount,
+    client::{Client, ClientAccount, ClientId},
+    database::Database,
+    parse_csv::CsvTransaction,
+    telemetry::MemoryStats,
+    transaction::{TransactionId, TransactionState},
+};
+use std::cmp::{Ordering, Reverse};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+
+// A read-only HTTP view over a database, either restored from a snapshot or live next to the TCP
+// server. There is no route that changes state, so it is safe to hand to analysts.
+//
+//  GET /accounts                      every account, optionally `?sort=held&limit={n}`
+//  GET /accounts/{client}             a single account
+//  GET /accounts/{client}/transactions the transaction history of an account
+//  GET /transactions/{tx}             a transaction, looked up across every account
+//  GET /chargebacks                   charged back transactions, newest ids first, `?limit={n}`
+//  GET /stats                         totals and memory stats of the engine
+//  GET /dashboard                     a page showing the stats, top held accounts and chargebacks
+
+/// The dashboard page, which reads everything it shows from the routes above.
+const DASHBOARD: &str = include_str!("../dashboard/index.html");
+
+/// How many chargebacks are listed unless a limit is given.
+const DEFAULT_CHARGEBACKS_LIMIT: usize = 50;
+
+/// A response ready to be written to the connection.
+#[derive(Debug, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl HttpResponse {
+    fn json<T: serde::Serialize>(value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Self::ok(body),
+            Err(e) => Self::error(500, &e.to_string()),
+        }
+    }
+
+    fn ok(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    fn html(body: &str) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/html; charset=utf-8",
+            body: String::from(body),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: serde_json::json!({ "error": message }).to_string(),
+        }
+    }
+
+    fn reason(&self) -> &str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        }
+    }
+
+    /// Writes the response as HTTP/1.1, closing the connection afterwards.
+    pub fn write_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.status,
+            self.reason(),
+            self.content_type,
+            self.body.len()
+        )?;
+        if self.status == 405 {
+            write!(writer, "Allow: GET\r\n")?;
+        }
+        write!(writer, "\r\n{}", self.body)?;
+        writer.flush()
+    }
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+struct AccountView {
+    client: ClientId,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool,
+}
+
+impl AccountView {
+    fn new(account: &Client) -> Self {
+        Self {
+            client: account.id(),
+            available: account.available().to_string(),
+            held: account.held().to_string(),
+            total: account.total().to_string(),
+            locked: account.locked(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+struct TransactionView {
+    state: TransactionState,
+    #[serde(flatten)]
+    transaction: CsvTransaction,
+}
+
+/// The totals across every account, followed by the memory stats.
+#[derive(Debug, PartialEq, serde::Serialize)]
+struct StatsView {
+    locked_accounts: usize,
+    available: String,
+    held: String,
+    total: String,
+    #[serde(flatten)]
+    memory: MemoryStats,
+}
+
+impl StatsView {
+    fn new(database: &Database<Client>) -> Self {
+        let mut locked_accounts = 0;
+        let mut available = Amount::zero();
+        let mut held = Amount::zero();
+        let mut total = Amount::zero();
+        for account in database.accounts() {
+            locked_accounts += usize::from(account.locked());
+            available = available + account.available();
+            held = held + account.held();
+            total = total + account.total();
+        }
+
+        Self {
+            locked_accounts,
+            available: available.to_string(),
+            held: held.to_string(),
+            total: total.to_string(),
+            memory: database.memory_stats(),
+        }
+    }
+}
+
+fn transactions(account: &Client) -> Vec<TransactionView> {
+    account
+        .to_snapshot()
+        .transactions
+        .into_iter()
+        .map(|(state, transaction)| TransactionView { state, transaction })
+        .collect()
+}
+
+/// How many account responses a live query API keeps.
+pub const DEFAULT_ACCOUNT_CACHE_LEN: usize = 256;
+
+/// Rendered `GET /accounts/{client}` responses of the most recently read accounts. Dashboards
+/// poll a few accounts far more often than they change, so an entry is kept until its account is
+/// written to or it's the least recently read of a full cache.
+pub struct AccountCache {
+    capacity: usize,
+    entries: Mutex<CacheEntries>,
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    /// The body of each cached account and when it was last read.
+    bodies: HashMap<ClientId, (String, u64)>,
+    reads: u64,
+}
+
+impl AccountCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(CacheEntries::default()),
+        }
+    }
+
+    fn get(&self, client: ClientId) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.reads += 1;
+        let reads = entries.reads;
+
+        let (body, last_read) = entries.bodies.get_mut(&client)?;
+        *last_read = reads;
+        Some(body.clone())
+    }
+
+    fn insert(&self, client: ClientId, body: String) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.bodies.len() >= self.capacity && !entries.bodies.contains_key(&client) {
+            let oldest = entries
+                .bodies
+                .iter()
+                .min_by_key(|(_, (_, last_read))| *last_read)
+                .map(|(client, _)| *client);
+            if let Some(oldest) = oldest {
+                entries.bodies.remove(&oldest);
+            }
+        }
+
+        let reads = entries.reads;
+        entries.bodies.insert(client, (body, reads));
+    }
+
+    /// Drops the client's response, called whenever a transaction changes the account.
+    pub fn invalidate(&self, client: ClientId) {
+        self.entries.lock().unwrap().bodies.remove(&client);
+    }
+
+    /// How many responses are cached.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().bodies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn segments(path: &str) -> Vec<&str> {
+    path.split('?')
+        .next()
+        .unwrap_or_default()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+/// The value of a query string parameter.
+fn query<'a>(path: &'a str, name: &str) -> Option<&'a str> {
+    path.split_once('?')?
+        .1
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// The `limit` parameter, if one is given.
+fn limit(path: &str) -> Result<Option<usize>, HttpResponse> {
+    query(path, "limit")
+        .map(|limit| {
+            limit
+                .parse()
+                .map_err(|_| HttpResponse::error(400, "invalid limit"))
+        })
+        .transpose()
+}
+
+/// Answers a single request, serving single accounts from the cache when they're in it.
+///
+/// Responses are only cached while the caller holds the database, so it must also hold it while
+/// invalidating for a write, or a response rendered before the write could be cached after it.
+pub fn route_cached(
+    method: &str,
+    path: &str,
+    database: &Database<Client>,
+    cache: &AccountCache,
+) -> HttpResponse {
+    let client = match (method, segments(path).as_slice()) {
+        ("GET", ["accounts", client]) => client.parse::<ClientId>().ok(),
+        _ => None,
+    };
+    let client = match client {
+        Some(client) => client,
+        None => return route(method, path, database),
+    };
+
+    if let Some(body) = cache.get(client) {
+        return HttpResponse::ok(body);
+    }
+
+    let response = route(method, path, database);
+    if response.status == 200 {
+        cache.insert(client, response.body.clone());
+    }
+    response
+}
+
+/// Answers a single request against the database.
+pub fn route(method: &str, path: &str, database: &Database<Client>) -> HttpResponse {
+    if method != "GET" {
+        return HttpResponse::error(405, "the query API is read-only");
+    }
+
+    let limit = match limit(path) {
+        Ok(limit) => limit,
+        Err(response) => return response,
+    };
+
+    match segments(path).as_slice() {
+        ["accounts"] => {
+            let mut accounts: Vec<&Client> = database.accounts().collect();
+            match query(path, "sort") {
+                None => {}
+                Some("held") => accounts
+                    .sort_by(|a, b| b.held().partial_cmp(&a.held()).unwrap_or(Ordering::Equal)),
+                Some(_) => return HttpResponse::error(400, "unknown sort"),
+            }
+            accounts.truncate(limit.unwrap_or(usize::MAX));
+
+            let accounts: Vec<AccountView> = accounts.into_iter().map(AccountView::new).collect();
+            HttpResponse::json(&accounts)
+        }
+        ["accounts", client] => match find_account(client, database) {
+            Ok(account) => HttpResponse::json(&AccountView::new(account)),
+            Err(response) => response,
+        },
+        ["accounts", client, "transactions"] => match find_account(client, database) {
+            Ok(account) => HttpResponse::json(&transactions(account)),
+            Err(response) => response,
+        },
+        ["transactions", tx] => {
+            let tx = match tx.parse::<TransactionId>() {
+                Ok(tx) => tx,
+                Err(_) => return HttpResponse::error(400, "invalid transaction id"),
+            };
+
+            let found = database
+                .accounts()
+                .flat_map(transactions)
+                .find(|view| view.transaction.tx == tx);
+
+            match found {
+                Some(view) => HttpResponse::json(&view),
+                None => HttpResponse::error(404, "transaction not found"),
+            }
+        }
+        ["chargebacks"] => {
+            // Nothing records when a chargeback happened, so the newest are taken to be those of
+            // the highest transaction ids.
+            let mut chargebacks: Vec<TransactionView> = database
+                .accounts()
+                .flat_map(transactions)
+                .filter(|view| view.state == TransactionState::Chargebacked)
+                .collect();
+            chargebacks.sort_by_key(|view| Reverse(view.transaction.tx));
+            chargebacks.truncate(limit.unwrap_or(DEFAULT_CHARGEBACKS_LIMIT));
+            HttpResponse::json(&chargebacks)
+        }
+        ["stats"] => HttpResponse::json(&StatsView::new(database)),
+        ["dashboard"] => HttpResponse::html(DASHBOARD),
+        _ => HttpResponse::error(404, "unknown route"),
+    }
+}
+
+fn find_account<'a>(
+    client: &str,
+    database: &'a Database<Client>,
+) -> Result<&'a Client, HttpResponse> {
+    let client = client
+        .parse::<ClientId>()
+        .map_err(|_| HttpResponse::error(400, "invalid client id"))?;
+
+    database
+        .account(client)
+        .ok_or_else(|| HttpResponse::error(404, "account not found"))
+}
+
+/// Serves the query API on the given address. Each connection is served on its own thread.
+pub fn serve(addr: &str, database: Database<Client>) -> io::Result<()> {
+    let database = Arc::new(database);
+    serve_with(addr, move |method, path| route(method, path, &database))
+}
+
+/// Serves the query API over a database that is still being written to, caching single accounts.
+/// Whoever writes to the database must invalidate the accounts it changes while holding the lock.
+pub fn serve_live(
+    addr: &str,
+    database: Arc<RwLock<Database<Client>>>,
+    cache: Arc<AccountCache>,
+) -> io::Result<()> {
+    serve_with(addr, move |method, path| {
+        route_cached(method, path, &database.read().unwrap(), &cache)
+    })
+}
+
+fn serve_with<F>(addr: &str, respond: F) -> io::Result<()>
+where
+    F: Fn(&str, &str) -> HttpResponse + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(addr)?;
+    let respond = Arc::new(respond);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        let respond = respond.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle_stream(stream, respond.as_ref()) {
+                eprintln!("Connection closed with error: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn handle_stream(
+    stream: TcpStream,
+    respond: &dyn Fn(&str, &str) -> HttpResponse,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Headers are read and ignored, as no route depends on them.
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => respond(method, path),
+        _ => HttpResponse::error(400, "malformed request line"),
+    };
+
+    response.write_to(&mut writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{amount, places};
+    use crate::transaction::{Transaction, TransactionType};
+
+    fn database() -> Database<Client> {
+        let mut database = Database::new();
+        database
+            .apply_transaction(Transaction {
+                transaction_type: TransactionType::Deposit(amount("2")),
+                client: 3,
+                id: 8,
+                timestamp: None,
+                currency: None,
+            })
+            .unwrap();
+        database
+    }
+
+    #[test]
+    fn route_accounts_returns_every_account() {
+        let response = route("GET", "/accounts", &database());
+
+        assert_eq!(200, response.status);
+        assert_eq!(
+            places("[{\"client\":3,\"available\":\"2.0000\",\"held\":\"0.0000\",\"total\":\"2.0000\",\"locked\":false}]"),
+            response.body
+        );
+    }
+
+    #[test]
+    fn route_account_transactions_returns_history() {
+        let response = route("GET", "/accounts/3/transactions", &database());
+
+        assert_eq!(200, response.status);
+        assert_eq!(
+            places("[{\"state\":\"Ok\",\"type\":\"deposit\",\"client\":\"3\",\"tx\":8,\"amount\":\"2.0000\",\"reason\":null,\"to\":null,\"case\":null,\"timestamp\":null,\"currency\":null,\"to_currency\":null,\"converted\":null}]"),
+            response.body
+        );
+    }
+
+    #[test]
+    fn route_transaction_returns_expected() {
+        let response = route("GET", "/transactions/8", &database());
+        assert_eq!(200, response.status);
+
+        let response = route("GET", "/transactions/9", &database());
+        assert_eq!(404, response.status);
+    }
+
+    #[test]
+    fn route_unknown_account_returns_not_found() {
+        assert_eq!(404, route("GET", "/accounts/4", &database()).status);
+        assert_eq!(400, route("GET", "/accounts/four", &database()).status);
+    }
+
+    #[test]
+    fn route_cached_serves_account_until_invalidated() {
+        let mut database = database();
+        let cache = AccountCache::new(1);
+
+        let first = route_cached("GET", "/accounts/3", &database, &cache);
+        assert_eq!(1, cache.len());
+
+        database
+            .apply_transaction(Transaction {
+                transaction_type: TransactionType::Deposit(amount("1")),
+                client: 3,
+                id: 9,
+                timestamp: None,
+                currency: None,
+            })
+            .unwrap();
+        assert_eq!(first, route_cached("GET", "/accounts/3", &database, &cache));
+
+        cache.invalidate(3);
+        let second = route_cached("GET", "/accounts/3", &database, &cache);
+        assert_eq!(true, second.body.contains(&places("\"total\":\"3.0000\"")));
+    }
+
+    #[test]
+    fn account_cache_insert_evicts_least_recently_read() {
+        let cache = AccountCache::new(2);
+        cache.insert(1, String::from("one"));
+        cache.insert(2, String::from("two"));
+        assert_eq!(Some(String::from("one")), cache.get(1));
+
+        cache.insert(3, String::from("three"));
+
+        assert_eq!(None, cache.get(2));
+        assert_eq!(Some(String::from("one")), cache.get(1));
+        assert_eq!(Some(String::from("three")), cache.get(3));
+    }
+
+    fn incident_database() -> Database<Client> {
+        let mut database = database();
+        let transactions = [
+            (TransactionType::Deposit(amount("5")), 4, 10),
+            (TransactionType::Dispute { case: None }, 4, 10),
+            (TransactionType::Deposit(amount("1")), 5, 11),
+            (TransactionType::Dispute { case: None }, 5, 11),
+            (TransactionType::Deposit(amount("3")), 6, 12),
+            (TransactionType::Dispute { case: None }, 6, 12),
+            (TransactionType::Chargeback { case: None }, 6, 12),
+        ];
+        for (transaction_type, client, id) in transactions.iter() {
+            database
+                .apply_transaction(Transaction {
+                    transaction_type: *transaction_type,
+                    client: *client,
+                    id: *id,
+                    timestamp: None,
+                    currency: None,
+                })
+                .unwrap();
+        }
+        database
+    }
+
+    #[test]
+    fn route_accounts_sorted_by_held_returns_top_accounts() {
+        let response = route("GET", "/accounts?sort=held&limit=2", &incident_database());
+
+        assert_eq!(200, response.status);
+        let accounts: Vec<serde_json::Value> = serde_json::from_str(&response.body).unwrap();
+        let clients: Vec<u64> = accounts
+            .iter()
+            .map(|account| account["client"].as_u64().unwrap())
+            .collect();
+        assert_eq!(vec![4, 5], clients);
+
+        assert_eq!(400, route("GET", "/accounts?sort=owed", &database()).status);
+        assert_eq!(400, route("GET", "/accounts?limit=-1", &database()).status);
+    }
+
+    #[test]
+    fn route_chargebacks_and_stats_return_expected() {
+        let database = incident_database();
+
+        let response = route("GET", "/chargebacks?limit=5", &database);
+        assert_eq!(200, response.status);
+        assert_eq!(
+            places("[{\"state\":\"Chargebacked\",\"type\":\"deposit\",\"client\":\"6\",\"tx\":12,\"amount\":\"3.0000\",\"reason\":null,\"to\":null,\"case\":null,\"timestamp\":null,\"currency\":null,\"to_currency\":null,\"converted\":null}]"),
+            response.body
+        );
+
+        let response = route("GET", "/stats", &database);
+        assert_eq!(200, response.status);
+        let stats: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(1, stats["locked_accounts"]);
+        assert_eq!(places("6.0000"), stats["held"]);
+        assert_eq!(places("8.0000"), stats["total"]);
+        assert_eq!(4, stats["accounts"]);
+    }
+
+    #[test]
+    fn route_dashboard_returns_page() {
+        let response = route("GET", "/dashboard", &database());
+        let mut written = vec![];
+        response.write_to(&mut written).unwrap();
+
+        assert_eq!(200, response.status);
+        let written = String::from_utf8(written).unwrap();
+        assert_eq!(true, written.contains("Content-Type: text/html"));
+        assert_eq!(true, written.contains("/accounts?sort=held"));
+    }
+
+    #[test]
+    fn route_write_method_returns_method_not_allowed() {
+        let response = route("POST", "/accounts", &database());
+
+        assert_eq!(405, response.status);
+    }
+}
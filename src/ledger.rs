@@ -0,0 +1,821 @@
+use crate::{
+    amount::Amount,
+    client::{Client, ClientAccount, ClientId},
+    clock::Timestamp,
+    pipeline::TransactionObserver,
+    transaction::{Transaction, TransactionError, TransactionId, TransactionType},
+};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::rc::Rc;
+use std::str::FromStr;
+
+// Every applied transaction is rendered as a balanced journal entry, so finance can audit a run
+// with their usual double-entry tooling. Client funds are liabilities of the engine, split into
+// available and held accounts, and money enters and leaves through a settlement asset account.
+//
+// Disputes, resolves and chargebacks carry no amount, so the amount of every deposit and
+// withdrawal is kept until the end of the run. A dispute of a deposit moves the funds from
+// available to held and its chargeback returns them through settlement. A dispute of a withdrawal
+// holds funds the engine advances to the client, which are owed back through the disputes account.
+//
+// In double-entry mode the entries are also posted to a ledger kept in memory, and once the run is
+// over its trial balance must sum to zero. Every entry balancing is only half the check, so the
+// client accounts of the ledger must also hold what the engine says each client holds.
+
+#[derive(Debug)]
+pub enum LedgerError {
+    Io(io::Error),
+    Chart(serde_json::Error),
+}
+
+impl From<io::Error> for LedgerError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// The text format journal entries are written in.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum LedgerFormat {
+    /// Ledger-CLI journal.
+    #[default]
+    Ledger,
+    /// Beancount, which also needs every account to be opened before it's used.
+    Beancount,
+}
+
+impl FromStr for LedgerFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ledger" => Ok(Self::Ledger),
+            "beancount" => Ok(Self::Beancount),
+            _ => Err(format!("unknown ledger format {}", s)),
+        }
+    }
+}
+
+/// The accounts postings are made to. `{client}` in the client accounts is replaced by the
+/// client's id.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct ChartOfAccounts {
+    /// Where deposits come from and withdrawals and chargebacks go.
+    pub settlement: String,
+    /// Funds advanced to clients disputing a withdrawal.
+    pub disputes: String,
+    /// Where fees charged to clients go.
+    pub fees: String,
+    pub client_available: String,
+    pub client_held: String,
+    pub commodity: String,
+}
+
+impl Default for ChartOfAccounts {
+    fn default() -> Self {
+        Self {
+            settlement: String::from("Assets:Settlement"),
+            disputes: String::from("Assets:Disputes"),
+            fees: String::from("Income:Fees"),
+            client_available: String::from("Liabilities:Clients:{client}:Available"),
+            client_held: String::from("Liabilities:Clients:{client}:Held"),
+            commodity: String::from("USD"),
+        }
+    }
+}
+
+impl ChartOfAccounts {
+    /// Reads a chart from a JSON object. Accounts it leaves out keep their default.
+    pub fn load(path: &str) -> Result<Self, LedgerError> {
+        let reader = BufReader::new(File::open(path)?);
+        serde_json::from_reader(reader).map_err(LedgerError::Chart)
+    }
+
+    fn available(&self, client: ClientId) -> String {
+        self.client_available
+            .replace("{client}", &client.to_string())
+    }
+
+    fn held(&self, client: ClientId) -> String {
+        self.client_held.replace("{client}", &client.to_string())
+    }
+}
+
+/// An amount debited (positive) or credited (negative) to an account.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Posting {
+    pub account: String,
+    pub amount: Amount,
+}
+
+/// Turns applied transactions into the postings that balance them.
+#[derive(Default)]
+pub struct Journal {
+    chart: ChartOfAccounts,
+    /// The amount of every applied deposit and withdrawal, and whether it was a deposit.
+    amounts: HashMap<TransactionId, (Amount, bool)>,
+}
+
+impl Journal {
+    pub fn new(chart: ChartOfAccounts) -> Self {
+        Self {
+            chart,
+            amounts: HashMap::new(),
+        }
+    }
+
+    /// Remembers the amount of a deposit or withdrawal applied before the journal was started, so
+    /// disputes of it can be posted.
+    fn remember(&mut self, transaction: &Transaction) {
+        match transaction.transaction_type {
+            TransactionType::Deposit(amount) => {
+                self.amounts.insert(transaction.id, (amount, true));
+            }
+            TransactionType::Withdrawal(amount) => {
+                self.amounts.insert(transaction.id, (amount, false));
+            }
+            _ => {}
+        }
+    }
+
+    /// The postings for an applied transaction. Returns `None` for a transaction that moves no
+    /// funds, or one that refers to a transaction applied before the journal was started.
+    pub fn postings(&mut self, transaction: &Transaction) -> Option<Vec<Posting>> {
+        let client = transaction.client;
+        let chart = &self.chart;
+
+        let (debit, credit, amount) = match transaction.transaction_type {
+            TransactionType::Deposit(amount) => {
+                self.amounts.insert(transaction.id, (amount, true));
+                (chart.settlement.clone(), chart.available(client), amount)
+            }
+            TransactionType::Withdrawal(amount) => {
+                self.amounts.insert(transaction.id, (amount, false));
+                (chart.available(client), chart.settlement.clone(), amount)
+            }
+            TransactionType::Dispute { .. } => match self.amounts.get(&transaction.id)? {
+                (amount, true) => (chart.available(client), chart.held(client), *amount),
+                (amount, false) => (chart.disputes.clone(), chart.held(client), *amount),
+            },
+            TransactionType::Resolve => {
+                let (amount, _) = self.amounts.get(&transaction.id)?;
+                (chart.held(client), chart.available(client), *amount)
+            }
+            TransactionType::Chargeback { .. } => match self.amounts.get(&transaction.id)? {
+                (amount, true) => (chart.held(client), chart.settlement.clone(), *amount),
+                (amount, false) => (chart.held(client), chart.disputes.clone(), *amount),
+            },
+            TransactionType::Hold { amount, .. } => {
+                (chart.available(client), chart.held(client), amount)
+            }
+            TransactionType::Release { amount, .. } => {
+                (chart.held(client), chart.available(client), amount)
+            }
+            TransactionType::Transfer { to, amount } => {
+                (chart.available(client), chart.available(to), amount)
+            }
+            TransactionType::Fee { amount } => {
+                (chart.available(client), chart.fees.clone(), amount)
+            }
+            // The journal is kept in a single commodity, so it can't balance a conversion
+            // between currencies.
+            TransactionType::Unlock | TransactionType::Convert { .. } => return None,
+        };
+
+        Some(vec![
+            Posting {
+                account: debit,
+                amount,
+            },
+            Posting {
+                account: credit,
+                amount: Amount::zero() - amount,
+            },
+        ])
+    }
+}
+
+/// Writes a journal entry for every applied transaction.
+pub struct LedgerWriter {
+    journal: Journal,
+    commodity: String,
+    format: LedgerFormat,
+    /// Every entry is dated the day of the run, as transactions carry no time of their own.
+    date: String,
+    writer: Box<dyn Write>,
+    /// Accounts already opened in a Beancount journal.
+    opened: HashSet<String>,
+    /// The first failure to write an entry, kept until the writer is finished.
+    error: Option<io::Error>,
+}
+
+impl LedgerWriter {
+    pub fn new(
+        chart: ChartOfAccounts,
+        format: LedgerFormat,
+        now: Timestamp,
+        writer: Box<dyn Write>,
+    ) -> Self {
+        Self {
+            commodity: chart.commodity.clone(),
+            journal: Journal::new(chart),
+            format,
+            date: date(now),
+            writer,
+            opened: HashSet::new(),
+            error: None,
+        }
+    }
+
+    /// Flushes the journal. Returns the first failure to write an entry, if any.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.writer.flush()
+    }
+
+    fn write_entry(&mut self, transaction: &Transaction) -> io::Result<()> {
+        let postings = match self.journal.postings(transaction) {
+            Some(postings) => postings,
+            None => return Ok(()),
+        };
+        let kind = kind(transaction);
+
+        match self.format {
+            LedgerFormat::Ledger => {
+                writeln!(
+                    self.writer,
+                    "{} * ({}) {} client {}",
+                    self.date, transaction.id, kind, transaction.client
+                )?;
+            }
+            LedgerFormat::Beancount => {
+                for posting in postings.iter() {
+                    if self.opened.insert(posting.account.clone()) {
+                        writeln!(
+                            self.writer,
+                            "{} open {} {}",
+                            self.date, posting.account, self.commodity
+                        )?;
+                    }
+                }
+                writeln!(
+                    self.writer,
+                    "{} * \"client {}\" \"{} tx {}\"",
+                    self.date, transaction.client, kind, transaction.id
+                )?;
+            }
+        }
+
+        for posting in postings {
+            writeln!(
+                self.writer,
+                "    {}  {} {}",
+                posting.account, posting.amount, self.commodity
+            )?;
+        }
+        writeln!(self.writer)
+    }
+}
+
+/// Shares a ledger writer with the pipeline, so it can be finished once the run is over.
+pub struct LedgerObserver(pub Rc<RefCell<LedgerWriter>>);
+
+impl LedgerObserver {
+    fn write_entry(&mut self, transaction: &Transaction) {
+        let mut writer = self.0.borrow_mut();
+        if writer.error.is_none() {
+            if let Err(e) = writer.write_entry(transaction) {
+                writer.error = Some(e);
+            }
+        }
+    }
+}
+
+impl TransactionObserver for LedgerObserver {
+    fn on_transaction(&mut self, transaction: &Transaction, result: &Result<(), TransactionError>) {
+        if result.is_ok() {
+            self.write_entry(transaction);
+        }
+    }
+
+    fn on_fee(&mut self, fee: &Transaction) {
+        self.write_entry(fee);
+    }
+}
+
+/// Every applied transaction posted to a ledger kept in memory, with the balance of each account.
+pub struct DoubleEntryLedger {
+    journal: Journal,
+    balances: BTreeMap<String, Amount>,
+}
+
+impl DoubleEntryLedger {
+    pub fn new(chart: ChartOfAccounts) -> Self {
+        Self {
+            journal: Journal::new(chart),
+            balances: BTreeMap::new(),
+        }
+    }
+
+    /// Starts from the balances the accounts hold before the run, such as those restored from a
+    /// snapshot, as if they had been deposited through settlement. The deposits and withdrawals in
+    /// their histories can be disputed during the run.
+    pub fn opening<'a>(chart: ChartOfAccounts, accounts: impl Iterator<Item = &'a Client>) -> Self {
+        let mut ledger = Self::new(chart);
+        for account in accounts {
+            for (_, transaction) in account.history() {
+                ledger.journal.remember(transaction);
+            }
+            let chart = &ledger.journal.chart;
+            let postings = [
+                (chart.settlement.clone(), account.total()),
+                (
+                    chart.available(account.id()),
+                    Amount::zero() - account.available(),
+                ),
+                (chart.held(account.id()), Amount::zero() - account.held()),
+            ];
+            for (account, amount) in postings {
+                ledger.add(account, amount);
+            }
+        }
+        ledger
+    }
+
+    /// Posts the entry for an applied transaction, if it moves any funds.
+    pub fn post(&mut self, transaction: &Transaction) {
+        for posting in self.journal.postings(transaction).into_iter().flatten() {
+            self.add(posting.account, posting.amount);
+        }
+    }
+
+    fn add(&mut self, account: String, amount: Amount) {
+        let balance = self.balances.entry(account).or_default();
+        *balance = *balance + amount;
+    }
+
+    /// The clients whose balances in the ledger differ from those of their accounts, in client
+    /// order. Clients holding other currencies are left out, as the ledger is kept in a single
+    /// commodity and conversions aren't posted.
+    pub fn mismatches<'a>(&self, accounts: impl Iterator<Item = &'a Client>) -> Vec<Mismatch> {
+        let mut accounts: Vec<&Client> = accounts
+            .filter(|account| account.other_currencies().is_empty())
+            .collect();
+        accounts.sort_by_key(|account| account.id());
+
+        // Client accounts are liabilities, so what the ledger owes a client is credited.
+        let owed = |account: String| {
+            Amount::zero() - self.balances.get(&account).copied().unwrap_or_default()
+        };
+        let chart = &self.journal.chart;
+        let mut mismatches = vec![];
+        for account in accounts {
+            let ledger = (
+                owed(chart.available(account.id())),
+                owed(chart.held(account.id())),
+            );
+            let balances = (account.available(), account.held());
+            if ledger != balances {
+                mismatches.push(Mismatch {
+                    client: account.id(),
+                    ledger,
+                    account: balances,
+                });
+            }
+        }
+        mismatches
+    }
+
+    /// The balance of every account posted to.
+    pub fn trial_balance(&self) -> TrialBalance {
+        TrialBalance {
+            accounts: self.balances.clone(),
+        }
+    }
+}
+
+/// The balance of every account in a ledger, by account.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrialBalance {
+    pub accounts: BTreeMap<String, Amount>,
+}
+
+impl TrialBalance {
+    /// The sum of the accounts with a debit balance.
+    pub fn debits(&self) -> Amount {
+        self.accounts
+            .values()
+            .filter(|balance| !balance.less_than_zero())
+            .fold(Amount::zero(), |sum, balance| sum + *balance)
+    }
+
+    /// The sum of the accounts with a credit balance, which is negative.
+    pub fn credits(&self) -> Amount {
+        self.accounts
+            .values()
+            .filter(|balance| balance.less_than_zero())
+            .fold(Amount::zero(), |sum, balance| sum + *balance)
+    }
+
+    /// Whether the debits and credits sum to zero, as every entry balanced.
+    pub fn is_balanced(&self) -> bool {
+        self.debits() + self.credits() == Amount::zero()
+    }
+}
+
+/// A client whose balances in the ledger differ from those of its account.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    pub client: ClientId,
+    /// The available and held funds the ledger owes the client.
+    pub ledger: (Amount, Amount),
+    /// The available and held funds of the client's account.
+    pub account: (Amount, Amount),
+}
+
+/// Writes a line per mismatch.
+pub fn write_mismatches(mismatches: &[Mismatch], writer: &mut dyn Write) -> io::Result<()> {
+    for mismatch in mismatches {
+        writeln!(
+            writer,
+            "client {}: ledger available {} held {} != account available {} held {}",
+            mismatch.client,
+            mismatch.ledger.0,
+            mismatch.ledger.1,
+            mismatch.account.0,
+            mismatch.account.1
+        )?;
+    }
+    writer.flush()
+}
+
+/// Shares a double-entry ledger with the pipeline, so its trial balance can be checked once the
+/// run is over.
+pub struct DoubleEntryObserver(pub Rc<RefCell<DoubleEntryLedger>>);
+
+impl TransactionObserver for DoubleEntryObserver {
+    fn on_transaction(&mut self, transaction: &Transaction, result: &Result<(), TransactionError>) {
+        if result.is_ok() {
+            self.0.borrow_mut().post(transaction);
+        }
+    }
+
+    fn on_fee(&mut self, fee: &Transaction) {
+        self.0.borrow_mut().post(fee);
+    }
+}
+
+/// The name of the transaction's type used in input files.
+fn kind(transaction: &Transaction) -> &'static str {
+    match transaction.transaction_type {
+        TransactionType::Deposit(_) => "deposit",
+        TransactionType::Withdrawal(_) => "withdrawal",
+        TransactionType::Dispute { .. } => "dispute",
+        TransactionType::Resolve => "resolve",
+        TransactionType::Chargeback { .. } => "chargeback",
+        TransactionType::Hold { .. } => "hold",
+        TransactionType::Release { .. } => "release",
+        TransactionType::Transfer { .. } => "transfer",
+        TransactionType::Unlock => "unlock",
+        TransactionType::Convert { .. } => "convert",
+        TransactionType::Fee { .. } => "fee",
+    }
+}
+
+/// The UTC date of the timestamp as `YYYY-MM-DD`.
+fn date(timestamp: Timestamp) -> String {
+    // Converts days since the epoch to a civil date, counting in 400 year eras from 0000-03-01.
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        amount, chargeback, deposit, dispute, places, resolve, transaction, withdrawal,
+    };
+    use crate::{
+        amount::RoundingMode,
+        database::Database,
+        fees::{Fee, FeeSchedule},
+        pipeline::PipelineBuilder,
+    };
+
+    /// Collects everything written, so it can be read after the writer is moved into a ledger.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn date_returns_expected() {
+        assert_eq!("1970-01-01", date(0));
+        assert_eq!("2000-02-29", date(951_782_400));
+        assert_eq!("2021-03-01", date(1_614_556_800 + 86_399));
+    }
+
+    #[test]
+    fn journal_postings_match_balances() {
+        let mut journal = Journal::default();
+
+        let transactions = [
+            deposit(1, 1, 30000),
+            withdrawal(1, 2, 10000),
+            dispute(1, 2),
+            resolve(1, 2),
+            transaction(
+                1,
+                3,
+                TransactionType::Transfer {
+                    to: 2,
+                    amount: Amount::new(10000),
+                },
+            ),
+            deposit(2, 4, 15000),
+            dispute(2, 4),
+            chargeback(2, 4),
+        ];
+
+        let mut totals: HashMap<String, Amount> = HashMap::new();
+        for transaction in transactions.iter() {
+            let postings = journal.postings(transaction).unwrap();
+            let sum = postings
+                .iter()
+                .fold(Amount::zero(), |sum, posting| sum + posting.amount);
+            assert_eq!(Amount::zero(), sum);
+
+            for posting in postings {
+                let total = totals.entry(posting.account).or_default();
+                *total = *total + posting.amount;
+            }
+        }
+
+        // Client funds are liabilities, so their balances are credits.
+        assert_eq!(
+            Amount::new(-20000),
+            totals["Liabilities:Clients:1:Available"]
+        );
+        assert_eq!(
+            Amount::new(-10000),
+            totals["Liabilities:Clients:2:Available"]
+        );
+        assert_eq!(Amount::zero(), totals["Liabilities:Clients:1:Held"]);
+        assert_eq!(Amount::zero(), totals["Liabilities:Clients:2:Held"]);
+        assert_eq!(Amount::new(20000), totals["Assets:Settlement"]);
+        assert_eq!(Amount::new(10000), totals["Assets:Disputes"]);
+    }
+
+    #[test]
+    fn double_entry_observer_trial_balance_sums_to_zero() {
+        let ledger = Rc::new(RefCell::new(DoubleEntryLedger::new(
+            ChartOfAccounts::default(),
+        )));
+        let mut observer = DoubleEntryObserver(ledger.clone());
+
+        let deposit = deposit(1, 1, 30000);
+        let withdrawal = withdrawal(1, 2, 10000);
+        observer.on_transaction(&deposit, &Ok(()));
+        observer.on_transaction(&withdrawal, &Ok(()));
+        observer.on_transaction(&withdrawal, &Err(TransactionError::ClientLocked));
+        observer.on_transaction(&dispute(1, 1), &Ok(()));
+        observer.on_transaction(&chargeback(1, 1), &Ok(()));
+
+        let trial = ledger.borrow().trial_balance();
+        assert_eq!(true, trial.is_balanced());
+        // The charged back deposit was partly withdrawn, so the client owes the rest.
+        assert_eq!(Amount::new(10000), trial.debits());
+        assert_eq!(Amount::new(-10000), trial.credits());
+        assert_eq!(
+            Amount::new(10000),
+            trial.accounts["Liabilities:Clients:1:Available"]
+        );
+        assert_eq!(Amount::zero(), trial.accounts["Liabilities:Clients:1:Held"]);
+        assert_eq!(Amount::new(-10000), trial.accounts["Assets:Settlement"]);
+    }
+
+    #[test]
+    fn double_entry_ledger_mismatches_returns_none_after_run() {
+        let schedule = FeeSchedule {
+            deposit: None,
+            withdrawal: Some(Fee::Flat(Amount::new(1000))),
+        };
+        let mut database = Database::<Client>::new().with_fees(schedule, RoundingMode::HalfUp);
+        database.apply_transaction(deposit(1, 1, 50000)).unwrap();
+        let ledger = Rc::new(RefCell::new(DoubleEntryLedger::opening(
+            ChartOfAccounts::default(),
+            database.accounts(),
+        )));
+        let mut observer = DoubleEntryObserver(ledger.clone());
+
+        for transaction in [
+            withdrawal(1, 2, 10000),
+            dispute(1, 2),
+            chargeback(1, 2),
+            transaction(
+                1,
+                3,
+                TransactionType::Transfer {
+                    to: 2,
+                    amount: Amount::new(5000),
+                },
+            ),
+            dispute(1, 1),
+        ] {
+            let result = database.apply_transaction(transaction);
+            observer.on_transaction(&transaction, &result);
+            for fee in database.take_charged_fees() {
+                observer.on_fee(&fee);
+            }
+        }
+
+        assert_eq!(true, ledger.borrow().trial_balance().is_balanced());
+        assert_eq!(
+            Vec::<Mismatch>::new(),
+            ledger.borrow().mismatches(database.accounts())
+        );
+    }
+
+    #[test]
+    fn double_entry_ledger_mismatches_returns_clients_that_differ() {
+        let mut database = Database::<Client>::new();
+        database.apply_transaction(deposit(1, 1, 50000)).unwrap();
+        database.apply_transaction(deposit(2, 2, 10000)).unwrap();
+        // Only client 2's deposit reaches the ledger.
+        let mut ledger = DoubleEntryLedger::new(ChartOfAccounts::default());
+        ledger.post(&deposit(2, 2, 10000));
+
+        let mismatches = ledger.mismatches(database.accounts());
+        let expected = vec![Mismatch {
+            client: 1,
+            ledger: (Amount::zero(), Amount::zero()),
+            account: (Amount::new(50000), Amount::zero()),
+        }];
+        assert_eq!(true, ledger.trial_balance().is_balanced());
+        assert_eq!(expected, mismatches);
+
+        let mut output = vec![];
+        write_mismatches(&mismatches, &mut output).unwrap();
+        assert_eq!(
+            format!(
+                "client 1: ledger available {0} held {0} != account available {1} held {0}\n",
+                Amount::zero(),
+                Amount::new(50000)
+            ),
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn trial_balance_is_balanced_returns_expected() {
+        let mut trial = TrialBalance::default();
+        assert_eq!(true, trial.is_balanced());
+
+        trial
+            .accounts
+            .insert(String::from("Assets:Settlement"), Amount::new(100));
+        assert_eq!(false, trial.is_balanced());
+    }
+
+    #[test]
+    fn journal_postings_unknown_dispute_returns_none() {
+        let mut journal = Journal::default();
+
+        let actual = journal.postings(&dispute(1, 1));
+
+        assert_eq!(None, actual);
+    }
+
+    #[test]
+    fn ledger_writer_writes_format() {
+        let deposit = transaction(1, 7, TransactionType::Deposit(amount("1.5")));
+        let chart = ChartOfAccounts {
+            client_available: String::from("Liabilities:Client{client}"),
+            ..ChartOfAccounts::default()
+        };
+
+        let buffer = SharedBuffer::default();
+        let ledger = Rc::new(RefCell::new(LedgerWriter::new(
+            chart.clone(),
+            LedgerFormat::Ledger,
+            0,
+            Box::new(buffer.clone()),
+        )));
+        let mut observer = LedgerObserver(ledger.clone());
+        observer.on_transaction(&deposit, &Ok(()));
+        observer.on_transaction(&deposit, &Err(TransactionError::ClientLocked));
+        ledger.borrow_mut().finish().unwrap();
+
+        let expected = places(
+            "1970-01-01 * (7) deposit client 1\n\
+             \x20   Assets:Settlement  1.5000 USD\n\
+             \x20   Liabilities:Client1  -1.5000 USD\n\n",
+        );
+        assert_eq!(expected, String::from_utf8(buffer.0.take()).unwrap());
+
+        let buffer = SharedBuffer::default();
+        let ledger = Rc::new(RefCell::new(LedgerWriter::new(
+            chart,
+            LedgerFormat::Beancount,
+            0,
+            Box::new(buffer.clone()),
+        )));
+        let mut observer = LedgerObserver(ledger.clone());
+        observer.on_transaction(&deposit, &Ok(()));
+        observer.on_transaction(&deposit, &Ok(()));
+        ledger.borrow_mut().finish().unwrap();
+
+        let entry = places(
+            "1970-01-01 * \"client 1\" \"deposit tx 7\"\n\
+             \x20   Assets:Settlement  1.5000 USD\n\
+             \x20   Liabilities:Client1  -1.5000 USD\n\n",
+        );
+        let expected = format!(
+            "1970-01-01 open Assets:Settlement USD\n\
+             1970-01-01 open Liabilities:Client1 USD\n{}{}",
+            entry, entry
+        );
+        assert_eq!(expected, String::from_utf8(buffer.0.take()).unwrap());
+    }
+
+    #[test]
+    fn pipeline_fees_reach_ledger_and_double_entry_observers() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 5.0\nwithdrawal, 1, 2, 1.0\n";
+        let schedule = FeeSchedule {
+            deposit: Some(Fee::Flat(amount("0.1"))),
+            withdrawal: Some(Fee::Flat(amount("0.1"))),
+        };
+
+        for shards in [1, 2] {
+            let buffer = SharedBuffer::default();
+            let writer = Rc::new(RefCell::new(LedgerWriter::new(
+                ChartOfAccounts::default(),
+                LedgerFormat::Ledger,
+                0,
+                Box::new(buffer.clone()),
+            )));
+            let ledger = Rc::new(RefCell::new(DoubleEntryLedger::new(
+                ChartOfAccounts::default(),
+            )));
+
+            let database = PipelineBuilder::new(
+                Database::<Client>::new().with_fees(schedule.clone(), RoundingMode::HalfUp),
+            )
+            .input_reader(Box::new(input.as_bytes()))
+            .output(Box::new(io::sink()))
+            .shards(shards)
+            .observer(Box::new(LedgerObserver(writer.clone())))
+            .observer(Box::new(DoubleEntryObserver(ledger.clone())))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+            writer.borrow_mut().finish().unwrap();
+
+            let output = String::from_utf8(buffer.0.take()).unwrap();
+            let fee = places(
+                "1970-01-01 * (1) fee client 1\n\
+                 \x20   Liabilities:Clients:1:Available  0.1000 USD\n\
+                 \x20   Income:Fees  -0.1000 USD\n\n",
+            );
+            assert_eq!(true, output.contains(&fee), "{}", output);
+            assert_eq!(4, output.matches(" * (").count());
+
+            let trial = ledger.borrow().trial_balance();
+            assert_eq!(true, trial.is_balanced());
+            assert_eq!(amount("-0.2"), trial.accounts["Income:Fees"]);
+            assert_eq!(
+                Amount::zero() - database.account(1).unwrap().available(),
+                trial.accounts["Liabilities:Clients:1:Available"]
+            );
+        }
+    }
+}
@@ -0,0 +1,72 @@
+// Tests compare against literal bools to keep the expected value first.
+#![cfg_attr(test, allow(clippy::bool_assert_comparison))]
+
+#[cfg(test)]
+mod allocations;
+pub mod amount;
+pub mod approval;
+pub mod audit;
+pub mod backtest;
+pub mod checkpoint;
+pub mod client;
+pub mod clock;
+pub mod coalesce;
+pub mod compression;
+pub mod conformance;
+pub mod currency;
+pub mod database;
+pub mod diff;
+pub mod engine;
+pub mod enrich;
+pub mod event;
+pub mod export;
+pub mod fees;
+#[cfg(test)]
+mod fixtures;
+pub mod follow;
+pub mod grace;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod http;
+pub mod ledger;
+pub mod manifest;
+pub mod overdraft;
+pub mod parallel_csv;
+#[cfg(feature = "avro-input")]
+pub mod parse_avro;
+pub mod parse_csv;
+pub mod parse_fixed_width;
+pub mod parse_jsonl;
+#[cfg(feature = "parquet-input")]
+pub mod parse_parquet;
+pub mod pipeline;
+pub mod protocol;
+pub mod random;
+pub mod rates;
+pub mod reconcile;
+pub mod remap;
+pub mod reorder;
+pub mod repl;
+pub mod report;
+pub mod retention;
+pub mod risk;
+pub mod rules;
+pub mod run;
+pub mod sample;
+pub mod scheduler;
+pub mod server;
+pub mod shard;
+pub mod sink;
+pub mod snapshot;
+pub mod source;
+pub mod spill;
+pub mod storage;
+pub mod stress;
+pub mod telemetry;
+pub mod throttle;
+pub mod transaction;
+pub mod validate;
+pub mod velocity;
+pub mod verify;
+pub mod wal;
+pub mod websocket;
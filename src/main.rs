@@ -1,117 +1,424 @@
+// Tests compare against literal bools to keep the expected value first.
+#![cfg_attr(test, allow(clippy::bool_assert_comparison))]
+
 use std::env;
-use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
 
-pub mod amount;
-pub mod client;
-mod database;
-mod parse_csv;
 mod parse_env_args;
-pub mod transaction;
-use parse_env_args::{env_args_parse_file, EnvArgsParseError};
+use parse_env_args::{
+    env_args_parse, env_args_parse_randomness, resolve_input_file, ApprovalAction, Command,
+    EnvArgsParseError,
+};
+use payments::{
+    approval::{ApprovalError, ApprovalQueue},
+    backtest::{self, Policy},
+    client::{Client, ClientAccount, ClientId, ErasureError},
+    compression,
+    conformance::{self, ConformanceError},
+    database::{Database, MergeError},
+    diff::{self, DiffError, ReportedBalances},
+    engine::Engine,
+    export::{self, ClientExport, TombstoneEvent},
+    http, parse_csv,
+    pipeline::{InputFormat, PipelineError},
+    random::Rng,
+    repl,
+    report::Report,
+    run::{Run, RunError},
+    server::{self, ServeError},
+    snapshot::{self, Snapshot, SnapshotError, SnapshotFormat},
+    source::SourceError,
+    stress::{self, StressConfig},
+    validate,
+};
 
 #[derive(Debug)]
 pub enum ApplicationError {
     EnvArgs(EnvArgsParseError),
-    CsvParseError(Box<dyn Error>),
+    Run(RunError),
+    Serve(ServeError),
+    Pipeline(PipelineError),
+    Approval(ApprovalError),
+    Server(std::io::Error),
+    Snapshot(SnapshotError),
+    Merge(MergeError),
+    Output(std::io::Error),
+    ClientNotFound(ClientId),
+    Erasure(ErasureError),
+    Conformance(ConformanceError),
+    ConformanceFailed {
+        failed: usize,
+    },
+    Validation(SourceError),
+    ValidationFailed {
+        invalid: u64,
+    },
+    Diff(DiffError),
+    /// The reports compared by `diff` differ in this many balances.
+    BalancesDiffer {
+        balances: usize,
+    },
+    /// Invariants didn't hold after a stress run.
+    StressFailed {
+        failed: usize,
+    },
 }
 
-#[cfg(not(feature = "test-large-files"))]
 fn main() -> Result<(), ApplicationError> {
-    let args: Vec<String> = env::args().collect();
+    let (randomness, args) =
+        env_args_parse_randomness(env::args().collect()).map_err(ApplicationError::EnvArgs)?;
 
-    let file_path = match env_args_parse_file(args) {
-        Ok(path) => path,
-        Err(e) => {
-            return Err(ApplicationError::EnvArgs(e));
+    match env_args_parse(args) {
+        Ok(Command::Process(mut args)) => {
+            args.file_path =
+                resolve_input_file(&args.file_path).map_err(ApplicationError::EnvArgs)?;
+            Run::new(*args)
+                .randomness(randomness)
+                .run()
+                .map(|_| ())
+                .map_err(ApplicationError::Run)
         }
-    };
+        Ok(Command::Approvals { queue_path, action }) => approvals(queue_path, action),
+        Ok(Command::Serve(config)) => server::serve(*config).map_err(ApplicationError::Serve),
+        Ok(Command::ExportClient {
+            snapshot_path,
+            snapshot_format,
+            client,
+            redact_amounts,
+            output,
+            force_migrate,
+        }) => {
+            let database = Database::load_snapshot(&snapshot_path, snapshot_format, force_migrate)
+                .map_err(ApplicationError::Snapshot)?;
+            let account = database
+                .account(client)
+                .ok_or(ApplicationError::ClientNotFound(client))?;
+            let export = ClientExport::new(account, redact_amounts);
 
-    let mut database = database::Database::new();
+            let mut writer: Box<dyn Write> = match output {
+                Some(output) => Box::new(File::create(output).map_err(ApplicationError::Output)?),
+                None => Box::new(std::io::stdout()),
+            };
+            export
+                .write_json(&mut writer)
+                .map_err(ApplicationError::Output)
+        }
+        Ok(Command::EraseClient {
+            snapshot_path,
+            snapshot_format,
+            client,
+            audit_log,
+            force_migrate,
+        }) => erase_client(
+            &snapshot_path,
+            snapshot_format,
+            client,
+            audit_log,
+            force_migrate,
+        ),
+        Ok(Command::ServeSnapshot {
+            snapshot_path,
+            snapshot_format,
+            http_addr,
+            force_migrate,
+        }) => {
+            let database = Database::load_snapshot(&snapshot_path, snapshot_format, force_migrate)
+                .map_err(ApplicationError::Snapshot)?;
+            http::serve(&http_addr, database).map_err(ApplicationError::Server)
+        }
+        Ok(Command::Merge {
+            first,
+            second,
+            snapshot_format,
+            snapshot_out,
+            output,
+            force_migrate,
+        }) => {
+            let mut database = Database::load_snapshot(&first, snapshot_format, force_migrate)
+                .map_err(ApplicationError::Snapshot)?;
+            let other = Database::load_snapshot(&second, snapshot_format, force_migrate)
+                .map_err(ApplicationError::Snapshot)?;
+            database.merge(other).map_err(ApplicationError::Merge)?;
 
-    let transactions = match parse_csv::execute(file_path) {
-        Ok(transactions) => transactions,
-        Err(e) => {
-            return Err(ApplicationError::CsvParseError(e));
+            if let Some(snapshot_out) = snapshot_out {
+                database
+                    .save_snapshot(&snapshot_out, snapshot_format)
+                    .map_err(ApplicationError::Snapshot)?;
+            }
+            let mut writer: Box<dyn Write> = match output {
+                Some(output) => Box::new(BufWriter::new(
+                    File::create(output).map_err(ApplicationError::Output)?,
+                )),
+                None => Box::new(std::io::stdout()),
+            };
+            database
+                .write_report(&Report::standard(), &mut writer)
+                .map_err(ApplicationError::Output)
         }
-    };
+        Ok(Command::Diff {
+            before,
+            after,
+            snapshot_format,
+            force_migrate,
+        }) => {
+            let before = read_balances(&before, snapshot_format, force_migrate)?;
+            let after = read_balances(&after, snapshot_format, force_migrate)?;
+            let diffs = diff::diff(&before, &after);
+            diff::write_diff(&diffs, &mut std::io::stdout()).map_err(ApplicationError::Output)?;
+            // Like diff(1), differences fail the command so scripts can catch regressions.
+            match diffs.len() {
+                0 => Ok(()),
+                balances => Err(ApplicationError::BalancesDiffer { balances }),
+            }
+        }
+        Ok(Command::Query {
+            client,
+            file_path,
+            snapshot_path,
+            snapshot_format,
+            force_migrate,
+            history,
+        }) => {
+            let database = query_database(
+                file_path.as_deref(),
+                snapshot_path.as_deref(),
+                snapshot_format,
+                force_migrate,
+            )?;
+            let account = database
+                .account(client)
+                .ok_or(ApplicationError::ClientNotFound(client))?;
 
-    for transaction in transactions {
-        match database.apply_transaction(transaction) {
-            Ok(_) => {
-                // Succesfully processed, so no further actions.
+            if history {
+                return export::write_history(&export::history(account), &mut std::io::stdout())
+                    .map_err(|e| ApplicationError::Output(e.into()));
             }
-            Err(e) => {
-                // TODO: error handling for invalid transactions?
+
+            let report = Report::standard();
+            let mut stdout = std::io::stdout();
+            writeln!(stdout, "{}", report.header()).map_err(ApplicationError::Output)?;
+            writeln!(stdout, "{}", report.row(account)).map_err(ApplicationError::Output)?;
+            for other in account.other_currencies() {
+                writeln!(stdout, "{}", report.row(&other)).map_err(ApplicationError::Output)?;
             }
+            Ok(())
         }
+        Ok(Command::Repl {
+            snapshot_path,
+            snapshot_format,
+            force_migrate,
+        }) => {
+            let mut database = match snapshot_path {
+                Some(path) => Database::load_snapshot(&path, snapshot_format, force_migrate)
+                    .map_err(ApplicationError::Snapshot)?,
+                None => Database::new(),
+            };
+            repl::run(
+                &mut database,
+                snapshot_format,
+                std::io::stdin().lock(),
+                std::io::stdout(),
+            )
+            .map_err(ApplicationError::Output)
+        }
+        Ok(Command::Backtest {
+            file_path,
+            input_format,
+            policies,
+        }) => {
+            let file_path = resolve_input_file(&file_path).map_err(ApplicationError::EnvArgs)?;
+            run_backtest(&file_path, input_format, &policies)
+        }
+        Ok(Command::Conformance { suite_path }) => run_conformance(suite_path),
+        Ok(Command::Validate { file_path }) => {
+            let file_path = resolve_input_file(&file_path).map_err(ApplicationError::EnvArgs)?;
+            validate_file(&file_path)
+        }
+        Ok(Command::Stress(config)) => run_stress(config, randomness.rng("stress")),
+        Err(e) => Err(ApplicationError::EnvArgs(e)),
     }
+}
 
-    database.output();
+/// The accounts restored from the snapshot, if given, with the transaction file applied on top.
+/// Transactions are applied as the engine does, so rejected ones are skipped.
+fn query_database(
+    file_path: Option<&str>,
+    snapshot_path: Option<&str>,
+    snapshot_format: SnapshotFormat,
+    force_migrate: bool,
+) -> Result<Database<Client>, ApplicationError> {
+    let database = match snapshot_path {
+        Some(path) => Database::load_snapshot(path, snapshot_format, force_migrate)
+            .map_err(ApplicationError::Snapshot)?,
+        None => Database::new(),
+    };
+    let mut engine = Engine::from_database(database);
 
-    Ok(())
+    if let Some(file_path) = file_path {
+        let file_path = resolve_input_file(file_path).map_err(ApplicationError::EnvArgs)?;
+        engine
+            .process_file(&file_path, InputFormat::from_path(&file_path))
+            .map_err(|e| ApplicationError::Pipeline(PipelineError::Input(e)))?;
+    }
+
+    Ok(engine.into_database())
 }
 
-// This is a simple way to test large files.
-#[cfg(feature = "test-large-files")]
-fn main() {
-    test_large_files::execute();
+/// The balances reported in a CSV report, or those of the accounts in a snapshot.
+fn read_balances(
+    path: &str,
+    snapshot_format: SnapshotFormat,
+    force_migrate: bool,
+) -> Result<ReportedBalances, ApplicationError> {
+    match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("csv") => {
+            let file = File::open(path).map_err(ApplicationError::Output)?;
+            diff::read_report(BufReader::new(file)).map_err(ApplicationError::Diff)
+        }
+        _ => {
+            let database = Database::load_snapshot(path, snapshot_format, force_migrate)
+                .map_err(ApplicationError::Snapshot)?;
+            Ok(diff::account_balances(database.accounts()))
+        }
+    }
 }
 
-#[cfg(feature = "test-large-files")]
-mod test_large_files {
-    use rand::{seq::SliceRandom, Rng};
+fn erase_client(
+    snapshot_path: &str,
+    snapshot_format: SnapshotFormat,
+    client: ClientId,
+    audit_log: Option<String>,
+    force_migrate: bool,
+) -> Result<(), ApplicationError> {
+    let snapshot = snapshot::read_from_path(snapshot_path, snapshot_format, force_migrate)
+        .map_err(ApplicationError::Snapshot)?;
+    let config_hash = snapshot.config_hash.clone();
+    let mut database = Database::from_snapshot(snapshot).map_err(ApplicationError::Snapshot)?;
+
+    let tombstone = database
+        .account_mut(client)
+        .ok_or(ApplicationError::ClientNotFound(client))?
+        .erase()
+        .map_err(ApplicationError::Erasure)?;
 
-    use crate::{
-        amount::Amount,
-        client::{Client, ClientAccount, ClientId},
-        database::Database,
-        transaction::{Transaction, TransactionError, TransactionId, TransactionType},
+    let snapshot = Snapshot {
+        config_hash,
+        ..database.snapshot()
     };
+    snapshot::write_to_path(&snapshot, snapshot_path, snapshot_format)
+        .map_err(ApplicationError::Snapshot)?;
 
-    pub fn execute() {
-        let mut rng = rand::thread_rng();
+    let event = TombstoneEvent::new(client, tombstone);
+    match audit_log {
+        Some(audit_log) => {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(audit_log)
+                .map_err(ApplicationError::Output)?;
+            event.write_json_line(&mut file)
+        }
+        None => event.write_json_line(&mut std::io::stdout()),
+    }
+    .map_err(ApplicationError::Output)
+}
 
-        let mut db = Database::<Client>::new();
+fn run_backtest(
+    file_path: &str,
+    input_format: Option<InputFormat>,
+    policies: &[Policy],
+) -> Result<(), ApplicationError> {
+    let input_format = input_format.unwrap_or_else(|| InputFormat::from_path(file_path));
+    // Every policy replays the same transactions, so they are read once up front.
+    let transactions = input_format
+        .read(file_path)
+        .map_err(|e| ApplicationError::Pipeline(PipelineError::Input(e)))?;
 
-        // Create some transactions
-        let mut transactions = vec![];
+    let outcomes = backtest::run(&transactions, policies).map_err(ApplicationError::Pipeline)?;
+    backtest::write_report(&outcomes, &mut std::io::stdout()).map_err(ApplicationError::Output)
+}
 
-        for transaction_id in 0..TransactionId::MAX as usize + 1 {
-            if transaction_id % 100000 == 0 {
-                println!(
-                    "Build {:?} out of {:?} transactions. {:?}% complete.",
-                    transaction_id,
-                    TransactionId::MAX,
-                    transaction_id / TransactionId::MAX as usize
-                );
+fn run_conformance(suite_path: Option<String>) -> Result<(), ApplicationError> {
+    let cases = match suite_path {
+        Some(path) => conformance::load(&path),
+        None => conformance::parse(conformance::SUITE),
+    }
+    .map_err(ApplicationError::Conformance)?;
+
+    let mut failed = 0;
+    for case in &cases {
+        match conformance::run_case(case, Client::new) {
+            Ok(_) => println!("ok {}", case.name),
+            Err(mismatch) => {
+                failed += 1;
+                println!("FAILED {}: {:?}", case.name, mismatch);
             }
+        }
+    }
+    println!("{} passed, {} failed", cases.len() - failed, failed);
 
-            let client_id: ClientId = rng.gen();
+    match failed {
+        0 => Ok(()),
+        failed => Err(ApplicationError::ConformanceFailed { failed }),
+    }
+}
 
-            let transaction = Transaction {
-                transaction_type: TransactionType::Deposit(Amount::new(342)),
-                client: client_id,
-                id: transaction_id as TransactionId,
-            };
+fn validate_file(file_path: &str) -> Result<(), ApplicationError> {
+    let reader =
+        compression::open(file_path).map_err(|e| ApplicationError::Validation(e.into()))?;
+    let report =
+        validate::validate(BufReader::new(reader)).map_err(ApplicationError::Validation)?;
+    report
+        .write(&mut std::io::stdout())
+        .map_err(ApplicationError::Output)?;
 
-            transactions.push(transaction);
-        }
+    match report.is_valid() {
+        true => Ok(()),
+        false => Err(ApplicationError::ValidationFailed {
+            invalid: report.invalid_count(),
+        }),
+    }
+}
 
-        transactions.shuffle(&mut rng);
+fn run_stress(config: StressConfig, rng: Rng) -> Result<(), ApplicationError> {
+    let mut database = Database::<Client>::new();
+    let report = stress::run(config, rng, &mut database, &mut std::io::stderr())
+        .map_err(ApplicationError::Output)?;
+    report
+        .write(&mut std::io::stdout())
+        .map_err(ApplicationError::Output)?;
 
-        for (i, transaction) in transactions.iter().enumerate() {
-            if i % 100000 == 0 {
+    match report.failed() {
+        0 => Ok(()),
+        failed => Err(ApplicationError::StressFailed { failed }),
+    }
+}
+
+fn approvals(queue_path: String, action: ApprovalAction) -> Result<(), ApplicationError> {
+    let mut queue = ApprovalQueue::load(&queue_path).map_err(ApplicationError::Approval)?;
+
+    match action {
+        ApprovalAction::List => {
+            println!("status, type, client, tx, amount");
+            for (status, transaction) in queue.entries() {
+                let record = parse_csv::CsvTransaction::from_transaction(transaction);
                 println!(
-                    "Build {:?} out of {:?} transactions. {:?}% complete.",
-                    i,
-                    TransactionId::MAX,
-                    i / TransactionId::MAX as usize
+                    "{:?}, {}, {}, {}, {}",
+                    status,
+                    record.transaction_type,
+                    record.client,
+                    record.tx,
+                    record.amount.unwrap_or_default()
                 );
             }
-
-            let db_result = db.apply_transaction(*transaction);
+            return Ok(());
         }
-
-        db.output();
+        ApprovalAction::Approve(transaction_id) => queue.approve(transaction_id),
+        ApprovalAction::Reject(transaction_id) => queue.reject(transaction_id),
     }
+    .map_err(ApplicationError::Approval)?;
+
+    queue.save(&queue_path).map_err(ApplicationError::Approval)
 }
@@ -0,0 +1,228 @@
+use crate::{
+    clock::Timestamp,
+    pipeline::{Disposition, EngineConfig, TransactionObserver},
+    transaction::{Transaction, TransactionError, Warning},
+};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::rc::Rc;
+
+// A machine readable summary written at the end of a batch run, so orchestrators can check the
+// run did what was expected without scraping its logs.
+
+/// What a run read, how it was configured, what it did and where it wrote.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct RunManifest {
+    pub engine_version: &'static str,
+    pub input: InputManifest,
+    pub config_hash: String,
+    pub counts: RunCounts,
+    pub started_at: Timestamp,
+    pub duration_ms: u128,
+    /// Every file the run wrote, by what it holds.
+    pub outputs: Vec<OutputManifest>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct InputManifest {
+    pub path: String,
+    pub format: String,
+    pub bytes: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct OutputManifest {
+    pub kind: &'static str,
+    pub path: String,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize)]
+pub struct RunCounts {
+    pub transactions: u64,
+    pub applied: u64,
+    pub rejected: u64,
+    /// Transactions skipped because a previous run applied them.
+    pub skipped: u64,
+    pub warnings: u64,
+    /// Of the warnings, those for transactions applied above the soft limit.
+    pub soft_limit_warnings: u64,
+    /// Records of unknown types that were skipped with a warning.
+    pub unknown_types: u64,
+    /// Well formed records that were skipped rather than applied, such as deposits without an
+    /// amount.
+    pub ignored: u64,
+    pub accounts: u64,
+}
+
+impl RunManifest {
+    /// Writes the manifest as pretty printed JSON to the file at the given path.
+    pub fn write_to_path(&self, path: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+}
+
+/// The version of the engine that produced a run.
+pub fn engine_version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+/// Names the engine version and config hash that produced an output, so it can be traced back to
+/// the run.
+pub fn stamp(config_hash: &str) -> String {
+    format!(
+        "engine_version={} config_hash={}",
+        engine_version(),
+        config_hash
+    )
+}
+
+/// Whether outputs written by the given engine version can be read by this one. Versions are
+/// compatible when their major versions match, and while the major version is 0 their minor
+/// versions must match too.
+pub fn is_compatible(version: &str) -> bool {
+    let parts = |version: &str| -> Option<(u64, u64)> {
+        let mut parts = version.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        Some((major, minor))
+    };
+
+    match (parts(version), parts(engine_version())) {
+        (Some((0, minor)), Some((0, current_minor))) => minor == current_minor,
+        (Some((major, _)), Some((current_major, _))) => major == current_major,
+        _ => false,
+    }
+}
+
+/// A stable hash of the engine configuration, so runs with the same settings can be matched.
+/// FNV-1a is used as it's fixed across Rust versions, unlike the standard library's hasher.
+pub fn config_hash(config: &EngineConfig) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in format!("{:?}", config).bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// Counts the outcome of every transaction for the manifest.
+pub struct RunCounter(pub Rc<RefCell<RunCounts>>);
+
+impl TransactionObserver for RunCounter {
+    fn on_transaction(
+        &mut self,
+        _transaction: &Transaction,
+        result: &Result<(), TransactionError>,
+    ) {
+        let mut counts = self.0.borrow_mut();
+        counts.transactions += 1;
+        match result {
+            Ok(_) => counts.applied += 1,
+            Err(e) if e.is_skip() => counts.skipped += 1,
+            Err(_) => counts.rejected += 1,
+        }
+    }
+
+    fn on_warning(&mut self, _transaction: &Transaction, warning: &Warning) {
+        let mut counts = self.0.borrow_mut();
+        counts.warnings += 1;
+        if let Warning::SoftLimit { .. } = warning {
+            counts.soft_limit_warnings += 1;
+        }
+    }
+
+    fn on_unknown_type(&mut self, _transaction_type: &str) {
+        self.0.borrow_mut().unknown_types += 1;
+    }
+
+    /// Applied and rejected transactions are counted by `on_transaction`.
+    fn on_disposition(&mut self, _line: Option<u64>, disposition: &Disposition) {
+        if let Disposition::Ignored { .. } = disposition {
+            self.0.borrow_mut().ignored += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+
+    #[test]
+    fn config_hash_is_stable_and_depends_on_config() {
+        let config = EngineConfig::default();
+        let strict = EngineConfig {
+            strict: true,
+            ..EngineConfig::default()
+        };
+
+        assert_eq!(config_hash(&config), config_hash(&EngineConfig::default()));
+        assert_eq!(16, config_hash(&config).len());
+        assert_eq!(false, config_hash(&config) == config_hash(&strict));
+    }
+
+    #[test]
+    fn is_compatible_returns_expected() {
+        assert_eq!(true, is_compatible(engine_version()));
+        assert_eq!(true, is_compatible("0.1.7"));
+        assert_eq!(false, is_compatible("0.2.0"));
+        assert_eq!(false, is_compatible("1.1.0"));
+        assert_eq!(false, is_compatible(""));
+    }
+
+    #[test]
+    fn run_counter_counts_outcomes() {
+        let counts = Rc::new(RefCell::new(RunCounts::default()));
+        let mut counter = RunCounter(counts.clone());
+        let transaction = Transaction {
+            transaction_type: crate::transaction::TransactionType::Deposit(Amount::new(1)),
+            client: 1,
+            id: 1,
+            timestamp: None,
+            currency: None,
+        };
+
+        counter.on_transaction(&transaction, &Ok(()));
+        counter.on_transaction(&transaction, &Err(TransactionError::ClientLocked));
+        counter.on_transaction(
+            &transaction,
+            &Err(TransactionError::AlreadyProcessed { transaction_id: 1 }),
+        );
+        counter.on_unknown_type("refund");
+        counter.on_disposition(
+            Some(4),
+            &Disposition::Ignored {
+                reason: "deposit without an amount",
+            },
+        );
+        counter.on_warning(
+            &transaction,
+            &Warning::SoftLimit {
+                limit: Amount::zero(),
+            },
+        );
+        counter.on_warning(
+            &transaction,
+            &Warning::AboveMaximum {
+                maximum: Amount::zero(),
+            },
+        );
+
+        let expected = RunCounts {
+            transactions: 3,
+            applied: 1,
+            rejected: 1,
+            skipped: 1,
+            warnings: 2,
+            soft_limit_warnings: 1,
+            unknown_types: 1,
+            ignored: 1,
+            accounts: 0,
+        };
+        assert_eq!(expected, *counts.borrow());
+    }
+}
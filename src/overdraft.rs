@@ -0,0 +1,135 @@
+use crate::{amount::Amount, client::ClientId};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// An overdraft limit lets withdrawals and fees take a client's available balance below zero, down
+// to minus the limit. Every client gets the engine-wide limit unless a limits file, with
+// `client, limit` rows, gives it its own. Only withdrawals and fees beyond the limit are rejected.
+
+#[derive(Debug)]
+pub enum OverdraftError {
+    Csv(csv::Error),
+    /// The limit isn't a non-negative amount.
+    InvalidLimit {
+        passed: String,
+    },
+    /// The file lists the client more than once.
+    DuplicateClient {
+        client: ClientId,
+    },
+}
+
+impl From<csv::Error> for OverdraftError {
+    fn from(e: csv::Error) -> Self {
+        Self::Csv(e)
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct LimitRecord {
+    client: ClientId,
+    limit: String,
+}
+
+/// How far below zero each client's available balance may go.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OverdraftLimits {
+    /// The limit of clients without one of their own.
+    pub default: Amount,
+    clients: HashMap<ClientId, Amount>,
+}
+
+impl OverdraftLimits {
+    /// Every client gets the same limit.
+    pub fn new(default: Amount) -> Self {
+        Self {
+            default,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Reads the limits of individual clients from a CSV file with `client, limit` columns.
+    pub fn load(mut self, path: &str) -> Result<Self, OverdraftError> {
+        let rdr = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        self.read(rdr)?;
+        Ok(self)
+    }
+
+    fn read<R: std::io::Read>(&mut self, mut rdr: csv::Reader<R>) -> Result<(), OverdraftError> {
+        for result in rdr.deserialize() {
+            let record: LimitRecord = result?;
+            let limit = match Amount::from_str(&record.limit) {
+                Ok(limit) if !limit.less_than_zero() => limit,
+                _ => {
+                    return Err(OverdraftError::InvalidLimit {
+                        passed: record.limit,
+                    })
+                }
+            };
+
+            if !self.insert(record.client, limit) {
+                return Err(OverdraftError::DuplicateClient {
+                    client: record.client,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets the client's own limit, returning false if it already had one.
+    pub fn insert(&mut self, client: ClientId, limit: Amount) -> bool {
+        self.clients.insert(client, limit).is_none()
+    }
+
+    /// The client's limit.
+    pub fn limit(&self, client: ClientId) -> Amount {
+        self.clients.get(&client).copied().unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::amount;
+
+    fn read(input: &str) -> Result<OverdraftLimits, OverdraftError> {
+        let mut limits = OverdraftLimits::new(Amount::new(10000));
+        limits.read(
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(input.as_bytes()),
+        )?;
+        Ok(limits)
+    }
+
+    #[test]
+    fn overdraft_limits_read_returns_expected() {
+        let limits = read("client, limit\n1, 50.00\n2, 0\n").unwrap();
+
+        assert_eq!(amount("50"), limits.limit(1));
+        assert_eq!(Amount::zero(), limits.limit(2));
+        assert_eq!(Amount::new(10000), limits.limit(3));
+    }
+
+    #[test]
+    fn overdraft_limits_read_invalid_rows_return_err() {
+        assert_eq!(
+            true,
+            matches!(
+                read("client, limit\n1, -5\n"),
+                Err(OverdraftError::InvalidLimit { .. })
+            )
+        );
+        assert_eq!(
+            true,
+            matches!(
+                read("client, limit\n1, 5\n1, 6\n"),
+                Err(OverdraftError::DuplicateClient { client: 1 })
+            )
+        );
+    }
+}
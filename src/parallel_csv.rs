@@ -0,0 +1,330 @@
+use crate::{
+    parse_csv::{self, CsvTransaction, TransactionKinds},
+    source::{InputPosition, SourceError, TransactionSource},
+    transaction::Transaction,
+};
+use rayon::prelude::*;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read};
+
+// Deserializing records dominates the time spent on large files. The input is read in blocks
+// that end on a line boundary, each block is split into line-aligned chunks, and the chunks are
+// deserialized into CSV records on every core. Records are then converted and returned in input
+// order, so nothing downstream can tell the difference. Chunks are split on newlines, so a quoted
+// field can't span lines.
+
+/// Bytes read from the input at a time.
+const BLOCK_LEN: usize = 4 * 1024 * 1024;
+
+/// Chunks aren't made smaller than this, so small inputs aren't split across threads for nothing.
+const MIN_CHUNK_LEN: usize = 64 * 1024;
+
+enum Record {
+    Parsed(CsvTransaction),
+    /// The record could not be read as CSV.
+    Unreadable(csv::Error),
+    /// The record was read but doesn't match the columns.
+    Invalid(csv::Error),
+}
+
+/// A record with its line and where the input continues after it.
+struct ChunkRecord {
+    line: u64,
+    end: InputPosition,
+    record: Record,
+}
+
+/// Streams transactions from CSV with a header row, deserializing records in parallel.
+pub struct ParallelCsvSource<R: Read> {
+    reader: BufReader<R>,
+    headers: Option<csv::StringRecord>,
+    records: VecDeque<ChunkRecord>,
+    /// Where the next block starts.
+    next_block: InputPosition,
+    exhausted: bool,
+    line: u64,
+    position: InputPosition,
+    strict: bool,
+    kinds: TransactionKinds,
+    delimiter: u8,
+}
+
+impl<R: Read> ParallelCsvSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            headers: None,
+            records: VecDeque::new(),
+            next_block: InputPosition::default(),
+            exhausted: false,
+            line: 0,
+            position: InputPosition::default(),
+            strict: false,
+            kinds: TransactionKinds::default(),
+            delimiter: parse_csv::DEFAULT_DELIMITER,
+        }
+    }
+
+    /// Reads from a reader that has already been moved past the header row to the given position
+    /// of its file, using the headers read from the start of the file.
+    pub fn resume(reader: R, headers: csv::StringRecord, position: InputPosition) -> Self {
+        Self {
+            headers: Some(headers),
+            next_block: position,
+            line: position.line,
+            position,
+            ..Self::new(reader)
+        }
+    }
+
+    /// Returns records that would be ignored as errors instead of skipping them.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// How records of a type the engine doesn't know are handled.
+    pub fn kinds(mut self, kinds: TransactionKinds) -> Self {
+        self.kinds = kinds;
+        self
+    }
+
+    /// Separates fields with the delimiter rather than a comma.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Whether the first row is a header row, as it is by default. Without one, every row is a
+    /// record with the columns in the order of `parse_csv::positional_headers`.
+    pub fn has_headers(mut self, has_headers: bool) -> Self {
+        if !has_headers {
+            self.headers = Some(parse_csv::positional_headers());
+        }
+        self
+    }
+
+    fn read_headers(&mut self) -> Result<csv::StringRecord, SourceError> {
+        let mut header = vec![];
+        self.reader.read_until(b'\n', &mut header)?;
+        self.next_block = InputPosition {
+            byte: header.len() as u64,
+            line: 1,
+        };
+
+        parse_csv::read_headers(header.as_slice(), self.delimiter)
+    }
+
+    /// Reads the next block and deserializes its records. Returns false once the input is
+    /// exhausted.
+    fn read_block(&mut self) -> Result<bool, SourceError> {
+        let mut block = Vec::with_capacity(BLOCK_LEN);
+        (&mut self.reader)
+            .take(BLOCK_LEN as u64)
+            .read_to_end(&mut block)?;
+        if block.last().is_some_and(|byte| *byte != b'\n') {
+            self.reader.read_until(b'\n', &mut block)?;
+        }
+        if block.is_empty() {
+            return Ok(false);
+        }
+
+        let headers = self.headers.as_ref();
+        let delimiter = self.delimiter;
+        let chunks = split_lines(&block, rayon::current_num_threads());
+        let parsed: Vec<(Vec<ChunkRecord>, usize)> = chunks
+            .par_iter()
+            .map(|chunk| (parse_chunk(chunk, headers, delimiter), count_lines(chunk)))
+            .collect();
+
+        for (chunk, (records, lines)) in chunks.iter().zip(parsed) {
+            let start = self.next_block;
+            self.records
+                .extend(records.into_iter().map(|record| ChunkRecord {
+                    line: start.line + record.line,
+                    end: InputPosition {
+                        byte: start.byte + record.end.byte,
+                        line: start.line + record.end.line,
+                    },
+                    record: record.record,
+                }));
+            self.next_block = InputPosition {
+                byte: start.byte + chunk.len() as u64,
+                line: start.line + lines as u64,
+            };
+        }
+
+        Ok(true)
+    }
+}
+
+impl<R: Read> TransactionSource for ParallelCsvSource<R> {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        if self.headers.is_none() {
+            match self.read_headers() {
+                Ok(headers) => self.headers = Some(self.kinds.headers(&headers)),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        loop {
+            let record = match self.records.pop_front() {
+                Some(record) => record,
+                None if self.exhausted => return None,
+                None => {
+                    match self.read_block() {
+                        Ok(true) => {}
+                        Ok(false) => self.exhausted = true,
+                        Err(e) => {
+                            self.exhausted = true;
+                            return Some(Err(e));
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            self.line = record.line;
+            self.position = record.end;
+
+            let parsed = match record.record {
+                Record::Parsed(parsed) => {
+                    self.kinds
+                        .convert(parsed.as_record(), record.line, self.strict)
+                }
+                Record::Unreadable(e) => Err(e.into()),
+                Record::Invalid(e) => Err(SourceError::InvalidRecord {
+                    line: record.line,
+                    error: e.into(),
+                }),
+            };
+
+            return Some(parsed);
+        }
+    }
+
+    fn line(&self) -> Option<u64> {
+        Some(self.line)
+    }
+
+    fn position(&self) -> Option<InputPosition> {
+        Some(self.position)
+    }
+}
+
+/// Splits the block into about `count` chunks, each ending just after a newline.
+fn split_lines(block: &[u8], count: usize) -> Vec<&[u8]> {
+    let target = (block.len() / count.max(1)).max(MIN_CHUNK_LEN);
+
+    let mut chunks = vec![];
+    let mut rest = block;
+    while !rest.is_empty() {
+        let end = match rest.iter().skip(target).position(|byte| *byte == b'\n') {
+            Some(newline) => target + newline + 1,
+            None => rest.len(),
+        };
+        let (chunk, remaining) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = remaining;
+    }
+    chunks
+}
+
+fn count_lines(chunk: &[u8]) -> usize {
+    chunk.iter().filter(|byte| **byte == b'\n').count()
+}
+
+/// Deserializes every record in the chunk, with lines and positions relative to its start.
+fn parse_chunk(
+    chunk: &[u8],
+    headers: Option<&csv::StringRecord>,
+    delimiter: u8,
+) -> Vec<ChunkRecord> {
+    let mut rdr = parse_csv::reader_builder(false)
+        .delimiter(delimiter)
+        .from_reader(chunk);
+    let mut record = csv::StringRecord::new();
+
+    let mut records = vec![];
+    loop {
+        let read = match rdr.read_record(&mut record) {
+            Ok(true) => record
+                .deserialize(headers)
+                .map_or_else(Record::Invalid, Record::Parsed),
+            Ok(false) => break,
+            Err(e) => Record::Unreadable(e),
+        };
+
+        // The reader stops at the start of the next record's line.
+        let end = rdr.position();
+        records.push(ChunkRecord {
+            line: record.position().map(|p| p.line()).unwrap_or_default(),
+            end: InputPosition {
+                byte: end.byte(),
+                line: end.line() - 1,
+            },
+            record: read,
+        });
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csv::CsvSource;
+
+    fn read_all<S: TransactionSource>(mut source: S) -> Vec<(String, Option<u64>, InputPosition)> {
+        let mut results = vec![];
+        while let Some(result) = source.next() {
+            let result = match result {
+                Ok(transaction) => format!("{:?}", transaction),
+                Err(e) => e.to_string(),
+            };
+            results.push((result, source.line(), source.position().unwrap()));
+        }
+        results
+    }
+
+    #[test]
+    fn split_lines_ends_chunks_on_newlines() {
+        let line = "deposit, 1, 1, 1.0\n";
+        let block = line.repeat(MIN_CHUNK_LEN / line.len() * 3 + 1);
+
+        let chunks = split_lines(block.as_bytes(), 4);
+
+        assert_eq!(true, chunks.len() > 1);
+        assert_eq!(
+            block.len(),
+            chunks.iter().map(|chunk| chunk.len()).sum::<usize>()
+        );
+        for chunk in chunks {
+            assert_eq!(Some(&b'\n'), chunk.last());
+        }
+    }
+
+    #[test]
+    fn parallel_csv_source_matches_csv_source() {
+        let mut input = String::from("type, client, tx, amount\n");
+        for id in 1..20_000 {
+            let record = match id % 7 {
+                0 => format!("dispute, {}, {},\n", id % 13, id - 1),
+                1 => String::from("\n"),
+                2 => format!("deposit, x, {}, 1.0\n", id),
+                3 => format!("withdrawal, {}, {}, 0.5\n", id % 13, id),
+                _ => format!("deposit, {}, {}, 1.{}\n", id % 13, id, id % 10),
+            };
+            input.push_str(&record);
+        }
+
+        let expected = read_all(CsvSource::new(input.as_bytes()));
+        let actual = read_all(ParallelCsvSource::new(input.as_bytes()));
+
+        assert_eq!(expected.len(), actual.len());
+        assert_eq!(expected, actual);
+
+        let input = input.replace(',', "\t");
+        let tabbed = read_all(ParallelCsvSource::new(input.as_bytes()).delimiter(b'\t'));
+        assert_eq!(expected, tabbed);
+    }
+}
@@ -0,0 +1,318 @@
+use crate::{
+    currency::Currency,
+    parse_csv::{CsvTransaction, ParseCsvError, TransactionKinds},
+    source::{InputPosition, SourceError, TransactionSource},
+    transaction::Transaction,
+};
+use apache_avro::{from_value, Reader, Schema};
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::io::Read;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+// Avro object container files, such as those written by the Kafka Connect sink. Records are read
+// with `SCHEMA`: files written with another schema are resolved against it, so extra fields are
+// dropped, missing optional fields are null and ints may be written where longs are read. Each
+// record is then converted as a CSV record so all formats share the same validation. Records are
+// counted as lines, from 1.
+
+/// The schema transaction records are read with. Amounts are strings so they keep their exact
+/// value.
+pub const SCHEMA: &str = r#"{
+    "type": "record",
+    "name": "Transaction",
+    "namespace": "payments",
+    "fields": [
+        {"name": "type", "type": "string"},
+        {"name": "client", "type": "int"},
+        {"name": "tx", "type": "long"},
+        {"name": "amount", "type": ["null", "string"], "default": null},
+        {"name": "reason", "type": ["null", "int"], "default": null},
+        {"name": "to", "type": ["null", "int"], "default": null},
+        {"name": "case", "type": ["null", "long"], "default": null},
+        {"name": "timestamp", "type": ["null", "long"], "default": null},
+        {"name": "currency", "type": ["null", "string"], "default": null},
+        {"name": "to_currency", "type": ["null", "string"], "default": null},
+        {"name": "converted", "type": ["null", "string"], "default": null}
+    ]
+}"#;
+
+fn schema() -> &'static Schema {
+    static PARSED: OnceLock<Schema> = OnceLock::new();
+    PARSED.get_or_init(|| Schema::parse_str(SCHEMA).expect("the transaction schema is valid"))
+}
+
+#[derive(serde::Deserialize)]
+struct AvroTransaction {
+    #[serde(rename = "type")]
+    transaction_type: String,
+    client: i32,
+    tx: i64,
+    amount: Option<String>,
+    reason: Option<i32>,
+    to: Option<i32>,
+    case: Option<i64>,
+    #[serde(default)]
+    timestamp: Option<i64>,
+    #[serde(default)]
+    currency: Option<String>,
+    #[serde(default)]
+    to_currency: Option<String>,
+    #[serde(default)]
+    converted: Option<String>,
+}
+
+impl TryFrom<AvroTransaction> for CsvTransaction {
+    type Error = ParseCsvError;
+
+    /// Fails for ids that are out of range and currencies that aren't three letter codes.
+    fn try_from(record: AvroTransaction) -> Result<Self, Self::Error> {
+        Ok(Self {
+            transaction_type: record.transaction_type,
+            client: record.client.to_string(),
+            tx: id(record.tx, "tx")?,
+            amount: record.amount,
+            reason: record
+                .reason
+                .map(|reason| id(reason, "reason"))
+                .transpose()?,
+            to: record.to.map(|to| id(to, "to")).transpose()?,
+            case: record.case.map(|case| id(case, "case")).transpose()?,
+            timestamp: record
+                .timestamp
+                .map(|timestamp| id(timestamp, "timestamp"))
+                .transpose()?,
+            currency: record.currency.map(currency).transpose()?,
+            to_currency: record.to_currency.map(currency).transpose()?,
+            converted: record.converted,
+        })
+    }
+}
+
+fn currency(code: String) -> Result<Currency, ParseCsvError> {
+    Currency::from_str(&code)
+        .map_err(|_| ParseCsvError::Other(format!("invalid currency {}", code).into()))
+}
+
+fn id<T, V>(value: V, name: &str) -> Result<T, ParseCsvError>
+where
+    T: TryFrom<V>,
+    V: Copy + Display,
+{
+    T::try_from(value)
+        .map_err(|_| ParseCsvError::Other(format!("invalid {} {}", name, value).into()))
+}
+
+/// Reads transactions from an Avro object container file, one per record.
+pub struct AvroSource<R: Read> {
+    records: Option<Reader<'static, R>>,
+    /// Why the file couldn't be opened, returned by the first call to `next`.
+    error: Option<SourceError>,
+    /// Records already read by an earlier run.
+    skip: u64,
+    line: u64,
+    strict: bool,
+    kinds: TransactionKinds,
+}
+
+impl<R: Read> AvroSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self::resume(reader, InputPosition::default())
+    }
+
+    /// Reads the records after the given position, whose lines are the records already read.
+    /// Blocks can't be sought, so the records before it are read and skipped.
+    pub fn resume(reader: R, position: InputPosition) -> Self {
+        let (records, error) = match Reader::with_schema(schema(), reader) {
+            Ok(records) => (Some(records), None),
+            Err(e) => (None, Some(SourceError::invalid_data(e))),
+        };
+        Self {
+            records,
+            error,
+            skip: position.line,
+            line: 0,
+            strict: false,
+            kinds: TransactionKinds::default(),
+        }
+    }
+
+    /// Returns records that would be ignored as errors instead of skipping them.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// How records of a type the engine doesn't know are handled.
+    pub fn kinds(mut self, kinds: TransactionKinds) -> Self {
+        self.kinds = kinds;
+        self
+    }
+}
+
+impl<R: Read> TransactionSource for AvroSource<R> {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        if let Some(error) = self.error.take() {
+            return Some(Err(error));
+        }
+
+        loop {
+            // A block that can't be decoded leaves nothing after it that can be trusted.
+            let value = match self.records.as_mut()?.next()? {
+                Ok(value) => value,
+                Err(e) => {
+                    self.records = None;
+                    return Some(Err(SourceError::invalid_data(e)));
+                }
+            };
+            self.line += 1;
+            if self.line <= self.skip {
+                continue;
+            }
+
+            let record = from_value::<AvroTransaction>(&value)
+                .map_err(|e| ParseCsvError::Other(Box::new(e)))
+                .and_then(CsvTransaction::try_from);
+            let parsed = match record {
+                Ok(record) => self
+                    .kinds
+                    .convert(record.as_record(), self.line, self.strict),
+                Err(error) => Err(SourceError::InvalidRecord {
+                    line: self.line,
+                    error: Box::new(error),
+                }),
+            };
+
+            return Some(parsed);
+        }
+    }
+
+    fn line(&self) -> Option<u64> {
+        Some(self.line)
+    }
+
+    /// Records are skipped rather than sought when resuming, so only the line is kept.
+    fn position(&self) -> Option<InputPosition> {
+        Some(InputPosition {
+            byte: 0,
+            line: self.line,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::Amount, transaction::TransactionType};
+    use apache_avro::Writer;
+
+    #[derive(serde::Serialize)]
+    struct Written {
+        #[serde(rename = "type")]
+        transaction_type: &'static str,
+        client: i32,
+        tx: i64,
+        amount: Option<&'static str>,
+    }
+
+    /// Written with a narrower schema than `SCHEMA`, as a sink that only knows some fields would.
+    const WRITER_SCHEMA: &str = r#"{
+        "type": "record",
+        "name": "Transaction",
+        "namespace": "payments",
+        "fields": [
+            {"name": "type", "type": "string"},
+            {"name": "client", "type": "int"},
+            {"name": "tx", "type": "long"},
+            {"name": "amount", "type": ["null", "string"]}
+        ]
+    }"#;
+
+    fn avro_file(records: Vec<Written>) -> Vec<u8> {
+        let schema = Schema::parse_str(WRITER_SCHEMA).unwrap();
+        let mut writer = Writer::new(&schema, vec![]);
+        for record in records {
+            writer.append_ser(record).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    fn written(transaction_type: &'static str, tx: i64, amount: Option<&'static str>) -> Written {
+        Written {
+            transaction_type,
+            client: 1,
+            tx,
+            amount,
+        }
+    }
+
+    #[test]
+    fn avro_source_resolves_records_into_transactions() {
+        let file = avro_file(vec![
+            written("deposit", 1, Some("1.5")),
+            written("withdrawal", 2, Some("0.5")),
+            written("dispute", 1, None),
+        ]);
+        let mut source = AvroSource::new(file.as_slice());
+
+        let expected = [
+            (TransactionType::Deposit(Amount::new(15000)), 1),
+            (TransactionType::Withdrawal(Amount::new(5000)), 2),
+            (TransactionType::Dispute { case: None }, 1),
+        ];
+        for (transaction_type, id) in expected.iter() {
+            let expected = Transaction {
+                transaction_type: *transaction_type,
+                client: 1,
+                id: *id,
+                timestamp: None,
+                currency: None,
+            };
+            assert_eq!(Some(expected), source.next().map(Result::unwrap));
+        }
+        assert_eq!(Some(3), source.line());
+        assert_eq!(true, source.next().is_none());
+
+        let position = InputPosition { byte: 0, line: 2 };
+        let mut source = AvroSource::resume(file.as_slice(), position);
+        let transaction = source.next().unwrap().unwrap();
+        assert_eq!(
+            TransactionType::Dispute { case: None },
+            transaction.transaction_type
+        );
+        assert_eq!(true, source.next().is_none());
+    }
+
+    #[test]
+    fn avro_source_returns_invalid_records_and_files() {
+        let file = avro_file(vec![
+            written("deposit", -1, Some("1.0")),
+            written("deposit", 2, Some("1.0")),
+        ]);
+        let mut source = AvroSource::new(file.as_slice());
+        match source.next() {
+            Some(Err(SourceError::InvalidRecord { line: 1, .. })) => {}
+            other => panic!("expected an invalid record, got {:?}", other),
+        }
+        assert_eq!(true, source.next().unwrap().is_ok());
+
+        let mut source = AvroSource::new("type,client,tx,amount\n".as_bytes());
+        assert_eq!(true, matches!(source.next(), Some(Err(SourceError::Io(_)))));
+        assert_eq!(true, source.next().is_none());
+
+        let schema = Schema::parse_str(&WRITER_SCHEMA.replace("\"tx\"", "\"id\"")).unwrap();
+        let mut writer = Writer::new(&schema, vec![]);
+        let record = apache_avro::types::Value::Record(vec![
+            (String::from("type"), "deposit".into()),
+            (String::from("client"), 1.into()),
+            (String::from("id"), 1i64.into()),
+            (String::from("amount"), Some("1.0").into()),
+        ]);
+        writer.append(record).unwrap();
+        let file = writer.into_inner().unwrap();
+        let mut source = AvroSource::new(file.as_slice());
+        assert_eq!(true, matches!(source.next(), Some(Err(SourceError::Io(_)))));
+        assert_eq!(true, source.next().is_none());
+    }
+}
@@ -1,92 +1,988 @@
 use crate::{
-    amount::Amount,
+    amount::{Amount, AmountError, PrecisionPolicy},
     client::ClientId,
-    transaction::{Transaction, TransactionId, TransactionType},
+    clock::Timestamp,
+    currency::Currency,
+    source::{InputPosition, SourceError, TransactionSource},
+    transaction::{CaseId, ReasonCode, Transaction, TransactionId, TransactionType},
 };
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
+use std::num::ParseIntError;
+use std::rc::Rc;
+use std::str::FromStr;
 
-pub fn execute(file_path: String) -> Result<Vec<Transaction>, Box<dyn Error>> {
-    let mut file = File::open(file_path)?;
+/// Why CSV couldn't be read as transactions.
+#[derive(Debug)]
+pub enum ParseCsvError {
+    Io(io::Error),
+    /// The CSV is malformed, or a field doesn't deserialize into its column's type.
+    Csv(csv::Error),
+    Amount(AmountError),
+    /// The client id isn't a number, or doesn't fit in a `ClientId`.
+    ClientId(ParseIntError),
+    /// The record is well formed but skipped rather than applied.
+    Ignored(IgnoredRecord),
+    /// Raised by a registered `TransactionKind`.
+    Other(Box<dyn Error>),
+    /// The error of the record read from the given line.
+    Record {
+        line: u64,
+        error: Box<ParseCsvError>,
+    },
+}
 
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
+impl std::fmt::Display for ParseCsvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Csv(e) => write!(f, "{}", e),
+            Self::Amount(e) => write!(f, "{}", e),
+            Self::ClientId(e) => write!(f, "{}", e),
+            Self::Ignored(e) => write!(f, "{}", e),
+            Self::Other(e) => write!(f, "{}", e),
+            Self::Record { line, error } => write!(f, "line {}: {}", line, error),
+        }
+    }
+}
 
-    // Note: I ran into an issue with whitespace, so just replaced it all to get it working.
-    contents = contents.replace("\r\n", "\n").replace(" ", "");
+impl Error for ParseCsvError {}
 
-    let mut rdr = csv::ReaderBuilder::new()
-        .has_headers(true)
-        .flexible(true)
-        .terminator(csv::Terminator::CRLF)
-        .from_reader(contents.as_bytes());
+impl From<io::Error> for ParseCsvError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<csv::Error> for ParseCsvError {
+    fn from(e: csv::Error) -> Self {
+        Self::Csv(e)
+    }
+}
+
+impl From<AmountError> for ParseCsvError {
+    fn from(e: AmountError) -> Self {
+        Self::Amount(e)
+    }
+}
+
+impl From<ParseIntError> for ParseCsvError {
+    fn from(e: ParseIntError) -> Self {
+        Self::ClientId(e)
+    }
+}
+
+impl From<SourceError> for ParseCsvError {
+    fn from(e: SourceError) -> Self {
+        match e {
+            SourceError::Io(e) => Self::Io(e),
+            SourceError::Csv(e) => Self::Csv(e),
+            SourceError::InvalidRecord { line, error } => Self::Record {
+                line,
+                error: match error.downcast::<ParseCsvError>() {
+                    Ok(error) => error,
+                    Err(error) => Box::new(Self::Other(error)),
+                },
+            },
+            e => Self::Other(Box::new(e)),
+        }
+    }
+}
+
+pub fn execute(file_path: String) -> Result<Vec<Transaction>, ParseCsvError> {
+    let file = File::open(file_path)?;
+    read(file)
+}
+
+/// Reads every transaction from CSV with a header row, skipping ignored records.
+pub fn read<R: Read>(reader: R) -> Result<Vec<Transaction>, ParseCsvError> {
+    let mut source = CsvSource::new(reader);
 
     let mut transactions = vec![];
+    while let Some(result) = source.next() {
+        match result {
+            Err(SourceError::Ignored { .. }) => continue,
+            result => transactions.push(result?),
+        }
+    }
+    Ok(transactions)
+}
 
-    for result in rdr.deserialize() {
-        let record: CsvTransaction = result?;
+/// Streams transactions from CSV with a header row.
+pub struct CsvSource<R: Read> {
+    rdr: csv::Reader<R>,
+    headers: Option<csv::StringRecord>,
+    record: csv::StringRecord,
+    /// The record with its fields trimmed, reused so reading doesn't allocate.
+    trimmed: csv::StringRecord,
+    line: u64,
+    /// Where the reader started in its file, as csv positions are relative to the reader.
+    offset: InputPosition,
+    position: InputPosition,
+    strict: bool,
+    kinds: TransactionKinds,
+    delimiter: u8,
+}
+
+/// The delimiter of CSV that isn't told otherwise.
+pub const DEFAULT_DELIMITER: u8 = b',';
+
+/// The columns of a record without a header row, in order. Trailing optional columns may be
+/// omitted.
+pub fn positional_headers() -> csv::StringRecord {
+    csv::StringRecord::from(vec![
+        "type",
+        "client",
+        "tx",
+        "amount",
+        "reason",
+        "to",
+        "case",
+        "timestamp",
+        "currency",
+        "to_currency",
+        "converted",
+    ])
+}
+
+// Whitespace around fields is common in our inputs, so it's trimmed rather than treated as data.
+pub(crate) fn reader_builder(has_headers: bool) -> csv::ReaderBuilder {
+    let mut builder = csv::ReaderBuilder::new();
+    builder
+        .has_headers(has_headers)
+        .flexible(true)
+        .trim(csv::Trim::All);
+    builder
+}
+
+/// Like `reader_builder`, but only trims the headers. The reader's own trimming allocates a new
+/// record for every row, so fields are trimmed with `trim_into` instead.
+fn untrimmed_reader_builder(has_headers: bool) -> csv::ReaderBuilder {
+    let mut builder = reader_builder(has_headers);
+    builder.trim(csv::Trim::Headers);
+    builder
+}
+
+/// Copies the record into `trimmed` without the whitespace around its fields. The copy reuses
+/// the buffers of `trimmed`, so it stops allocating once they're large enough.
+pub(crate) fn trim_into(record: &csv::StringRecord, trimmed: &mut csv::StringRecord) {
+    trimmed.clear();
+    for field in record.iter() {
+        trimmed.push_field(field.trim());
+    }
+    trimmed.set_position(record.position().cloned());
+}
+
+/// Reads the header row of a CSV file with fields separated by the delimiter.
+pub fn read_headers<R: Read>(reader: R, delimiter: u8) -> Result<csv::StringRecord, SourceError> {
+    Ok(reader_builder(true)
+        .delimiter(delimiter)
+        .from_reader(reader)
+        .headers()?
+        .clone())
+}
+
+impl<R: Read> CsvSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            rdr: untrimmed_reader_builder(true).from_reader(reader),
+            headers: None,
+            record: csv::StringRecord::new(),
+            trimmed: csv::StringRecord::new(),
+            line: 0,
+            offset: InputPosition::default(),
+            position: InputPosition::default(),
+            strict: false,
+            kinds: TransactionKinds::default(),
+            delimiter: DEFAULT_DELIMITER,
+        }
+    }
+
+    /// Reads from a reader that has already been moved past the header row to the given position
+    /// of its file, using the headers read from the start of the file.
+    pub fn resume(reader: R, headers: csv::StringRecord, position: InputPosition) -> Self {
+        Self {
+            rdr: untrimmed_reader_builder(false).from_reader(reader),
+            headers: Some(headers),
+            record: csv::StringRecord::new(),
+            trimmed: csv::StringRecord::new(),
+            line: position.line,
+            offset: position,
+            position,
+            strict: false,
+            kinds: TransactionKinds::default(),
+            delimiter: DEFAULT_DELIMITER,
+        }
+    }
+
+    /// Returns records that would be ignored as errors instead of skipping them.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// How records of a type the engine doesn't know are handled.
+    pub fn kinds(mut self, kinds: TransactionKinds) -> Self {
+        self.kinds = kinds;
+        self
+    }
+
+    /// Separates fields with the delimiter, such as `b'\t'` or `b';'`, rather than a comma. Must
+    /// be set before reading, as the reader is made again around its input.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self.rebuild()
+    }
+
+    /// Whether the first row is a header row, as it is by default. Without one, every row is a
+    /// record with the columns in the order of `positional_headers`. Must be set before reading.
+    pub fn has_headers(mut self, has_headers: bool) -> Self {
+        if !has_headers {
+            self.headers = Some(positional_headers());
+        }
+        self.rebuild()
+    }
 
-        let transaction = record.into_transaction()?;
-        match transaction {
-            Some(transaction) => {
-                transactions.push(transaction);
+    /// Makes the reader again around its input for the current settings.
+    fn rebuild(mut self) -> Self {
+        // Only sources without headers read the header row; the others were given them.
+        let has_headers = self.headers.is_none();
+        self.rdr = untrimmed_reader_builder(has_headers)
+            .delimiter(self.delimiter)
+            .from_reader(self.rdr.into_inner());
+        self
+    }
+}
+
+impl<R: Read> TransactionSource for CsvSource<R> {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        if self.headers.is_none() {
+            match self.rdr.headers() {
+                Ok(headers) => self.headers = Some(self.kinds.headers(headers)),
+                Err(e) => return Some(Err(e.into())),
             }
-            None => {}
         }
+
+        match self.rdr.read_record(&mut self.record) {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => return Some(Err(e.into())),
+        }
+
+        let line = self.offset.line + self.record.position().map(|p| p.line()).unwrap_or_default();
+        self.line = line;
+
+        // The reader stops at the start of the next record's line.
+        let end = self.rdr.position();
+        self.position = InputPosition {
+            byte: self.offset.byte + end.byte(),
+            line: self.offset.line + end.line() - 1,
+        };
+
+        trim_into(&self.record, &mut self.trimmed);
+        let parsed = match self.trimmed.deserialize(self.headers.as_ref()) {
+            Ok(record) => self.kinds.convert(record, line, self.strict),
+            Err(e) => Err(SourceError::InvalidRecord {
+                line,
+                error: Box::new(ParseCsvError::Csv(e)),
+            }),
+        };
+
+        Some(parsed)
     }
-    Ok(transactions)
+
+    fn line(&self) -> Option<u64> {
+        Some(self.line)
+    }
+
+    fn position(&self) -> Option<InputPosition> {
+        Some(self.position)
+    }
+}
+
+/// Parses a single headerless record, e.g. `deposit, 1, 4, 2.5`.
+pub fn parse_record(line: &str) -> Result<Option<Transaction>, ParseCsvError> {
+    let line = line.trim().replace(' ', "");
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(line.as_bytes());
+
+    // Deserialize against the standard headers so trailing optional columns may be omitted.
+    let headers = positional_headers();
+
+    match rdr.records().next() {
+        Some(record) => {
+            let record: CsvTransaction = record?.deserialize(Some(&headers))?;
+            record.into_transaction()
+        }
+        None => Ok(None),
+    }
+}
+
+/// What happens to records whose type is neither built in nor registered.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum UnknownTypePolicy {
+    /// Skip the record, returning it as `SourceError::Ignored` like any other ignored record.
+    #[default]
+    Skip,
+    /// Skip the record, returning `SourceError::UnknownType` so it can be logged. The source can
+    /// still be read after it.
+    Warn,
+    /// Return the record as `SourceError::InvalidRecord`.
+    Reject,
+}
+
+impl FromStr for UnknownTypePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "warn" => Ok(Self::Warn),
+            "reject" => Ok(Self::Reject),
+            _ => Err(String::from(s)),
+        }
+    }
+}
+
+/// Converts records of a type the engine doesn't know into transactions it applies, so extensions
+/// can accept new upstream types without changing the parsers.
+pub trait TransactionKind {
+    /// The transaction to apply for the record, or `None` to skip it.
+    fn parse(&self, record: &CsvTransaction) -> Result<Option<Transaction>, ParseCsvError>;
+}
+
+pub(crate) const BUILT_IN_TYPES: [&str; 11] = [
+    "deposit",
+    "withdrawal",
+    "hold",
+    "release",
+    "transfer",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "unlock",
+    "convert",
+    "fee",
+];
+
+/// Reads the columns of third-party CSV under the names the engine expects, e.g. `customer_id` as
+/// `client`, so their header rows don't have to be rewritten.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColumnMap {
+    /// Each header and the column it's read as.
+    renames: Vec<(String, String)>,
+}
+
+impl ColumnMap {
+    /// Reads the column headed `header` as `column`, one of the columns of `positional_headers`.
+    pub fn rename(mut self, header: &str, column: &str) -> Result<Self, String> {
+        let header = header.trim();
+        let column = column.trim();
+        if header.is_empty() || !positional_headers().iter().any(|known| known == column) {
+            return Err(format!("{}={}", header, column));
+        }
+        if self
+            .renames
+            .iter()
+            .any(|(from, to)| from == header || to == column)
+        {
+            return Err(format!("{}={} is mapped twice", header, column));
+        }
+        self.renames
+            .push((String::from(header), String::from(column)));
+        Ok(self)
+    }
+
+    /// The header row with every mapped header replaced by its column.
+    pub fn apply(&self, headers: &csv::StringRecord) -> csv::StringRecord {
+        headers
+            .iter()
+            .map(|header| {
+                self.renames
+                    .iter()
+                    .find(|(from, _)| from == header)
+                    .map_or(header, |(_, to)| to.as_str())
+            })
+            .collect()
+    }
+}
+
+impl FromStr for ColumnMap {
+    type Err = String;
+
+    /// Parses comma separated `header=column` pairs, e.g. `customer_id=client,value=amount`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',').try_fold(Self::default(), |map, pair| {
+            let (header, column) = pair.split_once('=').ok_or_else(|| String::from(pair))?;
+            map.rename(header, column)
+        })
+    }
+}
+
+/// The transaction kinds registered by name, what happens to records of any other unknown type,
+/// and the names of the columns they're read from.
+#[derive(Clone, Default)]
+pub struct TransactionKinds {
+    kinds: HashMap<String, Rc<dyn TransactionKind>>,
+    unknown: UnknownTypePolicy,
+    columns: ColumnMap,
+    precision: PrecisionPolicy,
 }
 
-#[derive(serde::Deserialize, Debug)]
+impl TransactionKinds {
+    /// Records whose type is `name` are converted by `kind`. Built in types can't be replaced.
+    pub fn register(mut self, name: &str, kind: Box<dyn TransactionKind>) -> Self {
+        self.kinds.insert(String::from(name), Rc::from(kind));
+        self
+    }
+
+    /// What happens to records of a type that isn't registered.
+    pub fn unknown_types(mut self, policy: UnknownTypePolicy) -> Self {
+        self.unknown = policy;
+        self
+    }
+
+    /// Renames the columns of CSV header rows before records are read against them.
+    pub fn columns(mut self, columns: ColumnMap) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// What happens to amounts of built in types with more decimal places than are kept.
+    pub fn precision(mut self, policy: PrecisionPolicy) -> Self {
+        self.precision = policy;
+        self
+    }
+
+    /// The header row read from the input, as records are read against it.
+    pub fn headers(&self, headers: &csv::StringRecord) -> csv::StringRecord {
+        self.columns.apply(headers)
+    }
+
+    /// Converts a record read from the given line. Ignored records are returned as
+    /// `SourceError::Ignored`, or as invalid records when strict.
+    pub fn convert(
+        &self,
+        record: CsvRecord<'_>,
+        line: u64,
+        strict: bool,
+    ) -> Result<Transaction, SourceError> {
+        let transaction_type = record.transaction_type.trim();
+
+        let parsed = if BUILT_IN_TYPES.contains(&transaction_type) {
+            record.parse_with(self.precision)
+        } else if let Some(kind) = self.kinds.get(transaction_type) {
+            kind.parse(&CsvTransaction::from(record))
+                .and_then(|transaction| {
+                    transaction.ok_or_else(|| ignored("skipped by its transaction kind"))
+                })
+        } else {
+            match self.unknown {
+                UnknownTypePolicy::Skip if !strict => Err(ignored("unknown transaction type")),
+                UnknownTypePolicy::Warn if !strict => {
+                    return Err(SourceError::UnknownType {
+                        line,
+                        transaction_type: String::from(transaction_type),
+                    })
+                }
+                _ => {
+                    return Err(SourceError::InvalidRecord {
+                        line,
+                        error: Box::new(ignored("unknown transaction type")),
+                    })
+                }
+            }
+        };
+
+        parsed.map_err(|error| match error {
+            ParseCsvError::Ignored(IgnoredRecord { reason }) if !strict => {
+                SourceError::Ignored { line, reason }
+            }
+            error => SourceError::InvalidRecord {
+                line,
+                error: Box::new(error),
+            },
+        })
+    }
+}
+
+/// A record that is well formed but is skipped rather than applied, such as a deposit without an
+/// amount or an unknown transaction type.
+#[derive(Debug, PartialEq)]
+pub struct IgnoredRecord {
+    pub reason: &'static str,
+}
+
+impl std::fmt::Display for IgnoredRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ignored record: {}", self.reason)
+    }
+}
+
+impl Error for IgnoredRecord {}
+
+fn ignored(reason: &'static str) -> ParseCsvError {
+    ParseCsvError::Ignored(IgnoredRecord { reason })
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
 pub struct CsvTransaction {
     #[serde(rename = "type")]
     pub transaction_type: String,
     pub client: String,
     pub tx: TransactionId,
     pub amount: Option<String>,
+    #[serde(default)]
+    pub reason: Option<ReasonCode>,
+    /// The client a transfer is sent to.
+    #[serde(default)]
+    pub to: Option<ClientId>,
+    /// The dispute management case a dispute or chargeback belongs to.
+    #[serde(default)]
+    pub case: Option<CaseId>,
+    /// When the transaction happened upstream, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub timestamp: Option<Timestamp>,
+    /// The three letter code of the currency the amount is in.
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    /// The currency a conversion is made into.
+    #[serde(default)]
+    pub to_currency: Option<Currency>,
+    /// The amount a conversion credits, if it isn't to be priced from the loaded rates.
+    #[serde(default)]
+    pub converted: Option<String>,
 }
 
 impl CsvTransaction {
-    pub fn into_transaction(&self) -> Result<Option<Transaction>, Box<dyn Error>> {
-        let amount = self.amount.clone().unwrap_or("".to_string());
+    /// Creates the CSV record that parses back into the given transaction.
+    pub fn from_transaction(transaction: &Transaction) -> Self {
+        let (to_currency, converted) = match transaction.transaction_type {
+            TransactionType::Convert { to, converted, .. } => (Some(to), converted),
+            _ => (None, None),
+        };
+        let (transaction_type, reason, to, case) = match transaction.transaction_type {
+            TransactionType::Deposit(_) => ("deposit", None, None, None),
+            TransactionType::Withdrawal(_) => ("withdrawal", None, None, None),
+            TransactionType::Dispute { case } => ("dispute", None, None, case),
+            TransactionType::Resolve => ("resolve", None, None, None),
+            TransactionType::Chargeback { case } => ("chargeback", None, None, case),
+            TransactionType::Hold { reason, .. } => ("hold", Some(reason), None, None),
+            TransactionType::Release { reason, .. } => ("release", Some(reason), None, None),
+            TransactionType::Transfer { to, .. } => ("transfer", None, Some(to), None),
+            TransactionType::Unlock => ("unlock", None, None, None),
+            TransactionType::Convert { .. } => ("convert", None, None, None),
+            TransactionType::Fee { .. } => ("fee", None, None, None),
+        };
+
+        Self {
+            transaction_type: transaction_type.to_string(),
+            client: transaction.client.to_string(),
+            tx: transaction.id,
+            amount: transaction.amount().map(|amount| amount.to_string()),
+            reason,
+            to,
+            case,
+            timestamp: transaction.timestamp,
+            currency: transaction.currency,
+            to_currency,
+            converted: converted.map(|converted| converted.to_string()),
+        }
+    }
+
+    /// The record borrowing this one's fields.
+    pub fn as_record(&self) -> CsvRecord<'_> {
+        CsvRecord {
+            transaction_type: &self.transaction_type,
+            client: &self.client,
+            tx: self.tx,
+            amount: self.amount.as_deref(),
+            reason: self.reason,
+            to: self.to,
+            case: self.case,
+            timestamp: self.timestamp,
+            currency: self.currency,
+            to_currency: self.to_currency,
+            converted: self.converted.as_deref(),
+        }
+    }
+
+    /// Converts the record, skipping records that are ignored.
+    pub fn into_transaction(self) -> Result<Option<Transaction>, ParseCsvError> {
+        self.as_record().into_transaction()
+    }
+
+    /// Converts the record, returning an `IgnoredRecord` error for records that would be skipped.
+    pub fn parse(self) -> Result<Transaction, ParseCsvError> {
+        self.as_record().parse()
+    }
+}
+
+impl From<CsvRecord<'_>> for CsvTransaction {
+    fn from(record: CsvRecord<'_>) -> Self {
+        Self {
+            transaction_type: String::from(record.transaction_type),
+            client: String::from(record.client),
+            tx: record.tx,
+            amount: record.amount.map(String::from),
+            reason: record.reason,
+            to: record.to,
+            case: record.case,
+            timestamp: record.timestamp,
+            currency: record.currency,
+            to_currency: record.to_currency,
+            converted: record.converted.map(String::from),
+        }
+    }
+}
+
+/// A `CsvTransaction` borrowing its text fields from the row it was read from, so reading a row
+/// doesn't allocate.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CsvRecord<'a> {
+    #[serde(rename = "type")]
+    pub transaction_type: &'a str,
+    pub client: &'a str,
+    pub tx: TransactionId,
+    pub amount: Option<&'a str>,
+    #[serde(default)]
+    pub reason: Option<ReasonCode>,
+    #[serde(default)]
+    pub to: Option<ClientId>,
+    #[serde(default)]
+    pub case: Option<CaseId>,
+    #[serde(default)]
+    pub timestamp: Option<Timestamp>,
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    #[serde(default)]
+    pub to_currency: Option<Currency>,
+    #[serde(default)]
+    pub converted: Option<&'a str>,
+}
 
-        let amount_empty = amount.trim() == "";
+impl CsvRecord<'_> {
+    /// Converts the record, skipping records that are ignored.
+    pub fn into_transaction(self) -> Result<Option<Transaction>, ParseCsvError> {
+        match self.parse() {
+            Ok(transaction) => Ok(Some(transaction)),
+            Err(ParseCsvError::Ignored(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
 
-        let transaction_type = match self.transaction_type.trim() {
+    /// Converts the record, returning an `IgnoredRecord` error for records that would be skipped.
+    pub fn parse(self) -> Result<Transaction, ParseCsvError> {
+        self.parse_with(PrecisionPolicy::default())
+    }
+
+    /// Converts the record as `parse`, applying the policy to amounts with more decimal places
+    /// than are kept.
+    pub fn parse_with(self, precision: PrecisionPolicy) -> Result<Transaction, ParseCsvError> {
+        let amount = self.amount.unwrap_or_default().trim();
+        let amount_empty = amount.is_empty();
+        let transaction_type = self.transaction_type.trim();
+
+        let transaction_type = match transaction_type {
             "deposit" => {
-                // TODO: With more time, implement an actual parse error here? For now fail gracefully by ignoring.
                 if amount_empty {
-                    return Ok(None);
+                    return Err(ignored("deposit without an amount"));
                 }
 
-                let amount = Amount::from_str(&amount)?;
+                let amount = Amount::parse(amount, precision)?;
                 TransactionType::Deposit(amount)
             }
             "withdrawal" => {
-                // TODO: With more time, implement an actual parse error here? For now fail gracefully by ignoring.
                 if amount_empty {
-                    return Ok(None);
+                    return Err(ignored("withdrawal without an amount"));
                 }
 
-                let amount = Amount::from_str(&amount)?;
+                let amount = Amount::parse(amount, precision)?;
                 TransactionType::Withdrawal(amount)
             }
-            "dispute" => TransactionType::Dispute,
-            "resolve" => TransactionType::Resolve,
-            "chargeback" => TransactionType::Chargeback,
-            _ => {
-                // TODO: With more time, implement an actual parse error here?
-                return Ok(None);
+            "hold" | "release" => {
+                let reason = match self.reason {
+                    Some(reason) => reason,
+                    None => return Err(ignored("hold or release without a reason")),
+                };
+
+                if amount_empty {
+                    return Err(ignored("hold or release without an amount"));
+                }
+
+                let amount = Amount::parse(amount, precision)?;
+                if transaction_type == "hold" {
+                    TransactionType::Hold { amount, reason }
+                } else {
+                    TransactionType::Release { amount, reason }
+                }
+            }
+            "transfer" => {
+                let to = match self.to {
+                    Some(to) => to,
+                    None => return Err(ignored("transfer without a recipient")),
+                };
+
+                if amount_empty {
+                    return Err(ignored("transfer without an amount"));
+                }
+
+                let amount = Amount::parse(amount, precision)?;
+                TransactionType::Transfer { to, amount }
+            }
+            "convert" => {
+                let to = match self.to_currency {
+                    Some(to) => to,
+                    None => return Err(ignored("conversion without a currency to convert into")),
+                };
+
+                if amount_empty {
+                    return Err(ignored("conversion without an amount"));
+                }
+
+                let amount = Amount::parse(amount, precision)?;
+                let converted = match self.converted.map(str::trim) {
+                    Some(converted) if !converted.is_empty() => {
+                        Some(Amount::parse(converted, precision)?)
+                    }
+                    _ => None,
+                };
+                TransactionType::Convert {
+                    amount,
+                    to,
+                    converted,
+                }
             }
+            "fee" => {
+                if amount_empty {
+                    return Err(ignored("fee without an amount"));
+                }
+
+                let amount = Amount::parse(amount, precision)?;
+                TransactionType::Fee { amount }
+            }
+            "dispute" => TransactionType::Dispute { case: self.case },
+            "resolve" => TransactionType::Resolve,
+            "chargeback" => TransactionType::Chargeback { case: self.case },
+            "unlock" => TransactionType::Unlock,
+            _ => return Err(ignored("unknown transaction type")),
         };
 
         let client_id = self.client.parse::<ClientId>()?;
 
-        Ok(Some(Transaction {
+        Ok(Transaction {
             transaction_type,
             client: client_id,
             id: self.tx,
-        }))
+            timestamp: self.timestamp,
+            currency: self.currency,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocations;
+    use crate::amount::DECIMAL_PLACES;
+    use crate::fixtures::{amount, places};
+
+    #[test]
+    fn read_returns_cause_and_line_of_invalid_record() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 2.0\ndeposit, 70000, 2, 1.0\n";
+
+        match read(input.as_bytes()) {
+            Err(ParseCsvError::Record { line, error }) => {
+                assert_eq!(3, line);
+                assert_eq!(true, matches!(*error, ParseCsvError::ClientId(_)));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn csv_source_reads_records_without_allocating() {
+        let mut input = String::from("type, client, tx, amount\n");
+        // Ids of the same width, so the buffers never need to grow.
+        for tx in 1000..3000 {
+            input.push_str(&format!("deposit, {}, {}, {}.25\n", tx % 7, tx, tx % 100));
+        }
+        let mut source = CsvSource::new(input.as_bytes());
+        for _ in 0..1000 {
+            source.next().unwrap().unwrap();
+        }
+
+        let (_, allocations) = allocations::counting(|| {
+            for _ in 0..1000 {
+                source.next().unwrap().unwrap();
+            }
+        });
+
+        assert_eq!(0, allocations);
+    }
+
+    #[test]
+    fn csv_source_delimiter_reads_tsv_and_semicolons() {
+        let expected = read("type, client, tx, amount\ndeposit, 1, 1, 1.5\n".as_bytes()).unwrap();
+
+        for (input, delimiter) in [
+            ("type\tclient\ttx\tamount\ndeposit\t1\t1\t1.5\n", b'\t'),
+            ("type; client; tx; amount\ndeposit; 1; 1; 1.5\n", b';'),
+        ] {
+            let mut source = CsvSource::new(input.as_bytes()).delimiter(delimiter);
+            assert_eq!(Some(expected[0]), source.next().map(Result::unwrap));
+            assert_eq!(true, source.next().is_none());
+        }
+    }
+
+    #[test]
+    fn csv_source_has_headers_false_reads_first_row_as_record() {
+        let expected = read(
+            "type, client, tx, amount\ndeposit, 1, 1, 1.5\nwithdrawal, 1, 2, 0.5\n".as_bytes(),
+        )
+        .unwrap();
+
+        let input = "deposit; 1; 1; 1.5\nwithdrawal; 1; 2; 0.5\n";
+        let mut source = CsvSource::new(input.as_bytes())
+            .has_headers(false)
+            .delimiter(b';');
+        assert_eq!(Some(expected[0]), source.next().map(Result::unwrap));
+        assert_eq!(Some(1), source.line());
+        assert_eq!(Some(expected[1]), source.next().map(Result::unwrap));
+        assert_eq!(Some(2), source.line());
+        assert_eq!(true, source.next().is_none());
+    }
+
+    #[test]
+    fn csv_source_column_map_reads_renamed_headers() {
+        let expected = read("type, client, tx, amount\ndeposit, 1, 1, 1.5\n".as_bytes()).unwrap();
+
+        let columns: ColumnMap = "transaction_type=type,customer_id=client,txn=tx,value=amount"
+            .parse()
+            .unwrap();
+        let input = "transaction_type, customer_id, txn, value\ndeposit, 1, 1, 1.5\n";
+        let mut source =
+            CsvSource::new(input.as_bytes()).kinds(TransactionKinds::default().columns(columns));
+        assert_eq!(Some(expected[0]), source.next().map(Result::unwrap));
+        assert_eq!(true, source.next().is_none());
+    }
+
+    #[test]
+    fn csv_source_precision_error_rejects_excess_decimal_places() {
+        let excess = format!("1.{}", "2".repeat(DECIMAL_PLACES as usize + 1));
+        let input = format!(
+            "type, client, tx, amount\ndeposit, 1, 1, 1.5\ndeposit, 1, 2, {}\n",
+            excess
+        );
+        let mut source = CsvSource::new(input.as_bytes())
+            .kinds(TransactionKinds::default().precision(PrecisionPolicy::Error));
+
+        assert_eq!(true, source.next().unwrap().is_ok());
+        match source.next() {
+            Some(Err(SourceError::InvalidRecord { line, error })) => {
+                assert_eq!(3, line);
+                let expected =
+                    format!("{} has more than {} decimal places", excess, DECIMAL_PLACES);
+                assert_eq!(expected, error.to_string());
+            }
+            _ => panic!("expected an invalid record"),
+        }
+    }
+
+    #[test]
+    fn column_map_from_str_rejects_unknown_and_repeated_columns() {
+        assert_eq!(true, "value=amount".parse::<ColumnMap>().is_ok());
+        assert_eq!(true, "value=total".parse::<ColumnMap>().is_err());
+        assert_eq!(true, "value".parse::<ColumnMap>().is_err());
+        assert_eq!(true, "=amount".parse::<ColumnMap>().is_err());
+        assert_eq!(
+            true,
+            "value=amount,total=amount".parse::<ColumnMap>().is_err()
+        );
+    }
+
+    #[test]
+    fn read_keeps_optional_timestamp() {
+        let input = "type, client, tx, amount, timestamp\ndeposit, 1, 1, 1.5, 1700000000\ndeposit, 1, 2, 1.5,\n";
+        let timestamps: Vec<Option<Timestamp>> = read(input.as_bytes())
+            .unwrap()
+            .iter()
+            .map(|transaction| transaction.timestamp)
+            .collect();
+        assert_eq!(vec![Some(1_700_000_000), None], timestamps);
+    }
+
+    #[test]
+    fn read_keeps_optional_currency() {
+        let input =
+            "type, client, tx, amount, currency\ndeposit, 1, 1, 1.5, eur\ndeposit, 1, 2, 1.5,\n";
+        let currencies: Vec<Option<Currency>> = read(input.as_bytes())
+            .unwrap()
+            .iter()
+            .map(|transaction| transaction.currency)
+            .collect();
+        assert_eq!(vec![Currency::from_str("EUR").ok(), None], currencies);
+
+        let input = "type, client, tx, amount, currency\ndeposit, 1, 1, 1.5, euro\n";
+        assert_eq!(true, read(input.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn read_convert_returns_expected() {
+        let input = "type, client, tx, amount, currency, to_currency, converted\n\
+            convert, 1, 1, 1.5, EUR, usd,\n\
+            convert, 1, 2, 1.5, EUR, USD, 1.62\n\
+            convert, 1, 3, 1.5, EUR, ,\n";
+        let usd = Currency::from_str("USD").unwrap();
+        let types: Vec<TransactionType> = read(input.as_bytes())
+            .unwrap()
+            .iter()
+            .map(|transaction| transaction.transaction_type)
+            .collect();
+
+        // A conversion without a currency to convert into is ignored.
+        assert_eq!(
+            vec![
+                TransactionType::Convert {
+                    amount: amount("1.5"),
+                    to: usd,
+                    converted: None,
+                },
+                TransactionType::Convert {
+                    amount: amount("1.5"),
+                    to: usd,
+                    converted: Some(amount("1.62")),
+                },
+            ],
+            types
+        );
+
+        let record = CsvTransaction::from_transaction(&read(input.as_bytes()).unwrap()[1]);
+        assert_eq!(Some(usd), record.to_currency);
+        assert_eq!(Some(places("1.6200")), record.converted);
+    }
+
+    #[test]
+    fn parse_record_returns_typed_errors() {
+        assert_eq!(
+            true,
+            matches!(
+                parse_record("deposit, 1, 1, 2.x"),
+                Err(ParseCsvError::Amount(_))
+            )
+        );
+        assert_eq!(
+            true,
+            matches!(
+                parse_record("deposit, 1, x, 2.0"),
+                Err(ParseCsvError::Csv(_))
+            )
+        );
+        assert_eq!(true, matches!(parse_record("deposit, 1, 1,"), Ok(None)));
     }
 }
@@ -1,85 +1,3645 @@
-use std::path::Path;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use payments::{
+    amount::{Amount, PrecisionPolicy, RoundingMode},
+    audit::AuditFormat,
+    backtest::Policy,
+    client::ClientId,
+    compression,
+    database::DuplicatePolicy,
+    ledger::LedgerFormat,
+    parse_csv::{ColumnMap, UnknownTypePolicy},
+    pipeline::{ApprovalConfig, InputFormat, MaximumPolicy},
+    random::Randomness,
+    report::{AmountFormat, Rounding},
+    retention::{Retention, SnapshotSchedule},
+    risk::{RiskConfig, RiskPolicy},
+    run::{AuditLogConfig, CheckpointConfig, ClientMapConfig, LedgerConfig, RunConfig},
+    sample::SampleRate,
+    scheduler::DEFAULT_MAX_INFLIGHT_PER_CLIENT,
+    server::{ServeConfig, WireProtocol},
+    shard::Partitioning,
+    sink::ReportFormat,
+    snapshot::SnapshotFormat,
+    stress::StressConfig,
+    throttle::RateLimit,
+    transaction::TransactionId,
+    velocity::VelocityPolicy,
+};
 
 #[derive(PartialEq, Debug)]
 pub enum EnvArgsParseError {
     ArgumentsTooShort,
-    ExpectedCsvFile { passed: String },
+    ExpectedCsvFile {
+        passed: String,
+    },
+    UnknownArgument {
+        passed: String,
+    },
+    MissingValue {
+        flag: String,
+    },
+    InvalidValue {
+        flag: String,
+        passed: String,
+    },
+    MissingArgument {
+        flag: String,
+    },
+    /// Two flags that can't be used together were both passed.
+    ConflictingArguments {
+        flag: String,
+        other: String,
+    },
+    FileNotFound {
+        passed: String,
+    },
+    /// The file exists but this user isn't allowed to read it.
+    PermissionDenied {
+        passed: String,
+    },
+    /// The path names a directory or something else that isn't a regular file.
+    NotAFile {
+        passed: String,
+    },
+    /// The file couldn't be opened for another reason.
+    UnreadableFile {
+        passed: String,
+        kind: io::ErrorKind,
+    },
+}
+
+/// The operation requested on the command line.
+#[derive(PartialEq, Debug)]
+pub enum Command {
+    /// Process a transaction file and output the resulting accounts.
+    Process(Box<RunConfig>),
+    /// Inspect or decide on transactions parked in an approval queue.
+    Approvals {
+        queue_path: String,
+        action: ApprovalAction,
+    },
+    /// Listen for framed transaction batches over TCP, keeping accounts in `storage` if given.
+    Serve(Box<ServeConfig>),
+    /// Export everything held about a client in a snapshot as JSON.
+    ExportClient {
+        snapshot_path: String,
+        snapshot_format: SnapshotFormat,
+        client: ClientId,
+        redact_amounts: bool,
+        output: Option<String>,
+        /// Read the snapshot even if an incompatible engine version wrote it.
+        force_migrate: bool,
+    },
+    /// Erase the transaction history of a closed client in a snapshot.
+    EraseClient {
+        snapshot_path: String,
+        snapshot_format: SnapshotFormat,
+        client: ClientId,
+        audit_log: Option<String>,
+        /// Read the snapshot even if an incompatible engine version wrote it.
+        force_migrate: bool,
+    },
+    /// Serve the read-only HTTP query API over a snapshot.
+    ServeSnapshot {
+        snapshot_path: String,
+        snapshot_format: SnapshotFormat,
+        http_addr: String,
+        /// Read the snapshot even if an incompatible engine version wrote it.
+        force_migrate: bool,
+    },
+    /// Combine the snapshots of two runs over transactions with other ids into one report.
+    Merge {
+        first: String,
+        second: String,
+        snapshot_format: SnapshotFormat,
+        /// Where the combined state is saved, if it is.
+        snapshot_out: Option<String>,
+        output: Option<String>,
+        /// Read the snapshots even if an incompatible engine version wrote them.
+        force_migrate: bool,
+    },
+    /// Print how each client's balances differ between two reports or snapshots.
+    Diff {
+        before: String,
+        after: String,
+        /// The format of either side that isn't a CSV report.
+        snapshot_format: SnapshotFormat,
+        /// Read snapshots even if an incompatible engine version wrote them.
+        force_migrate: bool,
+    },
+    /// Print a single client's balances after applying a transaction file, a snapshot, or a
+    /// transaction file on top of a snapshot.
+    Query {
+        client: ClientId,
+        file_path: Option<String>,
+        snapshot_path: Option<String>,
+        snapshot_format: SnapshotFormat,
+        /// Read the snapshot even if an incompatible engine version wrote it.
+        force_migrate: bool,
+        /// Print the client's transaction history, with the state of each transaction, instead of
+        /// its balances.
+        history: bool,
+    },
+    /// Apply transactions typed one at a time, starting from the snapshot if given.
+    Repl {
+        snapshot_path: Option<String>,
+        snapshot_format: SnapshotFormat,
+        /// Read the snapshot even if an incompatible engine version wrote it.
+        force_migrate: bool,
+    },
+    /// Replay a transaction file under several policies and compare the outcomes.
+    Backtest {
+        file_path: String,
+        input_format: Option<InputFormat>,
+        policies: Vec<Policy>,
+    },
+    /// Run the conformance suite against the reference accounts, using the bundled suite unless a
+    /// path is given.
+    Conformance { suite_path: Option<String> },
+    /// Check every record of a CSV file without applying anything.
+    Validate { file_path: String },
+    /// Apply generated load in-process, reporting throughput and checking the accounts add up.
+    Stress(StressConfig),
+}
+
+/// How many seconds apart snapshots are taken unless configured otherwise.
+const DEFAULT_SNAPSHOT_INTERVAL: u64 = 60 * 60;
+
+/// How many transactions are read between checkpoints unless `--checkpoint-every` is passed.
+const DEFAULT_CHECKPOINT_EVERY: u64 = 100_000;
+
+#[derive(PartialEq, Debug)]
+pub enum ApprovalAction {
+    List,
+    Approve(TransactionId),
+    Reject(TransactionId),
+}
+
+/// Takes the flags that apply to every command out of the arguments: `--seed <n>` seeds every
+/// random number the run draws, and `--deterministic` seeds them from a fixed seed when none is
+/// given.
+pub fn env_args_parse_randomness(
+    args: Vec<String>,
+) -> Result<(Randomness, Vec<String>), EnvArgsParseError> {
+    let mut randomness = Randomness::default();
+    let mut rest = vec![];
+
+    let mut flags = args.iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--seed" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse() {
+                    Ok(seed) => randomness.seed = Some(seed),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--deterministic" => randomness.deterministic = true,
+            _ => rest.push(flag.clone()),
+        }
+    }
+
+    Ok((randomness, rest))
+}
+
+pub fn env_args_parse(args: Vec<String>) -> Result<Command, EnvArgsParseError> {
+    const COMMAND_ARG: usize = 1;
+    if args.len() > COMMAND_ARG && args[COMMAND_ARG] == "approvals" {
+        return parse_approvals(&args[COMMAND_ARG + 1..]);
+    }
+    if args.len() > COMMAND_ARG && args[COMMAND_ARG] == "serve" {
+        return parse_serve(&args[COMMAND_ARG + 1..]);
+    }
+    if args.len() > COMMAND_ARG && args[COMMAND_ARG] == "export-client" {
+        return parse_export_client(&args[COMMAND_ARG + 1..]);
+    }
+    if args.len() > COMMAND_ARG && args[COMMAND_ARG] == "erase-client" {
+        return parse_erase_client(&args[COMMAND_ARG + 1..]);
+    }
+    if args.len() > COMMAND_ARG && args[COMMAND_ARG] == "serve-snapshot" {
+        return parse_serve_snapshot(&args[COMMAND_ARG + 1..]);
+    }
+    if args.len() > COMMAND_ARG && args[COMMAND_ARG] == "merge" {
+        return parse_merge(&args[COMMAND_ARG + 1..]);
+    }
+    if args.len() > COMMAND_ARG && args[COMMAND_ARG] == "diff" {
+        return parse_diff(&args[COMMAND_ARG + 1..]);
+    }
+    if args.len() > COMMAND_ARG && args[COMMAND_ARG] == "query" {
+        return parse_query(&args[COMMAND_ARG + 1..]);
+    }
+    if args.len() > COMMAND_ARG && args[COMMAND_ARG] == "repl" {
+        return parse_repl(&args[COMMAND_ARG + 1..]);
+    }
+    if args.len() > COMMAND_ARG && args[COMMAND_ARG] == "conformance" {
+        return parse_conformance(&args[COMMAND_ARG + 1..]);
+    }
+    if args.len() > COMMAND_ARG && args[COMMAND_ARG] == "validate" {
+        return parse_validate(&args[COMMAND_ARG + 1..]);
+    }
+    if args.len() > COMMAND_ARG && args[COMMAND_ARG] == "stress" {
+        return parse_stress(&args[COMMAND_ARG + 1..]);
+    }
+    if args.len() > COMMAND_ARG && args[COMMAND_ARG] == "backtest" {
+        return parse_backtest(&args[COMMAND_ARG + 1..]);
+    }
+
+    let file_path = env_args_parse_file(args.clone())?;
+
+    let mut threshold = None;
+    let mut queue_path = None;
+    let mut snapshot_in = None;
+    let mut snapshot_out = None;
+    let mut snapshot_format = SnapshotFormat::default();
+    let mut columns = None;
+    let mut extended = false;
+    let mut coalesce = false;
+    let mut output = None;
+    let mut format = ReportFormat::default();
+    let mut input_format = None;
+    let mut delimiter = None;
+    let mut no_headers = false;
+    let mut column_map = None;
+    let mut error_log = None;
+    let mut strict = false;
+    let mut duplicate_policy = DuplicatePolicy::default();
+    let mut soft_limit = None;
+    let mut maximum_amount = None;
+    let mut maximum_policy = MaximumPolicy::default();
+    let mut manifest = None;
+    let mut client_map = None;
+    let mut storage = None;
+    let mut sample = None;
+    let mut checkpoint_path = None;
+    let mut checkpoint_every = None;
+    let mut resume = false;
+    let mut unknown_types = UnknownTypePolicy::default();
+    let mut excess_precision = None;
+    let mut rounding_mode = RoundingMode::default();
+    let mut rates = None;
+    let mut fees = None;
+    let mut overdraft = None;
+    let mut overdraft_limits = None;
+    let mut minimum_balance = None;
+    let mut velocity_limits = None;
+    let mut velocity_policy = VelocityPolicy::default();
+    let mut risk_threshold = None;
+    let mut risk_policy = RiskPolicy::default();
+    let mut risk_large_amount = None;
+    let mut ledger_path = None;
+    let mut ledger_format = None;
+    let mut audit_log = None;
+    let mut audit_format = None;
+    let mut double_entry = false;
+    let mut verify = false;
+    let mut chart = None;
+    let mut shards = 1;
+    let mut partitioner = Partitioning::default();
+    let mut round_to = None;
+    let mut amount_format = AmountFormat::default();
+    let mut bucket = None;
+    let mut suppress_below = None;
+    let mut force_migrate = false;
+    let mut stamp = false;
+    let mut pending_disputes = None;
+    let mut reconciliation = None;
+    let mut dispute_grace = None;
+    let mut reorder_window = None;
+    let mut max_rate = None;
+    let mut burst = None;
+    let mut follow = false;
+
+    let mut flags = args.iter().skip(2);
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--approval-threshold" => {
+                let value = flag_value(flag, flags.next())?;
+                match Amount::from_str(value) {
+                    Ok(amount) => threshold = Some(amount),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--soft-limit" => {
+                let value = flag_value(flag, flags.next())?;
+                match Amount::from_str(value) {
+                    Ok(amount) => soft_limit = Some(amount),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--max-amount" => maximum_amount = Some(parse_step(flag, flags.next())?),
+            "--max-amount-policy" => {
+                let value = flag_value(flag, flags.next())?;
+                match MaximumPolicy::from_str(value) {
+                    Ok(policy) => maximum_policy = policy,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--approval-queue" => {
+                queue_path = Some(flag_value(flag, flags.next())?.clone());
+            }
+            "--snapshot-in" => {
+                snapshot_in = Some(flag_value(flag, flags.next())?.clone());
+            }
+            "--snapshot-out" => {
+                snapshot_out = Some(flag_value(flag, flags.next())?.clone());
+            }
+            "--columns" => {
+                let value = flag_value(flag, flags.next())?;
+                columns = Some(value.split(',').map(|c| c.trim().to_string()).collect());
+            }
+            "--extended" => extended = true,
+            "--coalesce" => coalesce = true,
+            "--follow" => follow = true,
+            "--force-migrate" => force_migrate = true,
+            "--no-headers" => no_headers = true,
+            "--stamp" => stamp = true,
+            "--strict" => strict = true,
+            "--duplicate-ids" => {
+                let value = flag_value(flag, flags.next())?;
+                match DuplicatePolicy::from_str(value) {
+                    Ok(value) => duplicate_policy = value,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--output" => {
+                output = Some(flag_value(flag, flags.next())?.clone());
+            }
+            "--error-log" => {
+                error_log = Some(flag_value(flag, flags.next())?.clone());
+            }
+            "--dispute-grace" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse() {
+                    Ok(records) if records > 0 => dispute_grace = Some(records),
+                    _ => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--reorder-window" => reorder_window = Some(parse_positive(flag, flags.next())?),
+            "--max-rate" => max_rate = Some(parse_positive(flag, flags.next())?),
+            "--burst" => burst = Some(parse_positive(flag, flags.next())?),
+            "--pending-disputes" => {
+                pending_disputes = Some(flag_value(flag, flags.next())?.clone());
+            }
+            "--reconciliation" => {
+                reconciliation = Some(flag_value(flag, flags.next())?.clone());
+            }
+            "--rates" => {
+                rates = Some(flag_value(flag, flags.next())?.clone());
+            }
+            "--fees" => {
+                fees = Some(flag_value(flag, flags.next())?.clone());
+            }
+            "--overdraft" => {
+                let value = flag_value(flag, flags.next())?;
+                match Amount::from_str(value) {
+                    Ok(limit) if !limit.less_than_zero() => overdraft = Some(limit),
+                    _ => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--overdraft-limits" => {
+                overdraft_limits = Some(flag_value(flag, flags.next())?.clone());
+            }
+            "--minimum-balance" => {
+                let value = flag_value(flag, flags.next())?;
+                match Amount::from_str(value) {
+                    Ok(minimum) if !minimum.less_than_zero() => minimum_balance = Some(minimum),
+                    _ => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--velocity-limits" => {
+                velocity_limits = Some(flag_value(flag, flags.next())?.clone());
+            }
+            "--velocity-policy" => {
+                let value = flag_value(flag, flags.next())?;
+                match VelocityPolicy::from_str(value) {
+                    Ok(policy) => velocity_policy = policy,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--risk-threshold" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse() {
+                    Ok(threshold) if threshold <= 100 => risk_threshold = Some(threshold),
+                    _ => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--risk-policy" => {
+                let value = flag_value(flag, flags.next())?;
+                match RiskPolicy::from_str(value) {
+                    Ok(policy) => risk_policy = policy,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--risk-large-amount" => risk_large_amount = Some(parse_step(flag, flags.next())?),
+            "--manifest" => {
+                manifest = Some(flag_value(flag, flags.next())?.clone());
+            }
+            "--storage" => {
+                storage = Some(flag_value(flag, flags.next())?.clone());
+            }
+            "--checkpoint" => {
+                checkpoint_path = Some(flag_value(flag, flags.next())?.clone());
+            }
+            "--checkpoint-every" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse() {
+                    Ok(every) if every > 0 => checkpoint_every = Some(every),
+                    _ => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--resume" => resume = true,
+            "--ledger" => ledger_path = Some(flag_value(flag, flags.next())?.clone()),
+            "--ledger-format" => {
+                let value = flag_value(flag, flags.next())?;
+                match LedgerFormat::from_str(value) {
+                    Ok(format) => ledger_format = Some(format),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--audit-log" => audit_log = Some(flag_value(flag, flags.next())?.clone()),
+            "--audit-format" => {
+                let value = flag_value(flag, flags.next())?;
+                match AuditFormat::from_str(value) {
+                    Ok(format) => audit_format = Some(format),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--double-entry" => double_entry = true,
+            "--verify" => verify = true,
+            "--chart" => chart = Some(flag_value(flag, flags.next())?.clone()),
+            "--shards" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse() {
+                    Ok(count) if count > 0 => shards = count,
+                    _ => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--partitioner" => {
+                let value = flag_value(flag, flags.next())?;
+                match Partitioning::from_str(value) {
+                    Ok(value) => partitioner = value,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--round-to" => round_to = Some(parse_step(flag, flags.next())?),
+            "--bucket" => bucket = Some(parse_step(flag, flags.next())?),
+            "--amount-format" => {
+                let value = flag_value(flag, flags.next())?;
+                match AmountFormat::from_str(value) {
+                    Ok(format) => amount_format = format,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--suppress-below" => {
+                let value = flag_value(flag, flags.next())?;
+                match Amount::from_str(value) {
+                    Ok(amount) => suppress_below = Some(amount),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--unknown-types" => {
+                let value = flag_value(flag, flags.next())?;
+                match UnknownTypePolicy::from_str(value) {
+                    Ok(policy) => unknown_types = policy,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--excess-precision" => {
+                let value = flag_value(flag, flags.next())?;
+                match PrecisionPolicy::from_str(value) {
+                    Ok(policy) => excess_precision = Some(policy),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--rounding-mode" => {
+                let value = flag_value(flag, flags.next())?;
+                match RoundingMode::from_str(value) {
+                    Ok(mode) => rounding_mode = mode,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--sample" => {
+                let value = flag_value(flag, flags.next())?;
+                match SampleRate::from_str(value) {
+                    Ok(rate) => sample = Some(rate),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--client-map" => {
+                let value = flag_value(flag, flags.next())?;
+                client_map = Some(ClientMapConfig::File(value.clone()));
+            }
+            "--client-key" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse() {
+                    Ok(key) => client_map = Some(ClientMapConfig::Key(key)),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--input-format" => {
+                let value = flag_value(flag, flags.next())?;
+                match InputFormat::from_str(value) {
+                    Ok(value) => input_format = Some(value),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--column-map" => {
+                let value = flag_value(flag, flags.next())?;
+                match ColumnMap::from_str(value) {
+                    Ok(value) => column_map = Some(value),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--delimiter" => {
+                let value = flag_value(flag, flags.next())?;
+                match parse_delimiter(value) {
+                    Some(value) => delimiter = Some(value),
+                    None => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--format" => {
+                let value = flag_value(flag, flags.next())?;
+                match ReportFormat::from_str(value) {
+                    Ok(value) => format = value,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--snapshot-format" => {
+                let value = flag_value(flag, flags.next())?;
+                match SnapshotFormat::from_str(value) {
+                    Ok(format) => snapshot_format = format,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            _ => {
+                return Err(EnvArgsParseError::UnknownArgument {
+                    passed: flag.clone(),
+                })
+            }
+        }
+    }
+
+    let approval = match (threshold, queue_path) {
+        (Some(threshold), Some(queue_path)) => Some(ApprovalConfig {
+            threshold,
+            queue_path,
+        }),
+        (Some(_), None) => {
+            return Err(EnvArgsParseError::MissingArgument {
+                flag: String::from("--approval-queue"),
+            })
+        }
+        (None, Some(_)) => {
+            return Err(EnvArgsParseError::MissingArgument {
+                flag: String::from("--approval-threshold"),
+            })
+        }
+        (None, None) => None,
+    };
+
+    let checkpoint = match checkpoint_path {
+        Some(path) => Some(CheckpointConfig {
+            path,
+            every: checkpoint_every.unwrap_or(DEFAULT_CHECKPOINT_EVERY),
+            resume,
+        }),
+        None if resume || checkpoint_every.is_some() => {
+            return Err(EnvArgsParseError::MissingArgument {
+                flag: String::from("--checkpoint"),
+            })
+        }
+        None => None,
+    };
+
+    let ledger = match ledger_path {
+        Some(path) => Some(LedgerConfig {
+            path,
+            format: ledger_format.unwrap_or_default(),
+            chart,
+        }),
+        None if ledger_format.is_some() || chart.is_some() => {
+            return Err(EnvArgsParseError::MissingArgument {
+                flag: String::from("--ledger"),
+            })
+        }
+        None => None,
+    };
+
+    let audit_log = match audit_log {
+        Some(path) => Some(AuditLogConfig {
+            path,
+            format: audit_format.unwrap_or_default(),
+        }),
+        None if audit_format.is_some() => {
+            return Err(EnvArgsParseError::MissingArgument {
+                flag: String::from("--audit-log"),
+            })
+        }
+        None => None,
+    };
+
+    let risk = match risk_threshold {
+        Some(threshold) => {
+            let config = RiskConfig::new(threshold);
+            Some(RiskConfig {
+                policy: risk_policy,
+                large_amount: risk_large_amount.unwrap_or(config.large_amount),
+                ..config
+            })
+        }
+        None if risk_policy != RiskPolicy::default() || risk_large_amount.is_some() => {
+            return Err(EnvArgsParseError::MissingArgument {
+                flag: String::from("--risk-threshold"),
+            })
+        }
+        None => None,
+    };
+
+    // The stamp is a comment row, which only CSV has room for.
+    if stamp && format != ReportFormat::Csv {
+        return Err(EnvArgsParseError::ConflictingArguments {
+            flag: String::from("--stamp"),
+            other: String::from("--format"),
+        });
+    }
+
+    // Coalescing reads the whole file before applying anything, so it would never finish.
+    if follow && coalesce {
+        return Err(EnvArgsParseError::ConflictingArguments {
+            flag: String::from("--follow"),
+            other: String::from("--coalesce"),
+        });
+    }
+    let rate_limit = match (max_rate, burst) {
+        (Some(per_second), burst) => {
+            Some(RateLimit::new(per_second).burst(burst.unwrap_or(per_second)))
+        }
+        (None, Some(_)) => {
+            return Err(EnvArgsParseError::MissingArgument {
+                flag: String::from("--max-rate"),
+            })
+        }
+        (None, None) => None,
+    };
+
+    // The delimiter applies to the format the file would be read as, which must be CSV.
+    if let Some(delimiter) = delimiter {
+        let format = input_format.unwrap_or_else(|| InputFormat::from_path(&file_path));
+        match format.with_delimiter(delimiter) {
+            Some(format) => input_format = Some(format),
+            None => {
+                return Err(EnvArgsParseError::ConflictingArguments {
+                    flag: String::from("--delimiter"),
+                    other: String::from("--input-format"),
+                })
+            }
+        }
+    }
+    if no_headers {
+        let format = input_format.unwrap_or_else(|| InputFormat::from_path(&file_path));
+        match format.without_headers() {
+            Some(format) => input_format = Some(format),
+            None => {
+                return Err(EnvArgsParseError::ConflictingArguments {
+                    flag: String::from("--no-headers"),
+                    other: String::from("--input-format"),
+                })
+            }
+        }
+    }
+    // Columns are only named by the header row of CSV.
+    if column_map.is_some() {
+        let format = input_format.unwrap_or_else(|| InputFormat::from_path(&file_path));
+        let other = match format.has_headers() {
+            Some(true) => None,
+            Some(false) => Some("--no-headers"),
+            None => Some("--input-format"),
+        };
+        if let Some(other) = other {
+            return Err(EnvArgsParseError::ConflictingArguments {
+                flag: String::from("--column-map"),
+                other: String::from(other),
+            });
+        }
+    }
+    if follow && input_format.is_some_and(|format| !format.can_follow()) {
+        return Err(EnvArgsParseError::ConflictingArguments {
+            flag: String::from("--follow"),
+            other: String::from("--input-format"),
+        });
+    }
+
+    let rounding = match (round_to, bucket) {
+        (Some(_), Some(_)) => {
+            return Err(EnvArgsParseError::ConflictingArguments {
+                flag: String::from("--bucket"),
+                other: String::from("--round-to"),
+            })
+        }
+        (Some(step), None) => Some(Rounding::Nearest(step, rounding_mode)),
+        (None, Some(step)) => Some(Rounding::Bucket(step)),
+        (None, None) => None,
+    };
+
+    Ok(Command::Process(Box::new(RunConfig {
+        file_path,
+        approval,
+        snapshot_in,
+        snapshot_out,
+        snapshot_format,
+        columns,
+        extended,
+        coalesce,
+        output,
+        format,
+        input_format,
+        error_log,
+        strict,
+        duplicate_policy,
+        soft_limit,
+        maximum_amount,
+        maximum_policy,
+        manifest,
+        client_map,
+        storage,
+        sample,
+        checkpoint,
+        unknown_types,
+        column_map: column_map.unwrap_or_default(),
+        excess_precision: excess_precision.unwrap_or(PrecisionPolicy::Round(rounding_mode)),
+        rounding_mode,
+        rates,
+        fees,
+        overdraft,
+        overdraft_limits,
+        minimum_balance,
+        velocity_limits,
+        velocity_policy,
+        risk,
+        ledger,
+        audit_log,
+        double_entry,
+        verify,
+        shards,
+        partitioner,
+        rounding,
+        amount_format,
+        suppress_below,
+        force_migrate,
+        stamp,
+        pending_disputes,
+        reconciliation,
+        dispute_grace,
+        reorder_window,
+        rate_limit,
+        follow,
+    })))
+}
+
+fn parse_approvals(args: &[String]) -> Result<Command, EnvArgsParseError> {
+    if args.len() < 2 {
+        return Err(EnvArgsParseError::ArgumentsTooShort);
+    }
+
+    let queue_path = args[0].clone();
+    let action = match args[1].as_str() {
+        "list" => ApprovalAction::List,
+        "approve" => ApprovalAction::Approve(parse_transaction_id(&args[1], args.get(2))?),
+        "reject" => ApprovalAction::Reject(parse_transaction_id(&args[1], args.get(2))?),
+        other => {
+            return Err(EnvArgsParseError::UnknownArgument {
+                passed: String::from(other),
+            })
+        }
+    };
+
+    Ok(Command::Approvals { queue_path, action })
+}
+
+fn parse_serve(args: &[String]) -> Result<Command, EnvArgsParseError> {
+    let mut tcp_addr = None;
+    let mut storage = None;
+    let mut wal = None;
+    let mut max_inflight_per_client = DEFAULT_MAX_INFLIGHT_PER_CLIENT;
+    let mut telemetry_interval = None;
+    let mut history_cap = None;
+    let mut history_spill = None;
+    let mut http_addr = None;
+    let mut protocol = WireProtocol::default();
+    let mut grpc_addr = None;
+    let mut websocket_addr = None;
+    let mut snapshot_dir = None;
+    let mut snapshot_interval = None;
+    let mut snapshot_format = None;
+    let mut keep_last = None;
+    let mut keep_daily = None;
+
+    let mut flags = args.iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--tcp" => tcp_addr = Some(flag_value(flag, flags.next())?.clone()),
+            "--storage" => storage = Some(flag_value(flag, flags.next())?.clone()),
+            "--wal" => wal = Some(flag_value(flag, flags.next())?.clone()),
+            "--max-inflight-per-client" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse() {
+                    Ok(max) if max > 0 => max_inflight_per_client = max,
+                    _ => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--telemetry-interval" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse() {
+                    Ok(seconds) if seconds > 0 => telemetry_interval = Some(seconds),
+                    _ => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--history-cap" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse() {
+                    Ok(cap) if cap > 0 => history_cap = Some(cap),
+                    _ => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--history-spill" => history_spill = Some(flag_value(flag, flags.next())?.clone()),
+            "--http" => http_addr = Some(flag_value(flag, flags.next())?.clone()),
+            "--grpc" => grpc_addr = Some(flag_value(flag, flags.next())?.clone()),
+            "--websocket" => websocket_addr = Some(flag_value(flag, flags.next())?.clone()),
+            "--snapshot-dir" => snapshot_dir = Some(flag_value(flag, flags.next())?.clone()),
+            "--snapshot-interval" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse() {
+                    Ok(seconds) if seconds > 0 => snapshot_interval = Some(seconds),
+                    _ => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--snapshot-format" => {
+                let value = flag_value(flag, flags.next())?;
+                match SnapshotFormat::from_str(value) {
+                    Ok(format) => snapshot_format = Some(format),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--keep-last" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse() {
+                    Ok(count) if count > 0 => keep_last = Some(count),
+                    _ => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--keep-daily" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse() {
+                    Ok(days) => keep_daily = Some(days),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--protocol" => {
+                let value = flag_value(flag, flags.next())?;
+                match WireProtocol::from_str(value) {
+                    Ok(value) => protocol = value,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            _ => {
+                return Err(EnvArgsParseError::UnknownArgument {
+                    passed: flag.clone(),
+                })
+            }
+        }
+    }
+
+    // Both recover the accounts on startup, and replaying the log over stored accounts would apply
+    // disputes and resolves twice.
+    if storage.is_some() && wal.is_some() {
+        return Err(EnvArgsParseError::ConflictingArguments {
+            flag: String::from("--wal"),
+            other: String::from("--storage"),
+        });
+    }
+
+    // The spill file only lives as long as the process, so stored accounts would lose the history
+    // spilled before a restart.
+    if storage.is_some() && history_spill.is_some() {
+        return Err(EnvArgsParseError::ConflictingArguments {
+            flag: String::from("--history-spill"),
+            other: String::from("--storage"),
+        });
+    }
+    if history_spill.is_some() && history_cap.is_none() {
+        return Err(EnvArgsParseError::MissingArgument {
+            flag: String::from("--history-cap"),
+        });
+    }
+
+    let snapshots = match snapshot_dir {
+        Some(dir) => {
+            let defaults = Retention::default();
+            Some(SnapshotSchedule {
+                dir,
+                interval: snapshot_interval.unwrap_or(DEFAULT_SNAPSHOT_INTERVAL),
+                format: snapshot_format.unwrap_or_default(),
+                retention: Retention {
+                    keep_last: keep_last.unwrap_or(defaults.keep_last),
+                    keep_daily: keep_daily.unwrap_or(defaults.keep_daily),
+                },
+            })
+        }
+        None if snapshot_interval.is_some()
+            || snapshot_format.is_some()
+            || keep_last.is_some()
+            || keep_daily.is_some() =>
+        {
+            return Err(EnvArgsParseError::MissingArgument {
+                flag: String::from("--snapshot-dir"),
+            })
+        }
+        None => None,
+    };
+
+    match tcp_addr {
+        Some(tcp_addr) => Ok(Command::Serve(Box::new(ServeConfig {
+            tcp_addr,
+            storage,
+            wal,
+            max_inflight_per_client,
+            telemetry_interval,
+            history_cap,
+            history_spill,
+            http_addr,
+            protocol,
+            grpc_addr,
+            websocket_addr,
+            snapshots,
+        }))),
+        None => Err(EnvArgsParseError::MissingArgument {
+            flag: String::from("--tcp"),
+        }),
+    }
+}
+
+fn parse_export_client(args: &[String]) -> Result<Command, EnvArgsParseError> {
+    let mut snapshot_path = None;
+    let mut snapshot_format = SnapshotFormat::default();
+    let mut client = None;
+    let mut redact_amounts = false;
+    let mut output = None;
+    let mut force_migrate = false;
+
+    let mut flags = args.iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--id" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse::<ClientId>() {
+                    Ok(value) => client = Some(value),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--snapshot" => snapshot_path = Some(flag_value(flag, flags.next())?.clone()),
+            "--snapshot-format" => {
+                let value = flag_value(flag, flags.next())?;
+                match SnapshotFormat::from_str(value) {
+                    Ok(format) => snapshot_format = format,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--redact-amounts" => redact_amounts = true,
+            "--force-migrate" => force_migrate = true,
+            "--output" => output = Some(flag_value(flag, flags.next())?.clone()),
+            _ => {
+                return Err(EnvArgsParseError::UnknownArgument {
+                    passed: flag.clone(),
+                })
+            }
+        }
+    }
+
+    let client = client.ok_or_else(|| EnvArgsParseError::MissingArgument {
+        flag: String::from("--id"),
+    })?;
+    let snapshot_path = snapshot_path.ok_or_else(|| EnvArgsParseError::MissingArgument {
+        flag: String::from("--snapshot"),
+    })?;
+
+    Ok(Command::ExportClient {
+        snapshot_path,
+        snapshot_format,
+        client,
+        redact_amounts,
+        output,
+        force_migrate,
+    })
+}
+
+fn parse_erase_client(args: &[String]) -> Result<Command, EnvArgsParseError> {
+    let mut snapshot_path = None;
+    let mut snapshot_format = SnapshotFormat::default();
+    let mut client = None;
+    let mut audit_log = None;
+    let mut force_migrate = false;
+
+    let mut flags = args.iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--id" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse::<ClientId>() {
+                    Ok(value) => client = Some(value),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--snapshot" => snapshot_path = Some(flag_value(flag, flags.next())?.clone()),
+            "--snapshot-format" => {
+                let value = flag_value(flag, flags.next())?;
+                match SnapshotFormat::from_str(value) {
+                    Ok(format) => snapshot_format = format,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--audit-log" => audit_log = Some(flag_value(flag, flags.next())?.clone()),
+            "--force-migrate" => force_migrate = true,
+            _ => {
+                return Err(EnvArgsParseError::UnknownArgument {
+                    passed: flag.clone(),
+                })
+            }
+        }
+    }
+
+    let client = client.ok_or_else(|| EnvArgsParseError::MissingArgument {
+        flag: String::from("--id"),
+    })?;
+    let snapshot_path = snapshot_path.ok_or_else(|| EnvArgsParseError::MissingArgument {
+        flag: String::from("--snapshot"),
+    })?;
+
+    Ok(Command::EraseClient {
+        snapshot_path,
+        snapshot_format,
+        client,
+        audit_log,
+        force_migrate,
+    })
+}
+
+fn parse_serve_snapshot(args: &[String]) -> Result<Command, EnvArgsParseError> {
+    if args.is_empty() {
+        return Err(EnvArgsParseError::ArgumentsTooShort);
+    }
+
+    let snapshot_path = args[0].clone();
+    let mut snapshot_format = SnapshotFormat::default();
+    let mut host = String::from("127.0.0.1");
+    let mut port = None;
+    let mut force_migrate = false;
+
+    let mut flags = args.iter().skip(1);
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--port" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse::<u16>() {
+                    Ok(value) => port = Some(value),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--host" => host = flag_value(flag, flags.next())?.clone(),
+            "--force-migrate" => force_migrate = true,
+            "--snapshot-format" => {
+                let value = flag_value(flag, flags.next())?;
+                match SnapshotFormat::from_str(value) {
+                    Ok(format) => snapshot_format = format,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            _ => {
+                return Err(EnvArgsParseError::UnknownArgument {
+                    passed: flag.clone(),
+                })
+            }
+        }
+    }
+
+    match port {
+        Some(port) => Ok(Command::ServeSnapshot {
+            snapshot_path,
+            snapshot_format,
+            http_addr: format!("{}:{}", host, port),
+            force_migrate,
+        }),
+        None => Err(EnvArgsParseError::MissingArgument {
+            flag: String::from("--port"),
+        }),
+    }
+}
+
+fn parse_merge(args: &[String]) -> Result<Command, EnvArgsParseError> {
+    if args.len() < 2 {
+        return Err(EnvArgsParseError::ArgumentsTooShort);
+    }
+
+    let first = args[0].clone();
+    let second = args[1].clone();
+    let mut snapshot_format = SnapshotFormat::default();
+    let mut snapshot_out = None;
+    let mut output = None;
+    let mut force_migrate = false;
+
+    let mut flags = args.iter().skip(2);
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--snapshot-format" => {
+                let value = flag_value(flag, flags.next())?;
+                match SnapshotFormat::from_str(value) {
+                    Ok(format) => snapshot_format = format,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--snapshot-out" => snapshot_out = Some(flag_value(flag, flags.next())?.clone()),
+            "--output" => output = Some(flag_value(flag, flags.next())?.clone()),
+            "--force-migrate" => force_migrate = true,
+            _ => {
+                return Err(EnvArgsParseError::UnknownArgument {
+                    passed: flag.clone(),
+                })
+            }
+        }
+    }
+
+    Ok(Command::Merge {
+        first,
+        second,
+        snapshot_format,
+        snapshot_out,
+        output,
+        force_migrate,
+    })
+}
+
+fn parse_diff(args: &[String]) -> Result<Command, EnvArgsParseError> {
+    if args.len() < 2 {
+        return Err(EnvArgsParseError::ArgumentsTooShort);
+    }
+
+    let before = args[0].clone();
+    let after = args[1].clone();
+    let mut snapshot_format = SnapshotFormat::default();
+    let mut force_migrate = false;
+
+    let mut flags = args.iter().skip(2);
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--snapshot-format" => {
+                let value = flag_value(flag, flags.next())?;
+                match SnapshotFormat::from_str(value) {
+                    Ok(format) => snapshot_format = format,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--force-migrate" => force_migrate = true,
+            _ => {
+                return Err(EnvArgsParseError::UnknownArgument {
+                    passed: flag.clone(),
+                })
+            }
+        }
+    }
+
+    Ok(Command::Diff {
+        before,
+        after,
+        snapshot_format,
+        force_migrate,
+    })
+}
+
+fn parse_query(args: &[String]) -> Result<Command, EnvArgsParseError> {
+    let mut client = None;
+    let mut file_path = None;
+    let mut snapshot_path = None;
+    let mut snapshot_format = SnapshotFormat::default();
+    let mut force_migrate = false;
+    let mut history = false;
+
+    let mut flags = args.iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--client" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse::<ClientId>() {
+                    Ok(value) => client = Some(value),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--snapshot" => snapshot_path = Some(flag_value(flag, flags.next())?.clone()),
+            "--snapshot-format" => {
+                let value = flag_value(flag, flags.next())?;
+                match SnapshotFormat::from_str(value) {
+                    Ok(format) => snapshot_format = format,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--force-migrate" => force_migrate = true,
+            "--history" => history = true,
+            path if !path.starts_with("--") && file_path.is_none() => {
+                file_path = Some(String::from(path))
+            }
+            _ => {
+                return Err(EnvArgsParseError::UnknownArgument {
+                    passed: flag.clone(),
+                })
+            }
+        }
+    }
+
+    let client = client.ok_or_else(|| EnvArgsParseError::MissingArgument {
+        flag: String::from("--client"),
+    })?;
+    if file_path.is_none() && snapshot_path.is_none() {
+        return Err(EnvArgsParseError::ArgumentsTooShort);
+    }
+
+    Ok(Command::Query {
+        client,
+        file_path,
+        snapshot_path,
+        snapshot_format,
+        force_migrate,
+        history,
+    })
+}
+
+fn parse_repl(args: &[String]) -> Result<Command, EnvArgsParseError> {
+    let mut snapshot_path = None;
+    let mut snapshot_format = SnapshotFormat::default();
+    let mut force_migrate = false;
+
+    let mut flags = args.iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--snapshot" => snapshot_path = Some(flag_value(flag, flags.next())?.clone()),
+            "--snapshot-format" => {
+                let value = flag_value(flag, flags.next())?;
+                match SnapshotFormat::from_str(value) {
+                    Ok(format) => snapshot_format = format,
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--force-migrate" => force_migrate = true,
+            _ => {
+                return Err(EnvArgsParseError::UnknownArgument {
+                    passed: flag.clone(),
+                })
+            }
+        }
+    }
+
+    Ok(Command::Repl {
+        snapshot_path,
+        snapshot_format,
+        force_migrate,
+    })
+}
+
+fn parse_conformance(args: &[String]) -> Result<Command, EnvArgsParseError> {
+    match args {
+        [] => Ok(Command::Conformance { suite_path: None }),
+        [path] => Ok(Command::Conformance {
+            suite_path: Some(path.clone()),
+        }),
+        [_, extra, ..] => Err(EnvArgsParseError::UnknownArgument {
+            passed: extra.clone(),
+        }),
+    }
+}
+
+fn parse_validate(args: &[String]) -> Result<Command, EnvArgsParseError> {
+    match args {
+        [] => Err(EnvArgsParseError::ArgumentsTooShort),
+        [file_path] => Ok(Command::Validate {
+            file_path: file_path.clone(),
+        }),
+        [_, extra, ..] => Err(EnvArgsParseError::UnknownArgument {
+            passed: extra.clone(),
+        }),
+    }
+}
+
+/// How much load a stress run generates unless told otherwise.
+const DEFAULT_STRESS_TRANSACTIONS: u32 = 1_000_000;
+const DEFAULT_STRESS_CLIENTS: ClientId = 1000;
+const DEFAULT_STRESS_DISPUTES_PER_MILLION: u32 = 10_000;
+
+fn parse_stress(args: &[String]) -> Result<Command, EnvArgsParseError> {
+    let mut config = StressConfig {
+        transactions: DEFAULT_STRESS_TRANSACTIONS,
+        clients: DEFAULT_STRESS_CLIENTS,
+        disputes_per_million: DEFAULT_STRESS_DISPUTES_PER_MILLION,
+    };
+
+    let mut flags = args.iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--transactions" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse() {
+                    Ok(count) if count > 0 => config.transactions = count,
+                    _ => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--clients" => {
+                let value = flag_value(flag, flags.next())?;
+                match value.parse() {
+                    Ok(count) if count > 0 => config.clients = count,
+                    _ => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--disputes" => {
+                let value = flag_value(flag, flags.next())?;
+                let percent: f64 = match value.trim_end_matches('%').parse() {
+                    Ok(percent) if (0.0..=100.0).contains(&percent) => percent,
+                    _ => return Err(invalid_value(flag, value)),
+                };
+                config.disputes_per_million = (percent * 10_000.0).round() as u32;
+            }
+            _ => {
+                return Err(EnvArgsParseError::UnknownArgument {
+                    passed: flag.clone(),
+                })
+            }
+        }
+    }
+
+    Ok(Command::Stress(config))
 }
 
-pub fn env_args_parse_file(args: Vec<String>) -> Result<String, EnvArgsParseError> {
-    const MIN_ARG_LEN: usize = 2;
-    const FILE_ARG: usize = 1;
-    if args.len() < MIN_ARG_LEN {
-        return Err(EnvArgsParseError::ArgumentsTooShort);
+fn parse_backtest(args: &[String]) -> Result<Command, EnvArgsParseError> {
+    if args.is_empty() {
+        return Err(EnvArgsParseError::ArgumentsTooShort);
+    }
+
+    let file_path = args[0].clone();
+    let mut input_format = None;
+    let mut policies = vec![];
+
+    let mut flags = args.iter().skip(1);
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--policy" => {
+                let value = flag_value(flag, flags.next())?;
+                match Policy::from_str(value) {
+                    Ok(policy) => policies.push(policy),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            "--input-format" => {
+                let value = flag_value(flag, flags.next())?;
+                match InputFormat::from_str(value) {
+                    Ok(format) => input_format = Some(format),
+                    Err(_) => return Err(invalid_value(flag, value)),
+                }
+            }
+            _ => {
+                return Err(EnvArgsParseError::UnknownArgument {
+                    passed: flag.clone(),
+                })
+            }
+        }
+    }
+
+    if policies.is_empty() {
+        return Err(EnvArgsParseError::MissingArgument {
+            flag: String::from("--policy"),
+        });
+    }
+
+    Ok(Command::Backtest {
+        file_path,
+        input_format,
+        policies,
+    })
+}
+
+fn parse_transaction_id(
+    flag: &str,
+    value: Option<&String>,
+) -> Result<TransactionId, EnvArgsParseError> {
+    let value = flag_value(flag, value)?;
+    value
+        .parse::<TransactionId>()
+        .map_err(|_| invalid_value(flag, value))
+}
+
+fn flag_value<'a>(flag: &str, value: Option<&'a String>) -> Result<&'a String, EnvArgsParseError> {
+    value.ok_or_else(|| EnvArgsParseError::MissingValue {
+        flag: String::from(flag),
+    })
+}
+
+/// Parses an amount that must be above zero, such as the step amounts are rounded to.
+fn parse_step(flag: &str, value: Option<&String>) -> Result<Amount, EnvArgsParseError> {
+    let value = flag_value(flag, value)?;
+    match Amount::from_str(value) {
+        Ok(step) if step > Amount::zero() => Ok(step),
+        _ => Err(invalid_value(flag, value)),
+    }
+}
+
+/// Parses a count that must be above zero.
+fn parse_positive(flag: &str, value: Option<&String>) -> Result<u64, EnvArgsParseError> {
+    let value = flag_value(flag, value)?;
+    match value.parse() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(invalid_value(flag, value)),
+    }
+}
+
+fn invalid_value(flag: &str, value: &str) -> EnvArgsParseError {
+    EnvArgsParseError::InvalidValue {
+        flag: String::from(flag),
+        passed: String::from(value),
+    }
+}
+
+/// Resolves an input file passed on the command line to its canonical path, expanding a leading
+/// `~` to the home directory, and checks that it can be read.
+pub fn resolve_input_file(passed: &str) -> Result<String, EnvArgsParseError> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"));
+    let path = expand_tilde(passed, home.as_deref().map(Path::new));
+
+    let error = |e: io::Error| match e.kind() {
+        io::ErrorKind::NotFound => EnvArgsParseError::FileNotFound {
+            passed: String::from(passed),
+        },
+        io::ErrorKind::PermissionDenied => EnvArgsParseError::PermissionDenied {
+            passed: String::from(passed),
+        },
+        kind => EnvArgsParseError::UnreadableFile {
+            passed: String::from(passed),
+            kind,
+        },
+    };
+
+    let path = fs::canonicalize(path).map_err(error)?;
+    if !fs::metadata(&path).map_err(error)?.is_file() {
+        return Err(EnvArgsParseError::NotAFile {
+            passed: String::from(passed),
+        });
+    }
+    // Canonicalizing only needs the directories to be searchable, not the file to be readable.
+    File::open(&path).map_err(error)?;
+
+    Ok(simplify(path).to_string_lossy().into_owned())
+}
+
+/// Replaces a leading `~` with the home directory. Other users' home directories (`~name`) aren't
+/// expanded.
+fn expand_tilde(passed: &str, home: Option<&Path>) -> PathBuf {
+    let rest = match passed.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(std::path::is_separator) => rest,
+        _ => return PathBuf::from(passed),
+    };
+
+    match home {
+        Some(home) => home.join(rest.trim_start_matches(std::path::is_separator)),
+        None => PathBuf::from(passed),
+    }
+}
+
+/// Windows canonicalizes to the `\\?\` form, which lifts the path length limit but isn't
+/// understood by every tool that reads the path from our outputs. It's only kept for paths too
+/// long to be written any other way.
+#[cfg(windows)]
+fn simplify(path: PathBuf) -> PathBuf {
+    const MAX_PATH: usize = 260;
+
+    let simplified = {
+        let text = path.to_string_lossy();
+        match text.strip_prefix(r"\\?\UNC\") {
+            Some(unc) => Some(format!(r"\\{}", unc)),
+            None => text.strip_prefix(r"\\?\").map(String::from),
+        }
+    };
+
+    match simplified {
+        Some(simplified) if simplified.len() < MAX_PATH => PathBuf::from(simplified),
+        _ => path,
+    }
+}
+
+#[cfg(not(windows))]
+fn simplify(path: PathBuf) -> PathBuf {
+    path
+}
+
+/// A single ASCII character other than a quote or line break, or `tab`.
+fn parse_delimiter(value: &str) -> Option<u8> {
+    match value.as_bytes() {
+        b"tab" | b"\\t" => Some(b'\t'),
+        [byte] if byte.is_ascii() && !matches!(byte, b'"' | b'\n' | b'\r') => Some(*byte),
+        _ => None,
+    }
+}
+
+pub fn env_args_parse_file(args: Vec<String>) -> Result<String, EnvArgsParseError> {
+    const MIN_ARG_LEN: usize = 2;
+    const FILE_ARG: usize = 1;
+    if args.len() < MIN_ARG_LEN {
+        return Err(EnvArgsParseError::ArgumentsTooShort);
+    }
+
+    let file_arg = args[FILE_ARG].clone();
+    let file_path = Path::new(compression::uncompressed_path(&args[FILE_ARG]));
+
+    let invalid_file_error = EnvArgsParseError::ExpectedCsvFile {
+        passed: file_arg.clone(),
+    };
+
+    // TSV, JSON lines and fixed-width `.dat` files are accepted alongside CSV, and Parquet and
+    // Avro files when built with support for them.
+    let binary = |ext| {
+        (cfg!(feature = "parquet-input") && ext == "parquet")
+            || (cfg!(feature = "avro-input") && ext == "avro")
+    };
+    match file_path.extension() {
+        Some(ext) => {
+            if ext != "csv"
+                && ext != "tsv"
+                && ext != "jsonl"
+                && ext != "ndjson"
+                && ext != "dat"
+                && !binary(ext)
+            {
+                return Err(invalid_file_error);
+            }
+        }
+        None => return Err(invalid_file_error),
+    }
+
+    Ok(file_arg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_args_parse_file_args_too_short_returns_err_args_to_short() {
+        let args = vec![];
+        let actual = env_args_parse_file(args);
+        assert_eq!(true, actual.is_err());
+        assert_eq!(EnvArgsParseError::ArgumentsTooShort, actual.unwrap_err());
+    }
+
+    #[test]
+    fn expand_tilde_returns_expected() {
+        let home = Path::new("/home/operator");
+
+        assert_eq!(
+            PathBuf::from("/home/operator/in/transactions.csv"),
+            expand_tilde("~/in/transactions.csv", Some(home))
+        );
+        assert_eq!(
+            PathBuf::from("/home/operator"),
+            expand_tilde("~", Some(home))
+        );
+        assert_eq!(
+            PathBuf::from("~other/transactions.csv"),
+            expand_tilde("~other/transactions.csv", Some(home))
+        );
+        assert_eq!(PathBuf::from("~/t.csv"), expand_tilde("~/t.csv", None));
+    }
+
+    #[test]
+    fn resolve_input_file_returns_expected() {
+        let dir = env::temp_dir().join("payments_resolve_input_file");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("transactions.csv");
+        fs::write(&file, "type, client, tx, amount\n").unwrap();
+
+        let resolved = resolve_input_file(&file.to_string_lossy()).unwrap();
+        assert_eq!(fs::canonicalize(&file).unwrap(), PathBuf::from(resolved));
+
+        let missing = dir.join("missing.csv").to_string_lossy().into_owned();
+        assert_eq!(
+            Err(EnvArgsParseError::FileNotFound {
+                passed: missing.clone()
+            }),
+            resolve_input_file(&missing)
+        );
+
+        let directory = dir.to_string_lossy().into_owned();
+        assert_eq!(
+            Err(EnvArgsParseError::NotAFile {
+                passed: directory.clone()
+            }),
+            resolve_input_file(&directory)
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn env_args_parse_file_no_extension_returns_err_not_csv() {
+        let test_file = "transactions";
+        let args = ["target\\debug\\payments.exe", test_file]
+            .iter()
+            .map(|s| String::from(*s))
+            .collect();
+
+        let actual = env_args_parse_file(args);
+        assert_eq!(true, actual.is_err());
+
+        let expected = EnvArgsParseError::ExpectedCsvFile {
+            passed: String::from(test_file),
+        };
+
+        assert_eq!(expected, actual.unwrap_err());
+    }
+
+    #[test]
+    fn env_args_parse_file_not_csv_returns_err_not_csv() {
+        let test_files = vec!["transactions.csvs", ".css", " ", "blah", "foo.bar", ".csv"];
+        for test_file in test_files {
+            let args = ["target\\debug\\payments.exe", test_file]
+                .iter()
+                .map(|s| String::from(*s))
+                .collect();
+
+            let actual = env_args_parse_file(args);
+            assert_eq!(true, actual.is_err());
+
+            let expected = EnvArgsParseError::ExpectedCsvFile {
+                passed: String::from(test_file),
+            };
+
+            assert_eq!(expected, actual.unwrap_err());
+        }
+    }
+
+    #[test]
+    fn env_args_parse_file_compressed_returns_ok() {
+        for test_file in ["transactions.csv.zst", "transactions.jsonl.zst"] {
+            let args = to_args(&["payments", test_file]);
+            assert_eq!(Ok(String::from(test_file)), env_args_parse_file(args));
+        }
+
+        let args = to_args(&["payments", "transactions.zst"]);
+        let expected = EnvArgsParseError::ExpectedCsvFile {
+            passed: String::from("transactions.zst"),
+        };
+        assert_eq!(Err(expected), env_args_parse_file(args));
+    }
+
+    #[test]
+    fn env_args_parse_file_jsonl_returns_ok() {
+        for test_file in ["transactions.jsonl", "transactions.ndjson"] {
+            let args = ["target\\debug\\payments.exe", test_file]
+                .iter()
+                .map(|s| String::from(*s))
+                .collect();
+
+            assert_eq!(Ok(String::from(test_file)), env_args_parse_file(args));
+        }
+    }
+
+    #[test]
+    fn env_args_parse_error_log_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--error-log",
+            "rejected.log",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(Some(String::from("rejected.log")), args.error_log)
+            }
+            _ => panic!("expected process arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_rates_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--rates",
+            "rates.csv",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(Some(String::from("rates.csv")), args.rates),
+            _ => panic!("expected process arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_fees_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--fees",
+            "fees.csv",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(Some(String::from("fees.csv")), args.fees),
+            _ => panic!("expected process arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_overdraft_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--overdraft",
+            "50",
+            "--overdraft-limits",
+            "limits.csv",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(Some(Amount::from_str("50").unwrap()), args.overdraft);
+                assert_eq!(Some(String::from("limits.csv")), args.overdraft_limits);
+            }
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--overdraft",
+            "-1",
+        ]));
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("--overdraft"),
+            passed: String::from("-1"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_minimum_balance_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--minimum-balance",
+            "10.0000",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(Some(Amount::from_str("10").unwrap()), args.minimum_balance)
+            }
+            _ => panic!("expected process arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_velocity_limits_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--velocity-limits",
+            "velocity.csv",
+            "--velocity-policy",
+            "report",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(Some(String::from("velocity.csv")), args.velocity_limits);
+                assert_eq!(VelocityPolicy::Report, args.velocity_policy);
+            }
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--velocity-policy",
+            "flag",
+        ]));
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("--velocity-policy"),
+            passed: String::from("flag"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_risk_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--risk-threshold",
+            "70",
+            "--risk-policy",
+            "block",
+            "--risk-large-amount",
+            "500",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => {
+                let expected = RiskConfig {
+                    policy: RiskPolicy::Block,
+                    large_amount: Amount::from_str("500").unwrap(),
+                    ..RiskConfig::new(70)
+                };
+                assert_eq!(Some(expected), args.risk);
+            }
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--risk-threshold",
+            "101",
+        ]));
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("--risk-threshold"),
+            passed: String::from("101"),
+        };
+        assert_eq!(Err(expected), actual);
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--risk-policy",
+            "block",
+        ]));
+        let expected = EnvArgsParseError::MissingArgument {
+            flag: String::from("--risk-threshold"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_pending_disputes_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--pending-disputes",
+            "disputes.csv",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(Some(String::from("disputes.csv")), args.pending_disputes)
+            }
+            _ => panic!("expected process arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_reorder_window_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--reorder-window",
+            "300",
+        ]));
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(Some(300), args.reorder_window),
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--reorder-window",
+            "soon",
+        ]));
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("--reorder-window"),
+            passed: String::from("soon"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_dispute_grace_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--dispute-grace",
+            "2",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(Some(2), args.dispute_grace),
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--dispute-grace",
+            "0",
+        ]));
+        assert_eq!(
+            Err(EnvArgsParseError::InvalidValue {
+                flag: String::from("--dispute-grace"),
+                passed: String::from("0"),
+            }),
+            actual
+        );
+    }
+
+    #[test]
+    fn env_args_parse_follow_returns_expected() {
+        let actual = env_args_parse(to_args(&["payments", "transactions.csv", "--follow"]));
+
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(true, args.follow),
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--follow",
+            "--coalesce",
+        ]));
+        let expected = EnvArgsParseError::ConflictingArguments {
+            flag: String::from("--follow"),
+            other: String::from("--coalesce"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_strict_returns_expected() {
+        let actual = env_args_parse(to_args(&["payments", "transactions.csv", "--strict"]));
+
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(true, args.strict),
+            _ => panic!("expected process arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_duplicate_ids_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--duplicate-ids",
+            "warn",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(DuplicatePolicy::Warn, args.duplicate_policy),
+            _ => panic!("expected process arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_soft_limit_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--soft-limit",
+            "100",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(Some(Amount::from_str("100").unwrap()), args.soft_limit)
+            }
+            _ => panic!("expected process arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_max_amount_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--max-amount",
+            "10000",
+            "--max-amount-policy",
+            "flag",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(
+                    Some(Amount::from_str("10000").unwrap()),
+                    args.maximum_amount
+                );
+                assert_eq!(MaximumPolicy::Flag, args.maximum_policy);
+            }
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--max-amount",
+            "0",
+        ]));
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("--max-amount"),
+            passed: String::from("0"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_manifest_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--manifest",
+            "run.json",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(Some(String::from("run.json")), args.manifest),
+            _ => panic!("expected process arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_client_map_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--client-map",
+            "clients.csv",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(
+                Some(ClientMapConfig::File(String::from("clients.csv"))),
+                args.client_map
+            ),
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--client-key",
+            "42",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(Some(ClientMapConfig::Key(42)), args.client_map)
+            }
+            _ => panic!("expected process arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_checkpoint_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--checkpoint",
+            "run.ckpt",
+            "--resume",
+        ]));
+
+        let expected = CheckpointConfig {
+            path: String::from("run.ckpt"),
+            every: DEFAULT_CHECKPOINT_EVERY,
+            resume: true,
+        };
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(Some(expected), args.checkpoint),
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--checkpoint-every",
+            "10",
+        ]));
+        let expected = EnvArgsParseError::MissingArgument {
+            flag: String::from("--checkpoint"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_shards_returns_expected() {
+        let actual = env_args_parse(to_args(&["payments", "transactions.csv", "--shards", "8"]));
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(8, args.shards),
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--shards",
+            "8",
+            "--partitioner",
+            "kafka-int",
+        ]));
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(
+                Partitioning::Kafka(payments::shard::KafkaKey::Int),
+                args.partitioner
+            ),
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&["payments", "transactions.csv", "--shards", "0"]));
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("--shards"),
+            passed: String::from("0"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_shared_report_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--bucket",
+            "1000",
+            "--suppress-below",
+            "50",
+        ]));
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(
+                    Some(Rounding::Bucket(Amount::from_str("1000").unwrap())),
+                    args.rounding
+                );
+                assert_eq!(Some(Amount::from_str("50").unwrap()), args.suppress_below);
+            }
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--round-to",
+            "100",
+            "--bucket",
+            "1000",
+        ]));
+        let expected = EnvArgsParseError::ConflictingArguments {
+            flag: String::from("--bucket"),
+            other: String::from("--round-to"),
+        };
+        assert_eq!(Err(expected), actual);
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--round-to",
+            "0",
+        ]));
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("--round-to"),
+            passed: String::from("0"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_stamp_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--stamp",
+            "--force-migrate",
+        ]));
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(true, args.stamp);
+                assert_eq!(true, args.force_migrate);
+            }
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--stamp",
+            "--format",
+            "json",
+        ]));
+        let expected = EnvArgsParseError::ConflictingArguments {
+            flag: String::from("--stamp"),
+            other: String::from("--format"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_stress_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "stress",
+            "--transactions",
+            "5000",
+            "--clients",
+            "20",
+            "--disputes",
+            "2.5%",
+        ]));
+        let expected = Command::Stress(StressConfig {
+            transactions: 5000,
+            clients: 20,
+            disputes_per_million: 25_000,
+        });
+        assert_eq!(Ok(expected), actual);
+
+        let actual = env_args_parse(to_args(&["payments", "stress", "--disputes", "120%"]));
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("--disputes"),
+            passed: String::from("120%"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_randomness_returns_expected() {
+        let actual = env_args_parse_randomness(to_args(&[
+            "payments",
+            "stress",
+            "--seed",
+            "42",
+            "--clients",
+            "20",
+            "--deterministic",
+        ]));
+        let expected = Randomness {
+            seed: Some(42),
+            deterministic: true,
+        };
+        assert_eq!(
+            Ok((
+                expected,
+                to_args(&["payments", "stress", "--clients", "20"])
+            )),
+            actual
+        );
+
+        let actual = env_args_parse_randomness(to_args(&["payments", "t.csv"]));
+        assert_eq!(
+            Ok((Randomness::default(), to_args(&["payments", "t.csv"]))),
+            actual
+        );
+
+        let actual = env_args_parse_randomness(to_args(&["payments", "stress", "--seed", "-1"]));
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("--seed"),
+            passed: String::from("-1"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_validate_returns_expected() {
+        let actual = env_args_parse(to_args(&["payments", "validate", "partner.csv"]));
+        let expected = Command::Validate {
+            file_path: String::from("partner.csv"),
+        };
+        assert_eq!(Ok(expected), actual);
+
+        let actual = env_args_parse(to_args(&["payments", "validate"]));
+        assert_eq!(Err(EnvArgsParseError::ArgumentsTooShort), actual);
+    }
+
+    #[test]
+    fn env_args_parse_ledger_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--ledger",
+            "run.beancount",
+            "--ledger-format",
+            "beancount",
+            "--chart",
+            "chart.json",
+        ]));
+
+        let expected = LedgerConfig {
+            path: String::from("run.beancount"),
+            format: LedgerFormat::Beancount,
+            chart: Some(String::from("chart.json")),
+        };
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(Some(expected), args.ledger),
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--chart",
+            "chart.json",
+        ]));
+        let expected = EnvArgsParseError::MissingArgument {
+            flag: String::from("--ledger"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_audit_log_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--audit-log",
+            "audit.jsonl",
+            "--audit-format",
+            "jsonl",
+        ]));
+
+        let expected = AuditLogConfig {
+            path: String::from("audit.jsonl"),
+            format: AuditFormat::JsonLines,
+        };
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(Some(expected), args.audit_log),
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--audit-format",
+            "csv",
+        ]));
+        let expected = EnvArgsParseError::MissingArgument {
+            flag: String::from("--audit-log"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_verify_returns_expected() {
+        let actual = env_args_parse(to_args(&["payments", "transactions.csv", "--verify"]));
+
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(true, args.verify),
+            _ => panic!("expected process arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_reconciliation_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--reconciliation",
+            "totals.csv",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(Some(String::from("totals.csv")), args.reconciliation)
+            }
+            _ => panic!("expected process arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_double_entry_returns_expected() {
+        let actual = env_args_parse(to_args(&["payments", "transactions.csv", "--double-entry"]));
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(true, args.double_entry),
+            _ => panic!("expected process arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_unknown_types_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--unknown-types",
+            "warn",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(UnknownTypePolicy::Warn, args.unknown_types),
+            _ => panic!("expected process arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_excess_precision_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--excess-precision",
+            "error",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(PrecisionPolicy::Error, args.excess_precision)
+            }
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--excess-precision",
+            "floor",
+        ]));
+        assert_eq!(
+            Err(EnvArgsParseError::InvalidValue {
+                flag: String::from("--excess-precision"),
+                passed: String::from("floor"),
+            }),
+            actual
+        );
+    }
+
+    #[test]
+    fn env_args_parse_amount_format_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--amount-format",
+            "trimmed",
+        ]));
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(AmountFormat::Trimmed, args.amount_format),
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--amount-format",
+            "short",
+        ]));
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("--amount-format"),
+            passed: String::from("short"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_rounding_mode_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--rounding-mode",
+            "half-even",
+            "--round-to",
+            "100",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => {
+                let half_even = RoundingMode::HalfEven;
+                assert_eq!(half_even, args.rounding_mode);
+                assert_eq!(PrecisionPolicy::Round(half_even), args.excess_precision);
+                assert_eq!(
+                    Some(Rounding::Nearest(
+                        Amount::from_str("100").unwrap(),
+                        half_even
+                    )),
+                    args.rounding
+                );
+            }
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--rounding-mode",
+            "half-even",
+            "--excess-precision",
+            "error",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(PrecisionPolicy::Error, args.excess_precision)
+            }
+            _ => panic!("expected process arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_sample_returns_expected() {
+        let actual = env_args_parse(to_args(&["payments", "transactions.csv", "--sample", "1%"]));
+
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(Some(SampleRate::from_str("1%").unwrap()), args.sample)
+            }
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&["payments", "transactions.csv", "--sample", "0%"]));
+        assert_eq!(
+            Err(EnvArgsParseError::InvalidValue {
+                flag: String::from("--sample"),
+                passed: String::from("0%"),
+            }),
+            actual
+        );
     }
 
-    let file_arg = args[FILE_ARG].clone();
-    let file_path = Path::new(&args[FILE_ARG]);
+    #[test]
+    fn env_args_parse_storage_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--storage",
+            "accounts.db",
+        ]));
 
-    let invalid_file_error = EnvArgsParseError::ExpectedCsvFile {
-        passed: file_arg.clone(),
-    };
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(Some(String::from("accounts.db")), args.storage)
+            }
+            _ => panic!("expected process arguments"),
+        }
 
-    match file_path.extension() {
-        Some(ext) => {
-            if ext != "csv" {
-                return Err(invalid_file_error);
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "serve",
+            "--tcp",
+            "127.0.0.1:7000",
+            "--storage",
+            "accounts.db",
+        ]));
+
+        let expected = Command::Serve(Box::new(ServeConfig {
+            tcp_addr: String::from("127.0.0.1:7000"),
+            storage: Some(String::from("accounts.db")),
+            wal: None,
+            max_inflight_per_client: DEFAULT_MAX_INFLIGHT_PER_CLIENT,
+            telemetry_interval: None,
+            history_cap: None,
+            history_spill: None,
+            http_addr: None,
+            protocol: WireProtocol::Framed,
+            grpc_addr: None,
+            websocket_addr: None,
+            snapshots: None,
+        }));
+        assert_eq!(Ok(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_input_format_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--input-format",
+            "jsonl",
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => {
+                assert_eq!(Some(InputFormat::JsonLines), args.input_format)
             }
+            _ => panic!("expected process arguments"),
         }
-        None => return Err(invalid_file_error),
     }
 
-    Ok(file_arg)
-}
+    #[test]
+    fn env_args_parse_max_rate_returns_expected() {
+        let cases = [
+            (vec!["--max-rate", "500"], RateLimit::new(500)),
+            (
+                vec!["--max-rate", "500", "--burst", "50"],
+                RateLimit::new(500).burst(50),
+            ),
+        ];
+        for (flags, expected) in cases {
+            let mut args = vec!["payments", "transactions.csv"];
+            args.extend(flags);
+            match env_args_parse(to_args(&args)) {
+                Ok(Command::Process(args)) => assert_eq!(Some(expected), args.rate_limit),
+                _ => panic!("expected process arguments"),
+            }
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let actual = env_args_parse(to_args(&["payments", "transactions.csv", "--burst", "50"]));
+        let expected = EnvArgsParseError::MissingArgument {
+            flag: String::from("--max-rate"),
+        };
+        assert_eq!(Err(expected), actual);
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--max-rate",
+            "0",
+        ]));
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("--max-rate"),
+            passed: String::from("0"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
 
     #[test]
-    fn env_args_parse_file_args_too_short_returns_err_args_to_short() {
-        let args = vec![];
-        let actual = env_args_parse_file(args);
+    fn env_args_parse_delimiter_returns_expected() {
+        let cases = [
+            (
+                "transactions.csv",
+                ";",
+                InputFormat::Csv {
+                    delimiter: b';',
+                    has_headers: true,
+                },
+            ),
+            (
+                "transactions.csv",
+                "tab",
+                InputFormat::Csv {
+                    delimiter: b'\t',
+                    has_headers: true,
+                },
+            ),
+            (
+                "transactions.tsv",
+                "|",
+                InputFormat::Csv {
+                    delimiter: b'|',
+                    has_headers: true,
+                },
+            ),
+        ];
+        for (file, delimiter, expected) in cases {
+            let actual = env_args_parse(to_args(&["payments", file, "--delimiter", delimiter]));
+            match actual {
+                Ok(Command::Process(args)) => assert_eq!(Some(expected), args.input_format),
+                _ => panic!("expected process arguments"),
+            }
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--input-format",
+            "csv-parallel",
+            "--delimiter",
+            ";",
+        ]));
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(
+                Some(InputFormat::ParallelCsv {
+                    delimiter: b';',
+                    has_headers: true,
+                }),
+                args.input_format
+            ),
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.jsonl",
+            "--delimiter",
+            ";",
+        ]));
+        let expected = EnvArgsParseError::ConflictingArguments {
+            flag: String::from("--delimiter"),
+            other: String::from("--input-format"),
+        };
+        assert_eq!(Err(expected), actual);
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--delimiter",
+            ";;",
+        ]));
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("--delimiter"),
+            passed: String::from(";;"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_no_headers_returns_expected() {
+        let actual = env_args_parse(to_args(&["payments", "transactions.tsv", "--no-headers"]));
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(
+                Some(InputFormat::Csv {
+                    delimiter: b'\t',
+                    has_headers: false,
+                }),
+                args.input_format
+            ),
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&["payments", "transactions.jsonl", "--no-headers"]));
+        let expected = EnvArgsParseError::ConflictingArguments {
+            flag: String::from("--no-headers"),
+            other: String::from("--input-format"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_column_map_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--column-map",
+            "customer_id=client,value=amount",
+        ]));
+        let expected = ColumnMap::default()
+            .rename("customer_id", "client")
+            .and_then(|map| map.rename("value", "amount"))
+            .unwrap();
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(expected, args.column_map),
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--column-map",
+            "value=total",
+        ]));
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("--column-map"),
+            passed: String::from("value=total"),
+        };
+        assert_eq!(Err(expected), actual);
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--no-headers",
+            "--column-map",
+            "value=amount",
+        ]));
+        let expected = EnvArgsParseError::ConflictingArguments {
+            flag: String::from("--column-map"),
+            other: String::from("--no-headers"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_input_format_fixed_width_returns_expected() {
+        let spec = "type:10,client:6,tx:10,skip:2,amount:14";
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "feed.dat",
+            "--input-format",
+            &format!("fixed-width={}", spec),
+        ]));
+
+        match actual {
+            Ok(Command::Process(args)) => assert_eq!(
+                Some(InputFormat::FixedWidth(spec.parse().unwrap())),
+                args.input_format
+            ),
+            _ => panic!("expected process arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "feed.dat",
+            "--input-format",
+            "fixed-width=type:10,client:6",
+        ]));
         assert_eq!(true, actual.is_err());
-        assert_eq!(EnvArgsParseError::ArgumentsTooShort, actual.unwrap_err());
+    }
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| String::from(*s)).collect()
     }
 
     #[test]
-    fn env_args_parse_file_no_extension_returns_err_not_csv() {
-        let test_file = "transactions";
-        let args = vec!["target\\debug\\payments.exe", test_file]
-            .iter()
-            .map(|s| String::from(*s))
-            .collect();
+    fn env_args_parse_file_only_returns_process_without_approval() {
+        let actual = env_args_parse(to_args(&["payments", "transactions.csv"]));
 
-        let actual = env_args_parse_file(args);
+        let expected = Command::Process(Box::new(RunConfig {
+            file_path: String::from("transactions.csv"),
+            approval: None,
+            snapshot_in: None,
+            snapshot_out: None,
+            snapshot_format: SnapshotFormat::Bincode,
+            columns: None,
+            extended: false,
+            coalesce: false,
+            output: None,
+            format: ReportFormat::Csv,
+            input_format: None,
+            error_log: None,
+            strict: false,
+            duplicate_policy: DuplicatePolicy::Reject,
+            soft_limit: None,
+            maximum_amount: None,
+            maximum_policy: MaximumPolicy::Reject,
+            manifest: None,
+            client_map: None,
+            storage: None,
+            sample: None,
+            checkpoint: None,
+            unknown_types: UnknownTypePolicy::Skip,
+            column_map: ColumnMap::default(),
+            excess_precision: PrecisionPolicy::Round(RoundingMode::HalfUp),
+            rounding_mode: RoundingMode::HalfUp,
+            rates: None,
+            fees: None,
+            overdraft: None,
+            overdraft_limits: None,
+            minimum_balance: None,
+            velocity_limits: None,
+            velocity_policy: VelocityPolicy::Reject,
+            risk: None,
+            ledger: None,
+            audit_log: None,
+            double_entry: false,
+            verify: false,
+            shards: 1,
+            partitioner: Partitioning::Hash,
+            rounding: None,
+            amount_format: AmountFormat::Fixed,
+            suppress_below: None,
+            force_migrate: false,
+            stamp: false,
+            pending_disputes: None,
+            reconciliation: None,
+            dispute_grace: None,
+            reorder_window: None,
+            rate_limit: None,
+            follow: false,
+        }));
+        assert_eq!(Ok(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_approval_flags_returns_process_with_approval() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--approval-threshold",
+            "1000.5",
+            "--approval-queue",
+            "queue.csv",
+        ]));
+
+        let expected = Command::Process(Box::new(RunConfig {
+            file_path: String::from("transactions.csv"),
+            approval: Some(ApprovalConfig {
+                threshold: Amount::from_str("1000.5").unwrap(),
+                queue_path: String::from("queue.csv"),
+            }),
+            snapshot_in: None,
+            snapshot_out: None,
+            snapshot_format: SnapshotFormat::Bincode,
+            columns: None,
+            extended: false,
+            coalesce: false,
+            output: None,
+            format: ReportFormat::Csv,
+            input_format: None,
+            error_log: None,
+            strict: false,
+            duplicate_policy: DuplicatePolicy::Reject,
+            soft_limit: None,
+            maximum_amount: None,
+            maximum_policy: MaximumPolicy::Reject,
+            manifest: None,
+            client_map: None,
+            storage: None,
+            sample: None,
+            checkpoint: None,
+            unknown_types: UnknownTypePolicy::Skip,
+            column_map: ColumnMap::default(),
+            excess_precision: PrecisionPolicy::Round(RoundingMode::HalfUp),
+            rounding_mode: RoundingMode::HalfUp,
+            rates: None,
+            fees: None,
+            overdraft: None,
+            overdraft_limits: None,
+            minimum_balance: None,
+            velocity_limits: None,
+            velocity_policy: VelocityPolicy::Reject,
+            risk: None,
+            ledger: None,
+            audit_log: None,
+            double_entry: false,
+            verify: false,
+            shards: 1,
+            partitioner: Partitioning::Hash,
+            rounding: None,
+            amount_format: AmountFormat::Fixed,
+            suppress_below: None,
+            force_migrate: false,
+            stamp: false,
+            pending_disputes: None,
+            reconciliation: None,
+            dispute_grace: None,
+            reorder_window: None,
+            rate_limit: None,
+            follow: false,
+        }));
+        assert_eq!(Ok(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_snapshot_flags_returns_process_with_snapshot() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--snapshot-in",
+            "yesterday.json",
+            "--snapshot-out",
+            "state.json",
+            "--snapshot-format",
+            "json",
+        ]));
+
+        let expected = Command::Process(Box::new(RunConfig {
+            file_path: String::from("transactions.csv"),
+            approval: None,
+            snapshot_in: Some(String::from("yesterday.json")),
+            snapshot_out: Some(String::from("state.json")),
+            snapshot_format: SnapshotFormat::Json,
+            columns: None,
+            extended: false,
+            coalesce: false,
+            output: None,
+            format: ReportFormat::Csv,
+            input_format: None,
+            error_log: None,
+            strict: false,
+            duplicate_policy: DuplicatePolicy::Reject,
+            soft_limit: None,
+            maximum_amount: None,
+            maximum_policy: MaximumPolicy::Reject,
+            manifest: None,
+            client_map: None,
+            storage: None,
+            sample: None,
+            checkpoint: None,
+            unknown_types: UnknownTypePolicy::Skip,
+            column_map: ColumnMap::default(),
+            excess_precision: PrecisionPolicy::Round(RoundingMode::HalfUp),
+            rounding_mode: RoundingMode::HalfUp,
+            rates: None,
+            fees: None,
+            overdraft: None,
+            overdraft_limits: None,
+            minimum_balance: None,
+            velocity_limits: None,
+            velocity_policy: VelocityPolicy::Reject,
+            risk: None,
+            ledger: None,
+            audit_log: None,
+            double_entry: false,
+            verify: false,
+            shards: 1,
+            partitioner: Partitioning::Hash,
+            rounding: None,
+            amount_format: AmountFormat::Fixed,
+            suppress_below: None,
+            force_migrate: false,
+            stamp: false,
+            pending_disputes: None,
+            reconciliation: None,
+            dispute_grace: None,
+            reorder_window: None,
+            rate_limit: None,
+            follow: false,
+        }));
+        assert_eq!(Ok(expected), actual);
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--snapshot-format",
+            "yaml",
+        ]));
+
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("--snapshot-format"),
+            passed: String::from("yaml"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_columns_returns_selected_columns() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--columns",
+            "client, total",
+        ]));
+
+        let expected = Command::Process(Box::new(RunConfig {
+            file_path: String::from("transactions.csv"),
+            approval: None,
+            snapshot_in: None,
+            snapshot_out: None,
+            snapshot_format: SnapshotFormat::Bincode,
+            columns: Some(vec![String::from("client"), String::from("total")]),
+            extended: false,
+            coalesce: false,
+            output: None,
+            format: ReportFormat::Csv,
+            input_format: None,
+            error_log: None,
+            strict: false,
+            duplicate_policy: DuplicatePolicy::Reject,
+            soft_limit: None,
+            maximum_amount: None,
+            maximum_policy: MaximumPolicy::Reject,
+            manifest: None,
+            client_map: None,
+            storage: None,
+            sample: None,
+            checkpoint: None,
+            unknown_types: UnknownTypePolicy::Skip,
+            column_map: ColumnMap::default(),
+            excess_precision: PrecisionPolicy::Round(RoundingMode::HalfUp),
+            rounding_mode: RoundingMode::HalfUp,
+            rates: None,
+            fees: None,
+            overdraft: None,
+            overdraft_limits: None,
+            minimum_balance: None,
+            velocity_limits: None,
+            velocity_policy: VelocityPolicy::Reject,
+            risk: None,
+            ledger: None,
+            audit_log: None,
+            double_entry: false,
+            verify: false,
+            shards: 1,
+            partitioner: Partitioning::Hash,
+            rounding: None,
+            amount_format: AmountFormat::Fixed,
+            suppress_below: None,
+            force_migrate: false,
+            stamp: false,
+            pending_disputes: None,
+            reconciliation: None,
+            dispute_grace: None,
+            reorder_window: None,
+            rate_limit: None,
+            follow: false,
+        }));
+        assert_eq!(Ok(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_coalesce_returns_expected() {
+        let actual = env_args_parse(to_args(&["payments", "transactions.csv", "--coalesce"]));
+
+        let expected = Command::Process(Box::new(RunConfig {
+            file_path: String::from("transactions.csv"),
+            approval: None,
+            snapshot_in: None,
+            snapshot_out: None,
+            snapshot_format: SnapshotFormat::Bincode,
+            columns: None,
+            extended: false,
+            coalesce: true,
+            output: None,
+            format: ReportFormat::Csv,
+            input_format: None,
+            error_log: None,
+            strict: false,
+            duplicate_policy: DuplicatePolicy::Reject,
+            soft_limit: None,
+            maximum_amount: None,
+            maximum_policy: MaximumPolicy::Reject,
+            manifest: None,
+            client_map: None,
+            storage: None,
+            sample: None,
+            checkpoint: None,
+            unknown_types: UnknownTypePolicy::Skip,
+            column_map: ColumnMap::default(),
+            excess_precision: PrecisionPolicy::Round(RoundingMode::HalfUp),
+            rounding_mode: RoundingMode::HalfUp,
+            rates: None,
+            fees: None,
+            overdraft: None,
+            overdraft_limits: None,
+            minimum_balance: None,
+            velocity_limits: None,
+            velocity_policy: VelocityPolicy::Reject,
+            risk: None,
+            ledger: None,
+            audit_log: None,
+            double_entry: false,
+            verify: false,
+            shards: 1,
+            partitioner: Partitioning::Hash,
+            rounding: None,
+            amount_format: AmountFormat::Fixed,
+            suppress_below: None,
+            force_migrate: false,
+            stamp: false,
+            pending_disputes: None,
+            reconciliation: None,
+            dispute_grace: None,
+            reorder_window: None,
+            rate_limit: None,
+            follow: false,
+        }));
+        assert_eq!(Ok(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_output_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--output",
+            "accounts.csv",
+        ]));
+
+        let expected = Command::Process(Box::new(RunConfig {
+            file_path: String::from("transactions.csv"),
+            approval: None,
+            snapshot_in: None,
+            snapshot_out: None,
+            snapshot_format: SnapshotFormat::Bincode,
+            columns: None,
+            extended: false,
+            coalesce: false,
+            output: Some(String::from("accounts.csv")),
+            format: ReportFormat::Csv,
+            input_format: None,
+            error_log: None,
+            strict: false,
+            duplicate_policy: DuplicatePolicy::Reject,
+            soft_limit: None,
+            maximum_amount: None,
+            maximum_policy: MaximumPolicy::Reject,
+            manifest: None,
+            client_map: None,
+            storage: None,
+            sample: None,
+            checkpoint: None,
+            unknown_types: UnknownTypePolicy::Skip,
+            column_map: ColumnMap::default(),
+            excess_precision: PrecisionPolicy::Round(RoundingMode::HalfUp),
+            rounding_mode: RoundingMode::HalfUp,
+            rates: None,
+            fees: None,
+            overdraft: None,
+            overdraft_limits: None,
+            minimum_balance: None,
+            velocity_limits: None,
+            velocity_policy: VelocityPolicy::Reject,
+            risk: None,
+            ledger: None,
+            audit_log: None,
+            double_entry: false,
+            verify: false,
+            shards: 1,
+            partitioner: Partitioning::Hash,
+            rounding: None,
+            amount_format: AmountFormat::Fixed,
+            suppress_below: None,
+            force_migrate: false,
+            stamp: false,
+            pending_disputes: None,
+            reconciliation: None,
+            dispute_grace: None,
+            reorder_window: None,
+            rate_limit: None,
+            follow: false,
+        }));
+        assert_eq!(Ok(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_format_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--format",
+            "jsonl",
+        ]));
+
+        let expected = Command::Process(Box::new(RunConfig {
+            file_path: String::from("transactions.csv"),
+            approval: None,
+            snapshot_in: None,
+            snapshot_out: None,
+            snapshot_format: SnapshotFormat::Bincode,
+            columns: None,
+            extended: false,
+            coalesce: false,
+            output: None,
+            format: ReportFormat::JsonLines,
+            input_format: None,
+            error_log: None,
+            strict: false,
+            duplicate_policy: DuplicatePolicy::Reject,
+            soft_limit: None,
+            maximum_amount: None,
+            maximum_policy: MaximumPolicy::Reject,
+            manifest: None,
+            client_map: None,
+            storage: None,
+            sample: None,
+            checkpoint: None,
+            unknown_types: UnknownTypePolicy::Skip,
+            column_map: ColumnMap::default(),
+            excess_precision: PrecisionPolicy::Round(RoundingMode::HalfUp),
+            rounding_mode: RoundingMode::HalfUp,
+            rates: None,
+            fees: None,
+            overdraft: None,
+            overdraft_limits: None,
+            minimum_balance: None,
+            velocity_limits: None,
+            velocity_policy: VelocityPolicy::Reject,
+            risk: None,
+            ledger: None,
+            audit_log: None,
+            double_entry: false,
+            verify: false,
+            shards: 1,
+            partitioner: Partitioning::Hash,
+            rounding: None,
+            amount_format: AmountFormat::Fixed,
+            suppress_below: None,
+            force_migrate: false,
+            stamp: false,
+            pending_disputes: None,
+            reconciliation: None,
+            dispute_grace: None,
+            reorder_window: None,
+            rate_limit: None,
+            follow: false,
+        }));
+        assert_eq!(Ok(expected), actual);
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--format",
+            "xml",
+        ]));
         assert_eq!(true, actual.is_err());
+    }
 
-        let expected = EnvArgsParseError::ExpectedCsvFile {
-            passed: String::from(test_file),
+    #[test]
+    fn env_args_parse_output_without_path_returns_err() {
+        let actual = env_args_parse(to_args(&["payments", "transactions.csv", "--output"]));
+
+        let expected = EnvArgsParseError::MissingValue {
+            flag: String::from("--output"),
         };
+        assert_eq!(Err(expected), actual);
+    }
 
-        assert_eq!(expected, actual.unwrap_err());
+    #[test]
+    fn env_args_parse_approval_threshold_without_queue_returns_err() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "transactions.csv",
+            "--approval-threshold",
+            "1000",
+        ]));
+
+        let expected = EnvArgsParseError::MissingArgument {
+            flag: String::from("--approval-queue"),
+        };
+        assert_eq!(Err(expected), actual);
     }
 
     #[test]
-    fn env_args_parse_file_not_csv_returns_err_not_csv() {
-        let test_files = vec!["transactions.csvs", ".css", " ", "blah", "foo.bar", ".csv"];
-        for test_file in test_files {
-            let args = vec!["target\\debug\\payments.exe", test_file]
-                .iter()
-                .map(|s| String::from(*s))
-                .collect();
+    fn env_args_parse_unknown_flag_returns_err() {
+        let actual = env_args_parse(to_args(&["payments", "transactions.csv", "--nope"]));
 
-            let actual = env_args_parse_file(args);
-            assert_eq!(true, actual.is_err());
+        let expected = EnvArgsParseError::UnknownArgument {
+            passed: String::from("--nope"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
 
-            let expected = EnvArgsParseError::ExpectedCsvFile {
-                passed: String::from(test_file),
-            };
+    #[test]
+    fn env_args_parse_approvals_returns_action() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "approvals",
+            "queue.csv",
+            "approve",
+            "42",
+        ]));
 
-            assert_eq!(expected, actual.unwrap_err());
+        let expected = Command::Approvals {
+            queue_path: String::from("queue.csv"),
+            action: ApprovalAction::Approve(42),
+        };
+        assert_eq!(Ok(expected), actual);
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "approvals",
+            "queue.csv",
+            "reject",
+            "x",
+        ]));
+
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("reject"),
+            passed: String::from("x"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_serve_wal_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "serve",
+            "--tcp",
+            "127.0.0.1:7000",
+            "--wal",
+            "server.wal",
+        ]));
+
+        let expected = Command::Serve(Box::new(ServeConfig {
+            tcp_addr: String::from("127.0.0.1:7000"),
+            storage: None,
+            wal: Some(String::from("server.wal")),
+            max_inflight_per_client: DEFAULT_MAX_INFLIGHT_PER_CLIENT,
+            telemetry_interval: None,
+            history_cap: None,
+            history_spill: None,
+            http_addr: None,
+            protocol: WireProtocol::Framed,
+            grpc_addr: None,
+            websocket_addr: None,
+            snapshots: None,
+        }));
+        assert_eq!(Ok(expected), actual);
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "serve",
+            "--tcp",
+            "127.0.0.1:7000",
+            "--wal",
+            "server.wal",
+            "--storage",
+            "accounts.db",
+        ]));
+
+        let expected = EnvArgsParseError::ConflictingArguments {
+            flag: String::from("--wal"),
+            other: String::from("--storage"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_serve_returns_tcp_addr() {
+        let actual = env_args_parse(to_args(&["payments", "serve", "--tcp", "127.0.0.1:7000"]));
+
+        let expected = Command::Serve(Box::new(ServeConfig {
+            tcp_addr: String::from("127.0.0.1:7000"),
+            storage: None,
+            wal: None,
+            max_inflight_per_client: DEFAULT_MAX_INFLIGHT_PER_CLIENT,
+            telemetry_interval: None,
+            history_cap: None,
+            history_spill: None,
+            http_addr: None,
+            protocol: WireProtocol::Framed,
+            grpc_addr: None,
+            websocket_addr: None,
+            snapshots: None,
+        }));
+        assert_eq!(Ok(expected), actual);
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "serve",
+            "--tcp",
+            "127.0.0.1:7000",
+            "--max-inflight-per-client",
+            "8",
+        ]));
+
+        let expected = Command::Serve(Box::new(ServeConfig {
+            tcp_addr: String::from("127.0.0.1:7000"),
+            storage: None,
+            wal: None,
+            max_inflight_per_client: 8,
+            telemetry_interval: None,
+            history_cap: None,
+            history_spill: None,
+            http_addr: None,
+            protocol: WireProtocol::Framed,
+            grpc_addr: None,
+            websocket_addr: None,
+            snapshots: None,
+        }));
+        assert_eq!(Ok(expected), actual);
+
+        let actual = env_args_parse(to_args(&["payments", "serve"]));
+
+        let expected = EnvArgsParseError::MissingArgument {
+            flag: String::from("--tcp"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_serve_http_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "serve",
+            "--tcp",
+            "127.0.0.1:7000",
+            "--http",
+            "127.0.0.1:8080",
+        ]));
+
+        match actual {
+            Ok(Command::Serve(config)) => {
+                assert_eq!(Some(String::from("127.0.0.1:8080")), config.http_addr)
+            }
+            _ => panic!("expected serve arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_serve_grpc_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "serve",
+            "--tcp",
+            "127.0.0.1:7000",
+            "--grpc",
+            "127.0.0.1:50051",
+        ]));
+        match actual {
+            Ok(Command::Serve(config)) => {
+                assert_eq!(Some(String::from("127.0.0.1:50051")), config.grpc_addr)
+            }
+            _ => panic!("expected serve arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_serve_websocket_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "serve",
+            "--tcp",
+            "127.0.0.1:7000",
+            "--websocket",
+            "127.0.0.1:9001",
+        ]));
+        match actual {
+            Ok(Command::Serve(config)) => {
+                assert_eq!(Some(String::from("127.0.0.1:9001")), config.websocket_addr)
+            }
+            _ => panic!("expected serve arguments"),
+        }
+    }
+
+    #[test]
+    fn env_args_parse_serve_snapshots_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "serve",
+            "--tcp",
+            "127.0.0.1:7000",
+            "--snapshot-dir",
+            "state",
+            "--keep-last",
+            "5",
+        ]));
+        let expected = SnapshotSchedule {
+            dir: String::from("state"),
+            interval: DEFAULT_SNAPSHOT_INTERVAL,
+            format: SnapshotFormat::Bincode,
+            retention: Retention {
+                keep_last: 5,
+                keep_daily: 30,
+            },
+        };
+        match actual {
+            Ok(Command::Serve(config)) => assert_eq!(Some(expected), config.snapshots),
+            _ => panic!("expected serve arguments"),
+        }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "serve",
+            "--tcp",
+            "127.0.0.1:7000",
+            "--keep-daily",
+            "7",
+        ]));
+        let expected = EnvArgsParseError::MissingArgument {
+            flag: String::from("--snapshot-dir"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_serve_protocol_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "serve",
+            "--tcp",
+            "127.0.0.1:7000",
+            "--protocol",
+            "lines",
+        ]));
+        match actual {
+            Ok(Command::Serve(config)) => assert_eq!(WireProtocol::Lines, config.protocol),
+            _ => panic!("expected serve arguments"),
         }
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "serve",
+            "--tcp",
+            "127.0.0.1:7000",
+            "--protocol",
+            "http",
+        ]));
+        let expected = EnvArgsParseError::InvalidValue {
+            flag: String::from("--protocol"),
+            passed: String::from("http"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_serve_telemetry_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "serve",
+            "--tcp",
+            "127.0.0.1:7000",
+            "--telemetry-interval",
+            "60",
+            "--history-cap",
+            "1000",
+            "--history-spill",
+            "history.spill",
+        ]));
+
+        let expected = Command::Serve(Box::new(ServeConfig {
+            tcp_addr: String::from("127.0.0.1:7000"),
+            storage: None,
+            wal: None,
+            max_inflight_per_client: DEFAULT_MAX_INFLIGHT_PER_CLIENT,
+            telemetry_interval: Some(60),
+            history_cap: Some(1000),
+            history_spill: Some(String::from("history.spill")),
+            http_addr: None,
+            protocol: WireProtocol::Framed,
+            grpc_addr: None,
+            websocket_addr: None,
+            snapshots: None,
+        }));
+        assert_eq!(Ok(expected), actual);
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "serve",
+            "--tcp",
+            "127.0.0.1:7000",
+            "--history-spill",
+            "history.spill",
+        ]));
+        let expected = EnvArgsParseError::MissingArgument {
+            flag: String::from("--history-cap"),
+        };
+        assert_eq!(Err(expected), actual);
     }
 
     #[test]
@@ -87,7 +3647,7 @@ mod tests {
         let test_files = vec!["transactions.csv", "c::/derp.csv"];
 
         for test_file in test_files {
-            let args = vec!["target\\debug\\payments.exe", test_file]
+            let args = ["target\\debug\\payments.exe", test_file]
                 .iter()
                 .map(|s| String::from(*s))
                 .collect();
@@ -98,4 +3658,279 @@ mod tests {
             assert_eq!(String::from(test_file), actual.unwrap());
         }
     }
+
+    #[test]
+    fn env_args_parse_serve_snapshot_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "serve-snapshot",
+            "state.json",
+            "--port",
+            "8080",
+            "--snapshot-format",
+            "json",
+            "--force-migrate",
+        ]));
+
+        let expected = Command::ServeSnapshot {
+            snapshot_path: String::from("state.json"),
+            snapshot_format: SnapshotFormat::Json,
+            http_addr: String::from("127.0.0.1:8080"),
+            force_migrate: true,
+        };
+        assert_eq!(Ok(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_serve_snapshot_without_port_returns_err() {
+        let actual = env_args_parse(to_args(&["payments", "serve-snapshot", "state.bin"]));
+
+        let expected = EnvArgsParseError::MissingArgument {
+            flag: String::from("--port"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_merge_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "merge",
+            "first.json",
+            "second.json",
+            "--snapshot-format",
+            "json",
+            "--snapshot-out",
+            "merged.json",
+        ]));
+
+        let expected = Command::Merge {
+            first: String::from("first.json"),
+            second: String::from("second.json"),
+            snapshot_format: SnapshotFormat::Json,
+            snapshot_out: Some(String::from("merged.json")),
+            output: None,
+            force_migrate: false,
+        };
+        assert_eq!(Ok(expected), actual);
+
+        let actual = env_args_parse(to_args(&["payments", "merge", "first.json"]));
+        assert_eq!(Err(EnvArgsParseError::ArgumentsTooShort), actual);
+    }
+
+    #[test]
+    fn env_args_parse_diff_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "diff",
+            "before.csv",
+            "after.json",
+            "--snapshot-format",
+            "json",
+        ]));
+
+        let expected = Command::Diff {
+            before: String::from("before.csv"),
+            after: String::from("after.json"),
+            snapshot_format: SnapshotFormat::Json,
+            force_migrate: false,
+        };
+        assert_eq!(Ok(expected), actual);
+
+        let actual = env_args_parse(to_args(&["payments", "diff", "before.csv"]));
+        assert_eq!(Err(EnvArgsParseError::ArgumentsTooShort), actual);
+    }
+
+    #[test]
+    fn env_args_parse_query_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "query",
+            "--client",
+            "42",
+            "transactions.csv",
+        ]));
+
+        let expected = Command::Query {
+            client: 42,
+            file_path: Some(String::from("transactions.csv")),
+            snapshot_path: None,
+            snapshot_format: SnapshotFormat::Bincode,
+            force_migrate: false,
+            history: false,
+        };
+        assert_eq!(Ok(expected), actual);
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "query",
+            "--snapshot",
+            "state.json",
+            "--snapshot-format",
+            "json",
+            "--client",
+            "7",
+            "--history",
+        ]));
+        let expected = Command::Query {
+            client: 7,
+            file_path: None,
+            snapshot_path: Some(String::from("state.json")),
+            snapshot_format: SnapshotFormat::Json,
+            force_migrate: false,
+            history: true,
+        };
+        assert_eq!(Ok(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_query_without_input_returns_err() {
+        let actual = env_args_parse(to_args(&["payments", "query", "--client", "42"]));
+        assert_eq!(Err(EnvArgsParseError::ArgumentsTooShort), actual);
+
+        let actual = env_args_parse(to_args(&["payments", "query", "transactions.csv"]));
+        let expected = EnvArgsParseError::MissingArgument {
+            flag: String::from("--client"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_repl_returns_expected() {
+        let actual = env_args_parse(to_args(&["payments", "repl"]));
+        let expected = Command::Repl {
+            snapshot_path: None,
+            snapshot_format: SnapshotFormat::Bincode,
+            force_migrate: false,
+        };
+        assert_eq!(Ok(expected), actual);
+
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "repl",
+            "--snapshot",
+            "state.bin",
+            "--force-migrate",
+        ]));
+        let expected = Command::Repl {
+            snapshot_path: Some(String::from("state.bin")),
+            snapshot_format: SnapshotFormat::Bincode,
+            force_migrate: true,
+        };
+        assert_eq!(Ok(expected), actual);
+
+        let actual = env_args_parse(to_args(&["payments", "repl", "transactions.csv"]));
+        let expected = EnvArgsParseError::UnknownArgument {
+            passed: String::from("transactions.csv"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_conformance_returns_expected() {
+        assert_eq!(
+            Ok(Command::Conformance { suite_path: None }),
+            env_args_parse(to_args(&["payments", "conformance"]))
+        );
+        assert_eq!(
+            Ok(Command::Conformance {
+                suite_path: Some(String::from("cases.json"))
+            }),
+            env_args_parse(to_args(&["payments", "conformance", "cases.json"]))
+        );
+    }
+
+    #[test]
+    fn env_args_parse_backtest_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "backtest",
+            "transactions.csv",
+            "--policy",
+            "baseline",
+            "--policy",
+            "strict:approval-threshold=1000",
+        ]));
+
+        let expected = Command::Backtest {
+            file_path: String::from("transactions.csv"),
+            input_format: None,
+            policies: vec![
+                Policy::from_str("baseline").unwrap(),
+                Policy::from_str("strict:approval-threshold=1000").unwrap(),
+            ],
+        };
+        assert_eq!(Ok(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_backtest_without_policy_returns_err() {
+        let actual = env_args_parse(to_args(&["payments", "backtest", "transactions.csv"]));
+
+        let expected = EnvArgsParseError::MissingArgument {
+            flag: String::from("--policy"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_export_client_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "export-client",
+            "--id",
+            "42",
+            "--snapshot",
+            "state.bin",
+            "--redact-amounts",
+        ]));
+
+        let expected = Command::ExportClient {
+            snapshot_path: String::from("state.bin"),
+            snapshot_format: SnapshotFormat::Bincode,
+            client: 42,
+            redact_amounts: true,
+            output: None,
+            force_migrate: false,
+        };
+        assert_eq!(Ok(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_export_client_without_id_returns_err() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "export-client",
+            "--snapshot",
+            "state.bin",
+        ]));
+
+        let expected = EnvArgsParseError::MissingArgument {
+            flag: String::from("--id"),
+        };
+        assert_eq!(Err(expected), actual);
+    }
+
+    #[test]
+    fn env_args_parse_erase_client_returns_expected() {
+        let actual = env_args_parse(to_args(&[
+            "payments",
+            "erase-client",
+            "--id",
+            "42",
+            "--snapshot",
+            "state.bin",
+            "--audit-log",
+            "audit.jsonl",
+        ]));
+
+        let expected = Command::EraseClient {
+            snapshot_path: String::from("state.bin"),
+            snapshot_format: SnapshotFormat::Bincode,
+            client: 42,
+            audit_log: Some(String::from("audit.jsonl")),
+            force_migrate: false,
+        };
+        assert_eq!(Ok(expected), actual);
+    }
 }
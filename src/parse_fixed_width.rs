@@ -0,0 +1,385 @@
+use crate::{
+    parse_csv::{CsvRecord, ParseCsvError, TransactionKinds},
+    source::{InputPosition, SourceError, TransactionSource},
+    transaction::Transaction,
+};
+use std::fmt::Display;
+use std::io::BufRead;
+use std::str::FromStr;
+
+// Fixed-width text, as produced by legacy mainframe feeds, one transaction per line with each
+// field padded to the width of its column:
+//  deposit   000001000000000100000000001.5
+// The columns are given by a spec such as `type:10,client:6,tx:10,amount:14`, which is also the
+// default. Columns named `skip` are filler and ignored, and a line may stop short of its last
+// columns, which are then empty. Fields are trimmed and converted as CSV records so all formats
+// share the same validation.
+
+/// The most columns a spec may have, so specs can be copied like the other input formats.
+const MAX_COLUMNS: usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Column {
+    Type,
+    Client,
+    Tx,
+    Amount,
+    Reason,
+    To,
+    Case,
+    Timestamp,
+    Currency,
+    ToCurrency,
+    Converted,
+    /// Padding between the fields that are read.
+    Skip,
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "type" => Ok(Self::Type),
+            "client" => Ok(Self::Client),
+            "tx" => Ok(Self::Tx),
+            "amount" => Ok(Self::Amount),
+            "reason" => Ok(Self::Reason),
+            "to" => Ok(Self::To),
+            "case" => Ok(Self::Case),
+            "timestamp" => Ok(Self::Timestamp),
+            "currency" => Ok(Self::Currency),
+            "to_currency" => Ok(Self::ToCurrency),
+            "converted" => Ok(Self::Converted),
+            "skip" => Ok(Self::Skip),
+            _ => Err(String::from(s)),
+        }
+    }
+}
+
+/// The columns of each line, in order, with their widths in bytes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FixedWidthSpec {
+    columns: [(Column, u16); MAX_COLUMNS],
+    len: usize,
+}
+
+impl Default for FixedWidthSpec {
+    /// The layout of the mainframe feed: type 10, client 6, tx 10 and amount 14.
+    fn default() -> Self {
+        Self::new(&[
+            (Column::Type, 10),
+            (Column::Client, 6),
+            (Column::Tx, 10),
+            (Column::Amount, 14),
+        ])
+        .expect("the default spec is valid")
+    }
+}
+
+impl FixedWidthSpec {
+    /// Fails if a column other than `skip` is repeated or has no width, if `type`, `client` or
+    /// `tx` is missing, or if there are too many columns.
+    pub fn new(columns: &[(Column, u16)]) -> Result<Self, String> {
+        if columns.len() > MAX_COLUMNS {
+            return Err(format!("more than {} columns", MAX_COLUMNS));
+        }
+        for (index, (column, width)) in columns.iter().enumerate() {
+            if *width == 0 {
+                return Err(format!("{:?} column has no width", column));
+            }
+            if *column != Column::Skip && columns[..index].iter().any(|(c, _)| c == column) {
+                return Err(format!("{:?} column is repeated", column));
+            }
+        }
+        for required in [Column::Type, Column::Client, Column::Tx] {
+            if !columns.iter().any(|(column, _)| *column == required) {
+                return Err(format!("{:?} column is missing", required));
+            }
+        }
+
+        let mut spec = Self {
+            columns: [(Column::Skip, 0); MAX_COLUMNS],
+            len: columns.len(),
+        };
+        spec.columns[..columns.len()].copy_from_slice(columns);
+        Ok(spec)
+    }
+
+    pub fn columns(&self) -> &[(Column, u16)] {
+        &self.columns[..self.len]
+    }
+
+    /// Splits the line into its trimmed fields, leaving out filler.
+    fn fields<'a>(&self, line: &'a str) -> Result<Fields<'a>, ParseCsvError> {
+        let mut fields = Fields::default();
+        let mut start = 0;
+        for (column, width) in self.columns() {
+            let width = usize::from(*width);
+            let end = (start + width).min(line.len());
+            let field = line
+                .get(start.min(end)..end)
+                .ok_or_else(|| {
+                    ParseCsvError::Other(format!("{:?} column splits a character", column).into())
+                })?
+                .trim();
+            start += width;
+
+            let field = if field.is_empty() { None } else { Some(field) };
+            match column {
+                Column::Type => fields.transaction_type = field,
+                Column::Client => fields.client = field,
+                Column::Tx => fields.tx = field,
+                Column::Amount => fields.amount = field,
+                Column::Reason => fields.reason = field,
+                Column::To => fields.to = field,
+                Column::Case => fields.case = field,
+                Column::Timestamp => fields.timestamp = field,
+                Column::Currency => fields.currency = field,
+                Column::ToCurrency => fields.to_currency = field,
+                Column::Converted => fields.converted = field,
+                Column::Skip => {}
+            }
+        }
+        Ok(fields)
+    }
+}
+
+impl FromStr for FixedWidthSpec {
+    type Err = String;
+
+    /// Parses comma separated `name:width` columns, e.g. `type:10,client:6,tx:10,amount:14`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let columns = s
+            .split(',')
+            .map(|column| {
+                let (name, width) = column
+                    .trim()
+                    .split_once(':')
+                    .ok_or_else(|| String::from(column))?;
+                let width = width.trim().parse().map_err(|_| String::from(column))?;
+                Ok((name.trim().parse()?, width))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Self::new(&columns)
+    }
+}
+
+/// The fields of a line that aren't empty.
+#[derive(Default)]
+struct Fields<'a> {
+    transaction_type: Option<&'a str>,
+    client: Option<&'a str>,
+    tx: Option<&'a str>,
+    amount: Option<&'a str>,
+    reason: Option<&'a str>,
+    to: Option<&'a str>,
+    case: Option<&'a str>,
+    timestamp: Option<&'a str>,
+    currency: Option<&'a str>,
+    to_currency: Option<&'a str>,
+    converted: Option<&'a str>,
+}
+
+impl<'a> Fields<'a> {
+    fn into_record(self) -> Result<CsvRecord<'a>, ParseCsvError> {
+        Ok(CsvRecord {
+            transaction_type: self.transaction_type.ok_or_else(|| missing("type"))?,
+            client: self.client.ok_or_else(|| missing("client"))?,
+            tx: parse_field(self.tx.ok_or_else(|| missing("tx"))?, "tx")?,
+            amount: self.amount,
+            reason: self
+                .reason
+                .map(|reason| parse_field(reason, "reason"))
+                .transpose()?,
+            to: self.to.map(|to| parse_field(to, "to")).transpose()?,
+            case: self
+                .case
+                .map(|case| parse_field(case, "case"))
+                .transpose()?,
+            timestamp: self
+                .timestamp
+                .map(|timestamp| parse_field(timestamp, "timestamp"))
+                .transpose()?,
+            currency: self
+                .currency
+                .map(|currency| parse_field(currency, "currency"))
+                .transpose()?,
+            to_currency: self
+                .to_currency
+                .map(|currency| parse_field(currency, "to_currency"))
+                .transpose()?,
+            converted: self.converted,
+        })
+    }
+}
+
+fn parse_field<T>(value: &str, name: &str) -> Result<T, ParseCsvError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| ParseCsvError::Other(format!("invalid {} {}: {}", name, value, e).into()))
+}
+
+fn missing(name: &str) -> ParseCsvError {
+    ParseCsvError::Other(format!("missing {}", name).into())
+}
+
+/// Reads one fixed-width transaction per line, skipping blank lines.
+pub struct FixedWidthSource<R: BufRead> {
+    reader: R,
+    spec: FixedWidthSpec,
+    buffer: String,
+    position: InputPosition,
+    strict: bool,
+    kinds: TransactionKinds,
+}
+
+impl<R: BufRead> FixedWidthSource<R> {
+    pub fn new(reader: R, spec: FixedWidthSpec) -> Self {
+        Self::resume(reader, spec, InputPosition::default())
+    }
+
+    /// Reads from a reader that has already been moved to the given position of its file.
+    pub fn resume(reader: R, spec: FixedWidthSpec, position: InputPosition) -> Self {
+        Self {
+            reader,
+            spec,
+            buffer: String::new(),
+            position,
+            strict: false,
+            kinds: TransactionKinds::default(),
+        }
+    }
+
+    /// Returns records that would be ignored as errors instead of skipping them.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// How records of a type the engine doesn't know are handled.
+    pub fn kinds(mut self, kinds: TransactionKinds) -> Self {
+        self.kinds = kinds;
+        self
+    }
+}
+
+impl<R: BufRead> TransactionSource for FixedWidthSource<R> {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        loop {
+            self.buffer.clear();
+            match self.reader.read_line(&mut self.buffer) {
+                Ok(0) => return None,
+                Ok(read) => self.position.byte += read as u64,
+                Err(e) => return Some(Err(e.into())),
+            }
+            self.position.line += 1;
+
+            // Padding is part of the columns, so only the line ending is removed.
+            let line = self.buffer.trim_end_matches(&['\r', '\n'][..]);
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let line_number = self.position.line;
+            let parsed = match self.spec.fields(line).and_then(Fields::into_record) {
+                Ok(record) => self.kinds.convert(record, line_number, self.strict),
+                Err(error) => Err(SourceError::InvalidRecord {
+                    line: line_number,
+                    error: Box::new(error),
+                }),
+            };
+
+            return Some(parsed);
+        }
+    }
+
+    fn line(&self) -> Option<u64> {
+        Some(self.position.line)
+    }
+
+    fn position(&self) -> Option<InputPosition> {
+        Some(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::amount;
+    use crate::transaction::TransactionType;
+
+    #[test]
+    fn fixed_width_spec_from_str_returns_expected() {
+        assert_eq!(
+            Ok(FixedWidthSpec::default()),
+            FixedWidthSpec::from_str("type:10, client:6, tx:10, amount:14")
+        );
+
+        let spec =
+            FixedWidthSpec::from_str("skip:2,type:10,client:6,tx:10,skip:4,amount:14").unwrap();
+        assert_eq!(6, spec.columns().len());
+
+        for passed in [
+            "type:10,client:6",
+            "type:10,client:6,tx:10,tx:10",
+            "type:10,client:6,tx:0",
+            "type:10,client:6,tx:10,balance:14",
+            "type10,client:6,tx:10",
+        ] {
+            assert_eq!(
+                true,
+                FixedWidthSpec::from_str(passed).is_err(),
+                "{}",
+                passed
+            );
+        }
+    }
+
+    #[test]
+    fn fixed_width_source_reads_padded_columns() {
+        let input = "deposit   000001000000000100000000001.5\r\n\
+                     \n\
+                     withdrawal     2         2         0.25\n\
+                     dispute   0000010000000001\n";
+        let mut source = FixedWidthSource::new(input.as_bytes(), FixedWidthSpec::default());
+
+        let expected = [
+            (TransactionType::Deposit(amount("1.5")), 1, 1),
+            (TransactionType::Withdrawal(amount("0.25")), 2, 2),
+            (TransactionType::Dispute { case: None }, 1, 1),
+        ];
+        for (transaction_type, client, id) in expected.iter() {
+            let expected = Transaction {
+                transaction_type: *transaction_type,
+                client: *client,
+                id: *id,
+                timestamp: None,
+                currency: None,
+            };
+            assert_eq!(Some(expected), source.next().map(Result::unwrap));
+        }
+        assert_eq!(Some(4), source.line());
+        assert_eq!(true, source.next().is_none());
+    }
+
+    #[test]
+    fn fixed_width_source_returns_invalid_records() {
+        let input = "deposit   00000100000000x100000000001.5\ndeposit   000001\n";
+        let mut source = FixedWidthSource::new(input.as_bytes(), FixedWidthSpec::default());
+
+        for line in [1, 2] {
+            match source.next() {
+                Some(Err(SourceError::InvalidRecord { line: actual, .. })) => {
+                    assert_eq!(line, actual)
+                }
+                other => panic!("expected an invalid record, got {:?}", other),
+            }
+        }
+        assert_eq!(true, source.next().is_none());
+    }
+}
@@ -0,0 +1,216 @@
+use crate::{
+    client::ClientId,
+    clock::Timestamp,
+    currency::Currency,
+    parse_csv::{CsvTransaction, TransactionKinds},
+    source::{InputPosition, SourceError, TransactionSource},
+    transaction::{CaseId, ReasonCode, Transaction, TransactionId},
+};
+use std::error::Error;
+use std::io::BufRead;
+
+// Newline delimited JSON, one transaction object per line with the same fields as the CSV input:
+//  {"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+// Records are converted to CSV records so both formats share the same validation.
+
+/// Amounts may be given as strings or numbers. Strings are preferred as they keep the exact value.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum JsonAmount {
+    Text(String),
+    Number(serde_json::Number),
+}
+
+impl JsonAmount {
+    fn into_text(self) -> String {
+        match self {
+            Self::Text(text) => text,
+            Self::Number(number) => number.to_string(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct JsonTransaction {
+    #[serde(rename = "type")]
+    transaction_type: String,
+    client: ClientId,
+    tx: TransactionId,
+    #[serde(default)]
+    amount: Option<JsonAmount>,
+    #[serde(default)]
+    reason: Option<ReasonCode>,
+    #[serde(default)]
+    to: Option<ClientId>,
+    #[serde(default)]
+    case: Option<CaseId>,
+    #[serde(default)]
+    timestamp: Option<Timestamp>,
+    #[serde(default)]
+    currency: Option<Currency>,
+    #[serde(default)]
+    to_currency: Option<Currency>,
+    #[serde(default)]
+    converted: Option<JsonAmount>,
+}
+
+impl From<JsonTransaction> for CsvTransaction {
+    fn from(record: JsonTransaction) -> Self {
+        Self {
+            transaction_type: record.transaction_type,
+            client: record.client.to_string(),
+            tx: record.tx,
+            amount: record.amount.map(JsonAmount::into_text),
+            reason: record.reason,
+            to: record.to,
+            case: record.case,
+            timestamp: record.timestamp,
+            currency: record.currency,
+            to_currency: record.to_currency,
+            converted: record.converted.map(JsonAmount::into_text),
+        }
+    }
+}
+
+/// Parses a single JSON object into a transaction. Returns `None` for records that are ignored,
+/// just as the CSV parser does.
+pub fn parse_line(line: &str) -> Result<Option<Transaction>, Box<dyn Error>> {
+    let record: JsonTransaction = serde_json::from_str(line)?;
+    Ok(CsvTransaction::from(record).into_transaction()?)
+}
+
+/// Parses a single JSON object into a transaction, returning an error for records that would be
+/// ignored.
+pub fn parse_line_strict(line: &str) -> Result<Transaction, Box<dyn Error>> {
+    let record: JsonTransaction = serde_json::from_str(line)?;
+    Ok(CsvTransaction::from(record).parse()?)
+}
+
+/// Reads one JSON transaction per line, skipping blank lines.
+pub struct JsonlSource<R: BufRead> {
+    reader: R,
+    position: InputPosition,
+    strict: bool,
+    kinds: TransactionKinds,
+}
+
+impl<R: BufRead> JsonlSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self::resume(reader, InputPosition::default())
+    }
+
+    /// Reads from a reader that has already been moved to the given position of its file.
+    pub fn resume(reader: R, position: InputPosition) -> Self {
+        Self {
+            reader,
+            position,
+            strict: false,
+            kinds: TransactionKinds::default(),
+        }
+    }
+
+    /// Returns records that would be ignored as errors instead of skipping them.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// How records of a type the engine doesn't know are handled.
+    pub fn kinds(mut self, kinds: TransactionKinds) -> Self {
+        self.kinds = kinds;
+        self
+    }
+}
+
+impl<R: BufRead> TransactionSource for JsonlSource<R> {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        let mut buffer = String::new();
+
+        loop {
+            buffer.clear();
+            match self.reader.read_line(&mut buffer) {
+                Ok(0) => return None,
+                Ok(read) => self.position.byte += read as u64,
+                Err(e) => return Some(Err(e.into())),
+            }
+            self.position.line += 1;
+
+            if buffer.trim().is_empty() {
+                continue;
+            }
+
+            let line = self.position.line;
+            let parsed = match serde_json::from_str::<JsonTransaction>(&buffer) {
+                Ok(record) => {
+                    let record = CsvTransaction::from(record);
+                    self.kinds.convert(record.as_record(), line, self.strict)
+                }
+                Err(e) => Err(SourceError::InvalidRecord {
+                    line,
+                    error: e.into(),
+                }),
+            };
+
+            return Some(parsed);
+        }
+    }
+
+    fn line(&self) -> Option<u64> {
+        Some(self.position.line)
+    }
+
+    fn position(&self) -> Option<InputPosition> {
+        Some(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::amount;
+    use crate::transaction::TransactionType;
+
+    #[test]
+    fn parse_line_returns_expected() {
+        let actual = parse_line(r#"{"type": "deposit", "client": 1, "tx": 2, "amount": "1.5"}"#)
+            .unwrap()
+            .unwrap();
+
+        let expected = Transaction {
+            transaction_type: TransactionType::Deposit(amount("1.5")),
+            client: 1,
+            id: 2,
+            timestamp: None,
+            currency: None,
+        };
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parse_line_numeric_amount_returns_expected() {
+        let actual = parse_line(r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": 2.25}"#)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(Some(amount("2.25")), actual.amount());
+    }
+
+    #[test]
+    fn parse_line_garbage_returns_err() {
+        assert_eq!(true, parse_line(r#"{"type": "deposit"}"#).is_err());
+        assert_eq!(true, parse_line("deposit, 1, 2, 1.5").is_err());
+    }
+
+    #[test]
+    fn jsonl_source_skips_blank_lines_and_reports_line() {
+        let input = "{\"type\": \"dispute\", \"client\": 1, \"tx\": 2}\n\n{\"type\": 3}\n";
+        let mut source = JsonlSource::new(input.as_bytes());
+
+        assert_eq!(true, source.next().unwrap().is_ok());
+        match source.next() {
+            Some(Err(SourceError::InvalidRecord { line, .. })) => assert_eq!(3, line),
+            _ => panic!("expected an invalid record"),
+        }
+        assert_eq!(true, source.next().is_none());
+    }
+}
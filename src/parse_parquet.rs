@@ -0,0 +1,377 @@
+use crate::{
+    parse_csv::{CsvRecord, ParseCsvError, TransactionKinds},
+    source::{InputPosition, SourceError, TransactionSource},
+    transaction::Transaction,
+};
+use arrow_array::{cast::AsArray, Array, RecordBatch, StringArray};
+use arrow_cast::cast;
+use arrow_schema::{ArrowError, DataType};
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use parquet::arrow::ProjectionMask;
+use parquet::file::reader::ChunkReader;
+use std::fmt::Display;
+use std::io::Read;
+use std::str::FromStr;
+
+// Parquet files, such as data lake exports, with the same columns as the CSV input: `type`,
+// `client`, `tx` and an optional `amount`, along with the optional `reason`, `to`, `case`,
+// `timestamp`, which is in seconds since the Unix epoch, `currency`, `to_currency` and
+// `converted`.
+// Columns are read a batch at a time and cast to strings, so amounts may be stored as strings,
+// decimals or floats and ids as any integer type. Each row is then converted as a CSV record so
+// all formats share the same validation. Rows are counted as lines, from 1.
+
+const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+const COLUMNS: [&str; 11] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "reason",
+    "to",
+    "case",
+    "timestamp",
+    "currency",
+    "to_currency",
+    "converted",
+];
+
+/// Reads transactions from a Parquet file, one per row.
+pub struct ParquetSource {
+    batches: Option<ParquetRecordBatchReader>,
+    /// Why the file couldn't be opened, returned by the first call to `next`.
+    error: Option<SourceError>,
+    batch: Option<Batch>,
+    line: u64,
+    strict: bool,
+    kinds: TransactionKinds,
+}
+
+impl ParquetSource {
+    pub fn new<T: ChunkReader + 'static>(input: T) -> Self {
+        Self::resume(input, InputPosition::default())
+    }
+
+    /// Reads the rows after the given position, whose lines are the rows already read.
+    pub fn resume<T: ChunkReader + 'static>(input: T, position: InputPosition) -> Self {
+        let (batches, error) = match open(input, position.line) {
+            Ok(batches) => (Some(batches), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            batches,
+            error,
+            batch: None,
+            line: position.line,
+            strict: false,
+            kinds: TransactionKinds::default(),
+        }
+    }
+
+    /// Reads the whole input into memory first, as a Parquet file is read starting from its
+    /// footer. Files are read from disk as they're needed when given to `new` instead.
+    pub fn from_reader(mut reader: Box<dyn Read>) -> Self {
+        let mut buffer = vec![];
+        match reader.read_to_end(&mut buffer) {
+            Ok(_) => Self::new(bytes::Bytes::from(buffer)),
+            Err(e) => Self {
+                batches: None,
+                error: Some(e.into()),
+                batch: None,
+                line: 0,
+                strict: false,
+                kinds: TransactionKinds::default(),
+            },
+        }
+    }
+
+    /// Returns records that would be ignored as errors instead of skipping them.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// How records of a type the engine doesn't know are handled.
+    pub fn kinds(mut self, kinds: TransactionKinds) -> Self {
+        self.kinds = kinds;
+        self
+    }
+
+    /// Reads the next batch once the current one has been read.
+    fn next_batch(&mut self) -> Option<Result<(), SourceError>> {
+        if self
+            .batch
+            .as_ref()
+            .is_some_and(|batch| batch.row < batch.rows)
+        {
+            return Some(Ok(()));
+        }
+
+        let batch = self.batches.as_mut()?.next()?;
+        match batch.and_then(|batch| Batch::new(&batch)) {
+            Ok(batch) => {
+                self.batch = Some(batch);
+                Some(Ok(()))
+            }
+            Err(e) => Some(Err(SourceError::invalid_data(e))),
+        }
+    }
+}
+
+impl TransactionSource for ParquetSource {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        if let Some(error) = self.error.take() {
+            return Some(Err(error));
+        }
+
+        if let Err(error) = self.next_batch()? {
+            return Some(Err(error));
+        }
+        let batch = self.batch.as_mut()?;
+        let row = batch.row;
+        batch.row += 1;
+        self.line += 1;
+
+        let parsed = match batch.record(row) {
+            Ok(record) => self.kinds.convert(record, self.line, self.strict),
+            Err(error) => Err(SourceError::InvalidRecord {
+                line: self.line,
+                error: Box::new(error),
+            }),
+        };
+
+        Some(parsed)
+    }
+
+    fn line(&self) -> Option<u64> {
+        Some(self.line)
+    }
+
+    /// Rows are skipped rather than sought when resuming, so only the line is kept.
+    fn position(&self) -> Option<InputPosition> {
+        Some(InputPosition {
+            byte: 0,
+            line: self.line,
+        })
+    }
+}
+
+/// Reads the transaction columns of the file, skipping the given number of rows.
+fn open<T: ChunkReader + 'static>(
+    input: T,
+    skip: u64,
+) -> Result<ParquetRecordBatchReader, SourceError> {
+    let builder =
+        ParquetRecordBatchReaderBuilder::try_new(input).map_err(SourceError::invalid_data)?;
+    for name in REQUIRED_COLUMNS.iter() {
+        if builder.schema().field_with_name(name).is_err() {
+            return Err(SourceError::invalid_data(format!(
+                "missing column {}",
+                name
+            )));
+        }
+    }
+
+    let columns = ProjectionMask::columns(builder.parquet_schema(), COLUMNS.iter().copied());
+    builder
+        .with_projection(columns)
+        .with_offset(skip as usize)
+        .build()
+        .map_err(SourceError::invalid_data)
+}
+
+/// A batch of rows with every column cast to strings, as the CSV parser reads them.
+struct Batch {
+    rows: usize,
+    row: usize,
+    types: StringArray,
+    clients: StringArray,
+    txs: StringArray,
+    amounts: Option<StringArray>,
+    reasons: Option<StringArray>,
+    tos: Option<StringArray>,
+    cases: Option<StringArray>,
+    timestamps: Option<StringArray>,
+    currencies: Option<StringArray>,
+    to_currencies: Option<StringArray>,
+    converted: Option<StringArray>,
+}
+
+impl Batch {
+    fn new(batch: &RecordBatch) -> Result<Self, ArrowError> {
+        let required = |name| {
+            column(batch, name)?
+                .ok_or_else(|| ArrowError::SchemaError(format!("missing column {}", name)))
+        };
+        Ok(Self {
+            rows: batch.num_rows(),
+            row: 0,
+            types: required("type")?,
+            clients: required("client")?,
+            txs: required("tx")?,
+            amounts: column(batch, "amount")?,
+            reasons: column(batch, "reason")?,
+            tos: column(batch, "to")?,
+            cases: column(batch, "case")?,
+            timestamps: column(batch, "timestamp")?,
+            currencies: column(batch, "currency")?,
+            to_currencies: column(batch, "to_currency")?,
+            converted: column(batch, "converted")?,
+        })
+    }
+
+    fn record(&self, row: usize) -> Result<CsvRecord<'_>, ParseCsvError> {
+        let tx = value(Some(&self.txs), row).ok_or_else(|| missing("tx"))?;
+        Ok(CsvRecord {
+            transaction_type: value(Some(&self.types), row).ok_or_else(|| missing("type"))?,
+            client: value(Some(&self.clients), row).ok_or_else(|| missing("client"))?,
+            tx: parse_field(tx, "tx")?,
+            amount: value(self.amounts.as_ref(), row),
+            reason: value(self.reasons.as_ref(), row)
+                .map(|reason| parse_field(reason, "reason"))
+                .transpose()?,
+            to: value(self.tos.as_ref(), row)
+                .map(|to| parse_field(to, "to"))
+                .transpose()?,
+            case: value(self.cases.as_ref(), row)
+                .map(|case| parse_field(case, "case"))
+                .transpose()?,
+            timestamp: value(self.timestamps.as_ref(), row)
+                .map(|timestamp| parse_field(timestamp, "timestamp"))
+                .transpose()?,
+            currency: value(self.currencies.as_ref(), row)
+                .map(|currency| parse_field(currency, "currency"))
+                .transpose()?,
+            to_currency: value(self.to_currencies.as_ref(), row)
+                .map(|currency| parse_field(currency, "to_currency"))
+                .transpose()?,
+            converted: value(self.converted.as_ref(), row),
+        })
+    }
+}
+
+/// The named column cast to strings, if the batch has it.
+fn column(batch: &RecordBatch, name: &str) -> Result<Option<StringArray>, ArrowError> {
+    batch
+        .column_by_name(name)
+        .map(|column| Ok(cast(column, &DataType::Utf8)?.as_string::<i32>().clone()))
+        .transpose()
+}
+
+/// The value of the row, unless the column is missing or the value is null.
+fn value(column: Option<&StringArray>, row: usize) -> Option<&str> {
+    column
+        .filter(|column| column.is_valid(row))
+        .map(|column| column.value(row))
+}
+
+fn parse_field<T>(value: &str, name: &str) -> Result<T, ParseCsvError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e| ParseCsvError::Other(format!("invalid {} {}: {}", name, value, e).into()))
+}
+
+fn missing(name: &str) -> ParseCsvError {
+    ParseCsvError::Other(format!("missing {}", name).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::Amount, transaction::TransactionType};
+    use arrow_array::{ArrayRef, Decimal128Array, Int64Array, UInt16Array};
+    use parquet::arrow::ArrowWriter;
+    use std::sync::Arc;
+
+    fn parquet_file(columns: Vec<(&str, ArrayRef)>) -> bytes::Bytes {
+        let batch = RecordBatch::try_from_iter(columns).unwrap();
+        let mut output = vec![];
+        let mut writer = ArrowWriter::try_new(&mut output, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        bytes::Bytes::from(output)
+    }
+
+    fn transactions() -> bytes::Bytes {
+        let amounts = Decimal128Array::from(vec![Some(15000), Some(5000), None])
+            .with_precision_and_scale(18, 4)
+            .unwrap();
+        parquet_file(vec![
+            (
+                "type",
+                Arc::new(StringArray::from(vec!["deposit", "withdrawal", "dispute"])),
+            ),
+            ("client", Arc::new(UInt16Array::from(vec![1, 1, 1]))),
+            ("tx", Arc::new(Int64Array::from(vec![1, 2, 1]))),
+            ("amount", Arc::new(amounts)),
+        ])
+    }
+
+    #[test]
+    fn parquet_source_maps_columns_into_transactions() {
+        let mut source = ParquetSource::new(transactions());
+
+        let expected = [
+            (TransactionType::Deposit(Amount::new(15000)), 1),
+            (TransactionType::Withdrawal(Amount::new(5000)), 2),
+            (TransactionType::Dispute { case: None }, 1),
+        ];
+        for (transaction_type, id) in expected.iter() {
+            let expected = Transaction {
+                transaction_type: *transaction_type,
+                client: 1,
+                id: *id,
+                timestamp: None,
+                currency: None,
+            };
+            assert_eq!(Some(expected), source.next().map(Result::unwrap));
+        }
+        assert_eq!(Some(3), source.line());
+        assert_eq!(true, source.next().is_none());
+    }
+
+    #[test]
+    fn parquet_source_resume_skips_rows_read() {
+        let position = InputPosition { byte: 0, line: 2 };
+        let mut source = ParquetSource::resume(transactions(), position);
+
+        let transaction = source.next().unwrap().unwrap();
+        assert_eq!(
+            TransactionType::Dispute { case: None },
+            transaction.transaction_type
+        );
+        assert_eq!(Some(3), source.line());
+        assert_eq!(true, source.next().is_none());
+    }
+
+    #[test]
+    fn parquet_source_returns_invalid_records_and_missing_columns() {
+        let file = parquet_file(vec![
+            (
+                "type",
+                Arc::new(StringArray::from(vec!["deposit", "deposit"])),
+            ),
+            ("client", Arc::new(Int64Array::from(vec![-1, 2]))),
+            ("tx", Arc::new(Int64Array::from(vec![Some(1), None]))),
+            ("amount", Arc::new(StringArray::from(vec!["1.0", "2.0"]))),
+        ]);
+        let mut source = ParquetSource::new(file);
+        for _ in 0..2 {
+            match source.next() {
+                Some(Err(SourceError::InvalidRecord { .. })) => {}
+                other => panic!("expected an invalid record, got {:?}", other),
+            }
+        }
+        assert_eq!(true, source.next().is_none());
+
+        let file = parquet_file(vec![("type", Arc::new(StringArray::from(vec!["deposit"])))]);
+        let mut source = ParquetSource::new(file);
+        assert_eq!(true, matches!(source.next(), Some(Err(SourceError::Io(_)))));
+        assert_eq!(true, source.next().is_none());
+    }
+}
@@ -0,0 +1,2207 @@
+#[cfg(feature = "avro-input")]
+use crate::parse_avro::AvroSource;
+#[cfg(feature = "parquet-input")]
+use crate::parse_parquet::ParquetSource;
+use crate::{
+    amount::Amount,
+    approval::{self, ApprovalError, ApprovalQueue},
+    checkpoint::Checkpointer,
+    client::ClientAccount,
+    clock::Timestamp,
+    coalesce::{self, Step},
+    compression,
+    database::Database,
+    enrich::{self, Enricher},
+    follow::{Follow, FollowSource},
+    grace::GraceSource,
+    parallel_csv::ParallelCsvSource,
+    parse_csv::{self, CsvSource, TransactionKinds},
+    parse_fixed_width::{FixedWidthSource, FixedWidthSpec},
+    parse_jsonl::JsonlSource,
+    remap::{ClientMap, OriginalClientColumn, RemapSource},
+    reorder::ReorderSource,
+    report::Report,
+    risk::{RiskPolicy, RiskScorer},
+    sample::{SampleRate, SampleSource},
+    shard::{Partitioner, Partitioning, ShardPool},
+    sink::{CsvReportSink, ErrorSink, EventSink, ReportSink, SinkError},
+    snapshot::SnapshotError,
+    source::{self, InputPosition, SourceError, TransactionSource},
+    throttle::{RateLimit, ThrottleSource},
+    transaction::{Transaction, TransactionError, TransactionType, Warning},
+};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+
+// The pipeline reads transactions from an input, applies them to a database and writes the
+// account report to a sink. Every stage can be swapped out through the builder.
+
+/// Notified of the outcome of every transaction the pipeline applies.
+pub trait TransactionObserver {
+    /// Called after a transaction has been applied or rejected.
+    fn on_transaction(&mut self, transaction: &Transaction, result: &Result<(), TransactionError>);
+
+    /// Called after an applied transaction has been flagged.
+    fn on_warning(&mut self, _transaction: &Transaction, _warning: &Warning) {}
+
+    /// Called after a record of an unknown type has been skipped under
+    /// `UnknownTypePolicy::Warn`.
+    fn on_unknown_type(&mut self, _transaction_type: &str) {}
+
+    /// Called with what became of every record, including those ignored rather than applied.
+    fn on_disposition(&mut self, _line: Option<u64>, _disposition: &Disposition) {}
+
+    /// Called with each fee entry charged on an applied transaction, after the transaction.
+    fn on_fee(&mut self, _fee: &Transaction) {}
+}
+
+/// What became of a record the pipeline read.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Disposition {
+    Applied,
+    /// The record was well formed but skipped, such as a deposit without an amount.
+    Ignored {
+        reason: &'static str,
+    },
+    Rejected {
+        error: TransactionError,
+    },
+}
+
+impl From<&Result<(), TransactionError>> for Disposition {
+    fn from(result: &Result<(), TransactionError>) -> Self {
+        match result {
+            Ok(()) => Self::Applied,
+            Err(error) => Self::Rejected { error: *error },
+        }
+    }
+}
+
+/// Where the pipeline reads transactions from. Paths and readers are parsed using the pipeline's
+/// input format, while sources produce transactions themselves.
+pub enum Input {
+    Path(String),
+    Reader(Box<dyn Read>),
+    Source(Box<dyn TransactionSource>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InputFormat {
+    /// CSV with fields separated by the delimiter, which is a tab for TSV. Without a header row
+    /// the columns are in the order of `parse_csv::positional_headers`.
+    Csv {
+        delimiter: u8,
+        has_headers: bool,
+    },
+    /// CSV with records deserialized on every core. Fields can't span lines.
+    ParallelCsv {
+        delimiter: u8,
+        has_headers: bool,
+    },
+    JsonLines,
+    /// Fixed-width text with the columns of the spec.
+    FixedWidth(FixedWidthSpec),
+    /// Parquet files with the CSV input's columns.
+    #[cfg(feature = "parquet-input")]
+    Parquet,
+    /// Avro object container files of records with `parse_avro::SCHEMA`.
+    #[cfg(feature = "avro-input")]
+    Avro,
+}
+
+impl InputFormat {
+    /// A source that parses this format from the reader. A strict source returns records that
+    /// would be ignored as errors.
+    pub fn source(
+        &self,
+        reader: Box<dyn Read>,
+        strict: bool,
+        kinds: &TransactionKinds,
+    ) -> Box<dyn TransactionSource> {
+        match self {
+            Self::Csv {
+                delimiter,
+                has_headers,
+            } => Box::new(
+                CsvSource::new(reader)
+                    .delimiter(*delimiter)
+                    .has_headers(*has_headers)
+                    .strict(strict)
+                    .kinds(kinds.clone()),
+            ),
+            Self::ParallelCsv {
+                delimiter,
+                has_headers,
+            } => Box::new(
+                ParallelCsvSource::new(reader)
+                    .delimiter(*delimiter)
+                    .has_headers(*has_headers)
+                    .strict(strict)
+                    .kinds(kinds.clone()),
+            ),
+            Self::JsonLines => Box::new(
+                JsonlSource::new(BufReader::new(reader))
+                    .strict(strict)
+                    .kinds(kinds.clone()),
+            ),
+            Self::FixedWidth(spec) => Box::new(
+                FixedWidthSource::new(BufReader::new(reader), *spec)
+                    .strict(strict)
+                    .kinds(kinds.clone()),
+            ),
+            #[cfg(feature = "parquet-input")]
+            Self::Parquet => Box::new(
+                ParquetSource::from_reader(reader)
+                    .strict(strict)
+                    .kinds(kinds.clone()),
+            ),
+            #[cfg(feature = "avro-input")]
+            Self::Avro => Box::new(
+                AvroSource::new(BufReader::new(reader))
+                    .strict(strict)
+                    .kinds(kinds.clone()),
+            ),
+        }
+    }
+
+    /// A source that parses the file at the path, decompressing it if it's compressed.
+    pub fn open(
+        &self,
+        path: &str,
+        strict: bool,
+        kinds: &TransactionKinds,
+    ) -> Result<Box<dyn TransactionSource>, SourceError> {
+        // Parquet files are read from disk as they're needed rather than all at once.
+        #[cfg(feature = "parquet-input")]
+        if *self == Self::Parquet && !compression::is_compressed(path) {
+            return Ok(Box::new(
+                ParquetSource::new(File::open(path)?)
+                    .strict(strict)
+                    .kinds(kinds.clone()),
+            ));
+        }
+
+        Ok(self.source(compression::open(path)?, strict, kinds))
+    }
+
+    /// Every transaction in the file at the path, skipping ignored records, for callers that need
+    /// the whole input at once.
+    pub fn read(&self, path: &str) -> Result<Vec<Transaction>, SourceError> {
+        let mut source = self.open(path, false, &TransactionKinds::default())?;
+        source::transactions(source.as_mut()).collect()
+    }
+
+    /// Whether records appended to a file of this format can be followed.
+    pub fn can_follow(&self) -> bool {
+        matches!(self, Self::Csv { .. } | Self::ParallelCsv { .. })
+    }
+
+    /// The delimiter of CSV formats.
+    pub fn delimiter(&self) -> Option<u8> {
+        match self {
+            Self::Csv { delimiter, .. } | Self::ParallelCsv { delimiter, .. } => Some(*delimiter),
+            _ => None,
+        }
+    }
+
+    /// Whether CSV formats start with a header row.
+    pub fn has_headers(&self) -> Option<bool> {
+        match self {
+            Self::Csv { has_headers, .. } | Self::ParallelCsv { has_headers, .. } => {
+                Some(*has_headers)
+            }
+            _ => None,
+        }
+    }
+
+    /// The format with fields separated by the delimiter, or `None` if it isn't CSV.
+    pub fn with_delimiter(self, delimiter: u8) -> Option<Self> {
+        match self {
+            Self::Csv { has_headers, .. } => Some(Self::Csv {
+                delimiter,
+                has_headers,
+            }),
+            Self::ParallelCsv { has_headers, .. } => Some(Self::ParallelCsv {
+                delimiter,
+                has_headers,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The format without a header row, or `None` if it isn't CSV.
+    pub fn without_headers(self) -> Option<Self> {
+        match self {
+            Self::Csv { delimiter, .. } => Some(Self::Csv {
+                delimiter,
+                has_headers: false,
+            }),
+            Self::ParallelCsv { delimiter, .. } => Some(Self::ParallelCsv {
+                delimiter,
+                has_headers: false,
+            }),
+            _ => None,
+        }
+    }
+
+    /// A source that continues parsing the file from the given position.
+    pub fn resume(
+        &self,
+        mut file: File,
+        strict: bool,
+        kinds: &TransactionKinds,
+        position: InputPosition,
+    ) -> Result<Box<dyn TransactionSource>, SourceError> {
+        match self {
+            Self::Csv {
+                delimiter,
+                has_headers,
+            } => {
+                let headers = resume_headers(&mut file, *delimiter, *has_headers, kinds)?;
+                file.seek(SeekFrom::Start(position.byte))?;
+                Ok(Box::new(
+                    CsvSource::resume(file, headers, position)
+                        .delimiter(*delimiter)
+                        .strict(strict)
+                        .kinds(kinds.clone()),
+                ))
+            }
+            Self::ParallelCsv {
+                delimiter,
+                has_headers,
+            } => {
+                let headers = resume_headers(&mut file, *delimiter, *has_headers, kinds)?;
+                file.seek(SeekFrom::Start(position.byte))?;
+                Ok(Box::new(
+                    ParallelCsvSource::resume(file, headers, position)
+                        .delimiter(*delimiter)
+                        .strict(strict)
+                        .kinds(kinds.clone()),
+                ))
+            }
+            Self::JsonLines => {
+                file.seek(SeekFrom::Start(position.byte))?;
+                Ok(Box::new(
+                    JsonlSource::resume(BufReader::new(file), position)
+                        .strict(strict)
+                        .kinds(kinds.clone()),
+                ))
+            }
+            Self::FixedWidth(spec) => {
+                file.seek(SeekFrom::Start(position.byte))?;
+                Ok(Box::new(
+                    FixedWidthSource::resume(BufReader::new(file), *spec, position)
+                        .strict(strict)
+                        .kinds(kinds.clone()),
+                ))
+            }
+            #[cfg(feature = "parquet-input")]
+            Self::Parquet => Ok(Box::new(
+                ParquetSource::resume(file, position)
+                    .strict(strict)
+                    .kinds(kinds.clone()),
+            )),
+            #[cfg(feature = "avro-input")]
+            Self::Avro => Ok(Box::new(
+                AvroSource::resume(BufReader::new(file), position)
+                    .strict(strict)
+                    .kinds(kinds.clone()),
+            )),
+        }
+    }
+
+    /// Detects the format from the file extension, defaulting to CSV. The extension of a
+    /// compressed file is the one before its compression extension.
+    pub fn from_path(path: &str) -> Self {
+        let path = compression::uncompressed_path(path);
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("tsv") => Self::Csv {
+                delimiter: b'\t',
+                has_headers: true,
+            },
+            Some("jsonl") | Some("ndjson") => Self::JsonLines,
+            Some("dat") => Self::FixedWidth(FixedWidthSpec::default()),
+            #[cfg(feature = "parquet-input")]
+            Some("parquet") => Self::Parquet,
+            #[cfg(feature = "avro-input")]
+            Some("avro") => Self::Avro,
+            _ => Self::default(),
+        }
+    }
+}
+
+impl Default for InputFormat {
+    fn default() -> Self {
+        Self::Csv {
+            delimiter: parse_csv::DEFAULT_DELIMITER,
+            has_headers: true,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = SourceError;
+
+    /// Fixed-width input is `fixed-width` for the default columns, or `fixed-width=` followed by
+    /// a column spec.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(spec) = s.strip_prefix("fixed-width=") {
+            return spec
+                .parse()
+                .map(Self::FixedWidth)
+                .map_err(|e| SourceError::UnknownFormat {
+                    passed: format!("{} ({})", s, e),
+                });
+        }
+
+        match s {
+            "csv" => Ok(Self::default()),
+            "tsv" => Ok(Self::Csv {
+                delimiter: b'\t',
+                has_headers: true,
+            }),
+            "csv-parallel" => Ok(Self::ParallelCsv {
+                delimiter: parse_csv::DEFAULT_DELIMITER,
+                has_headers: true,
+            }),
+            "jsonl" => Ok(Self::JsonLines),
+            "fixed-width" => Ok(Self::FixedWidth(FixedWidthSpec::default())),
+            #[cfg(feature = "parquet-input")]
+            "parquet" => Ok(Self::Parquet),
+            #[cfg(feature = "avro-input")]
+            "avro" => Ok(Self::Avro),
+            _ => Err(SourceError::UnknownFormat {
+                passed: String::from(s),
+            }),
+        }
+    }
+}
+
+/// Transactions above `threshold` are parked in the queue at `queue_path` for an operator.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApprovalConfig {
+    pub threshold: Amount,
+    pub queue_path: String,
+}
+
+/// What happens to deposits and withdrawals above the maximum amount.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum MaximumPolicy {
+    /// Reject them with `TransactionError::AboveMaximumAmount`.
+    #[default]
+    Reject,
+    /// Apply them but flag them with a warning.
+    Flag,
+}
+
+impl FromStr for MaximumPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "flag" => Ok(Self::Flag),
+            _ => Err(String::from(s)),
+        }
+    }
+}
+
+/// Settings that change how transactions are applied.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EngineConfig {
+    pub approval: Option<ApprovalConfig>,
+    /// Merge each client's consecutive deposits and withdrawals before applying them. The whole
+    /// input is read first, and nothing is merged if it contains disputes, resolves or chargebacks.
+    pub coalesce: bool,
+    /// Stop at the first ignored record or rejected transaction instead of skipping it.
+    pub strict: bool,
+    /// Deposits and withdrawals above this amount are applied but flagged with a warning, so
+    /// limits can be tuned before they are enforced.
+    pub soft_limit: Option<Amount>,
+    /// Deposits and withdrawals above this amount are rejected or flagged, depending on the
+    /// policy, to catch fat-finger rows.
+    pub maximum_amount: Option<(Amount, MaximumPolicy)>,
+    /// Sort transactions by timestamp within this many seconds of the newest one read. Those read
+    /// later than that are applied but flagged with a warning.
+    pub reorder_window: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum PipelineError {
+    MissingInput,
+    Input(SourceError),
+    Approval(ApprovalError),
+    Output(SinkError),
+    Checkpoint(SnapshotError),
+    /// Only inputs read from a path can be resumed.
+    NotResumable,
+    /// Only CSV files read from a path can be followed.
+    NotFollowable,
+    /// A transaction was rejected in strict mode.
+    Rejected {
+        line: Option<u64>,
+        // Boxed to keep the error small, as it's returned from every stage.
+        transaction: Box<Transaction>,
+        error: TransactionError,
+    },
+}
+
+/// Assembles a pipeline from its stages.
+pub struct PipelineBuilder<Account>
+where
+    Account: ClientAccount,
+{
+    database: Database<Account>,
+    input: Option<Input>,
+    format: InputFormat,
+    config: EngineConfig,
+    enrichers: Vec<Box<dyn Enricher>>,
+    observers: Vec<Box<dyn TransactionObserver>>,
+    event_sinks: Vec<Box<dyn EventSink>>,
+    error_sinks: Vec<Box<dyn ErrorSink>>,
+    report: Report<Account>,
+    report_sink: Box<dyn ReportSink<Account>>,
+    client_map: Option<ClientMap>,
+    risk: Option<RiskScorer>,
+    sample: Option<SampleRate>,
+    grace: Option<u64>,
+    rate_limit: Option<RateLimit>,
+    follow: Option<Follow>,
+    checkpoints: Option<Checkpoints<Account>>,
+    resume: Option<InputPosition>,
+    kinds: TransactionKinds,
+    sharding: Option<Sharding<Account>>,
+    partitioner: Arc<dyn Partitioner>,
+}
+
+/// How many shards transactions are applied on, how clients are assigned to them, and how to
+/// start them.
+struct Sharding<Account>
+where
+    Account: ClientAccount,
+{
+    count: usize,
+    partitioner: Arc<dyn Partitioner>,
+    start: fn(Database<Account>, usize, Arc<dyn Partitioner>) -> ShardPool<Account>,
+}
+
+/// How often a run's progress is saved, and where.
+struct Checkpoints<Account>
+where
+    Account: ClientAccount,
+{
+    every: u64,
+    checkpointer: Box<dyn Checkpointer<Account>>,
+}
+
+impl<Account> PipelineBuilder<Account>
+where
+    Account: ClientAccount,
+{
+    /// Starts a pipeline that applies transactions to the given database, writing the standard
+    /// report to stdout.
+    pub fn new(database: Database<Account>) -> Self {
+        Self {
+            database,
+            input: None,
+            format: InputFormat::default(),
+            config: EngineConfig::default(),
+            enrichers: vec![],
+            observers: vec![],
+            event_sinks: vec![],
+            error_sinks: vec![],
+            report: Report::standard(),
+            report_sink: Box::new(CsvReportSink::stdout()),
+            client_map: None,
+            risk: None,
+            sample: None,
+            grace: None,
+            rate_limit: None,
+            follow: None,
+            checkpoints: None,
+            resume: None,
+            kinds: TransactionKinds::default(),
+            sharding: None,
+            partitioner: Arc::new(Partitioning::default()),
+        }
+    }
+
+    /// Reads transactions from the file at the given path.
+    pub fn input_path(mut self, path: &str) -> Self {
+        self.input = Some(Input::Path(String::from(path)));
+        self
+    }
+
+    /// Reads transactions from the given reader.
+    pub fn input_reader(mut self, reader: Box<dyn Read>) -> Self {
+        self.input = Some(Input::Reader(reader));
+        self
+    }
+
+    /// Reads transactions from a custom source.
+    pub fn source(mut self, source: Box<dyn TransactionSource>) -> Self {
+        self.input = Some(Input::Source(source));
+        self
+    }
+
+    /// The format of the input.
+    pub fn format(mut self, format: InputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Settings that change how transactions are applied.
+    pub fn config(mut self, config: EngineConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Adds an enricher that transforms or refuses each transaction before it is applied, after
+    /// the enrichers added before it. With a client map, enrichers see the anonymized ids.
+    pub fn enricher(mut self, enricher: Box<dyn Enricher>) -> Self {
+        self.enrichers.push(enricher);
+        self
+    }
+
+    /// Adds an observer that is notified of every applied or rejected transaction.
+    pub fn observer(mut self, observer: Box<dyn TransactionObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Adds a sink that records the outcome of every applied or rejected transaction.
+    pub fn event_sink(mut self, sink: Box<dyn EventSink>) -> Self {
+        self.event_sinks.push(sink);
+        self
+    }
+
+    /// Adds a sink that records every rejected transaction.
+    pub fn error_sink(mut self, sink: Box<dyn ErrorSink>) -> Self {
+        self.error_sinks.push(sink);
+        self
+    }
+
+    /// The columns written for each account.
+    pub fn report(mut self, report: Report<Account>) -> Self {
+        self.report = report;
+        self
+    }
+
+    /// Writes the report as CSV to the given writer.
+    pub fn output(mut self, output: Box<dyn Write>) -> Self {
+        self.report_sink = Box::new(CsvReportSink::new(output));
+        self
+    }
+
+    /// Where the report is written.
+    pub fn report_sink(mut self, sink: Box<dyn ReportSink<Account>>) -> Self {
+        self.report_sink = sink;
+        self
+    }
+
+    /// Transaction kinds registered for types the engine doesn't know, and what happens to records
+    /// of any other unknown type. Only applies to paths and readers, not custom sources.
+    pub fn kinds(mut self, kinds: TransactionKinds) -> Self {
+        self.kinds = kinds;
+        self
+    }
+
+    /// Anonymizes client ids as they are read, mapping them back in the report and everything the
+    /// listeners are told. The returned database keeps the anonymized ids.
+    pub fn client_map(mut self, map: ClientMap) -> Self {
+        self.client_map = Some(map);
+        self
+    }
+
+    /// Scores every transaction for risk, flagging or blocking those at or above the scorer's
+    /// threshold. With a client map, clients are scored under their anonymized ids.
+    pub fn risk(mut self, scorer: RiskScorer) -> Self {
+        self.risk = Some(scorer);
+        self
+    }
+
+    /// Only reads the transactions of a share of the clients, keeping each sampled client's whole
+    /// history.
+    pub fn sample(mut self, rate: SampleRate) -> Self {
+        self.sample = Some(rate);
+        self
+    }
+
+    /// Holds a resolve or chargeback that arrives before its dispute for up to `records` more
+    /// records, applying it straight after the dispute instead of rejecting it.
+    pub fn dispute_grace(mut self, records: u64) -> Self {
+        self.grace = Some(records);
+        self
+    }
+
+    /// Reads transactions no faster than the limit, such as when backfilling into storage shared
+    /// with other work.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Keeps reading the input file as rows are appended to it, until `follow` is stopped. The
+    /// report is written whenever `follow` asks for it, as well as at the end.
+    pub fn follow(mut self, follow: Follow) -> Self {
+        self.follow = Some(follow);
+        self
+    }
+
+    /// Saves the progress of the run after every `every` transactions read, so it can be resumed.
+    /// Nothing is saved while coalescing, as transactions are only applied once all are read.
+    pub fn checkpoint(mut self, every: u64, checkpointer: Box<dyn Checkpointer<Account>>) -> Self {
+        self.checkpoints = Some(Checkpoints {
+            every: every.max(1),
+            checkpointer,
+        });
+        self
+    }
+
+    /// Starts reading the input path from a checkpointed position rather than the beginning.
+    pub fn resume_from(mut self, position: InputPosition) -> Self {
+        self.resume = Some(position);
+        self
+    }
+
+    /// Finishes assembling the pipeline.
+    pub fn build(self) -> Result<Pipeline<Account>, PipelineError> {
+        let input = self.input.ok_or(PipelineError::MissingInput)?;
+        // Resuming and following seek through the file, which a compressed stream can't do.
+        let seekable = matches!(&input, Input::Path(path) if !compression::is_compressed(path));
+        if self.resume.is_some() && !seekable {
+            return Err(PipelineError::NotResumable);
+        }
+        if self.follow.is_some() && (!seekable || !self.format.can_follow()) {
+            return Err(PipelineError::NotFollowable);
+        }
+
+        Ok(Pipeline {
+            database: self.database,
+            input,
+            format: self.format,
+            config: self.config,
+            enrichers: self.enrichers,
+            listeners: Listeners {
+                observers: self.observers,
+                event_sinks: self.event_sinks,
+                error_sinks: self.error_sinks,
+                client_map: self.client_map,
+                risk: self.risk,
+                latest: None,
+            },
+            report: self.report,
+            report_sink: self.report_sink,
+            sample: self.sample,
+            grace: self.grace,
+            rate_limit: self.rate_limit,
+            follow: self.follow,
+            checkpoints: self.checkpoints,
+            resume: self.resume,
+            kinds: self.kinds,
+            sharding: self.sharding,
+        })
+    }
+}
+
+impl<Account> PipelineBuilder<Account>
+where
+    Account: ClientAccount + Send + 'static,
+{
+    /// Applies transactions on `count` threads, each keeping the accounts of a share of the
+    /// clients. Listeners are still told about transactions in the order they were read. Nothing
+    /// is sharded while coalescing.
+    pub fn shards(mut self, count: usize) -> Self {
+        self.sharding = match count {
+            0 | 1 => None,
+            count => Some(Sharding {
+                count,
+                partitioner: self.partitioner.clone(),
+                start: ShardPool::start,
+            }),
+        };
+        self
+    }
+
+    /// Assigns clients to shards with the partitioner rather than `Partitioning::Hash`.
+    pub fn partitioner(mut self, partitioner: Arc<dyn Partitioner>) -> Self {
+        if let Some(sharding) = &mut self.sharding {
+            sharding.partitioner = partitioner.clone();
+        }
+        self.partitioner = partitioner;
+        self
+    }
+}
+
+/// A fully assembled pipeline, ready to run.
+pub struct Pipeline<Account>
+where
+    Account: ClientAccount,
+{
+    database: Database<Account>,
+    input: Input,
+    format: InputFormat,
+    config: EngineConfig,
+    enrichers: Vec<Box<dyn Enricher>>,
+    listeners: Listeners,
+    report: Report<Account>,
+    report_sink: Box<dyn ReportSink<Account>>,
+    sample: Option<SampleRate>,
+    grace: Option<u64>,
+    rate_limit: Option<RateLimit>,
+    follow: Option<Follow>,
+    checkpoints: Option<Checkpoints<Account>>,
+    resume: Option<InputPosition>,
+    kinds: TransactionKinds,
+    sharding: Option<Sharding<Account>>,
+}
+
+/// Where transactions are applied: the database itself, or shards of it on their own threads.
+enum Workers<Account>
+where
+    Account: ClientAccount,
+{
+    // Boxed to keep the enum small, as the database holds its configuration inline.
+    Serial(Box<Database<Account>>),
+    Sharded(ShardPool<Account>),
+}
+
+impl<Account> Workers<Account>
+where
+    Account: ClientAccount,
+{
+    fn start(database: Database<Account>, sharding: &Option<Sharding<Account>>) -> Self {
+        match sharding {
+            Some(sharding) => Self::Sharded((sharding.start)(
+                database,
+                sharding.count,
+                sharding.partitioner.clone(),
+            )),
+            None => Self::Serial(Box::new(database)),
+        }
+    }
+
+    /// Applies a transaction, telling the listeners about every transaction applied so far in the
+    /// order they were read.
+    fn apply(
+        &mut self,
+        listeners: &mut Listeners,
+        config: &EngineConfig,
+        transaction: Transaction,
+        line: Option<u64>,
+    ) -> Result<(), PipelineError> {
+        match self {
+            Self::Serial(database) => apply(database, listeners, config, transaction, line),
+            Self::Sharded(pool) => {
+                pool.submit(transaction, line);
+                while let Some(outcome) = pool.next_outcome() {
+                    report(
+                        listeners,
+                        config,
+                        outcome.transaction,
+                        outcome.result,
+                        &outcome.fees,
+                        outcome.line,
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Tells the listeners about every transaction submitted so far, so what's reported next
+    /// follows them in the order they were read.
+    fn flush(
+        &mut self,
+        listeners: &mut Listeners,
+        config: &EngineConfig,
+    ) -> Result<(), PipelineError> {
+        if let Self::Sharded(pool) = self {
+            for outcome in pool.wait() {
+                report(
+                    listeners,
+                    config,
+                    outcome.transaction,
+                    outcome.result,
+                    &outcome.fees,
+                    outcome.line,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for every transaction to be applied, returning the whole database.
+    fn finish(
+        self,
+        listeners: &mut Listeners,
+        config: &EngineConfig,
+    ) -> Result<Database<Account>, PipelineError> {
+        match self {
+            Self::Serial(database) => Ok(*database),
+            Self::Sharded(mut pool) => {
+                for outcome in pool.wait() {
+                    report(
+                        listeners,
+                        config,
+                        outcome.transaction,
+                        outcome.result,
+                        &outcome.fees,
+                        outcome.line,
+                    )?;
+                }
+                Ok(pool.join())
+            }
+        }
+    }
+}
+
+/// Everything told about the outcome of each transaction.
+struct Listeners {
+    observers: Vec<Box<dyn TransactionObserver>>,
+    event_sinks: Vec<Box<dyn EventSink>>,
+    error_sinks: Vec<Box<dyn ErrorSink>>,
+    client_map: Option<ClientMap>,
+    risk: Option<RiskScorer>,
+    /// The newest timestamp of the transactions applied so far.
+    latest: Option<Timestamp>,
+}
+
+impl<Account> Pipeline<Account>
+where
+    Account: ClientAccount,
+{
+    /// Applies every input transaction, writes the report and returns the resulting database.
+    pub fn run(mut self) -> Result<Database<Account>, PipelineError> {
+        if let Some((maximum, MaximumPolicy::Reject)) = self.config.maximum_amount {
+            self.database = self.database.with_maximum_amount(maximum);
+        }
+
+        let mut source = match self.input {
+            Input::Path(path) if self.follow.is_some() => {
+                let file = File::open(path).map_err(|e| PipelineError::Input(e.into()))?;
+                follow_source(
+                    file,
+                    self.format,
+                    self.config.strict,
+                    &self.kinds,
+                    self.resume,
+                )
+                .map_err(PipelineError::Input)?
+            }
+            Input::Path(path) => match self.resume {
+                Some(position) => {
+                    let file = File::open(path).map_err(|e| PipelineError::Input(e.into()))?;
+                    self.format
+                        .resume(file, self.config.strict, &self.kinds, position)
+                        .map_err(PipelineError::Input)?
+                }
+                None => self
+                    .format
+                    .open(&path, self.config.strict, &self.kinds)
+                    .map_err(PipelineError::Input)?,
+            },
+            Input::Reader(reader) => self.format.source(reader, self.config.strict, &self.kinds),
+            Input::Source(source) => source,
+        };
+        // Clients are sampled by their original ids, so the same clients are kept whatever the map.
+        if let Some(rate) = self.sample {
+            source = Box::new(SampleSource::new(source, rate));
+        }
+        if let Some(map) = &self.listeners.client_map {
+            source = Box::new(RemapSource::new(source, map.clone()));
+        }
+        if let Some(window) = self.config.reorder_window {
+            source = Box::new(ReorderSource::new(source, window));
+        }
+        if let Some(grace) = self.grace {
+            source = Box::new(GraceSource::new(source, grace));
+        }
+        if let Some(limit) = self.rate_limit {
+            source = Box::new(ThrottleSource::new(source, limit));
+        }
+
+        // Transactions approved since the last run are applied before any new input.
+        let mut approval_queue = match &self.config.approval {
+            Some(approval) => {
+                let mut queue =
+                    ApprovalQueue::load(&approval.queue_path).map_err(PipelineError::Approval)?;
+                for transaction in queue.take_approved() {
+                    apply(
+                        &mut self.database,
+                        &mut self.listeners,
+                        &self.config,
+                        transaction,
+                        None,
+                    )?;
+                }
+                Some(queue)
+            }
+            None => None,
+        };
+
+        // Coalescing needs to see the whole batch, so transactions are held back until the end.
+        let mut batch = vec![];
+        let mut read: u64 = 0;
+
+        let sharding = match self.config.coalesce {
+            true => None,
+            false => self.sharding,
+        };
+        let mut workers = Workers::start(self.database, &sharding);
+
+        while let Some(transaction) = source.next() {
+            if let Some(follow) = &self.follow {
+                if follow.stopped() {
+                    break;
+                }
+                if follow.take_report_request() {
+                    // Shards are joined so the report covers every account.
+                    let database = workers.finish(&mut self.listeners, &self.config)?;
+                    self.listeners.flush()?;
+                    write_report(
+                        &mut self.report,
+                        self.report_sink.as_mut(),
+                        &self.listeners.client_map,
+                        &database,
+                    )?;
+                    workers = Workers::start(database, &sharding);
+                }
+            }
+
+            let transaction = match transaction {
+                Ok(transaction) => transaction,
+                Err(SourceError::Ignored { line, reason }) => {
+                    workers.flush(&mut self.listeners, &self.config)?;
+                    self.listeners.ignored(line, reason)?;
+                    continue;
+                }
+                Err(SourceError::UnknownType {
+                    line,
+                    transaction_type,
+                }) => {
+                    workers.flush(&mut self.listeners, &self.config)?;
+                    self.listeners.unknown_type(line, &transaction_type)?;
+                    continue;
+                }
+                Err(SourceError::Waiting) => {
+                    if let Some(follow) = &self.follow {
+                        thread::sleep(follow.poll_interval);
+                    }
+                    continue;
+                }
+                Err(e) => return Err(PipelineError::Input(e)),
+            };
+            let transaction = match enrich::enrich(&mut self.enrichers, transaction) {
+                Ok(transaction) => transaction,
+                Err(reason) => {
+                    workers.flush(&mut self.listeners, &self.config)?;
+                    report(
+                        &mut self.listeners,
+                        &self.config,
+                        transaction,
+                        Err(TransactionError::Refused { reason }),
+                        &[],
+                        source.line(),
+                    )?;
+                    continue;
+                }
+            };
+            if let Some(score) = self.listeners.blocked(&transaction) {
+                workers.flush(&mut self.listeners, &self.config)?;
+                report(
+                    &mut self.listeners,
+                    &self.config,
+                    transaction,
+                    Err(TransactionError::HighRisk { score }),
+                    &[],
+                    source.line(),
+                )?;
+                continue;
+            }
+            read += 1;
+
+            let parked = match (&self.config.approval, &mut approval_queue) {
+                (Some(approval), Some(queue))
+                    if approval::requires_approval(&transaction, approval.threshold) =>
+                {
+                    queue.park(transaction);
+                    true
+                }
+                _ => false,
+            };
+
+            if self.config.coalesce {
+                if !parked {
+                    batch.push((transaction, source.line()));
+                }
+                continue;
+            }
+
+            if !parked {
+                workers.apply(
+                    &mut self.listeners,
+                    &self.config,
+                    transaction,
+                    source.line(),
+                )?;
+            }
+
+            match (&mut self.checkpoints, source.position()) {
+                (Some(checkpoints), Some(position)) if read.is_multiple_of(checkpoints.every) => {
+                    // Everything the checkpoint covers is written out first, so nothing is lost
+                    // if the run stops before the next one. Shards are joined to save the
+                    // accounts and started again afterwards.
+                    let database = workers.finish(&mut self.listeners, &self.config)?;
+                    self.listeners.flush()?;
+                    if let (Some(approval), Some(queue)) = (&self.config.approval, &approval_queue)
+                    {
+                        queue
+                            .save(&approval.queue_path)
+                            .map_err(PipelineError::Approval)?;
+                    }
+                    checkpoints
+                        .checkpointer
+                        .checkpoint(&database, position)
+                        .map_err(PipelineError::Checkpoint)?;
+                    workers = Workers::start(database, &sharding);
+                }
+                _ => {}
+            }
+        }
+
+        let mut database = workers.finish(&mut self.listeners, &self.config)?;
+        for (step, lines) in coalesce::plan_lines(batch) {
+            apply_step(
+                &mut database,
+                &mut self.listeners,
+                &self.config,
+                step,
+                &lines,
+            )?;
+        }
+
+        self.listeners.flush()?;
+
+        if let (Some(approval), Some(queue)) = (&self.config.approval, &approval_queue) {
+            queue
+                .save(&approval.queue_path)
+                .map_err(PipelineError::Approval)?;
+        }
+
+        write_report(
+            &mut self.report,
+            self.report_sink.as_mut(),
+            &self.listeners.client_map,
+            &database,
+        )?;
+
+        Ok(database)
+    }
+}
+
+/// A source that follows the file, continuing from `resume` if given.
+fn follow_source(
+    mut file: File,
+    format: InputFormat,
+    strict: bool,
+    kinds: &TransactionKinds,
+    resume: Option<InputPosition>,
+) -> Result<Box<dyn TransactionSource>, SourceError> {
+    // Only CSV can be followed.
+    let delimiter = format.delimiter().unwrap_or(parse_csv::DEFAULT_DELIMITER);
+    let has_headers = format.has_headers().unwrap_or(true);
+    let source = match resume {
+        Some(position) => {
+            let headers = resume_headers(&mut file, delimiter, has_headers, kinds)?;
+            file.seek(SeekFrom::Start(position.byte))?;
+            FollowSource::resume(file, headers, position)
+        }
+        None => FollowSource::new(file),
+    };
+    Ok(Box::new(
+        source
+            .delimiter(delimiter)
+            .has_headers(has_headers)
+            .strict(strict)
+            .kinds(kinds.clone()),
+    ))
+}
+
+/// The headers of a CSV file being resumed, read from its start unless it has no header row.
+fn resume_headers(
+    file: &mut File,
+    delimiter: u8,
+    has_headers: bool,
+    kinds: &TransactionKinds,
+) -> Result<csv::StringRecord, SourceError> {
+    match has_headers {
+        true => Ok(kinds.headers(&parse_csv::read_headers(file, delimiter)?)),
+        false => Ok(parse_csv::positional_headers()),
+    }
+}
+
+/// Writes the report of every account, under their original ids if they were anonymized.
+fn write_report<Account>(
+    report: &mut Report<Account>,
+    sink: &mut dyn ReportSink<Account>,
+    client_map: &Option<ClientMap>,
+    database: &Database<Account>,
+) -> Result<(), PipelineError>
+where
+    Account: ClientAccount,
+{
+    match client_map {
+        Some(map) => {
+            report.replace(Box::new(OriginalClientColumn { map: map.clone() }));
+            let mut accounts: Vec<&Account> = database.accounts().collect();
+            accounts.sort_by_key(|account| map.reverse(account.id()));
+            let report = report.for_accounts(accounts.iter().copied());
+            sink.write_report(&report, &mut accounts.into_iter())
+        }
+        None => database.write_to_sink(report, sink),
+    }
+    .map_err(PipelineError::Output)
+}
+
+/// Applies a transaction and tells the listeners.
+fn apply<Account>(
+    database: &mut Database<Account>,
+    listeners: &mut Listeners,
+    config: &EngineConfig,
+    transaction: Transaction,
+    line: Option<u64>,
+) -> Result<(), PipelineError>
+where
+    Account: ClientAccount,
+{
+    let result = database.apply_transaction(transaction);
+    let fees = database.take_charged_fees();
+    report(listeners, config, transaction, result, &fees, line)
+}
+
+/// Tells the listeners the outcome of a transaction and the fees charged on it. In strict mode a
+/// rejection stops the pipeline, but a transaction skipped as already processed doesn't.
+fn report(
+    listeners: &mut Listeners,
+    config: &EngineConfig,
+    transaction: Transaction,
+    result: Result<(), TransactionError>,
+    fees: &[Transaction],
+    line: Option<u64>,
+) -> Result<(), PipelineError> {
+    listeners.notify(config, &transaction, &result, line)?;
+    listeners.charged(fees);
+
+    match result {
+        Err(error) if config.strict && !error.is_skip() => Err(PipelineError::Rejected {
+            line,
+            transaction: Box::new(listeners.original(&transaction)),
+            error,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Applies a coalesced step, whose transactions were read from the input lines. If a merged run is
+/// rejected, its transactions are applied one by one so each is accepted or rejected exactly as it
+/// would have been without coalescing.
+fn apply_step<Account>(
+    database: &mut Database<Account>,
+    listeners: &mut Listeners,
+    config: &EngineConfig,
+    step: Step,
+    lines: &[Option<u64>],
+) -> Result<(), PipelineError>
+where
+    Account: ClientAccount,
+{
+    if let Step::Single(transaction) = step {
+        return apply(database, listeners, config, transaction, lines[0]);
+    }
+
+    if database
+        .apply_merged(step.transactions(), step.merged())
+        .is_ok()
+    {
+        for (transaction, line) in step.transactions().iter().zip(lines) {
+            listeners.notify(config, transaction, &Ok(()), *line)?;
+        }
+        listeners.charged(&database.take_charged_fees());
+        return Ok(());
+    }
+
+    for (transaction, line) in step.transactions().iter().zip(lines) {
+        apply(database, listeners, config, *transaction, *line)?;
+    }
+
+    Ok(())
+}
+
+/// Why an applied transaction should be flagged, if at all.
+fn warnings(config: &EngineConfig, transaction: &Transaction) -> Vec<Warning> {
+    let mut warnings = vec![];
+    match config.soft_limit {
+        Some(limit) if approval::requires_approval(transaction, limit) => {
+            warnings.push(Warning::SoftLimit { limit })
+        }
+        _ => {}
+    }
+
+    let amount = match transaction.transaction_type {
+        TransactionType::Deposit(amount) | TransactionType::Withdrawal(amount) => Some(amount),
+        _ => None,
+    };
+    match (config.maximum_amount, amount) {
+        (Some((maximum, MaximumPolicy::Flag)), Some(amount)) if amount > maximum => {
+            warnings.push(Warning::AboveMaximum { maximum })
+        }
+        _ => {}
+    }
+
+    warnings
+}
+
+impl Listeners {
+    /// The transaction as it was read, before its clients were anonymized.
+    fn original(&self, transaction: &Transaction) -> Transaction {
+        match &self.client_map {
+            Some(map) => map.reverse_transaction(transaction),
+            None => *transaction,
+        }
+    }
+
+    fn notify(
+        &mut self,
+        config: &EngineConfig,
+        transaction: &Transaction,
+        result: &Result<(), TransactionError>,
+        line: Option<u64>,
+    ) -> Result<(), PipelineError> {
+        let risky = match &mut self.risk {
+            Some(risk) if risk.policy() == RiskPolicy::Annotate => risk.flag(transaction),
+            _ => None,
+        };
+        let transaction = &self.original(transaction);
+
+        let disposition = Disposition::from(result);
+        for observer in self.observers.iter_mut() {
+            observer.on_transaction(transaction, result);
+            observer.on_disposition(line, &disposition);
+        }
+
+        for sink in self.event_sinks.iter_mut() {
+            sink.record(transaction, result)
+                .map_err(PipelineError::Output)?;
+        }
+
+        if let Err(error) = result {
+            for sink in self.error_sinks.iter_mut() {
+                sink.record(line, transaction, error)
+                    .map_err(PipelineError::Output)?;
+            }
+        }
+
+        let late = self.late(config, transaction);
+        if result.is_ok() {
+            for warning in warnings(config, transaction) {
+                self.warn(transaction, &warning, line)?;
+            }
+        }
+        if let (Ok(_), Some(warning)) = (result, late) {
+            self.warn(transaction, &warning, line)?;
+        }
+        if let (Ok(_), Some(score)) = (result, risky) {
+            self.warn(transaction, &Warning::HighRisk { score }, line)?;
+        }
+
+        Ok(())
+    }
+
+    fn charged(&mut self, fees: &[Transaction]) {
+        for fee in fees {
+            let fee = &self.original(fee);
+            for observer in self.observers.iter_mut() {
+                observer.on_fee(fee);
+            }
+        }
+    }
+
+    /// The risk score of a transaction that is blocked rather than applied.
+    fn blocked(&mut self, transaction: &Transaction) -> Option<u8> {
+        match &mut self.risk {
+            Some(risk) if risk.policy() == RiskPolicy::Block => risk.flag(transaction),
+            _ => None,
+        }
+    }
+
+    /// Whether the transaction is older than one applied before it, which with a reorder window
+    /// means it was read too late to be sorted in.
+    fn late(&mut self, config: &EngineConfig, transaction: &Transaction) -> Option<Warning> {
+        config.reorder_window?;
+        let timestamp = transaction.timestamp?;
+        match self.latest {
+            Some(latest) if timestamp < latest => Some(Warning::Late {
+                behind: latest - timestamp,
+            }),
+            _ => {
+                self.latest = Some(timestamp);
+                None
+            }
+        }
+    }
+
+    fn warn(
+        &mut self,
+        transaction: &Transaction,
+        warning: &Warning,
+        line: Option<u64>,
+    ) -> Result<(), PipelineError> {
+        for observer in self.observers.iter_mut() {
+            observer.on_warning(transaction, warning);
+        }
+
+        for sink in self.event_sinks.iter_mut() {
+            sink.record_warning(transaction, warning)
+                .map_err(PipelineError::Output)?;
+        }
+
+        for sink in self.error_sinks.iter_mut() {
+            sink.record_warning(line, transaction, warning)
+                .map_err(PipelineError::Output)?;
+        }
+
+        Ok(())
+    }
+
+    fn ignored(&mut self, line: u64, reason: &'static str) -> Result<(), PipelineError> {
+        let disposition = Disposition::Ignored { reason };
+        for observer in self.observers.iter_mut() {
+            observer.on_disposition(Some(line), &disposition);
+        }
+
+        for sink in self.error_sinks.iter_mut() {
+            sink.record_ignored(line, reason)
+                .map_err(PipelineError::Output)?;
+        }
+
+        Ok(())
+    }
+
+    fn unknown_type(&mut self, line: u64, transaction_type: &str) -> Result<(), PipelineError> {
+        for observer in self.observers.iter_mut() {
+            observer.on_unknown_type(transaction_type);
+        }
+
+        for sink in self.error_sinks.iter_mut() {
+            sink.record_unknown_type(line, transaction_type)
+                .map_err(PipelineError::Output)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), PipelineError> {
+        for sink in self.event_sinks.iter_mut() {
+            sink.flush().map_err(PipelineError::Output)?;
+        }
+
+        for sink in self.error_sinks.iter_mut() {
+            sink.flush().map_err(PipelineError::Output)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+    use crate::fixtures::{amount, places, transaction};
+    use crate::parse_csv::{CsvTransaction, ParseCsvError, TransactionKind, UnknownTypePolicy};
+    use crate::risk::{RiskConfig, RiskScoreColumn};
+    use crate::sink::{ErrorLogSink, JsonLinesEventSink, JsonReportSink};
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    /// Collects everything written to it so tests can inspect the output.
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct CountingObserver(Rc<RefCell<(usize, usize)>>);
+
+    impl TransactionObserver for CountingObserver {
+        fn on_transaction(
+            &mut self,
+            _transaction: &Transaction,
+            result: &Result<(), TransactionError>,
+        ) {
+            let mut counts = self.0.borrow_mut();
+            match result {
+                Ok(_) => counts.0 += 1,
+                Err(_) => counts.1 += 1,
+            }
+        }
+    }
+
+    const INPUT: &str = "type, client, tx, amount\ndeposit, 1, 1, 2.0\nwithdrawal, 1, 2, 5.0\n";
+
+    /// Applies refunds as deposits.
+    struct RefundKind;
+
+    impl TransactionKind for RefundKind {
+        fn parse(&self, record: &CsvTransaction) -> Result<Option<Transaction>, ParseCsvError> {
+            let deposit = CsvTransaction {
+                transaction_type: String::from("deposit"),
+                ..record.clone()
+            };
+            deposit.into_transaction()
+        }
+    }
+
+    const UNKNOWN_INPUT: &str =
+        "type, client, tx, amount\nrefund, 1, 1, 2.0\nbonus, 1, 2, 5.0\ndeposit, 1, 3, 1.0\n";
+
+    fn run_kinds(kinds: TransactionKinds, errors: SharedOutput) -> Result<String, PipelineError> {
+        let output = SharedOutput::default();
+        PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(UNKNOWN_INPUT.as_bytes()))
+            .output(Box::new(output.clone()))
+            .error_sink(Box::new(ErrorLogSink::new(errors)))
+            .kinds(kinds.register("refund", Box::new(RefundKind)))
+            .build()?
+            .run()?;
+
+        let actual = String::from_utf8(output.0.borrow().clone()).unwrap();
+        Ok(actual)
+    }
+
+    #[test]
+    fn pipeline_run_dispatches_registered_kinds_and_applies_unknown_type_policy() {
+        let expected =
+            places("client, available, held, total, locked\n1, 3.0000, 0.0000, 3.0000, false\n");
+
+        let errors = SharedOutput::default();
+        let actual = run_kinds(TransactionKinds::default(), errors.clone()).unwrap();
+        assert_eq!(expected, actual);
+        assert_eq!(
+            "line 3: ignored: unknown transaction type\n",
+            String::from_utf8(errors.0.borrow().clone()).unwrap()
+        );
+
+        let errors = SharedOutput::default();
+        let kinds = TransactionKinds::default().unknown_types(UnknownTypePolicy::Warn);
+        let actual = run_kinds(kinds, errors.clone()).unwrap();
+        assert_eq!(expected, actual);
+        assert_eq!(
+            "line 3: warning: skipped unknown transaction type \"bonus\"\n",
+            String::from_utf8(errors.0.borrow().clone()).unwrap()
+        );
+
+        let kinds = TransactionKinds::default().unknown_types(UnknownTypePolicy::Reject);
+        match run_kinds(kinds, SharedOutput::default()) {
+            Err(PipelineError::Input(SourceError::InvalidRecord { line, .. })) => {
+                assert_eq!(3, line)
+            }
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn pipeline_run_sharded_reports_unknown_type_after_earlier_records() {
+        let input = "type, client, tx, amount\nwithdrawal, 1, 1, 5.0\nbonus, 2, 2, 1.0\n";
+        let errors = SharedOutput::default();
+
+        PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(input.as_bytes()))
+            .output(Box::new(io::sink()))
+            .error_sink(Box::new(ErrorLogSink::new(errors.clone())))
+            .kinds(TransactionKinds::default().unknown_types(UnknownTypePolicy::Warn))
+            .shards(4)
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        assert_eq!(
+            places(
+                "line 2: client 1, tx 1: InvalidWithdrawal { resulting_amount: -5.0000 }\n\
+             line 3: warning: skipped unknown transaction type \"bonus\"\n"
+            ),
+            String::from_utf8(errors.0.take()).unwrap()
+        );
+    }
+
+    #[test]
+    fn pipeline_run_writes_report_to_output() {
+        let output = SharedOutput::default();
+
+        PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(INPUT.as_bytes()))
+            .output(Box::new(output.clone()))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let actual = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(
+            places("client, available, held, total, locked\n1, 2.0000, 0.0000, 2.0000, false\n"),
+            actual
+        );
+    }
+
+    fn run_with_shards(input: &'static str, shards: usize) -> (String, String) {
+        let output = SharedOutput::default();
+        let errors = SharedOutput::default();
+
+        PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(input.as_bytes()))
+            .output(Box::new(output.clone()))
+            .error_sink(Box::new(ErrorLogSink::new(errors.clone())))
+            .shards(shards)
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let output = String::from_utf8(output.0.take()).unwrap();
+        let errors = String::from_utf8(errors.0.take()).unwrap();
+        (output, errors)
+    }
+
+    #[test]
+    fn pipeline_run_sharded_matches_serial() {
+        let input = include_str!("../test/test8.csv");
+
+        let (output, errors) = run_with_shards(input, 1);
+        assert_eq!(false, errors.is_empty());
+        assert_eq!((output, errors), run_with_shards(input, 4));
+    }
+
+    #[test]
+    fn pipeline_run_notifies_observers() {
+        let counts = Rc::new(RefCell::new((0, 0)));
+
+        PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(INPUT.as_bytes()))
+            .output(Box::new(io::sink()))
+            .observer(Box::new(CountingObserver(counts.clone())))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        assert_eq!((1, 1), *counts.borrow());
+    }
+
+    type Dispositions = Rc<RefCell<Vec<(Option<u64>, Disposition)>>>;
+
+    struct DispositionObserver(Dispositions);
+
+    impl TransactionObserver for DispositionObserver {
+        fn on_transaction(
+            &mut self,
+            _transaction: &Transaction,
+            _result: &Result<(), TransactionError>,
+        ) {
+        }
+
+        fn on_disposition(&mut self, line: Option<u64>, disposition: &Disposition) {
+            self.0.borrow_mut().push((line, disposition.clone()));
+        }
+    }
+
+    #[test]
+    fn pipeline_run_reports_ignored_records_as_dispositions() {
+        let input =
+            "type, client, tx, amount\ndeposit, 1, 1, 2.0\ndeposit, 1, 2\nwithdrawal, 1, 3, 5.0\n";
+        let dispositions = Rc::new(RefCell::new(vec![]));
+        let errors = SharedOutput::default();
+
+        PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(input.as_bytes()))
+            .output(Box::new(io::sink()))
+            .error_sink(Box::new(ErrorLogSink::new(errors.clone())))
+            .observer(Box::new(DispositionObserver(dispositions.clone())))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                (Some(2), Disposition::Applied),
+                (
+                    Some(3),
+                    Disposition::Ignored {
+                        reason: "deposit without an amount"
+                    }
+                ),
+                (
+                    Some(4),
+                    Disposition::Rejected {
+                        error: TransactionError::InvalidWithdrawal {
+                            resulting_amount: amount("-3")
+                        }
+                    }
+                ),
+            ],
+            *dispositions.borrow()
+        );
+        let errors = String::from_utf8(errors.0.take()).unwrap();
+        assert_eq!(
+            true,
+            errors.starts_with("line 3: ignored: deposit without an amount\nline 4: ")
+        );
+    }
+
+    #[test]
+    fn pipeline_run_reads_custom_source() {
+        let output = SharedOutput::default();
+        let transactions = vec![transaction(3, 1, TransactionType::Deposit(amount("1")))];
+
+        PipelineBuilder::new(Database::<Client>::new())
+            .source(Box::new(crate::source::IterSource::new(transactions)))
+            .report(Report::standard().select(&[String::from("total")]).unwrap())
+            .output(Box::new(output.clone()))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let actual = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(places("total\n1.0000\n"), actual);
+    }
+
+    #[test]
+    fn pipeline_build_without_input_returns_err() {
+        let result = PipelineBuilder::new(Database::<Client>::new()).build();
+
+        assert_eq!(true, result.is_err());
+    }
+
+    #[test]
+    fn pipeline_run_writes_report_to_custom_sink() {
+        let output = SharedOutput::default();
+
+        PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(INPUT.as_bytes()))
+            .report(
+                Report::standard()
+                    .select(&[String::from("client")])
+                    .unwrap(),
+            )
+            .report_sink(Box::new(JsonReportSink::new(output.clone())))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let actual = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!("[{\"client\":1}]\n", actual);
+    }
+
+    #[test]
+    fn pipeline_run_client_map_reverses_outputs() {
+        let output = SharedOutput::default();
+        let errors = SharedOutput::default();
+        let map = ClientMap::keyed(7);
+
+        let database = PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(INPUT.as_bytes()))
+            .output(Box::new(output.clone()))
+            .error_sink(Box::new(ErrorLogSink::new(errors.clone())))
+            .client_map(map.clone())
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let anonymized = map.forward(1).unwrap();
+        assert_eq!(true, database.account(anonymized).is_some());
+
+        let actual = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(
+            places("client, available, held, total, locked\n1, 2.0000, 0.0000, 2.0000, false\n"),
+            actual
+        );
+
+        let actual = String::from_utf8(errors.0.borrow().clone()).unwrap();
+        assert_eq!(true, actual.starts_with("line 3: client 1, tx 2:"));
+    }
+
+    #[test]
+    fn pipeline_run_records_events() {
+        let events = SharedOutput::default();
+
+        PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(INPUT.as_bytes()))
+            .output(Box::new(io::sink()))
+            .event_sink(Box::new(JsonLinesEventSink::new(events.clone())))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let actual = String::from_utf8(events.0.borrow().clone()).unwrap();
+        assert_eq!(2, actual.lines().count());
+    }
+
+    #[test]
+    fn pipeline_run_records_rejected_transactions_with_line() {
+        let errors = SharedOutput::default();
+
+        PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(INPUT.as_bytes()))
+            .output(Box::new(io::sink()))
+            .error_sink(Box::new(ErrorLogSink::new(errors.clone())))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let actual = String::from_utf8(errors.0.borrow().clone()).unwrap();
+        assert_eq!(
+            places("line 3: client 1, tx 2: InvalidWithdrawal { resulting_amount: -3.0000 }\n"),
+            actual
+        );
+    }
+
+    #[test]
+    fn pipeline_run_dispute_grace_applies_early_chargeback() {
+        let input =
+            "type, client, tx, amount\ndeposit, 1, 1, 2.0\nchargeback, 1, 1,\ndispute, 1, 1,\n";
+        let run = |grace: Option<u64>| {
+            let errors = SharedOutput::default();
+            let mut builder = PipelineBuilder::new(Database::<Client>::new())
+                .input_reader(Box::new(input.as_bytes()))
+                .output(Box::new(io::sink()))
+                .error_sink(Box::new(ErrorLogSink::new(errors.clone())));
+            if let Some(grace) = grace {
+                builder = builder.dispute_grace(grace);
+            }
+            let database = builder.build().unwrap().run().unwrap();
+            let errors = String::from_utf8(errors.0.borrow().clone()).unwrap();
+            (database.account(1).unwrap().locked(), errors)
+        };
+
+        let (locked, errors) = run(None);
+        assert_eq!(false, locked);
+        assert_eq!(
+            true,
+            errors.starts_with("line 3: client 1, tx 1: Unprocessable")
+        );
+
+        assert_eq!((true, String::new()), run(Some(1)));
+    }
+
+    #[test]
+    fn pipeline_run_follow_reports_on_request_until_stopped() {
+        let path = std::env::temp_dir().join("payments_pipeline_follow.csv");
+        std::fs::write(&path, "type, client, tx, amount\ndeposit, 1, 1, 2.0\n").unwrap();
+        let follow = Follow {
+            poll_interval: Duration::from_millis(1),
+            ..Follow::new()
+        };
+        let signals = follow.clone();
+        let signaller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            signals.report.store(true, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            signals.stop.store(true, Ordering::SeqCst);
+        });
+
+        let output = SharedOutput::default();
+        let database = PipelineBuilder::new(Database::<Client>::new())
+            .input_path(path.to_str().unwrap())
+            .output(Box::new(output.clone()))
+            .follow(follow)
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+        signaller.join().unwrap();
+
+        let report =
+            places("client, available, held, total, locked\n1, 2.0000, 0.0000, 2.0000, false\n");
+        assert_eq!(
+            format!("{}{}", report, report),
+            String::from_utf8(output.0.borrow().clone()).unwrap()
+        );
+        assert_eq!(1, database.accounts().count());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn pipeline_run_follow_rejects_readers() {
+        let result = PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(INPUT.as_bytes()))
+            .follow(Follow::new())
+            .build();
+
+        assert_eq!(true, matches!(result, Err(PipelineError::NotFollowable)));
+    }
+
+    #[test]
+    fn pipeline_run_strict_stops_at_rejected_transaction() {
+        let result = PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(INPUT.as_bytes()))
+            .config(EngineConfig {
+                strict: true,
+                ..EngineConfig::default()
+            })
+            .output(Box::new(io::sink()))
+            .build()
+            .unwrap()
+            .run();
+
+        match result {
+            Err(PipelineError::Rejected { line, .. }) => assert_eq!(Some(3), line),
+            _ => panic!("expected a rejected transaction"),
+        }
+    }
+
+    #[test]
+    fn pipeline_run_strict_stops_at_ignored_record() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 2.0\ndeposit, 1, 2,\n";
+
+        let result = PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(input.as_bytes()))
+            .config(EngineConfig {
+                strict: true,
+                ..EngineConfig::default()
+            })
+            .output(Box::new(io::sink()))
+            .build()
+            .unwrap()
+            .run();
+
+        match result {
+            Err(PipelineError::Input(SourceError::InvalidRecord { line, .. })) => {
+                assert_eq!(3, line)
+            }
+            _ => panic!("expected an ignored record"),
+        }
+    }
+
+    #[test]
+    fn pipeline_run_reorder_window_sorts_by_timestamp_and_flags_late() {
+        // The withdrawal happened after the deposit but was read first, and the last deposit
+        // arrives after both were applied, further behind than the window allows.
+        let input = "type, client, tx, amount, timestamp\n\
+            withdrawal, 1, 2, 1.0, 110\n\
+            deposit, 1, 1, 2.0, 100\n\
+            deposit, 1, 3, 1.0, 500\n\
+            deposit, 1, 4, 1.0, 50\n";
+        let output = SharedOutput::default();
+        let errors = SharedOutput::default();
+
+        PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(input.as_bytes()))
+            .config(EngineConfig {
+                reorder_window: Some(60),
+                ..EngineConfig::default()
+            })
+            .output(Box::new(output.clone()))
+            .error_sink(Box::new(ErrorLogSink::new(errors.clone())))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let actual = String::from_utf8(output.0.take()).unwrap();
+        assert_eq!(
+            places("client, available, held, total, locked\n1, 3.0000, 0.0000, 3.0000, false\n"),
+            actual
+        );
+        let actual = String::from_utf8(errors.0.take()).unwrap();
+        assert_eq!(
+            "line 5: client 1, tx 4: warning: Late { behind: 60 }\n",
+            actual
+        );
+    }
+
+    #[test]
+    fn pipeline_run_soft_limit_applies_and_warns() {
+        let output = SharedOutput::default();
+        let errors = SharedOutput::default();
+
+        PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(INPUT.as_bytes()))
+            .config(EngineConfig {
+                soft_limit: Some(amount("1")),
+                ..EngineConfig::default()
+            })
+            .output(Box::new(output.clone()))
+            .error_sink(Box::new(ErrorLogSink::new(errors.clone())))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let actual = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(
+            places("client, available, held, total, locked\n1, 2.0000, 0.0000, 2.0000, false\n"),
+            actual
+        );
+
+        let actual = String::from_utf8(errors.0.borrow().clone()).unwrap();
+        assert_eq!(
+            places("line 2: client 1, tx 1: warning: SoftLimit { limit: 1.0000 }\nline 3: client 1, tx 2: InvalidWithdrawal { resulting_amount: -3.0000 }\n"),
+            actual
+        );
+    }
+
+    #[test]
+    fn pipeline_run_maximum_amount_rejects_or_flags() {
+        let run = |policy| {
+            let output = SharedOutput::default();
+            let errors = SharedOutput::default();
+
+            PipelineBuilder::new(Database::<Client>::new())
+                .input_reader(Box::new(INPUT.as_bytes()))
+                .config(EngineConfig {
+                    maximum_amount: Some((amount("1"), policy)),
+                    ..EngineConfig::default()
+                })
+                .output(Box::new(output.clone()))
+                .error_sink(Box::new(ErrorLogSink::new(errors.clone())))
+                .build()
+                .unwrap()
+                .run()
+                .unwrap();
+
+            let output = String::from_utf8(output.0.borrow().clone()).unwrap();
+            let errors = String::from_utf8(errors.0.borrow().clone()).unwrap();
+            (output, errors)
+        };
+
+        let (output, errors) = run(MaximumPolicy::Reject);
+        assert_eq!("client, available, held, total, locked\n", output);
+        assert_eq!(
+            places("line 2: client 1, tx 1: AboveMaximumAmount { maximum: 1.0000 }\nline 3: client 1, tx 2: AboveMaximumAmount { maximum: 1.0000 }\n"),
+            errors
+        );
+
+        let (output, errors) = run(MaximumPolicy::Flag);
+        assert_eq!(
+            places("client, available, held, total, locked\n1, 2.0000, 0.0000, 2.0000, false\n"),
+            output
+        );
+        assert_eq!(
+            places("line 2: client 1, tx 1: warning: AboveMaximum { maximum: 1.0000 }\nline 3: client 1, tx 2: InvalidWithdrawal { resulting_amount: -3.0000 }\n"),
+            errors
+        );
+    }
+
+    #[test]
+    fn pipeline_run_enrichers_transform_and_refuse() {
+        let output = SharedOutput::default();
+        let errors = SharedOutput::default();
+        let pause_withdrawals = |transaction: Transaction| match transaction.transaction_type {
+            TransactionType::Withdrawal(_) => Err("withdrawals are paused"),
+            _ => Ok(transaction),
+        };
+        let move_to_client_2 = |transaction: Transaction| {
+            Ok(Transaction {
+                client: 2,
+                ..transaction
+            })
+        };
+
+        PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(INPUT.as_bytes()))
+            .enricher(Box::new(pause_withdrawals))
+            .enricher(Box::new(move_to_client_2))
+            .output(Box::new(output.clone()))
+            .error_sink(Box::new(ErrorLogSink::new(errors.clone())))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let actual = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(
+            places("client, available, held, total, locked\n2, 2.0000, 0.0000, 2.0000, false\n"),
+            actual
+        );
+        let actual = String::from_utf8(errors.0.borrow().clone()).unwrap();
+        assert_eq!(
+            "line 3: client 1, tx 2: Refused { reason: \"withdrawals are paused\" }\n",
+            actual
+        );
+    }
+
+    #[test]
+    fn pipeline_run_risk_annotates_or_blocks() {
+        let run = |policy| {
+            let output = SharedOutput::default();
+            let errors = SharedOutput::default();
+            let scorer = RiskScorer::new(RiskConfig {
+                policy,
+                large_amount: amount("1"),
+                ..RiskConfig::new(40)
+            });
+            let mut report = Report::standard();
+            report.register(Box::new(RiskScoreColumn(scorer.scores())));
+
+            PipelineBuilder::new(Database::<Client>::new())
+                .input_reader(Box::new(INPUT.as_bytes()))
+                .risk(scorer)
+                .report(report)
+                .output(Box::new(output.clone()))
+                .error_sink(Box::new(ErrorLogSink::new(errors.clone())))
+                .build()
+                .unwrap()
+                .run()
+                .unwrap();
+
+            let output = String::from_utf8(output.0.borrow().clone()).unwrap();
+            let errors = String::from_utf8(errors.0.borrow().clone()).unwrap();
+            (output, errors)
+        };
+
+        let (output, errors) = run(RiskPolicy::Annotate);
+        assert_eq!(
+            places("client, available, held, total, locked, risk_score\n1, 2.0000, 0.0000, 2.0000, false, 40\n"),
+            output
+        );
+        assert_eq!(
+            places("line 2: client 1, tx 1: warning: HighRisk { score: 40 }\nline 3: client 1, tx 2: InvalidWithdrawal { resulting_amount: -3.0000 }\n"),
+            errors
+        );
+
+        let (output, errors) = run(RiskPolicy::Block);
+        assert_eq!(
+            "client, available, held, total, locked, risk_score\n",
+            output
+        );
+        assert_eq!(
+            "line 2: client 1, tx 1: HighRisk { score: 40 }\nline 3: client 1, tx 2: HighRisk { score: 40 }\n",
+            errors
+        );
+    }
+
+    #[test]
+    fn pipeline_run_coalesce_matches_uncoalesced_report() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 2.0\ndeposit, 2, 2, 1.0\ndeposit, 1, 3, 3.0\nwithdrawal, 1, 4, 4.0\nwithdrawal, 1, 5, 4.0\n";
+        let mut reports = vec![];
+
+        for coalesce in [false, true] {
+            let output = SharedOutput::default();
+            let errors = SharedOutput::default();
+            let counts = Rc::new(RefCell::new((0, 0)));
+
+            PipelineBuilder::new(Database::<Client>::new())
+                .input_reader(Box::new(input.as_bytes()))
+                .config(EngineConfig {
+                    coalesce,
+                    ..EngineConfig::default()
+                })
+                .observer(Box::new(CountingObserver(counts.clone())))
+                .output(Box::new(output.clone()))
+                .error_sink(Box::new(ErrorLogSink::new(errors.clone())))
+                .build()
+                .unwrap()
+                .run()
+                .unwrap();
+
+            assert_eq!((4, 1), *counts.borrow());
+            reports.push((
+                String::from_utf8(output.0.borrow().clone()).unwrap(),
+                String::from_utf8(errors.0.borrow().clone()).unwrap(),
+            ));
+        }
+
+        assert_eq!(reports[0], reports[1]);
+        assert_eq!(true, reports[1].1.starts_with("line 6: client 1, tx 5:"));
+    }
+
+    #[test]
+    fn pipeline_run_reads_json_lines() {
+        let output = SharedOutput::default();
+        let input = "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"2.0\"}\n";
+
+        PipelineBuilder::new(Database::<Client>::new())
+            .input_reader(Box::new(input.as_bytes()))
+            .format(InputFormat::JsonLines)
+            .output(Box::new(output.clone()))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let actual = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(
+            places("client, available, held, total, locked\n1, 2.0000, 0.0000, 2.0000, false\n"),
+            actual
+        );
+    }
+
+    #[test]
+    fn input_format_from_path_returns_expected() {
+        assert_eq!(
+            InputFormat::default(),
+            InputFormat::from_path("transactions.csv")
+        );
+        assert_eq!(
+            InputFormat::Csv {
+                delimiter: b'\t',
+                has_headers: true,
+            },
+            InputFormat::from_path("transactions.tsv")
+        );
+        assert_eq!(
+            InputFormat::JsonLines,
+            InputFormat::from_path("transactions.jsonl")
+        );
+        assert_eq!(
+            InputFormat::JsonLines,
+            InputFormat::from_path("transactions.ndjson")
+        );
+    }
+
+    #[cfg(feature = "parquet-input")]
+    #[test]
+    fn input_format_parquet_is_read_but_not_followed() {
+        assert_eq!(
+            InputFormat::Parquet,
+            InputFormat::from_path("transactions.parquet")
+        );
+        assert_eq!(
+            InputFormat::Parquet,
+            InputFormat::from_str("parquet").unwrap()
+        );
+        assert_eq!(false, InputFormat::Parquet.can_follow());
+    }
+
+    #[cfg(feature = "avro-input")]
+    #[test]
+    fn input_format_avro_is_read_but_not_followed() {
+        assert_eq!(
+            InputFormat::Avro,
+            InputFormat::from_path("transactions.avro")
+        );
+        assert_eq!(InputFormat::Avro, InputFormat::from_str("avro").unwrap());
+        assert_eq!(false, InputFormat::Avro.can_follow());
+    }
+}
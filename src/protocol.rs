@@ -0,0 +1,269 @@
+use crate::{
+    client::ClientAccount,
+    database::Database,
+    parse_csv, parse_jsonl,
+    transaction::{Transaction, TransactionError},
+};
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::sync::Mutex;
+
+// Framed batch protocol used by the TCP listener.
+//
+// A request frame is a 4 byte big-endian payload length followed by the payload: a batch of
+// headerless CSV records (`type,client,tx,amount[,reason[,to]]`), one per line.
+//
+// The response frame uses the same length prefix, and its payload holds one result code byte per
+// non-empty line of the request, in order. Submitters use the codes to decide which records need
+// to be corrected and resent.
+
+/// Frames larger than this are refused rather than allocated.
+pub const MAX_FRAME_LEN: u32 = 16 * 1024 * 1024;
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResultCode {
+    Applied = 0,
+    Ignored = 1,
+    Malformed = 2,
+    InvalidClient = 10,
+    InvalidDeposit = 11,
+    InvalidWithdrawal = 12,
+    InvalidHold = 13,
+    InvalidRelease = 14,
+    NotFound = 15,
+    Unprocessable = 16,
+    ClientLocked = 17,
+    Refused = 18,
+    DuplicateTransactionId = 19,
+    BelongsToAnotherClient = 20,
+    InvalidTransfer = 21,
+    RecipientLocked = 22,
+    TransferToSelf = 23,
+    NotLocked = 24,
+    AlreadyProcessed = 25,
+    NotLogged = 26,
+    CurrencyMismatch = 27,
+    InvalidConversion = 28,
+    RateUnavailable = 29,
+    ConvertToSameCurrency = 30,
+    InvalidFee = 31,
+    BelowMinimumBalance = 32,
+    AboveMaximumAmount = 33,
+    VelocityLimitExceeded = 34,
+    HighRisk = 35,
+    RuleRejected = 36,
+}
+
+impl ResultCode {
+    /// The code reported for the outcome of applying a transaction.
+    pub fn from_result(result: &Result<(), TransactionError>) -> Self {
+        match result {
+            Ok(_) => Self::Applied,
+            Err(TransactionError::InvalidClient { .. }) => Self::InvalidClient,
+            Err(TransactionError::InvalidDeposit { .. }) => Self::InvalidDeposit,
+            Err(TransactionError::InvalidWithdrawal { .. }) => Self::InvalidWithdrawal,
+            Err(TransactionError::InvalidHold { .. }) => Self::InvalidHold,
+            Err(TransactionError::InvalidRelease { .. }) => Self::InvalidRelease,
+            Err(TransactionError::NotFound { .. }) => Self::NotFound,
+            Err(TransactionError::Unprocessable { .. }) => Self::Unprocessable,
+            Err(TransactionError::ClientLocked) => Self::ClientLocked,
+            Err(TransactionError::Refused { .. }) => Self::Refused,
+            Err(TransactionError::DuplicateTransactionId { .. }) => Self::DuplicateTransactionId,
+            Err(TransactionError::BelongsToAnotherClient { .. }) => Self::BelongsToAnotherClient,
+            Err(TransactionError::InvalidTransfer { .. }) => Self::InvalidTransfer,
+            Err(TransactionError::RecipientLocked { .. }) => Self::RecipientLocked,
+            Err(TransactionError::TransferToSelf) => Self::TransferToSelf,
+            Err(TransactionError::NotLocked) => Self::NotLocked,
+            Err(TransactionError::AlreadyProcessed { .. }) => Self::AlreadyProcessed,
+            Err(TransactionError::NotLogged) => Self::NotLogged,
+            Err(TransactionError::CurrencyMismatch { .. }) => Self::CurrencyMismatch,
+            Err(TransactionError::InvalidConversion { .. }) => Self::InvalidConversion,
+            Err(TransactionError::RateUnavailable { .. }) => Self::RateUnavailable,
+            Err(TransactionError::ConvertToSameCurrency) => Self::ConvertToSameCurrency,
+            Err(TransactionError::InvalidFee { .. }) => Self::InvalidFee,
+            Err(TransactionError::BelowMinimumBalance { .. }) => Self::BelowMinimumBalance,
+            Err(TransactionError::AboveMaximumAmount { .. }) => Self::AboveMaximumAmount,
+            Err(TransactionError::VelocityLimitExceeded { .. }) => Self::VelocityLimitExceeded,
+            Err(TransactionError::HighRisk { .. }) => Self::HighRisk,
+            Err(TransactionError::RuleRejected { .. }) => Self::RuleRejected,
+        }
+    }
+}
+
+/// Reads a single frame. Returns `None` if the stream was closed before a new frame started.
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len_bytes = [0; 4];
+    match reader.read_exact(&mut len_bytes) {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = u32::from_be_bytes(len_bytes);
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "frame of {} bytes exceeds the {} byte limit",
+                len, MAX_FRAME_LEN
+            ),
+        ));
+    }
+
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+
+    Ok(Some(payload))
+}
+
+/// Writes a single length-prefixed frame.
+pub fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+/// Parses every non-empty line of the batch. Records that are ignored are `None`, and malformed
+/// records are errors.
+pub fn parse_batch(payload: &[u8]) -> Vec<Result<Option<Transaction>, ()>> {
+    String::from_utf8_lossy(payload)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| parse_csv::parse_record(line).map_err(|_| ()))
+        .collect()
+}
+
+/// Parses a single line of the line protocol: a JSON object, or otherwise a headerless CSV
+/// record. Records that are ignored are `None`, and malformed records are errors.
+pub fn parse_line(line: &str) -> Result<Option<Transaction>, Box<dyn Error>> {
+    if line.trim_start().starts_with('{') {
+        parse_jsonl::parse_line(line)
+    } else {
+        Ok(parse_csv::parse_record(line)?)
+    }
+}
+
+/// Applies every record in the batch, returning the result code for each non-empty line.
+pub fn process_batch<Account>(payload: &[u8], database: &Mutex<Database<Account>>) -> Vec<u8>
+where
+    Account: ClientAccount,
+{
+    let records = parse_batch(payload);
+
+    // Hold the lock for the whole batch so it's applied without interleaving other submitters.
+    let mut database = database.lock().unwrap();
+
+    records
+        .into_iter()
+        .map(|record| match record {
+            Ok(Some(transaction)) => {
+                ResultCode::from_result(&database.apply_transaction(transaction))
+            }
+            Ok(None) => ResultCode::Ignored,
+            Err(_) => ResultCode::Malformed,
+        } as u8)
+        .collect()
+}
+
+/// Answers request frames until the submitter closes the connection.
+pub fn handle_connection<R, W, Account>(
+    reader: &mut R,
+    writer: &mut W,
+    database: &Mutex<Database<Account>>,
+) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+    Account: ClientAccount,
+{
+    while let Some(payload) = read_frame(reader)? {
+        let codes = process_batch(&payload, database);
+        write_frame(writer, &codes)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+    use std::io::Cursor;
+
+    fn frame(payload: &str) -> Vec<u8> {
+        let mut bytes = vec![];
+        write_frame(&mut bytes, payload.as_bytes()).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn read_frame_write_frame_round_trips() {
+        let mut reader = Cursor::new(frame("deposit,1,1,2.0"));
+
+        let actual = read_frame(&mut reader).unwrap();
+        assert_eq!(Some(b"deposit,1,1,2.0".to_vec()), actual);
+        assert_eq!(None, read_frame(&mut reader).unwrap());
+    }
+
+    #[test]
+    fn read_frame_too_large_returns_err() {
+        let mut reader = Cursor::new((MAX_FRAME_LEN + 1).to_be_bytes().to_vec());
+
+        let actual = read_frame(&mut reader);
+        assert_eq!(true, actual.is_err());
+        assert_eq!(io::ErrorKind::InvalidData, actual.unwrap_err().kind());
+    }
+
+    #[test]
+    fn parse_line_reads_csv_and_json() {
+        let csv = parse_line("deposit, 1, 1, 2.0").unwrap();
+        let json =
+            parse_line("{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"2.0\"}");
+
+        assert_eq!(true, csv.is_some());
+        assert_eq!(csv, json.unwrap());
+        assert_eq!(None, parse_line("deposit, 1, 1,").unwrap());
+        assert_eq!(true, parse_line("{\"type\": \"deposit\"").is_err());
+    }
+
+    #[test]
+    fn process_batch_returns_code_per_record() {
+        let database = Mutex::new(Database::<Client>::new());
+        let payload = "deposit, 1, 1, 2.0\n\nwithdrawal, 1, 2, 5.0\ndeposit, 1, 3,\ngarbage\n";
+
+        let actual = process_batch(payload.as_bytes(), &database);
+
+        let expected = vec![
+            ResultCode::Applied as u8,
+            ResultCode::InvalidWithdrawal as u8,
+            ResultCode::Ignored as u8,
+            ResultCode::Malformed as u8,
+        ];
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn handle_connection_answers_each_frame() {
+        let database = Mutex::new(Database::<Client>::new());
+        let mut request = frame("deposit,4,1,3.0");
+        request.extend(frame("dispute,4,1\ndispute,4,1"));
+
+        let mut reader = Cursor::new(request);
+        let mut writer = vec![];
+        handle_connection(&mut reader, &mut writer, &database).unwrap();
+
+        let mut responses = Cursor::new(writer);
+        assert_eq!(
+            Some(vec![ResultCode::Applied as u8]),
+            read_frame(&mut responses).unwrap()
+        );
+        assert_eq!(
+            Some(vec![
+                ResultCode::Applied as u8,
+                ResultCode::Unprocessable as u8
+            ]),
+            read_frame(&mut responses).unwrap()
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+// Every feature that draws random numbers takes its generator from the run's `Randomness`, so one
+// `--seed` reproduces a whole run. Each feature draws from its own stream, named after it, so
+// drawing more numbers in one feature never changes what another draws. Without a seed the
+// streams are seeded from entropy, unless the run is deterministic.
+
+/// The seed used by deterministic runs that aren't given one.
+pub const DEFAULT_SEED: u64 = 0x5eed_5eed;
+
+/// A small, fast generator. Nothing drawn from it needs to be unpredictable, only spread out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rng {
+    seed: u64,
+    state: u64,
+}
+
+impl Rng {
+    /// A generator drawing straight from the seed rather than from one of a run's streams.
+    pub fn seeded(seed: u64) -> Self {
+        Self { seed, state: seed }
+    }
+
+    /// The seed of the run the generator belongs to, which reproduces it when passed to `--seed`.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The next number of a SplitMix64 sequence.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number from 0 up to, but not including, the bound.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+/// Where a run's random numbers come from.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Randomness {
+    /// Seeds every stream of the run.
+    pub seed: Option<u64>,
+    /// Seeds every stream from `DEFAULT_SEED` when no seed is given, rather than from entropy.
+    pub deterministic: bool,
+}
+
+impl Randomness {
+    /// The generator for the named stream.
+    pub fn rng(&self, stream: &str) -> Rng {
+        let seed = match (self.seed, self.deterministic) {
+            (Some(seed), _) => seed,
+            (None, true) => DEFAULT_SEED,
+            // The standard library keys every `RandomState` randomly.
+            (None, false) => RandomState::new().build_hasher().finish(),
+        };
+        Rng {
+            seed,
+            state: seed ^ stream_hash(stream),
+        }
+    }
+}
+
+/// FNV-1a, which unlike the standard library's hasher is the same in every build.
+fn stream_hash(stream: &str) -> u64 {
+    stream.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draws(mut rng: Rng) -> Vec<u64> {
+        (0..4).map(|_| rng.next_u64()).collect()
+    }
+
+    #[test]
+    fn randomness_rng_repeats_seeded_streams() {
+        let unseeded = Randomness::default().rng("stress");
+        let reseeded = Randomness {
+            seed: Some(unseeded.seed()),
+            deterministic: false,
+        };
+        assert_eq!(draws(unseeded), draws(reseeded.rng("stress")));
+
+        let seeded = Randomness {
+            seed: Some(7),
+            deterministic: false,
+        };
+        assert_eq!(draws(seeded.rng("stress")), draws(seeded.rng("stress")));
+        assert_eq!(
+            false,
+            draws(seeded.rng("stress")) == draws(seeded.rng("jitter"))
+        );
+
+        let deterministic = Randomness {
+            seed: None,
+            deterministic: true,
+        };
+        assert_eq!(DEFAULT_SEED, deterministic.rng("stress").seed());
+        assert_eq!(
+            draws(deterministic.rng("stress")),
+            draws(deterministic.rng("stress"))
+        );
+    }
+
+    #[test]
+    fn rng_below_stays_under_bound() {
+        let mut rng = Rng::seeded(DEFAULT_SEED);
+        assert_eq!(true, (0..1000).all(|_| rng.below(10) < 10));
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{
+    amount::{Amount, RoundingMode},
+    currency::Currency,
+};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// A rates file gives the rate for converting one currency into another, as `from, to, rate`
+// rows such as `EUR, USD, 1.0825`. Each direction is listed separately, as a buy and sell rate
+// usually differ. A conversion is priced once, when it's applied, and the amount it credits is
+// kept with it, so replaying it later gives the same result whatever rates are loaded then.
+
+#[derive(Debug)]
+pub enum RatesError {
+    Csv(csv::Error),
+    /// The rate isn't a positive decimal number.
+    InvalidRate {
+        passed: String,
+    },
+    /// The file lists the pair more than once.
+    DuplicatePair {
+        from: Currency,
+        to: Currency,
+    },
+}
+
+impl From<csv::Error> for RatesError {
+    fn from(e: csv::Error) -> Self {
+        Self::Csv(e)
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct RateRecord {
+    from: Currency,
+    to: Currency,
+    rate: String,
+}
+
+/// The rates conversions are priced at, by currency pair.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExchangeRates {
+    rates: HashMap<(Currency, Currency), Decimal>,
+}
+
+impl ExchangeRates {
+    /// Reads a CSV rates file with `from, to, rate` columns.
+    pub fn load(path: &str) -> Result<Self, RatesError> {
+        let rdr = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        Self::read(rdr)
+    }
+
+    fn read<R: std::io::Read>(mut rdr: csv::Reader<R>) -> Result<Self, RatesError> {
+        let mut rates = Self::default();
+        for result in rdr.deserialize() {
+            let record: RateRecord = result?;
+            let rate = match Decimal::from_str(&record.rate) {
+                Ok(rate) if rate > Decimal::ZERO => rate,
+                _ => {
+                    return Err(RatesError::InvalidRate {
+                        passed: record.rate,
+                    })
+                }
+            };
+
+            if !rates.insert(record.from, record.to, rate) {
+                return Err(RatesError::DuplicatePair {
+                    from: record.from,
+                    to: record.to,
+                });
+            }
+        }
+
+        Ok(rates)
+    }
+
+    /// Sets the rate of the pair, returning false if it already had one.
+    pub fn insert(&mut self, from: Currency, to: Currency, rate: Decimal) -> bool {
+        self.rates.insert((from, to), rate).is_none()
+    }
+
+    /// The rate for converting `from` into `to`, if one was loaded.
+    pub fn rate(&self, from: Currency, to: Currency) -> Option<Decimal> {
+        self.rates.get(&(from, to)).copied()
+    }
+
+    /// The amount credited for converting the amount from one currency into another, rounded with
+    /// the rounding mode.
+    pub fn convert(
+        &self,
+        amount: Amount,
+        from: Currency,
+        to: Currency,
+        mode: RoundingMode,
+    ) -> Option<Amount> {
+        self.rate(from, to).map(|rate| amount.scale(rate, mode))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn currency(code: &str) -> Currency {
+        Currency::from_str(code).unwrap()
+    }
+
+    fn read(input: &str) -> Result<ExchangeRates, RatesError> {
+        ExchangeRates::read(
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(input.as_bytes()),
+        )
+    }
+
+    #[test]
+    fn exchange_rates_read_returns_expected() {
+        let rates = read("from, to, rate\nEUR, USD, 1.0825\nusd, eur, 0.9200\n").unwrap();
+
+        assert_eq!(
+            Some(Decimal::new(10825, 4)),
+            rates.rate(currency("EUR"), currency("USD"))
+        );
+        assert_eq!(
+            Some(Decimal::new(9200, 4)),
+            rates.rate(currency("USD"), currency("EUR"))
+        );
+        assert_eq!(None, rates.rate(currency("EUR"), currency("GBP")));
+    }
+
+    #[test]
+    fn exchange_rates_read_invalid_rate_returns_err() {
+        assert_eq!(
+            true,
+            matches!(
+                read("from, to, rate\nEUR, USD, -1\n"),
+                Err(RatesError::InvalidRate { .. })
+            )
+        );
+        assert_eq!(
+            true,
+            matches!(
+                read("from, to, rate\nEUR, USD, 1.1\nEUR, USD, 1.2\n"),
+                Err(RatesError::DuplicatePair { .. })
+            )
+        );
+    }
+
+    #[test]
+    fn exchange_rates_convert_rounds_with_mode() {
+        let mut rates = ExchangeRates::default();
+        rates.insert(currency("EUR"), currency("USD"), Decimal::new(10825, 4));
+
+        assert_eq!(
+            Some(Amount::new(10825)),
+            rates.convert(
+                Amount::new(10000),
+                currency("EUR"),
+                currency("USD"),
+                RoundingMode::HalfUp
+            )
+        );
+        // 0.0005 EUR is 0.00054125 USD.
+        assert_eq!(
+            Some(Amount::new(5)),
+            rates.convert(
+                Amount::new(5),
+                currency("EUR"),
+                currency("USD"),
+                RoundingMode::HalfUp
+            )
+        );
+        assert_eq!(
+            None,
+            rates.convert(
+                Amount::new(5),
+                currency("USD"),
+                currency("EUR"),
+                RoundingMode::HalfUp
+            )
+        );
+    }
+}
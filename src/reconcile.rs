@@ -0,0 +1,212 @@
+use crate::{
+    amount::Amount,
+    client::{Client, ClientAccount},
+    currency::Currency,
+    database::AccountObserver,
+    transaction::{Transaction, TransactionError, TransactionType},
+};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+// The reconciliation summary has the numbers auditors ask for first: how much came in, went out
+// and was charged back over the run, how much is held and how many accounts are locked once it's
+// over, and how many records were rejected. Amounts are taken from the transactions applied, and a
+// chargeback counts the amount of the transaction it reversed. Balances kept in a client's other
+// currencies are left out, as amounts in different currencies can't be summed.
+
+/// The amounts moved by the transactions applied in a run.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RunTotals {
+    pub deposited: Amount,
+    pub withdrawn: Amount,
+    pub charged_back: Amount,
+}
+
+/// Adds up the transactions the database applies. Shared so the totals can be read once the run
+/// is over.
+#[derive(Clone, Default)]
+pub struct TotalsObserver(pub Arc<Mutex<RunTotals>>);
+
+impl AccountObserver<Client> for TotalsObserver {
+    /// Counts the notified transaction itself rather than the account's events, as a coalesced
+    /// run is notified once per original transaction after a single merged event, and a fee is
+    /// recorded as an event after the transaction it was charged on.
+    fn on_transaction(
+        &mut self,
+        transaction: &Transaction,
+        result: &Result<(), TransactionError>,
+        account: Option<&Client>,
+    ) {
+        let account = match (result, account) {
+            (Ok(()), Some(account)) => account,
+            _ => return,
+        };
+        let own = |currency: Option<Currency>| currency.is_none() || currency == account.currency();
+
+        let mut totals = self.0.lock().unwrap();
+        match transaction.transaction_type {
+            TransactionType::Deposit(amount) if own(transaction.currency) => {
+                totals.deposited = totals.deposited + amount
+            }
+            TransactionType::Withdrawal(amount) if own(transaction.currency) => {
+                totals.withdrawn = totals.withdrawn + amount
+            }
+            // A chargeback reverses the amount of the transaction it refers to.
+            TransactionType::Chargeback { .. } => {
+                let reversed = account
+                    .history_index(transaction.id)
+                    .map(|index| account.history()[index].1)
+                    .filter(|reversed| own(reversed.currency));
+                if let Some(amount) = reversed.and_then(|reversed| reversed.amount()) {
+                    totals.charged_back = totals.charged_back + amount;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The summary written once a run is over.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Reconciliation {
+    pub deposited: Amount,
+    pub withdrawn: Amount,
+    pub held: Amount,
+    pub charged_back: Amount,
+    pub locked_accounts: u64,
+    pub rejected: u64,
+}
+
+impl Reconciliation {
+    /// Combines the run's totals with the accounts as they are at the end of it.
+    pub fn new<'a>(
+        totals: RunTotals,
+        accounts: impl Iterator<Item = &'a Client>,
+        rejected: u64,
+    ) -> Self {
+        let mut reconciliation = Self {
+            deposited: totals.deposited,
+            withdrawn: totals.withdrawn,
+            held: Amount::zero(),
+            charged_back: totals.charged_back,
+            locked_accounts: 0,
+            rejected,
+        };
+        for account in accounts {
+            reconciliation.held = reconciliation.held + account.held();
+            if account.locked() {
+                reconciliation.locked_accounts += 1;
+            }
+        }
+        reconciliation
+    }
+
+    /// Writes the summary as a header row and a row of totals.
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "deposited, withdrawn, held, charged_back, locked_accounts, rejected"
+        )?;
+        writeln!(
+            writer,
+            "{}, {}, {}, {}, {}, {}",
+            self.deposited,
+            self.withdrawn,
+            self.held,
+            self.charged_back,
+            self.locked_accounts,
+            self.rejected
+        )?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{chargeback, deposit, dispute, withdrawal};
+    use crate::{
+        amount::RoundingMode,
+        coalesce::{self, Step},
+        database::Database,
+        fees::{Fee, FeeSchedule},
+    };
+
+    #[test]
+    fn reconciliation_write_returns_expected() {
+        let observer = TotalsObserver::default();
+        let mut database = Database::<Client>::new();
+        database.subscribe(Box::new(observer.clone()));
+
+        for transaction in [
+            deposit(1, 1, 50000),
+            withdrawal(1, 2, 10000),
+            withdrawal(1, 3, 90000),
+            deposit(2, 4, 20000),
+            dispute(2, 4),
+            chargeback(2, 4),
+            deposit(3, 5, 30000),
+            dispute(3, 5),
+        ] {
+            let _result = database.apply_transaction(transaction);
+        }
+
+        let totals = *observer.0.lock().unwrap();
+        let reconciliation = Reconciliation::new(totals, database.accounts(), 1);
+        let mut output = vec![];
+        reconciliation.write(&mut output).unwrap();
+
+        assert_eq!(
+            format!(
+                "deposited, withdrawn, held, charged_back, locked_accounts, rejected\n\
+                 {}, {}, {}, {}, 1, 1\n",
+                Amount::new(100000),
+                Amount::new(10000),
+                Amount::new(30000),
+                Amount::new(20000)
+            ),
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    fn totals(database: &mut Database<Client>) -> TotalsObserver {
+        let observer = TotalsObserver::default();
+        database.subscribe(Box::new(observer.clone()));
+        observer
+    }
+
+    #[test]
+    fn totals_observer_counts_each_coalesced_transaction_once() {
+        let mut database = Database::<Client>::new();
+        let observer = totals(&mut database);
+
+        let steps = coalesce::plan(vec![
+            deposit(1, 1, 10000),
+            deposit(1, 2, 20000),
+            deposit(1, 3, 130000),
+        ]);
+        assert_eq!(true, matches!(steps[..], [Step::Run(_)]));
+        database
+            .apply_merged(steps[0].transactions(), steps[0].merged())
+            .unwrap();
+
+        assert_eq!(Amount::new(160000), observer.0.lock().unwrap().deposited);
+    }
+
+    #[test]
+    fn totals_observer_counts_transactions_charged_fees() {
+        let schedule = FeeSchedule {
+            deposit: Some(Fee::Flat(Amount::new(1000))),
+            withdrawal: Some(Fee::Flat(Amount::new(1000))),
+        };
+        let mut database = Database::<Client>::new().with_fees(schedule, RoundingMode::HalfUp);
+        let observer = totals(&mut database);
+
+        database.apply_transaction(deposit(1, 1, 50000)).unwrap();
+        database.apply_transaction(withdrawal(1, 2, 10000)).unwrap();
+
+        let totals = *observer.0.lock().unwrap();
+        assert_eq!(Amount::new(50000), totals.deposited);
+        assert_eq!(Amount::new(10000), totals.withdrawn);
+    }
+}
@@ -0,0 +1,320 @@
+use crate::{
+    client::{ClientAccount, ClientId},
+    report::ReportColumn,
+    source::{InputPosition, SourceError, TransactionSource},
+    transaction::{Transaction, TransactionType},
+};
+use std::collections::HashMap;
+use std::error::Error;
+
+// Production inputs can be replayed in staging under anonymized client ids. Ids are mapped as
+// transactions are read, so the engine only ever sees the anonymized ids, and mapped back in
+// everything written about a client so results can be compared with production.
+
+#[derive(Debug)]
+pub enum RemapError {
+    Csv(csv::Error),
+    /// The mapping file lists the client more than once.
+    DuplicateClient {
+        client: ClientId,
+    },
+    /// The mapping file gives more than one client the same alias.
+    DuplicateAlias {
+        alias: ClientId,
+    },
+}
+
+impl From<csv::Error> for RemapError {
+    fn from(e: csv::Error) -> Self {
+        Self::Csv(e)
+    }
+}
+
+/// A client that is missing from the mapping file.
+#[derive(Debug, PartialEq)]
+pub struct UnmappedClient {
+    pub client: ClientId,
+}
+
+impl std::fmt::Display for UnmappedClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "client {} is not in the client map", self.client)
+    }
+}
+
+impl Error for UnmappedClient {}
+
+#[derive(serde::Deserialize)]
+struct MappingRecord {
+    client: ClientId,
+    alias: ClientId,
+}
+
+/// Maps client ids to anonymized ids and back.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientMap {
+    /// Pairs read from a mapping file. Clients that aren't listed can't be mapped.
+    Table {
+        forward: HashMap<ClientId, ClientId>,
+        reverse: HashMap<ClientId, ClientId>,
+    },
+    /// A keyed permutation of every client id, so no table needs to be shared.
+    Keyed { key: u64 },
+}
+
+impl ClientMap {
+    /// Reads a CSV mapping file with `client, alias` columns.
+    pub fn load(path: &str) -> Result<Self, RemapError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+
+        let mut forward = HashMap::new();
+        let mut reverse = HashMap::new();
+        for result in rdr.deserialize() {
+            let record: MappingRecord = result?;
+
+            if forward.insert(record.client, record.alias).is_some() {
+                return Err(RemapError::DuplicateClient {
+                    client: record.client,
+                });
+            }
+            if reverse.insert(record.alias, record.client).is_some() {
+                return Err(RemapError::DuplicateAlias {
+                    alias: record.alias,
+                });
+            }
+        }
+
+        Ok(Self::Table { forward, reverse })
+    }
+
+    /// Maps every client id through a permutation derived from the key.
+    pub fn keyed(key: u64) -> Self {
+        Self::Keyed { key }
+    }
+
+    /// The anonymized id of the client.
+    pub fn forward(&self, client: ClientId) -> Option<ClientId> {
+        match self {
+            Self::Table { forward, .. } => forward.get(&client).copied(),
+            Self::Keyed { key } => Some(permute(*key, client)),
+        }
+    }
+
+    /// The original id of an anonymized client. Ids that were never mapped are returned as is.
+    pub fn reverse(&self, client: ClientId) -> ClientId {
+        match self {
+            Self::Table { reverse, .. } => reverse.get(&client).copied().unwrap_or(client),
+            Self::Keyed { key } => unpermute(*key, client),
+        }
+    }
+
+    /// The transaction with its client, and the recipient of a transfer, anonymized.
+    pub fn forward_transaction(
+        &self,
+        transaction: Transaction,
+    ) -> Result<Transaction, UnmappedClient> {
+        let forward = |client| self.forward(client).ok_or(UnmappedClient { client });
+
+        let transaction_type = match transaction.transaction_type {
+            TransactionType::Transfer { to, amount } => TransactionType::Transfer {
+                to: forward(to)?,
+                amount,
+            },
+            transaction_type => transaction_type,
+        };
+
+        Ok(Transaction {
+            transaction_type,
+            client: forward(transaction.client)?,
+            ..transaction
+        })
+    }
+
+    /// The transaction with its original client ids.
+    pub fn reverse_transaction(&self, transaction: &Transaction) -> Transaction {
+        let transaction_type = match transaction.transaction_type {
+            TransactionType::Transfer { to, amount } => TransactionType::Transfer {
+                to: self.reverse(to),
+                amount,
+            },
+            transaction_type => transaction_type,
+        };
+
+        Transaction {
+            transaction_type,
+            client: self.reverse(transaction.client),
+            ..*transaction
+        }
+    }
+}
+
+const ROUNDS: u8 = 4;
+
+/// A round of a Feistel network over the two bytes of a client id. Each round is reversible
+/// whatever the round function, so the whole permutation is too.
+fn round_function(key: u64, round: u8, half: u8) -> u8 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key.to_le_bytes().iter().chain(&[round, half]) {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    // FNV-1a barely mixes its last bytes into the high bits, so they are spread before truncating.
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash as u8
+}
+
+fn permute(key: u64, client: ClientId) -> ClientId {
+    let [mut left, mut right] = client.to_be_bytes();
+    for round in 0..ROUNDS {
+        let next = left ^ round_function(key, round, right);
+        left = right;
+        right = next;
+    }
+    ClientId::from_be_bytes([left, right])
+}
+
+fn unpermute(key: u64, client: ClientId) -> ClientId {
+    let [mut left, mut right] = client.to_be_bytes();
+    for round in (0..ROUNDS).rev() {
+        let previous = right ^ round_function(key, round, left);
+        right = left;
+        left = previous;
+    }
+    ClientId::from_be_bytes([left, right])
+}
+
+/// Anonymizes the clients of every transaction read from the inner source.
+pub struct RemapSource {
+    inner: Box<dyn TransactionSource>,
+    map: ClientMap,
+}
+
+impl RemapSource {
+    pub fn new(inner: Box<dyn TransactionSource>, map: ClientMap) -> Self {
+        Self { inner, map }
+    }
+}
+
+impl TransactionSource for RemapSource {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        let transaction = match self.inner.next()? {
+            Ok(transaction) => transaction,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let line = self.inner.line().unwrap_or_default();
+        Some(self.map.forward_transaction(transaction).map_err(|error| {
+            SourceError::InvalidRecord {
+                line,
+                error: Box::new(error),
+            }
+        }))
+    }
+
+    fn line(&self) -> Option<u64> {
+        self.inner.line()
+    }
+
+    fn position(&self) -> Option<InputPosition> {
+        self.inner.position()
+    }
+}
+
+/// The original id of the client, in place of the report's `client` column.
+pub struct OriginalClientColumn {
+    pub map: ClientMap,
+}
+
+impl<Account: ClientAccount> ReportColumn<Account> for OriginalClientColumn {
+    fn name(&self) -> &str {
+        "client"
+    }
+
+    fn value(&self, account: &Account) -> String {
+        format!("{:?}", self.map.reverse(account.id()))
+    }
+
+    fn json_value(&self, account: &Account) -> serde_json::Value {
+        serde_json::json!(self.map.reverse(account.id()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::Amount, source::IterSource};
+
+    fn table() -> ClientMap {
+        let forward: HashMap<ClientId, ClientId> = vec![(1, 501), (2, 502)].into_iter().collect();
+        let reverse = forward.iter().map(|(k, v)| (*v, *k)).collect();
+        ClientMap::Table { forward, reverse }
+    }
+
+    #[test]
+    fn client_map_keyed_is_a_permutation() {
+        let map = ClientMap::keyed(0x5eed);
+
+        let mut seen = vec![false; 1 << 16];
+        for client in 0..=ClientId::MAX {
+            let alias = map.forward(client).unwrap();
+            assert_eq!(client, map.reverse(alias));
+            seen[alias as usize] = true;
+        }
+        assert_eq!(true, seen.iter().all(|seen| *seen));
+        assert_eq!(false, map.forward(1) == ClientMap::keyed(0x5eee).forward(1));
+    }
+
+    #[test]
+    fn client_map_forward_transaction_maps_transfer_recipient() {
+        let transfer = Transaction {
+            transaction_type: TransactionType::Transfer {
+                to: 2,
+                amount: Amount::new(1),
+            },
+            client: 1,
+            id: 3,
+            timestamp: None,
+            currency: None,
+        };
+
+        let expected = Transaction {
+            transaction_type: TransactionType::Transfer {
+                to: 502,
+                amount: Amount::new(1),
+            },
+            client: 501,
+            id: 3,
+            timestamp: None,
+            currency: None,
+        };
+        let map = table();
+        assert_eq!(Ok(expected), map.forward_transaction(transfer));
+        assert_eq!(transfer, map.reverse_transaction(&expected));
+    }
+
+    #[test]
+    fn remap_source_unmapped_client_returns_err() {
+        let deposit = |client| Transaction {
+            transaction_type: TransactionType::Deposit(Amount::new(1)),
+            client,
+            id: 1,
+            timestamp: None,
+            currency: None,
+        };
+        let inner = IterSource::new(vec![deposit(1), deposit(3)]);
+        let mut source = RemapSource::new(Box::new(inner), table());
+
+        assert_eq!(501, source.next().unwrap().unwrap().client);
+        match source.next().unwrap() {
+            Err(SourceError::InvalidRecord { error, .. }) => {
+                assert_eq!(true, error.is::<UnmappedClient>())
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
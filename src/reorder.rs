@@ -0,0 +1,158 @@
+use crate::{
+    clock::Timestamp,
+    source::{InputPosition, SourceError, TransactionSource},
+    transaction::Transaction,
+};
+use std::collections::{BTreeMap, VecDeque};
+
+// Merged feeds aren't strictly ordered, so a transaction may be read a little after ones that
+// happened later. A reorder window holds transactions back until one at least the window newer
+// has been read, returning them sorted by timestamp. One that arrives later than the window can't
+// be sorted in; it's returned straight away, and the pipeline flags it if a newer one was already
+// applied. Transactions without a timestamp keep their place relative to the ones read before
+// them.
+
+/// Sorts transactions by timestamp, holding each back until one `window` seconds newer is read.
+pub struct ReorderSource {
+    inner: Box<dyn TransactionSource>,
+    window: u64,
+    /// Transactions waiting to be returned, by timestamp and then the order they were read in.
+    held: BTreeMap<(Timestamp, u64), (Transaction, Option<u64>)>,
+    /// Errors are returned as soon as they're read.
+    errors: VecDeque<(SourceError, Option<u64>)>,
+    /// The newest timestamp read so far.
+    latest: Option<Timestamp>,
+    read: u64,
+    exhausted: bool,
+    line: Option<u64>,
+}
+
+impl ReorderSource {
+    pub fn new(inner: Box<dyn TransactionSource>, window: u64) -> Self {
+        Self {
+            inner,
+            window,
+            held: BTreeMap::new(),
+            errors: VecDeque::new(),
+            latest: None,
+            read: 0,
+            exhausted: false,
+            line: None,
+        }
+    }
+
+    /// The oldest transaction held, if no transaction within the window of it can still arrive.
+    fn pop_ready(&mut self) -> Option<(Transaction, Option<u64>)> {
+        let (timestamp, _) = *self.held.keys().next()?;
+        let ready = self.exhausted
+            || self
+                .latest
+                .is_some_and(|latest| timestamp.saturating_add(self.window) <= latest);
+        if !ready {
+            return None;
+        }
+        self.held.pop_first().map(|(_, held)| held)
+    }
+
+    fn hold(&mut self, transaction: Transaction, line: Option<u64>) {
+        if transaction.timestamp > self.latest {
+            self.latest = transaction.timestamp;
+        }
+        let timestamp = transaction.timestamp.or(self.latest).unwrap_or_default();
+        self.held
+            .insert((timestamp, self.read), (transaction, line));
+        self.read += 1;
+    }
+}
+
+impl TransactionSource for ReorderSource {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        loop {
+            if let Some((error, line)) = self.errors.pop_front() {
+                self.line = line;
+                return Some(Err(error));
+            }
+            if let Some((transaction, line)) = self.pop_ready() {
+                self.line = line;
+                return Some(Ok(transaction));
+            }
+            if self.exhausted {
+                return None;
+            }
+
+            match self.inner.next() {
+                Some(Ok(transaction)) => self.hold(transaction, self.inner.line()),
+                // Nothing more can arrive for now, so held transactions wait for the next read.
+                Some(Err(SourceError::Waiting)) => return Some(Err(SourceError::Waiting)),
+                Some(Err(e)) => self.errors.push_back((e, self.inner.line())),
+                None => self.exhausted = true,
+            }
+        }
+    }
+
+    fn line(&self) -> Option<u64> {
+        self.line
+    }
+
+    /// Held transactions would be lost if a run resumed after them, so there's no position to
+    /// resume from while any are held.
+    fn position(&self) -> Option<InputPosition> {
+        if self.held.is_empty() && self.errors.is_empty() {
+            self.inner.position()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::Amount, source::IterSource, transaction::TransactionType};
+
+    fn deposit(id: u32, timestamp: Option<Timestamp>) -> Transaction {
+        Transaction {
+            transaction_type: TransactionType::Deposit(Amount::new(10000)),
+            client: 1,
+            id,
+            timestamp,
+            currency: None,
+        }
+    }
+
+    fn read_ids(transactions: Vec<Transaction>, window: u64) -> Vec<u32> {
+        let mut source = ReorderSource::new(Box::new(IterSource::new(transactions)), window);
+        let mut ids = vec![];
+        while let Some(transaction) = source.next() {
+            ids.push(transaction.unwrap().id);
+        }
+        ids
+    }
+
+    #[test]
+    fn reorder_source_sorts_within_window() {
+        let transactions = vec![
+            deposit(1, Some(100)),
+            deposit(3, Some(130)),
+            deposit(2, Some(110)),
+            deposit(4, None),
+            deposit(5, Some(200)),
+            // Older than the window allows, so it's returned as soon as it's read.
+            deposit(6, Some(120)),
+            deposit(7, Some(190)),
+        ];
+
+        assert_eq!(vec![1, 2, 3, 4, 6, 7, 5], read_ids(transactions, 30));
+    }
+
+    #[test]
+    fn reorder_source_keeps_order_of_equal_timestamps() {
+        let transactions = vec![
+            deposit(1, Some(100)),
+            deposit(2, Some(100)),
+            deposit(3, Some(90)),
+        ];
+
+        assert_eq!(vec![3, 1, 2], read_ids(transactions, 60));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{
+    client::{Client, ClientId},
+    database::Database,
+    export, parse_csv,
+    report::Report,
+    snapshot::SnapshotFormat,
+};
+use std::io::{self, BufRead, Write};
+
+// The REPL applies transactions typed one at a time, for trying out how the engine handles a
+// sequence of them or reproducing what happened to a client during a support investigation.
+// Transactions are typed as records without commas, e.g. `deposit 1 100 5.0`, and go through the
+// same database as a run over a file, so rules and observers apply as they would there.
+
+const HELP: &str = "\
+<type> <client> <tx> [amount] [reason]  apply a transaction, e.g. `deposit 1 100 5.0`
+report                                  print the balances of every client
+history <client>                        print a client's transactions and their states
+save <path>                             save a snapshot of every account
+help                                    print this message
+quit                                    leave the REPL";
+
+/// Reads commands until the input ends or `quit` is entered, writing the outcome of each.
+/// Snapshots are saved in `snapshot_format`.
+pub fn run<R, W>(
+    database: &mut Database<Client>,
+    snapshot_format: SnapshotFormat,
+    input: R,
+    mut output: W,
+) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut lines = input.lines();
+    loop {
+        write!(output, "> ")?;
+        output.flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(()),
+        };
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => {}
+            ["quit" | "exit"] => return Ok(()),
+            ["help"] => writeln!(output, "{}", HELP)?,
+            ["report"] => database.write_report(&Report::standard(), &mut output)?,
+            ["history", client] => match client.parse::<ClientId>() {
+                Ok(client) => match database.account(client) {
+                    Some(account) => export::write_history(&export::history(account), &mut output)
+                        .map_err(io::Error::from)?,
+                    None => writeln!(output, "client {} has no account", client)?,
+                },
+                Err(_) => writeln!(output, "invalid client: {}", client)?,
+            },
+            ["save", path] => match database.save_snapshot(path, snapshot_format) {
+                Ok(()) => writeln!(output, "saved {}", path)?,
+                Err(e) => writeln!(output, "failed to save {}: {:?}", path, e)?,
+            },
+            fields => match parse_csv::parse_record(&fields.join(",")) {
+                Ok(Some(transaction)) => match database.apply_transaction(transaction) {
+                    Ok(()) => writeln!(output, "applied")?,
+                    Err(e) => writeln!(output, "rejected: {:?}", e)?,
+                },
+                Ok(None) => writeln!(output, "ignored")?,
+                Err(e) => writeln!(output, "error: {}", e)?,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::places;
+
+    fn run_commands(database: &mut Database<Client>, commands: &str) -> String {
+        let mut output = vec![];
+        run(
+            database,
+            SnapshotFormat::Bincode,
+            commands.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn run_applies_transactions_to_database() {
+        let mut database = Database::new();
+
+        let actual = run_commands(
+            &mut database,
+            "deposit 1 100 5.0\n\
+             withdrawal 1 101 6.0\n\
+             dispute 1 100\n\
+             \n\
+             report\n\
+             history 1\n\
+             quit\n\
+             deposit 1 102 1.0\n",
+        );
+
+        assert_eq!(
+            places(
+                "> applied\n\
+             > rejected: InvalidWithdrawal { resulting_amount: -1.0000 }\n\
+             > applied\n\
+             > \
+             > client, available, held, total, locked\n\
+             1, 0.0000, 5.0000, 5.0000, false\n\
+             > tx,type,amount,currency,state\n\
+             100,deposit,5.0000,,Disputed\n\
+             100,dispute,,,Ok\n\
+             > "
+            ),
+            actual
+        );
+        assert_eq!(
+            true,
+            database.account(1).unwrap().history_index(102).is_none()
+        );
+    }
+
+    #[test]
+    fn run_reports_invalid_commands() {
+        let mut database = Database::new();
+
+        let actual = run_commands(
+            &mut database,
+            "history 7\nhistory x\nbogus 1 2\ndeposit 1 x 2.0\n",
+        );
+
+        let lines: Vec<&str> = actual.split("> ").collect();
+        assert_eq!("client 7 has no account\n", lines[1]);
+        assert_eq!("invalid client: x\n", lines[2]);
+        assert_eq!("ignored\n", lines[3]);
+        assert_eq!(true, lines[4].starts_with("error: "));
+        assert_eq!(0, database.accounts().count());
+    }
+}
@@ -0,0 +1,691 @@
+use crate::{
+    amount::{Amount, RoundingMode},
+    client::ClientAccount,
+};
+use std::str::FromStr;
+use std::sync::Arc;
+
+// The account report is built from columns so new subsystems can contribute their own values
+// without changing how the report is output.
+
+/// A single column of the account report.
+pub trait ReportColumn<Account>
+where
+    Account: ClientAccount,
+{
+    /// The header of the column.
+    fn name(&self) -> &str;
+
+    /// The value of the column for the given account.
+    fn value(&self, account: &Account) -> String;
+
+    /// The value of the column as JSON. Amounts stay strings so they keep their exact value.
+    fn json_value(&self, account: &Account) -> serde_json::Value {
+        serde_json::Value::String(self.value(account))
+    }
+
+    /// The value of the column as an amount, for columns that hold one.
+    fn amount(&self, _account: &Account) -> Option<Amount> {
+        None
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReportError {
+    UnknownColumn { passed: String },
+}
+
+/// The id of the client.
+pub struct ClientColumn;
+
+impl<Account: ClientAccount> ReportColumn<Account> for ClientColumn {
+    fn name(&self) -> &str {
+        "client"
+    }
+
+    fn value(&self, account: &Account) -> String {
+        format!("{:?}", account.id())
+    }
+
+    fn json_value(&self, account: &Account) -> serde_json::Value {
+        serde_json::json!(account.id())
+    }
+}
+
+/// The funds available to the client.
+pub struct AvailableColumn;
+
+impl<Account: ClientAccount> ReportColumn<Account> for AvailableColumn {
+    fn name(&self) -> &str {
+        "available"
+    }
+
+    fn value(&self, account: &Account) -> String {
+        format!("{:?}", account.available())
+    }
+
+    fn amount(&self, account: &Account) -> Option<Amount> {
+        Some(account.available())
+    }
+}
+
+/// The funds held from the client.
+pub struct HeldColumn;
+
+impl<Account: ClientAccount> ReportColumn<Account> for HeldColumn {
+    fn name(&self) -> &str {
+        "held"
+    }
+
+    fn value(&self, account: &Account) -> String {
+        format!("{:?}", account.held())
+    }
+
+    fn amount(&self, account: &Account) -> Option<Amount> {
+        Some(account.held())
+    }
+}
+
+/// The total balance of the client.
+pub struct TotalColumn;
+
+impl<Account: ClientAccount> ReportColumn<Account> for TotalColumn {
+    fn name(&self) -> &str {
+        "total"
+    }
+
+    fn value(&self, account: &Account) -> String {
+        format!("{:?}", account.total())
+    }
+
+    fn amount(&self, account: &Account) -> Option<Amount> {
+        Some(account.total())
+    }
+}
+
+/// Whether the client is frozen.
+pub struct LockedColumn;
+
+impl<Account: ClientAccount> ReportColumn<Account> for LockedColumn {
+    fn name(&self) -> &str {
+        "locked"
+    }
+
+    fn value(&self, account: &Account) -> String {
+        format!("{:?}", account.locked())
+    }
+
+    fn json_value(&self, account: &Account) -> serde_json::Value {
+        serde_json::json!(account.locked())
+    }
+}
+
+/// The lifetime sum of accepted deposits.
+pub struct DepositsTotalColumn;
+
+impl<Account: ClientAccount> ReportColumn<Account> for DepositsTotalColumn {
+    fn name(&self) -> &str {
+        "deposits_total"
+    }
+
+    fn value(&self, account: &Account) -> String {
+        format!("{:?}", account.stats().deposits)
+    }
+
+    fn amount(&self, account: &Account) -> Option<Amount> {
+        Some(account.stats().deposits)
+    }
+}
+
+/// The lifetime sum of accepted withdrawals.
+pub struct WithdrawalsTotalColumn;
+
+impl<Account: ClientAccount> ReportColumn<Account> for WithdrawalsTotalColumn {
+    fn name(&self) -> &str {
+        "withdrawals_total"
+    }
+
+    fn value(&self, account: &Account) -> String {
+        format!("{:?}", account.stats().withdrawals)
+    }
+
+    fn amount(&self, account: &Account) -> Option<Amount> {
+        Some(account.stats().withdrawals)
+    }
+}
+
+/// The lifetime sum of fees charged.
+pub struct FeesTotalColumn;
+
+impl<Account: ClientAccount> ReportColumn<Account> for FeesTotalColumn {
+    fn name(&self) -> &str {
+        "fees_total"
+    }
+
+    fn value(&self, account: &Account) -> String {
+        format!("{:?}", account.stats().fees)
+    }
+
+    fn amount(&self, account: &Account) -> Option<Amount> {
+        Some(account.stats().fees)
+    }
+}
+
+/// The number of accepted disputes.
+pub struct DisputeCountColumn;
+
+impl<Account: ClientAccount> ReportColumn<Account> for DisputeCountColumn {
+    fn name(&self) -> &str {
+        "dispute_count"
+    }
+
+    fn value(&self, account: &Account) -> String {
+        format!("{:?}", account.stats().disputes)
+    }
+
+    fn json_value(&self, account: &Account) -> serde_json::Value {
+        serde_json::json!(account.stats().disputes)
+    }
+}
+
+/// The number of accepted chargebacks.
+pub struct ChargebackCountColumn;
+
+impl<Account: ClientAccount> ReportColumn<Account> for ChargebackCountColumn {
+    fn name(&self) -> &str {
+        "chargeback_count"
+    }
+
+    fn value(&self, account: &Account) -> String {
+        format!("{:?}", account.stats().chargebacks)
+    }
+
+    fn json_value(&self, account: &Account) -> serde_json::Value {
+        serde_json::json!(account.stats().chargebacks)
+    }
+}
+
+/// The latest timestamp of an accepted transaction, empty if none had one.
+pub struct LastActivityColumn;
+
+impl<Account: ClientAccount> ReportColumn<Account> for LastActivityColumn {
+    fn name(&self) -> &str {
+        "last_activity"
+    }
+
+    fn value(&self, account: &Account) -> String {
+        account
+            .stats()
+            .last_activity
+            .map(|timestamp| timestamp.to_string())
+            .unwrap_or_default()
+    }
+
+    fn json_value(&self, account: &Account) -> serde_json::Value {
+        serde_json::json!(account.stats().last_activity)
+    }
+}
+
+/// The currency the account is kept in, empty if none is known.
+pub struct CurrencyColumn;
+
+impl<Account: ClientAccount> ReportColumn<Account> for CurrencyColumn {
+    fn name(&self) -> &str {
+        "currency"
+    }
+
+    fn value(&self, account: &Account) -> String {
+        account
+            .currency()
+            .map(|currency| currency.to_string())
+            .unwrap_or_default()
+    }
+
+    fn json_value(&self, account: &Account) -> serde_json::Value {
+        serde_json::json!(account.currency())
+    }
+}
+
+/// Whether the account's available balance is below zero, drawing on an overdraft.
+pub struct OverdrawnColumn;
+
+impl<Account: ClientAccount> ReportColumn<Account> for OverdrawnColumn {
+    fn name(&self) -> &str {
+        "overdrawn"
+    }
+
+    fn value(&self, account: &Account) -> String {
+        format!("{:?}", account.available().less_than_zero())
+    }
+
+    fn json_value(&self, account: &Account) -> serde_json::Value {
+        serde_json::json!(account.available().less_than_zero())
+    }
+}
+
+/// How amounts are coarsened in reports shared outside the business.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Rounding {
+    /// Amounts are rounded to a multiple of the step with the rounding mode.
+    Nearest(Amount, RoundingMode),
+    /// Amounts are replaced with the range of the step they fall in, as `lower..upper`.
+    Bucket(Amount),
+}
+
+impl Rounding {
+    pub fn apply(&self, amount: Amount) -> String {
+        match self {
+            Self::Nearest(step, mode) => format!("{:?}", amount.round_to(*step, *mode)),
+            Self::Bucket(step) => {
+                let lower = amount.floor_to(*step);
+                format!("{:?}..{:?}", lower, lower + *step)
+            }
+        }
+    }
+}
+
+/// A column whose amounts are coarsened, leaving any other value as it is.
+struct RoundedColumn<Account>
+where
+    Account: ClientAccount,
+{
+    column: Arc<dyn ReportColumn<Account>>,
+    rounding: Rounding,
+}
+
+impl<Account: ClientAccount> ReportColumn<Account> for RoundedColumn<Account> {
+    fn name(&self) -> &str {
+        self.column.name()
+    }
+
+    fn value(&self, account: &Account) -> String {
+        match self.column.amount(account) {
+            Some(amount) => self.rounding.apply(amount),
+            None => self.column.value(account),
+        }
+    }
+
+    fn json_value(&self, account: &Account) -> serde_json::Value {
+        match self.column.amount(account) {
+            Some(amount) => serde_json::Value::String(self.rounding.apply(amount)),
+            None => self.column.json_value(account),
+        }
+    }
+
+    /// Rounded amounts are still amounts, but a bucket is a range.
+    fn amount(&self, account: &Account) -> Option<Amount> {
+        match self.rounding {
+            Rounding::Nearest(step, mode) => self
+                .column
+                .amount(account)
+                .map(|amount| amount.round_to(step, mode)),
+            Rounding::Bucket(_) => None,
+        }
+    }
+}
+
+/// How amounts are written in the report.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum AmountFormat {
+    /// Every decimal place, e.g. `1.5000`, so reports diff cleanly.
+    #[default]
+    Fixed,
+    /// Without trailing zeros after the decimal point, e.g. `1.5`.
+    Trimmed,
+}
+
+impl AmountFormat {
+    pub fn apply(&self, amount: Amount) -> String {
+        match self {
+            Self::Fixed => amount.to_string(),
+            Self::Trimmed => amount.trimmed(),
+        }
+    }
+}
+
+impl FromStr for AmountFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(Self::Fixed),
+            "trimmed" => Ok(Self::Trimmed),
+            _ => Err(String::from(s)),
+        }
+    }
+}
+
+/// The ordered set of columns output for each account.
+pub struct Report<Account>
+where
+    Account: ClientAccount,
+{
+    columns: Vec<Arc<dyn ReportColumn<Account>>>,
+    format: AmountFormat,
+}
+
+impl<Account> Clone for Report<Account>
+where
+    Account: ClientAccount,
+{
+    fn clone(&self) -> Self {
+        Self {
+            columns: self.columns.clone(),
+            format: self.format,
+        }
+    }
+}
+
+impl<Account> Report<Account>
+where
+    Account: ClientAccount,
+{
+    /// A report without any columns.
+    pub fn empty() -> Self {
+        Self {
+            columns: vec![],
+            format: AmountFormat::default(),
+        }
+    }
+
+    /// The standard `client, available, held, total, locked` report.
+    pub fn standard() -> Self {
+        let mut report = Self::empty();
+        report.register(Box::new(ClientColumn));
+        report.register(Box::new(AvailableColumn));
+        report.register(Box::new(HeldColumn));
+        report.register(Box::new(TotalColumn));
+        report.register(Box::new(LockedColumn));
+        report
+    }
+
+    /// The standard report followed by the lifetime counters of each account.
+    pub fn extended() -> Self {
+        let mut report = Self::standard();
+        report.register(Box::new(DepositsTotalColumn));
+        report.register(Box::new(WithdrawalsTotalColumn));
+        report.register(Box::new(FeesTotalColumn));
+        report.register(Box::new(DisputeCountColumn));
+        report.register(Box::new(ChargebackCountColumn));
+        report.register(Box::new(LastActivityColumn));
+        report.register(Box::new(CurrencyColumn));
+        report.register(Box::new(OverdrawnColumn));
+        report
+    }
+
+    /// Appends a column to the report.
+    pub fn register(&mut self, column: Box<dyn ReportColumn<Account>>) {
+        self.columns.push(Arc::from(column));
+    }
+
+    /// Replaces the column with the same name, if the report has one.
+    pub fn replace(&mut self, column: Box<dyn ReportColumn<Account>>) {
+        if let Some(existing) = self
+            .columns
+            .iter_mut()
+            .find(|existing| existing.name() == column.name())
+        {
+            *existing = Arc::from(column);
+        }
+    }
+
+    /// Keeps only the named columns, in the order given.
+    pub fn select(mut self, names: &[String]) -> Result<Self, ReportError> {
+        let mut selected = vec![];
+
+        for name in names {
+            let index = self
+                .columns
+                .iter()
+                .position(|column| column.name() == name.as_str());
+
+            match index {
+                Some(index) => selected.push(self.columns.remove(index)),
+                None => {
+                    return Err(ReportError::UnknownColumn {
+                        passed: name.clone(),
+                    })
+                }
+            }
+        }
+
+        Ok(Self {
+            columns: selected,
+            format: self.format,
+        })
+    }
+
+    /// The same columns with every amount coarsened.
+    pub fn rounded(&self, rounding: Rounding) -> Self
+    where
+        Account: 'static,
+    {
+        let columns = self
+            .columns
+            .iter()
+            .map(|column| {
+                Arc::new(RoundedColumn {
+                    column: column.clone(),
+                    rounding,
+                }) as Arc<dyn ReportColumn<Account>>
+            })
+            .collect();
+
+        Self {
+            columns,
+            format: self.format,
+        }
+    }
+
+    /// The report with a currency column appended if any of the accounts holds more than one
+    /// currency, so the rows of a client's balances can be told apart. A report that already has
+    /// one is left as it is.
+    pub fn for_accounts<'a>(&self, mut accounts: impl Iterator<Item = &'a Account>) -> Self
+    where
+        Account: 'a,
+    {
+        let mut report = self.clone();
+        if !self.names().contains(&"currency")
+            && accounts.any(|account| !account.other_currencies().is_empty())
+        {
+            report.register(Box::new(CurrencyColumn));
+        }
+        report
+    }
+
+    /// Writes the amounts of the report in the format.
+    pub fn formatted(mut self, format: AmountFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// The names of the columns, in order.
+    pub fn names(&self) -> Vec<&str> {
+        self.columns.iter().map(|column| column.name()).collect()
+    }
+
+    /// The value of every column for the given account, in order.
+    pub fn values(&self, account: &Account) -> Vec<String> {
+        self.columns
+            .iter()
+            .map(|column| match column.amount(account) {
+                Some(amount) => self.format.apply(amount),
+                None => column.value(account),
+            })
+            .collect()
+    }
+
+    /// The value of every column for the given account as JSON, in order.
+    pub fn json_values(&self, account: &Account) -> Vec<serde_json::Value> {
+        self.columns
+            .iter()
+            .map(|column| match column.amount(account) {
+                Some(amount) => serde_json::Value::String(self.format.apply(amount)),
+                None => column.json_value(account),
+            })
+            .collect()
+    }
+
+    /// The header row of the report.
+    pub fn header(&self) -> String {
+        self.names().join(", ")
+    }
+
+    /// The row of the report for the given account.
+    pub fn row(&self, account: &Account) -> String {
+        self.values(account).join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{amount, places};
+    use crate::{
+        client::Client,
+        transaction::{Transaction, TransactionType},
+    };
+
+    struct ConstantColumn;
+
+    impl ReportColumn<Client> for ConstantColumn {
+        fn name(&self) -> &str {
+            "tier"
+        }
+
+        fn value(&self, _account: &Client) -> String {
+            String::from("gold")
+        }
+    }
+
+    #[test]
+    fn report_standard_returns_expected_header_and_row() {
+        let report = Report::<Client>::standard();
+
+        assert_eq!("client, available, held, total, locked", report.header());
+        assert_eq!(
+            places("4, 0.0000, 0.0000, 0.0000, false"),
+            report.row(&Client::new(4))
+        );
+    }
+
+    #[test]
+    fn report_extended_appends_lifetime_counters() {
+        let report = Report::<Client>::extended();
+
+        assert_eq!(
+            "client, available, held, total, locked, deposits_total, withdrawals_total, fees_total, dispute_count, chargeback_count, last_activity, currency, overdrawn",
+            report.header()
+        );
+        assert_eq!(
+            places("4, 0.0000, 0.0000, 0.0000, false, 0.0000, 0.0000, 0.0000, 0, 0, , , false"),
+            report.row(&Client::new(4))
+        );
+
+        let mut client = Client::new(4);
+        for (id, timestamp) in [
+            (1, Some(1_700_000_060)),
+            (2, Some(1_700_000_000)),
+            (3, None),
+        ] {
+            client
+                .execute_transaction(Transaction {
+                    transaction_type: TransactionType::Deposit(amount("1")),
+                    client: 4,
+                    id,
+                    timestamp,
+                    currency: "EUR".parse().ok(),
+                })
+                .unwrap();
+        }
+        assert_eq!(
+            places("4, 3.0000, 0.0000, 3.0000, false, 3.0000, 0.0000, 0.0000, 0, 0, 1700000060, EUR, false"),
+            report.row(&client)
+        );
+    }
+
+    #[test]
+    fn report_register_appends_column() {
+        let mut report = Report::<Client>::standard();
+        report.register(Box::new(ConstantColumn));
+
+        assert_eq!(
+            "client, available, held, total, locked, tier",
+            report.header()
+        );
+        assert_eq!(
+            places("4, 0.0000, 0.0000, 0.0000, false, gold"),
+            report.row(&Client::new(4))
+        );
+    }
+
+    #[test]
+    fn report_select_keeps_named_columns_in_order() {
+        let names = vec![String::from("total"), String::from("client")];
+        let report = Report::<Client>::standard().select(&names).unwrap();
+
+        assert_eq!("total, client", report.header());
+        assert_eq!(places("0.0000, 4"), report.row(&Client::new(4)));
+    }
+
+    #[test]
+    fn report_rounded_coarsens_only_amounts() {
+        let mut client = Client::new(4);
+        client
+            .execute_transaction(Transaction {
+                transaction_type: TransactionType::Deposit(amount("126")),
+                client: 4,
+                id: 1,
+                timestamp: None,
+                currency: None,
+            })
+            .unwrap();
+
+        let report = Report::<Client>::standard();
+        let nearest = report.rounded(Rounding::Nearest(amount("100"), RoundingMode::HalfUp));
+        let bucket = report.rounded(Rounding::Bucket(amount("100")));
+
+        assert_eq!("client, available, held, total, locked", nearest.header());
+        assert_eq!(
+            places("4, 100.0000, 0.0000, 100.0000, false"),
+            nearest.row(&client)
+        );
+        assert_eq!(
+            places("4, 100.0000..200.0000, 0.0000..100.0000, 100.0000..200.0000, false"),
+            bucket.row(&client)
+        );
+        assert_eq!(serde_json::json!(4), bucket.json_values(&client)[0]);
+    }
+
+    #[test]
+    fn report_formatted_trimmed_drops_trailing_zeros() {
+        let mut client = Client::new(4);
+        client
+            .execute_transaction(Transaction {
+                transaction_type: TransactionType::Deposit(amount("1.5")),
+                client: 4,
+                id: 1,
+                timestamp: None,
+                currency: None,
+            })
+            .unwrap();
+
+        let fixed = Report::<Client>::standard();
+        let trimmed = Report::<Client>::standard().formatted(AmountFormat::Trimmed);
+
+        assert_eq!(
+            places("4, 1.5000, 0.0000, 1.5000, false"),
+            fixed.row(&client)
+        );
+        assert_eq!("4, 1.5, 0, 1.5, false", trimmed.row(&client));
+        assert_eq!(serde_json::json!("1.5"), trimmed.json_values(&client)[1]);
+    }
+
+    #[test]
+    fn report_select_unknown_column_returns_err() {
+        let names = vec![String::from("risk")];
+        let result = Report::<Client>::standard().select(&names);
+
+        assert_eq!(true, result.is_err());
+    }
+}
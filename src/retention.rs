@@ -0,0 +1,234 @@
+use crate::{
+    client::Client,
+    clock::{Clock, Timestamp},
+    database::Database,
+    scheduler::Periodic,
+    snapshot::{self, Snapshot, SnapshotError, SnapshotFormat},
+};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// A server can snapshot its accounts into a directory at an interval. Each snapshot is named after
+// the time it was taken, and after every snapshot the directory is pruned: the latest few are
+// kept, along with the newest of each recent day. Only files named like snapshots are considered,
+// so anything else an operator keeps in the directory is left alone.
+
+const SECONDS_PER_DAY: Timestamp = 24 * 60 * 60;
+
+/// Which snapshots are kept once a new one is taken.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Retention {
+    /// How many of the latest snapshots are kept.
+    pub keep_last: usize,
+    /// For how many days, counting today, the newest snapshot of each day is kept.
+    pub keep_daily: u32,
+}
+
+impl Default for Retention {
+    /// The latest day of hourly snapshots, and a snapshot a day for a month.
+    fn default() -> Self {
+        Self {
+            keep_last: 24,
+            keep_daily: 30,
+        }
+    }
+}
+
+impl Retention {
+    /// The snapshots taken at the given times that are no longer kept at `now`.
+    pub fn pruned(&self, taken: &[Timestamp], now: Timestamp) -> Vec<Timestamp> {
+        let mut taken = taken.to_vec();
+        taken.sort_unstable_by(|a, b| b.cmp(a));
+        taken.dedup();
+
+        let today = now / SECONDS_PER_DAY;
+        let mut days = HashSet::new();
+        let mut pruned = vec![];
+        for (index, taken) in taken.into_iter().enumerate() {
+            let day = taken / SECONDS_PER_DAY;
+            let recent_day = today.saturating_sub(day) < Timestamp::from(self.keep_daily);
+            let newest_of_day = recent_day && days.insert(day);
+
+            if index >= self.keep_last && !newest_of_day {
+                pruned.push(taken);
+            }
+        }
+        pruned
+    }
+}
+
+/// The accounts are snapshotted into `dir` every `interval` seconds, keeping the snapshots that
+/// `retention` keeps.
+#[derive(PartialEq, Debug)]
+pub struct SnapshotSchedule {
+    pub dir: String,
+    pub interval: u64,
+    pub format: SnapshotFormat,
+    pub retention: Retention,
+}
+
+/// Snapshots a database into a directory at an interval, pruning the snapshots it no longer
+/// keeps.
+pub struct PeriodicSnapshots {
+    dir: PathBuf,
+    interval: Duration,
+    format: SnapshotFormat,
+    retention: Retention,
+    clock: Box<dyn Clock>,
+    last: Instant,
+}
+
+impl PeriodicSnapshots {
+    pub fn new(
+        dir: &str,
+        interval: Duration,
+        format: SnapshotFormat,
+        retention: Retention,
+        clock: Box<dyn Clock>,
+    ) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+            interval,
+            format,
+            retention,
+            clock,
+            last: Instant::now(),
+        }
+    }
+
+    /// The path of the snapshot taken at the given time.
+    fn path(&self, taken: Timestamp) -> PathBuf {
+        self.dir
+            .join(format!("snapshot-{}.{}", taken, extension(self.format)))
+    }
+
+    /// Writes a snapshot of the database, then removes the snapshots no longer kept. Returns the
+    /// path of the new snapshot.
+    pub fn take(&self, snapshot: &Snapshot) -> Result<PathBuf, SnapshotError> {
+        fs::create_dir_all(&self.dir)?;
+        let now = self.clock.now();
+        let path = self.path(now);
+
+        // Written under another name first so a crash never leaves a partial snapshot that
+        // looks complete.
+        let partial = path.with_extension("partial");
+        snapshot::write_to_path(snapshot, &partial.to_string_lossy(), self.format)?;
+        fs::rename(&partial, &path)?;
+
+        let taken = taken_snapshots(&self.dir, self.format)?;
+        for pruned in self.retention.pruned(&taken, now) {
+            fs::remove_file(self.path(pruned))?;
+        }
+        Ok(path)
+    }
+}
+
+impl Periodic<Client> for PeriodicSnapshots {
+    fn until_due(&self) -> Duration {
+        self.interval.saturating_sub(self.last.elapsed())
+    }
+
+    /// Failing to snapshot is logged rather than stopping the database.
+    fn tick(&mut self, database: &Database<Client>) {
+        if self.until_due() > Duration::from_secs(0) {
+            return;
+        }
+        self.last = Instant::now();
+
+        if let Err(e) = self.take(&database.snapshot()) {
+            eprintln!("Failed to snapshot accounts: {:?}", e);
+        }
+    }
+}
+
+fn extension(format: SnapshotFormat) -> &'static str {
+    match format {
+        SnapshotFormat::Bincode => "bin",
+        SnapshotFormat::Json => "json",
+        SnapshotFormat::Cbor => "cbor",
+    }
+}
+
+/// When each snapshot in the directory was taken.
+fn taken_snapshots(dir: &Path, format: SnapshotFormat) -> Result<Vec<Timestamp>, SnapshotError> {
+    let suffix = format!(".{}", extension(format));
+
+    let mut taken = vec![];
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let timestamp = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("snapshot-"))
+            .and_then(|name| name.strip_suffix(suffix.as_str()))
+            .and_then(|timestamp| timestamp.parse().ok());
+        if let Some(timestamp) = timestamp {
+            taken.push(timestamp);
+        }
+    }
+    Ok(taken)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SteppingClock;
+
+    #[test]
+    fn retention_pruned_keeps_latest_and_newest_of_recent_days() {
+        let retention = Retention {
+            keep_last: 2,
+            keep_daily: 3,
+        };
+        let day = SECONDS_PER_DAY;
+        let now = 10 * day + 500;
+        let taken = [
+            10 * day + 400,
+            10 * day + 300,
+            10 * day + 200,
+            9 * day + 900,
+            9 * day + 100,
+            8 * day + 100,
+            7 * day + 100,
+        ];
+
+        let pruned = retention.pruned(&taken, now);
+
+        assert_eq!(vec![10 * day + 200, 9 * day + 100, 7 * day + 100], pruned);
+    }
+
+    #[test]
+    fn periodic_snapshots_take_prunes_directory() {
+        let dir = std::env::temp_dir().join("payments_retention_take");
+        let _result = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("notes.txt"), "kept").unwrap();
+
+        let snapshots = PeriodicSnapshots::new(
+            &dir.to_string_lossy(),
+            Duration::from_secs(60),
+            SnapshotFormat::Json,
+            Retention {
+                keep_last: 2,
+                keep_daily: 0,
+            },
+            Box::new(SteppingClock::new(1000, 60)),
+        );
+        let snapshot = Database::<Client>::new().snapshot();
+        for _ in 0..3 {
+            snapshots.take(&snapshot).unwrap();
+        }
+
+        let mut names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(
+            vec!["notes.txt", "snapshot-1060.json", "snapshot-1120.json"],
+            names
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{
+    amount::{self, Amount},
+    client::{ClientAccount, ClientId},
+    clock::Timestamp,
+    report::ReportColumn,
+    transaction::{Transaction, TransactionType},
+};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::str::FromStr;
+
+// Every transaction read is scored from 0 to 100 on signals of fraud: 40 points for a large
+// deposit, withdrawal or transfer, 30 for following a rapid sequence of the client's transactions,
+// and up to 30 for the share of the client's transactions that are disputes. Scores only depend
+// on the order transactions are read in, so they're the same however the run is sharded.
+// Transactions scored at or above the threshold are flagged in the error log or blocked.
+
+const LARGE_AMOUNT_POINTS: u8 = 40;
+const RAPID_SEQUENCE_POINTS: u8 = 30;
+const DISPUTE_POINTS: u8 = 30;
+
+/// What happens to transactions scored at or above the threshold.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum RiskPolicy {
+    /// Apply them but flag them with a warning.
+    #[default]
+    Annotate,
+    /// Reject them with `TransactionError::HighRisk`.
+    Block,
+}
+
+impl FromStr for RiskPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "annotate" => Ok(Self::Annotate),
+            "block" => Ok(Self::Block),
+            _ => Err(String::from(s)),
+        }
+    }
+}
+
+/// How transactions are scored, and what happens to those that score too high.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RiskConfig {
+    /// The lowest score that is flagged or blocked.
+    pub threshold: u8,
+    pub policy: RiskPolicy,
+    /// Amounts at least this large score as large.
+    pub large_amount: Amount,
+    /// A transaction following this many of the client's within the window scores as rapid.
+    pub rapid_count: usize,
+    /// The window of a rapid sequence, in seconds.
+    pub rapid_window: u64,
+}
+
+impl RiskConfig {
+    /// Flags transactions scored at or above the threshold, with a large amount of 10,000 and
+    /// rapid sequences of 5 transactions within a minute.
+    pub fn new(threshold: u8) -> Self {
+        Self {
+            threshold,
+            policy: RiskPolicy::default(),
+            large_amount: Amount::new(10_000 * 10_i64.pow(amount::DECIMAL_PLACES)),
+            rapid_count: 5,
+            rapid_window: 60,
+        }
+    }
+}
+
+/// The highest score of each client's transactions, shared with the report.
+#[derive(Clone, Debug, Default)]
+pub struct RiskScores(Rc<RefCell<HashMap<ClientId, u8>>>);
+
+impl RiskScores {
+    pub fn get(&self, client: ClientId) -> Option<u8> {
+        self.0.borrow().get(&client).copied()
+    }
+}
+
+/// What a client has done so far, as it's scored.
+#[derive(Default)]
+struct ClientRisk {
+    /// The timestamps of the client's latest transactions, oldest first.
+    recent: VecDeque<Timestamp>,
+    transactions: u32,
+    disputes: u32,
+}
+
+/// Scores transactions in the order they're read.
+pub struct RiskScorer {
+    config: RiskConfig,
+    clients: HashMap<ClientId, ClientRisk>,
+    scores: RiskScores,
+}
+
+impl RiskScorer {
+    pub fn new(config: RiskConfig) -> Self {
+        Self {
+            config,
+            clients: HashMap::new(),
+            scores: RiskScores::default(),
+        }
+    }
+
+    pub fn policy(&self) -> RiskPolicy {
+        self.config.policy
+    }
+
+    /// Each client's highest score, updated as transactions are scored.
+    pub fn scores(&self) -> RiskScores {
+        self.scores.clone()
+    }
+
+    /// Scores the transaction and counts it towards the client's later scores.
+    pub fn score(&mut self, transaction: &Transaction) -> u8 {
+        let config = &self.config;
+        let client = self.clients.entry(transaction.client).or_default();
+        let mut score = 0;
+
+        let amount = match transaction.transaction_type {
+            TransactionType::Deposit(amount)
+            | TransactionType::Withdrawal(amount)
+            | TransactionType::Transfer { amount, .. } => Some(amount),
+            _ => None,
+        };
+        if amount.is_some_and(|amount| amount >= config.large_amount) {
+            score += LARGE_AMOUNT_POINTS;
+        }
+
+        if let Some(timestamp) = transaction.timestamp {
+            let window = config.rapid_window;
+            let rapid = client
+                .recent
+                .iter()
+                .filter(|at| **at <= timestamp && timestamp - **at < window)
+                .count();
+            if rapid >= config.rapid_count {
+                score += RAPID_SEQUENCE_POINTS;
+            }
+            client.recent.push_back(timestamp);
+            if client.recent.len() > config.rapid_count {
+                client.recent.pop_front();
+            }
+        }
+
+        client.transactions += 1;
+        if matches!(
+            transaction.transaction_type,
+            TransactionType::Dispute { .. }
+        ) {
+            client.disputes += 1;
+        }
+        score += (u32::from(DISPUTE_POINTS) * client.disputes / client.transactions) as u8;
+
+        let mut scores = self.scores.0.borrow_mut();
+        let highest = scores.entry(transaction.client).or_default();
+        *highest = score.max(*highest);
+
+        score
+    }
+
+    /// Scores the transaction, returning the score if it's at or above the threshold.
+    pub fn flag(&mut self, transaction: &Transaction) -> Option<u8> {
+        Some(self.score(transaction)).filter(|score| *score >= self.config.threshold)
+    }
+}
+
+/// The highest score of the client's transactions, 0 if none were scored.
+pub struct RiskScoreColumn(pub RiskScores);
+
+impl<Account: ClientAccount> ReportColumn<Account> for RiskScoreColumn {
+    fn name(&self) -> &str {
+        "risk_score"
+    }
+
+    fn value(&self, account: &Account) -> String {
+        self.0.get(account.id()).unwrap_or_default().to_string()
+    }
+
+    fn json_value(&self, account: &Account) -> serde_json::Value {
+        serde_json::json!(self.0.get(account.id()).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(transaction_type: TransactionType, timestamp: Option<Timestamp>) -> Transaction {
+        Transaction {
+            transaction_type,
+            client: 1,
+            id: 1,
+            timestamp,
+            currency: None,
+        }
+    }
+
+    fn config() -> RiskConfig {
+        RiskConfig {
+            threshold: 50,
+            policy: RiskPolicy::Annotate,
+            large_amount: Amount::new(1000),
+            rapid_count: 2,
+            rapid_window: 60,
+        }
+    }
+
+    #[test]
+    fn risk_policy_from_str_returns_expected() {
+        assert_eq!(Ok(RiskPolicy::Annotate), RiskPolicy::from_str("annotate"));
+        assert_eq!(Ok(RiskPolicy::Block), RiskPolicy::from_str("block"));
+        assert_eq!(Err(String::from("flag")), RiskPolicy::from_str("flag"));
+    }
+
+    #[test]
+    fn risk_config_new_large_amount_is_ten_thousand() {
+        assert_eq!(
+            Amount::from_str("10000").unwrap(),
+            RiskConfig::new(70).large_amount
+        );
+    }
+
+    #[test]
+    fn risk_scorer_score_adds_each_signal() {
+        let mut scorer = RiskScorer::new(config());
+
+        let small = transaction(TransactionType::Deposit(Amount::new(999)), Some(0));
+        let large = transaction(TransactionType::Withdrawal(Amount::new(1000)), Some(10));
+        assert_eq!(0, scorer.score(&small));
+        assert_eq!(40, scorer.score(&large));
+
+        // Two transactions within the minute make the third rapid.
+        assert_eq!(70, scorer.score(&large));
+        let later = transaction(TransactionType::Deposit(Amount::new(1)), Some(100));
+        assert_eq!(0, scorer.score(&later));
+
+        // One dispute in five transactions is a fifth of the dispute points.
+        let dispute = transaction(TransactionType::Dispute { case: None }, None);
+        assert_eq!(6, scorer.score(&dispute));
+        assert_eq!(Some(70), scorer.scores().get(1));
+        assert_eq!(None, scorer.scores().get(2));
+    }
+
+    #[test]
+    fn risk_scorer_flag_returns_scores_at_or_above_threshold() {
+        let mut scorer = RiskScorer::new(config());
+
+        let large = transaction(TransactionType::Deposit(Amount::new(1000)), None);
+        assert_eq!(None, scorer.flag(&large));
+        let dispute = transaction(TransactionType::Dispute { case: None }, None);
+        assert_eq!(None, scorer.flag(&dispute));
+        // Two disputes in four transactions are half the dispute points.
+        assert_eq!(None, scorer.flag(&dispute));
+        assert_eq!(Some(55), scorer.flag(&large));
+    }
+}
@@ -0,0 +1,107 @@
+use crate::{
+    client::{ClientAccount, ClientId},
+    currency::Currency,
+    transaction::Transaction,
+};
+use std::collections::HashSet;
+
+// Rules let embedders add their own checks to the database, such as blocklists or currency
+// restrictions, without changing how accounts apply transactions. Each rule sees the client's
+// account as it is before the transaction, and the first rule to reject it stops it being
+// applied with `TransactionError::RuleRejected`.
+
+/// What a rule decides about a transaction.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RuleDecision {
+    Allow,
+    Reject { reason: &'static str },
+}
+
+/// A check every transaction must pass before it's applied.
+pub trait TransactionRule<Account>: Send + Sync
+where
+    Account: ClientAccount,
+{
+    /// Decides on the transaction given the client's account, which is `None` until the client's
+    /// first transaction has been applied.
+    fn evaluate(&self, account: Option<&Account>, transaction: &Transaction) -> RuleDecision;
+}
+
+/// Rejects every transaction of the listed clients.
+pub struct Blocklist(pub HashSet<ClientId>);
+
+impl<Account: ClientAccount> TransactionRule<Account> for Blocklist {
+    fn evaluate(&self, _account: Option<&Account>, transaction: &Transaction) -> RuleDecision {
+        match self.0.contains(&transaction.client) {
+            true => RuleDecision::Reject {
+                reason: "client is blocklisted",
+            },
+            false => RuleDecision::Allow,
+        }
+    }
+}
+
+/// Rejects transactions in currencies other than the listed ones. Transactions without a currency
+/// are allowed.
+pub struct AllowedCurrencies(pub HashSet<Currency>);
+
+impl<Account: ClientAccount> TransactionRule<Account> for AllowedCurrencies {
+    fn evaluate(&self, _account: Option<&Account>, transaction: &Transaction) -> RuleDecision {
+        match transaction.currency {
+            Some(currency) if !self.0.contains(&currency) => RuleDecision::Reject {
+                reason: "currency is not allowed",
+            },
+            _ => RuleDecision::Allow,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::Amount, client::Client, transaction::TransactionType};
+    use std::str::FromStr;
+
+    fn deposit(client: ClientId, currency: Option<Currency>) -> Transaction {
+        Transaction {
+            transaction_type: TransactionType::Deposit(Amount::new(1)),
+            client,
+            id: 1,
+            timestamp: None,
+            currency,
+        }
+    }
+
+    #[test]
+    fn blocklist_evaluate_rejects_listed_clients() {
+        let rule = Blocklist(vec![2].into_iter().collect());
+
+        assert_eq!(
+            RuleDecision::Allow,
+            TransactionRule::<Client>::evaluate(&rule, None, &deposit(1, None))
+        );
+        assert_eq!(
+            RuleDecision::Reject {
+                reason: "client is blocklisted"
+            },
+            TransactionRule::<Client>::evaluate(&rule, None, &deposit(2, None))
+        );
+    }
+
+    #[test]
+    fn allowed_currencies_evaluate_rejects_other_currencies() {
+        let eur = Currency::from_str("EUR").unwrap();
+        let usd = Currency::from_str("USD").unwrap();
+        let rule = AllowedCurrencies(vec![eur].into_iter().collect());
+
+        let evaluate = |transaction| TransactionRule::<Client>::evaluate(&rule, None, &transaction);
+        assert_eq!(RuleDecision::Allow, evaluate(deposit(1, Some(eur))));
+        assert_eq!(RuleDecision::Allow, evaluate(deposit(1, None)));
+        assert_eq!(
+            RuleDecision::Reject {
+                reason: "currency is not allowed"
+            },
+            evaluate(deposit(1, Some(usd)))
+        );
+    }
+}
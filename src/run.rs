@@ -0,0 +1,823 @@
+use crate::{
+    amount::{Amount, PrecisionPolicy, RoundingMode},
+    audit::{AuditFormat, AuditLog, AuditObserver},
+    checkpoint::{Checkpoint, FileCheckpointer},
+    client::Client,
+    clock::{Clock, SystemClock},
+    compression,
+    database::{Database, DuplicatePolicy},
+    export,
+    fees::{FeeSchedule, FeesError},
+    follow::Follow,
+    ledger::{
+        self, ChartOfAccounts, DoubleEntryLedger, DoubleEntryObserver, LedgerError, LedgerFormat,
+        LedgerObserver, LedgerWriter,
+    },
+    manifest::{self, InputManifest, OutputManifest, RunCounter, RunCounts, RunManifest},
+    overdraft::{OverdraftError, OverdraftLimits},
+    parse_csv::{ColumnMap, TransactionKinds, UnknownTypePolicy},
+    pipeline::{
+        ApprovalConfig, EngineConfig, InputFormat, MaximumPolicy, PipelineBuilder, PipelineError,
+    },
+    random::Randomness,
+    rates::{ExchangeRates, RatesError},
+    reconcile::{Reconciliation, TotalsObserver},
+    remap::{ClientMap, RemapError},
+    report::{AmountFormat, Report, ReportError, Rounding},
+    risk::{RiskConfig, RiskScoreColumn, RiskScorer},
+    sample::SampleRate,
+    shard::Partitioning,
+    sink::{
+        CsvReportSink, ErrorLogSink, ErrorSink, ReportFormat, ReportSink, SharedReportSink,
+        SinkError,
+    },
+    snapshot::{self, Snapshot, SnapshotError, SnapshotFormat},
+    storage::StorageError,
+    throttle::RateLimit,
+    velocity::{VelocityError, VelocityLimits, VelocityPolicy},
+    verify,
+};
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// A run is everything the `process` command does with a transaction file: the database is
+// restored and given its policies, the pipeline is assembled with its observers and sinks, and
+// once it has run, every check and output the config asks for follows. Embedders that want the
+// command's behaviour build a `RunConfig`, while those replacing stages use `PipelineBuilder`.
+
+/// Everything a run over a transaction file is configured with, as the `process` command takes
+/// it from the command line.
+#[derive(PartialEq, Debug)]
+pub struct RunConfig {
+    pub file_path: String,
+    pub approval: Option<ApprovalConfig>,
+    pub snapshot_in: Option<String>,
+    pub snapshot_out: Option<String>,
+    pub snapshot_format: SnapshotFormat,
+    pub columns: Option<Vec<String>>,
+    pub extended: bool,
+    pub coalesce: bool,
+    pub output: Option<String>,
+    pub format: ReportFormat,
+    pub input_format: Option<InputFormat>,
+    pub error_log: Option<String>,
+    pub strict: bool,
+    pub duplicate_policy: DuplicatePolicy,
+    pub soft_limit: Option<Amount>,
+    /// The largest a single deposit or withdrawal may be.
+    pub maximum_amount: Option<Amount>,
+    /// Whether deposits and withdrawals above the maximum are rejected or flagged.
+    pub maximum_policy: MaximumPolicy,
+    pub manifest: Option<String>,
+    pub client_map: Option<ClientMapConfig>,
+    pub storage: Option<String>,
+    pub sample: Option<SampleRate>,
+    pub checkpoint: Option<CheckpointConfig>,
+    pub unknown_types: UnknownTypePolicy,
+    /// The names of CSV columns, read under the ones the engine expects.
+    pub column_map: ColumnMap,
+    /// What happens to amounts with more decimal places than are kept.
+    pub excess_precision: PrecisionPolicy,
+    /// How amounts are rounded wherever digits are dropped.
+    pub rounding_mode: RoundingMode,
+    /// The rates file conversions are priced from.
+    pub rates: Option<String>,
+    /// The fee schedule deposits and withdrawals are charged from.
+    pub fees: Option<String>,
+    /// How far below zero every client may draw its available balance.
+    pub overdraft: Option<Amount>,
+    /// A file of clients with their own overdraft limit.
+    pub overdraft_limits: Option<String>,
+    /// The available balance withdrawals must leave.
+    pub minimum_balance: Option<Amount>,
+    /// The velocity limits file clients are held to.
+    pub velocity_limits: Option<String>,
+    /// Whether transactions beyond a velocity limit are rejected or reported.
+    pub velocity_policy: VelocityPolicy,
+    /// How transactions are scored for risk, if they are.
+    pub risk: Option<RiskConfig>,
+    pub ledger: Option<LedgerConfig>,
+    pub audit_log: Option<AuditLogConfig>,
+    /// Post every applied transaction to a double-entry ledger whose trial balance and client
+    /// balances are checked once the run is over.
+    pub double_entry: bool,
+    /// Check every account's invariants once the run is over, failing if any don't hold.
+    pub verify: bool,
+    /// How many threads transactions are applied on.
+    pub shards: usize,
+    /// How clients are assigned to shards.
+    pub partitioner: Partitioning,
+    /// How amounts are coarsened in a report shared outside the business.
+    pub rounding: Option<Rounding>,
+    /// How amounts are written in the report.
+    pub amount_format: AmountFormat,
+    /// Accounts with a total below this are left out of the report.
+    pub suppress_below: Option<Amount>,
+    /// Read snapshots and checkpoints even if an incompatible engine version wrote them.
+    pub force_migrate: bool,
+    /// Start the report with a comment naming the engine version and config hash.
+    pub stamp: bool,
+    /// Where the transactions still under dispute are written once the run is complete.
+    pub pending_disputes: Option<String>,
+    /// Where the reconciliation totals are written once the run is complete.
+    pub reconciliation: Option<String>,
+    /// How many records a resolve or chargeback may wait for its dispute. Without it, one that
+    /// arrives first is rejected.
+    pub dispute_grace: Option<u64>,
+    /// How many seconds transactions are held back to be sorted by timestamp.
+    pub reorder_window: Option<u64>,
+    /// How fast transactions are read, to spare storage shared with other work while backfilling.
+    pub rate_limit: Option<RateLimit>,
+    /// Keep reading the file as rows are appended to it, until the process is interrupted.
+    pub follow: bool,
+}
+
+impl RunConfig {
+    /// A run over the file with the command line's defaults: the standard report is written to
+    /// stdout, rejections to stderr, and nothing else is checked or written.
+    pub fn new(file_path: &str) -> Self {
+        let rounding_mode = RoundingMode::default();
+        Self {
+            file_path: String::from(file_path),
+            approval: None,
+            snapshot_in: None,
+            snapshot_out: None,
+            snapshot_format: SnapshotFormat::default(),
+            columns: None,
+            extended: false,
+            coalesce: false,
+            output: None,
+            format: ReportFormat::default(),
+            input_format: None,
+            error_log: None,
+            strict: false,
+            duplicate_policy: DuplicatePolicy::default(),
+            soft_limit: None,
+            maximum_amount: None,
+            maximum_policy: MaximumPolicy::default(),
+            manifest: None,
+            client_map: None,
+            storage: None,
+            sample: None,
+            checkpoint: None,
+            unknown_types: UnknownTypePolicy::default(),
+            column_map: ColumnMap::default(),
+            excess_precision: PrecisionPolicy::Round(rounding_mode),
+            rounding_mode,
+            rates: None,
+            fees: None,
+            overdraft: None,
+            overdraft_limits: None,
+            minimum_balance: None,
+            velocity_limits: None,
+            velocity_policy: VelocityPolicy::default(),
+            risk: None,
+            ledger: None,
+            audit_log: None,
+            double_entry: false,
+            verify: false,
+            shards: 1,
+            partitioner: Partitioning::default(),
+            rounding: None,
+            amount_format: AmountFormat::default(),
+            suppress_below: None,
+            force_migrate: false,
+            stamp: false,
+            pending_disputes: None,
+            reconciliation: None,
+            dispute_grace: None,
+            reorder_window: None,
+            rate_limit: None,
+            follow: false,
+        }
+    }
+}
+
+/// How client ids are anonymized while a file is processed.
+#[derive(PartialEq, Debug)]
+pub enum ClientMapConfig {
+    /// A CSV mapping file with `client, alias` columns.
+    File(String),
+    /// A key for a permutation of every client id.
+    Key(u64),
+}
+
+/// Progress is saved to `path` every `every` transactions. With `resume`, the run continues from
+/// the checkpoint at `path` if there is one.
+#[derive(PartialEq, Debug)]
+pub struct CheckpointConfig {
+    pub path: String,
+    pub every: u64,
+    pub resume: bool,
+}
+
+/// A journal entry for every applied transaction is written to `path`, using the chart of
+/// accounts at `chart` if given.
+#[derive(PartialEq, Debug)]
+pub struct LedgerConfig {
+    pub path: String,
+    pub format: LedgerFormat,
+    pub chart: Option<String>,
+}
+
+/// Every decision the engine makes is appended to the audit log at `path`.
+#[derive(PartialEq, Debug)]
+pub struct AuditLogConfig {
+    pub path: String,
+    pub format: AuditFormat,
+}
+
+#[derive(Debug)]
+pub enum RunError {
+    Pipeline(PipelineError),
+    Snapshot(SnapshotError),
+    Report(ReportError),
+    Output(std::io::Error),
+    ErrorLog(SinkError),
+    ClientMap(RemapError),
+    Rates(RatesError),
+    Fees(FeesError),
+    Overdraft(OverdraftError),
+    Velocity(VelocityError),
+    Storage(StorageError),
+    Ledger(LedgerError),
+    PendingDisputes(csv::Error),
+    Checkpoint(SnapshotError),
+    /// The checkpoint to resume from was taken while reading another file.
+    CheckpointMismatch {
+        input_path: String,
+    },
+    /// The trial balance of the double-entry ledger didn't sum to zero.
+    Unbalanced {
+        debits: Amount,
+        credits: Amount,
+    },
+    /// The double-entry ledger owes this many clients other balances than their accounts hold.
+    LedgerMismatch {
+        clients: usize,
+    },
+    /// Invariants that don't hold after the run, this many of them.
+    InvariantsViolated {
+        violations: usize,
+    },
+}
+
+/// A run over a transaction file, configured by a `RunConfig`.
+pub struct Run {
+    config: RunConfig,
+    randomness: Randomness,
+}
+
+impl Run {
+    pub fn new(config: RunConfig) -> Self {
+        Self {
+            config,
+            randomness: Randomness::default(),
+        }
+    }
+
+    /// Where the run's random numbers come from.
+    pub fn randomness(mut self, randomness: Randomness) -> Self {
+        self.randomness = randomness;
+        self
+    }
+
+    /// Applies the file and writes every output, returning the accounts it leaves. Warnings and
+    /// failed checks are written to stderr.
+    pub fn run(self) -> Result<Database<Client>, RunError> {
+        let config = self.config;
+        let started_at = SystemClock.now();
+        let started = Instant::now();
+
+        // Each client's risk score is part of the extended report when transactions are scored.
+        let risk = config.risk.map(RiskScorer::new);
+        let mut extended = Report::extended();
+        if let Some(risk) = &risk {
+            extended.register(Box::new(RiskScoreColumn(risk.scores())));
+        }
+
+        // Any column of the extended report may be selected, even without --extended.
+        let report = match &config.columns {
+            Some(columns) => extended.select(columns).map_err(RunError::Report)?,
+            None if config.extended => extended,
+            None => Report::standard(),
+        }
+        .formatted(config.amount_format);
+
+        let resume = match &config.checkpoint {
+            Some(checkpoint) => {
+                resume_checkpoint(checkpoint, &config.file_path, config.force_migrate)?
+            }
+            None => None,
+        };
+        let resume_position = resume.as_ref().map(|checkpoint| checkpoint.position);
+
+        // Storage is saved as transactions are applied, so it's at least as recent as a checkpoint.
+        // Transactions it already holds are skipped as already processed.
+        let database = match (&config.storage, resume, &config.snapshot_in) {
+            (Some(storage), _, _) => Database::open_storage(storage).map_err(RunError::Storage)?,
+            (None, Some(checkpoint), _) => {
+                Database::from_snapshot(checkpoint.snapshot).map_err(RunError::Checkpoint)?
+            }
+            (None, None, Some(snapshot_in)) => {
+                Database::load_snapshot(snapshot_in, config.snapshot_format, config.force_migrate)
+                    .map_err(RunError::Snapshot)?
+            }
+            (None, None, None) => Database::new(),
+        }
+        .with_duplicate_policy(config.duplicate_policy);
+        let database = match &config.rates {
+            Some(path) => database.with_rates(
+                ExchangeRates::load(path).map_err(RunError::Rates)?,
+                config.rounding_mode,
+            ),
+            None => database,
+        };
+        let database = match &config.fees {
+            Some(path) => database.with_fees(
+                FeeSchedule::load(path).map_err(RunError::Fees)?,
+                config.rounding_mode,
+            ),
+            None => database,
+        };
+        let database = match (config.overdraft, &config.overdraft_limits) {
+            (None, None) => database,
+            (default, path) => {
+                let limits = OverdraftLimits::new(default.unwrap_or_default());
+                let limits = match path {
+                    Some(path) => limits.load(path).map_err(RunError::Overdraft)?,
+                    None => limits,
+                };
+                database.with_overdraft(limits)
+            }
+        };
+        let database = match config.minimum_balance {
+            Some(minimum) => database.with_minimum_balance(minimum),
+            None => database,
+        };
+        let mut database = match &config.velocity_limits {
+            Some(path) => database.with_velocity_limits(
+                VelocityLimits::load(path).map_err(RunError::Velocity)?,
+                config.velocity_policy,
+            ),
+            None => database,
+        };
+        // Decisions are logged by the database, with the balances they leave, and records ignored
+        // before reaching it by the pipeline.
+        let audit = match &config.audit_log {
+            Some(audit_log) => {
+                let log = AuditLog::append(&audit_log.path, audit_log.format)
+                    .map_err(RunError::Output)?;
+                let observer = AuditObserver(Arc::new(Mutex::new(log)));
+                database.subscribe(Box::new(observer.clone()));
+                Some(observer)
+            }
+            None => None,
+        };
+        // The ledger uses the same chart of accounts as the exported journal, and starts from the
+        // balances the accounts start the run with.
+        let double_entry = match config.double_entry {
+            true => {
+                let chart = match config
+                    .ledger
+                    .as_ref()
+                    .and_then(|ledger| ledger.chart.as_ref())
+                {
+                    Some(chart) => ChartOfAccounts::load(chart).map_err(RunError::Ledger)?,
+                    None => ChartOfAccounts::default(),
+                };
+                let ledger = DoubleEntryLedger::opening(chart, database.accounts());
+                Some(Rc::new(RefCell::new(ledger)))
+            }
+            false => None,
+        };
+        // Flows are added up from the balances the accounts start the run with.
+        let flows = match config.verify {
+            true => {
+                let observer = verify::FlowObserver::opening(database.accounts());
+                database.subscribe(Box::new(observer.clone()));
+                Some(observer)
+            }
+            false => None,
+        };
+        let totals = match &config.reconciliation {
+            Some(_) => {
+                let observer = TotalsObserver::default();
+                database.subscribe(Box::new(observer.clone()));
+                Some(observer)
+            }
+            None => None,
+        };
+
+        let input_format = config
+            .input_format
+            .unwrap_or_else(|| InputFormat::from_path(&config.file_path));
+
+        let approval_queue = config
+            .approval
+            .as_ref()
+            .map(|approval| approval.queue_path.clone());
+        let engine = EngineConfig {
+            approval: config.approval.map(|approval| ApprovalConfig {
+                threshold: approval.threshold,
+                queue_path: approval.queue_path,
+            }),
+            coalesce: config.coalesce,
+            strict: config.strict,
+            soft_limit: config.soft_limit,
+            maximum_amount: config.maximum_amount.zip(Some(config.maximum_policy)),
+            reorder_window: config.reorder_window,
+        };
+
+        let config_hash = manifest::config_hash(&engine);
+        let counts = Rc::new(RefCell::new(RunCounts::default()));
+
+        let mut pipeline = PipelineBuilder::new(database)
+            .input_path(&config.file_path)
+            .format(input_format)
+            .config(engine)
+            .observer(Box::new(RunCounter(counts.clone())))
+            .report(report);
+
+        // Writing the report to a file keeps stdout free for logs.
+        let writer: Box<dyn Write> = match &config.output {
+            Some(output) => {
+                let file = File::create(output).map_err(RunError::Output)?;
+                compression::compress_for(output, Box::new(BufWriter::new(file)))
+                    .map_err(RunError::Output)?
+            }
+            None => Box::new(std::io::stdout()),
+        };
+        let mut report_sink: Box<dyn ReportSink<Client>> = if config.stamp {
+            Box::new(CsvReportSink::new(writer).stamp(manifest::stamp(&config_hash)))
+        } else {
+            config.format.sink(writer)
+        };
+        if config.rounding.is_some() || config.suppress_below.is_some() {
+            let mut shared = SharedReportSink::new(report_sink);
+            if let Some(rounding) = config.rounding {
+                shared = shared.rounding(rounding);
+            }
+            if let Some(threshold) = config.suppress_below {
+                shared = shared.suppress_below(threshold);
+            }
+            report_sink = Box::new(shared);
+        }
+        pipeline = pipeline.report_sink(report_sink);
+
+        // Rejected transactions are logged so failures can be audited. A resumed run adds to the log
+        // of the interrupted one.
+        let error_sink: Box<dyn ErrorSink> = match &config.error_log {
+            Some(error_log) if resume_position.is_some() => {
+                Box::new(ErrorLogSink::append(error_log).map_err(RunError::ErrorLog)?)
+            }
+            Some(error_log) => {
+                Box::new(ErrorLogSink::create(error_log).map_err(RunError::ErrorLog)?)
+            }
+            None => Box::new(ErrorLogSink::stderr()),
+        };
+        pipeline = pipeline.error_sink(error_sink);
+
+        match &config.client_map {
+            Some(ClientMapConfig::File(path)) => {
+                let map = ClientMap::load(path).map_err(RunError::ClientMap)?;
+                pipeline = pipeline.client_map(map);
+            }
+            Some(ClientMapConfig::Key(key)) => {
+                pipeline = pipeline.client_map(ClientMap::keyed(*key))
+            }
+            None => {}
+        }
+        // Sampling is repeatable by design, so it only changes with an explicit seed.
+        if let Some(rate) = config.sample {
+            pipeline = pipeline.sample(rate.seeded(self.randomness.seed.unwrap_or_default()));
+        }
+        if let Some(risk) = risk {
+            pipeline = pipeline.risk(risk);
+        }
+        if let Some(records) = config.dispute_grace {
+            pipeline = pipeline.dispute_grace(records);
+        }
+        if let Some(limit) = config.rate_limit {
+            pipeline = pipeline.rate_limit(limit);
+        }
+        if config.follow {
+            pipeline = pipeline.follow(Follow::from_signals());
+        }
+        pipeline = pipeline.kinds(
+            TransactionKinds::default()
+                .unknown_types(config.unknown_types)
+                .columns(config.column_map.clone())
+                .precision(config.excess_precision),
+        );
+        if let Some(checkpoint) = &config.checkpoint {
+            let checkpointer = FileCheckpointer {
+                path: checkpoint.path.clone(),
+                input_path: config.file_path.clone(),
+            };
+            pipeline = pipeline.checkpoint(checkpoint.every, Box::new(checkpointer));
+        }
+        if let Some(position) = resume_position {
+            pipeline = pipeline.resume_from(position);
+        }
+        pipeline = pipeline
+            .shards(config.shards)
+            .partitioner(Arc::new(config.partitioner));
+        let ledger = match &config.ledger {
+            Some(ledger) => {
+                let chart = match &ledger.chart {
+                    Some(chart) => ChartOfAccounts::load(chart).map_err(RunError::Ledger)?,
+                    None => ChartOfAccounts::default(),
+                };
+                let file = File::create(&ledger.path).map_err(RunError::Output)?;
+                let writer = Rc::new(RefCell::new(LedgerWriter::new(
+                    chart,
+                    ledger.format,
+                    started_at,
+                    Box::new(BufWriter::new(file)),
+                )));
+                pipeline = pipeline.observer(Box::new(LedgerObserver(writer.clone())));
+                Some(writer)
+            }
+            None => None,
+        };
+
+        if let Some(audit) = &audit {
+            pipeline = pipeline.observer(Box::new(audit.clone()));
+        }
+
+        if let Some(ledger) = &double_entry {
+            pipeline = pipeline.observer(Box::new(DoubleEntryObserver(ledger.clone())));
+        }
+
+        let mut database = pipeline
+            .build()
+            .and_then(|pipeline| pipeline.run())
+            .map_err(RunError::Pipeline)?;
+        database.flush_storage().map_err(RunError::Storage)?;
+        if let Some(ledger) = ledger {
+            ledger.borrow_mut().finish().map_err(RunError::Output)?;
+        }
+        if let Some(ledger) = double_entry {
+            let ledger = ledger.borrow();
+            let trial = ledger.trial_balance();
+            if !trial.is_balanced() {
+                return Err(RunError::Unbalanced {
+                    debits: trial.debits(),
+                    credits: trial.credits(),
+                });
+            }
+            let mismatches = ledger.mismatches(database.accounts());
+            if !mismatches.is_empty() {
+                ledger::write_mismatches(&mismatches, &mut std::io::stderr())
+                    .map_err(RunError::Output)?;
+                return Err(RunError::LedgerMismatch {
+                    clients: mismatches.len(),
+                });
+            }
+        }
+        if let Some(audit) = audit {
+            audit.0.lock().unwrap().finish().map_err(RunError::Output)?;
+        }
+        if let Some(flows) = flows {
+            let violations = verify::verify(database.accounts(), &flows.0.lock().unwrap());
+            if !violations.is_empty() {
+                verify::write_violations(&violations, &mut std::io::stderr())
+                    .map_err(RunError::Output)?;
+                return Err(RunError::InvariantsViolated {
+                    violations: violations.len(),
+                });
+            }
+        }
+
+        // The run is complete, so there is nothing left to resume.
+        if let Some(checkpoint) = &config.checkpoint {
+            match fs::remove_file(&checkpoint.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(RunError::Output(e))
+                }
+                _ => {}
+            }
+        }
+
+        let skipped = counts.borrow().skipped;
+        if skipped > 0 {
+            eprintln!(
+                "{} transactions were already processed in a previous run and were skipped",
+                skipped
+            );
+        }
+
+        let unknown_types = counts.borrow().unknown_types;
+        if unknown_types > 0 {
+            eprintln!(
+                "warning: {} records of unknown types were skipped",
+                unknown_types
+            );
+        }
+
+        let ignored = counts.borrow().ignored;
+        if ignored > 0 {
+            eprintln!(
+                "warning: {} records were ignored rather than applied",
+                ignored
+            );
+        }
+
+        let soft_limit_warnings = counts.borrow().soft_limit_warnings;
+        if soft_limit_warnings > 0 {
+            eprintln!(
+                "warning: {} transactions were applied above the soft limit",
+                soft_limit_warnings
+            );
+        }
+
+        for id in database.duplicates() {
+            eprintln!("warning: tx {} reused an id that was already applied", id);
+        }
+
+        for (id, limit) in database.velocity_breaches() {
+            eprintln!("warning: tx {} exceeded the velocity limit {:?}", id, limit);
+        }
+
+        if let Some(snapshot_out) = &config.snapshot_out {
+            let snapshot = Snapshot {
+                config_hash: Some(config_hash.clone()),
+                ..database.snapshot()
+            };
+            snapshot::write_to_path(&snapshot, snapshot_out, config.snapshot_format)
+                .map_err(RunError::Snapshot)?;
+        }
+
+        if let Some(path) = &config.pending_disputes {
+            let disputes = export::pending_disputes(database.accounts());
+            File::create(path)
+                .map_err(csv::Error::from)
+                .and_then(|file| {
+                    export::write_pending_disputes(&disputes, &mut BufWriter::new(file))
+                })
+                .map_err(RunError::PendingDisputes)?;
+        }
+
+        if let (Some(path), Some(totals)) = (&config.reconciliation, totals) {
+            let totals = *totals.0.lock().unwrap();
+            let reconciliation =
+                Reconciliation::new(totals, database.accounts(), counts.borrow().rejected);
+            File::create(path)
+                .and_then(|file| reconciliation.write(&mut BufWriter::new(file)))
+                .map_err(RunError::Output)?;
+        }
+
+        // Written last so its presence means every other output is complete.
+        if let Some(manifest_path) = &config.manifest {
+            let mut counts = *counts.borrow();
+            counts.accounts = database.accounts().count() as u64;
+
+            let outputs = [
+                ("report", &config.output),
+                ("snapshot", &config.snapshot_out),
+                ("error_log", &config.error_log),
+                ("approval_queue", &approval_queue),
+                ("pending_disputes", &config.pending_disputes),
+                ("reconciliation", &config.reconciliation),
+                (
+                    "ledger",
+                    &config.ledger.as_ref().map(|ledger| ledger.path.clone()),
+                ),
+                (
+                    "audit_log",
+                    &config
+                        .audit_log
+                        .as_ref()
+                        .map(|audit_log| audit_log.path.clone()),
+                ),
+            ]
+            .iter()
+            .filter_map(|(kind, path)| {
+                path.as_ref().map(|path| OutputManifest {
+                    kind,
+                    path: path.clone(),
+                })
+            })
+            .collect();
+
+            let run = RunManifest {
+                engine_version: manifest::engine_version(),
+                input: InputManifest {
+                    path: config.file_path.clone(),
+                    format: format!("{:?}", input_format),
+                    bytes: fs::metadata(&config.file_path)
+                        .map_err(RunError::Output)?
+                        .len(),
+                },
+                config_hash,
+                counts,
+                started_at,
+                duration_ms: started.elapsed().as_millis(),
+                outputs,
+            };
+            run.write_to_path(manifest_path).map_err(RunError::Output)?;
+        }
+
+        Ok(database)
+    }
+}
+
+/// The checkpoint to resume from, if resuming was asked for and a checkpoint was left behind.
+fn resume_checkpoint(
+    config: &CheckpointConfig,
+    file_path: &str,
+    force_migrate: bool,
+) -> Result<Option<Checkpoint>, RunError> {
+    if !config.resume || !Path::new(&config.path).exists() {
+        return Ok(None);
+    }
+
+    let checkpoint = Checkpoint::read_from_path(&config.path).map_err(RunError::Checkpoint)?;
+    checkpoint
+        .snapshot
+        .check_version(force_migrate)
+        .map_err(RunError::Checkpoint)?;
+    if checkpoint.input_path != file_path {
+        return Err(RunError::CheckpointMismatch {
+            input_path: checkpoint.input_path,
+        });
+    }
+
+    Ok(Some(checkpoint))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::places, source::InputPosition};
+
+    fn write_input(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn run_writes_the_report_and_error_log() {
+        let input = write_input(
+            "payments_run_report.csv",
+            "type, client, tx, amount\ndeposit, 1, 1, 2.0\nwithdrawal, 1, 2, 5.0\n",
+        );
+        let output = std::env::temp_dir().join("payments_run_report_out.csv");
+        let error_log = std::env::temp_dir().join("payments_run_report_errors.log");
+        let config = RunConfig {
+            output: Some(output.to_string_lossy().into_owned()),
+            error_log: Some(error_log.to_string_lossy().into_owned()),
+            ..RunConfig::new(&input)
+        };
+
+        let database = Run::new(config).run().unwrap();
+
+        assert_eq!(1, database.accounts().count());
+        assert_eq!(
+            places("client, available, held, total, locked\n1, 2.0000, 0.0000, 2.0000, false\n"),
+            fs::read_to_string(&output).unwrap()
+        );
+        let errors = fs::read_to_string(&error_log).unwrap();
+        assert!(errors.starts_with("line 3: client 1, tx 2:"));
+    }
+
+    #[test]
+    fn run_resume_rejects_a_checkpoint_of_another_file() {
+        let input = write_input(
+            "payments_run_resume.csv",
+            "type, client, tx, amount\ndeposit, 1, 1, 2.0\n",
+        );
+        let path = std::env::temp_dir().join("payments_run_resume.checkpoint");
+        let path = path.to_string_lossy().into_owned();
+        Checkpoint {
+            input_path: String::from("other.csv"),
+            position: InputPosition::default(),
+            snapshot: Database::<Client>::new().snapshot(),
+        }
+        .write_to_path(&path)
+        .unwrap();
+        let config = RunConfig {
+            output: Some(write_input("payments_run_resume_out.csv", "")),
+            checkpoint: Some(CheckpointConfig {
+                path,
+                every: 1,
+                resume: true,
+            }),
+            ..RunConfig::new(&input)
+        };
+
+        match Run::new(config).run() {
+            Err(RunError::CheckpointMismatch { input_path }) => {
+                assert_eq!("other.csv", input_path)
+            }
+            _ => panic!("expected a checkpoint mismatch"),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{
+    client::ClientId,
+    source::{InputPosition, SourceError, TransactionSource},
+    transaction::Transaction,
+};
+use std::str::FromStr;
+
+// A quick smoke test over a huge file only needs a share of the clients. Sampling picks clients
+// rather than transactions, so each sampled client keeps its whole history and dispute chains
+// still run end to end. Clients are picked by a hash of their id, so every run picks the same
+// ones unless it's given another seed.
+
+/// The share of clients to keep, in parts per million.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SampleRate {
+    parts_per_million: u32,
+    seed: u64,
+}
+
+const MILLION: u32 = 1_000_000;
+
+impl SampleRate {
+    /// Picks another share of the clients, the same one for every run given the seed.
+    pub fn seeded(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Whether the client's transactions are kept.
+    pub fn includes(&self, client: ClientId) -> bool {
+        let hash = (u64::from(client) ^ self.seed).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32;
+        (hash % u64::from(MILLION)) < u64::from(self.parts_per_million)
+    }
+}
+
+impl FromStr for SampleRate {
+    type Err = String;
+
+    /// Parses a percentage such as `1%` or `0.5%`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let percent: f64 = s
+            .trim()
+            .trim_end_matches('%')
+            .parse()
+            .map_err(|_| String::from(s))?;
+
+        if !(percent > 0.0 && percent <= 100.0) {
+            return Err(String::from(s));
+        }
+
+        Ok(Self {
+            parts_per_million: (percent * 10_000.0).round() as u32,
+            seed: 0,
+        })
+    }
+}
+
+/// Keeps only the transactions of sampled clients. A transfer is kept if its sender is sampled.
+pub struct SampleSource {
+    inner: Box<dyn TransactionSource>,
+    rate: SampleRate,
+}
+
+impl SampleSource {
+    pub fn new(inner: Box<dyn TransactionSource>, rate: SampleRate) -> Self {
+        Self { inner, rate }
+    }
+}
+
+impl TransactionSource for SampleSource {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        loop {
+            match self.inner.next()? {
+                Ok(transaction) if !self.rate.includes(transaction.client) => continue,
+                result => return Some(result),
+            }
+        }
+    }
+
+    fn line(&self) -> Option<u64> {
+        self.inner.line()
+    }
+
+    fn position(&self) -> Option<InputPosition> {
+        self.inner.position()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::Amount, source::IterSource, transaction::TransactionType};
+
+    #[test]
+    fn sample_rate_from_str_returns_expected() {
+        assert_eq!(
+            Ok(SampleRate {
+                parts_per_million: 10_000,
+                seed: 0,
+            }),
+            SampleRate::from_str("1%")
+        );
+        assert_eq!(
+            Ok(SampleRate {
+                parts_per_million: 5_000,
+                seed: 0,
+            }),
+            SampleRate::from_str("0.5")
+        );
+
+        for passed in ["0%", "101%", "-1%", "abc"] {
+            assert_eq!(Err(String::from(passed)), SampleRate::from_str(passed));
+        }
+    }
+
+    #[test]
+    fn sample_rate_includes_roughly_the_share_of_clients() {
+        let rate = SampleRate::from_str("10%").unwrap();
+
+        let included = (0..=ClientId::MAX)
+            .filter(|client| rate.includes(*client))
+            .count();
+
+        assert_eq!(true, (6000..7100).contains(&included), "{}", included);
+        assert_eq!(
+            true,
+            (0..=ClientId::MAX)
+                .all(|client| SampleRate::from_str("100%").unwrap().includes(client))
+        );
+    }
+
+    #[test]
+    fn sample_rate_seeded_picks_other_clients() {
+        let rate = SampleRate::from_str("10%").unwrap();
+        let seeded = rate.seeded(42);
+
+        let picked = |rate: SampleRate| -> Vec<ClientId> {
+            (0..1000).filter(|client| rate.includes(*client)).collect()
+        };
+        assert_eq!(picked(seeded), picked(rate.seeded(42)));
+        assert_eq!(false, picked(seeded) == picked(rate));
+        assert_eq!(picked(rate), picked(rate.seeded(0)));
+    }
+
+    #[test]
+    fn sample_source_keeps_whole_histories_of_sampled_clients() {
+        let rate = SampleRate::from_str("50%").unwrap();
+        let sampled = (0..).find(|client| rate.includes(*client)).unwrap();
+        let skipped = (0..).find(|client| !rate.includes(*client)).unwrap();
+
+        let transaction = |transaction_type, client, id| Transaction {
+            transaction_type,
+            client,
+            id,
+            timestamp: None,
+            currency: None,
+        };
+        let transactions = vec![
+            transaction(TransactionType::Deposit(Amount::new(1)), sampled, 1),
+            transaction(TransactionType::Deposit(Amount::new(1)), skipped, 2),
+            transaction(TransactionType::Dispute { case: None }, sampled, 1),
+            transaction(TransactionType::Dispute { case: None }, skipped, 2),
+        ];
+        let mut source = SampleSource::new(Box::new(IterSource::new(transactions.clone())), rate);
+
+        assert_eq!(transactions[0], source.next().unwrap().unwrap());
+        assert_eq!(transactions[2], source.next().unwrap().unwrap());
+        assert_eq!(true, source.next().is_none());
+    }
+}
@@ -0,0 +1,331 @@
+use crate::{
+    client::{ClientAccount, ClientId},
+    database::Database,
+    protocol::{self, ResultCode},
+    transaction::Transaction,
+};
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Condvar, Mutex, RwLock};
+use std::time::Duration;
+
+// Batches from every connection are split into per-client queues, and a single worker applies
+// them taking one transaction from each client in turn. A client flooding the server only grows
+// its own queue, so other clients keep progressing. Each client's transactions are still applied
+// in the order they were submitted.
+
+/// How many transactions a client may have queued unless configured otherwise.
+pub const DEFAULT_MAX_INFLIGHT_PER_CLIENT: usize = 1024;
+
+/// Work the worker does at an interval between transactions, such as reporting memory stats.
+pub trait Periodic<Account>: Send
+where
+    Account: ClientAccount,
+{
+    /// How long until the work is next due.
+    fn until_due(&self) -> Duration;
+
+    /// Does the work if it's due.
+    fn tick(&mut self, database: &Database<Account>);
+}
+
+/// How long until the first of the tasks is due, or `None` without any.
+fn until_first_due<Account>(tasks: &[Box<dyn Periodic<Account>>]) -> Option<Duration>
+where
+    Account: ClientAccount,
+{
+    tasks.iter().map(|task| task.until_due()).min()
+}
+
+/// A queued transaction and where to send its result.
+struct Job {
+    transaction: Transaction,
+    index: usize,
+    reply: Sender<(usize, ResultCode)>,
+}
+
+#[derive(Default)]
+struct Queues {
+    by_client: HashMap<ClientId, VecDeque<Job>>,
+    /// Clients with queued transactions, in the order they take their turns.
+    turns: VecDeque<ClientId>,
+}
+
+/// Applies transactions from many submitters, round-robin across clients.
+pub struct FairScheduler {
+    queues: Mutex<Queues>,
+    /// Signalled when a transaction is queued.
+    work: Condvar,
+    /// Signalled when a transaction leaves a client's queue.
+    room: Condvar,
+    max_inflight: usize,
+}
+
+impl FairScheduler {
+    /// A scheduler that makes submitters wait while a client has `max_inflight` transactions
+    /// queued.
+    pub fn new(max_inflight: usize) -> Self {
+        Self {
+            queues: Mutex::new(Queues::default()),
+            work: Condvar::new(),
+            room: Condvar::new(),
+            max_inflight: max_inflight.max(1),
+        }
+    }
+
+    /// Queues the job behind the client's earlier transactions, waiting for room if needed. A
+    /// transfer is queued for its sender.
+    fn submit(&self, job: Job) {
+        let client = job.transaction.client;
+
+        let mut queues = self.queues.lock().unwrap();
+        while queues.by_client.get(&client).map_or(0, VecDeque::len) >= self.max_inflight {
+            queues = self.room.wait(queues).unwrap();
+        }
+
+        let queue = queues.by_client.entry(client).or_default();
+        queue.push_back(job);
+        if queue.len() == 1 {
+            queues.turns.push_back(client);
+        }
+        self.work.notify_one();
+    }
+
+    /// Takes the oldest transaction of the client whose turn it is, waiting for one to be queued.
+    fn next(&self) -> Job {
+        self.next_within(None).unwrap()
+    }
+
+    /// Takes the next transaction as `next` does, giving up once the timeout passes.
+    fn next_within(&self, timeout: Option<Duration>) -> Option<Job> {
+        let mut queues = self.queues.lock().unwrap();
+        let client = loop {
+            match (queues.turns.pop_front(), timeout) {
+                (Some(client), _) => break client,
+                (None, None) => queues = self.work.wait(queues).unwrap(),
+                (None, Some(timeout)) => {
+                    let (waited, result) = self.work.wait_timeout(queues, timeout).unwrap();
+                    queues = waited;
+                    if result.timed_out() && queues.turns.is_empty() {
+                        return None;
+                    }
+                }
+            }
+        };
+
+        let queue = queues.by_client.get_mut(&client).unwrap();
+        let job = queue.pop_front().unwrap();
+        if queue.is_empty() {
+            queues.by_client.remove(&client);
+        } else {
+            queues.turns.push_back(client);
+        }
+
+        self.room.notify_all();
+        Some(job)
+    }
+
+    /// Applies queued transactions to the database as they arrive. Never returns, so it's run on
+    /// its own thread.
+    pub fn run<Account>(&self, database: &mut Database<Account>)
+    where
+        Account: ClientAccount,
+    {
+        loop {
+            let job = self.next();
+            apply(database, job);
+        }
+    }
+
+    /// Applies queued transactions as `run` does, doing the periodic tasks whenever they're due,
+    /// even while no transactions arrive.
+    pub fn run_periodic<Account>(
+        &self,
+        database: &mut Database<Account>,
+        tasks: &mut [Box<dyn Periodic<Account>>],
+    ) where
+        Account: ClientAccount,
+    {
+        loop {
+            for task in tasks.iter_mut() {
+                task.tick(database);
+            }
+            if let Some(job) = self.next_within(until_first_due(tasks)) {
+                apply(database, job);
+            }
+        }
+    }
+
+    /// Applies queued transactions to a database that readers share, holding the write lock for
+    /// one transaction at a time. `written` is told about every transaction while the lock is
+    /// still held, so readers never see a change before it's been handled.
+    pub fn run_shared<Account, F>(
+        &self,
+        database: &RwLock<Database<Account>>,
+        mut tasks: Vec<Box<dyn Periodic<Account>>>,
+        written: F,
+    ) where
+        Account: ClientAccount,
+        F: Fn(&Transaction),
+    {
+        loop {
+            if !tasks.is_empty() {
+                let database = database.read().unwrap();
+                for task in tasks.iter_mut() {
+                    task.tick(&database);
+                }
+            }
+
+            if let Some(job) = self.next_within(until_first_due(&tasks)) {
+                let transaction = job.transaction;
+                let mut database = database.write().unwrap();
+                apply(&mut database, job);
+                written(&transaction);
+            }
+        }
+    }
+
+    /// Queues a single transaction and waits for it to be applied. Returns `None` if transactions
+    /// are no longer being applied.
+    pub fn process(&self, transaction: Transaction) -> Option<ResultCode> {
+        let (reply, result) = mpsc::channel();
+        self.submit(Job {
+            transaction,
+            index: 0,
+            reply,
+        });
+        result.recv().ok().map(|(_, code)| code)
+    }
+
+    /// Queues every record in the batch and waits for them to be applied, returning the result
+    /// code for each non-empty line. Records of other batches may be applied in between.
+    pub fn process_batch(&self, payload: &[u8]) -> Vec<u8> {
+        let records = protocol::parse_batch(payload);
+        let mut codes = vec![ResultCode::Ignored; records.len()];
+
+        let (reply, results) = mpsc::channel();
+        let mut queued = 0;
+        for (index, record) in records.into_iter().enumerate() {
+            match record {
+                Ok(Some(transaction)) => {
+                    self.submit(Job {
+                        transaction,
+                        index,
+                        reply: reply.clone(),
+                    });
+                    queued += 1;
+                }
+                Ok(None) => codes[index] = ResultCode::Ignored,
+                Err(_) => codes[index] = ResultCode::Malformed,
+            }
+        }
+
+        for (index, code) in results.iter().take(queued) {
+            codes[index] = code;
+        }
+
+        codes.into_iter().map(|code| code as u8).collect()
+    }
+}
+
+fn apply<Account>(database: &mut Database<Account>, job: Job)
+where
+    Account: ClientAccount,
+{
+    let code = ResultCode::from_result(&database.apply_transaction(job.transaction));
+    // The submitter only goes away if its connection failed, so the result isn't needed.
+    let _result = job.reply.send((job.index, code));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::Amount, client::Client, transaction::TransactionType};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn job(client: ClientId, id: u32, reply: &Sender<(usize, ResultCode)>) -> Job {
+        Job {
+            transaction: Transaction {
+                transaction_type: TransactionType::Deposit(Amount::new(1)),
+                client,
+                id,
+                timestamp: None,
+                currency: None,
+            },
+            index: id as usize,
+            reply: reply.clone(),
+        }
+    }
+
+    #[test]
+    fn fair_scheduler_next_takes_clients_in_turn() {
+        let scheduler = FairScheduler::new(10);
+        let (reply, _results) = mpsc::channel();
+
+        for id in 1..=3 {
+            scheduler.submit(job(1, id, &reply));
+        }
+        scheduler.submit(job(2, 4, &reply));
+        scheduler.submit(job(3, 5, &reply));
+
+        let order: Vec<u32> = (0..5).map(|_| scheduler.next().transaction.id).collect();
+        assert_eq!(vec![1, 4, 5, 2, 3], order);
+    }
+
+    #[test]
+    fn fair_scheduler_next_within_times_out_when_empty() {
+        let scheduler = FairScheduler::new(10);
+        let (reply, _results) = mpsc::channel();
+
+        assert_eq!(
+            true,
+            scheduler
+                .next_within(Some(Duration::from_millis(1)))
+                .is_none()
+        );
+
+        scheduler.submit(job(1, 1, &reply));
+        let job = scheduler.next_within(Some(Duration::from_millis(1)));
+        assert_eq!(Some(1), job.map(|job| job.transaction.id));
+    }
+
+    #[test]
+    fn fair_scheduler_submit_waits_for_room() {
+        let scheduler = Arc::new(FairScheduler::new(2));
+        let (reply, _results) = mpsc::channel();
+        scheduler.submit(job(1, 1, &reply));
+        scheduler.submit(job(1, 2, &reply));
+
+        let submitter = {
+            let scheduler = scheduler.clone();
+            let reply = reply.clone();
+            thread::spawn(move || scheduler.submit(job(1, 3, &reply)))
+        };
+
+        assert_eq!(1, scheduler.next().transaction.id);
+        submitter.join().unwrap();
+        assert_eq!(2, scheduler.next().transaction.id);
+        assert_eq!(3, scheduler.next().transaction.id);
+    }
+
+    #[test]
+    fn fair_scheduler_process_batch_returns_code_per_record() {
+        let scheduler = Arc::new(FairScheduler::new(1));
+        {
+            let scheduler = scheduler.clone();
+            thread::spawn(move || scheduler.run(&mut Database::<Client>::new()));
+        }
+
+        let payload = "deposit, 1, 1, 2.0\n\nwithdrawal, 1, 2, 5.0\ndeposit, 2, 3,\ngarbage\n";
+        let actual = scheduler.process_batch(payload.as_bytes());
+
+        let expected = vec![
+            ResultCode::Applied as u8,
+            ResultCode::InvalidWithdrawal as u8,
+            ResultCode::Ignored as u8,
+            ResultCode::Malformed as u8,
+        ];
+        assert_eq!(expected, actual);
+    }
+}
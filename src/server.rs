@@ -0,0 +1,388 @@
+use crate::{
+    client::{Client, ClientAccount},
+    clock::SystemClock,
+    database::Database,
+    http::{self, AccountCache, DEFAULT_ACCOUNT_CACHE_LEN},
+    protocol::{self, ResultCode},
+    report::Report,
+    retention::{PeriodicSnapshots, SnapshotSchedule},
+    scheduler::{FairScheduler, Periodic, DEFAULT_MAX_INFLIGHT_PER_CLIENT},
+    spill::{HistorySpill, SpillError},
+    storage::StorageError,
+    telemetry::Telemetry,
+    transaction::TransactionType,
+    wal::WalError,
+    websocket,
+};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+/// How submitters talk to the TCP server.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum WireProtocol {
+    /// Length-prefixed batches of CSV records, answered with a result code byte per record.
+    #[default]
+    Framed,
+    /// A CSV record or JSON object per line, each answered with the name of its result code.
+    /// A `report` line is answered with the current balances as CSV, followed by a blank line.
+    Lines,
+}
+
+impl FromStr for WireProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "framed" => Ok(Self::Framed),
+            "lines" => Ok(Self::Lines),
+            _ => Err(String::from(s)),
+        }
+    }
+}
+
+/// The services served alongside the TCP connections, by address.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Endpoints<'a> {
+    /// The read-only query API.
+    pub http: Option<&'a str>,
+    pub grpc: Option<&'a str>,
+    /// Transactions streamed over WebSocket, each answered with an acknowledgment.
+    pub websocket: Option<&'a str>,
+}
+
+/// Everything the `serve` command is configured with.
+#[derive(PartialEq, Debug)]
+pub struct ServeConfig {
+    pub tcp_addr: String,
+    /// Where accounts are kept, if they are.
+    pub storage: Option<String>,
+    /// A write-ahead log to recover from and log every transaction to.
+    pub wal: Option<String>,
+    /// How many transactions a client may have queued before submitters wait.
+    pub max_inflight_per_client: usize,
+    /// How many seconds apart memory stats are reported.
+    pub telemetry_interval: Option<u64>,
+    /// How many history entries an account may keep before it's compacted.
+    pub history_cap: Option<usize>,
+    /// Where history is moved once compacting isn't enough.
+    pub history_spill: Option<String>,
+    /// Where the query API is served over the live accounts.
+    pub http_addr: Option<String>,
+    /// How submitters talk to the server.
+    pub protocol: WireProtocol,
+    /// Where the engine is served over gRPC too.
+    pub grpc_addr: Option<String>,
+    /// Where transactions are accepted over WebSocket.
+    pub websocket_addr: Option<String>,
+    pub snapshots: Option<SnapshotSchedule>,
+}
+
+#[derive(Debug)]
+pub enum ServeError {
+    Storage(StorageError),
+    Wal(WalError),
+    Spill(SpillError),
+    Io(io::Error),
+}
+
+/// Opens the accounts the config keeps them in, then serves them until the server stops.
+pub fn serve(config: ServeConfig) -> Result<(), ServeError> {
+    let mut database = match (&config.storage, &config.wal) {
+        (Some(storage), _) => Database::open_storage(storage).map_err(ServeError::Storage)?,
+        (None, Some(wal)) => Database::new().with_wal(wal).map_err(ServeError::Wal)?,
+        (None, None) => Database::new(),
+    };
+    if let Some(cap) = config.history_cap {
+        let spill = match &config.history_spill {
+            Some(path) => Some(HistorySpill::create(path).map_err(ServeError::Spill)?),
+            None => None,
+        };
+        database = database.with_history_cap(cap, spill);
+    }
+
+    let mut tasks: Vec<Box<dyn Periodic<Client>>> = vec![];
+    if let Some(seconds) = config.telemetry_interval {
+        tasks.push(Box::new(Telemetry::new(
+            Duration::from_secs(seconds),
+            Box::new(std::io::stderr()),
+        )));
+    }
+    if let Some(snapshots) = config.snapshots {
+        tasks.push(Box::new(PeriodicSnapshots::new(
+            &snapshots.dir,
+            Duration::from_secs(snapshots.interval),
+            snapshots.format,
+            snapshots.retention,
+            Box::new(SystemClock),
+        )));
+    }
+    // Reports and queries read the accounts while transactions are applied.
+    let endpoints = Endpoints {
+        http: config.http_addr.as_deref(),
+        grpc: config.grpc_addr.as_deref(),
+        websocket: config.websocket_addr.as_deref(),
+    };
+    let shared = endpoints != Endpoints::default() || config.protocol == WireProtocol::Lines;
+    let max_inflight = config.max_inflight_per_client;
+    if shared {
+        serve_tcp_shared(
+            &config.tcp_addr,
+            config.protocol,
+            endpoints,
+            database,
+            max_inflight,
+            tasks,
+        )
+    } else if !tasks.is_empty() {
+        serve_tcp_periodic(&config.tcp_addr, database, max_inflight, tasks)
+    } else {
+        serve_tcp_fair(&config.tcp_addr, database, max_inflight)
+    }
+    .map_err(ServeError::Io)
+}
+
+/// Listens on the given address, applying framed transaction batches from every connection to
+/// the shared database. Each connection is served on its own thread.
+pub fn serve_tcp<Account>(addr: &str, database: Database<Account>) -> io::Result<()>
+where
+    Account: ClientAccount + Send + 'static,
+{
+    serve_tcp_fair(addr, database, DEFAULT_MAX_INFLIGHT_PER_CLIENT)
+}
+
+/// Serves TCP connections as `serve_tcp` does, letting each client have at most `max_inflight`
+/// transactions queued. Connections submitting more for a client wait until some are applied.
+pub fn serve_tcp_fair<Account>(
+    addr: &str,
+    mut database: Database<Account>,
+    max_inflight: usize,
+) -> io::Result<()>
+where
+    Account: ClientAccount + Send + 'static,
+{
+    serve_scheduled(addr, max_inflight, move |scheduler| {
+        scheduler.run(&mut database)
+    })
+}
+
+/// Serves TCP connections as `serve_tcp_fair` does, doing the periodic tasks, such as reporting
+/// memory stats or taking snapshots, whenever they're due.
+pub fn serve_tcp_periodic<Account>(
+    addr: &str,
+    mut database: Database<Account>,
+    max_inflight: usize,
+    mut tasks: Vec<Box<dyn Periodic<Account>>>,
+) -> io::Result<()>
+where
+    Account: ClientAccount + Send + 'static,
+{
+    serve_scheduled(addr, max_inflight, move |scheduler| {
+        scheduler.run_periodic(&mut database, &mut tasks)
+    })
+}
+
+/// Serves TCP connections speaking the given protocol, with the accounts readable while
+/// transactions are applied. With an HTTP endpoint, the read-only query API is served over them
+/// too, and single accounts are cached until a transaction changes them. With gRPC or WebSocket
+/// endpoints, transactions are accepted there as well, sharing the scheduler with the TCP
+/// connections.
+pub fn serve_tcp_shared(
+    addr: &str,
+    protocol: WireProtocol,
+    endpoints: Endpoints,
+    database: Database<Client>,
+    max_inflight: usize,
+    tasks: Vec<Box<dyn Periodic<Client>>>,
+) -> io::Result<()> {
+    let database = Arc::new(RwLock::new(database));
+    let cache = Arc::new(AccountCache::new(DEFAULT_ACCOUNT_CACHE_LEN));
+
+    if let Some(http_addr) = endpoints.http {
+        let database = database.clone();
+        let cache = cache.clone();
+        let http_addr = String::from(http_addr);
+        thread::spawn(move || {
+            if let Err(e) = http::serve_live(&http_addr, database, cache) {
+                eprintln!("Query API stopped with error: {}", e);
+            }
+        });
+    }
+
+    let run = {
+        let database = database.clone();
+        move |scheduler: &FairScheduler| {
+            scheduler.run_shared(&database, tasks, |transaction| {
+                cache.invalidate(transaction.client);
+                if let TransactionType::Transfer { to, .. } = transaction.transaction_type {
+                    cache.invalidate(to);
+                }
+            })
+        }
+    };
+
+    let listener = TcpListener::bind(addr)?;
+    let scheduler = start_scheduler(max_inflight, run);
+
+    if let Some(grpc_addr) = endpoints.grpc {
+        serve_grpc(grpc_addr, scheduler.clone(), database.clone())?;
+    }
+    if let Some(websocket_addr) = endpoints.websocket {
+        let scheduler = scheduler.clone();
+        let websocket_addr = String::from(websocket_addr);
+        thread::spawn(move || {
+            if let Err(e) = websocket::serve(&websocket_addr, scheduler) {
+                eprintln!("WebSocket endpoint stopped with error: {}", e);
+            }
+        });
+    }
+
+    match protocol {
+        WireProtocol::Framed => serve_connections(listener, scheduler, handle_stream),
+        WireProtocol::Lines => serve_connections(listener, scheduler, move |stream, scheduler| {
+            handle_lines(stream, scheduler, &database)
+        }),
+    }
+}
+
+/// Serves the engine over gRPC on its own thread.
+#[cfg(feature = "grpc")]
+fn serve_grpc(
+    addr: &str,
+    scheduler: Arc<FairScheduler>,
+    database: Arc<RwLock<Database<Client>>>,
+) -> io::Result<()> {
+    let addr = String::from(addr);
+    thread::spawn(move || {
+        if let Err(e) = crate::grpc::serve(&addr, scheduler, database) {
+            eprintln!("gRPC service stopped with error: {}", e);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "grpc"))]
+fn serve_grpc(
+    _addr: &str,
+    _scheduler: Arc<FairScheduler>,
+    _database: Arc<RwLock<Database<Client>>>,
+) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "gRPC requires building with the grpc feature",
+    ))
+}
+
+/// Accepts connections, with `run` applying the transactions they queue.
+fn serve_scheduled<F>(addr: &str, max_inflight: usize, run: F) -> io::Result<()>
+where
+    F: FnOnce(&FairScheduler) + Send + 'static,
+{
+    let listener = TcpListener::bind(addr)?;
+    serve_connections(listener, start_scheduler(max_inflight, run), handle_stream)
+}
+
+/// Starts the worker that applies queued transactions with `run`. Transactions from every
+/// submitter are applied by this single worker, taking turns by client.
+fn start_scheduler<F>(max_inflight: usize, run: F) -> Arc<FairScheduler>
+where
+    F: FnOnce(&FairScheduler) + Send + 'static,
+{
+    let scheduler = Arc::new(FairScheduler::new(max_inflight));
+    {
+        let scheduler = scheduler.clone();
+        thread::spawn(move || run(&scheduler));
+    }
+    scheduler
+}
+
+/// Accepts connections, serving each with `handle` on its own thread.
+fn serve_connections<H>(
+    listener: TcpListener,
+    scheduler: Arc<FairScheduler>,
+    handle: H,
+) -> io::Result<()>
+where
+    H: Fn(TcpStream, &FairScheduler) -> io::Result<()> + Send + Sync + 'static,
+{
+    let handle = Arc::new(handle);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        let scheduler = scheduler.clone();
+        let handle = handle.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &scheduler) {
+                eprintln!("Connection closed with error: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn handle_stream(stream: TcpStream, scheduler: &FairScheduler) -> io::Result<()> {
+    let mut reader = stream.try_clone()?;
+    let mut writer = stream;
+
+    while let Some(payload) = protocol::read_frame(&mut reader)? {
+        let codes = scheduler.process_batch(&payload);
+        protocol::write_frame(&mut writer, &codes)?;
+    }
+
+    Ok(())
+}
+
+/// Answers lines until the submitter closes the connection. Each transaction is applied before
+/// the next line is read, so a report includes everything the connection sent before it.
+fn handle_lines(
+    stream: TcpStream,
+    scheduler: &FairScheduler,
+    database: &RwLock<Database<Client>>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        let record = line.trim();
+        if record.is_empty() {
+            continue;
+        }
+        if record == "report" {
+            // Rendered before writing, so a slow reader doesn't hold up transactions.
+            let mut report = vec![];
+            database
+                .read()
+                .unwrap()
+                .write_report(&Report::standard(), &mut report)?;
+            writer.write_all(&report)?;
+            writeln!(writer)?;
+            continue;
+        }
+
+        let code = match protocol::parse_line(record) {
+            Ok(Some(transaction)) => scheduler
+                .process(transaction)
+                .ok_or_else(|| io::Error::other("transactions are no longer being applied"))?,
+            Ok(None) => ResultCode::Ignored,
+            Err(_) => ResultCode::Malformed,
+        };
+        writeln!(writer, "{:?}", code)?;
+    }
+}
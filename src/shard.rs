@@ -0,0 +1,578 @@
+use crate::{
+    client::{ClientAccount, ClientId},
+    database::Database,
+    transaction::{Transaction, TransactionError, TransactionId, TransactionType},
+};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+// Transactions of different clients are independent, so the accounts are split into shards by
+// client and each shard applies its transactions on its own thread. A client's transactions all go
+// to the same shard, so they're applied in the order they were read.
+//
+// The few transactions that involve two shards are applied by the pool itself once both shards
+// are idle: transfers between clients of different shards, and transactions reusing an id another
+// shard's client introduced, which is looked up there first so it's caught as it would be without
+// sharding. Outcomes are returned in the order transactions were submitted.
+//
+// Which shard keeps a client is decided by a `Partitioner`. Deployments consuming a partitioned
+// topic can use the partitioner of their producers, so a replay of the topic and live consumption
+// of it give each client to the same shard.
+
+/// How many transactions may wait for each shard before submitting blocks.
+const QUEUE_LEN: usize = 1024;
+
+/// The shard of `count` that keeps the client, as `Partitioning::Hash` assigns it.
+pub fn shard_of(client: ClientId, count: usize) -> usize {
+    let hash = (u64::from(client)).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32;
+    (hash % count as u64) as usize
+}
+
+/// Decides which shard keeps each client.
+pub trait Partitioner: Send + Sync {
+    /// The shard of `count` that keeps the client. Must be below `count` and the same every time
+    /// it's asked.
+    fn shard(&self, client: ClientId, count: usize) -> usize;
+}
+
+/// How a Kafka producer serialized the client ids it keyed records with.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KafkaKey {
+    /// The decimal id, as the `StringSerializer` writes it.
+    String,
+    /// The id as a big-endian 32 bit integer, as the `IntegerSerializer` writes it.
+    Int,
+}
+
+/// The built in ways of assigning clients to shards.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Partitioning {
+    /// Spreads clients evenly whatever their ids.
+    #[default]
+    Hash,
+    /// The client id modulo the shard count.
+    Modulo,
+    /// The partition Kafka's default partitioner gives a record keyed by the client, so each
+    /// shard keeps the clients of one partition when there are as many shards as partitions.
+    Kafka(KafkaKey),
+}
+
+impl Partitioner for Partitioning {
+    fn shard(&self, client: ClientId, count: usize) -> usize {
+        match self {
+            Self::Hash => shard_of(client, count),
+            Self::Modulo => usize::from(client) % count,
+            Self::Kafka(key) => {
+                let hash = match key {
+                    KafkaKey::String => murmur2(client.to_string().as_bytes()),
+                    KafkaKey::Int => murmur2(&i32::from(client).to_be_bytes()),
+                };
+                (hash & 0x7fff_ffff) as usize % count
+            }
+        }
+    }
+}
+
+impl FromStr for Partitioning {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hash" => Ok(Self::Hash),
+            "modulo" => Ok(Self::Modulo),
+            "kafka" => Ok(Self::Kafka(KafkaKey::String)),
+            "kafka-int" => Ok(Self::Kafka(KafkaKey::Int)),
+            _ => Err(String::from(s)),
+        }
+    }
+}
+
+/// The 32 bit MurmurHash2 Kafka hashes record keys with.
+fn murmur2(data: &[u8]) -> u32 {
+    const M: u32 = 0x5bd1_e995;
+    let mut h = 0x9747_b28c ^ data.len() as u32;
+
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> 24;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M) ^ k;
+    }
+
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, byte) in rest.iter().enumerate() {
+            h ^= u32::from(*byte) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^ (h >> 15)
+}
+
+/// A transaction that has been applied or rejected.
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub transaction: Transaction,
+    /// The input line it was read from.
+    pub line: Option<u64>,
+    pub result: Result<(), TransactionError>,
+    /// The fee entries charged on it.
+    pub fees: Vec<Transaction>,
+}
+
+enum Message {
+    Apply {
+        sequence: u64,
+        transaction: Transaction,
+        line: Option<u64>,
+    },
+    /// A client of another shard introduced the id.
+    NoteOwner {
+        transaction_id: TransactionId,
+        owner: ClientId,
+    },
+    /// Answered once every earlier message has been handled.
+    Barrier(Sender<()>),
+}
+
+struct Shard<Account>
+where
+    Account: ClientAccount,
+{
+    database: Arc<Mutex<Database<Account>>>,
+    queue: SyncSender<Message>,
+    worker: JoinHandle<()>,
+}
+
+/// Applies transactions on a thread per shard of the clients.
+pub struct ShardPool<Account>
+where
+    Account: ClientAccount,
+{
+    shards: Vec<Shard<Account>>,
+    partitioner: Arc<dyn Partitioner>,
+    results: Receiver<(u64, Outcome)>,
+    /// The client that first used each id that introduces one.
+    ids: HashMap<TransactionId, ClientId>,
+    /// The sequence number of the next transaction submitted.
+    submitted: u64,
+    /// The sequence number of the next outcome returned.
+    returned: u64,
+    /// Outcomes waiting for an earlier transaction to be applied.
+    pending: BTreeMap<u64, Outcome>,
+}
+
+impl<Account> ShardPool<Account>
+where
+    Account: ClientAccount + Send + 'static,
+{
+    /// Splits the database into `count` shards, assigning clients with the partitioner, and starts
+    /// a thread for each.
+    pub fn start(
+        database: Database<Account>,
+        count: usize,
+        partitioner: Arc<dyn Partitioner>,
+    ) -> Self {
+        let (sender, results) = mpsc::channel();
+
+        // Ids already in the database belong to the clients that introduced them.
+        let mut pool = Self {
+            shards: vec![],
+            partitioner: partitioner.clone(),
+            results,
+            ids: database.owners().collect(),
+            submitted: 0,
+            returned: 0,
+            pending: BTreeMap::new(),
+        };
+
+        for shard in database.split(count, partitioner.as_ref()) {
+            let database = Arc::new(Mutex::new(shard));
+            let (queue, messages) = mpsc::sync_channel(QUEUE_LEN);
+            let worker = {
+                let database = database.clone();
+                let sender = sender.clone();
+                thread::spawn(move || work(&database, messages, sender))
+            };
+            pool.shards.push(Shard {
+                database,
+                queue,
+                worker,
+            });
+        }
+
+        pool
+    }
+}
+
+impl<Account> ShardPool<Account>
+where
+    Account: ClientAccount,
+{
+    /// Queues the transaction on the shard of its client. Transactions involving another shard are
+    /// applied before returning.
+    pub fn submit(&mut self, transaction: Transaction, line: Option<u64>) {
+        let sequence = self.submitted;
+        self.submitted += 1;
+
+        let shard = self.shard_of(transaction.client);
+
+        match self.ids.get(&transaction.id) {
+            Some(&client) if self.shard_of(client) != shard => {
+                let other = self.shard_of(client);
+                self.barrier(other);
+                let owner = self.shards[other]
+                    .database
+                    .lock()
+                    .unwrap()
+                    .owner(transaction.id);
+                match owner {
+                    Some(owner) => self.send(
+                        shard,
+                        Message::NoteOwner {
+                            transaction_id: transaction.id,
+                            owner,
+                        },
+                    ),
+                    // The other client's transaction was rejected, so the id is still free.
+                    None if transaction.introduces_id() => {
+                        self.ids.insert(transaction.id, transaction.client);
+                    }
+                    None => {}
+                }
+            }
+            None if transaction.introduces_id() => {
+                self.ids.insert(transaction.id, transaction.client);
+            }
+            _ => {}
+        }
+
+        if let TransactionType::Transfer { to, .. } = transaction.transaction_type {
+            let recipient = self.shard_of(to);
+            if recipient != shard {
+                let result = self.transfer(shard, recipient, transaction);
+                self.pending.insert(
+                    sequence,
+                    Outcome {
+                        transaction,
+                        line,
+                        result,
+                        fees: vec![],
+                    },
+                );
+                return;
+            }
+        }
+
+        self.send(
+            shard,
+            Message::Apply {
+                sequence,
+                transaction,
+                line,
+            },
+        );
+    }
+
+    /// The outcome of the next transaction in submission order, if it has been applied.
+    pub fn next_outcome(&mut self) -> Option<Outcome> {
+        while let Ok((sequence, outcome)) = self.results.try_recv() {
+            self.pending.insert(sequence, outcome);
+        }
+
+        let outcome = self.pending.remove(&self.returned)?;
+        self.returned += 1;
+        Some(outcome)
+    }
+
+    /// Waits for every submitted transaction to be applied, returning the outcomes not returned
+    /// yet in submission order.
+    pub fn wait(&mut self) -> Vec<Outcome> {
+        let remaining = self.submitted - self.returned;
+        while (self.pending.len() as u64) < remaining {
+            // Workers only stop once the pool is dropped.
+            let (sequence, outcome) = self.results.recv().unwrap();
+            self.pending.insert(sequence, outcome);
+        }
+
+        self.returned = self.submitted;
+        std::mem::take(&mut self.pending).into_values().collect()
+    }
+
+    /// Stops the workers once every submitted transaction is applied, joining the shards back
+    /// into one database. Outcomes not returned yet are dropped.
+    pub fn join(self) -> Database<Account> {
+        let mut databases = vec![];
+        for shard in self.shards {
+            drop(shard.queue);
+            // A worker only panics if applying a transaction did, which is already reported.
+            let _result = shard.worker.join();
+            let database = match Arc::try_unwrap(shard.database) {
+                Ok(database) => database.into_inner().unwrap(),
+                Err(_) => unreachable!("the worker has stopped"),
+            };
+            databases.push(database);
+        }
+
+        let mut databases = databases.into_iter();
+        let mut database = databases.next().unwrap();
+        for shard in databases {
+            database.join(shard);
+        }
+        database
+    }
+
+    fn shard_of(&self, client: ClientId) -> usize {
+        self.partitioner.shard(client, self.shards.len())
+    }
+
+    fn send(&self, shard: usize, message: Message) {
+        self.shards[shard]
+            .queue
+            .send(message)
+            .expect("shard worker stopped");
+    }
+
+    /// Waits until the shard has handled everything sent to it.
+    fn barrier(&self, shard: usize) {
+        let (reply, done) = mpsc::channel();
+        self.send(shard, Message::Barrier(reply));
+        done.recv().expect("shard worker stopped");
+    }
+
+    /// Applies a transfer to a client of another shard. The recipient is moved into the sender's
+    /// shard for the transfer, so it's checked and credited exactly as without sharding.
+    fn transfer(
+        &self,
+        sender: usize,
+        recipient: usize,
+        transaction: Transaction,
+    ) -> Result<(), TransactionError> {
+        let to = match transaction.transaction_type {
+            TransactionType::Transfer { to, .. } => to,
+            _ => unreachable!("only transfers have a recipient"),
+        };
+
+        self.barrier(sender);
+        self.barrier(recipient);
+        let mut from = self.shards[sender].database.lock().unwrap();
+        let mut into = self.shards[recipient].database.lock().unwrap();
+
+        if let Some(account) = into.remove_account(to) {
+            from.insert_account(account);
+        }
+        let result = from.apply_transaction(transaction);
+        if let Some(account) = from.remove_account(to) {
+            into.insert_account(account);
+        }
+
+        result
+    }
+}
+
+/// Applies the messages sent to a shard until the pool is dropped.
+fn work<Account>(
+    database: &Mutex<Database<Account>>,
+    messages: Receiver<Message>,
+    results: Sender<(u64, Outcome)>,
+) where
+    Account: ClientAccount,
+{
+    for message in messages {
+        match message {
+            Message::Apply {
+                sequence,
+                transaction,
+                line,
+            } => {
+                let (result, fees) = {
+                    let mut database = database.lock().unwrap();
+                    let result = database.apply_transaction(transaction);
+                    (result, database.take_charged_fees())
+                };
+                let outcome = Outcome {
+                    transaction,
+                    line,
+                    result,
+                    fees,
+                };
+                // The pool only goes away while stopping the workers.
+                let _result = results.send((sequence, outcome));
+            }
+            Message::NoteOwner {
+                transaction_id,
+                owner,
+            } => database.lock().unwrap().note_owner(transaction_id, owner),
+            Message::Barrier(reply) => {
+                let _result = reply.send(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::Amount, client::Client, database::DuplicatePolicy};
+
+    /// Transactions of many clients, with transfers between them, ids reused by other clients and
+    /// disputes of other clients' transactions.
+    fn transactions() -> Vec<Transaction> {
+        let mut seed: u64 = 42;
+        let mut next = move |bound: u64| {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 33) % bound
+        };
+
+        (0..2000)
+            .map(|i| {
+                let client = next(40) as ClientId + 1;
+                let transaction_type = match next(10) {
+                    0..=3 => TransactionType::Deposit(Amount::new(next(50_000) as i64)),
+                    4 | 5 => TransactionType::Withdrawal(Amount::new(next(50_000) as i64)),
+                    6 => TransactionType::Dispute { case: None },
+                    7 => TransactionType::Resolve,
+                    8 => TransactionType::Chargeback { case: None },
+                    _ => TransactionType::Transfer {
+                        to: next(40) as ClientId + 1,
+                        amount: Amount::new(next(20_000) as i64),
+                    },
+                };
+                // Disputes refer to earlier ids, and some new transactions reuse one.
+                let id = match transaction_type {
+                    TransactionType::Dispute { .. }
+                    | TransactionType::Resolve
+                    | TransactionType::Chargeback { .. } => next(i + 1) as TransactionId,
+                    _ if next(20) == 0 => next(i + 1) as TransactionId,
+                    _ => i as TransactionId,
+                };
+                Transaction {
+                    transaction_type,
+                    client,
+                    id,
+                    timestamp: None,
+                    currency: None,
+                }
+            })
+            .collect()
+    }
+
+    fn sharded_matches_serial(policy: DuplicatePolicy, partitioning: Partitioning) {
+        let transactions = transactions();
+
+        let mut serial = Database::new().with_duplicate_policy(policy);
+        let expected: Vec<Outcome> = transactions
+            .iter()
+            .map(|transaction| Outcome {
+                transaction: *transaction,
+                line: None,
+                result: serial.apply_transaction(*transaction),
+                fees: vec![],
+            })
+            .collect();
+
+        let mut pool = ShardPool::start(
+            Database::new().with_duplicate_policy(policy),
+            4,
+            Arc::new(partitioning),
+        );
+        let mut actual = vec![];
+        for transaction in transactions {
+            pool.submit(transaction, None);
+            actual.extend(std::iter::from_fn(|| pool.next_outcome()));
+        }
+        actual.extend(pool.wait());
+        let sharded = pool.join();
+
+        assert_eq!(expected, actual);
+        assert_eq!(serial.snapshot(), sharded.snapshot());
+
+        let mut expected_duplicates = serial.duplicates().to_vec();
+        let mut actual_duplicates = sharded.duplicates().to_vec();
+        expected_duplicates.sort_unstable();
+        actual_duplicates.sort_unstable();
+        assert_eq!(expected_duplicates, actual_duplicates);
+    }
+
+    #[test]
+    fn shard_pool_matches_serial_database() {
+        sharded_matches_serial(DuplicatePolicy::Reject, Partitioning::Hash);
+        sharded_matches_serial(DuplicatePolicy::Warn, Partitioning::Hash);
+        sharded_matches_serial(
+            DuplicatePolicy::Reject,
+            Partitioning::Kafka(KafkaKey::String),
+        );
+    }
+
+    #[test]
+    fn partitioning_kafka_matches_kafka_default_partitioner() {
+        // Hashes from the tests of Kafka's own implementation.
+        assert_eq!(-973932308, murmur2(b"21") as i32);
+        assert_eq!(-790332482, murmur2(b"foobar") as i32);
+        assert_eq!(479470107, murmur2(b"abc") as i32);
+
+        let kafka = Partitioning::Kafka(KafkaKey::String);
+        assert_eq!(
+            (-973932308i32 & 0x7fff_ffff) as usize % 12,
+            kafka.shard(21, 12)
+        );
+        for client in 0..1000 {
+            assert_eq!(true, kafka.shard(client, 12) < 12);
+            assert_eq!(
+                true,
+                Partitioning::Kafka(KafkaKey::Int).shard(client, 5) < 5
+            );
+        }
+        assert_eq!(3, Partitioning::Modulo.shard(11, 4));
+        assert_eq!(Ok(kafka), Partitioning::from_str("kafka"));
+        assert_eq!(true, Partitioning::from_str("round-robin").is_err());
+    }
+
+    #[test]
+    fn shard_pool_join_keeps_restored_ids() {
+        let mut database = Database::<Client>::new();
+        for client in 1..=8 {
+            let deposit = Transaction {
+                transaction_type: TransactionType::Deposit(Amount::new(10000)),
+                client,
+                id: u32::from(client),
+                timestamp: None,
+                currency: None,
+            };
+            database.apply_transaction(deposit).unwrap();
+        }
+        let expected = database.snapshot();
+
+        let mut pool = ShardPool::start(database, 3, Arc::new(Partitioning::Modulo));
+        let reused = Transaction {
+            transaction_type: TransactionType::Deposit(Amount::new(10000)),
+            client: 2,
+            id: 1,
+            timestamp: None,
+            currency: None,
+        };
+        pool.submit(reused, Some(10));
+        let outcomes = pool.wait();
+        let database = pool.join();
+
+        let expected_outcome = Outcome {
+            transaction: reused,
+            line: Some(10),
+            result: Err(TransactionError::DuplicateTransactionId { transaction_id: 1 }),
+            fees: vec![],
+        };
+        assert_eq!(vec![expected_outcome], outcomes);
+        assert_eq!(expected, database.snapshot());
+        assert_eq!(Some(1), database.owner(1));
+    }
+}
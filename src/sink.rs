@@ -0,0 +1,818 @@
+use crate::{
+    amount::Amount,
+    client::{ClientAccount, ClientId},
+    parse_csv::CsvTransaction,
+    report::{Report, Rounding},
+    transaction::{Transaction, TransactionError, Warning},
+};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+
+// Sinks mirror sources: they decide where account summaries and transaction events end up, so
+// library users can plug in their own destinations without changing the engine.
+
+#[derive(Debug)]
+pub enum SinkError {
+    Io(io::Error),
+    Csv(csv::Error),
+    Json(serde_json::Error),
+    UnknownFormat { passed: String },
+}
+
+impl std::fmt::Display for SinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Csv(e) => write!(f, "{}", e),
+            Self::Json(e) => write!(f, "{}", e),
+            Self::UnknownFormat { passed } => write!(f, "unknown report format {}", passed),
+        }
+    }
+}
+
+impl Error for SinkError {}
+
+impl From<io::Error> for SinkError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<csv::Error> for SinkError {
+    fn from(e: csv::Error) -> Self {
+        Self::Csv(e)
+    }
+}
+
+impl From<serde_json::Error> for SinkError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+/// A destination for the account report.
+pub trait ReportSink<Account>
+where
+    Account: ClientAccount,
+{
+    /// Writes the report for each of the given accounts.
+    fn write_report(
+        &mut self,
+        report: &Report<Account>,
+        accounts: &mut dyn Iterator<Item = &Account>,
+    ) -> Result<(), SinkError>;
+}
+
+/// Writes the report as CSV, the format the engine has always output.
+pub struct CsvReportSink<W: Write> {
+    writer: W,
+    stamp: Option<String>,
+}
+
+impl<W: Write> CsvReportSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            stamp: None,
+        }
+    }
+
+    /// Writes the stamp as a `#` comment row before the header.
+    pub fn stamp(mut self, stamp: String) -> Self {
+        self.stamp = Some(stamp);
+        self
+    }
+}
+
+impl CsvReportSink<io::Stdout> {
+    /// Writes the report to stdout.
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write, Account: ClientAccount> ReportSink<Account> for CsvReportSink<W> {
+    fn write_report(
+        &mut self,
+        report: &Report<Account>,
+        accounts: &mut dyn Iterator<Item = &Account>,
+    ) -> Result<(), SinkError> {
+        if let Some(stamp) = &self.stamp {
+            writeln!(self.writer, "# {}", stamp)?;
+        }
+        writeln!(self.writer, "{}", report.header())?;
+
+        for account in accounts {
+            writeln!(self.writer, "{}", report.row(account))?;
+            for other in account.other_currencies() {
+                writeln!(self.writer, "{}", report.row(&other))?;
+            }
+        }
+
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes the report as a JSON array with one object per account, keyed by column name.
+pub struct JsonReportSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonReportSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl JsonReportSink<BufWriter<File>> {
+    /// Writes the report to the file at the given path, replacing its contents.
+    pub fn create(path: &str) -> Result<Self, SinkError> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write, Account: ClientAccount> ReportSink<Account> for JsonReportSink<W> {
+    fn write_report(
+        &mut self,
+        report: &Report<Account>,
+        accounts: &mut dyn Iterator<Item = &Account>,
+    ) -> Result<(), SinkError> {
+        let names = report.names();
+        let rows: Vec<JsonRow> = accounts
+            .flat_map(|account| {
+                std::iter::once(report.json_values(account)).chain(
+                    account
+                        .other_currencies()
+                        .into_iter()
+                        .map(|other| report.json_values(&other)),
+                )
+            })
+            .map(|values| JsonRow {
+                names: &names,
+                values,
+            })
+            .collect();
+
+        serde_json::to_writer(&mut self.writer, &rows)?;
+        writeln!(self.writer)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// A report row serialized as an object whose keys keep the order of the report's columns.
+struct JsonRow<'a> {
+    names: &'a [&'a str],
+    values: Vec<serde_json::Value>,
+}
+
+impl<'a> Serialize for JsonRow<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.names.len()))?;
+        for (name, value) in self.names.iter().zip(self.values.iter()) {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+/// Writes the report as JSON lines, one object per account keyed by column name.
+pub struct JsonLinesReportSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesReportSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write, Account: ClientAccount> ReportSink<Account> for JsonLinesReportSink<W> {
+    fn write_report(
+        &mut self,
+        report: &Report<Account>,
+        accounts: &mut dyn Iterator<Item = &Account>,
+    ) -> Result<(), SinkError> {
+        let names = report.names();
+
+        for account in accounts {
+            let others = account.other_currencies();
+            for account in std::iter::once(account).chain(others.iter()) {
+                let row = JsonRow {
+                    names: &names,
+                    values: report.json_values(account),
+                };
+                serde_json::to_writer(&mut self.writer, &row)?;
+                writeln!(self.writer)?;
+            }
+        }
+
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// The built-in report formats.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ReportFormat {
+    #[default]
+    Csv,
+    Json,
+    JsonLines,
+}
+
+impl ReportFormat {
+    /// A sink that writes this format to the writer.
+    pub fn sink<Account: ClientAccount>(
+        &self,
+        writer: Box<dyn Write>,
+    ) -> Box<dyn ReportSink<Account>> {
+        match self {
+            Self::Csv => Box::new(CsvReportSink::new(writer)),
+            Self::Json => Box::new(JsonReportSink::new(writer)),
+            Self::JsonLines => Box::new(JsonLinesReportSink::new(writer)),
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = SinkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "jsonl" => Ok(Self::JsonLines),
+            _ => Err(SinkError::UnknownFormat {
+                passed: String::from(s),
+            }),
+        }
+    }
+}
+
+/// Writes a report meant for people outside the business, such as external analysts, to another
+/// sink. Amounts can be rounded or bucketed, and accounts with a total below a threshold are left
+/// out so small balances can't be traced back to a client.
+pub struct SharedReportSink<Account>
+where
+    Account: ClientAccount,
+{
+    sink: Box<dyn ReportSink<Account>>,
+    rounding: Option<Rounding>,
+    suppress_below: Option<Amount>,
+}
+
+impl<Account: ClientAccount> SharedReportSink<Account> {
+    pub fn new(sink: Box<dyn ReportSink<Account>>) -> Self {
+        Self {
+            sink,
+            rounding: None,
+            suppress_below: None,
+        }
+    }
+
+    /// How amounts are coarsened.
+    pub fn rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = Some(rounding);
+        self
+    }
+
+    /// Leaves out accounts whose total is below the threshold.
+    pub fn suppress_below(mut self, threshold: Amount) -> Self {
+        self.suppress_below = Some(threshold);
+        self
+    }
+}
+
+impl<Account: ClientAccount + 'static> ReportSink<Account> for SharedReportSink<Account> {
+    fn write_report(
+        &mut self,
+        report: &Report<Account>,
+        accounts: &mut dyn Iterator<Item = &Account>,
+    ) -> Result<(), SinkError> {
+        let threshold = self.suppress_below;
+        let mut shared = accounts.filter(|account| match threshold {
+            Some(threshold) => account.total() >= threshold,
+            None => true,
+        });
+
+        match self.rounding {
+            Some(rounding) => self
+                .sink
+                .write_report(&report.rounded(rounding), &mut shared),
+            None => self.sink.write_report(report, &mut shared),
+        }
+    }
+}
+
+/// A destination for the outcome of every transaction the engine applies.
+pub trait EventSink {
+    /// Records that a transaction has been applied or rejected.
+    fn record(
+        &mut self,
+        transaction: &Transaction,
+        result: &Result<(), TransactionError>,
+    ) -> Result<(), SinkError>;
+
+    /// Records that an applied transaction was flagged.
+    fn record_warning(
+        &mut self,
+        _transaction: &Transaction,
+        _warning: &Warning,
+    ) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /// Called once all transactions have been recorded.
+    fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+/// A single transaction outcome as written by the event sinks.
+#[derive(Debug, PartialEq, serde::Serialize)]
+struct EventRecord {
+    #[serde(rename = "type")]
+    transaction_type: String,
+    client: String,
+    tx: u32,
+    amount: Option<String>,
+    reason: Option<u16>,
+    to: Option<ClientId>,
+    outcome: String,
+}
+
+impl EventRecord {
+    fn new(transaction: &Transaction, outcome: String) -> Self {
+        let record = CsvTransaction::from_transaction(transaction);
+
+        Self {
+            transaction_type: record.transaction_type,
+            client: record.client,
+            tx: record.tx,
+            amount: record.amount,
+            reason: record.reason,
+            to: record.to,
+            outcome,
+        }
+    }
+
+    fn from_result(transaction: &Transaction, result: &Result<(), TransactionError>) -> Self {
+        let outcome = match result {
+            Ok(_) => String::from("applied"),
+            Err(e) => format!("{:?}", e),
+        };
+        Self::new(transaction, outcome)
+    }
+
+    fn from_warning(transaction: &Transaction, warning: &Warning) -> Self {
+        Self::new(transaction, format!("warning: {:?}", warning))
+    }
+}
+
+/// Writes one CSV row per transaction outcome.
+pub struct CsvEventSink<W: Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: Write> CsvEventSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+        }
+    }
+}
+
+impl<W: Write> EventSink for CsvEventSink<W> {
+    fn record(
+        &mut self,
+        transaction: &Transaction,
+        result: &Result<(), TransactionError>,
+    ) -> Result<(), SinkError> {
+        self.writer
+            .serialize(EventRecord::from_result(transaction, result))?;
+        Ok(())
+    }
+
+    fn record_warning(
+        &mut self,
+        transaction: &Transaction,
+        warning: &Warning,
+    ) -> Result<(), SinkError> {
+        self.writer
+            .serialize(EventRecord::from_warning(transaction, warning))?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes one JSON object per line for every transaction outcome.
+pub struct JsonLinesEventSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesEventSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl JsonLinesEventSink<BufWriter<File>> {
+    /// Writes events to the file at the given path, replacing its contents.
+    pub fn create(path: &str) -> Result<Self, SinkError> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> EventSink for JsonLinesEventSink<W> {
+    fn record(
+        &mut self,
+        transaction: &Transaction,
+        result: &Result<(), TransactionError>,
+    ) -> Result<(), SinkError> {
+        serde_json::to_writer(
+            &mut self.writer,
+            &EventRecord::from_result(transaction, result),
+        )?;
+        writeln!(self.writer)?;
+        Ok(())
+    }
+
+    fn record_warning(
+        &mut self,
+        transaction: &Transaction,
+        warning: &Warning,
+    ) -> Result<(), SinkError> {
+        serde_json::to_writer(
+            &mut self.writer,
+            &EventRecord::from_warning(transaction, warning),
+        )?;
+        writeln!(self.writer)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// A destination for rejected transactions, kept so failures can be audited.
+pub trait ErrorSink {
+    /// Records a rejected transaction and the input line it was read from, if known.
+    fn record(
+        &mut self,
+        line: Option<u64>,
+        transaction: &Transaction,
+        error: &TransactionError,
+    ) -> Result<(), SinkError>;
+
+    /// Records an applied transaction that was flagged, so it shows up next to the rejections.
+    fn record_warning(
+        &mut self,
+        _line: Option<u64>,
+        _transaction: &Transaction,
+        _warning: &Warning,
+    ) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /// Records a skipped record of an unknown type.
+    fn record_unknown_type(
+        &mut self,
+        _line: u64,
+        _transaction_type: &str,
+    ) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /// Records a well formed record that was skipped rather than applied.
+    fn record_ignored(&mut self, _line: u64, _reason: &str) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /// Called once all transactions have been applied.
+    fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+/// Writes one line of text per rejected or flagged transaction, e.g.
+/// `line 4: client 1, tx 7: ClientLocked`.
+pub struct ErrorLogSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> ErrorLogSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl ErrorLogSink<io::Stderr> {
+    pub fn stderr() -> Self {
+        Self::new(io::stderr())
+    }
+}
+
+impl ErrorLogSink<BufWriter<File>> {
+    /// Writes errors to the file at the given path, replacing its contents.
+    pub fn create(path: &str) -> Result<Self, SinkError> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Writes errors to the end of the file at the given path, creating it if needed.
+    pub fn append(path: &str) -> Result<Self, SinkError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+}
+
+impl<W: Write> ErrorSink for ErrorLogSink<W> {
+    fn record(
+        &mut self,
+        line: Option<u64>,
+        transaction: &Transaction,
+        error: &TransactionError,
+    ) -> Result<(), SinkError> {
+        // Transactions replayed from the approval queue or applied after coalescing have no line.
+        if let Some(line) = line {
+            write!(self.writer, "line {}: ", line)?;
+        }
+        writeln!(
+            self.writer,
+            "client {}, tx {}: {:?}",
+            transaction.client, transaction.id, error
+        )?;
+        Ok(())
+    }
+
+    fn record_warning(
+        &mut self,
+        line: Option<u64>,
+        transaction: &Transaction,
+        warning: &Warning,
+    ) -> Result<(), SinkError> {
+        if let Some(line) = line {
+            write!(self.writer, "line {}: ", line)?;
+        }
+        writeln!(
+            self.writer,
+            "client {}, tx {}: warning: {:?}",
+            transaction.client, transaction.id, warning
+        )?;
+        Ok(())
+    }
+
+    fn record_unknown_type(&mut self, line: u64, transaction_type: &str) -> Result<(), SinkError> {
+        writeln!(
+            self.writer,
+            "line {}: warning: skipped unknown transaction type {:?}",
+            line, transaction_type
+        )?;
+        Ok(())
+    }
+
+    fn record_ignored(&mut self, line: u64, reason: &str) -> Result<(), SinkError> {
+        writeln!(self.writer, "line {}: ignored: {}", line, reason)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{amount, places};
+    use crate::{amount::RoundingMode, client::Client, transaction::TransactionType};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Collects everything written to it so tests can inspect the output.
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn deposit() -> Transaction {
+        Transaction {
+            transaction_type: TransactionType::Deposit(amount("2")),
+            client: 4,
+            id: 9,
+            timestamp: None,
+            currency: None,
+        }
+    }
+
+    #[test]
+    fn csv_report_sink_write_report_returns_expected() {
+        let mut output = vec![];
+        let account = Client::new(4);
+
+        CsvReportSink::new(&mut output)
+            .write_report(&Report::standard(), &mut vec![&account].into_iter())
+            .unwrap();
+
+        assert_eq!(
+            places("client, available, held, total, locked\n4, 0.0000, 0.0000, 0.0000, false\n"),
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn csv_report_sink_stamp_writes_comment_before_header() {
+        let mut output = vec![];
+        let account = Client::new(4);
+
+        CsvReportSink::new(&mut output)
+            .stamp(String::from("engine_version=0.1.0"))
+            .write_report(&Report::standard(), &mut vec![&account].into_iter())
+            .unwrap();
+
+        assert_eq!(
+            places("# engine_version=0.1.0\nclient, available, held, total, locked\n4, 0.0000, 0.0000, 0.0000, false\n"),
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn csv_report_sink_write_report_writes_row_per_currency() {
+        let mut output = vec![];
+        let mut account = Client::new(4);
+        let mut transaction = deposit();
+        transaction.currency = "EUR".parse().ok();
+        account.execute_transaction(transaction).unwrap();
+        transaction.id = 10;
+        transaction.currency = "USD".parse().ok();
+        account.execute_transaction(transaction).unwrap();
+        let report = Report::extended()
+            .select(&[
+                String::from("client"),
+                String::from("currency"),
+                String::from("available"),
+            ])
+            .unwrap();
+
+        CsvReportSink::new(&mut output)
+            .write_report(&report, &mut vec![&account].into_iter())
+            .unwrap();
+
+        assert_eq!(
+            places("client, currency, available\n4, EUR, 2.0000\n4, USD, 2.0000\n"),
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn json_report_sink_write_report_keeps_column_order() {
+        let mut output = vec![];
+        let account = Client::new(4);
+        let report = Report::standard()
+            .select(&[String::from("total"), String::from("client")])
+            .unwrap();
+
+        JsonReportSink::new(&mut output)
+            .write_report(&report, &mut vec![&account].into_iter())
+            .unwrap();
+
+        assert_eq!(
+            places("[{\"total\":\"0.0000\",\"client\":4}]\n"),
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn json_lines_report_sink_write_report_returns_expected() {
+        let mut output = vec![];
+        let first = Client::new(4);
+        let second = Client::new(5);
+
+        JsonLinesReportSink::new(&mut output)
+            .write_report(&Report::standard(), &mut vec![&first, &second].into_iter())
+            .unwrap();
+
+        assert_eq!(
+            places("{\"client\":4,\"available\":\"0.0000\",\"held\":\"0.0000\",\"total\":\"0.0000\",\"locked\":false}\n{\"client\":5,\"available\":\"0.0000\",\"held\":\"0.0000\",\"total\":\"0.0000\",\"locked\":false}\n"),
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn shared_report_sink_suppresses_and_rounds() {
+        let output = SharedOutput::default();
+        let mut large = Client::new(4);
+        large
+            .execute_transaction(Transaction {
+                transaction_type: TransactionType::Deposit(amount("126")),
+                client: 4,
+                id: 1,
+                timestamp: None,
+                currency: None,
+            })
+            .unwrap();
+        let mut small = Client::new(5);
+        small
+            .execute_transaction(Transaction {
+                transaction_type: TransactionType::Deposit(amount("4")),
+                client: 5,
+                id: 2,
+                timestamp: None,
+                currency: None,
+            })
+            .unwrap();
+
+        let inner = Box::new(CsvReportSink::new(output.clone()));
+        SharedReportSink::new(inner)
+            .rounding(Rounding::Nearest(amount("100"), RoundingMode::HalfUp))
+            .suppress_below(amount("5"))
+            .write_report(&Report::standard(), &mut vec![&large, &small].into_iter())
+            .unwrap();
+
+        assert_eq!(
+            places(
+                "client, available, held, total, locked\n4, 100.0000, 0.0000, 100.0000, false\n"
+            ),
+            String::from_utf8(output.0.borrow().clone()).unwrap()
+        );
+    }
+
+    #[test]
+    fn report_format_from_str_returns_expected() {
+        assert_eq!(ReportFormat::Csv, ReportFormat::from_str("csv").unwrap());
+        assert_eq!(ReportFormat::Json, ReportFormat::from_str("json").unwrap());
+        assert_eq!(
+            ReportFormat::JsonLines,
+            ReportFormat::from_str("jsonl").unwrap()
+        );
+        assert_eq!(true, ReportFormat::from_str("xml").is_err());
+    }
+
+    #[test]
+    fn csv_event_sink_record_returns_expected() {
+        let mut output = vec![];
+        {
+            let mut sink = CsvEventSink::new(&mut output);
+            sink.record(&deposit(), &Ok(())).unwrap();
+            sink.record(&deposit(), &Err(TransactionError::ClientLocked))
+                .unwrap();
+            sink.flush().unwrap();
+        }
+
+        assert_eq!(
+            places("type,client,tx,amount,reason,to,outcome\ndeposit,4,9,2.0000,,,applied\ndeposit,4,9,2.0000,,,ClientLocked\n"),
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn json_lines_event_sink_record_returns_expected() {
+        let mut output = vec![];
+        {
+            let mut sink = JsonLinesEventSink::new(&mut output);
+            sink.record(&deposit(), &Ok(())).unwrap();
+            sink.flush().unwrap();
+        }
+
+        assert_eq!(
+            places("{\"type\":\"deposit\",\"client\":\"4\",\"tx\":9,\"amount\":\"2.0000\",\"reason\":null,\"to\":null,\"outcome\":\"applied\"}\n"),
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn error_log_sink_record_returns_expected() {
+        let mut output = vec![];
+        {
+            let mut sink = ErrorLogSink::new(&mut output);
+            sink.record(Some(3), &deposit(), &TransactionError::ClientLocked)
+                .unwrap();
+            sink.record(None, &deposit(), &TransactionError::ClientLocked)
+                .unwrap();
+        }
+
+        assert_eq!(
+            "line 3: client 4, tx 9: ClientLocked\nclient 4, tx 9: ClientLocked\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
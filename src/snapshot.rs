@@ -0,0 +1,331 @@
+use crate::{
+    client::{ClientId, Tombstone},
+    clock::Timestamp,
+    currency::Currency,
+    manifest,
+    parse_csv::CsvTransaction,
+    transaction::{CaseId, ReasonCode, TransactionId, TransactionState},
+};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::str::FromStr;
+
+// Snapshots capture the end state of a run. Amounts are stored as decimal strings so every
+// encoding keeps the exact value regardless of how it represents numbers. Every snapshot is
+// stamped with the engine version that wrote it, and one written by an incompatible version is
+// refused unless loading is forced, as it may have been produced under different rules.
+
+/// The persisted state of every client the database has seen.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Snapshot {
+    /// The version of the engine that wrote the snapshot.
+    #[serde(default)]
+    pub engine_version: String,
+    /// The hash of the configuration of the run that wrote the snapshot, if it came from one.
+    #[serde(default)]
+    pub config_hash: Option<String>,
+    pub clients: Vec<ClientSnapshot>,
+}
+
+impl Snapshot {
+    /// Refuses a snapshot written by an incompatible engine version unless `force_migrate` is set,
+    /// in which case it's read as if the current version wrote it.
+    pub fn check_version(&self, force_migrate: bool) -> Result<(), SnapshotError> {
+        if force_migrate || manifest::is_compatible(&self.engine_version) {
+            return Ok(());
+        }
+
+        Err(SnapshotError::IncompatibleVersion {
+            found: self.engine_version.clone(),
+        })
+    }
+}
+
+/// The persisted state of a single client.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ClientSnapshot {
+    pub id: ClientId,
+    pub available: String,
+    pub held: String,
+    pub locked: bool,
+    pub transactions: Vec<(TransactionState, CsvTransaction)>,
+    pub risk_holds: Vec<(ReasonCode, String)>,
+    pub lifetime_deposits: String,
+    pub lifetime_withdrawals: String,
+    pub dispute_count: u32,
+    pub chargeback_count: u32,
+    pub tombstone: Option<Tombstone>,
+    /// The case each disputed or charged back transaction belongs to, by id.
+    #[serde(default)]
+    pub cases: Vec<(TransactionId, CaseId)>,
+    /// The latest timestamp of an accepted transaction.
+    #[serde(default)]
+    pub last_activity: Option<Timestamp>,
+    /// The currency the account is kept in.
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    /// The balances the client holds in other currencies.
+    #[serde(default)]
+    pub balances: Vec<BalanceSnapshot>,
+    /// The sum of fees charged, missing from snapshots taken before fees were.
+    #[serde(default)]
+    pub lifetime_fees: Option<String>,
+}
+
+/// The persisted funds and counters of a client in one of its other currencies.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct BalanceSnapshot {
+    pub currency: Currency,
+    pub available: String,
+    pub held: String,
+    pub risk_holds: Vec<(ReasonCode, String)>,
+    pub lifetime_deposits: String,
+    pub lifetime_withdrawals: String,
+    pub dispute_count: u32,
+    pub chargeback_count: u32,
+    #[serde(default)]
+    pub last_activity: Option<Timestamp>,
+    #[serde(default)]
+    pub lifetime_fees: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    Codec(Box<dyn Error + Send + Sync>),
+    InvalidAmount {
+        passed: String,
+    },
+    InvalidTransaction {
+        tx: u32,
+    },
+    UnknownFormat {
+        passed: String,
+    },
+    /// The snapshot was written by an engine version this one can't safely read.
+    IncompatibleVersion {
+        found: String,
+    },
+}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Encodes and decodes snapshots in a particular format.
+pub trait SnapshotCodec {
+    /// Writes the snapshot to the writer.
+    fn encode(&self, snapshot: &Snapshot, writer: &mut dyn Write) -> Result<(), SnapshotError>;
+
+    /// Reads a snapshot from the reader.
+    fn decode(&self, reader: &mut dyn Read) -> Result<Snapshot, SnapshotError>;
+}
+
+/// Compact binary snapshots, the default for production.
+pub struct BincodeCodec;
+
+impl SnapshotCodec for BincodeCodec {
+    fn encode(&self, snapshot: &Snapshot, writer: &mut dyn Write) -> Result<(), SnapshotError> {
+        bincode::serialize_into(writer, snapshot).map_err(|e| SnapshotError::Codec(e))
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> Result<Snapshot, SnapshotError> {
+        bincode::deserialize_from(reader).map_err(|e| SnapshotError::Codec(e))
+    }
+}
+
+/// Human readable snapshots for debugging.
+pub struct JsonCodec;
+
+impl SnapshotCodec for JsonCodec {
+    fn encode(&self, snapshot: &Snapshot, writer: &mut dyn Write) -> Result<(), SnapshotError> {
+        serde_json::to_writer_pretty(writer, snapshot).map_err(|e| SnapshotError::Codec(e.into()))
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> Result<Snapshot, SnapshotError> {
+        serde_json::from_reader(reader).map_err(|e| SnapshotError::Codec(e.into()))
+    }
+}
+
+/// Compact snapshots in a self describing binary format.
+pub struct CborCodec;
+
+impl SnapshotCodec for CborCodec {
+    fn encode(&self, snapshot: &Snapshot, writer: &mut dyn Write) -> Result<(), SnapshotError> {
+        ciborium::into_writer(snapshot, writer).map_err(|e| SnapshotError::Codec(e.into()))
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> Result<Snapshot, SnapshotError> {
+        ciborium::from_reader(reader).map_err(|e| SnapshotError::Codec(e.into()))
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum SnapshotFormat {
+    #[default]
+    Bincode,
+    Json,
+    Cbor,
+}
+
+impl SnapshotFormat {
+    /// The codec that reads and writes this format.
+    pub fn codec(&self) -> Box<dyn SnapshotCodec> {
+        match self {
+            Self::Bincode => Box::new(BincodeCodec),
+            Self::Json => Box::new(JsonCodec),
+            Self::Cbor => Box::new(CborCodec),
+        }
+    }
+}
+
+impl FromStr for SnapshotFormat {
+    type Err = SnapshotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bincode" => Ok(Self::Bincode),
+            "json" => Ok(Self::Json),
+            "cbor" => Ok(Self::Cbor),
+            _ => Err(SnapshotError::UnknownFormat {
+                passed: String::from(s),
+            }),
+        }
+    }
+}
+
+/// Reads a snapshot in the given format from a file, refusing one from an incompatible engine
+/// version unless `force_migrate` is set.
+pub fn read_from_path(
+    path: &str,
+    format: SnapshotFormat,
+    force_migrate: bool,
+) -> Result<Snapshot, SnapshotError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let snapshot = format.codec().decode(&mut reader)?;
+    snapshot.check_version(force_migrate)?;
+    Ok(snapshot)
+}
+
+/// Writes a snapshot in the given format to a file, replacing its contents.
+pub fn write_to_path(
+    snapshot: &Snapshot,
+    path: &str,
+    format: SnapshotFormat,
+) -> Result<(), SnapshotError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    format.codec().encode(snapshot, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            engine_version: String::from(manifest::engine_version()),
+            config_hash: Some(String::from("0123456789abcdef")),
+            clients: vec![ClientSnapshot {
+                id: 7,
+                available: String::from("1.5000"),
+                held: String::from("2.0000"),
+                locked: true,
+                transactions: vec![(
+                    TransactionState::Disputed,
+                    CsvTransaction {
+                        transaction_type: String::from("deposit"),
+                        client: String::from("7"),
+                        tx: 3,
+                        amount: Some(String::from("2.0000")),
+                        reason: None,
+                        to: None,
+                        case: None,
+                        timestamp: Some(1_700_000_000),
+                        currency: "EUR".parse().ok(),
+                        to_currency: None,
+                        converted: None,
+                    },
+                )],
+                risk_holds: vec![(4, String::from("0.5000"))],
+                lifetime_deposits: String::from("2.0000"),
+                lifetime_withdrawals: String::from("0.0000"),
+                dispute_count: 1,
+                chargeback_count: 0,
+                cases: vec![(3, 9001)],
+                tombstone: Some(Tombstone {
+                    erased_transactions: 3,
+                }),
+                last_activity: Some(1_700_000_000),
+                currency: "EUR".parse().ok(),
+                balances: vec![BalanceSnapshot {
+                    currency: "USD".parse().unwrap(),
+                    available: String::from("3.0000"),
+                    held: String::from("0.0000"),
+                    risk_holds: vec![],
+                    lifetime_deposits: String::from("3.0000"),
+                    lifetime_withdrawals: String::from("0.0000"),
+                    dispute_count: 0,
+                    chargeback_count: 0,
+                    last_activity: None,
+                    lifetime_fees: None,
+                }],
+                lifetime_fees: Some(String::from("0.2500")),
+            }],
+        }
+    }
+
+    #[test]
+    fn snapshot_codecs_round_trip() {
+        let formats = vec![
+            SnapshotFormat::Bincode,
+            SnapshotFormat::Json,
+            SnapshotFormat::Cbor,
+        ];
+
+        for format in formats {
+            let codec = format.codec();
+            let mut bytes = vec![];
+            codec.encode(&snapshot(), &mut bytes).unwrap();
+
+            let actual = codec.decode(&mut bytes.as_slice()).unwrap();
+            assert_eq!(snapshot(), actual);
+        }
+    }
+
+    #[test]
+    fn snapshot_check_version_refuses_incompatible_unless_forced() {
+        let old = Snapshot {
+            engine_version: String::from("0.0.3"),
+            ..snapshot()
+        };
+
+        assert_eq!(true, snapshot().check_version(false).is_ok());
+        assert_eq!(true, old.check_version(true).is_ok());
+        match old.check_version(false) {
+            Err(SnapshotError::IncompatibleVersion { found }) => assert_eq!("0.0.3", found),
+            _ => panic!("expected an incompatible version"),
+        }
+    }
+
+    #[test]
+    fn snapshot_format_from_str_returns_expected() {
+        assert_eq!(
+            SnapshotFormat::Bincode,
+            SnapshotFormat::from_str("bincode").unwrap()
+        );
+        assert_eq!(
+            SnapshotFormat::Json,
+            SnapshotFormat::from_str("json").unwrap()
+        );
+        assert_eq!(
+            SnapshotFormat::Cbor,
+            SnapshotFormat::from_str("cbor").unwrap()
+        );
+        assert_eq!(true, SnapshotFormat::from_str("yaml").is_err());
+    }
+}
@@ -0,0 +1,289 @@
+use crate::{parse_csv, transaction::Transaction};
+use std::error::Error;
+use std::io::{self, BufRead};
+
+// Sources produce transactions one at a time so the engine never needs the whole input in memory,
+// and so proprietary inputs can be plugged in without changing the parsers.
+
+#[derive(Debug)]
+pub enum SourceError {
+    Io(io::Error),
+    Csv(csv::Error),
+    InvalidRecord {
+        line: u64,
+        error: Box<dyn Error>,
+    },
+    /// A well formed record was skipped rather than applied, such as a deposit without an amount.
+    /// The source can still be read after it.
+    Ignored {
+        line: u64,
+        reason: &'static str,
+    },
+    /// A record of an unknown type was skipped under `UnknownTypePolicy::Warn`.
+    UnknownType {
+        line: u64,
+        transaction_type: String,
+    },
+    UnknownFormat {
+        passed: String,
+    },
+    /// A followed file has no complete record to read yet. More may be appended.
+    Waiting,
+}
+
+impl std::fmt::Display for SourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Csv(e) => write!(f, "{}", e),
+            Self::InvalidRecord { line, error } => write!(f, "line {}: {}", line, error),
+            Self::Ignored { line, reason } => {
+                write!(f, "line {}: ignored record: {}", line, reason)
+            }
+            Self::UnknownType {
+                line,
+                transaction_type,
+            } => write!(
+                f,
+                "line {}: skipped unknown transaction type {}",
+                line, transaction_type
+            ),
+            Self::UnknownFormat { passed } => write!(f, "unknown input format {}", passed),
+            Self::Waiting => write!(f, "waiting for more input"),
+        }
+    }
+}
+
+impl Error for SourceError {}
+
+impl SourceError {
+    /// An input that can't be decoded, such as a corrupt binary file.
+    pub fn invalid_data<E: Into<Box<dyn Error + Send + Sync>>>(error: E) -> Self {
+        Self::Io(io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+impl From<io::Error> for SourceError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<csv::Error> for SourceError {
+    fn from(e: csv::Error) -> Self {
+        Self::Csv(e)
+    }
+}
+
+/// A point in an input file, just after a record.
+#[derive(Copy, Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct InputPosition {
+    /// Bytes read from the start of the file.
+    pub byte: u64,
+    /// Lines read from the start of the file.
+    pub line: u64,
+}
+
+/// A stream of transactions. Records that are ignored by the parser are returned as
+/// `SourceError::Ignored` so they can be counted, and reading continues after them.
+pub trait TransactionSource {
+    /// The next transaction, or `None` once the source is exhausted.
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>>;
+
+    /// The input line of the transaction last returned, for sources that read lines.
+    fn line(&self) -> Option<u64> {
+        None
+    }
+
+    /// Where the input continues after the transaction last returned, for sources that read files
+    /// and can be resumed.
+    fn position(&self) -> Option<InputPosition> {
+        None
+    }
+}
+
+/// Reads headerless CSV records, one per line, such as those sent over a TCP connection.
+pub struct LineSource<R: BufRead> {
+    reader: R,
+    line: u64,
+}
+
+impl<R: BufRead> LineSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, line: 0 }
+    }
+}
+
+impl<R: BufRead> TransactionSource for LineSource<R> {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        let mut buffer = String::new();
+
+        loop {
+            buffer.clear();
+            match self.reader.read_line(&mut buffer) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e.into())),
+            }
+            self.line += 1;
+
+            if buffer.trim().is_empty() {
+                continue;
+            }
+
+            match parse_csv::parse_record(&buffer) {
+                Ok(Some(transaction)) => return Some(Ok(transaction)),
+                Ok(None) => continue,
+                Err(error) => {
+                    return Some(Err(SourceError::InvalidRecord {
+                        line: self.line,
+                        error: Box::new(error),
+                    }))
+                }
+            }
+        }
+    }
+
+    fn line(&self) -> Option<u64> {
+        Some(self.line)
+    }
+}
+
+/// Serves transactions that are already in memory.
+pub struct IterSource<I: Iterator<Item = Transaction>> {
+    transactions: I,
+}
+
+impl<I: Iterator<Item = Transaction>> IterSource<I> {
+    pub fn new<T: IntoIterator<IntoIter = I>>(transactions: T) -> Self {
+        Self {
+            transactions: transactions.into_iter(),
+        }
+    }
+}
+
+impl<I: Iterator<Item = Transaction>> TransactionSource for IterSource<I> {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        self.transactions.next().map(Ok)
+    }
+}
+
+/// The transactions of a source, skipping ignored records. Any other error ends the input, so
+/// callers stop at the first one.
+pub fn transactions(
+    source: &mut dyn TransactionSource,
+) -> impl Iterator<Item = Result<Transaction, SourceError>> + '_ {
+    std::iter::from_fn(move || loop {
+        match source.next()? {
+            Err(SourceError::Ignored { .. }) => continue,
+            result => return Some(result),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::amount;
+    use crate::transaction::TransactionType;
+
+    #[test]
+    fn line_source_skips_blank_and_ignored_lines() {
+        let input = "deposit, 1, 1, 2.0\n\ndeposit, 1, 2,\nwithdrawal, 1, 3, 1.0\n";
+        let mut source = LineSource::new(input.as_bytes());
+
+        let first = source.next().unwrap().unwrap();
+        assert_eq!(
+            TransactionType::Deposit(amount("2")),
+            first.transaction_type
+        );
+
+        let second = source.next().unwrap().unwrap();
+        assert_eq!(3, second.id);
+
+        assert_eq!(true, source.next().is_none());
+    }
+
+    #[test]
+    fn transactions_skips_ignored_records() {
+        let input =
+            "type, client, tx, amount\ndeposit, 1, 1, 2.0\ndeposit, 1, 2,\nwithdrawal, 1, 3, 1.0\n";
+        let mut source = parse_csv::CsvSource::new(input.as_bytes());
+
+        let ids: Vec<u32> = transactions(&mut source)
+            .map(|transaction| transaction.unwrap().id)
+            .collect();
+
+        assert_eq!(vec![1, 3], ids);
+    }
+
+    #[test]
+    fn line_source_parses_transfer() {
+        let input = "transfer, 1, 1, 2.0, , 3\ntransfer, 1, 2, 2.0\n";
+        let mut source = LineSource::new(input.as_bytes());
+
+        let transfer = source.next().unwrap().unwrap();
+        assert_eq!(
+            TransactionType::Transfer {
+                to: 3,
+                amount: amount("2")
+            },
+            transfer.transaction_type
+        );
+
+        // A transfer without a recipient is ignored.
+        assert_eq!(true, source.next().is_none());
+    }
+
+    #[test]
+    fn line_source_parses_dispute_case() {
+        let input = "dispute, 1, 1, , , , 9001\nchargeback, 1, 1\n";
+        let mut source = LineSource::new(input.as_bytes());
+
+        assert_eq!(
+            TransactionType::Dispute { case: Some(9001) },
+            source.next().unwrap().unwrap().transaction_type
+        );
+        assert_eq!(
+            TransactionType::Chargeback { case: None },
+            source.next().unwrap().unwrap().transaction_type
+        );
+    }
+
+    #[test]
+    fn line_source_invalid_record_returns_line() {
+        let input = "deposit, 1, 1, 2.0\ndeposit, x, 2, 1.0\n";
+        let mut source = LineSource::new(input.as_bytes());
+
+        assert_eq!(true, source.next().unwrap().is_ok());
+        match source.next().unwrap() {
+            Err(SourceError::InvalidRecord { line, .. }) => assert_eq!(2, line),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn iter_source_returns_transactions_in_order() {
+        let transactions = vec![
+            Transaction {
+                transaction_type: TransactionType::Dispute { case: None },
+                client: 1,
+                id: 1,
+                timestamp: None,
+                currency: None,
+            },
+            Transaction {
+                transaction_type: TransactionType::Resolve,
+                client: 1,
+                id: 1,
+                timestamp: None,
+                currency: None,
+            },
+        ];
+        let mut source = IterSource::new(transactions.clone());
+
+        assert_eq!(transactions[0], source.next().unwrap().unwrap());
+        assert_eq!(transactions[1], source.next().unwrap().unwrap());
+        assert_eq!(true, source.next().is_none());
+    }
+}
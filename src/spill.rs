@@ -0,0 +1,167 @@
+use crate::{
+    client::ClientId,
+    parse_csv::CsvTransaction,
+    transaction::{Transaction, TransactionId, TransactionState},
+};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+// A client that is never disputed still keeps every deposit and withdrawal in memory, as any of
+// them may be disputed later. Once a client's history passes the cap, its oldest entries are moved
+// to a file and only their position is kept. A dispute, resolve or chargeback referring to one
+// moves it back into the history first, so results are the same as if it had never left.
+//
+// Each record is a 4 byte big-endian payload length and the payload: the state and the transaction
+// as a bincode encoded CSV record. The file only lives as long as the process, so it's truncated
+// when opened.
+
+#[derive(Debug)]
+pub enum SpillError {
+    Io(io::Error),
+    Encoding(bincode::Error),
+    /// A record could not be converted back into a transaction.
+    InvalidRecord {
+        offset: u64,
+    },
+}
+
+impl From<io::Error> for SpillError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<bincode::Error> for SpillError {
+    fn from(e: bincode::Error) -> Self {
+        Self::Encoding(e)
+    }
+}
+
+/// History entries moved out of memory, by client and id.
+pub struct HistorySpill {
+    file: File,
+    /// Where the record of every entry still in the file starts.
+    offsets: HashMap<(ClientId, TransactionId), u64>,
+    len: u64,
+}
+
+impl HistorySpill {
+    /// Creates an empty spill file at the path, replacing any left by a previous run.
+    pub fn create(path: &str) -> Result<Self, SpillError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        Ok(Self {
+            file,
+            offsets: HashMap::new(),
+            len: 0,
+        })
+    }
+
+    /// How many entries are in the file and not yet taken back.
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Appends the client's entries to the file.
+    pub fn write(
+        &mut self,
+        client: ClientId,
+        entries: &[(TransactionState, Transaction)],
+    ) -> Result<(), SpillError> {
+        let mut records = vec![];
+        let mut offsets = vec![];
+        for (state, transaction) in entries {
+            let payload =
+                bincode::serialize(&(state, CsvTransaction::from_transaction(transaction)))?;
+            offsets.push((transaction.id, self.len + records.len() as u64));
+            records.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            records.extend_from_slice(&payload);
+        }
+
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(&records)?;
+        self.len += records.len() as u64;
+
+        for (id, offset) in offsets {
+            self.offsets.insert((client, id), offset);
+        }
+        Ok(())
+    }
+
+    /// Removes the client's entry with the given id from the file, if it was spilled.
+    pub fn take(
+        &mut self,
+        client: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Option<(TransactionState, Transaction)>, SpillError> {
+        let offset = match self.offsets.remove(&(client, transaction_id)) {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut len = [0; 4];
+        self.file.read_exact(&mut len)?;
+        let mut payload = vec![0; u32::from_be_bytes(len) as usize];
+        self.file.read_exact(&mut payload)?;
+
+        let (state, record): (TransactionState, CsvTransaction) = bincode::deserialize(&payload)?;
+        match record.parse() {
+            Ok(transaction) => Ok(Some((state, transaction))),
+            Err(_) => Err(SpillError::InvalidRecord { offset }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::Amount, transaction::TransactionType};
+    use std::fs;
+
+    fn deposit(id: u32) -> Transaction {
+        Transaction {
+            transaction_type: TransactionType::Deposit(Amount::new(10000)),
+            client: 1,
+            id,
+            timestamp: None,
+            currency: None,
+        }
+    }
+
+    #[test]
+    fn history_spill_take_returns_written_entry_once() {
+        let path = std::env::temp_dir().join("payments_spill_take.bin");
+        let path = path.to_str().unwrap();
+
+        let mut spill = HistorySpill::create(path).unwrap();
+        let entries = vec![
+            (TransactionState::Ok, deposit(1)),
+            (TransactionState::Disputed, deposit(2)),
+        ];
+        spill.write(1, &entries).unwrap();
+        spill
+            .write(2, &[(TransactionState::Ok, deposit(3))])
+            .unwrap();
+
+        let taken = spill.take(1, 2).unwrap();
+        let missing = spill.take(2, 1).unwrap();
+        let again = spill.take(1, 2).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(Some((TransactionState::Disputed, deposit(2))), taken);
+        assert_eq!(None, missing);
+        assert_eq!(None, again);
+        assert_eq!(2, spill.len());
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{
+    client::ClientAccount, snapshot::SnapshotError, spill::SpillError, transaction::Transaction,
+    wal::WalError,
+};
+#[cfg(feature = "sled-storage")]
+use crate::{
+    client::{Client, ClientId},
+    parse_csv::CsvTransaction,
+    snapshot::ClientSnapshot,
+};
+
+// Accounts are kept in memory while transactions are applied. A storage backend persists every
+// change as it's made, so a long-running engine picks up where it left off after a restart.
+
+#[derive(Debug)]
+pub enum StorageError {
+    #[cfg(feature = "sled-storage")]
+    Sled(sled::Error),
+    Encoding(bincode::Error),
+    Snapshot(SnapshotError),
+    /// A transaction could not be written to the write-ahead log.
+    Wal(WalError),
+    /// History could not be moved to or from the spill file.
+    Spill(SpillError),
+    /// Storage was opened by a build without the `sled-storage` feature.
+    Unavailable,
+}
+
+#[cfg(feature = "sled-storage")]
+impl From<sled::Error> for StorageError {
+    fn from(e: sled::Error) -> Self {
+        Self::Sled(e)
+    }
+}
+
+impl From<bincode::Error> for StorageError {
+    fn from(e: bincode::Error) -> Self {
+        Self::Encoding(e)
+    }
+}
+
+impl From<WalError> for StorageError {
+    fn from(e: WalError) -> Self {
+        Self::Wal(e)
+    }
+}
+
+impl From<SpillError> for StorageError {
+    fn from(e: SpillError) -> Self {
+        Self::Spill(e)
+    }
+}
+
+impl From<SnapshotError> for StorageError {
+    fn from(e: SnapshotError) -> Self {
+        Self::Snapshot(e)
+    }
+}
+
+/// Persists accounts as transactions change them. Storage is shared with the database across
+/// server threads.
+pub trait Storage<Account>: Send + Sync
+where
+    Account: ClientAccount,
+{
+    /// Every saved account, loaded when the database is opened.
+    fn load(&mut self) -> Result<Vec<Account>, StorageError>;
+
+    /// Saves the changes the transaction made to the account.
+    fn save(&mut self, account: &Account, transaction: &Transaction) -> Result<(), StorageError>;
+
+    /// Makes every saved change durable.
+    fn flush(&mut self) -> Result<(), StorageError>;
+}
+
+/// An embedded sled database. Each client's balances are stored under `a` and its id, and each
+/// entry of its history under `t`, its id and the entry's position, so a transaction only
+/// rewrites what it changed.
+#[cfg(feature = "sled-storage")]
+pub struct SledStorage {
+    db: sled::Db,
+}
+
+#[cfg(feature = "sled-storage")]
+impl SledStorage {
+    /// Opens the database in the given directory, creating it if needed.
+    pub fn open(path: &str) -> Result<Self, StorageError> {
+        Ok(Self {
+            db: sled::open(path)?,
+        })
+    }
+
+    /// Uses a sled database that is already open.
+    pub fn from_db(db: sled::Db) -> Self {
+        Self { db }
+    }
+}
+
+#[cfg(feature = "sled-storage")]
+fn account_key(client: ClientId) -> Vec<u8> {
+    let mut key = vec![b'a'];
+    key.extend_from_slice(&client.to_be_bytes());
+    key
+}
+
+#[cfg(feature = "sled-storage")]
+fn history_key(client: ClientId, index: Option<usize>) -> Vec<u8> {
+    let mut key = vec![b't'];
+    key.extend_from_slice(&client.to_be_bytes());
+    if let Some(index) = index {
+        key.extend_from_slice(&(index as u64).to_be_bytes());
+    }
+    key
+}
+
+#[cfg(feature = "sled-storage")]
+impl Storage<Client> for SledStorage {
+    fn load(&mut self) -> Result<Vec<Client>, StorageError> {
+        let mut clients = vec![];
+
+        for account in self.db.scan_prefix(b"a") {
+            let (_, value) = account?;
+            let mut snapshot: ClientSnapshot = bincode::deserialize(&value)?;
+
+            // Keys sort by position, so the history comes back in order.
+            for entry in self.db.scan_prefix(history_key(snapshot.id, None)) {
+                let (_, value) = entry?;
+                snapshot.transactions.push(bincode::deserialize(&value)?);
+            }
+
+            clients.push(Client::from_snapshot(snapshot)?);
+        }
+
+        Ok(clients)
+    }
+
+    fn save(&mut self, account: &Client, transaction: &Transaction) -> Result<(), StorageError> {
+        let mut batch = sled::Batch::default();
+        batch.insert(
+            account_key(account.id()),
+            bincode::serialize(&account.to_snapshot_without_history())?,
+        );
+
+        // A new transaction is appended to the history, while disputes, resolves and chargebacks
+        // change the state of the transaction they refer to.
+        let history = account.history();
+        let changed = history
+            .len()
+            .checked_sub(1)
+            .into_iter()
+            .chain(account.history_index(transaction.id));
+        for index in changed {
+            let (state, transaction) = history[index];
+            let entry = (state, CsvTransaction::from_transaction(&transaction));
+            batch.insert(
+                history_key(account.id(), Some(index)),
+                bincode::serialize(&entry)?,
+            );
+        }
+
+        self.db.apply_batch(batch)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), StorageError> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "sled-storage"))]
+mod tests {
+    use super::*;
+    use crate::{
+        amount::Amount,
+        database::Database,
+        transaction::{TransactionState, TransactionType},
+    };
+
+    #[test]
+    fn sled_storage_reloads_balances_and_states() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+
+        let transaction = |transaction_type, id| Transaction {
+            transaction_type,
+            client: 1,
+            id,
+            timestamp: None,
+            currency: None,
+        };
+        let deposit = transaction(TransactionType::Deposit(Amount::new(20000)), 1);
+
+        {
+            let storage = SledStorage::from_db(db.clone());
+            let mut database = Database::with_storage(Box::new(storage)).unwrap();
+            database.apply_transaction(deposit).unwrap();
+            database
+                .apply_transaction(transaction(TransactionType::Deposit(Amount::new(5000)), 2))
+                .unwrap();
+            database
+                .apply_transaction(transaction(TransactionType::Dispute { case: None }, 1))
+                .unwrap();
+            database.flush_storage().unwrap();
+        }
+
+        let storage = SledStorage::from_db(db);
+        let mut database = Database::with_storage(Box::new(storage)).unwrap();
+        let client = database.account(1).unwrap();
+
+        assert_eq!(Amount::new(5000), client.available());
+        assert_eq!(Amount::new(20000), client.held());
+        assert_eq!((TransactionState::Disputed, deposit), client.history()[0]);
+        assert_eq!(true, database.apply_transaction(deposit).is_err());
+    }
+}
@@ -0,0 +1,369 @@
+use crate::{
+    amount::Amount,
+    client::{ClientAccount, ClientId},
+    database::Database,
+    random::Rng,
+    telemetry,
+    transaction::{Transaction, TransactionId, TransactionType},
+};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+// Load is generated in-process rather than read from a file, so the apply loop is measured without
+// parsing and a run needs no disk space. Generation is seeded, so the same settings and seed always
+// produce the same transactions. Once everything is applied the accounts are checked against the
+// money that went in and out, which catches accounting bugs that only show up under volume.
+
+/// How often progress is reported while the load is applied.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// One in this many settled disputes is charged back, the rest are resolved.
+const CHARGEBACK_ONE_IN: u64 = 10;
+
+/// The shape of the generated load.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StressConfig {
+    pub transactions: u32,
+    pub clients: ClientId,
+    /// The share of transactions that are disputes, resolves or chargebacks, in parts per million.
+    pub disputes_per_million: u32,
+}
+
+/// Generates the load, along with the amount each transaction moves. Disputes, resolves and
+/// chargebacks move the amount of the deposit they refer to.
+pub struct LoadGenerator {
+    config: StressConfig,
+    rng: Rng,
+    generated: u32,
+    next_id: TransactionId,
+    /// The latest undisputed deposit of each client.
+    deposits: Vec<Option<(TransactionId, Amount)>>,
+    /// Disputed deposits that haven't been resolved or charged back, oldest first.
+    open_disputes: VecDeque<(ClientId, TransactionId, Amount)>,
+}
+
+impl LoadGenerator {
+    pub fn new(config: StressConfig, rng: Rng) -> Self {
+        Self {
+            rng,
+            deposits: vec![None; usize::from(config.clients)],
+            config,
+            generated: 0,
+            next_id: 0,
+            open_disputes: VecDeque::new(),
+        }
+    }
+
+    fn new_transaction(&mut self, client: ClientId) -> (Transaction, Amount) {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        // Deposits outnumber withdrawals so most withdrawals have funds to draw on.
+        let amount = Amount::new(self.rng.below(10_000_000) as i64 + 1);
+        let transaction_type = if self.rng.below(10) < 7 {
+            self.deposits[usize::from(client)] = Some((id, amount));
+            TransactionType::Deposit(amount)
+        } else {
+            TransactionType::Withdrawal(amount)
+        };
+
+        let transaction = Transaction {
+            transaction_type,
+            client,
+            id,
+            timestamp: None,
+            currency: None,
+        };
+        (transaction, amount)
+    }
+
+    fn settle_dispute(&mut self) -> Option<(Transaction, Amount)> {
+        let (client, id, amount) = self.open_disputes.pop_front()?;
+        let transaction_type = if self.rng.below(CHARGEBACK_ONE_IN) == 0 {
+            TransactionType::Chargeback { case: None }
+        } else {
+            TransactionType::Resolve
+        };
+
+        let transaction = Transaction {
+            transaction_type,
+            client,
+            id,
+            timestamp: None,
+            currency: None,
+        };
+        Some((transaction, amount))
+    }
+
+    fn open_dispute(&mut self, client: ClientId) -> Option<(Transaction, Amount)> {
+        let (id, amount) = self.deposits[usize::from(client)].take()?;
+        self.open_disputes.push_back((client, id, amount));
+
+        let transaction = Transaction {
+            transaction_type: TransactionType::Dispute { case: None },
+            client,
+            id,
+            timestamp: None,
+            currency: None,
+        };
+        Some((transaction, amount))
+    }
+}
+
+impl Iterator for LoadGenerator {
+    type Item = (Transaction, Amount);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.generated == self.config.transactions {
+            return None;
+        }
+        self.generated += 1;
+
+        let client = self.rng.below(u64::from(self.config.clients)) as ClientId;
+        let disputing = self.rng.below(1_000_000) < u64::from(self.config.disputes_per_million);
+        if !disputing {
+            return Some(self.new_transaction(client));
+        }
+
+        let settled = if self.rng.below(2) == 0 {
+            self.settle_dispute()
+        } else {
+            None
+        };
+        let generated = settled
+            .or_else(|| self.open_dispute(client))
+            .or_else(|| self.settle_dispute());
+        match generated {
+            Some(generated) => Some(generated),
+            None => Some(self.new_transaction(client)),
+        }
+    }
+}
+
+/// A property the accounts must have once the load is applied, and whether they do.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Invariant {
+    pub name: &'static str,
+    pub holds: bool,
+}
+
+/// What a stress run applied, how fast, and whether the accounts add up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StressReport {
+    /// The seed the load was generated from, so the run can be repeated.
+    pub seed: u64,
+    pub transactions: u64,
+    pub applied: u64,
+    pub rejected: u64,
+    pub elapsed: Duration,
+    /// The peak resident set size of the process, where the platform reports it.
+    pub peak_resident_bytes: Option<u64>,
+    pub invariants: Vec<Invariant>,
+}
+
+impl StressReport {
+    pub fn records_per_sec(&self) -> f64 {
+        self.transactions as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// How many invariants don't hold.
+    pub fn failed(&self) -> usize {
+        self.invariants
+            .iter()
+            .filter(|invariant| !invariant.holds)
+            .count()
+    }
+
+    /// Writes the totals on one line of `name=value` metrics, then a line per invariant.
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write!(
+            writer,
+            "stress seed={} transactions={} applied={} rejected={} seconds={:.3} records_per_sec={:.0}",
+            self.seed,
+            self.transactions,
+            self.applied,
+            self.rejected,
+            self.elapsed.as_secs_f64(),
+            self.records_per_sec()
+        )?;
+        if let Some(peak_resident_bytes) = self.peak_resident_bytes {
+            write!(writer, " peak_resident_bytes={}", peak_resident_bytes)?;
+        }
+        writeln!(writer)?;
+
+        for invariant in self.invariants.iter() {
+            let outcome = if invariant.holds { "ok" } else { "FAILED" };
+            writeln!(writer, "{} {}", outcome, invariant.name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies the load generated from `rng` to the database, writing throughput to `progress` every
+/// second, then checks the accounts.
+pub fn run<Account>(
+    config: StressConfig,
+    rng: Rng,
+    database: &mut Database<Account>,
+    progress: &mut dyn Write,
+) -> io::Result<StressReport>
+where
+    Account: ClientAccount,
+{
+    let started = Instant::now();
+    let mut last_progress = started;
+    let mut report = StressReport {
+        seed: rng.seed(),
+        transactions: 0,
+        applied: 0,
+        rejected: 0,
+        elapsed: Duration::default(),
+        peak_resident_bytes: None,
+        invariants: vec![],
+    };
+    let mut charged_back = Amount::zero();
+    let mut chargebacks = 0;
+
+    for (transaction, amount) in LoadGenerator::new(config, rng) {
+        let is_chargeback = matches!(
+            transaction.transaction_type,
+            TransactionType::Chargeback { .. }
+        );
+        match database.apply_transaction(transaction) {
+            Ok(_) => {
+                report.applied += 1;
+                if is_chargeback {
+                    charged_back = charged_back + amount;
+                    chargebacks += 1;
+                }
+            }
+            Err(_) => report.rejected += 1,
+        }
+        report.transactions += 1;
+
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            last_progress = Instant::now();
+            let elapsed = started.elapsed().as_secs_f64();
+            write!(
+                progress,
+                "progress transactions={} records_per_sec={:.0}",
+                report.transactions,
+                report.transactions as f64 / elapsed
+            )?;
+            if let Some(resident_bytes) = telemetry::resident_bytes() {
+                write!(progress, " resident_bytes={}", resident_bytes)?;
+            }
+            writeln!(progress)?;
+        }
+    }
+
+    report.elapsed = started.elapsed();
+    report.peak_resident_bytes = telemetry::peak_resident_bytes();
+    report.invariants = check_invariants(database, charged_back, chargebacks);
+    Ok(report)
+}
+
+/// Checks the accounts against the money that went in and out.
+fn check_invariants<Account>(
+    database: &Database<Account>,
+    charged_back: Amount,
+    chargebacks: u32,
+) -> Vec<Invariant>
+where
+    Account: ClientAccount,
+{
+    let mut balances_add_up = true;
+    let mut held_not_negative = true;
+    let mut locked_after_chargeback = true;
+    let mut total = Amount::zero();
+    let mut flows = Amount::zero();
+    let mut counted_chargebacks = 0;
+
+    for account in database.accounts() {
+        let stats = account.stats();
+        balances_add_up &= account.available() + account.held() == account.total();
+        held_not_negative &= !account.held().less_than_zero();
+        locked_after_chargeback &= account.locked() == (stats.chargebacks > 0);
+        total = total + account.total();
+        flows = flows + stats.deposits - stats.withdrawals - stats.fees;
+        counted_chargebacks += stats.chargebacks;
+    }
+
+    vec![
+        Invariant {
+            name: "available plus held equals total",
+            holds: balances_add_up,
+        },
+        Invariant {
+            name: "held is never negative",
+            holds: held_not_negative,
+        },
+        Invariant {
+            name: "accounts are locked once charged back",
+            holds: locked_after_chargeback,
+        },
+        Invariant {
+            name: "totals equal deposits less withdrawals and chargebacks",
+            holds: total == flows - charged_back,
+        },
+        Invariant {
+            name: "every chargeback is counted",
+            holds: counted_chargebacks == chargebacks,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::Client, random::DEFAULT_SEED};
+
+    fn config() -> StressConfig {
+        StressConfig {
+            transactions: 20_000,
+            clients: 50,
+            disputes_per_million: 100_000,
+        }
+    }
+
+    fn generate() -> Vec<Transaction> {
+        LoadGenerator::new(config(), Rng::seeded(DEFAULT_SEED))
+            .map(|(t, _)| t)
+            .collect()
+    }
+
+    #[test]
+    fn load_generator_is_repeatable_and_mixes_types() {
+        let first = generate();
+        let second = generate();
+
+        assert_eq!(20_000, first.len());
+        assert_eq!(first, second);
+        for transaction_type in [
+            TransactionType::Dispute { case: None },
+            TransactionType::Resolve,
+            TransactionType::Chargeback { case: None },
+        ] {
+            assert_eq!(
+                true,
+                first.iter().any(|t| t.transaction_type == transaction_type)
+            );
+        }
+    }
+
+    #[test]
+    fn run_applies_load_and_invariants_hold() {
+        let mut database = Database::<Client>::new();
+        let mut progress = vec![];
+
+        let rng = Rng::seeded(DEFAULT_SEED);
+        let report = run(config(), rng, &mut database, &mut progress).unwrap();
+
+        assert_eq!(DEFAULT_SEED, report.seed);
+        assert_eq!(20_000, report.transactions);
+        assert_eq!(report.transactions, report.applied + report.rejected);
+        assert_eq!(5, report.invariants.len());
+        assert_eq!(0, report.failed());
+    }
+}
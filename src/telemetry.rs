@@ -0,0 +1,135 @@
+use crate::{client::ClientAccount, database::Database, scheduler::Periodic};
+use std::fs;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// How much a long-running database is holding on to.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize)]
+pub struct MemoryStats {
+    /// The resident set size of the process, where the platform reports it.
+    pub resident_bytes: Option<u64>,
+    pub accounts: usize,
+    /// Ids remembered to catch reuse.
+    pub transaction_ids: usize,
+    /// History entries kept in memory across every account.
+    pub history_entries: usize,
+    /// The most history entries kept by a single account.
+    pub largest_history: usize,
+    /// History entries dropped by compaction since the database was created.
+    pub compacted: u64,
+    /// History entries moved to the spill file.
+    pub spilled: usize,
+}
+
+impl MemoryStats {
+    /// Writes the stats as a single line of `name=value` metrics.
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write!(writer, "memory")?;
+        if let Some(resident_bytes) = self.resident_bytes {
+            write!(writer, " resident_bytes={}", resident_bytes)?;
+        }
+        writeln!(
+            writer,
+            " accounts={} transaction_ids={} history_entries={} largest_history={} compacted={} spilled={}",
+            self.accounts,
+            self.transaction_ids,
+            self.history_entries,
+            self.largest_history,
+            self.compacted,
+            self.spilled
+        )
+    }
+}
+
+/// The resident set size of the process. Only Linux reports it.
+pub fn resident_bytes() -> Option<u64> {
+    status_bytes("VmRSS:")
+}
+
+/// The largest the resident set size of the process has been. Only Linux reports it.
+pub fn peak_resident_bytes() -> Option<u64> {
+    status_bytes("VmHWM:")
+}
+
+/// Reads a size in kilobytes from the process status.
+fn status_bytes(field: &str) -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with(field))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// Writes the memory stats of a database at a fixed interval.
+pub struct Telemetry {
+    interval: Duration,
+    last: Instant,
+    writer: Box<dyn Write + Send>,
+}
+
+impl Telemetry {
+    pub fn new(interval: Duration, writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            interval,
+            last: Instant::now(),
+            writer,
+        }
+    }
+
+    /// How long until the next report is due.
+    pub fn until_due(&self) -> Duration {
+        self.interval.saturating_sub(self.last.elapsed())
+    }
+
+    /// Reports the database's stats if the interval has passed since the last report. Failing to
+    /// write is logged rather than stopping the database.
+    pub fn tick<Account>(&mut self, database: &Database<Account>)
+    where
+        Account: ClientAccount,
+    {
+        if self.until_due() > Duration::from_secs(0) {
+            return;
+        }
+        self.last = Instant::now();
+
+        if let Err(e) = database.memory_stats().write(&mut self.writer) {
+            eprintln!("Failed to report memory stats: {}", e);
+        }
+    }
+}
+
+impl<Account> Periodic<Account> for Telemetry
+where
+    Account: ClientAccount,
+{
+    fn until_due(&self) -> Duration {
+        Telemetry::until_due(self)
+    }
+
+    fn tick(&mut self, database: &Database<Account>) {
+        Telemetry::tick(self, database)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_stats_write_returns_expected() {
+        let stats = MemoryStats {
+            resident_bytes: Some(4096),
+            accounts: 2,
+            transaction_ids: 5,
+            history_entries: 7,
+            largest_history: 4,
+            compacted: 1,
+            spilled: 3,
+        };
+
+        let mut output = vec![];
+        stats.write(&mut output).unwrap();
+
+        let expected = "memory resident_bytes=4096 accounts=2 transaction_ids=5 history_entries=7 largest_history=4 compacted=1 spilled=3\n";
+        assert_eq!(expected, String::from_utf8(output).unwrap());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{
+    source::{InputPosition, SourceError, TransactionSource},
+    transaction::Transaction,
+};
+use std::convert::TryFrom;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Backfills replay months of history as fast as the input can be read, which can saturate a
+// storage cluster shared with live traffic. A rate limit paces the transactions read so they're
+// applied no faster than the limit on average, while letting a burst of them through at once after
+// a quiet spell, as a token bucket would. The bucket is kept as the time the next transaction is
+// due, so it's exact for any rate without counting fractional tokens.
+
+/// How fast transactions may be read.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    /// Transactions per second, on average.
+    pub per_second: u64,
+    /// How many transactions may be read at once, at least 1.
+    pub burst: u64,
+}
+
+impl RateLimit {
+    /// A limit allowing a second's worth of transactions at once.
+    pub fn new(per_second: u64) -> Self {
+        Self {
+            per_second,
+            burst: per_second,
+        }
+    }
+
+    pub fn burst(mut self, burst: u64) -> Self {
+        self.burst = burst;
+        self
+    }
+}
+
+/// Decides when each transaction may be read.
+#[derive(Debug)]
+pub struct TokenBucket {
+    /// The time between transactions at the limit.
+    interval: Duration,
+    /// How far ahead of its due time a transaction may be read, as part of a burst.
+    tolerance: Duration,
+    /// When the next transaction is due, had every earlier one been read at the limit.
+    due: Duration,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        let interval = Duration::from_nanos(1_000_000_000 / limit.per_second.max(1));
+        let burst = u32::try_from(limit.burst.max(1)).unwrap_or(u32::MAX);
+        Self {
+            interval,
+            tolerance: interval.saturating_mul(burst - 1),
+            due: Duration::ZERO,
+        }
+    }
+
+    /// How long to wait before reading a transaction, given the time since the bucket was made.
+    pub fn take(&mut self, now: Duration) -> Duration {
+        let earliest = self.due.saturating_sub(self.tolerance);
+        let read_at = now.max(earliest);
+        // Time spent idle below the limit is only saved up to the burst.
+        self.due = self.due.max(read_at) + self.interval;
+        read_at - now
+    }
+}
+
+/// Sleeps between the transactions of another source so they're read no faster than the limit.
+/// Errors aren't paced.
+pub struct ThrottleSource {
+    inner: Box<dyn TransactionSource>,
+    bucket: TokenBucket,
+    started: Instant,
+}
+
+impl ThrottleSource {
+    pub fn new(inner: Box<dyn TransactionSource>, limit: RateLimit) -> Self {
+        Self {
+            inner,
+            bucket: TokenBucket::new(limit),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl TransactionSource for ThrottleSource {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        let next = self.inner.next()?;
+        if next.is_ok() {
+            let wait = self.bucket.take(self.started.elapsed());
+            if !wait.is_zero() {
+                thread::sleep(wait);
+            }
+        }
+        Some(next)
+    }
+
+    fn line(&self) -> Option<u64> {
+        self.inner.line()
+    }
+
+    fn position(&self) -> Option<InputPosition> {
+        self.inner.position()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn token_bucket_take_allows_burst_then_paces() {
+        let mut bucket = TokenBucket::new(RateLimit::new(10).burst(3));
+
+        let waits: Vec<Duration> = (0..5).map(|_| bucket.take(Duration::ZERO)).collect();
+        assert_eq!(
+            vec![millis(0), millis(0), millis(0), millis(100), millis(200)],
+            waits
+        );
+
+        // After a quiet spell only the burst is saved up, not the whole spell.
+        let quiet = Duration::from_secs(60);
+        let waits: Vec<Duration> = (0..4).map(|_| bucket.take(quiet)).collect();
+        assert_eq!(vec![millis(0), millis(0), millis(0), millis(100)], waits);
+    }
+
+    #[test]
+    fn token_bucket_take_keeps_average_rate() {
+        let mut bucket = TokenBucket::new(RateLimit::new(1000).burst(1));
+
+        let mut now = Duration::ZERO;
+        for _ in 0..5000 {
+            now += bucket.take(now);
+        }
+        assert_eq!(Duration::from_millis(4999), now);
+    }
+}
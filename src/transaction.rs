@@ -1,17 +1,63 @@
-use crate::{amount::Amount, client::ClientId};
+use crate::{
+    amount::Amount,
+    client::ClientId,
+    clock::Timestamp,
+    currency::{Currency, Money},
+    velocity::VelocityLimit,
+};
 
 pub type TransactionId = u32;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+/// A code identifying why risk placed a hold on a client's funds.
+pub type ReasonCode = u16;
+
+/// A case number from the dispute management system.
+pub type CaseId = u64;
+
+#[derive(Copy, Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 pub enum TransactionType {
     Deposit(Amount),
     Withdrawal(Amount),
-    Dispute,
+    /// Holds the funds of an earlier deposit or withdrawal, under the given case if there is one.
+    Dispute {
+        case: Option<CaseId>,
+    },
     Resolve,
-    Chargeback,
+    /// Reverses a disputed transaction. A case given here replaces the dispute's.
+    Chargeback {
+        case: Option<CaseId>,
+    },
+    /// A manual risk hold that moves funds from available to held.
+    Hold {
+        amount: Amount,
+        reason: ReasonCode,
+    },
+    /// Releases funds previously held by risk for the same reason.
+    Release {
+        amount: Amount,
+        reason: ReasonCode,
+    },
+    /// Moves funds from the client's available balance to another client's.
+    Transfer {
+        to: ClientId,
+        amount: Amount,
+    },
+    /// Reinstates an account that was locked by a chargeback.
+    Unlock,
+    /// Moves the amount from the client's balance in the transaction's currency into its balance
+    /// in another. The amount credited is set from the loaded rates when it isn't given.
+    Convert {
+        amount: Amount,
+        to: Currency,
+        converted: Option<Amount>,
+    },
+    /// A fee charged from the available balance for the transaction with the same id.
+    Fee {
+        amount: Amount,
+    },
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 pub enum TransactionState {
     Ok,
     Disputed,
@@ -30,6 +76,23 @@ pub enum TransactionError {
     InvalidWithdrawal {
         resulting_amount: Amount,
     },
+    InvalidHold {
+        resulting_amount: Amount,
+    },
+    InvalidRelease {
+        reason: ReasonCode,
+        resulting_amount: Amount,
+    },
+    InvalidTransfer {
+        resulting_amount: Amount,
+    },
+    /// The client a transfer is sent to is locked.
+    RecipientLocked {
+        client: ClientId,
+    },
+    TransferToSelf,
+    /// An unlock was sent for an account that isn't locked.
+    NotLocked,
     NotFound {
         transaction_id: TransactionId,
     },
@@ -38,13 +101,95 @@ pub enum TransactionError {
         required_state: TransactionState,
     },
     ClientLocked,
+    /// An enricher refused the transaction before it was applied.
+    Refused {
+        reason: &'static str,
+    },
+    DuplicateTransactionId {
+        transaction_id: TransactionId,
+    },
+    /// The transaction was applied in a previous run, so it was skipped rather than applied again.
+    AlreadyProcessed {
+        transaction_id: TransactionId,
+    },
+    /// A dispute, resolve or chargeback referred to another client's transaction.
+    BelongsToAnotherClient {
+        transaction_id: TransactionId,
+        owner: ClientId,
+    },
+    /// The transaction could not be written to the write-ahead log, so it wasn't applied.
+    NotLogged,
+    /// The transaction is in a different currency from the account.
+    CurrencyMismatch {
+        expected: Currency,
+        passed: Currency,
+    },
+    InvalidConversion {
+        resulting_amount: Amount,
+    },
+    /// No rate is loaded for the pair, or the currency converted from isn't known.
+    RateUnavailable {
+        from: Option<Currency>,
+        to: Currency,
+    },
+    ConvertToSameCurrency,
+    /// The available balance can't cover the fee charged for the transaction.
+    InvalidFee {
+        resulting_amount: Amount,
+    },
+    /// The withdrawal would leave less than the minimum available balance.
+    BelowMinimumBalance {
+        minimum: Amount,
+        resulting_amount: Amount,
+    },
+    /// The deposit or withdrawal is above the largest amount a single one may be.
+    AboveMaximumAmount {
+        maximum: Amount,
+    },
+    /// The transaction would take the client beyond one of its velocity limits.
+    VelocityLimitExceeded {
+        limit: VelocityLimit,
+    },
+    /// The transaction was scored at or above the risk threshold.
+    HighRisk {
+        score: u8,
+    },
+    /// A rule added to the database rejected the transaction.
+    RuleRejected {
+        reason: &'static str,
+    },
+}
+
+impl TransactionError {
+    /// Whether the transaction was skipped as a re-delivery rather than rejected.
+    pub fn is_skip(&self) -> bool {
+        matches!(self, Self::AlreadyProcessed { .. })
+    }
+}
+
+/// Why a transaction that was applied has been flagged for review.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Warning {
+    /// The amount was above the soft limit.
+    SoftLimit { limit: Amount },
+    /// The amount was above the maximum, which is set to flag rather than reject.
+    AboveMaximum { maximum: Amount },
+    /// The transaction was read later than the reorder window allows, so it was applied after
+    /// one whose timestamp is this many seconds newer.
+    Late { behind: u64 },
+    /// The transaction was scored at or above the risk threshold.
+    HighRisk { score: u8 },
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 pub struct Transaction {
     pub transaction_type: TransactionType,
     pub client: ClientId,
     pub id: TransactionId,
+    /// When the transaction happened upstream, if the input says.
+    pub timestamp: Option<Timestamp>,
+    /// The currency of the transaction, if the input says.
+    pub currency: Option<Currency>,
 }
 
 impl Transaction {
@@ -53,23 +198,70 @@ impl Transaction {
         match self.transaction_type {
             TransactionType::Deposit(amount) => Some(amount),
             TransactionType::Withdrawal(amount) => Some(amount),
+            TransactionType::Hold { amount, .. } => Some(amount),
+            TransactionType::Release { amount, .. } => Some(amount),
+            TransactionType::Transfer { amount, .. } => Some(amount),
+            TransactionType::Convert { amount, .. } => Some(amount),
+            TransactionType::Fee { amount } => Some(amount),
             _ => None,
         }
     }
+
+    /// Returns the amount for the given transaction in its currency.
+    pub fn money(&self) -> Option<Money> {
+        self.amount().map(|amount| Money {
+            amount,
+            currency: self.currency,
+        })
+    }
+
+    /// Whether the transaction carries a new id, rather than referring back to an earlier one as
+    /// disputes, resolves, chargebacks and fees do.
+    pub fn introduces_id(&self) -> bool {
+        !matches!(
+            self.transaction_type,
+            TransactionType::Dispute { .. }
+                | TransactionType::Resolve
+                | TransactionType::Chargeback { .. }
+                | TransactionType::Fee { .. }
+        )
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixtures::{amount, places};
 
     fn transaction(transaction_type: TransactionType) -> Transaction {
         Transaction {
             transaction_type,
             client: 0,
             id: 1,
+            timestamp: None,
+            currency: None,
         }
     }
 
+    #[test]
+    fn transaction_serde_json_round_trips() {
+        let transaction = Transaction {
+            currency: "EUR".parse().ok(),
+            ..transaction(TransactionType::Transfer {
+                to: 2,
+                amount: amount("1.5"),
+            })
+        };
+
+        let json = serde_json::to_string(&transaction).unwrap();
+
+        assert_eq!(
+            places("{\"transaction_type\":{\"Transfer\":{\"to\":2,\"amount\":\"1.5000\"}},\"client\":0,\"id\":1,\"timestamp\":null,\"currency\":\"EUR\"}"),
+            json
+        );
+        assert_eq!(transaction, serde_json::from_str(&json).unwrap());
+    }
+
     #[test]
     fn transaction_amount_returns_amount() {
         let amount = Amount::new(1);
@@ -84,10 +276,29 @@ mod tests {
             transaction(TransactionType::Withdrawal(amount)).amount()
         );
 
+        let amount = Amount::new(77);
+        assert_eq!(
+            Some(amount),
+            transaction(TransactionType::Hold { amount, reason: 3 }).amount()
+        );
+        assert_eq!(
+            Some(amount),
+            transaction(TransactionType::Release { amount, reason: 3 }).amount()
+        );
+        assert_eq!(
+            Some(amount),
+            transaction(TransactionType::Transfer { to: 2, amount }).amount()
+        );
+        assert_eq!(
+            Some(amount),
+            transaction(TransactionType::Fee { amount }).amount()
+        );
+
         let types_without_amounts = vec![
-            TransactionType::Dispute,
+            TransactionType::Dispute { case: None },
             TransactionType::Resolve,
-            TransactionType::Chargeback,
+            TransactionType::Chargeback { case: None },
+            TransactionType::Unlock,
         ];
 
         for t in types_without_amounts {
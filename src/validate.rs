@@ -0,0 +1,307 @@
+use crate::{
+    amount::Amount,
+    client::ClientId,
+    currency::Currency,
+    parse_csv::{self, BUILT_IN_TYPES},
+    source::SourceError,
+    transaction::{ReasonCode, TransactionId},
+};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+// Parse errors only name the first record that failed and say little about why, which makes
+// debugging a partner's file slow. Validation reads the whole file without applying anything,
+// infers what kind of values each column holds and lists the values that don't parse, with the
+// lines they're on.
+
+/// At most this many invalid values are listed per column.
+const MAX_EXAMPLES: usize = 5;
+
+/// At most this many lines are listed per invalid value.
+const MAX_LINES: usize = 5;
+
+/// The columns every record needs.
+const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+
+/// What a value looks like, regardless of the column it's in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ValueType {
+    Empty,
+    Integer,
+    Decimal,
+    Text,
+}
+
+impl ValueType {
+    pub fn infer(value: &str) -> Self {
+        if value.is_empty() {
+            Self::Empty
+        } else if value.parse::<i64>().is_ok() {
+            Self::Integer
+        } else if Amount::from_str(value).is_ok() {
+            Self::Decimal
+        } else {
+            Self::Text
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Empty => "empty",
+            Self::Integer => "integer",
+            Self::Decimal => "decimal",
+            Self::Text => "text",
+        }
+    }
+}
+
+/// Whether the engine can parse the value in the named column. Columns it doesn't read accept
+/// anything.
+fn accepts(column: &str, value: &str) -> bool {
+    match column {
+        "type" => BUILT_IN_TYPES.contains(&value),
+        "client" => value.parse::<ClientId>().is_ok(),
+        "tx" => value.parse::<TransactionId>().is_ok(),
+        "amount" | "converted" => value.is_empty() || Amount::from_str(value).is_ok(),
+        "reason" => value.is_empty() || value.parse::<ReasonCode>().is_ok(),
+        "to" => value.is_empty() || value.parse::<ClientId>().is_ok(),
+        "currency" | "to_currency" => value.is_empty() || Currency::from_str(value).is_ok(),
+        _ => true,
+    }
+}
+
+/// A value the engine can't parse and the first lines it's on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InvalidValue {
+    pub value: String,
+    pub lines: Vec<u64>,
+    /// How many times the value appears.
+    pub count: u64,
+}
+
+/// The values found in a column.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnReport {
+    pub name: String,
+    /// How many values of each type the column holds.
+    pub types: BTreeMap<ValueType, u64>,
+    /// The first invalid values found.
+    pub invalid: Vec<InvalidValue>,
+    /// How many values don't parse, including those not listed.
+    pub invalid_count: u64,
+}
+
+impl ColumnReport {
+    fn new(name: &str) -> Self {
+        Self {
+            name: String::from(name),
+            types: BTreeMap::new(),
+            invalid: vec![],
+            invalid_count: 0,
+        }
+    }
+
+    fn add(&mut self, value: &str, line: u64) {
+        *self.types.entry(ValueType::infer(value)).or_default() += 1;
+
+        if accepts(&self.name, value) {
+            return;
+        }
+
+        self.invalid_count += 1;
+        let listed = self.invalid.len();
+        match self
+            .invalid
+            .iter_mut()
+            .find(|invalid| invalid.value == value)
+        {
+            Some(invalid) => {
+                invalid.count += 1;
+                if invalid.lines.len() < MAX_LINES {
+                    invalid.lines.push(line);
+                }
+            }
+            None if listed < MAX_EXAMPLES => self.invalid.push(InvalidValue {
+                value: String::from(value),
+                lines: vec![line],
+                count: 1,
+            }),
+            None => {}
+        }
+    }
+}
+
+/// What was found in every column of a file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationReport {
+    pub records: u64,
+    pub missing_columns: Vec<String>,
+    pub columns: Vec<ColumnReport>,
+}
+
+impl ValidationReport {
+    /// Whether every record can be parsed.
+    pub fn is_valid(&self) -> bool {
+        self.missing_columns.is_empty() && self.invalid_count() == 0
+    }
+
+    /// How many values don't parse.
+    pub fn invalid_count(&self) -> u64 {
+        self.columns.iter().map(|column| column.invalid_count).sum()
+    }
+
+    /// Writes a summary, with the types and invalid values of every column when the file isn't
+    /// valid.
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        if self.is_valid() {
+            return writeln!(writer, "{} records, all valid", self.records);
+        }
+
+        writeln!(
+            writer,
+            "{} records, {} invalid values",
+            self.records,
+            self.invalid_count()
+        )?;
+        for column in self.missing_columns.iter() {
+            writeln!(writer, "missing {} column", column)?;
+        }
+
+        for column in self.columns.iter() {
+            let types: Vec<String> = column
+                .types
+                .iter()
+                .map(|(value_type, count)| format!("{} {}", value_type.name(), count))
+                .collect();
+            writeln!(writer, "{}: {}", column.name, types.join(", "))?;
+
+            for invalid in column.invalid.iter() {
+                let lines: Vec<String> =
+                    invalid.lines.iter().map(|line| line.to_string()).collect();
+                let more = invalid.count - invalid.lines.len() as u64;
+                write!(
+                    writer,
+                    "  {} column contains '{}' at lines {}",
+                    column.name,
+                    invalid.value,
+                    lines.join(", ")
+                )?;
+                if more > 0 {
+                    write!(writer, " and {} more", more)?;
+                }
+                writeln!(writer)?;
+            }
+
+            let unlisted =
+                column.invalid_count - column.invalid.iter().map(|i| i.count).sum::<u64>();
+            if unlisted > 0 {
+                writeln!(writer, "  {} other invalid values", unlisted)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads every record of CSV with a header row, checking each value against its column.
+pub fn validate<R: Read>(reader: R) -> Result<ValidationReport, SourceError> {
+    let mut rdr = parse_csv::reader_builder(true).from_reader(reader);
+    let headers = rdr.headers()?.clone();
+
+    let missing_columns = REQUIRED_COLUMNS
+        .iter()
+        .filter(|column| !headers.iter().any(|header| header == **column))
+        .map(|column| String::from(*column))
+        .collect();
+    let mut columns: Vec<ColumnReport> = headers.iter().map(ColumnReport::new).collect();
+
+    let mut records = 0;
+    let mut record = csv::StringRecord::new();
+    while rdr.read_record(&mut record)? {
+        records += 1;
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+
+        // Records may leave out trailing optional columns.
+        for (column, value) in columns.iter_mut().zip(record.iter()) {
+            column.add(value, line);
+        }
+    }
+
+    Ok(ValidationReport {
+        records,
+        missing_columns,
+        columns,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_type_infer_returns_expected() {
+        assert_eq!(ValueType::Empty, ValueType::infer(""));
+        assert_eq!(ValueType::Integer, ValueType::infer("42"));
+        assert_eq!(ValueType::Decimal, ValueType::infer("1.5"));
+        assert_eq!(ValueType::Text, ValueType::infer("N/A"));
+    }
+
+    #[test]
+    fn validate_lists_invalid_values_with_lines() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 1.0\n\
+                     deposit, 1, 2, N/A\n\
+                     deposit, x, 3, 2.0\n\
+                     deposit, 1, 4, N/A\n\
+                     dispute, 1, 1,\n";
+
+        let report = validate(input.as_bytes()).unwrap();
+
+        assert_eq!(false, report.is_valid());
+        assert_eq!(5, report.records);
+        assert_eq!(3, report.invalid_count());
+
+        let amount = &report.columns[3];
+        let expected = vec![InvalidValue {
+            value: String::from("N/A"),
+            lines: vec![3, 5],
+            count: 2,
+        }];
+        assert_eq!(expected, amount.invalid);
+        assert_eq!(Some(&2), amount.types.get(&ValueType::Decimal));
+        assert_eq!(Some(&1), amount.types.get(&ValueType::Empty));
+
+        let mut output = vec![];
+        report.write(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            true,
+            output.contains("  amount column contains 'N/A' at lines 3, 5\n")
+        );
+        assert_eq!(
+            true,
+            output.contains("  client column contains 'x' at lines 4\n")
+        );
+    }
+
+    #[test]
+    fn validate_missing_column_is_invalid() {
+        let report = validate("type, tx\ndeposit, 1\n".as_bytes()).unwrap();
+
+        assert_eq!(false, report.is_valid());
+        assert_eq!(vec![String::from("client")], report.missing_columns);
+    }
+
+    #[test]
+    fn validate_valid_file_returns_valid() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\nwithdrawal, 1, 2, 0.5\n";
+
+        let report = validate(input.as_bytes()).unwrap();
+
+        assert_eq!(true, report.is_valid());
+        let mut output = vec![];
+        report.write(&mut output).unwrap();
+        assert_eq!("2 records, all valid\n", String::from_utf8(output).unwrap());
+    }
+}
@@ -0,0 +1,358 @@
+use crate::{
+    amount::Amount,
+    clock::Timestamp,
+    transaction::{Transaction, TransactionType},
+};
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+// Velocity limits cap how much a client may do within a rolling window of the transactions'
+// timestamps, as `limit, value, window` rows such as `withdrawal_total, 1000.00, 86400` or
+// `transaction_count, 20, 3600`, with windows in seconds. Only transactions that introduce an id
+// and have a timestamp are counted. The activity counted against the limits isn't kept in
+// snapshots, so a restored run starts with empty windows.
+
+#[derive(Debug)]
+pub enum VelocityError {
+    Csv(csv::Error),
+    /// The limit isn't `withdrawal_total` or `transaction_count`.
+    UnsupportedLimit {
+        passed: String,
+    },
+    /// The value isn't a positive amount or count, or the window isn't a positive number of
+    /// seconds.
+    InvalidLimit {
+        value: String,
+        window: String,
+    },
+    /// The file lists the limit more than once.
+    DuplicateLimit {
+        passed: String,
+    },
+}
+
+impl From<csv::Error> for VelocityError {
+    fn from(e: csv::Error) -> Self {
+        Self::Csv(e)
+    }
+}
+
+/// What happens to transactions that exceed a velocity limit.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum VelocityPolicy {
+    /// Reject them with `TransactionError::VelocityLimitExceeded`.
+    #[default]
+    Reject,
+    /// Apply them anyway and keep their ids in `Database::velocity_breaches`.
+    Report,
+}
+
+impl FromStr for VelocityPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "report" => Ok(Self::Report),
+            _ => Err(String::from(s)),
+        }
+    }
+}
+
+/// A limit a transaction exceeded.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum VelocityLimit {
+    /// The most a client may withdraw within the window.
+    WithdrawalTotal { total: Amount, window: u64 },
+    /// The most transactions a client may make within the window.
+    TransactionCount { count: u64, window: u64 },
+}
+
+#[derive(serde::Deserialize)]
+struct LimitRecord {
+    limit: String,
+    value: String,
+    window: String,
+}
+
+/// The velocity limits every client is held to.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VelocityLimits {
+    /// The most a client may withdraw, and the window in seconds.
+    pub withdrawal_total: Option<(Amount, u64)>,
+    /// The most transactions a client may make, and the window in seconds.
+    pub transaction_count: Option<(u64, u64)>,
+}
+
+impl VelocityLimits {
+    /// Reads a CSV limits file with `limit, value, window` columns.
+    pub fn load(path: &str) -> Result<Self, VelocityError> {
+        let rdr = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        Self::read(rdr)
+    }
+
+    fn read<R: std::io::Read>(mut rdr: csv::Reader<R>) -> Result<Self, VelocityError> {
+        let mut limits = Self::default();
+        for result in rdr.deserialize() {
+            let record: LimitRecord = result?;
+            let invalid = || VelocityError::InvalidLimit {
+                value: record.value.clone(),
+                window: record.window.clone(),
+            };
+            let window = match u64::from_str(&record.window) {
+                Ok(window) if window > 0 => window,
+                _ => return Err(invalid()),
+            };
+
+            let duplicate = match record.limit.as_str() {
+                "withdrawal_total" => {
+                    let total = match Amount::from_str(&record.value) {
+                        Ok(total) if total > Amount::zero() => total,
+                        _ => return Err(invalid()),
+                    };
+                    limits.withdrawal_total.replace((total, window)).is_some()
+                }
+                "transaction_count" => {
+                    let count = match u64::from_str(&record.value) {
+                        Ok(count) if count > 0 => count,
+                        _ => return Err(invalid()),
+                    };
+                    limits.transaction_count.replace((count, window)).is_some()
+                }
+                _ => {
+                    return Err(VelocityError::UnsupportedLimit {
+                        passed: record.limit,
+                    })
+                }
+            };
+            if duplicate {
+                return Err(VelocityError::DuplicateLimit {
+                    passed: record.limit,
+                });
+            }
+        }
+
+        Ok(limits)
+    }
+
+    /// The first limit the transaction would exceed on top of the client's recent activity, if
+    /// it's counted at all.
+    pub fn breach(
+        &self,
+        activity: Option<&Activity>,
+        transaction: &Transaction,
+    ) -> Option<VelocityLimit> {
+        let timestamp = transaction
+            .timestamp
+            .filter(|_| transaction.introduces_id())?;
+        let recent = |window: u64| {
+            activity
+                .into_iter()
+                .flat_map(|activity| activity.entries.iter())
+                .filter(move |(at, _)| *at <= timestamp && timestamp - at < window)
+        };
+
+        if let TransactionType::Withdrawal(amount) = transaction.transaction_type {
+            if let Some((total, window)) = self.withdrawal_total {
+                let withdrawn = recent(window).fold(amount, |sum, (_, withdrawn)| sum + *withdrawn);
+                if withdrawn > total {
+                    return Some(VelocityLimit::WithdrawalTotal { total, window });
+                }
+            }
+        }
+
+        if let Some((count, window)) = self.transaction_count {
+            if recent(window).count() as u64 >= count {
+                return Some(VelocityLimit::TransactionCount { count, window });
+            }
+        }
+
+        None
+    }
+
+    /// The longest window, beyond which activity no longer counts.
+    fn longest_window(&self) -> u64 {
+        let withdrawals = self.withdrawal_total.map_or(0, |(_, window)| window);
+        let transactions = self.transaction_count.map_or(0, |(_, window)| window);
+        withdrawals.max(transactions)
+    }
+}
+
+/// A client's recent transactions, as they're counted against the velocity limits.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Activity {
+    /// When each transaction happened and how much it withdrew, oldest first.
+    entries: VecDeque<(Timestamp, Amount)>,
+}
+
+impl Activity {
+    /// Counts an applied transaction, forgetting activity outside every window of the limits.
+    pub fn record(&mut self, limits: &VelocityLimits, transaction: &Transaction) {
+        let timestamp = match transaction
+            .timestamp
+            .filter(|_| transaction.introduces_id())
+        {
+            Some(timestamp) => timestamp,
+            None => return,
+        };
+        let withdrawn = match transaction.transaction_type {
+            TransactionType::Withdrawal(amount) => amount,
+            _ => Amount::zero(),
+        };
+
+        // Kept in timestamp order, so transactions that arrive late are still counted.
+        let at = self.entries.partition_point(|(at, _)| *at <= timestamp);
+        self.entries.insert(at, (timestamp, withdrawn));
+
+        let newest = self.entries.back().map_or(timestamp, |(at, _)| *at);
+        let oldest = newest.saturating_sub(limits.longest_window());
+        while self.entries.front().is_some_and(|(at, _)| *at < oldest) {
+            self.entries.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::amount;
+
+    fn read(input: &str) -> Result<VelocityLimits, VelocityError> {
+        VelocityLimits::read(
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(input.as_bytes()),
+        )
+    }
+
+    fn transaction(
+        id: u32,
+        transaction_type: TransactionType,
+        timestamp: Timestamp,
+    ) -> Transaction {
+        Transaction {
+            transaction_type,
+            client: 1,
+            id,
+            timestamp: Some(timestamp),
+            currency: None,
+        }
+    }
+
+    #[test]
+    fn velocity_limits_read_returns_expected() {
+        let limits = read(
+            "limit, value, window\nwithdrawal_total, 1000.00, 86400\ntransaction_count, 20, 3600\n",
+        )
+        .unwrap();
+
+        assert_eq!(Some((amount("1000"), 86400)), limits.withdrawal_total);
+        assert_eq!(Some((20, 3600)), limits.transaction_count);
+    }
+
+    #[test]
+    fn velocity_limits_read_invalid_rows_return_err() {
+        assert_eq!(
+            true,
+            matches!(
+                read("limit, value, window\nwithdrawal_total, 0, 86400\n"),
+                Err(VelocityError::InvalidLimit { .. })
+            )
+        );
+        assert_eq!(
+            true,
+            matches!(
+                read("limit, value, window\ntransaction_count, 2.5, 3600\n"),
+                Err(VelocityError::InvalidLimit { .. })
+            )
+        );
+        assert_eq!(
+            true,
+            matches!(
+                read("limit, value, window\ntransaction_count, 2, 0\n"),
+                Err(VelocityError::InvalidLimit { .. })
+            )
+        );
+        assert_eq!(
+            true,
+            matches!(
+                read("limit, value, window\ndeposit_total, 2, 60\n"),
+                Err(VelocityError::UnsupportedLimit { .. })
+            )
+        );
+        assert_eq!(
+            true,
+            matches!(
+                read("limit, value, window\ntransaction_count, 2, 60\ntransaction_count, 3, 60\n"),
+                Err(VelocityError::DuplicateLimit { .. })
+            )
+        );
+    }
+
+    #[test]
+    fn velocity_limits_breach_returns_expected() {
+        let limits = VelocityLimits {
+            withdrawal_total: Some((Amount::new(1000000), 86400)),
+            transaction_count: Some((2, 3600)),
+        };
+        let mut activity = Activity::default();
+        activity.record(
+            &limits,
+            &transaction(1, TransactionType::Withdrawal(Amount::new(600000)), 0),
+        );
+
+        // 60 + 50 is above the 100 withdrawn a day.
+        let withdrawal = transaction(2, TransactionType::Withdrawal(Amount::new(500000)), 4000);
+        assert_eq!(
+            Some(VelocityLimit::WithdrawalTotal {
+                total: Amount::new(1000000),
+                window: 86400
+            }),
+            limits.breach(Some(&activity), &withdrawal)
+        );
+        let withdrawal = transaction(2, TransactionType::Withdrawal(Amount::new(500000)), 86400);
+        assert_eq!(None, limits.breach(Some(&activity), &withdrawal));
+
+        activity.record(
+            &limits,
+            &transaction(2, TransactionType::Deposit(Amount::new(1)), 10),
+        );
+        let deposit = transaction(3, TransactionType::Deposit(Amount::new(1)), 20);
+        assert_eq!(
+            Some(VelocityLimit::TransactionCount {
+                count: 2,
+                window: 3600
+            }),
+            limits.breach(Some(&activity), &deposit)
+        );
+        let deposit = transaction(3, TransactionType::Deposit(Amount::new(1)), 3600);
+        assert_eq!(None, limits.breach(Some(&activity), &deposit));
+
+        let untimed = Transaction {
+            timestamp: None,
+            ..deposit
+        };
+        assert_eq!(None, limits.breach(Some(&activity), &untimed));
+    }
+
+    #[test]
+    fn activity_record_forgets_entries_outside_every_window() {
+        let limits = VelocityLimits {
+            withdrawal_total: None,
+            transaction_count: Some((5, 60)),
+        };
+        let mut activity = Activity::default();
+        for (id, timestamp) in [(1, 0), (2, 30), (3, 100), (4, 20)] {
+            activity.record(
+                &limits,
+                &transaction(id, TransactionType::Deposit(Amount::new(1)), timestamp),
+            );
+        }
+
+        let timestamps: Vec<Timestamp> = activity.entries.iter().map(|(at, _)| *at).collect();
+        assert_eq!(vec![100], timestamps);
+    }
+}
@@ -0,0 +1,321 @@
+use crate::{
+    amount::Amount,
+    client::{Client, ClientAccount, ClientId},
+    currency::Currency,
+    database::AccountObserver,
+    transaction::{Transaction, TransactionError, TransactionType},
+};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+// Verification checks what must hold once a run is over, so accounting bugs surface as a failed
+// run rather than a wrong report. The money on every account, summed per currency, must equal
+// what was there when the run started plus what the transactions accepted since moved in or out.
+// Those flows are added up from the transactions as the database accepts them rather than from
+// the accounts' own events, so a balance the engine got wrong doesn't also vouch for itself.
+
+/// An invariant that doesn't hold, for one client or for every account in a currency.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    /// The client it doesn't hold for, or `None` if it's one over every account.
+    pub client: Option<ClientId>,
+    pub invariant: &'static str,
+    /// The values that broke the invariant.
+    pub detail: String,
+}
+
+/// The money moved by accepted transactions, by client and by the currency of the balance it
+/// moved in, `None` being the client's own. The client's own currency is only looked up once the
+/// run is over, as the first transaction with a currency sets it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Flows {
+    moved: BTreeMap<(ClientId, Option<Currency>), Amount>,
+}
+
+impl Flows {
+    /// Starts from the balances the accounts hold before the run, such as those restored from a
+    /// snapshot.
+    pub fn opening<'a>(accounts: impl Iterator<Item = &'a Client>) -> Self {
+        let mut flows = Self::default();
+        for account in accounts {
+            flows.add(account.id(), None, account.total());
+            for balance in account.other_currencies() {
+                flows.add(account.id(), balance.currency(), balance.total());
+            }
+        }
+        flows
+    }
+
+    fn add(&mut self, client: ClientId, currency: Option<Currency>, amount: Amount) {
+        let moved = self.moved.entry((client, currency)).or_default();
+        *moved = *moved + amount;
+    }
+
+    /// What should be on every account once the run is over, per currency.
+    fn expected(&self, accounts: &BTreeMap<ClientId, &Client>) -> Totals {
+        let mut totals = Totals::new();
+        for ((client, currency), amount) in self.moved.iter() {
+            let own = accounts.get(client).and_then(|account| account.currency());
+            let total = totals.entry(currency.or(own)).or_default();
+            *total = *total + *amount;
+        }
+        totals
+    }
+}
+
+/// Amounts summed per currency, `None` for accounts that were never given one.
+type Totals = BTreeMap<Option<Currency>, Amount>;
+
+/// Adds up the flows of the transactions the database accepts. Shared so the flows can be read
+/// once the run is over.
+#[derive(Clone, Default)]
+pub struct FlowObserver(pub Arc<Mutex<Flows>>);
+
+impl FlowObserver {
+    /// Starts from the balances the accounts hold before the run.
+    pub fn opening<'a>(accounts: impl Iterator<Item = &'a Client>) -> Self {
+        Self(Arc::new(Mutex::new(Flows::opening(accounts))))
+    }
+}
+
+impl AccountObserver<Client> for FlowObserver {
+    fn on_transaction(
+        &mut self,
+        transaction: &Transaction,
+        result: &Result<(), TransactionError>,
+        account: Option<&Client>,
+    ) {
+        let account = match (result, account) {
+            (Ok(()), Some(account)) => account,
+            _ => return,
+        };
+        // Disputes, chargebacks and conversions move the amounts of the transaction as it was
+        // applied, which the notified one may not carry.
+        let applied = account
+            .history_index(transaction.id)
+            .map(|index| account.history()[index].1);
+        let client = transaction.client;
+        let currency = transaction.currency;
+
+        let mut flows = self.0.lock().unwrap();
+        match transaction.transaction_type {
+            TransactionType::Deposit(amount) => flows.add(client, currency, amount),
+            TransactionType::Withdrawal(amount) | TransactionType::Fee { amount } => {
+                flows.add(client, currency, Amount::zero() - amount)
+            }
+            TransactionType::Transfer { to, amount } => {
+                flows.add(client, currency, Amount::zero() - amount);
+                flows.add(to, currency, amount);
+            }
+            TransactionType::Convert { .. } => {
+                if let Some(TransactionType::Convert {
+                    amount,
+                    to,
+                    converted: Some(converted),
+                }) = applied.map(|applied| applied.transaction_type)
+                {
+                    flows.add(client, currency, Amount::zero() - amount);
+                    flows.add(client, Some(to), converted);
+                }
+            }
+            // A dispute of anything but a deposit advances its amount to the client until it's
+            // resolved or charged back.
+            TransactionType::Dispute { .. } => match applied {
+                Some(disputed)
+                    if !matches!(disputed.transaction_type, TransactionType::Deposit(_)) =>
+                {
+                    let amount = disputed.amount().unwrap_or_default();
+                    flows.add(client, disputed.currency, amount);
+                }
+                _ => {}
+            },
+            TransactionType::Chargeback { .. } => {
+                if let Some(reversed) = applied {
+                    let amount = reversed.amount().unwrap_or_default();
+                    flows.add(client, reversed.currency, Amount::zero() - amount);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Checks every account against the flows of the run, returning the invariants that don't hold:
+/// those of each client in client order, then those over every account in currency order.
+pub fn verify<'a>(accounts: impl Iterator<Item = &'a Client>, flows: &Flows) -> Vec<Violation> {
+    let accounts: BTreeMap<ClientId, &Client> =
+        accounts.map(|account| (account.id(), account)).collect();
+
+    let mut violations = vec![];
+    let mut actual = Totals::new();
+    for account in accounts.values() {
+        if account.held().less_than_zero() {
+            violations.push(Violation {
+                client: Some(account.id()),
+                invariant: "held is never negative",
+                detail: format!("held {}", account.held()),
+            });
+        }
+
+        let own = actual.entry(account.currency()).or_default();
+        *own = *own + account.total();
+        for balance in account.other_currencies() {
+            let total = actual.entry(balance.currency()).or_default();
+            *total = *total + balance.total();
+        }
+    }
+
+    let mut expected = flows.expected(&accounts);
+    for currency in actual.keys() {
+        expected.entry(*currency).or_default();
+    }
+    for (currency, expected) in expected {
+        let total = actual.get(&currency).copied().unwrap_or_default();
+        if total != expected {
+            let currency = currency.map_or_else(|| String::from("no currency"), |c| c.to_string());
+            violations.push(Violation {
+                client: None,
+                invariant: "balances equal opening balances plus accepted flows",
+                detail: format!(
+                    "{}: balances {} != opening balances plus flows {}",
+                    currency, total, expected
+                ),
+            });
+        }
+    }
+    violations
+}
+
+/// Writes a line per violation.
+pub fn write_violations(violations: &[Violation], writer: &mut dyn Write) -> io::Result<()> {
+    for violation in violations {
+        match violation.client {
+            Some(client) => write!(writer, "client {}: ", client)?,
+            None => write!(writer, "all clients: ")?,
+        }
+        writeln!(writer, "{} ({})", violation.invariant, violation.detail)?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        amount, chargeback, deposit, dispute, places, resolve, transaction, withdrawal,
+    };
+    use crate::{
+        amount::RoundingMode,
+        database::Database,
+        fees::{Fee, FeeSchedule},
+    };
+
+    #[test]
+    fn verify_returns_no_violations_after_run() {
+        let schedule = FeeSchedule {
+            deposit: Some(Fee::Flat(Amount::new(1000))),
+            withdrawal: None,
+        };
+        let mut database = Database::<Client>::new().with_fees(schedule, RoundingMode::HalfUp);
+        let observer = FlowObserver::opening(database.accounts());
+        database.subscribe(Box::new(observer.clone()));
+
+        for transaction in [
+            deposit(1, 1, 50000),
+            withdrawal(1, 2, 10000),
+            dispute(1, 2),
+            resolve(1, 2),
+            deposit(2, 3, 20000),
+            dispute(2, 3),
+            chargeback(2, 3),
+            transaction(
+                1,
+                4,
+                TransactionType::Transfer {
+                    to: 3,
+                    amount: Amount::new(5000),
+                },
+            ),
+        ] {
+            let _result = database.apply_transaction(transaction);
+        }
+        let flows = observer.0.lock().unwrap().clone();
+        assert_eq!(Vec::<Violation>::new(), verify(database.accounts(), &flows));
+
+        let restored = Database::<Client>::from_snapshot(database.snapshot()).unwrap();
+        let opening = Flows::opening(restored.accounts());
+        assert_eq!(
+            Vec::<Violation>::new(),
+            verify(restored.accounts(), &opening)
+        );
+    }
+
+    #[test]
+    fn verify_returns_violation_when_balances_drift_from_flows() {
+        let mut database = Database::<Client>::new();
+        let observer = FlowObserver::default();
+        database.subscribe(Box::new(observer.clone()));
+        database.apply_transaction(deposit(1, 1, 50000)).unwrap();
+
+        // A balance restored wrong is consistent with itself, but not with what was deposited.
+        let mut snapshot = database.account(1).unwrap().to_snapshot();
+        snapshot.available = String::from("6.0");
+        let client = Client::from_snapshot(snapshot).unwrap();
+
+        let expected = vec![Violation {
+            client: None,
+            invariant: "balances equal opening balances plus accepted flows",
+            detail: format!(
+                "no currency: balances {} != opening balances plus flows {}",
+                amount("6"),
+                Amount::new(50000)
+            ),
+        }];
+        let flows = observer.0.lock().unwrap().clone();
+        assert_eq!(expected, verify([client].iter(), &flows));
+    }
+
+    #[test]
+    fn verify_returns_violations_of_restored_accounts() {
+        let mut snapshot = Client::new(3).to_snapshot();
+        snapshot.held = String::from("-1.0");
+        let client = Client::from_snapshot(snapshot).unwrap();
+        let flows = Flows::opening([client.clone()].iter());
+
+        let expected = vec![Violation {
+            client: Some(3),
+            invariant: "held is never negative",
+            detail: places("held -1.0000"),
+        }];
+        assert_eq!(expected, verify([client].iter(), &flows));
+    }
+
+    #[test]
+    fn write_violations_returns_expected() {
+        let violations = vec![
+            Violation {
+                client: Some(3),
+                invariant: "held is never negative",
+                detail: places("held -1.0000"),
+            },
+            Violation {
+                client: None,
+                invariant: "balances equal opening balances plus accepted flows",
+                detail: places("EUR: balances 1.0000 != 2.0000"),
+            },
+        ];
+        let mut output = vec![];
+
+        write_violations(&violations, &mut output).unwrap();
+
+        assert_eq!(
+            places(
+                "client 3: held is never negative (held -1.0000)\n\
+             all clients: balances equal opening balances plus accepted flows \
+             (EUR: balances 1.0000 != 2.0000)\n"
+            ),
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
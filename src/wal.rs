@@ -0,0 +1,231 @@
+use crate::{parse_csv::CsvTransaction, transaction::Transaction};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+
+// Every transaction is appended to the log and synced to disk before it's applied, so after a
+// crash replaying the log into an empty database rebuilds exactly the state that was reached.
+//
+// Each record is a 4 byte big-endian payload length, an 8 byte FNV-1a checksum of the payload and
+// the payload: the transaction as a bincode encoded CSV record. A crash while appending can only
+// leave a partial record at the end of the log, which is dropped when the log is reopened.
+
+#[derive(Debug)]
+pub enum WalError {
+    Io(io::Error),
+    Encoding(bincode::Error),
+    /// A record before the end of the log doesn't match its checksum.
+    Corrupt {
+        offset: u64,
+    },
+    /// A record could not be converted back into a transaction.
+    InvalidRecord {
+        offset: u64,
+    },
+}
+
+impl From<io::Error> for WalError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<bincode::Error> for WalError {
+    fn from(e: bincode::Error) -> Self {
+        Self::Encoding(e)
+    }
+}
+
+const HEADER_LEN: u64 = 12;
+
+fn checksum(payload: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in payload {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// An append-only log of the transactions given to a database.
+pub struct WriteAheadLog {
+    file: File,
+}
+
+impl WriteAheadLog {
+    /// Reads every transaction in the log at the path, creating an empty log if there is none,
+    /// and opens it for appending. A partial record left at the end by a crash is removed.
+    pub fn open(path: &str) -> Result<(Self, Vec<Transaction>), WalError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let (transactions, valid_len) = replay(&file)?;
+        file.set_len(valid_len)?;
+
+        Ok((Self { file }, transactions))
+    }
+
+    /// Appends the transaction and waits until it's on disk.
+    pub fn append(&mut self, transaction: &Transaction) -> Result<(), WalError> {
+        let payload = bincode::serialize(&CsvTransaction::from_transaction(transaction))?;
+
+        let mut record = Vec::with_capacity(HEADER_LEN as usize + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        record.extend_from_slice(&checksum(&payload).to_be_bytes());
+        record.extend_from_slice(&payload);
+
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Reads every complete record, returning the transactions and the length of the log they span.
+fn replay(file: &File) -> Result<(Vec<Transaction>, u64), WalError> {
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut transactions = vec![];
+    let mut offset = 0;
+    let mut header = [0; HEADER_LEN as usize];
+    while offset + HEADER_LEN <= len {
+        reader.read_exact(&mut header)?;
+        let payload_len = u64::from(u32::from_be_bytes([
+            header[0], header[1], header[2], header[3],
+        ]));
+        let end = offset + HEADER_LEN + payload_len;
+        if end > len {
+            break;
+        }
+
+        let mut payload = vec![0; payload_len as usize];
+        reader.read_exact(&mut payload)?;
+        let mut expected = [0; 8];
+        expected.copy_from_slice(&header[4..]);
+        if checksum(&payload) != u64::from_be_bytes(expected) {
+            // Only the last record can have been torn by a crash.
+            if end == len {
+                break;
+            }
+            return Err(WalError::Corrupt { offset });
+        }
+
+        let record: CsvTransaction = bincode::deserialize(&payload)?;
+        let transaction = record
+            .parse()
+            .map_err(|_| WalError::InvalidRecord { offset })?;
+        transactions.push(transaction);
+        offset = end;
+    }
+
+    Ok((transactions, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        amount::Amount,
+        client::Client,
+        database::Database,
+        transaction::{TransactionError, TransactionType},
+    };
+    use std::fs;
+
+    fn deposit(id: u32) -> Transaction {
+        Transaction {
+            transaction_type: TransactionType::Deposit(Amount::new(10000)),
+            client: 1,
+            id,
+            timestamp: None,
+            currency: None,
+        }
+    }
+
+    #[test]
+    fn write_ahead_log_open_drops_torn_record() {
+        let path = std::env::temp_dir().join("payments_wal_torn.log");
+        let path = path.to_str().unwrap();
+        let _result = fs::remove_file(path);
+
+        {
+            let (mut wal, transactions) = WriteAheadLog::open(path).unwrap();
+            assert_eq!(true, transactions.is_empty());
+            wal.append(&deposit(1)).unwrap();
+            wal.append(&deposit(2)).unwrap();
+        }
+        let len = fs::metadata(path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let (mut wal, transactions) = WriteAheadLog::open(path).unwrap();
+        assert_eq!(vec![deposit(1)], transactions);
+        wal.append(&deposit(3)).unwrap();
+
+        let (_, transactions) = WriteAheadLog::open(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(vec![deposit(1), deposit(3)], transactions);
+    }
+
+    #[test]
+    fn write_ahead_log_open_corrupt_record_returns_err() {
+        let path = std::env::temp_dir().join("payments_wal_corrupt.log");
+        let path = path.to_str().unwrap();
+        let _result = fs::remove_file(path);
+
+        {
+            let (mut wal, _) = WriteAheadLog::open(path).unwrap();
+            wal.append(&deposit(1)).unwrap();
+            wal.append(&deposit(2)).unwrap();
+        }
+        let mut bytes = fs::read(path).unwrap();
+        bytes[HEADER_LEN as usize] ^= 0xff;
+        fs::write(path, bytes).unwrap();
+
+        let actual = WriteAheadLog::open(path);
+        fs::remove_file(path).unwrap();
+        match actual {
+            Err(WalError::Corrupt { offset }) => assert_eq!(0, offset),
+            _ => panic!("expected a corrupt log"),
+        }
+    }
+
+    #[test]
+    fn database_with_wal_recovers_state() {
+        let path = std::env::temp_dir().join("payments_wal_recover.log");
+        let path = path.to_str().unwrap();
+        let _result = fs::remove_file(path);
+
+        let dispute = Transaction {
+            transaction_type: TransactionType::Dispute { case: None },
+            client: 1,
+            id: 1,
+            timestamp: None,
+            currency: None,
+        };
+        let expected = {
+            let mut database = Database::<Client>::new().with_wal(path).unwrap();
+            database.apply_transaction(deposit(1)).unwrap();
+            database.apply_transaction(deposit(2)).unwrap();
+            database.apply_transaction(dispute).unwrap();
+            database.snapshot()
+        };
+
+        let mut recovered = Database::<Client>::new().with_wal(path).unwrap();
+        assert_eq!(expected, recovered.snapshot());
+
+        // A submitter that never saw the result resends, and the transaction isn't applied twice.
+        let actual = recovered.apply_transaction(deposit(2));
+        fs::remove_file(path).unwrap();
+        assert_eq!(
+            Err(TransactionError::AlreadyProcessed { transaction_id: 2 }),
+            actual
+        );
+    }
+}
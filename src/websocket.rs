@@ -0,0 +1,141 @@
+use crate::{
+    client::ClientId,
+    protocol::{self, ResultCode},
+    scheduler::FairScheduler,
+    transaction::TransactionId,
+};
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use tungstenite::Message;
+
+// Each text message is a CSV record or JSON object, as a line of the lines protocol is. It's
+// queued on the same scheduler as the TCP connections and answered with a JSON acknowledgment
+// once applied, before the next message is read, so an interactive client sees each rejection as
+// it happens. Pings are answered by the WebSocket library.
+
+/// The answer to a transaction message.
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct Acknowledgment {
+    /// The client and id of the transaction, unless the message wasn't a transaction.
+    pub client: Option<ClientId>,
+    pub tx: Option<TransactionId>,
+    pub applied: bool,
+    /// `Applied`, or why the transaction was ignored or rejected.
+    pub result: String,
+    pub code: u8,
+}
+
+impl Acknowledgment {
+    fn new(client: Option<ClientId>, tx: Option<TransactionId>, code: ResultCode) -> Self {
+        Self {
+            client,
+            tx,
+            applied: code == ResultCode::Applied,
+            result: format!("{:?}", code),
+            code: code as u8,
+        }
+    }
+}
+
+/// Applies the transaction in the message once it's its client's turn.
+pub fn acknowledge(message: &str, scheduler: &FairScheduler) -> io::Result<Acknowledgment> {
+    let transaction = match protocol::parse_line(message.trim()) {
+        Ok(Some(transaction)) => transaction,
+        Ok(None) => return Ok(Acknowledgment::new(None, None, ResultCode::Ignored)),
+        Err(_) => return Ok(Acknowledgment::new(None, None, ResultCode::Malformed)),
+    };
+
+    let code = scheduler
+        .process(transaction)
+        .ok_or_else(|| io::Error::other("transactions are no longer being applied"))?;
+    Ok(Acknowledgment::new(
+        Some(transaction.client),
+        Some(transaction.id),
+        code,
+    ))
+}
+
+/// Accepts WebSocket connections on the given address, serving each on its own thread.
+pub fn serve(addr: &str, scheduler: Arc<FairScheduler>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Failed to accept WebSocket connection: {}", e);
+                continue;
+            }
+        };
+
+        let scheduler = scheduler.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &scheduler) {
+                eprintln!("WebSocket connection closed with error: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Answers messages until the client closes the connection.
+fn handle(stream: TcpStream, scheduler: &FairScheduler) -> io::Result<()> {
+    let mut socket = tungstenite::accept(stream).map_err(io::Error::other)?;
+
+    loop {
+        let message = match socket.read() {
+            Ok(message) => message,
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(e) => return Err(io::Error::other(e)),
+        };
+
+        let acknowledgment = match message {
+            Message::Text(text) => acknowledge(text.as_str(), scheduler)?,
+            Message::Binary(_) => Acknowledgment::new(None, None, ResultCode::Malformed),
+            _ => continue,
+        };
+        let reply = serde_json::to_string(&acknowledgment)?;
+        socket
+            .send(Message::text(reply))
+            .map_err(io::Error::other)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use std::sync::RwLock;
+
+    #[test]
+    fn acknowledge_returns_result_of_each_message() {
+        let database = Arc::new(RwLock::new(Database::new()));
+        let scheduler = Arc::new(FairScheduler::new(8));
+        {
+            let scheduler = scheduler.clone();
+            thread::spawn(move || scheduler.run_shared(&database, vec![], |_| {}));
+        }
+
+        let deposit = acknowledge("deposit, 1, 1, 5.0", &scheduler).unwrap();
+        let withdrawal = acknowledge(
+            "{\"type\": \"withdrawal\", \"client\": 1, \"tx\": 2, \"amount\": \"9.0\"}",
+            &scheduler,
+        )
+        .unwrap();
+        let malformed = acknowledge("deposit, x", &scheduler).unwrap();
+
+        assert_eq!(
+            Acknowledgment::new(Some(1), Some(1), ResultCode::Applied),
+            deposit
+        );
+        assert_eq!(true, deposit.applied);
+        assert_eq!(
+            "{\"client\":1,\"tx\":2,\"applied\":false,\"result\":\"InvalidWithdrawal\",\"code\":12}",
+            serde_json::to_string(&withdrawal).unwrap()
+        );
+        assert_eq!(ResultCode::Malformed as u8, malformed.code);
+    }
+}